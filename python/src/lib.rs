@@ -0,0 +1,361 @@
+//! Python bindings for strif, exposing catalog loading, per-read interruption
+//! detection and the profile and merged profile data structures so that
+//! notebooks run the same algorithm as the command-line tool.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+
+use strif_core::utils::AlignmentScoreParams;
+use strif_core::InterruptionFinder as CoreFinder;
+
+fn to_py_err(e: anyhow::Error) -> PyErr {
+    match e.downcast_ref::<std::io::Error>() {
+        Some(_) => PyIOError::new_err(format!("{:#}", e)),
+        None => PyValueError::new_err(format!("{:#}", e)),
+    }
+}
+
+/// A locus of an ExpansionHunter-style catalog
+#[pyclass(get_all, frozen, module = "strif")]
+struct CatalogLocus {
+    locus_id: String,
+    locus_structure: String,
+    reference_region: String,
+    /// The repeat motif of the locus structure
+    motif: String,
+}
+
+#[pymethods]
+impl CatalogLocus {
+    fn __repr__(&self) -> String {
+        format!(
+            "CatalogLocus(locus_id={:?}, locus_structure={:?}, reference_region={:?})",
+            self.locus_id, self.locus_structure, self.reference_region
+        )
+    }
+}
+
+/// Loads the loci of a catalog JSON file
+#[pyfunction]
+fn load_catalog(path: PathBuf) -> PyResult<Vec<CatalogLocus>> {
+    let catalog = strif_core::Catalog::from_path(&path).map_err(to_py_err)?;
+    Ok(catalog
+        .loci
+        .into_iter()
+        .map(|locus| CatalogLocus {
+            motif: locus.motif().to_string(),
+            locus_id: locus.locus_id,
+            locus_structure: locus.locus_structure,
+            reference_region: locus.reference_region,
+        })
+        .collect())
+}
+
+/// Finds the interruptions of repeat sequences by aligning them to pure
+/// repeats of their motif, with the same scores as `strif profile`
+#[pyclass(module = "strif")]
+struct InterruptionFinder {
+    finder: CoreFinder,
+}
+
+#[pymethods]
+impl InterruptionFinder {
+    #[new]
+    #[pyo3(signature = (match_score=1, mismatch_penalty=8, gap_open_penalty=10, gap_extend_penalty=1))]
+    fn new(
+        match_score: i32,
+        mismatch_penalty: i32,
+        gap_open_penalty: i32,
+        gap_extend_penalty: i32,
+    ) -> Self {
+        InterruptionFinder {
+            finder: CoreFinder::new(AlignmentScoreParams {
+                match_score,
+                mismatch_penalty,
+                gap_open_penalty,
+                gap_extend_penalty,
+            }),
+        }
+    }
+
+    /// Returns the interruptions of a repeat sequence in the order they occur
+    fn find(&mut self, repeat_seq: &str, motif: &str) -> PyResult<Vec<String>> {
+        if motif.is_empty() {
+            return Err(PyValueError::new_err("The motif cannot be empty"));
+        }
+        Ok(self.finder.find(repeat_seq.as_bytes(), motif.as_bytes()))
+    }
+}
+
+/// A row of a per-sample interruption profile
+#[pyclass(get_all, set_all, from_py_object, module = "strif")]
+#[derive(Clone)]
+struct ProfileRecord {
+    locus_id: String,
+    reference_region: String,
+    motif: String,
+    read_count: u32,
+    /// (interruption, repeat length, count)
+    interruption_counts: Vec<(String, u32, f64)>,
+    mean_purity: Option<f64>,
+    median_purity: Option<f64>,
+    mean_confidence: Option<f64>,
+    max_confidence: Option<f64>,
+    median_copy_number: Option<f64>,
+}
+
+#[pymethods]
+impl ProfileRecord {
+    #[new]
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(signature = (locus_id, reference_region, motif, read_count, interruption_counts=Vec::new(), mean_purity=None, median_purity=None, mean_confidence=None, max_confidence=None, median_copy_number=None))]
+    fn new(
+        locus_id: String,
+        reference_region: String,
+        motif: String,
+        read_count: u32,
+        interruption_counts: Vec<(String, u32, f64)>,
+        mean_purity: Option<f64>,
+        median_purity: Option<f64>,
+        mean_confidence: Option<f64>,
+        max_confidence: Option<f64>,
+        median_copy_number: Option<f64>,
+    ) -> Self {
+        ProfileRecord {
+            locus_id,
+            reference_region,
+            motif,
+            read_count,
+            interruption_counts,
+            mean_purity,
+            median_purity,
+            mean_confidence,
+            max_confidence,
+            median_copy_number,
+        }
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "ProfileRecord(locus_id={:?}, read_count={}, interruptions={})",
+            self.locus_id,
+            self.read_count,
+            self.interruption_counts.len()
+        )
+    }
+}
+
+impl From<strif_core::ProfileRecord> for ProfileRecord {
+    fn from(record: strif_core::ProfileRecord) -> Self {
+        ProfileRecord {
+            locus_id: record.locus_id,
+            reference_region: record.reference_region,
+            motif: record.motif,
+            read_count: record.read_count,
+            interruption_counts: record.interruption_counts,
+            mean_purity: record.mean_purity,
+            median_purity: record.median_purity,
+            mean_confidence: record.mean_confidence,
+            max_confidence: record.max_confidence,
+            median_copy_number: record.median_copy_number,
+        }
+    }
+}
+
+impl From<ProfileRecord> for strif_core::ProfileRecord {
+    fn from(record: ProfileRecord) -> Self {
+        strif_core::ProfileRecord {
+            locus_id: record.locus_id,
+            reference_region: record.reference_region,
+            motif: record.motif,
+            read_count: record.read_count,
+            interruption_counts: record.interruption_counts,
+            mean_purity: record.mean_purity,
+            median_purity: record.median_purity,
+            mean_confidence: record.mean_confidence,
+            max_confidence: record.max_confidence,
+            median_copy_number: record.median_copy_number,
+        }
+    }
+}
+
+/// A row of a merged interruption profile
+#[pyclass(get_all, set_all, from_py_object, module = "strif")]
+#[derive(Clone)]
+struct MergedProfileRecord {
+    locus_id: String,
+    reference_region: String,
+    motif: String,
+    /// (sample, read count)
+    read_counts: Vec<(String, u32)>,
+    /// (sample, interruption, normalized count)
+    interruption_counts: Vec<(String, String, f64)>,
+}
+
+#[pymethods]
+impl MergedProfileRecord {
+    #[new]
+    #[pyo3(signature = (locus_id, reference_region, motif, read_counts=Vec::new(), interruption_counts=Vec::new()))]
+    fn new(
+        locus_id: String,
+        reference_region: String,
+        motif: String,
+        read_counts: Vec<(String, u32)>,
+        interruption_counts: Vec<(String, String, f64)>,
+    ) -> Self {
+        MergedProfileRecord {
+            locus_id,
+            reference_region,
+            motif,
+            read_counts,
+            interruption_counts,
+        }
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "MergedProfileRecord(locus_id={:?}, samples={}, interruptions={})",
+            self.locus_id,
+            self.read_counts.len(),
+            self.interruption_counts.len()
+        )
+    }
+}
+
+impl From<strif_core::MergedProfileRecord> for MergedProfileRecord {
+    fn from(record: strif_core::MergedProfileRecord) -> Self {
+        MergedProfileRecord {
+            locus_id: record.locus_id,
+            reference_region: record.reference_region,
+            motif: record.motif,
+            read_counts: record.read_counts,
+            interruption_counts: record.interruption_counts,
+        }
+    }
+}
+
+impl From<MergedProfileRecord> for strif_core::MergedProfileRecord {
+    fn from(record: MergedProfileRecord) -> Self {
+        strif_core::MergedProfileRecord {
+            locus_id: record.locus_id,
+            reference_region: record.reference_region,
+            motif: record.motif,
+            read_counts: record.read_counts,
+            interruption_counts: record.interruption_counts,
+        }
+    }
+}
+
+/// Reads the records of a per-sample profile TSV
+#[pyfunction]
+fn read_profile(path: PathBuf) -> PyResult<Vec<ProfileRecord>> {
+    let records = strif_core::read_profile(&path).map_err(to_py_err)?;
+    Ok(records.into_iter().map(ProfileRecord::from).collect())
+}
+
+/// Writes records as a per-sample profile TSV
+#[pyfunction]
+fn write_profile(path: PathBuf, records: Vec<ProfileRecord>) -> PyResult<()> {
+    let records: Vec<strif_core::ProfileRecord> = records.into_iter().map(Into::into).collect();
+    let mut out = BufWriter::new(File::create(&path)?);
+    strif_core::write_profile(&mut out, &records).map_err(to_py_err)
+}
+
+/// Reads the records of a merged profile TSV
+#[pyfunction]
+fn read_merged_profile(path: PathBuf) -> PyResult<Vec<MergedProfileRecord>> {
+    let records = strif_core::read_merged_profile(&path).map_err(to_py_err)?;
+    Ok(records.into_iter().map(MergedProfileRecord::from).collect())
+}
+
+/// Writes records as a merged profile TSV
+#[pyfunction]
+fn write_merged_profile(path: PathBuf, records: Vec<MergedProfileRecord>) -> PyResult<()> {
+    let records: Vec<strif_core::MergedProfileRecord> =
+        records.into_iter().map(Into::into).collect();
+    let mut out = BufWriter::new(File::create(&path)?);
+    strif_core::write_merged_profile(&mut out, &records).map_err(to_py_err)
+}
+
+#[pymodule]
+fn strif(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    m.add_class::<CatalogLocus>()?;
+    m.add_class::<InterruptionFinder>()?;
+    m.add_class::<ProfileRecord>()?;
+    m.add_class::<MergedProfileRecord>()?;
+    m.add_function(wrap_pyfunction!(load_catalog, m)?)?;
+    m.add_function(wrap_pyfunction!(read_profile, m)?)?;
+    m.add_function(wrap_pyfunction!(write_profile, m)?)?;
+    m.add_function(wrap_pyfunction!(read_merged_profile, m)?)?;
+    m.add_function(wrap_pyfunction!(write_merged_profile, m)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_written_from_python_are_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profile.tsv");
+        let record = ProfileRecord::new(
+            "HTT".to_string(),
+            "chr4:3074876-3074933".to_string(),
+            "CAG".to_string(),
+            12,
+            vec![("CAA".to_string(), 57, 2.0)],
+            Some(0.98),
+            None,
+            None,
+            None,
+            None,
+        );
+        write_profile(path.clone(), vec![record]).unwrap();
+        let records = read_profile(path).unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].locus_id, "HTT");
+        assert_eq!(records[0].read_count, 12);
+        assert_eq!(
+            records[0].interruption_counts,
+            [("CAA".to_string(), 57, 2.0)]
+        );
+        assert_eq!(records[0].mean_purity, Some(0.98));
+        assert_eq!(records[0].median_purity, None);
+    }
+
+    #[test]
+    fn merged_records_convert_to_and_from_the_core_records() {
+        let record = MergedProfileRecord::new(
+            "HTT".to_string(),
+            "chr4:3074876-3074933".to_string(),
+            "CAG".to_string(),
+            vec![("s1".to_string(), 10)],
+            vec![("s1".to_string(), "CAA".to_string(), 0.5)],
+        );
+        let core: strif_core::MergedProfileRecord = record.into();
+        assert_eq!(core.read_counts, [("s1".to_string(), 10)]);
+        let record = MergedProfileRecord::from(core);
+        assert_eq!(
+            record.interruption_counts,
+            [("s1".to_string(), "CAA".to_string(), 0.5)]
+        );
+    }
+
+    #[test]
+    fn the_finder_uses_the_scores_of_profile() {
+        let mut finder = InterruptionFinder::new(1, 8, 10, 1);
+        assert_eq!(
+            finder.find("CAGCAGCAACAGCAG", "CAG").unwrap(),
+            ["A".to_string()]
+        );
+        assert!(finder.find("CAGCAG", "CAG").unwrap().is_empty());
+        assert!(finder.find("CAGCAG", "").is_err());
+    }
+}
@@ -14,7 +14,7 @@ def run_command(
     gap_extend_score=None,
     visualize=False,
 ):
-    command = f"cargo run --release -- profile {repeat_seqs} {str_catalog} {output}"
+    command = f"cargo run --release -- profile {repeat_seqs} {str_catalog} {output} --force"
     if match_score is not None:
         command += f" -A={match_score}"
     if mismatch_score is not None:
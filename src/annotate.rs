@@ -0,0 +1,558 @@
+use std::io::prelude::*;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Ok, Result};
+use log::{info, warn};
+
+use crate::error::field;
+use crate::plan::Plan;
+use crate::schema::{self, OutputKind};
+use crate::utils::{
+    create_output, get_default_out_path, open_maybe_gzipped, reference_span, Region,
+};
+
+/// The genic context of a locus, in order of increasing precedence
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum GenicContext {
+    Intergenic,
+    Promoter,
+    Genic,
+    Intron,
+    Exon,
+    Utr3,
+    Utr5,
+}
+
+impl GenicContext {
+    pub fn name(&self) -> &'static str {
+        match self {
+            GenicContext::Intergenic => "intergenic",
+            GenicContext::Promoter => "promoter",
+            GenicContext::Genic => "genic",
+            GenicContext::Intron => "intron",
+            GenicContext::Exon => "exon",
+            GenicContext::Utr3 => "3_utr",
+            GenicContext::Utr5 => "5_utr",
+        }
+    }
+}
+
+struct Feature {
+    start: u64,
+    end: u64,
+    gene: String,
+    context: GenicContext,
+}
+
+/// Gene features indexed by chromosome for overlap queries
+pub struct Annotation {
+    features: HashMap<String, Vec<Feature>>,
+    max_feature_len: HashMap<String, u64>,
+    promoter_size: u64,
+}
+
+impl Annotation {
+    fn new(promoter_size: u64) -> Self {
+        Self {
+            features: HashMap::new(),
+            max_feature_len: HashMap::new(),
+            promoter_size,
+        }
+    }
+
+    fn add(&mut self, chrom: &str, start: u64, end: u64, gene: &str, context: GenicContext) {
+        self.features
+            .entry(chrom.to_string())
+            .or_default()
+            .push(Feature {
+                start,
+                end,
+                gene: gene.to_string(),
+                context,
+            });
+        let max_len = self.max_feature_len.entry(chrom.to_string()).or_default();
+        *max_len = (*max_len).max(end - start);
+    }
+
+    /// Adds a gene body and its promoter, which lies upstream of the
+    /// transcription start site on the gene's strand
+    fn add_gene(
+        &mut self,
+        chrom: &str,
+        start: u64,
+        end: u64,
+        reverse_strand: bool,
+        gene: &str,
+        context: GenicContext,
+    ) {
+        self.add(chrom, start, end, gene, context);
+        let promoter_size = self.promoter_size;
+        if promoter_size > 0 {
+            if reverse_strand {
+                self.add(
+                    chrom,
+                    end,
+                    end + promoter_size,
+                    gene,
+                    GenicContext::Promoter,
+                );
+            } else {
+                let promoter_start = start.saturating_sub(promoter_size);
+                self.add(chrom, promoter_start, start, gene, GenicContext::Promoter);
+            }
+        }
+    }
+
+    fn sort(&mut self) {
+        for features in self.features.values_mut() {
+            features.sort_by_key(|f| f.start);
+        }
+    }
+
+    /// Returns the genes overlapping a region and the highest precedence
+    /// genic context among the overlapping features
+    pub fn annotate(&self, region: &Region) -> (Vec<String>, GenicContext) {
+        let mut genes: Vec<String> = Vec::new();
+        let mut context = GenicContext::Intergenic;
+
+        let features = match self.features.get(&region.chrom) {
+            Some(features) => features,
+            None => return (genes, context),
+        };
+        let max_len = self.max_feature_len[&region.chrom];
+
+        // features are sorted by start, so only those starting before the end of
+        // the region and no further back than the longest feature can overlap
+        let last = features.partition_point(|f| f.start < region.end);
+        for feature in features[..last].iter().rev() {
+            if feature.start + max_len < region.start {
+                break;
+            }
+            if feature.end <= region.start {
+                continue;
+            }
+            context = context.max(feature.context);
+            if feature.context != GenicContext::Promoter && !genes.contains(&feature.gene) {
+                genes.push(feature.gene.clone());
+            }
+        }
+        if genes.is_empty() && context == GenicContext::Promoter {
+            // report the gene whose promoter the locus lies in
+            for feature in features[..last].iter().rev() {
+                if feature.start + max_len < region.start {
+                    break;
+                }
+                if feature.end > region.start && !genes.contains(&feature.gene) {
+                    genes.push(feature.gene.clone());
+                }
+            }
+        }
+        genes.sort();
+        (genes, context)
+    }
+}
+
+/// Loads gene features from a GFF3 file. Genes provide gene bodies and
+/// promoters, exons/CDSs and UTRs refine the context within genes, and the
+/// remainder of a gene body is treated as intronic.
+pub fn load_gff3(path: &Path, promoter_size: u64) -> Result<Annotation> {
+    let mut annotation = Annotation::new(promoter_size);
+    // transcript/exon features refer to their gene through Parent attributes
+    let mut gene_names: HashMap<String, String> = HashMap::new();
+    let mut parents: HashMap<String, String> = HashMap::new();
+    let mut pending: Vec<(String, u64, u64, String, GenicContext)> = Vec::new();
+
+    let reader = BufReader::new(File::open(path)?);
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 9 {
+            bail!(
+                "{} line {} has {} columns, expected 9",
+                path.display(),
+                i + 1,
+                fields.len()
+            );
+        }
+        let chrom = fields[0];
+        let feature_type = fields[2];
+        // GFF3 is 1-based and fully closed, convert to 0-based half-open
+        let start: u64 = fields[3]
+            .parse::<u64>()
+            .with_context(|| format!("Invalid start in {} line {}", path.display(), i + 1))?
+            .saturating_sub(1);
+        let end: u64 = fields[4]
+            .parse()
+            .with_context(|| format!("Invalid end in {} line {}", path.display(), i + 1))?;
+        let reverse_strand = fields[6] == "-";
+        let attributes = parse_gff3_attributes(fields[8]);
+        let id = attributes.get("ID").cloned().unwrap_or_default();
+        let parent = attributes.get("Parent").cloned();
+
+        match feature_type {
+            "gene" | "ncRNA_gene" | "pseudogene" => {
+                let name = ["Name", "gene_name", "gene", "ID"]
+                    .iter()
+                    .find_map(|key| attributes.get(*key))
+                    .cloned()
+                    .unwrap_or_else(|| format!("{}:{}-{}", chrom, start, end));
+                annotation.add_gene(
+                    chrom,
+                    start,
+                    end,
+                    reverse_strand,
+                    &name,
+                    GenicContext::Intron,
+                );
+                gene_names.insert(id, name);
+            }
+            "exon" | "CDS" | "five_prime_UTR" | "three_prime_UTR" => {
+                let context = match feature_type {
+                    "five_prime_UTR" => GenicContext::Utr5,
+                    "three_prime_UTR" => GenicContext::Utr3,
+                    _ => GenicContext::Exon,
+                };
+                if let Some(parent) = parent {
+                    pending.push((chrom.to_string(), start, end, parent, context));
+                }
+            }
+            _ => {
+                if let Some(parent) = parent {
+                    parents.insert(id, parent);
+                }
+            }
+        }
+    }
+
+    // resolve exons to gene names through their transcripts
+    for (chrom, start, end, parent, context) in pending {
+        let mut id = parent.split(',').next().unwrap().to_string();
+        let mut depth = 0;
+        while !gene_names.contains_key(&id) && depth < 8 {
+            match parents.get(&id) {
+                Some(parent) => id = parent.clone(),
+                None => break,
+            }
+            depth += 1;
+        }
+        match gene_names.get(&id) {
+            Some(gene) => annotation.add(&chrom, start, end, gene, context),
+            None => warn!("Could not resolve the gene of a feature with parent {}", id),
+        }
+    }
+
+    annotation.sort();
+    Ok(annotation)
+}
+
+/// Loads gene bodies from a BED file with the gene name in the fourth
+/// column and an optional strand in the sixth column
+pub fn load_bed(path: &Path, promoter_size: u64) -> Result<Annotation> {
+    let mut annotation = Annotation::new(promoter_size);
+    let reader = BufReader::new(File::open(path)?);
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+            || line.trim().is_empty()
+        {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 4 {
+            bail!(
+                "{} line {} has {} columns, expected at least 4",
+                path.display(),
+                i + 1,
+                fields.len()
+            );
+        }
+        let start: u64 = fields[1]
+            .parse()
+            .with_context(|| format!("Invalid start in {} line {}", path.display(), i + 1))?;
+        let end: u64 = fields[2]
+            .parse()
+            .with_context(|| format!("Invalid end in {} line {}", path.display(), i + 1))?;
+        let reverse_strand = fields.get(5) == Some(&"-");
+        annotation.add_gene(
+            fields[0],
+            start,
+            end,
+            reverse_strand,
+            fields[3],
+            GenicContext::Genic,
+        );
+    }
+    annotation.sort();
+    Ok(annotation)
+}
+
+/// Loads the annotation from a BED or GFF3 file based on its extension
+pub fn load_annotation(path: &Path, promoter_size: u64) -> Result<Annotation> {
+    let name = path.to_string_lossy().to_lowercase();
+    if name.ends_with(".bed") {
+        load_bed(path, promoter_size)
+    } else if name.ends_with(".gff3") || name.ends_with(".gff") {
+        load_gff3(path, promoter_size)
+    } else {
+        bail!(
+            "Could not determine the format of {}, expected a .bed or .gff3 file",
+            path.display()
+        )
+    }
+}
+
+fn parse_gff3_attributes(s: &str) -> HashMap<String, String> {
+    s.split(';')
+        .filter_map(|attribute| attribute.split_once('='))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+/// Loads a two column TSV of locus IDs and disease labels (no header)
+fn load_disease_loci(path: &Path) -> Result<HashMap<String, String>> {
+    let mut disease_loci: HashMap<String, String> = HashMap::new();
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(false)
+        .comment(Some(b'#'))
+        .from_path(path)?;
+    for result in reader.records() {
+        let record = result?;
+        let (locus_id, label) = match (record.get(0), record.get(1)) {
+            (Some(locus_id), Some(label)) => (locus_id, label),
+            _ => bail!(
+                "{} line {} should have a locus ID and a disease label",
+                path.display(),
+                record.position().map(|p| p.line()).unwrap_or(0)
+            ),
+        };
+        disease_loci.insert(locus_id.to_string(), label.to_string());
+    }
+    Ok(disease_loci)
+}
+
+pub fn annotate(
+    input: PathBuf,
+    annotation: PathBuf,
+    out_path: PathBuf,
+    disease_loci: Option<PathBuf>,
+    promoter_size: u64,
+) -> Result<()> {
+    let (input_kind, out_kind) = match schema::detect(&input)? {
+        OutputKind::Profile => (OutputKind::Profile, OutputKind::AnnotatedProfile),
+        OutputKind::MergedProfile => (
+            OutputKind::MergedProfile,
+            OutputKind::AnnotatedMergedProfile,
+        ),
+        kind => bail!(
+            "{} is a '{}' file, expected a profile or merged profile",
+            input.display(),
+            kind.name()
+        ),
+    };
+
+    info!("Loading annotation...");
+    let annotation = load_annotation(&annotation, promoter_size)?;
+    let disease_loci = match disease_loci {
+        Some(path) => load_disease_loci(&path)?,
+        None => HashMap::new(),
+    };
+
+    info!("Annotating loci...");
+    let file = open_maybe_gzipped(&input)
+        .with_context(|| format!("Could not open {}", input.display()))?;
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(true)
+        .comment(Some(b'#'))
+        .from_reader(file);
+    let headers = reader.headers()?.clone();
+    schema::validate(&input, &headers, input_kind)?;
+    let source = input.display();
+
+    let mut out_file = create_output(&out_path)?;
+    schema::write_header(&mut out_file, out_kind)?;
+
+    for result in reader.records() {
+        let record: csv::StringRecord = result?;
+        let locus_id: &str = field(&record, 0, &source)?;
+        let reference_region: &str = field(&record, 1, &source)?;
+
+        let (genes, context) = reference_region
+            .parse::<Region>()
+            .map(|region| annotation.annotate(&region))
+            .unwrap_or_else(|e| {
+                warn!("Could not annotate {}: {}", locus_id, e);
+                (Vec::new(), GenicContext::Intergenic)
+            });
+        let genes_str = if genes.is_empty() {
+            ".".to_string()
+        } else {
+            genes.join(",")
+        };
+        let disease = disease_loci
+            .get(locus_id)
+            .map(|d| d.as_str())
+            .unwrap_or(".");
+
+        // columns added to the input since it was written are left missing,
+        // except the coordinates of profiles, which are parsed from the region
+        let mut fields: Vec<String> = record.iter().map(str::to_string).collect();
+        let written = fields.len();
+        let width = input_kind.columns().map_or(0, |columns| columns.len());
+        fields.resize(width.max(written), ".".to_string());
+        if input_kind == OutputKind::Profile && written < 8 {
+            if let Some(span) = reference_span(reference_region) {
+                fields[7] = span.chrom;
+                fields[8] = span.start.to_string();
+                fields[9] = span.end.to_string();
+            }
+        }
+        writeln!(
+            out_file,
+            "{}\t{}\t{}\t{}",
+            fields.join("\t"),
+            genes_str,
+            context.name(),
+            disease
+        )?;
+    }
+
+    out_file.finish()?;
+
+    info!("Done!");
+
+    Ok(())
+}
+
+/// The command line arguments of `strif annotate`
+#[derive(Debug, clap::Args)]
+pub struct AnnotateArgs {
+    /// The path to the profile or merged profile
+    pub input: PathBuf,
+
+    /// The path to a gene annotation in BED (name in the fourth column) or GFF3 format
+    pub annotation: PathBuf,
+
+    /// The path to the annotated output. Defaults to the same directory as the input.
+    pub output: Option<PathBuf>,
+
+    /// The path to a TSV file of locus IDs and known disease labels (no header)
+    #[clap(short = 'd', long)]
+    pub disease_loci: Option<PathBuf>,
+
+    /// The number of base pairs upstream of a gene considered its promoter
+    #[clap(short = 'p', long, default_value = "1000")]
+    pub promoter_size: u64,
+}
+
+impl AnnotateArgs {
+    /// Runs `strif annotate`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let AnnotateArgs {
+            input,
+            annotation,
+            output,
+            disease_loci,
+            promoter_size,
+        } = self;
+        let out_path: PathBuf =
+            output.unwrap_or_else(|| get_default_out_path(&input, "annotated", "tsv"));
+        Plan::new("annotate", dry_run, force)
+            .records("input", &input, "loci")
+            .input("annotation", &annotation)
+            .output("annotated output", &out_path)
+            .execute(|| annotate(input, annotation, out_path, disease_loci, promoter_size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    const GFF3: &str = "##gff-version 3
+chr1\t.\tgene\t1001\t5000\t.\t+\t.\tID=gene1;Name=ABC
+chr1\t.\tmRNA\t1001\t5000\t.\t+\t.\tID=tx1;Parent=gene1
+chr1\t.\tfive_prime_UTR\t1001\t1100\t.\t+\t.\tParent=tx1
+chr1\t.\texon\t1001\t1500\t.\t+\t.\tParent=tx1
+chr1\t.\tgene\t8001\t9000\t.\t-\t.\tID=gene2;Name=XYZ
+";
+
+    fn annotate_region(annotation: &Annotation, region: &str) -> (Vec<String>, &'static str) {
+        let (genes, context) = annotation.annotate(&region.parse().unwrap());
+        (genes, context.name())
+    }
+
+    #[test]
+    fn gff3_features_refine_the_context_within_genes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("genes.gff3");
+        fs::write(&path, GFF3).unwrap();
+        let annotation = load_annotation(&path, 500).unwrap();
+
+        let abc = vec!["ABC".to_string()];
+        assert_eq!(
+            annotate_region(&annotation, "chr1:1050-1060"),
+            (abc.clone(), "5_utr")
+        );
+        assert_eq!(
+            annotate_region(&annotation, "chr1:1200-1210"),
+            (abc.clone(), "exon")
+        );
+        assert_eq!(
+            annotate_region(&annotation, "chr1:3000-3010"),
+            (abc.clone(), "intron")
+        );
+        // promoters lie upstream on the strand of the gene
+        assert_eq!(
+            annotate_region(&annotation, "chr1:600-610"),
+            (abc, "promoter")
+        );
+        let xyz = vec!["XYZ".to_string()];
+        assert_eq!(
+            annotate_region(&annotation, "chr1:9200-9210"),
+            (xyz, "promoter")
+        );
+        assert_eq!(
+            annotate_region(&annotation, "chr1:7000-7010"),
+            (vec![], "intergenic")
+        );
+        assert_eq!(
+            annotate_region(&annotation, "chr2:1050-1060"),
+            (vec![], "intergenic")
+        );
+    }
+
+    #[test]
+    fn bed_genes_overlapping_a_locus_are_all_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("genes.bed");
+        fs::write(
+            &path,
+            "track name=genes\nchr1\t100\t10000\tLONG\nchr1\t5000\t5100\tSHORT\t0\t-\n",
+        )
+        .unwrap();
+        let annotation = load_annotation(&path, 0).unwrap();
+        assert_eq!(
+            annotate_region(&annotation, "chr1:5050-5060"),
+            (vec!["LONG".to_string(), "SHORT".to_string()], "genic")
+        );
+        assert_eq!(
+            annotate_region(&annotation, "chr1:9000-9010"),
+            (vec!["LONG".to_string()], "genic")
+        );
+        assert!(load_annotation(&dir.path().join("genes.txt"), 0).is_err());
+    }
+}
@@ -0,0 +1,267 @@
+//! Arrow IPC streams of profiles and merged profiles, so that consumers such
+//! as polars or pyarrow can read the output of a pipe without parsing TSV
+
+pub(crate) use imp::{write_merged_profile, write_profile};
+
+/// The number of loci in each record batch
+#[cfg(feature = "arrow")]
+const BATCH_SIZE: usize = 8192;
+
+#[cfg(feature = "arrow")]
+mod imp {
+    use std::collections::HashMap;
+    use std::io::Write;
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use arrow_array::{ArrayRef, Float64Array, RecordBatch, StringArray, UInt32Array, UInt64Array};
+    use arrow_ipc::writer::StreamWriter;
+    use arrow_schema::{DataType, Field, Schema};
+
+    use super::BATCH_SIZE;
+    use crate::merge::MergedProfileRecord;
+    use crate::profile::ProfileRecord;
+    use crate::provenance;
+    use crate::schema::{OutputKind, SCHEMA_VERSION};
+    use crate::utils::reference_span;
+
+    /// The schema of a kind of output, with the schema line of its TSV as
+    /// metadata along with the provenance of this process. Packed count
+    /// columns are kept as strings, as in Parquet files.
+    fn schema(kind: OutputKind, types: &[(DataType, bool)]) -> Arc<Schema> {
+        let fields: Vec<Field> = kind
+            .columns()
+            .unwrap_or_default()
+            .iter()
+            .zip(types)
+            .map(|(name, (data_type, nullable))| Field::new(*name, data_type.clone(), *nullable))
+            .collect();
+        let mut metadata = HashMap::from([(
+            "strif_schema".to_string(),
+            format!("{};version={}", kind.name(), SCHEMA_VERSION),
+        )]);
+        metadata.extend(
+            provenance::fields()
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value)),
+        );
+        Arc::new(Schema::new_with_metadata(fields, metadata))
+    }
+
+    fn strings<'a>(values: impl Iterator<Item = &'a str>) -> ArrayRef {
+        Arc::new(StringArray::from_iter_values(values))
+    }
+
+    /// Writes records in batches, building the columns of each batch with `columns`
+    fn write_batches<W, T, F>(
+        out: W,
+        schema: Arc<Schema>,
+        records: impl Iterator<Item = Result<T>>,
+        columns: F,
+    ) -> Result<usize>
+    where
+        W: Write,
+        F: Fn(&[T]) -> Vec<ArrayRef>,
+    {
+        let mut writer = StreamWriter::try_new(out, &schema)?;
+        let mut batch: Vec<T> = Vec::with_capacity(BATCH_SIZE);
+        let mut count = 0;
+        for record in records {
+            batch.push(record?);
+            count += 1;
+            if batch.len() == BATCH_SIZE {
+                writer.write(&RecordBatch::try_new(schema.clone(), columns(&batch))?)?;
+                batch.clear();
+            }
+        }
+        if !batch.is_empty() {
+            writer.write(&RecordBatch::try_new(schema.clone(), columns(&batch))?)?;
+        }
+        writer.finish()?;
+        Ok(count)
+    }
+
+    /// Writes profile records as an Arrow IPC stream and returns how many
+    /// there were
+    pub fn write_profile<W: Write>(
+        out: W,
+        records: impl Iterator<Item = Result<ProfileRecord>>,
+    ) -> Result<usize> {
+        let schema = schema(
+            OutputKind::Profile,
+            &[
+                (DataType::Utf8, false),
+                (DataType::Utf8, false),
+                (DataType::Utf8, false),
+                (DataType::UInt32, false),
+                (DataType::Utf8, false),
+                (DataType::Float64, true),
+                (DataType::Float64, true),
+                (DataType::Utf8, true),
+                (DataType::UInt64, true),
+                (DataType::UInt64, true),
+                (DataType::Float64, true),
+                (DataType::Float64, true),
+                (DataType::Float64, true),
+            ],
+        );
+        write_batches(out, schema, records, |records: &[ProfileRecord]| {
+            let spans: Vec<_> = records
+                .iter()
+                .map(|r| reference_span(&r.reference_region))
+                .collect();
+            vec![
+                strings(records.iter().map(|r| r.locus_id.as_str())),
+                strings(records.iter().map(|r| r.reference_region.as_str())),
+                strings(records.iter().map(|r| r.motif.as_str())),
+                Arc::new(UInt32Array::from_iter_values(
+                    records.iter().map(|r| r.read_count),
+                )),
+                Arc::new(StringArray::from_iter_values(
+                    records.iter().map(|r| r.packed_interruption_counts()),
+                )),
+                Arc::new(Float64Array::from_iter(
+                    records.iter().map(|r| r.mean_purity),
+                )),
+                Arc::new(Float64Array::from_iter(
+                    records.iter().map(|r| r.median_purity),
+                )),
+                Arc::new(StringArray::from_iter(
+                    spans
+                        .iter()
+                        .map(|span| span.as_ref().map(|s| s.chrom.as_str())),
+                )),
+                Arc::new(UInt64Array::from_iter(
+                    spans.iter().map(|span| span.as_ref().map(|s| s.start)),
+                )),
+                Arc::new(UInt64Array::from_iter(
+                    spans.iter().map(|span| span.as_ref().map(|s| s.end)),
+                )),
+                Arc::new(Float64Array::from_iter(
+                    records.iter().map(|r| r.mean_confidence),
+                )),
+                Arc::new(Float64Array::from_iter(
+                    records.iter().map(|r| r.max_confidence),
+                )),
+                Arc::new(Float64Array::from_iter(
+                    records.iter().map(|r| r.median_copy_number),
+                )),
+            ]
+        })
+    }
+
+    /// Writes merged profile records as an Arrow IPC stream and returns how
+    /// many there were
+    pub fn write_merged_profile<W: Write>(
+        out: W,
+        records: impl Iterator<Item = Result<MergedProfileRecord>>,
+    ) -> Result<usize> {
+        let schema = schema(
+            OutputKind::MergedProfile,
+            &[
+                (DataType::Utf8, false),
+                (DataType::Utf8, false),
+                (DataType::Utf8, false),
+                (DataType::Utf8, false),
+                (DataType::Utf8, false),
+            ],
+        );
+        write_batches(out, schema, records, |records: &[MergedProfileRecord]| {
+            vec![
+                strings(records.iter().map(|r| r.locus_id.as_str())),
+                strings(records.iter().map(|r| r.reference_region.as_str())),
+                strings(records.iter().map(|r| r.motif.as_str())),
+                Arc::new(StringArray::from_iter_values(
+                    records.iter().map(|r| r.packed_read_counts()),
+                )),
+                Arc::new(StringArray::from_iter_values(
+                    records.iter().map(|r| r.packed_interruption_counts()),
+                )),
+            ]
+        })
+    }
+}
+
+#[cfg(not(feature = "arrow"))]
+mod imp {
+    use std::io::Write;
+
+    use anyhow::{bail, Result};
+
+    use crate::merge::MergedProfileRecord;
+    use crate::profile::ProfileRecord;
+
+    pub fn write_profile<W: Write>(
+        _out: W,
+        _records: impl Iterator<Item = Result<ProfileRecord>>,
+    ) -> Result<usize> {
+        bail!("Arrow IPC output requires strif to be built with the `arrow` feature")
+    }
+
+    pub fn write_merged_profile<W: Write>(
+        _out: W,
+        _records: impl Iterator<Item = Result<MergedProfileRecord>>,
+    ) -> Result<usize> {
+        bail!("Arrow IPC output requires strif to be built with the `arrow` feature")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{merged_record, profile_record};
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn streams_hold_the_columns_of_the_tsv() {
+        use arrow_array::{Array, StringArray, UInt32Array, UInt64Array};
+        use arrow_ipc::reader::StreamReader;
+
+        let records = (0..BATCH_SIZE + 1).map(|i| {
+            let mut record = profile_record(&format!("L{}", i), i as u32, &[("A", 30, 1.5)]);
+            if i == 1 {
+                record.reference_region = "unplaced".to_string();
+            }
+            Ok(record)
+        });
+        let mut out = Vec::new();
+        assert_eq!(write_profile(&mut out, records).unwrap(), BATCH_SIZE + 1);
+        let reader = StreamReader::try_new(out.as_slice(), None).unwrap();
+        assert_eq!(
+            reader.schema().metadata()["strif_schema"],
+            format!("profile;version={}", crate::schema::SCHEMA_VERSION)
+        );
+        let batches: Vec<_> = reader.map(Result::unwrap).collect();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[1].num_rows(), 1);
+        let batch = &batches[0];
+        let column = |name: &str| batch.column_by_name(name).unwrap().clone();
+        let read_counts = column("read_count");
+        let read_counts = read_counts.as_any().downcast_ref::<UInt32Array>().unwrap();
+        assert_eq!(read_counts.value(2), 2);
+        let counts = column("interruption_counts");
+        let counts = counts.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(counts.value(0), "A:30:1.5");
+        // loci without coordinates have null coordinate columns
+        let starts = column("start");
+        let starts = starts.as_any().downcast_ref::<UInt64Array>().unwrap();
+        assert_eq!(starts.value(0), 100);
+        assert!(starts.is_null(1));
+
+        let mut out = Vec::new();
+        let record = merged_record("HTT", &[("s1", 10)], &[("s1", "A", 0.5)]);
+        assert_eq!(
+            write_merged_profile(&mut out, [Ok(record)].into_iter()).unwrap(),
+            1
+        );
+    }
+
+    #[cfg(not(feature = "arrow"))]
+    #[test]
+    fn streams_need_the_arrow_feature() {
+        let records = [Ok(profile_record("HTT", 10, &[]))];
+        assert!(write_profile(Vec::new(), records.into_iter()).is_err());
+        let records = [Ok(merged_record("HTT", &[("s1", 10)], &[]))];
+        assert!(write_merged_profile(Vec::new(), records.into_iter()).is_err());
+    }
+}
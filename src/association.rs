@@ -0,0 +1,1578 @@
+use std::io::prelude::*;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
+    thread,
+};
+
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use statrs::distribution::{ChiSquared, ContinuousCDF, Normal, StudentsT};
+
+use crate::merge::{load_manifest, read_merged_profile};
+use crate::plan::Plan;
+use crate::schema::{self, OutputKind};
+use crate::threads::threads;
+use crate::utils::{create_output, get_default_out_path};
+
+const MAX_IRLS_ITERATIONS: usize = 25;
+const IRLS_TOLERANCE: f64 = 1e-8;
+
+/// Models relating interruption burden to a phenotype
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Model {
+    /// Linear regression for quantitative traits (t-test on the burden coefficient)
+    Linear,
+    /// Logistic regression for binary traits (Wald test on the burden coefficient)
+    Logistic,
+    /// Two-sided Mann-Whitney U test for binary traits without covariates
+    MannWhitney,
+    /// Kruskal-Wallis H test across the groups of a categorical trait (e.g. disease subtypes and
+    /// controls) without covariates
+    KruskalWallis,
+}
+
+impl Model {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Model::Linear => "linear",
+            Model::Logistic => "logistic",
+            Model::MannWhitney => "mann_whitney",
+            Model::KruskalWallis => "kruskal_wallis",
+        }
+    }
+}
+
+/// Multiple-testing corrections applied across all tests
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Correction {
+    /// Benjamini-Hochberg false discovery rate
+    Bh,
+    /// Bonferroni family-wise error rate
+    Bonferroni,
+    /// Report unadjusted p-values
+    None,
+}
+
+pub struct TestParams {
+    /// Phenotype column, defaults to the first column after the sample ID
+    pub phenotype: Option<String>,
+    pub covariates: Vec<String>,
+    /// Defaults to logistic regression for binary traits and linear regression otherwise
+    pub model: Option<Model>,
+    pub correction: Correction,
+    /// Minimum number of samples (per group for binary traits) to test a locus
+    pub min_samples: usize,
+    /// Test each interruption separately instead of the total burden of the locus
+    pub per_interruption: bool,
+    /// The number of permutations of the phenotypes used for empirical
+    /// p-values, or 0 for none
+    pub permutations: usize,
+    /// The seed of the permutations
+    pub seed: u64,
+    /// With the Kruskal-Wallis model, also compare each pair of groups with a
+    /// Mann-Whitney U test
+    pub pairwise: bool,
+    /// The phenotypes file is a `merge` manifest, whose second column is the
+    /// phenotype
+    pub manifest: bool,
+    /// A TSV with the covariates of the samples of a manifest
+    pub covariate_file: Option<PathBuf>,
+    /// Replace the values of a quantitative phenotype by their rank-based
+    /// inverse normal transform
+    pub inverse_normal: bool,
+}
+
+/// Which groups of a categorical phenotype a test compares
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    /// All groups at once, or the phenotype as a whole if it is not categorical
+    All,
+    /// Two groups by their indices, the first standing in for the cases
+    Pair(usize, usize),
+}
+
+/// The phenotype and covariates of a sample
+struct Phenotype {
+    value: f64,
+    covariates: Vec<f64>,
+}
+
+/// The burdens of a test, with the samples they belong to as indices of the
+/// phenotyped samples, so that the test can be rerun with permuted phenotypes
+struct TestData {
+    model: Model,
+    comparison: Comparison,
+    samples: Vec<usize>,
+    x: Vec<f64>,
+}
+
+struct TestResult {
+    locus_id: String,
+    reference_region: String,
+    motif: String,
+    interruption: String,
+    model: Model,
+    n_samples: usize,
+    effect: f64,
+    std_error: Option<f64>,
+    statistic: f64,
+    p_value: f64,
+    /// The groups of a categorical phenotype the test compares, e.g. `all` or
+    /// `subtype1_vs_control`
+    comparison: Option<String>,
+    /// The number of samples and mean burden of each group compared, as
+    /// `group:value` lists
+    group_sizes: Option<String>,
+    group_means: Option<String>,
+}
+
+/// Tests each locus of a merged profile for association between interruption
+/// burden and a phenotype
+pub fn association(
+    merged_profile: PathBuf,
+    phenotypes: PathBuf,
+    out_path: PathBuf,
+    params: TestParams,
+) -> Result<()> {
+    info!("Loading phenotypes...");
+    let categorical = params.model == Some(Model::KruskalWallis);
+    let (mut phenotypes, groups) = load_phenotypes(&phenotypes, &params, categorical)?;
+    let binary = phenotypes
+        .values()
+        .all(|p| p.value == 0.0 || p.value == 1.0);
+    let model = match params.model {
+        Some(model) => model,
+        None if binary => Model::Logistic,
+        None => Model::Linear,
+    };
+    if let Some(groups) = &groups {
+        if groups.len() < 2 {
+            bail!(
+                "The {} model needs a phenotype with at least two groups, found {}",
+                model.name(),
+                groups.join(", ")
+            );
+        }
+        if !params.covariates.is_empty() {
+            bail!("The {} model does not support covariates", model.name());
+        }
+    } else {
+        if params.pairwise {
+            bail!("--pairwise compares the groups of the kruskal_wallis model");
+        }
+        if model != Model::Linear && !binary {
+            bail!(
+                "The {} model needs a binary phenotype (0/1 or case/control)",
+                model.name()
+            );
+        }
+        if model == Model::MannWhitney && !params.covariates.is_empty() {
+            bail!("The mann_whitney model does not support covariates");
+        }
+    }
+    if params.inverse_normal {
+        if model != Model::Linear || binary {
+            bail!(
+                "--inverse-normal transforms quantitative phenotypes, which the linear model tests"
+            );
+        }
+        inverse_normal_transform(&mut phenotypes);
+    }
+    // samples are indexed in order of their IDs, so that permutations do not
+    // depend on the order of the phenotypes file
+    let mut sample_ids: Vec<&String> = phenotypes.keys().collect();
+    sample_ids.sort();
+    let sample_indices: HashMap<&str, usize> = sample_ids
+        .iter()
+        .enumerate()
+        .map(|(i, sample_id)| (sample_id.as_str(), i))
+        .collect();
+    let indexed: Vec<&Phenotype> = sample_ids.iter().map(|id| &phenotypes[*id]).collect();
+    match &groups {
+        Some(groups) => info!(
+            "Testing {} samples in {} groups ({}) with the {} model...",
+            phenotypes.len(),
+            groups.len(),
+            groups.join(", "),
+            model.name()
+        ),
+        None => info!(
+            "Testing {} samples with the {} model...",
+            phenotypes.len(),
+            model.name()
+        ),
+    }
+
+    let mut results: Vec<TestResult> = Vec::new();
+    let mut tests: Vec<TestData> = Vec::new();
+    let mut n_skipped: usize = 0;
+    for record in read_merged_profile(&merged_profile)? {
+        let mut samples: Vec<(&str, &Phenotype)> = record
+            .read_counts
+            .iter()
+            .filter_map(|(sample_id, _)| {
+                phenotypes
+                    .get(sample_id)
+                    .map(|phenotype| (sample_id.as_str(), phenotype))
+            })
+            .collect();
+
+        let enough_samples = match &groups {
+            // groups with too few samples at a locus are left out of its tests
+            Some(groups) => {
+                let mut sizes = vec![0; groups.len()];
+                for (_, p) in &samples {
+                    sizes[p.value as usize] += 1;
+                }
+                samples.retain(|(_, p)| sizes[p.value as usize] >= params.min_samples);
+                sizes.iter().filter(|n| **n >= params.min_samples).count() >= 2
+            }
+            None if model == Model::Linear => samples.len() >= params.min_samples,
+            None => {
+                let n_cases = samples.iter().filter(|(_, p)| p.value == 1.0).count();
+                n_cases >= params.min_samples && samples.len() - n_cases >= params.min_samples
+            }
+        };
+        if !enough_samples {
+            n_skipped += 1;
+            continue;
+        }
+
+        let mut burdens: HashMap<&str, HashMap<&str, f64>> = HashMap::new();
+        for (sample_id, interruption, count) in &record.interruption_counts {
+            let unit = if params.per_interruption {
+                interruption.as_str()
+            } else {
+                "."
+            };
+            *burdens
+                .entry(sample_id)
+                .or_default()
+                .entry(unit)
+                .or_default() += count;
+        }
+        let units: BTreeSet<&str> = if params.per_interruption {
+            samples
+                .iter()
+                .filter_map(|(sample_id, _)| burdens.get(sample_id))
+                .flat_map(|counts| counts.keys().copied())
+                .collect()
+        } else {
+            BTreeSet::from(["."])
+        };
+
+        for unit in units {
+            let x: Vec<f64> = samples
+                .iter()
+                .map(|(sample_id, _)| {
+                    *burdens
+                        .get(sample_id)
+                        .and_then(|counts| counts.get(unit))
+                        .unwrap_or(&0.0)
+                })
+                .collect();
+            let y: Vec<f64> = samples.iter().map(|(_, p)| p.value).collect();
+            let covariates: Vec<&[f64]> = samples
+                .iter()
+                .map(|(_, p)| p.covariates.as_slice())
+                .collect();
+
+            let mut comparisons = vec![(model, Comparison::All)];
+            if params.pairwise {
+                let present: BTreeSet<usize> = y.iter().map(|y| *y as usize).collect();
+                let present: Vec<usize> = present.into_iter().collect();
+                for (i, a) in present.iter().enumerate() {
+                    for b in &present[i + 1..] {
+                        comparisons.push((Model::MannWhitney, Comparison::Pair(*a, *b)));
+                    }
+                }
+            }
+            for (model, comparison) in comparisons {
+                let Some((effect, std_error, statistic, p_value)) =
+                    run_test(model, comparison, &x, &y, &covariates)
+                else {
+                    n_skipped += 1;
+                    continue;
+                };
+                let (comparison_name, group_sizes, group_means) = match &groups {
+                    Some(groups) => {
+                        let (sizes, means) = group_summaries(groups, comparison, &x, &y);
+                        let name = match comparison {
+                            Comparison::All => "all".to_string(),
+                            Comparison::Pair(a, b) => format!("{}_vs_{}", groups[a], groups[b]),
+                        };
+                        (Some(name), Some(sizes), Some(means))
+                    }
+                    None => (None, None, None),
+                };
+                let n_samples = match comparison {
+                    Comparison::All => samples.len(),
+                    Comparison::Pair(a, b) => y
+                        .iter()
+                        .filter(|y| **y == a as f64 || **y == b as f64)
+                        .count(),
+                };
+                results.push(TestResult {
+                    locus_id: record.locus_id.clone(),
+                    reference_region: record.reference_region.clone(),
+                    motif: record.motif.clone(),
+                    interruption: unit.to_string(),
+                    model,
+                    n_samples,
+                    effect,
+                    std_error,
+                    statistic,
+                    p_value,
+                    comparison: comparison_name,
+                    group_sizes,
+                    group_means,
+                });
+                if params.permutations > 0 {
+                    tests.push(TestData {
+                        model,
+                        comparison,
+                        samples: samples
+                            .iter()
+                            .map(|(sample_id, _)| sample_indices[sample_id])
+                            .collect(),
+                        x: x.clone(),
+                    });
+                }
+            }
+        }
+    }
+    if n_skipped > 0 {
+        warn!(
+            "Skipped {} test(s) with too few samples, no variation in burden or a model that did not converge",
+            n_skipped
+        );
+    }
+
+    let p_values: Vec<f64> = results.iter().map(|r| r.p_value).collect();
+    let adjusted = adjust_p_values(&p_values, params.correction);
+    let empirical: Option<(Vec<f64>, Vec<f64>)> = (params.permutations > 0).then(|| {
+        info!(
+            "Running {} permutations of {} test(s)...",
+            params.permutations,
+            tests.len()
+        );
+        empirical_p_values(&tests, &indexed, &p_values, &params, threads())
+    });
+    let mut order: Vec<usize> = (0..results.len()).collect();
+    order.sort_by(|&a, &b| {
+        p_values[a]
+            .total_cmp(&p_values[b])
+            .then(results[a].locus_id.cmp(&results[b].locus_id))
+            .then(results[a].interruption.cmp(&results[b].interruption))
+            .then(results[a].comparison.cmp(&results[b].comparison))
+    });
+
+    let mut out_file = create_output(&out_path)?;
+    schema::write_header(&mut out_file, OutputKind::Association)?;
+    for i in order {
+        let result = &results[i];
+        writeln!(
+            out_file,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            result.locus_id,
+            result.reference_region,
+            result.motif,
+            result.interruption,
+            result.model.name(),
+            result.n_samples,
+            result.effect,
+            result
+                .std_error
+                .map(|se| se.to_string())
+                .unwrap_or_else(|| ".".to_string()),
+            result.statistic,
+            result.p_value,
+            adjusted[i],
+            empirical
+                .as_ref()
+                .map_or(".".to_string(), |(p, _)| p[i].to_string()),
+            empirical
+                .as_ref()
+                .map_or(".".to_string(), |(_, fwer)| fwer[i].to_string()),
+            result.comparison.as_deref().unwrap_or("."),
+            result.group_sizes.as_deref().unwrap_or("."),
+            result.group_means.as_deref().unwrap_or(".")
+        )?;
+    }
+
+    info!("Wrote {} test(s)", results.len());
+    out_file.finish()?;
+
+    info!("Done!");
+    Ok(())
+}
+
+/// Tests the burdens of samples against their phenotypes with a model,
+/// returning (effect, standard error, statistic, p-value). A comparison of
+/// two groups of a categorical phenotype tests the samples of those groups
+/// only.
+fn run_test(
+    model: Model,
+    comparison: Comparison,
+    x: &[f64],
+    y: &[f64],
+    covariates: &[&[f64]],
+) -> Option<(f64, Option<f64>, f64, f64)> {
+    if let Comparison::Pair(a, b) = comparison {
+        let (a, b) = (a as f64, b as f64);
+        let (x, y): (Vec<f64>, Vec<f64>) = x
+            .iter()
+            .zip(y)
+            .filter(|(_, y)| **y == a || **y == b)
+            .map(|(x, y)| (*x, if *y == a { 1.0 } else { 0.0 }))
+            .unzip();
+        return mann_whitney_test(&x, &y);
+    }
+    match model {
+        Model::Linear => linear_test(x, y, covariates),
+        Model::Logistic => logistic_test(x, y, covariates),
+        Model::MannWhitney => mann_whitney_test(x, y),
+        Model::KruskalWallis => kruskal_wallis_test(x, y),
+    }
+}
+
+/// The number of samples and the mean burden of each group a test compares,
+/// as `group:value` lists in the order of the groups
+fn group_summaries(
+    groups: &[String],
+    comparison: Comparison,
+    x: &[f64],
+    y: &[f64],
+) -> (String, String) {
+    let mut sizes: Vec<String> = Vec::new();
+    let mut means: Vec<String> = Vec::new();
+    for (i, group) in groups.iter().enumerate() {
+        if matches!(comparison, Comparison::Pair(a, b) if i != a && i != b) {
+            continue;
+        }
+        let values: Vec<f64> = x
+            .iter()
+            .zip(y)
+            .filter(|(_, y)| **y == i as f64)
+            .map(|(x, _)| *x)
+            .collect();
+        if values.is_empty() {
+            continue;
+        }
+        sizes.push(format!("{}:{}", group, values.len()));
+        means.push(format!(
+            "{}:{}",
+            group,
+            values.iter().sum::<f64>() / values.len() as f64
+        ));
+    }
+    (sizes.join(","), means.join(","))
+}
+
+/// Computes empirical p-values by permuting the phenotypes and covariates of
+/// the samples, keeping their burdens, and rerunning every test on each
+/// permutation. The empirical p-value of a test is the fraction of
+/// permutations giving it a p-value at most its observed one, and its
+/// family-wise error estimate is the fraction of permutations whose smallest
+/// p-value over all tests is at most its observed one (min-P). Both count the
+/// observed labels as a permutation, so neither is 0. Permutations are split
+/// across threads, each shuffling the samples afresh with a generator seeded
+/// by its number so that the results do not depend on the number of threads.
+fn empirical_p_values(
+    tests: &[TestData],
+    phenotypes: &[&Phenotype],
+    p_values: &[f64],
+    params: &TestParams,
+    n_threads: usize,
+) -> (Vec<f64>, Vec<f64>) {
+    let n_threads = n_threads.clamp(1, params.permutations);
+    // for each thread, the permutations giving each test a p-value at most
+    // its observed one, and the smallest p-value of each permutation
+    let partials: Vec<(Vec<usize>, Vec<f64>)> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..n_threads)
+            .map(|worker| {
+                scope.spawn(move || {
+                    let mut exceedances = vec![0usize; tests.len()];
+                    let mut min_p_values: Vec<f64> = Vec::new();
+                    let mut labels: Vec<usize> = Vec::with_capacity(phenotypes.len());
+                    for permutation in (worker..params.permutations).step_by(n_threads) {
+                        let mut rng =
+                            StdRng::seed_from_u64(params.seed.wrapping_add(permutation as u64));
+                        // each permutation shuffles the identity, not the
+                        // previous permutation of its thread
+                        labels.clear();
+                        labels.extend(0..phenotypes.len());
+                        labels.shuffle(&mut rng);
+                        let mut min_p_value: f64 = 1.0;
+                        for (i, test) in tests.iter().enumerate() {
+                            let permuted: Vec<&Phenotype> = test
+                                .samples
+                                .iter()
+                                .map(|sample| phenotypes[labels[*sample]])
+                                .collect();
+                            let y: Vec<f64> = permuted.iter().map(|p| p.value).collect();
+                            let covariates: Vec<&[f64]> =
+                                permuted.iter().map(|p| p.covariates.as_slice()).collect();
+                            // tests that cannot be run count as not significant
+                            let p_value =
+                                run_test(test.model, test.comparison, &test.x, &y, &covariates)
+                                    .map_or(1.0, |(_, _, _, p_value)| p_value);
+                            if p_value <= p_values[i] {
+                                exceedances[i] += 1;
+                            }
+                            min_p_value = min_p_value.min(p_value);
+                        }
+                        min_p_values.push(min_p_value);
+                    }
+                    (exceedances, min_p_values)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("permutation thread panicked"))
+            .collect()
+    });
+
+    let mut exceedances = vec![0usize; tests.len()];
+    let mut min_p_values: Vec<f64> = Vec::with_capacity(params.permutations);
+    for (partial_exceedances, partial_min_p_values) in partials {
+        for (total, count) in exceedances.iter_mut().zip(partial_exceedances) {
+            *total += count;
+        }
+        min_p_values.extend(partial_min_p_values);
+    }
+    let denominator = (params.permutations + 1) as f64;
+    let empirical = exceedances
+        .iter()
+        .map(|count| (count + 1) as f64 / denominator)
+        .collect();
+    let fwer = p_values
+        .iter()
+        .map(|p_value| {
+            let count = min_p_values.iter().filter(|min| **min <= *p_value).count();
+            (count + 1) as f64 / denominator
+        })
+        .collect();
+    (empirical, fwer)
+}
+
+/// The phenotype of each sample and, for a categorical phenotype, its groups
+type Phenotypes = (HashMap<String, Phenotype>, Option<Vec<String>>);
+
+/// A sample of the phenotypes file as read, before its phenotype is parsed
+struct PhenotypeRow {
+    sample_id: String,
+    phenotype: String,
+    covariates: Vec<f64>,
+    line: u64,
+}
+
+/// Loads a TSV with a header row whose first column is the sample ID, or a
+/// `merge` manifest whose second column is the phenotype. The values of a
+/// categorical phenotype are group labels, which are returned in sorted order,
+/// and the value of each sample is the index of its group. Samples with a
+/// missing phenotype or covariate are left out.
+fn load_phenotypes(path: &Path, params: &TestParams, categorical: bool) -> Result<Phenotypes> {
+    let (rows, mut n_missing) = if params.manifest {
+        // the covariates of the samples of a manifest come from a file of
+        // their own, whose samples without them are left out
+        let (covariates, n_missing) = match &params.covariate_file {
+            Some(covariate_file) => {
+                let (rows, n_missing) = read_phenotype_rows(covariate_file, params, false)?;
+                let covariates: HashMap<String, Vec<f64>> = rows
+                    .into_iter()
+                    .map(|row| (row.sample_id, row.covariates))
+                    .collect();
+                (Some(covariates), n_missing)
+            }
+            None if !params.covariates.is_empty() => {
+                bail!("With --manifest, the --covariate columns are read from --covariate-file")
+            }
+            None => (None, 0),
+        };
+        let mut rows: Vec<PhenotypeRow> = Vec::new();
+        for (i, entry) in load_manifest(path)?.into_iter().enumerate() {
+            let covariates = match &covariates {
+                Some(covariates) => match covariates.get(&entry.sample_id) {
+                    Some(covariates) => covariates.clone(),
+                    None => continue,
+                },
+                None => Vec::new(),
+            };
+            rows.push(PhenotypeRow {
+                sample_id: entry.sample_id,
+                phenotype: entry.status,
+                covariates,
+                line: i as u64 + 1,
+            });
+        }
+        (rows, n_missing)
+    } else {
+        read_phenotype_rows(path, params, true)?
+    };
+
+    let groups: Option<Vec<String>> = categorical.then(|| {
+        let groups: BTreeSet<&str> = rows
+            .iter()
+            .map(|row| row.phenotype.trim())
+            .filter(|phenotype| !is_missing(phenotype))
+            .collect();
+        groups.into_iter().map(str::to_string).collect()
+    });
+    if let Some(group) = groups
+        .iter()
+        .flatten()
+        .find(|group| group.contains([':', ',']))
+    {
+        bail!(
+            "The group '{}' of {} has a ':' or ',', which the group columns of the results use as separators",
+            group,
+            path.display()
+        );
+    }
+
+    let mut phenotypes: HashMap<String, Phenotype> = HashMap::new();
+    for row in rows {
+        let value = match &groups {
+            Some(groups) => groups
+                .binary_search_by(|group| group.as_str().cmp(row.phenotype.trim()))
+                .ok()
+                .map(|group| group as f64),
+            None => parse_value(&row.phenotype, true).with_context(|| {
+                format!(
+                    "Invalid phenotype in {} line {}, test group labels with --model kruskal-wallis",
+                    path.display(),
+                    row.line
+                )
+            })?,
+        };
+        match value {
+            Some(value) => {
+                phenotypes.insert(
+                    row.sample_id,
+                    Phenotype {
+                        value,
+                        covariates: row.covariates,
+                    },
+                );
+            }
+            None => n_missing += 1,
+        }
+    }
+    if n_missing > 0 {
+        warn!(
+            "Left out {} sample(s) with a missing phenotype or covariate",
+            n_missing
+        );
+    }
+    if phenotypes.is_empty() {
+        bail!("{} has no samples with a phenotype", path.display());
+    }
+    Ok((phenotypes, groups))
+}
+
+/// Reads the phenotype (unless `with_phenotype` is false, e.g. for a file of
+/// covariates only) and covariates of each sample of a phenotypes file,
+/// returning them with the number of samples left out for a missing covariate
+fn read_phenotype_rows(
+    path: &Path,
+    params: &TestParams,
+    with_phenotype: bool,
+) -> Result<(Vec<PhenotypeRow>, usize)> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(true)
+        .comment(Some(b'#'))
+        .from_path(path)?;
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| -> Result<usize> {
+        match headers.iter().position(|h| h == name) {
+            Some(i) => Ok(i),
+            None => bail!("{} has no '{}' column", path.display(), name),
+        }
+    };
+    let phenotype_idx = match &params.phenotype {
+        _ if !with_phenotype => None,
+        Some(name) => Some(column(name)?),
+        None if headers.len() >= 2 => Some(1),
+        None => bail!(
+            "{} should have a sample ID column and at least one phenotype column",
+            path.display()
+        ),
+    };
+    let covariate_idxs: Vec<usize> = params
+        .covariates
+        .iter()
+        .map(|name| column(name))
+        .collect::<Result<Vec<usize>>>()?;
+
+    let mut rows: Vec<PhenotypeRow> = Vec::new();
+    let mut n_missing: usize = 0;
+    for result in reader.records() {
+        let record = result?;
+        let line = record.position().map(|p| p.line()).unwrap_or(0);
+        let mut covariates: Vec<f64> = Vec::with_capacity(covariate_idxs.len());
+        for &i in &covariate_idxs {
+            match parse_value(record.get(i).unwrap_or(""), false).with_context(|| {
+                format!(
+                    "Invalid '{}' covariate in {} line {}",
+                    &headers[i],
+                    path.display(),
+                    line
+                )
+            })? {
+                Some(value) => covariates.push(value),
+                None => break,
+            }
+        }
+        if covariates.len() < covariate_idxs.len() {
+            n_missing += 1;
+            continue;
+        }
+        rows.push(PhenotypeRow {
+            sample_id: record.get(0).unwrap().to_string(),
+            phenotype: phenotype_idx
+                .and_then(|i| record.get(i))
+                .unwrap_or("")
+                .to_string(),
+            covariates,
+            line,
+        });
+    }
+    Ok((rows, n_missing))
+}
+
+/// Replaces the value of each sample by the rank-based inverse normal
+/// transform of the values, Φ⁻¹((r - 3/8) / (n + 1/4)) for the rank r of a
+/// value among n (Blom), with ties given their average rank. The transformed
+/// values are normally distributed whatever the distribution of the trait, so
+/// that skewed traits and outliers do not break the linear model.
+fn inverse_normal_transform(phenotypes: &mut HashMap<String, Phenotype>) {
+    let mut sample_ids: Vec<String> = phenotypes.keys().cloned().collect();
+    sample_ids.sort();
+    let values: Vec<f64> = sample_ids.iter().map(|id| phenotypes[id].value).collect();
+    let (ranks, _) = rank(&values);
+    let n = values.len() as f64;
+    let normal = Normal::standard();
+    for (sample_id, rank) in sample_ids.iter().zip(ranks) {
+        if let Some(phenotype) = phenotypes.get_mut(sample_id) {
+            phenotype.value = normal.inverse_cdf((rank - 0.375) / (n + 0.25));
+        }
+    }
+}
+
+/// Whether a field is missing: NA, NaN, . or empty
+fn is_missing(s: &str) -> bool {
+    matches!(s.trim().to_lowercase().as_str(), "" | "na" | "nan" | ".")
+}
+
+/// Parses a numeric value, treating NA, . and empty fields as missing. The
+/// phenotype can also be case/control.
+fn parse_value(s: &str, allow_status: bool) -> Result<Option<f64>> {
+    if is_missing(s) {
+        return Ok(None);
+    }
+    let value = match s.trim().to_lowercase().as_str() {
+        "case" if allow_status => 1.0,
+        "control" if allow_status => 0.0,
+        other => match other.parse::<f64>() {
+            Ok(value) if value.is_finite() => value,
+            _ => bail!("'{}' is not a number", s),
+        },
+    };
+    Ok(Some(value))
+}
+
+/// Design matrix rows of [1, burden, covariates...]
+fn design_matrix(x: &[f64], covariates: &[&[f64]]) -> Vec<Vec<f64>> {
+    x.iter()
+        .zip(covariates)
+        .map(|(x, covariates)| {
+            let mut row = vec![1.0, *x];
+            row.extend_from_slice(covariates);
+            row
+        })
+        .collect()
+}
+
+/// Linear regression of y on the design matrix, returning (effect, standard
+/// error, t statistic, p-value) for the burden coefficient
+fn linear_test(
+    x: &[f64],
+    y: &[f64],
+    covariates: &[&[f64]],
+) -> Option<(f64, Option<f64>, f64, f64)> {
+    let design = design_matrix(x, covariates);
+    let n = design.len();
+    let p = design[0].len();
+    if n <= p {
+        return None;
+    }
+    let weights = vec![1.0; n];
+    let xtx_inv = invert(&weighted_gram(&design, &weights))?;
+    let xty: Vec<f64> = (0..p)
+        .map(|j| design.iter().zip(y).map(|(row, y)| row[j] * y).sum())
+        .collect();
+    let beta: Vec<f64> = (0..p)
+        .map(|i| (0..p).map(|j| xtx_inv[i][j] * xty[j]).sum())
+        .collect();
+    let rss: f64 = design
+        .iter()
+        .zip(y)
+        .map(|(row, y)| {
+            let fitted: f64 = row.iter().zip(&beta).map(|(a, b)| a * b).sum();
+            (y - fitted).powi(2)
+        })
+        .sum();
+    let df = (n - p) as f64;
+    let std_error = (rss / df * xtx_inv[1][1]).sqrt();
+    if !std_error.is_finite() || std_error == 0.0 {
+        return None;
+    }
+    let t = beta[1] / std_error;
+    let p_value = 2.0 * (1.0 - StudentsT::new(0.0, 1.0, df).ok()?.cdf(t.abs()));
+    Some((beta[1], Some(std_error), t, p_value.clamp(0.0, 1.0)))
+}
+
+/// Logistic regression fitted by iteratively reweighted least squares,
+/// returning (log odds ratio, standard error, Wald z, p-value) for the burden
+/// coefficient. Returns `None` if the fit does not converge (e.g. under
+/// complete separation).
+pub(crate) fn logistic_test(
+    x: &[f64],
+    y: &[f64],
+    covariates: &[&[f64]],
+) -> Option<(f64, Option<f64>, f64, f64)> {
+    let design = design_matrix(x, covariates);
+    let n = design.len();
+    let p = design[0].len();
+    if n <= p {
+        return None;
+    }
+    let mut beta = vec![0.0; p];
+    let mut converged = false;
+    let mut covariance: Vec<Vec<f64>> = Vec::new();
+    for _ in 0..MAX_IRLS_ITERATIONS {
+        let mu: Vec<f64> = design
+            .iter()
+            .map(|row| {
+                let eta: f64 = row.iter().zip(&beta).map(|(a, b)| a * b).sum();
+                1.0 / (1.0 + (-eta).exp())
+            })
+            .collect();
+        let weights: Vec<f64> = mu.iter().map(|mu| mu * (1.0 - mu)).collect();
+        covariance = invert(&weighted_gram(&design, &weights))?;
+        let gradient: Vec<f64> = (0..p)
+            .map(|j| {
+                design
+                    .iter()
+                    .zip(y.iter().zip(&mu))
+                    .map(|(row, (y, mu))| row[j] * (y - mu))
+                    .sum()
+            })
+            .collect();
+        let step: Vec<f64> = (0..p)
+            .map(|i| (0..p).map(|j| covariance[i][j] * gradient[j]).sum())
+            .collect();
+        for (b, s) in beta.iter_mut().zip(&step) {
+            *b += s;
+        }
+        if step.iter().all(|s| s.abs() < IRLS_TOLERANCE) {
+            converged = true;
+            break;
+        }
+    }
+    if !converged || beta.iter().any(|b| !b.is_finite()) {
+        return None;
+    }
+    let std_error = covariance[1][1].sqrt();
+    if !std_error.is_finite() || std_error == 0.0 {
+        return None;
+    }
+    let z = beta[1] / std_error;
+    let p_value = 2.0 * (1.0 - Normal::standard().cdf(z.abs()));
+    Some((beta[1], Some(std_error), z, p_value.clamp(0.0, 1.0)))
+}
+
+/// Two-sided Mann-Whitney U test using the normal approximation with tie and
+/// continuity corrections, returning (difference in mean burden, no standard
+/// error, U of the cases, p-value)
+pub(crate) fn mann_whitney_test(x: &[f64], y: &[f64]) -> Option<(f64, Option<f64>, f64, f64)> {
+    let n = x.len() as f64;
+    let (ranks, tie_term) = rank(x);
+
+    let n_cases = y.iter().filter(|y| **y == 1.0).count() as f64;
+    let n_controls = n - n_cases;
+    let rank_sum: f64 = ranks
+        .iter()
+        .zip(y)
+        .filter(|(_, y)| **y == 1.0)
+        .map(|(r, _)| r)
+        .sum();
+    let u = rank_sum - n_cases * (n_cases + 1.0) / 2.0;
+    let mean_u = n_cases * n_controls / 2.0;
+    let sd_u = (n_cases * n_controls / 12.0 * ((n + 1.0) - tie_term / (n * (n - 1.0)))).sqrt();
+    if !sd_u.is_finite() || sd_u == 0.0 {
+        return None;
+    }
+    let z = ((u - mean_u).abs() - 0.5).max(0.0) / sd_u;
+    let p_value = 2.0 * (1.0 - Normal::standard().cdf(z));
+
+    let mean = |group: f64| -> f64 {
+        let values: Vec<f64> = x
+            .iter()
+            .zip(y)
+            .filter(|(_, y)| **y == group)
+            .map(|(x, _)| *x)
+            .collect();
+        values.iter().sum::<f64>() / values.len() as f64
+    };
+    Some((mean(1.0) - mean(0.0), None, u, p_value.clamp(0.0, 1.0)))
+}
+
+/// Kruskal-Wallis H test across the groups of a categorical phenotype, whose
+/// values are group indices, with the tie correction and the chi-squared
+/// approximation. Returns (difference between the highest and lowest mean
+/// burden of a group, no standard error, H, p-value).
+fn kruskal_wallis_test(x: &[f64], y: &[f64]) -> Option<(f64, Option<f64>, f64, f64)> {
+    let n = x.len() as f64;
+    let (ranks, tie_term) = rank(x);
+    // the number of samples, rank sum and burden sum of each group
+    let mut groups: BTreeMap<u64, (f64, f64, f64)> = BTreeMap::new();
+    for ((x, rank), y) in x.iter().zip(&ranks).zip(y) {
+        let group = groups.entry(y.to_bits()).or_default();
+        group.0 += 1.0;
+        group.1 += rank;
+        group.2 += x;
+    }
+    if groups.len() < 2 {
+        return None;
+    }
+    let tie_correction = 1.0 - tie_term / (n.powi(3) - n);
+    if !tie_correction.is_finite() || tie_correction <= 0.0 {
+        return None;
+    }
+    let rank_term: f64 = groups
+        .values()
+        .map(|(size, rank_sum, _)| rank_sum.powi(2) / size)
+        .sum();
+    let h = (12.0 / (n * (n + 1.0)) * rank_term - 3.0 * (n + 1.0)) / tie_correction;
+    let df = (groups.len() - 1) as f64;
+    let p_value = 1.0 - ChiSquared::new(df).ok()?.cdf(h.max(0.0));
+
+    let means = groups.values().map(|(size, _, sum)| sum / size);
+    let effect = means.clone().fold(f64::MIN, f64::max) - means.fold(f64::MAX, f64::min);
+    Some((effect, None, h, p_value.clamp(0.0, 1.0)))
+}
+
+/// The ranks of values, averaged over ties, and the tie term sum(t^3 - t)
+/// over the sizes t of the groups of tied values
+fn rank(x: &[f64]) -> (Vec<f64>, f64) {
+    let mut order: Vec<usize> = (0..x.len()).collect();
+    order.sort_by(|&a, &b| x[a].total_cmp(&x[b]));
+
+    let mut ranks = vec![0.0; x.len()];
+    let mut tie_term = 0.0;
+    let mut i = 0;
+    while i < order.len() {
+        let mut j = i;
+        while j + 1 < order.len() && x[order[j + 1]] == x[order[i]] {
+            j += 1;
+        }
+        let rank = (i + j) as f64 / 2.0 + 1.0;
+        for k in i..=j {
+            ranks[order[k]] = rank;
+        }
+        let t = (j - i + 1) as f64;
+        tie_term += t.powi(3) - t;
+        i = j + 1;
+    }
+    (ranks, tie_term)
+}
+
+/// X'WX for a design matrix X and diagonal weights W
+fn weighted_gram(design: &[Vec<f64>], weights: &[f64]) -> Vec<Vec<f64>> {
+    let p = design[0].len();
+    let mut gram = vec![vec![0.0; p]; p];
+    for (row, w) in design.iter().zip(weights) {
+        for i in 0..p {
+            for j in 0..p {
+                gram[i][j] += w * row[i] * row[j];
+            }
+        }
+    }
+    gram
+}
+
+/// Inverts a small square matrix by Gauss-Jordan elimination with partial
+/// pivoting, returning `None` if it is singular
+fn invert(matrix: &[Vec<f64>]) -> Option<Vec<Vec<f64>>> {
+    let p = matrix.len();
+    let scale = matrix
+        .iter()
+        .flatten()
+        .fold(0.0_f64, |max, v| max.max(v.abs()));
+    let mut a: Vec<Vec<f64>> = matrix.to_vec();
+    let mut inverse: Vec<Vec<f64>> = (0..p)
+        .map(|i| (0..p).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+        .collect();
+    for col in 0..p {
+        let pivot = (col..p).max_by(|&r1, &r2| a[r1][col].abs().total_cmp(&a[r2][col].abs()))?;
+        if a[pivot][col].abs() <= scale * 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        inverse.swap(col, pivot);
+        let diag = a[col][col];
+        for j in 0..p {
+            a[col][j] /= diag;
+            inverse[col][j] /= diag;
+        }
+        for row in 0..p {
+            if row != col {
+                let factor = a[row][col];
+                for j in 0..p {
+                    a[row][j] -= factor * a[col][j];
+                    inverse[row][j] -= factor * inverse[col][j];
+                }
+            }
+        }
+    }
+    Some(inverse)
+}
+
+/// Adjusts p-values for multiple testing, keeping the input order
+pub fn adjust_p_values(p_values: &[f64], correction: Correction) -> Vec<f64> {
+    let m = p_values.len() as f64;
+    match correction {
+        Correction::None => p_values.to_vec(),
+        Correction::Bonferroni => p_values.iter().map(|p| (p * m).min(1.0)).collect(),
+        Correction::Bh => {
+            let mut order: Vec<usize> = (0..p_values.len()).collect();
+            order.sort_by(|&a, &b| p_values[a].total_cmp(&p_values[b]));
+            let mut adjusted = vec![0.0; p_values.len()];
+            let mut running_min: f64 = 1.0;
+            for (rank, &i) in order.iter().enumerate().rev() {
+                running_min = running_min.min(p_values[i] * m / (rank + 1) as f64);
+                adjusted[i] = running_min;
+            }
+            adjusted
+        }
+    }
+}
+
+/// The command line arguments of `strif test`
+#[derive(Debug, clap::Args)]
+pub struct AssociationArgs {
+    /// The path to the merged profile
+    pub merged_profile: PathBuf,
+
+    /// The path to a TSV with a header row, sample IDs in the first column and phenotypes/covariates in the others,
+    /// or with --manifest to a `merge` manifest
+    pub phenotypes: PathBuf,
+
+    /// The path to the association results. Defaults to the same directory as the merged profile.
+    pub output: Option<PathBuf>,
+
+    /// The phenotype column to test. Defaults to the second column. Binary phenotypes can be 0/1 or case/control,
+    /// and with the kruskal-wallis model the phenotype is any group labels.
+    #[clap(short = 'p', long)]
+    pub phenotype: Option<String>,
+
+    /// A numeric covariate column to adjust for. Can be repeated.
+    #[clap(short = 'c', long)]
+    pub covariate: Vec<String>,
+
+    /// The association model. Defaults to logistic for binary phenotypes and linear otherwise.
+    #[clap(long, value_enum)]
+    pub model: Option<Model>,
+
+    /// The multiple-testing correction
+    #[clap(long, value_enum, default_value = "bh")]
+    pub correction: Correction,
+
+    /// Minimum number of samples (per group for binary and categorical phenotypes) to test a locus
+    #[clap(short = 'n', long, default_value = "2")]
+    pub min_samples: usize,
+
+    /// Test each interruption separately instead of the total interruption burden of each locus
+    #[clap(short = 'i', long, action)]
+    pub per_interruption: bool,
+
+    /// The number of permutations of the phenotypes used to compute empirical p-values and
+    /// family-wise error estimates, run on --threads threads
+    #[clap(long, default_value = "0")]
+    pub permutations: usize,
+
+    /// The seed of the permutations
+    #[clap(long, default_value = "0")]
+    pub seed: u64,
+
+    /// With the kruskal-wallis model, also compare each pair of groups with a Mann-Whitney U test
+    #[clap(long)]
+    pub pairwise: bool,
+
+    /// Read the phenotypes from a `merge` manifest, whose status column is the phenotype, e.g.
+    /// case/control or the group of each sample
+    #[clap(long, conflicts_with = "phenotype")]
+    pub manifest: bool,
+
+    /// With --manifest, the path to a TSV with a header row, sample IDs in the first column and
+    /// the --covariate columns in the others
+    #[clap(long, requires = "manifest")]
+    pub covariate_file: Option<PathBuf>,
+
+    /// Replace the values of a quantitative phenotype by their rank-based inverse normal
+    /// transform before testing, e.g. for skewed traits such as age of onset
+    #[clap(long)]
+    pub inverse_normal: bool,
+}
+
+impl AssociationArgs {
+    /// Runs `strif test`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let AssociationArgs {
+            merged_profile,
+            phenotypes,
+            output,
+            phenotype,
+            covariate,
+            model,
+            correction,
+            min_samples,
+            per_interruption,
+            permutations,
+            seed,
+            pairwise,
+            manifest,
+            covariate_file,
+            inverse_normal,
+        } = self;
+        let out_path: PathBuf =
+            output.unwrap_or_else(|| get_default_out_path(&merged_profile, "association", "tsv"));
+        let params = TestParams {
+            phenotype,
+            covariates: covariate,
+            model,
+            correction,
+            min_samples,
+            per_interruption,
+            permutations,
+            seed,
+            pairwise,
+            manifest,
+            covariate_file,
+            inverse_normal,
+        };
+        Plan::new("test", dry_run, force)
+            .merged_profile("merged profile", &merged_profile)
+            .records("phenotypes", &phenotypes, "rows")
+            .maybe_input("covariates", params.covariate_file.as_deref())
+            .output("association results", &out_path)
+            .execute(|| association(merged_profile, phenotypes, out_path, params))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::testing::{data_rows, merged_record, write_merged_profile_file};
+
+    fn test_params(permutations: usize) -> TestParams {
+        TestParams {
+            phenotype: None,
+            covariates: Vec::new(),
+            model: None,
+            correction: Correction::Bh,
+            min_samples: 1,
+            per_interruption: false,
+            permutations,
+            seed: 42,
+            pairwise: false,
+            manifest: false,
+            covariate_file: None,
+            inverse_normal: false,
+        }
+    }
+
+    #[test]
+    fn empirical_p_values_do_not_depend_on_threads() {
+        let phenotypes: Vec<Phenotype> = (0..12)
+            .map(|i| Phenotype {
+                value: (i % 2) as f64,
+                covariates: Vec::new(),
+            })
+            .collect();
+        let phenotypes: Vec<&Phenotype> = phenotypes.iter().collect();
+        let tests: Vec<TestData> = (0..3)
+            .map(|t| TestData {
+                model: Model::MannWhitney,
+                comparison: Comparison::All,
+                samples: (0..12).collect(),
+                x: (0..12)
+                    .map(|i| ((i * (t + 3)) % 7 + i % 2) as f64)
+                    .collect(),
+            })
+            .collect();
+        let p_values = [0.05, 0.2, 0.5];
+        let params = test_params(50);
+        let single = empirical_p_values(&tests, &phenotypes, &p_values, &params, 1);
+        for n_threads in [2, 3, 7] {
+            assert_eq!(
+                empirical_p_values(&tests, &phenotypes, &p_values, &params, n_threads),
+                single
+            );
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{} is not within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn mann_whitney_matches_the_normal_approximation() {
+        // cases 5..8 against controls 1..4: U = 16, z = (16 - 8 - 0.5) / sqrt(12)
+        let x = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0];
+        let y = [0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0];
+        let (effect, std_error, u, p_value) = mann_whitney_test(&x, &y).unwrap();
+        assert_eq!((effect, std_error, u), (4.0, None, 16.0));
+        assert_close(p_value, 0.030383, 1e-6);
+
+        // with ties, the variance of U shrinks by the tie term 3 * (3^3 - 3)
+        let x = [1.0, 2.0, 2.0, 3.0, 2.0, 3.0, 3.0, 4.0];
+        let (_, _, u, p_value) = mann_whitney_test(&x, &y).unwrap();
+        assert_eq!(u, 13.0);
+        assert_close(p_value, 0.172034, 1e-6);
+
+        // all values tied
+        assert!(mann_whitney_test(&[1.0; 4], &[0.0, 0.0, 1.0, 1.0]).is_none());
+    }
+
+    #[test]
+    fn kruskal_wallis_matches_reference_values() {
+        // the example of Hollander & Wolfe (1973), p. 116, as in R's
+        // kruskal.test: H = 0.77143 on 2 degrees of freedom, p = 0.68
+        let groups: [&[f64]; 3] = [
+            &[2.9, 3.0, 2.5, 2.6, 3.2],
+            &[3.8, 2.7, 4.0, 2.4],
+            &[2.8, 3.4, 3.7, 2.2, 2.0],
+        ];
+        let (x, y): (Vec<f64>, Vec<f64>) = groups
+            .iter()
+            .enumerate()
+            .flat_map(|(i, values)| values.iter().map(move |value| (*value, i as f64)))
+            .unzip();
+        let (effect, std_error, h, p_value) = kruskal_wallis_test(&x, &y).unwrap();
+        assert_close(effect, 3.225 - 2.82, 1e-12);
+        assert_eq!(std_error, None);
+        assert_close(h, 0.771429, 1e-6);
+        // the chi-squared survival function on 2 degrees of freedom is exp(-H / 2)
+        assert_close(p_value, (-h / 2.0).exp(), 1e-9);
+
+        assert!(kruskal_wallis_test(&[1.0, 2.0], &[0.0, 0.0]).is_none());
+    }
+
+    #[test]
+    fn logistic_test_matches_the_odds_ratio_of_a_two_by_two_table() {
+        // with a binary burden and no covariates, the maximum likelihood log
+        // odds ratio is log(ad / bc) with standard error sqrt(1/a + 1/b + 1/c
+        // + 1/d), here of 6 cases and 4 controls against 3 cases and 7 controls
+        let mut x = Vec::new();
+        let mut y = Vec::new();
+        for (burden, status, count) in [(1.0, 1.0, 6), (1.0, 0.0, 4), (0.0, 1.0, 3), (0.0, 0.0, 7)]
+        {
+            x.extend(std::iter::repeat_n(burden, count));
+            y.extend(std::iter::repeat_n(status, count));
+        }
+        let covariates: Vec<&[f64]> = vec![&[]; x.len()];
+        let (log_odds_ratio, std_error, z, p_value) = logistic_test(&x, &y, &covariates).unwrap();
+        let std_error = std_error.unwrap();
+        assert_close(log_odds_ratio, (3.5_f64).ln(), 1e-6);
+        assert_close(
+            std_error,
+            (1.0 / 6.0 + 1.0 / 4.0 + 1.0 / 3.0 + 1.0 / 7.0_f64).sqrt(),
+            1e-6,
+        );
+        assert_close(z, log_odds_ratio / std_error, 1e-12);
+        assert_close(p_value, 0.184906, 1e-5);
+    }
+
+    #[test]
+    fn logistic_test_fails_under_complete_separation() {
+        let x = [0.0, 1.0, 2.0, 3.0, 4.0, 5.0];
+        let y = [0.0, 0.0, 0.0, 1.0, 1.0, 1.0];
+        let covariates: Vec<&[f64]> = vec![&[]; x.len()];
+        assert!(logistic_test(&x, &y, &covariates).is_none());
+    }
+
+    #[test]
+    fn benjamini_hochberg_keeps_the_input_order() {
+        let cases: [(&[f64], &[f64]); 3] = [
+            // sorted: 0.01 * 3 / 1, 0.04 * 3 / 2, 0.2 * 3 / 3
+            (&[0.04, 0.01, 0.2], &[0.06, 0.03, 0.2]),
+            // a larger p-value caps the adjusted p-values of smaller ones
+            (&[0.01, 0.02, 0.03, 0.04, 0.05], &[0.05; 5]),
+            (&[0.01, 0.04, 0.03, 0.005], &[0.02, 0.04, 0.04, 0.02]),
+        ];
+        for (p_values, expected) in cases {
+            let adjusted = adjust_p_values(p_values, Correction::Bh);
+            assert_eq!(adjusted.len(), expected.len());
+            for (p_value, expected) in adjusted.into_iter().zip(expected) {
+                assert_close(p_value, *expected, 1e-12);
+            }
+        }
+        assert_eq!(
+            adjust_p_values(&[0.3, 0.01], Correction::Bonferroni),
+            [0.6, 0.02]
+        );
+        assert!(adjust_p_values(&[], Correction::Bh).is_empty());
+    }
+
+    #[test]
+    fn linear_tests_regress_phenotypes_on_locus_burdens() {
+        let dir = tempfile::tempdir().unwrap();
+        let x = [0.1, 0.2, 0.3, 0.4, 0.5, 0.6];
+        let y = [1.1, 1.5, 1.5, 2.0, 1.9, 2.3];
+        let samples: Vec<String> = (1..=7).map(|i| format!("s{}", i)).collect();
+        let read_counts: Vec<(&str, u32)> = samples.iter().map(|s| (s.as_str(), 10)).collect();
+        // the burden of a sample is the sum over its interruptions
+        let mut interruption_counts: Vec<(&str, &str, f64)> = samples[..6]
+            .iter()
+            .zip(x)
+            .map(|(sample_id, x)| (sample_id.as_str(), "CAT", x - 0.05))
+            .collect();
+        interruption_counts.extend(samples[..6].iter().map(|s| (s.as_str(), "A", 0.05)));
+        let merged_profile = write_merged_profile_file(
+            dir.path(),
+            "merged.tsv",
+            &[
+                merged_record("L1", &read_counts, &interruption_counts),
+                merged_record("L2", &read_counts[..2], &[]),
+            ],
+        );
+        let phenotypes = dir.path().join("phenotypes.tsv");
+        let mut rows = String::from("sample\theight\n");
+        for (sample_id, y) in samples.iter().zip(y) {
+            rows.push_str(&format!("{}\t{}\n", sample_id, y));
+        }
+        // s7 has no phenotype and is left out
+        rows.push_str("s7\tNA\n");
+        fs::write(&phenotypes, rows).unwrap();
+
+        let out = dir.path().join("association.tsv");
+        let params = TestParams {
+            min_samples: 3,
+            ..test_params(0)
+        };
+        association(merged_profile, phenotypes, out.clone(), params).unwrap();
+        let rows = data_rows(&out);
+        assert_eq!(rows.len(), 1);
+        let fields: Vec<&str> = rows[0].split('\t').collect();
+        assert_eq!(
+            &fields[..6],
+            ["L1", "chr1:100-200", "CAG", ".", "linear", "6"]
+        );
+
+        let mean = |v: &[f64]| v.iter().sum::<f64>() / v.len() as f64;
+        let (mean_x, mean_y) = (mean(&x), mean(&y));
+        let sxy: f64 = x
+            .iter()
+            .zip(y)
+            .map(|(x, y)| (x - mean_x) * (y - mean_y))
+            .sum();
+        let sxx: f64 = x.iter().map(|x| (x - mean_x).powi(2)).sum();
+        assert_close(fields[6].parse().unwrap(), sxy / sxx, 1e-9);
+        let p_value: f64 = fields[9].parse().unwrap();
+        assert!(p_value < 0.01, "{}", p_value);
+        // a single test is its own Benjamini-Hochberg adjustment
+        assert_eq!(fields[9], fields[10]);
+    }
+
+    /// Writes a merged profile of a locus where samples s1, s2, ... have the
+    /// given burdens, returning the samples and its path
+    fn write_burdens(dir: &Path, burdens: &[f64]) -> (Vec<String>, PathBuf) {
+        let samples: Vec<String> = (1..=burdens.len()).map(|i| format!("s{}", i)).collect();
+        let read_counts: Vec<(&str, u32)> = samples.iter().map(|s| (s.as_str(), 10)).collect();
+        let interruption_counts: Vec<(&str, &str, f64)> = samples
+            .iter()
+            .zip(burdens)
+            .map(|(sample_id, burden)| (sample_id.as_str(), "CAT", *burden))
+            .collect();
+        let merged_profile = write_merged_profile_file(
+            dir,
+            "merged.tsv",
+            &[merged_record("L1", &read_counts, &interruption_counts)],
+        );
+        (samples, merged_profile)
+    }
+
+    #[test]
+    fn groups_are_compared_all_at_once_and_pairwise() {
+        let dir = tempfile::tempdir().unwrap();
+        let groups = [
+            "sca1", "sca1", "sca1", "sca2", "sca2", "sca2", "control", "control", "control",
+        ];
+        let (samples, merged_profile) =
+            write_burdens(dir.path(), &[0.9, 1.0, 1.1, 0.5, 0.6, 0.7, 0.1, 0.2, 0.3]);
+        let manifest = dir.path().join("manifest.tsv");
+        let rows: String = samples
+            .iter()
+            .zip(groups)
+            .map(|(sample_id, group)| {
+                format!("{}\t{}\t{}.profile.tsv\n", sample_id, group, sample_id)
+            })
+            .collect();
+        fs::write(&manifest, rows).unwrap();
+
+        let out = dir.path().join("association.tsv");
+        let params = TestParams {
+            model: Some(Model::KruskalWallis),
+            pairwise: true,
+            manifest: true,
+            correction: Correction::None,
+            ..test_params(0)
+        };
+        association(merged_profile, manifest, out.clone(), params).unwrap();
+        let rows: Vec<Vec<String>> = data_rows(&out)
+            .iter()
+            .map(|row| row.split('\t').map(str::to_string).collect())
+            .collect();
+        let columns = |i: usize| -> Vec<&str> { rows.iter().map(|row| row[i].as_str()).collect() };
+        assert_eq!(
+            columns(13),
+            ["all", "control_vs_sca1", "control_vs_sca2", "sca1_vs_sca2"]
+        );
+        assert_eq!(
+            columns(4),
+            [
+                "kruskal_wallis",
+                "mann_whitney",
+                "mann_whitney",
+                "mann_whitney"
+            ]
+        );
+        assert_eq!(columns(5), ["9", "6", "6", "6"]);
+        assert_eq!(rows[0][14], "control:3,sca1:3,sca2:3");
+        // the groups do not overlap, so H is at its largest for three groups of three
+        let h: f64 = rows[0][8].parse().unwrap();
+        assert_close(h, 7.2, 1e-9);
+        assert_close(rows[0][9].parse().unwrap(), (-3.6f64).exp(), 1e-9);
+    }
+
+    #[test]
+    fn inverse_normal_transforms_keep_only_the_ranks_of_phenotypes() {
+        let mut phenotypes: HashMap<String, Phenotype> =
+            [("s1", 10.0), ("s2", 1.0), ("s3", 5.0), ("s4", 100.0)]
+                .into_iter()
+                .map(|(sample_id, value)| {
+                    let phenotype = Phenotype {
+                        value,
+                        covariates: Vec::new(),
+                    };
+                    (sample_id.to_string(), phenotype)
+                })
+                .collect();
+        inverse_normal_transform(&mut phenotypes);
+        // Blom's scores of ranks 1 to 4 of 4 are symmetric around 0
+        let normal = Normal::standard();
+        assert_close(
+            phenotypes["s2"].value,
+            normal.inverse_cdf(0.625 / 4.25),
+            1e-12,
+        );
+        assert_close(phenotypes["s4"].value, -phenotypes["s2"].value, 1e-12);
+        assert_close(phenotypes["s3"].value, -phenotypes["s1"].value, 1e-12);
+        assert!(phenotypes["s2"].value < phenotypes["s3"].value);
+
+        let dir = tempfile::tempdir().unwrap();
+        let (samples, merged_profile) =
+            write_burdens(dir.path(), &[0.1, 0.3, 0.2, 0.5, 0.4, 0.7, 0.6, 0.8]);
+        // the results of a phenotype and of a monotone transform of it are the same
+        let onsets = [30.0, 41.0, 35.0, 52.0, 44.0, 60.0, 58.0, 71.0];
+        let ages = [61.0, 40.0, 55.0, 47.0, 38.0, 66.0, 52.0, 49.0];
+        let results = |transform: fn(f64) -> f64| {
+            let phenotypes = dir.path().join("phenotypes.tsv");
+            let mut rows = String::from("sample\tonset\tage\n");
+            for ((sample_id, onset), age) in samples.iter().zip(onsets).zip(ages) {
+                rows.push_str(&format!("{}\t{}\t{}\n", sample_id, transform(onset), age));
+            }
+            fs::write(&phenotypes, rows).unwrap();
+            let out = dir.path().join("association.tsv");
+            let params = TestParams {
+                covariates: vec!["age".to_string()],
+                inverse_normal: true,
+                ..test_params(0)
+            };
+            association(merged_profile.clone(), phenotypes, out.clone(), params).unwrap();
+            data_rows(&out)
+        };
+        let rows = results(|onset| onset);
+        assert_eq!(rows, results(f64::ln));
+        assert!(rows[0].contains("\tlinear\t8\t"));
+    }
+
+    #[test]
+    fn covariates_of_manifest_samples_come_from_a_covariate_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let (samples, merged_profile) =
+            write_burdens(dir.path(), &[0.1, 0.3, 0.2, 0.5, 0.4, 0.7, 0.6, 0.8]);
+        let manifest = dir.path().join("manifest.tsv");
+        let statuses = [
+            "control", "case", "control", "case", "control", "case", "control", "case",
+        ];
+        let rows: String = samples
+            .iter()
+            .zip(statuses)
+            .map(|(sample_id, status)| {
+                format!("{}\t{}\t{}.profile.tsv\n", sample_id, status, sample_id)
+            })
+            .collect();
+        fs::write(&manifest, rows).unwrap();
+        let covariate_file = dir.path().join("covariates.tsv");
+        // s8 has no covariates and is left out
+        fs::write(
+            &covariate_file,
+            "sample\tage\ns1\t61\ns2\t40\ns3\t55\ns4\t47\ns5\t38\ns6\t66\ns7\t52\n",
+        )
+        .unwrap();
+
+        let out = dir.path().join("association.tsv");
+        let params = TestParams {
+            covariates: vec!["age".to_string()],
+            manifest: true,
+            covariate_file: Some(covariate_file),
+            ..test_params(0)
+        };
+        association(
+            merged_profile.clone(),
+            manifest.clone(),
+            out.clone(),
+            params,
+        )
+        .unwrap();
+        let rows = data_rows(&out);
+        assert_eq!(rows.len(), 1);
+        assert!(rows[0].contains("\tlogistic\t7\t"));
+
+        // the covariates of a manifest are only read from a covariate file
+        let params = TestParams {
+            covariates: vec!["age".to_string()],
+            manifest: true,
+            ..test_params(0)
+        };
+        assert!(association(merged_profile, manifest, out, params).is_err());
+    }
+}
@@ -0,0 +1,389 @@
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use anyhow::{bail, Result};
+use log::info;
+
+use crate::interruptions::InterruptionFinder;
+use crate::plan::Plan;
+use crate::profile::load_str_catalog;
+use crate::schema::{self, OutputKind};
+use crate::simulate::{simulate_reads, InterruptionMode, SimulationParams};
+use crate::utils::{create_output, get_default_out_path, AlignmentScoreParams};
+
+/// The values of each alignment score to try. Every combination is run.
+pub struct ScoreGrid {
+    pub match_scores: Vec<i32>,
+    pub mismatch_penalties: Vec<i32>,
+    pub gap_open_penalties: Vec<i32>,
+    pub gap_extend_penalties: Vec<i32>,
+}
+
+impl ScoreGrid {
+    pub fn combinations(&self) -> Vec<AlignmentScoreParams> {
+        let mut combinations = Vec::new();
+        for &match_score in &self.match_scores {
+            for &mismatch_penalty in &self.mismatch_penalties {
+                for &gap_open_penalty in &self.gap_open_penalties {
+                    for &gap_extend_penalty in &self.gap_extend_penalties {
+                        combinations.push(AlignmentScoreParams {
+                            match_score,
+                            mismatch_penalty,
+                            gap_open_penalty,
+                            gap_extend_penalty,
+                        });
+                    }
+                }
+            }
+        }
+        combinations
+    }
+}
+
+/// A simulated read and the interruption of the allele it was sampled from
+struct TruthRead {
+    motif: Vec<u8>,
+    read_seq: Vec<u8>,
+    interruption: String,
+}
+
+/// How well the interruptions found with a set of scores match the truth
+struct BenchmarkResult {
+    params: AlignmentScoreParams,
+    interrupted_reads: usize,
+    /// Reads of an interrupted allele where that interruption was found
+    detected_reads: usize,
+    /// Reads where an interruption other than the simulated one was found
+    false_positive_reads: usize,
+    seconds: f64,
+}
+
+/// Simulates reads with known interruptions and profiles them with each
+/// combination of alignment scores, reporting the sensitivity, false
+/// positive rate and throughput of each
+pub fn benchmark(
+    str_catalog: PathBuf,
+    out_path: PathBuf,
+    sim_params: SimulationParams,
+    grid: ScoreGrid,
+    filter: Option<String>,
+) -> Result<()> {
+    sim_params.validate()?;
+    let combinations = grid.combinations();
+    if combinations.is_empty() {
+        bail!("At least one value of each alignment score is needed");
+    }
+
+    info!("Loading STR catalog...");
+    let (motifs, _, _, _, _) = load_str_catalog(str_catalog, filter)?;
+
+    info!("Simulating reads for {} loci...", motifs.len());
+    let mut reads: Vec<TruthRead> = Vec::new();
+    simulate_reads(&motifs, &sim_params, |read| {
+        reads.push(TruthRead {
+            motif: read.motif.to_vec(),
+            read_seq: read.read_seq,
+            interruption: read.interruption.to_string(),
+        });
+        Ok(())
+    })?;
+    if reads.is_empty() {
+        bail!("No reads were simulated, check the filter and coverage");
+    }
+
+    let mut out_file = create_output(&out_path)?;
+    schema::write_header(&mut out_file, OutputKind::Benchmark)?;
+    let total = combinations.len();
+    for (i, params) in combinations.into_iter().enumerate() {
+        info!(
+            "Profiling {} reads with scores {}/{}/{}/{} ({} of {})...",
+            reads.len(),
+            params.match_score,
+            params.mismatch_penalty,
+            params.gap_open_penalty,
+            params.gap_extend_penalty,
+            i + 1,
+            total
+        );
+        let result = run_benchmark(&reads, params);
+        write_result(&mut out_file, reads.len(), &result)?;
+    }
+    out_file.finish()?;
+
+    info!("Done!");
+    Ok(())
+}
+
+fn run_benchmark(reads: &[TruthRead], params: AlignmentScoreParams) -> BenchmarkResult {
+    let mut finder = InterruptionFinder::new(params);
+    let mut result = BenchmarkResult {
+        params,
+        interrupted_reads: 0,
+        detected_reads: 0,
+        false_positive_reads: 0,
+        seconds: 0.0,
+    };
+    let start = Instant::now();
+    for read in reads {
+        let found = finder.find(&read.read_seq, &read.motif);
+        if !read.interruption.is_empty() {
+            result.interrupted_reads += 1;
+            if found.contains(&read.interruption) {
+                result.detected_reads += 1;
+            }
+        }
+        if found.iter().any(|i| *i != read.interruption) {
+            result.false_positive_reads += 1;
+        }
+    }
+    result.seconds = start.elapsed().as_secs_f64();
+    result
+}
+
+fn write_result<W: Write>(out: &mut W, reads: usize, result: &BenchmarkResult) -> Result<()> {
+    let sensitivity = if result.interrupted_reads > 0 {
+        (result.detected_reads as f64 / result.interrupted_reads as f64).to_string()
+    } else {
+        ".".to_string()
+    };
+    let false_positive_rate = result.false_positive_reads as f64 / reads as f64;
+    let reads_per_second = reads as f64 / result.seconds.max(f64::EPSILON);
+    info!(
+        "Sensitivity {}, false positive rate {:.4}, {:.0} reads/s",
+        sensitivity, false_positive_rate, reads_per_second
+    );
+    writeln!(
+        out,
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.0}",
+        result.params.match_score,
+        result.params.mismatch_penalty,
+        result.params.gap_open_penalty,
+        result.params.gap_extend_penalty,
+        reads,
+        result.interrupted_reads,
+        sensitivity,
+        false_positive_rate,
+        reads_per_second
+    )?;
+    Ok(())
+}
+
+/// The command line arguments of `strif benchmark`
+#[derive(Debug, clap::Args)]
+pub struct BenchmarkArgs {
+    /// The path to a JSON file containing the catalog of repeat loci to simulate reads for
+    pub str_catalog: PathBuf,
+
+    /// The path to the benchmark results. Defaults to the same directory as the catalog.
+    pub output: Option<PathBuf>,
+
+    /// Filter locus IDs using a regular expression. Defaults to None.
+    #[clap(short = 'f', long)]
+    pub filter: Option<String>,
+
+    /// The match scores to try, separated by commas
+    #[clap(short = 'A', long, value_delimiter = ',', default_value = "1")]
+    pub match_score: Vec<i32>,
+
+    /// The mismatch penalties to try, separated by commas
+    #[clap(short = 'B', long, value_delimiter = ',', default_value = "8")]
+    pub mismatch_penalty: Vec<i32>,
+
+    /// The gap open penalties to try, separated by commas
+    #[clap(short = 'O', long, value_delimiter = ',', default_value = "10")]
+    pub gap_open_penalty: Vec<i32>,
+
+    /// The gap extension penalties to try, separated by commas
+    #[clap(short = 'E', long, value_delimiter = ',', default_value = "1")]
+    pub gap_extend_penalty: Vec<i32>,
+
+    /// The number of reads to simulate per locus
+    #[clap(short = 'c', long, default_value = "30")]
+    pub coverage: u32,
+
+    /// The minimum allele length in base pairs
+    #[clap(long, default_value = "10")]
+    pub min_repeat_len: usize,
+
+    /// The maximum allele length in base pairs
+    #[clap(long, default_value = "100")]
+    pub max_repeat_len: usize,
+
+    /// The probability that an allele carries an interruption
+    #[clap(long, default_value = "0.5")]
+    pub interruption_rate: f64,
+
+    /// The minimum interruption length in base pairs
+    #[clap(long, default_value = "1")]
+    pub min_interruption_len: usize,
+
+    /// The maximum interruption length in base pairs
+    #[clap(long, default_value = "3")]
+    pub max_interruption_len: usize,
+
+    /// How interruptions are introduced into alleles
+    #[clap(long, value_enum, default_value = "both")]
+    pub interruption_mode: InterruptionMode,
+
+    /// The per-base probability of a sequencing substitution error
+    #[clap(long, default_value = "0.001")]
+    pub substitution_error_rate: f64,
+
+    /// The per-base probability of a sequencing insertion or deletion error
+    #[clap(long, default_value = "0")]
+    pub indel_error_rate: f64,
+
+    /// The random seed
+    #[clap(long, default_value = "42")]
+    pub seed: u64,
+}
+
+impl BenchmarkArgs {
+    /// Runs `strif benchmark`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let BenchmarkArgs {
+            str_catalog,
+            output,
+            filter,
+            match_score,
+            mismatch_penalty,
+            gap_open_penalty,
+            gap_extend_penalty,
+            coverage,
+            min_repeat_len,
+            max_repeat_len,
+            interruption_rate,
+            min_interruption_len,
+            max_interruption_len,
+            interruption_mode,
+            substitution_error_rate,
+            indel_error_rate,
+            seed,
+        } = self;
+        let out_path: PathBuf =
+            output.unwrap_or_else(|| get_default_out_path(&str_catalog, "benchmark", "tsv"));
+        let sim_params = SimulationParams {
+            coverage,
+            min_repeat_len,
+            max_repeat_len,
+            interruption_rate,
+            min_interruption_len,
+            max_interruption_len,
+            interruption_mode,
+            substitution_error_rate,
+            indel_error_rate,
+            seed,
+        };
+        let grid = ScoreGrid {
+            match_scores: match_score,
+            mismatch_penalties: mismatch_penalty,
+            gap_open_penalties: gap_open_penalty,
+            gap_extend_penalties: gap_extend_penalty,
+        };
+        Plan::new("benchmark", dry_run, force)
+            .catalog(&str_catalog, filter.as_deref())
+            .note("score combinations", grid.combinations().len())
+            .note("reads per locus", coverage)
+            .output("results", &out_path)
+            .execute(|| benchmark(str_catalog, out_path, sim_params, grid, filter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{data_rows, write_catalog};
+
+    fn truth(read_seq: &str, interruption: &str) -> TruthRead {
+        TruthRead {
+            motif: b"CAG".to_vec(),
+            read_seq: read_seq.as_bytes().to_vec(),
+            interruption: interruption.to_string(),
+        }
+    }
+
+    #[test]
+    fn every_combination_of_scores_is_tried() {
+        let grid = ScoreGrid {
+            match_scores: vec![1, 2],
+            mismatch_penalties: vec![4, 8],
+            gap_open_penalties: vec![10],
+            gap_extend_penalties: vec![1, 2],
+        };
+        let combinations = grid.combinations();
+        assert_eq!(combinations.len(), 8);
+        assert_eq!(
+            (
+                combinations[1].match_score,
+                combinations[1].gap_extend_penalty
+            ),
+            (1, 2)
+        );
+        assert_eq!(combinations[7].match_score, 2);
+    }
+
+    #[test]
+    fn detections_and_false_positives_are_counted_per_read() {
+        let reads = [
+            truth("CAGCAGCAGCAG", ""),
+            truth("CAGCAGCAACAGCAG", "A"),
+            // the allele's interruption is missed and another one is found
+            truth("CAGCAGCAACAGCAG", "T"),
+        ];
+        let result = run_benchmark(&reads, AlignmentScoreParams::default());
+        assert_eq!(result.interrupted_reads, 2);
+        assert_eq!(result.detected_reads, 1);
+        assert_eq!(result.false_positive_reads, 1);
+
+        let mut out = Vec::new();
+        write_result(&mut out, reads.len(), &result).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let fields: Vec<&str> = out.trim_end().split('\t').collect();
+        assert_eq!(
+            fields[..8],
+            ["1", "8", "10", "1", "3", "2", "0.5", "0.3333333333333333"]
+        );
+    }
+
+    #[test]
+    fn benchmarks_write_a_row_per_combination() {
+        let dir = tempfile::tempdir().unwrap();
+        let out_path = dir.path().join("benchmark.tsv");
+        let sim_params = SimulationParams {
+            coverage: 5,
+            min_repeat_len: 30,
+            max_repeat_len: 60,
+            interruption_rate: 1.0,
+            min_interruption_len: 1,
+            max_interruption_len: 1,
+            interruption_mode: InterruptionMode::Substitute,
+            substitution_error_rate: 0.0,
+            indel_error_rate: 0.0,
+            seed: 7,
+        };
+        let grid = ScoreGrid {
+            match_scores: vec![1],
+            mismatch_penalties: vec![4, 8],
+            gap_open_penalties: vec![10],
+            gap_extend_penalties: vec![1],
+        };
+        benchmark(
+            write_catalog(dir.path()),
+            out_path.clone(),
+            sim_params,
+            grid,
+            None,
+        )
+        .unwrap();
+
+        let rows = data_rows(&out_path);
+        assert_eq!(rows.len(), 2);
+        for row in rows {
+            let fields: Vec<&str> = row.split('\t').collect();
+            // every read of the two loci carries an interruption
+            assert_eq!(fields[4], fields[5]);
+            assert!(fields[6].parse::<f64>().unwrap() > 0.5);
+        }
+    }
+}
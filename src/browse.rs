@@ -0,0 +1,661 @@
+use std::io::prelude::*;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+use log::info;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Modifier, Style, Stylize},
+    widgets::{Block, Cell, Paragraph, Row, Table, TableState, Wrap},
+    DefaultTerminal, Frame,
+};
+
+use crate::merge::{load_manifest, read_merged_profile, MergedProfileRecord};
+use crate::plan::Plan;
+use crate::report::{load_associations, Association};
+
+/// The maximum number of visual alignments shown for a locus
+const MAX_ALIGNMENTS: usize = 50;
+
+/// How the locus table is ordered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortBy {
+    File,
+    Burden,
+    PValue,
+}
+
+impl SortBy {
+    fn next(self) -> Self {
+        match self {
+            SortBy::File => SortBy::Burden,
+            SortBy::Burden => SortBy::PValue,
+            SortBy::PValue => SortBy::File,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            SortBy::File => "file order",
+            SortBy::Burden => "mean burden",
+            SortBy::PValue => "p-value",
+        }
+    }
+}
+
+/// What the lower pane shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pane {
+    Samples,
+    Alignments,
+}
+
+/// A locus with the summaries shown in the table
+struct Locus {
+    record: MergedProfileRecord,
+    /// Total normalized interruption count of each sample
+    burdens: BTreeMap<String, f64>,
+    mean_burden: f64,
+    association: Option<Association>,
+}
+
+struct App {
+    loci: Vec<Locus>,
+    statuses: HashMap<String, String>,
+    alignments: Vec<PathBuf>,
+    /// Indices of the loci matching the search, in display order
+    visible: Vec<usize>,
+    table: TableState,
+    sort_by: SortBy,
+    search: String,
+    searching: bool,
+    pane: Pane,
+    pane_scroll: u16,
+    /// Visual alignments of the selected locus, loaded on demand
+    alignment_text: Option<(usize, String)>,
+}
+
+/// Opens an interactive terminal browser for a merged profile
+pub fn browse(
+    merged_profile: PathBuf,
+    association: Option<PathBuf>,
+    manifest: Option<PathBuf>,
+    alignments: Vec<PathBuf>,
+) -> Result<()> {
+    info!("Loading merged profile...");
+    let records = read_merged_profile(&merged_profile)?;
+    if records.is_empty() {
+        bail!("{} has no loci to browse", merged_profile.display());
+    }
+    let statuses: HashMap<String, String> = match &manifest {
+        Some(manifest) => load_manifest(manifest)?
+            .into_iter()
+            .map(|entry| (entry.sample_id, entry.status))
+            .collect(),
+        None => HashMap::new(),
+    };
+    let associations = match &association {
+        Some(association) => load_associations(association)?,
+        None => HashMap::new(),
+    };
+
+    let sort_by = if association.is_some() {
+        SortBy::PValue
+    } else {
+        SortBy::File
+    };
+    let mut app = App::new(records, associations, statuses, alignments, sort_by);
+
+    let mut terminal = ratatui::try_init()?;
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+impl App {
+    /// Summarizes the burdens of each locus and shows all of them in the given order
+    fn new(
+        records: Vec<MergedProfileRecord>,
+        mut associations: HashMap<String, Association>,
+        statuses: HashMap<String, String>,
+        alignments: Vec<PathBuf>,
+        sort_by: SortBy,
+    ) -> Self {
+        let loci: Vec<Locus> = records
+            .into_iter()
+            .map(|record| {
+                let mut burdens: BTreeMap<String, f64> = record
+                    .read_counts
+                    .iter()
+                    .map(|(sample_id, _)| (sample_id.clone(), 0.0))
+                    .collect();
+                for (sample_id, _, count) in &record.interruption_counts {
+                    *burdens.entry(sample_id.clone()).or_default() += count;
+                }
+                let mean_burden = if burdens.is_empty() {
+                    0.0
+                } else {
+                    burdens.values().sum::<f64>() / burdens.len() as f64
+                };
+                let association = associations.remove(&record.locus_id);
+                Locus {
+                    record,
+                    burdens,
+                    mean_burden,
+                    association,
+                }
+            })
+            .collect();
+
+        let mut app = App {
+            loci,
+            statuses,
+            alignments,
+            visible: Vec::new(),
+            table: TableState::default(),
+            sort_by,
+            search: String::new(),
+            searching: false,
+            pane: Pane::Samples,
+            pane_scroll: 0,
+            alignment_text: None,
+        };
+        app.refresh();
+        app
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let key = match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => key,
+                _ => continue,
+            };
+            if self.searching {
+                match key.code {
+                    KeyCode::Enter | KeyCode::Esc => self.searching = false,
+                    KeyCode::Backspace => {
+                        self.search.pop();
+                        self.refresh();
+                    }
+                    KeyCode::Char(c) => {
+                        self.search.push(c);
+                        self.refresh();
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(())
+                }
+                KeyCode::Down | KeyCode::Char('j') => self.select(1),
+                KeyCode::Up | KeyCode::Char('k') => self.select(-1),
+                KeyCode::PageDown => self.select(20),
+                KeyCode::PageUp => self.select(-20),
+                KeyCode::Home | KeyCode::Char('g') => self.select(isize::MIN / 2),
+                KeyCode::End | KeyCode::Char('G') => self.select(isize::MAX / 2),
+                KeyCode::Char('/') => {
+                    self.searching = true;
+                    self.search.clear();
+                    self.refresh();
+                }
+                KeyCode::Char('s') => {
+                    self.sort_by = self.sort_by.next();
+                    self.refresh();
+                }
+                KeyCode::Char('a') | KeyCode::Enter => {
+                    self.pane = match self.pane {
+                        Pane::Samples => Pane::Alignments,
+                        Pane::Alignments => Pane::Samples,
+                    };
+                    self.pane_scroll = 0;
+                }
+                KeyCode::Char('J') => self.pane_scroll = self.pane_scroll.saturating_add(1),
+                KeyCode::Char('K') => self.pane_scroll = self.pane_scroll.saturating_sub(1),
+                _ => {}
+            }
+        }
+    }
+
+    /// Recomputes the visible loci after the search or sort order changed
+    fn refresh(&mut self) {
+        let search = self.search.to_lowercase();
+        self.visible = (0..self.loci.len())
+            .filter(|&i| {
+                let record = &self.loci[i].record;
+                search.is_empty()
+                    || record.locus_id.to_lowercase().contains(&search)
+                    || record.reference_region.to_lowercase().contains(&search)
+                    || record.motif.to_lowercase() == search
+            })
+            .collect();
+        let loci = &self.loci;
+        match self.sort_by {
+            SortBy::File => {}
+            SortBy::Burden => self
+                .visible
+                .sort_by(|&a, &b| loci[b].mean_burden.total_cmp(&loci[a].mean_burden)),
+            SortBy::PValue => self.visible.sort_by(|&a, &b| {
+                let p = |i: usize| {
+                    loci[i]
+                        .association
+                        .as_ref()
+                        .map_or(f64::INFINITY, |a| a.p_value)
+                };
+                p(a).total_cmp(&p(b))
+            }),
+        }
+        self.table.select(if self.visible.is_empty() {
+            None
+        } else {
+            Some(0)
+        });
+        self.pane_scroll = 0;
+    }
+
+    fn select(&mut self, delta: isize) {
+        if self.visible.is_empty() {
+            return;
+        }
+        let current = self.table.selected().unwrap_or(0) as isize;
+        let selected = (current + delta).clamp(0, self.visible.len() as isize - 1);
+        self.table.select(Some(selected as usize));
+        self.pane_scroll = 0;
+    }
+
+    fn selected_locus(&self) -> Option<usize> {
+        self.table.selected().map(|i| self.visible[i])
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [table_area, pane_area, help_area] = Layout::vertical([
+            Constraint::Percentage(55),
+            Constraint::Fill(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        self.draw_table(frame, table_area);
+        match self.pane {
+            Pane::Samples => self.draw_samples(frame, pane_area),
+            Pane::Alignments => self.draw_alignments(frame, pane_area),
+        }
+
+        let help = if self.searching {
+            format!("Search: {}_  (Enter to finish)", self.search)
+        } else {
+            "q quit  ↑/↓ move  / search  s sort  a samples/alignments  J/K scroll pane".to_string()
+        };
+        frame.render_widget(Paragraph::new(help).reversed(), help_area);
+    }
+
+    fn draw_table(&mut self, frame: &mut Frame, area: Rect) {
+        let header = Row::new([
+            "locus_id",
+            "region",
+            "motif",
+            "samples",
+            "mean burden",
+            "p-value",
+            "adjusted",
+        ])
+        .style(Style::new().bold());
+        let rows: Vec<Row> = self
+            .visible
+            .iter()
+            .map(|&i| {
+                let locus = &self.loci[i];
+                let (p_value, adjusted) = match &locus.association {
+                    Some(a) => (format!("{:.3e}", a.p_value), a.adjusted_p_value.clone()),
+                    None => (".".to_string(), ".".to_string()),
+                };
+                Row::new([
+                    Cell::from(locus.record.locus_id.clone()),
+                    Cell::from(locus.record.reference_region.clone()),
+                    Cell::from(locus.record.motif.clone()),
+                    Cell::from(locus.record.read_counts.len().to_string()),
+                    Cell::from(format!("{:.4}", locus.mean_burden)),
+                    Cell::from(p_value),
+                    Cell::from(adjusted),
+                ])
+            })
+            .collect();
+        let title = format!(
+            " {} of {} loci, sorted by {}{} ",
+            self.visible.len(),
+            self.loci.len(),
+            self.sort_by.name(),
+            if self.search.is_empty() {
+                String::new()
+            } else {
+                format!(", matching '{}'", self.search)
+            }
+        );
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Fill(2),
+                Constraint::Fill(2),
+                Constraint::Fill(1),
+                Constraint::Length(8),
+                Constraint::Length(12),
+                Constraint::Length(10),
+                Constraint::Length(10),
+            ],
+        )
+        .header(header)
+        .block(Block::bordered().title(title))
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, area, &mut self.table);
+    }
+
+    fn draw_samples(&self, frame: &mut Frame, area: Rect) {
+        let locus = match self.selected_locus() {
+            Some(i) => &self.loci[i],
+            None => {
+                let block = Block::bordered().title(" Samples ");
+                frame.render_widget(Paragraph::new("No locus selected").block(block), area);
+                return;
+            }
+        };
+        let read_counts: HashMap<&str, u32> = locus
+            .record
+            .read_counts
+            .iter()
+            .map(|(id, count)| (id.as_str(), *count))
+            .collect();
+        let mut interruptions: HashMap<&str, Vec<String>> = HashMap::new();
+        for (sample_id, interruption, count) in &locus.record.interruption_counts {
+            interruptions
+                .entry(sample_id)
+                .or_default()
+                .push(format!("{}:{:.4}", interruption, count));
+        }
+
+        let mut samples: Vec<(&String, &f64)> = locus.burdens.iter().collect();
+        samples.sort_by(|a, b| b.1.total_cmp(a.1).then(a.0.cmp(b.0)));
+        let rows: Vec<Row> = samples
+            .into_iter()
+            .skip(self.pane_scroll as usize)
+            .map(|(sample_id, burden)| {
+                Row::new([
+                    sample_id.clone(),
+                    self.statuses
+                        .get(sample_id)
+                        .map_or(".".to_string(), |s| s.clone()),
+                    read_counts
+                        .get(sample_id.as_str())
+                        .unwrap_or(&0)
+                        .to_string(),
+                    format!("{:.4}", burden),
+                    interruptions
+                        .get(sample_id.as_str())
+                        .map_or(".".to_string(), |i| i.join(",")),
+                ])
+            })
+            .collect();
+        let title = match &locus.association {
+            Some(a) => format!(
+                " Samples of {} (top association: {} effect={} p={:.3e}) ",
+                locus.record.locus_id, a.interruption, a.effect, a.p_value
+            ),
+            None => format!(" Samples of {} ", locus.record.locus_id),
+        };
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Fill(1),
+                Constraint::Length(10),
+                Constraint::Length(7),
+                Constraint::Length(10),
+                Constraint::Fill(3),
+            ],
+        )
+        .header(Row::new(["sample", "status", "reads", "burden", "interruptions"]).bold())
+        .block(Block::bordered().title(title));
+        frame.render_widget(table, area);
+    }
+
+    fn draw_alignments(&mut self, frame: &mut Frame, area: Rect) {
+        let block = Block::bordered().title(" Visual alignments ");
+        let i = match self.selected_locus() {
+            Some(i) => i,
+            None => return,
+        };
+        if self.alignment_text.as_ref().map(|(j, _)| *j) != Some(i) {
+            let text = if self.alignments.is_empty() {
+                "No visual alignments given, use --alignments with the output of `profile -z`"
+                    .to_string()
+            } else {
+                find_alignments(&self.alignments, &self.loci[i].record.locus_id)
+                    .unwrap_or_else(|e| format!("Could not read the visual alignments: {}", e))
+            };
+            self.alignment_text = Some((i, text));
+        }
+        let text = self.alignment_text.as_ref().unwrap().1.as_str();
+        let paragraph = Paragraph::new(text)
+            .block(block)
+            .wrap(Wrap { trim: false })
+            .scroll((self.pane_scroll, 0));
+        frame.render_widget(paragraph, area);
+    }
+}
+
+/// Collects the visual alignments of a locus from the output of `profile -z`
+fn find_alignments(paths: &[PathBuf], locus_id: &str) -> Result<String> {
+    let header = format!("Locus {}:", locus_id);
+    let mut text = String::new();
+    let mut n_found: usize = 0;
+    for path in paths {
+        let mut in_locus = false;
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if line.starts_with("Locus ") && line.ends_with(':') {
+                in_locus = line == header;
+                if in_locus {
+                    n_found += 1;
+                    if n_found > MAX_ALIGNMENTS {
+                        break;
+                    }
+                    text.push_str(&format!("{} ({})\n", line, file_name(path)));
+                }
+                continue;
+            }
+            if in_locus {
+                text.push_str(&line);
+                text.push('\n');
+            }
+        }
+        if n_found > MAX_ALIGNMENTS {
+            text.push_str(&format!(
+                "... only the first {} alignments are shown\n",
+                MAX_ALIGNMENTS
+            ));
+            break;
+        }
+    }
+    if n_found == 0 {
+        text = format!("No visual alignments of {} were found", locus_id);
+    }
+    Ok(text)
+}
+
+fn file_name(path: &Path) -> &str {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default()
+}
+
+/// The command line arguments of `strif browse`
+#[derive(Debug, clap::Args)]
+pub struct BrowseArgs {
+    /// The path to the merged profile
+    pub merged_profile: PathBuf,
+
+    /// The path to the output of `test`, used to show and sort loci by p-value
+    #[clap(short = 'a', long)]
+    pub association: Option<PathBuf>,
+
+    /// The path to the manifest, used to show the case-control status of samples
+    #[clap(short = 'm', long)]
+    pub manifest: Option<PathBuf>,
+
+    /// The path to a visual alignments file written by `profile -z`. Can be repeated.
+    #[clap(short = 'z', long)]
+    pub alignments: Vec<PathBuf>,
+}
+
+impl BrowseArgs {
+    /// Runs `strif browse`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let BrowseArgs {
+            merged_profile,
+            association,
+            manifest,
+            alignments,
+        } = self;
+        Plan::new("browse", dry_run, force)
+            .merged_profile("merged profile", &merged_profile)
+            .maybe_input("association results", association.as_deref())
+            .maybe_input("manifest", manifest.as_deref())
+            .inputs("visual alignments", &alignments)
+            .execute(|| browse(merged_profile, association, manifest, alignments))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use ratatui::{backend::TestBackend, Terminal};
+
+    use super::*;
+    use crate::testing::merged_record;
+
+    fn app(sort_by: SortBy) -> App {
+        let mut fmr1 = merged_record("FMR1", &[("s1", 10), ("s2", 10)], &[("s1", "CGA", 2.0)]);
+        fmr1.motif = "CGG".to_string();
+        let records = vec![
+            merged_record(
+                "HTT",
+                &[("s1", 10), ("s2", 10)],
+                &[("s1", "CAA", 0.5), ("s2", "CAA", 0.5)],
+            ),
+            fmr1,
+            merged_record("ATXN3", &[("s1", 10), ("s2", 10)], &[]),
+        ];
+        let associations = HashMap::from([(
+            "ATXN3".to_string(),
+            Association {
+                interruption: "CAA".to_string(),
+                effect: "0.5".to_string(),
+                p_value: 0.01,
+                adjusted_p_value: "0.03".to_string(),
+            },
+        )]);
+        let statuses = HashMap::from([("s1".to_string(), "case".to_string())]);
+        App::new(records, associations, statuses, Vec::new(), sort_by)
+    }
+
+    fn visible_loci(app: &App) -> Vec<&str> {
+        app.visible
+            .iter()
+            .map(|&i| app.loci[i].record.locus_id.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn loci_are_sorted_by_burden_or_p_value() {
+        let mut app = app(SortBy::File);
+        assert_eq!(visible_loci(&app), ["HTT", "FMR1", "ATXN3"]);
+        assert_eq!(app.loci[1].mean_burden, 1.0);
+
+        app.sort_by = app.sort_by.next();
+        app.refresh();
+        assert_eq!(visible_loci(&app), ["FMR1", "HTT", "ATXN3"]);
+
+        app.sort_by = app.sort_by.next();
+        app.refresh();
+        assert_eq!(visible_loci(&app)[0], "ATXN3");
+    }
+
+    #[test]
+    fn searches_match_locus_ids_regions_and_motifs() {
+        let mut app = app(SortBy::File);
+        app.search = "htt".to_string();
+        app.refresh();
+        assert_eq!(visible_loci(&app), ["HTT"]);
+
+        app.search = "cgg".to_string();
+        app.refresh();
+        assert_eq!(visible_loci(&app), ["FMR1"]);
+
+        app.search = "chr1:100".to_string();
+        app.refresh();
+        assert_eq!(app.visible.len(), 3);
+
+        app.search = "none".to_string();
+        app.refresh();
+        assert!(app.visible.is_empty());
+        assert_eq!(app.selected_locus(), None);
+    }
+
+    #[test]
+    fn selections_stay_within_the_visible_loci() {
+        let mut app = app(SortBy::File);
+        app.select(isize::MAX / 2);
+        assert_eq!(app.selected_locus(), Some(2));
+        app.select(-1);
+        assert_eq!(app.selected_locus(), Some(1));
+        app.select(isize::MIN / 2);
+        assert_eq!(app.selected_locus(), Some(0));
+    }
+
+    #[test]
+    fn the_samples_pane_shows_statuses_and_interruptions() {
+        let mut app = app(SortBy::Burden);
+        let mut terminal = Terminal::new(TestBackend::new(120, 30)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+
+        assert!(screen.contains("3 of 3 loci, sorted by mean burden"));
+        assert!(screen.contains("Samples of FMR1"));
+        assert!(screen.contains("case"));
+        assert!(screen.contains("CGA:2.0000"));
+    }
+
+    #[test]
+    fn alignments_of_a_locus_are_collected_across_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("s1.txt");
+        fs::write(
+            &path,
+            "Locus HTT:\nCAGCAACAG\nLocus FMR1:\nCGGCGACGG\nLocus HTT:\nCAGCAG\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            find_alignments(std::slice::from_ref(&path), "HTT").unwrap(),
+            "Locus HTT: (s1.txt)\nCAGCAACAG\nLocus HTT: (s1.txt)\nCAGCAG\n"
+        );
+        assert_eq!(
+            find_alignments(&[path], "ATXN3").unwrap(),
+            "No visual alignments of ATXN3 were found"
+        );
+    }
+}
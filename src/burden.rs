@@ -0,0 +1,399 @@
+use std::io::prelude::*;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+use log::{info, warn};
+
+use crate::annotate::load_annotation;
+use crate::association::{adjust_p_values, logistic_test, mann_whitney_test, Correction, Model};
+use crate::merge::{load_manifest, read_merged_profile};
+use crate::plan::Plan;
+use crate::schema::{self, OutputKind};
+use crate::utils::{create_output, get_default_out_path, Region};
+
+pub struct BurdenParams {
+    /// A GMT file of gene sets to aggregate over instead of single genes
+    pub gene_sets: Option<PathBuf>,
+    pub promoter_size: u64,
+    pub model: Model,
+    pub correction: Correction,
+    /// Minimum number of samples per group to test a gene or gene set
+    pub min_samples: usize,
+}
+
+/// Aggregates interruption burden per gene (or gene set) and tests for a
+/// difference between cases and controls
+pub fn burden(
+    merged_profile: PathBuf,
+    annotation: PathBuf,
+    manifest: PathBuf,
+    out_path: PathBuf,
+    params: BurdenParams,
+) -> Result<()> {
+    if matches!(params.model, Model::Linear | Model::KruskalWallis) {
+        bail!(
+            "Gene burden tests compare cases and controls, use the logistic or mann-whitney model"
+        );
+    }
+
+    let statuses: HashMap<String, f64> = load_manifest(&manifest)?
+        .into_iter()
+        .filter_map(|entry| match entry.status.to_lowercase().as_str() {
+            "case" => Some((entry.sample_id, 1.0)),
+            "control" => Some((entry.sample_id, 0.0)),
+            _ => None,
+        })
+        .collect();
+    if statuses.is_empty() {
+        bail!(
+            "{} has no samples with a case or control status",
+            manifest.display()
+        );
+    }
+
+    info!("Loading annotation...");
+    let annotation = load_annotation(&annotation, params.promoter_size)?;
+    let gene_sets = match &params.gene_sets {
+        Some(path) => Some(load_gene_sets(path)?),
+        None => None,
+    };
+
+    // group -> (number of loci, sample -> burden)
+    info!("Aggregating burden...");
+    let mut groups: BTreeMap<String, (usize, BTreeMap<String, f64>)> = BTreeMap::new();
+    for record in read_merged_profile(&merged_profile)? {
+        let genes: Vec<String> = match record.reference_region.parse::<Region>() {
+            Ok(region) => annotation.annotate(&region).0,
+            Err(e) => {
+                warn!("Could not annotate {}: {}", record.locus_id, e);
+                continue;
+            }
+        };
+        let mut locus_groups: HashSet<&str> = HashSet::new();
+        for gene in &genes {
+            match &gene_sets {
+                Some(gene_sets) => {
+                    if let Some(sets) = gene_sets.get(gene) {
+                        locus_groups.extend(sets.iter().map(|s| s.as_str()));
+                    }
+                }
+                None => {
+                    locus_groups.insert(gene);
+                }
+            }
+        }
+
+        for group in locus_groups {
+            let (loci, burdens) = groups.entry(group.to_string()).or_default();
+            *loci += 1;
+            for (sample_id, _) in &record.read_counts {
+                burdens.entry(sample_id.clone()).or_default();
+            }
+            for (sample_id, _, count) in &record.interruption_counts {
+                *burdens.entry(sample_id.clone()).or_default() += count;
+            }
+        }
+    }
+    info!("Testing {} groups...", groups.len());
+
+    struct GroupTest {
+        group: String,
+        loci: usize,
+        n_cases: usize,
+        n_controls: usize,
+        mean_cases: f64,
+        mean_controls: f64,
+        effect: f64,
+        statistic: f64,
+        p_value: f64,
+    }
+    let mut tests: Vec<GroupTest> = Vec::new();
+    let mut n_skipped: usize = 0;
+    for (group, (loci, burdens)) in groups {
+        let (x, y): (Vec<f64>, Vec<f64>) = burdens
+            .iter()
+            .filter_map(|(sample_id, burden)| statuses.get(sample_id).map(|s| (*burden, *s)))
+            .unzip();
+        let n_cases = y.iter().filter(|s| **s == 1.0).count();
+        let n_controls = y.len() - n_cases;
+        if n_cases < params.min_samples || n_controls < params.min_samples {
+            n_skipped += 1;
+            continue;
+        }
+        let test = match params.model {
+            Model::Logistic => logistic_test(&x, &y, &vec![&[][..]; x.len()]),
+            _ => mann_whitney_test(&x, &y),
+        };
+        let (effect, _, statistic, p_value) = match test {
+            Some(test) => test,
+            None => {
+                n_skipped += 1;
+                continue;
+            }
+        };
+        let mean = |group: f64| -> f64 {
+            let values: Vec<f64> = x
+                .iter()
+                .zip(&y)
+                .filter(|(_, s)| **s == group)
+                .map(|(x, _)| *x)
+                .collect();
+            values.iter().sum::<f64>() / values.len() as f64
+        };
+        tests.push(GroupTest {
+            group,
+            loci,
+            n_cases,
+            n_controls,
+            mean_cases: mean(1.0),
+            mean_controls: mean(0.0),
+            effect,
+            statistic,
+            p_value,
+        });
+    }
+    if n_skipped > 0 {
+        warn!(
+            "Skipped {} group(s) with too few cases or controls or no variation in burden",
+            n_skipped
+        );
+    }
+
+    let p_values: Vec<f64> = tests.iter().map(|t| t.p_value).collect();
+    let adjusted = adjust_p_values(&p_values, params.correction);
+    let mut order: Vec<usize> = (0..tests.len()).collect();
+    order.sort_by(|&a, &b| {
+        p_values[a]
+            .total_cmp(&p_values[b])
+            .then(tests[a].group.cmp(&tests[b].group))
+    });
+
+    let mut out_file = create_output(&out_path)?;
+    schema::write_header(&mut out_file, OutputKind::GeneBurden)?;
+    for i in order {
+        let t = &tests[i];
+        writeln!(
+            out_file,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            t.group,
+            t.loci,
+            t.n_cases,
+            t.n_controls,
+            t.mean_cases,
+            t.mean_controls,
+            t.effect,
+            t.statistic,
+            t.p_value,
+            adjusted[i]
+        )?;
+    }
+
+    out_file.finish()?;
+
+    info!("Done!");
+    Ok(())
+}
+
+/// Loads gene sets from a GMT file (set name, description, genes...) as a map
+/// from each gene to the sets it belongs to
+fn load_gene_sets(path: &Path) -> Result<HashMap<String, Vec<String>>> {
+    let mut gene_sets: HashMap<String, Vec<String>> = HashMap::new();
+    for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 3 {
+            bail!(
+                "{} line {} should have a set name, a description and at least one gene",
+                path.display(),
+                i + 1
+            );
+        }
+        for gene in &fields[2..] {
+            if !gene.is_empty() {
+                gene_sets
+                    .entry(gene.to_string())
+                    .or_default()
+                    .push(fields[0].to_string());
+            }
+        }
+    }
+    Ok(gene_sets)
+}
+
+/// The command line arguments of `strif burden`
+#[derive(Debug, clap::Args)]
+pub struct BurdenArgs {
+    /// The path to the merged profile
+    pub merged_profile: PathBuf,
+
+    /// The path to the gene-level results. Defaults to the same directory as the merged profile.
+    pub output: Option<PathBuf>,
+
+    /// The path to a gene annotation in BED (name in the fourth column) or GFF3 format
+    #[clap(short = 'a', long)]
+    pub annotation: PathBuf,
+
+    /// The path to the manifest, used for the case-control status of samples
+    #[clap(short = 'm', long)]
+    pub manifest: PathBuf,
+
+    /// The path to a GMT file of gene sets (e.g. pathways) to aggregate over instead of single genes
+    #[clap(short = 'g', long)]
+    pub gene_sets: Option<PathBuf>,
+
+    /// The number of base pairs upstream of a gene's start considered as its promoter
+    #[clap(short = 'p', long, default_value = "1000")]
+    pub promoter_size: u64,
+
+    /// The test comparing the burden of cases and controls
+    #[clap(long, value_enum, default_value = "mann-whitney")]
+    pub model: Model,
+
+    /// The multiple-testing correction
+    #[clap(long, value_enum, default_value = "bh")]
+    pub correction: Correction,
+
+    /// Minimum number of cases and of controls to test a gene or gene set
+    #[clap(short = 'n', long, default_value = "2")]
+    pub min_samples: usize,
+}
+
+impl BurdenArgs {
+    /// Runs `strif burden`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let BurdenArgs {
+            merged_profile,
+            output,
+            annotation,
+            manifest,
+            gene_sets,
+            promoter_size,
+            model,
+            correction,
+            min_samples,
+        } = self;
+        let out_path: PathBuf =
+            output.unwrap_or_else(|| get_default_out_path(&merged_profile, "gene_burden", "tsv"));
+        let params = BurdenParams {
+            gene_sets,
+            promoter_size,
+            model,
+            correction,
+            min_samples,
+        };
+        Plan::new("burden", dry_run, force)
+            .merged_profile("merged profile", &merged_profile)
+            .input("annotation", &annotation)
+            .input("manifest", &manifest)
+            .maybe_input("gene sets", params.gene_sets.as_deref())
+            .output("gene burden", &out_path)
+            .execute(|| burden(merged_profile, annotation, manifest, out_path, params))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::testing::{data_rows, merged_record, write_merged_profile_file};
+
+    /// The rows of a Mann-Whitney burden test of two genes, aggregated over
+    /// genes or over a GMT gene set of both
+    fn burden_rows(gene_sets: bool) -> Vec<String> {
+        let dir = tempfile::tempdir().unwrap();
+        let samples = [
+            ("c1", 10),
+            ("c2", 10),
+            ("c3", 10),
+            ("k1", 10),
+            ("k2", 10),
+            ("k3", 10),
+        ];
+        let mut records = vec![
+            merged_record(
+                "L1",
+                &samples,
+                &[
+                    ("c1", "CAT", 0.3),
+                    ("c2", "CAT", 0.4),
+                    ("c3", "CAT", 0.5),
+                    ("k1", "CAT", 0.1),
+                ],
+            ),
+            merged_record("L2", &samples, &[("c1", "A", 0.1), ("k2", "A", 0.05)]),
+            merged_record("L3", &samples, &[("k3", "A", 0.2)]),
+            merged_record("L4", &samples, &[("c1", "A", 1.0)]),
+        ];
+        for (record, region) in records.iter_mut().zip([
+            "chr1:100-200",
+            "chr1:250-280",
+            "chr2:100-200",
+            "chr3:100-200",
+        ]) {
+            record.reference_region = region.to_string();
+        }
+        let merged_profile = write_merged_profile_file(dir.path(), "merged.tsv", &records);
+        let annotation = dir.path().join("genes.bed");
+        fs::write(&annotation, "chr1\t50\t300\tGENE1\nchr2\t0\t1000\tGENE2\n").unwrap();
+        let manifest = dir.path().join("manifest.tsv");
+        let manifest_rows: String = samples
+            .iter()
+            .map(|(sample_id, _)| {
+                let status = if sample_id.starts_with('c') {
+                    "case"
+                } else {
+                    "control"
+                };
+                format!("{}\t{}\t{}.tsv\n", sample_id, status, sample_id)
+            })
+            .collect();
+        fs::write(&manifest, manifest_rows).unwrap();
+        let gene_sets = gene_sets.then(|| {
+            let path = dir.path().join("sets.gmt");
+            fs::write(&path, "BOTH\tgenes 1 and 2\tGENE1\tGENE2\n").unwrap();
+            path
+        });
+
+        let out = dir.path().join("burden.tsv");
+        let params = BurdenParams {
+            gene_sets,
+            promoter_size: 0,
+            model: Model::MannWhitney,
+            correction: Correction::Bonferroni,
+            min_samples: 3,
+        };
+        burden(merged_profile, annotation, manifest, out.clone(), params).unwrap();
+        data_rows(&out)
+    }
+
+    #[test]
+    fn burdens_are_summed_over_the_loci_of_a_gene() {
+        let rows = burden_rows(false);
+        assert_eq!(rows.len(), 2);
+        let gene1: Vec<&str> = rows[0].split('\t').collect();
+        // case burdens 0.4, 0.4, 0.5 against control burdens 0.1, 0.05, 0
+        assert_eq!(&gene1[..4], ["GENE1", "2", "3", "3"]);
+        assert!((gene1[4].parse::<f64>().unwrap() - 1.3 / 3.0).abs() < 1e-9);
+        assert!((gene1[5].parse::<f64>().unwrap() - 0.05).abs() < 1e-9);
+        assert_eq!(gene1[7], "9");
+        let p_value: f64 = gene1[8].parse().unwrap();
+        assert_eq!(gene1[9], (2.0 * p_value).to_string());
+        assert!(rows[1].starts_with("GENE2\t1\t3\t3\t"));
+    }
+
+    #[test]
+    fn gene_sets_aggregate_the_loci_of_their_genes() {
+        let rows = burden_rows(true);
+        assert_eq!(rows.len(), 1);
+        assert!(rows[0].starts_with("BOTH\t3\t3\t3\t"));
+    }
+}
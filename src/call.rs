@@ -0,0 +1,297 @@
+use std::io::prelude::*;
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::{bail, Ok, Result};
+use log::info;
+
+use crate::plan::Plan;
+use crate::profile::read_profile;
+use crate::schema::{self, OutputKind};
+use crate::utils::{create_output, get_default_out_path};
+
+/// The maximum reported genotype quality
+const MAX_QUALITY: f64 = 99.0;
+
+pub struct CallParams {
+    /// The probability that a read shows (or hides) an interruption due to error
+    pub error_rate: f64,
+    /// Loci with fewer reads are reported with a `LowDepth` filter
+    pub min_read_count: u32,
+    /// The minimum quality for a call to pass
+    pub min_quality: f64,
+}
+
+pub fn call(profile_path: PathBuf, out_path: PathBuf, params: CallParams) -> Result<()> {
+    if !(params.error_rate > 0.0 && params.error_rate < 0.5) {
+        bail!(
+            "The error rate must be between 0 and 0.5, got {}",
+            params.error_rate
+        );
+    }
+
+    info!("Loading profile...");
+    let records = read_profile(&profile_path)?;
+
+    info!(
+        "Calling interruption genotypes for {} loci...",
+        records.len()
+    );
+    let mut out_file = create_output(&out_path)?;
+    schema::write_header(&mut out_file, OutputKind::Calls)?;
+
+    for record in records {
+        // supporting reads and the repeat lengths of those reads per interruption
+        let mut support: HashMap<&str, (f64, Vec<u32>)> = HashMap::new();
+        for (interruption, repeat_len, count) in &record.interruption_counts {
+            let entry = support.entry(interruption).or_default();
+            entry.0 += count;
+            if !entry.1.contains(repeat_len) {
+                entry.1.push(*repeat_len);
+            }
+        }
+
+        let low_depth = record.read_count < params.min_read_count;
+        let mut called_any = false;
+        let mut interruptions: Vec<(&str, (f64, Vec<u32>))> = support.into_iter().collect();
+        interruptions.sort_by(|a, b| b.1 .0.total_cmp(&a.1 .0).then(a.0.cmp(b.0)));
+
+        for (interruption, (supporting_reads, mut allele_lengths)) in interruptions {
+            // an interruption can occur several times in one read, so cap the
+            // support, and expected counts of quality-weighted profiles are rounded
+            let supporting_reads = (supporting_reads.round() as u32).min(record.read_count);
+            let (genotype, quality) =
+                call_genotype(supporting_reads, record.read_count, params.error_rate);
+            if genotype == Genotype::HomRef {
+                continue;
+            }
+            called_any = true;
+            allele_lengths.sort_unstable();
+            let allele_lengths_str = allele_lengths
+                .iter()
+                .map(|l| l.to_string())
+                .collect::<Vec<String>>()
+                .join(",");
+            writeln!(
+                out_file,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{:.4}\t{}\t{:.1}\t{}\t{}",
+                record.locus_id,
+                record.reference_region,
+                record.motif,
+                record.read_count,
+                interruption,
+                supporting_reads,
+                supporting_reads as f64 / record.read_count as f64,
+                genotype,
+                quality,
+                filter_label(low_depth, quality, params.min_quality),
+                allele_lengths_str
+            )?;
+        }
+
+        // report loci without any interruption so absence is an explicit call
+        if !called_any {
+            let (genotype, quality) = call_genotype(0, record.read_count, params.error_rate);
+            writeln!(
+                out_file,
+                "{}\t{}\t{}\t{}\t.\t0\t0.0000\t{}\t{:.1}\t{}\t",
+                record.locus_id,
+                record.reference_region,
+                record.motif,
+                record.read_count,
+                genotype,
+                quality,
+                filter_label(low_depth, quality, params.min_quality),
+            )?;
+        }
+    }
+
+    out_file.finish()?;
+
+    info!("Done!");
+
+    Ok(())
+}
+
+/// Diploid genotype of a single interruption
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Genotype {
+    HomRef,
+    Het,
+    HomAlt,
+}
+
+impl std::fmt::Display for Genotype {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Genotype::HomRef => write!(f, "0/0"),
+            Genotype::Het => write!(f, "0/1"),
+            Genotype::HomAlt => write!(f, "1/1"),
+        }
+    }
+}
+
+/// Calls the most likely diploid genotype from the number of reads supporting
+/// an interruption using a binomial model, returning the genotype and its
+/// Phred-scaled quality
+pub fn call_genotype(supporting: u32, total: u32, error_rate: f64) -> (Genotype, f64) {
+    if total == 0 {
+        return (Genotype::HomRef, 0.0);
+    }
+    let k = supporting as f64;
+    let n = total as f64;
+    let log_likelihood = |p: f64| k * p.ln() + (n - k) * (1.0 - p).ln();
+
+    let genotypes = [Genotype::HomRef, Genotype::Het, Genotype::HomAlt];
+    let log_likelihoods = [
+        log_likelihood(error_rate),
+        log_likelihood(0.5),
+        log_likelihood(1.0 - error_rate),
+    ];
+
+    let (best, best_ll) = log_likelihoods
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .map(|(i, ll)| (i, *ll))
+        .unwrap();
+
+    // posterior probability of the best genotype under a flat prior
+    let others: f64 = log_likelihoods
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != best)
+        .map(|(_, ll)| (ll - best_ll).exp())
+        .sum();
+    let error_prob = others / (1.0 + others);
+    let quality = if error_prob > 0.0 {
+        (-10.0 * error_prob.log10()).min(MAX_QUALITY)
+    } else {
+        MAX_QUALITY
+    };
+
+    (genotypes[best], quality)
+}
+
+fn filter_label(low_depth: bool, quality: f64, min_quality: f64) -> &'static str {
+    if low_depth {
+        "LowDepth"
+    } else if quality < min_quality {
+        "LowQual"
+    } else {
+        "PASS"
+    }
+}
+
+/// The command line arguments of `strif call`
+#[derive(Debug, clap::Args)]
+pub struct CallArgs {
+    /// The path to the interruption profile
+    pub profile: PathBuf,
+
+    /// The path to write the calls to. Defaults to the same directory as the profile.
+    pub output: Option<PathBuf>,
+
+    /// The probability that a read shows or hides an interruption due to error
+    #[clap(short = 'e', long, default_value = "0.02")]
+    pub error_rate: f64,
+
+    /// Loci with fewer reads are reported with a LowDepth filter
+    #[clap(short = 'm', long, default_value = "10")]
+    pub min_read_count: u32,
+
+    /// Calls with a lower quality are reported with a LowQual filter
+    #[clap(short = 'q', long, default_value = "20")]
+    pub min_quality: f64,
+}
+
+impl CallArgs {
+    /// Runs `strif call`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let CallArgs {
+            profile,
+            output,
+            error_rate,
+            min_read_count,
+            min_quality,
+        } = self;
+        let out_path: PathBuf =
+            output.unwrap_or_else(|| get_default_out_path(&profile, "strif_calls", "tsv"));
+        let params = CallParams {
+            error_rate,
+            min_read_count,
+            min_quality,
+        };
+        Plan::new("call", dry_run, force)
+            .profile("profile", &profile)
+            .output("calls", &out_path)
+            .execute(|| call(profile, out_path, params))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{data_rows, profile_record, write_profile_file};
+
+    #[test]
+    fn genotypes_follow_the_fraction_of_supporting_reads() {
+        assert_eq!(call_genotype(0, 20, 0.01).0, Genotype::HomRef);
+        assert_eq!(call_genotype(10, 20, 0.01), (Genotype::Het, MAX_QUALITY));
+        assert_eq!(call_genotype(20, 20, 0.01).0, Genotype::HomAlt);
+        // homozygous calls are only as good as the odds against a heterozygote
+        let (_, quality) = call_genotype(20, 20, 0.01);
+        assert!((quality - 59.33).abs() < 0.01, "{}", quality);
+        assert_eq!(call_genotype(0, 0, 0.01), (Genotype::HomRef, 0.0));
+
+        // one of two reads is weak evidence of a heterozygous interruption
+        let (genotype, quality) = call_genotype(1, 2, 0.01);
+        assert_eq!(genotype, Genotype::Het);
+        assert!((quality - 11.34).abs() < 0.01, "{}", quality);
+    }
+
+    #[test]
+    fn calls_are_written_per_interruption_with_filters() {
+        let dir = tempfile::tempdir().unwrap();
+        let records = [
+            profile_record(
+                "L1",
+                20,
+                &[("CAT", 40, 6.0), ("CAT", 43, 4.0), ("A", 40, 20.0)],
+            ),
+            profile_record("L2", 2, &[("CAT", 40, 1.0)]),
+            profile_record("L3", 20, &[]),
+        ];
+        let profile = write_profile_file(dir.path(), "profile.tsv", &records);
+
+        let calls = dir.path().join("calls.tsv");
+        let params = CallParams {
+            error_rate: 0.01,
+            min_read_count: 5,
+            min_quality: 20.0,
+        };
+        call(profile, calls.clone(), params).unwrap();
+        assert_eq!(
+            data_rows(&calls),
+            [
+                "L1\tchr1:100-200\tCAG\t20\tA\t20\t1.0000\t1/1\t59.3\tPASS\t40",
+                "L1\tchr1:100-200\tCAG\t20\tCAT\t10\t0.5000\t0/1\t99.0\tPASS\t40,43",
+                "L2\tchr1:100-200\tCAG\t2\tCAT\t1\t0.5000\t0/1\t11.3\tLowDepth\t40",
+                "L3\tchr1:100-200\tCAG\t20\t.\t0\t0.0000\t0/0\t59.3\tPASS\t",
+            ]
+        );
+    }
+
+    #[test]
+    fn error_rates_must_be_below_one_half() {
+        let params = CallParams {
+            error_rate: 0.5,
+            min_read_count: 5,
+            min_quality: 20.0,
+        };
+        let error = call(PathBuf::from("p.tsv"), PathBuf::from("c.tsv"), params).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The error rate must be between 0 and 0.5, got 0.5"
+        );
+    }
+}
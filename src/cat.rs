@@ -0,0 +1,271 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use log::{debug, info};
+
+use crate::error::StrifError;
+use crate::extract::{read_repeat_seqs, RepeatSeqRecord, MISSING_QUALITIES};
+use crate::plan::Plan;
+use crate::schema::{self, OutputKind};
+use crate::utils::create_output;
+
+/// The optional columns of repeat sequences, which depend on the options
+/// they were extracted with and must be the same for every run of a sample
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Columns {
+    qualities: bool,
+    anchoring: bool,
+    read_names: bool,
+    coordinates: bool,
+}
+
+impl Columns {
+    /// The optional columns of a run. Some reads of a run extracted with
+    /// `--qualities` may still lack qualities, so a column counts as present
+    /// if any of its reads has it.
+    fn of(records: &[RepeatSeqRecord]) -> Self {
+        Self {
+            qualities: records.iter().any(|record| {
+                record
+                    .qualities
+                    .as_deref()
+                    .is_some_and(|qualities| qualities != MISSING_QUALITIES)
+            }),
+            anchoring: records.iter().any(|record| record.anchoring.is_some()),
+            read_names: records.iter().any(|record| record.read_name.is_some()),
+            coordinates: records.iter().any(|record| record.coordinates.is_some()),
+        }
+    }
+
+    /// The extract options behind the columns, e.g. `--qualities --anchoring`
+    fn describe(&self) -> String {
+        let options: Vec<&str> = [
+            (self.qualities, "--qualities"),
+            (self.anchoring, "--anchoring"),
+            (self.read_names, "--read-names"),
+            (self.coordinates, "--coordinates"),
+        ]
+        .into_iter()
+        .filter_map(|(set, option)| set.then_some(option))
+        .collect();
+        if options.is_empty() {
+            "none of --qualities, --anchoring, --read-names and --coordinates".to_string()
+        } else {
+            options.join(" ")
+        }
+    }
+}
+
+/// Concatenates the repeat sequences of several extraction runs of a sample,
+/// e.g. of its per-lane BAMlets, into a single file for profiling. The runs
+/// must have been extracted with the same options. With `dedup_read_names`,
+/// the reads of a locus whose name was already seen in an earlier run are
+/// dropped, so that a read extracted twice is only counted once; the mates of
+/// a fragment share a name but come from the same run, so both are kept.
+pub fn cat(inputs: Vec<PathBuf>, out_path: PathBuf, dedup_read_names: bool) -> Result<()> {
+    let mut columns: Option<(Columns, &Path)> = None;
+    // the (locus, read name) pairs of the runs written so far
+    let mut seen: HashSet<(String, String)> = HashSet::new();
+    let mut rows: u64 = 0;
+    let mut duplicates: u64 = 0;
+
+    let mut out_file = create_output(&out_path)?;
+    schema::write_header(&mut out_file, OutputKind::RepeatSeqs)?;
+    for input in &inputs {
+        let kind = schema::detect(input)?;
+        if kind != OutputKind::RepeatSeqs {
+            bail!(
+                "{} is a '{}' file, expected a '{}' file",
+                input.display(),
+                kind.name(),
+                OutputKind::RepeatSeqs.name()
+            );
+        }
+        if let Some((found_kind, version)) = schema::read_schema(input)? {
+            schema::check_schema(
+                &found_kind,
+                version,
+                OutputKind::RepeatSeqs,
+                &input.display().to_string(),
+            )?;
+        }
+
+        info!("Adding {}...", input.display());
+        let mut run: HashSet<(String, String)> = HashSet::new();
+        let mut input_rows: u64 = 0;
+        let records = read_repeat_seqs(input)?;
+        // runs without reads, e.g. of a lane that covers none of the loci,
+        // say nothing about the options they were extracted with
+        if !records.is_empty() {
+            let found = Columns::of(&records);
+            match columns {
+                Some((expected, first)) if found != expected => bail!(
+                    "{} was extracted with {} but {} with {}, extract every run of a sample with the same options",
+                    input.display(),
+                    found.describe(),
+                    first.display(),
+                    expected.describe()
+                ),
+                Some(_) => {}
+                None => columns = Some((found, input)),
+            }
+        }
+        for record in records {
+            if dedup_read_names {
+                let Some(read_name) = &record.read_name else {
+                    bail!(
+                        "{} has no read names to deduplicate by, extract it with --read-names",
+                        input.display()
+                    );
+                };
+                let key = (record.locus_id.clone(), read_name.clone());
+                if seen.contains(&key) {
+                    debug!(
+                        "Dropping read {} of {} from {}, which an earlier run has",
+                        read_name,
+                        record.locus_id,
+                        input.display()
+                    );
+                    duplicates += 1;
+                    continue;
+                }
+                run.insert(key);
+            }
+            record.write_to(&mut out_file)?;
+            input_rows += 1;
+        }
+        debug!(rows = input_rows; "Added {} rows of {}", input_rows, input.display());
+        rows += input_rows;
+        seen.extend(run);
+    }
+    out_file.finish()?;
+
+    info!(
+        inputs = inputs.len(), rows;
+        "Concatenated {} rows of {} runs",
+        rows,
+        inputs.len()
+    );
+    if dedup_read_names {
+        info!(
+            duplicates;
+            "Dropped {} reads already seen in an earlier run",
+            duplicates
+        );
+    }
+    if rows == 0 {
+        return Err(StrifError::EmptyOutput {
+            path: out_path.display().to_string(),
+            message: "the runs have no repeat sequences".to_string(),
+        }
+        .into());
+    }
+    info!("Done!");
+    Ok(())
+}
+
+/// The command line arguments of `strif cat`
+#[derive(Debug, clap::Args)]
+pub struct CatArgs {
+    /// The paths to the repeat sequences of each run
+    #[clap(required = true)]
+    pub inputs: Vec<PathBuf>,
+
+    /// The path to write the combined repeat sequences to
+    #[clap(short, long)]
+    pub output: PathBuf,
+
+    /// Drop the reads of a locus whose name an earlier run already has, e.g. when lanes
+    /// were demultiplexed into overlapping BAMlets. Needs runs extracted with --read-names.
+    #[clap(long)]
+    pub dedup_read_names: bool,
+}
+
+impl CatArgs {
+    /// Runs `strif cat`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let CatArgs {
+            inputs,
+            output,
+            dedup_read_names,
+        } = self;
+        let mut plan = Plan::new("cat", dry_run, force);
+        for input in &inputs {
+            plan.records("repeat sequences", input, "rows");
+        }
+        plan.output("combined repeat sequences", &output)
+            .execute(|| cat(inputs, output, dedup_read_names))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn runs_are_concatenated_dropping_reads_of_earlier_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let lane1 = dir.path().join("lane1.repeat_seqs.tsv");
+        let lane2 = dir.path().join("lane2.repeat_seqs.tsv");
+        fs::write(
+            &lane1,
+            "HTT\tCAGCAGCAG\t*\t1\t.\t.\tread1\n\
+             HTT\tCAGCAGCAG\t*\t1\t.\t.\tread1\n\
+             FMR1\tCGGCGG\t*\t1\t.\t.\tread2\n",
+        )
+        .unwrap();
+        fs::write(
+            &lane2,
+            "HTT\tCAGCAGCAG\t*\t1\t.\t.\tread1\n\
+             HTT\tCAGCATCAG\t*\t1\t.\t.\tread2\n",
+        )
+        .unwrap();
+        let out = dir.path().join("sample.repeat_seqs.tsv");
+        let seqs = |out: &Path| -> Vec<(String, String)> {
+            read_repeat_seqs(out)
+                .unwrap()
+                .into_iter()
+                .map(|record| (record.locus_id, record.read_name.unwrap()))
+                .collect()
+        };
+
+        cat(vec![lane1.clone(), lane2.clone()], out.clone(), false).unwrap();
+        assert_eq!(seqs(&out).len(), 5);
+        // both mates of read1 in lane1 are kept, its copy in lane2 dropped
+        cat(vec![lane1.clone(), lane2.clone()], out.clone(), true).unwrap();
+        let pair = |locus: &str, read: &str| (locus.to_string(), read.to_string());
+        assert_eq!(
+            seqs(&out),
+            [
+                pair("HTT", "read1"),
+                pair("HTT", "read1"),
+                pair("FMR1", "read2"),
+                pair("HTT", "read2"),
+            ]
+        );
+    }
+
+    #[test]
+    fn runs_extracted_with_other_options_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let lane1 = dir.path().join("lane1.repeat_seqs.tsv");
+        let lane2 = dir.path().join("lane2.repeat_seqs.tsv");
+        fs::write(&lane1, "HTT\tCAGCAG\tIIIIII\t1\n").unwrap();
+        fs::write(&lane2, "HTT\tCAGCAG\t*\t1\n").unwrap();
+        let out = dir.path().join("sample.repeat_seqs.tsv");
+        let error = cat(vec![lane1.clone(), lane2.clone()], out.clone(), false).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "{} was extracted with none of --qualities, --anchoring, --read-names and --coordinates but {} with --qualities, extract every run of a sample with the same options",
+                lane2.display(),
+                lane1.display()
+            )
+        );
+        // deduplicating needs read names
+        assert!(cat(vec![lane1], out, true).is_err());
+    }
+}
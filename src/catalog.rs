@@ -0,0 +1,693 @@
+use std::io::prelude::*;
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use bio::io::fasta;
+use log::{info, warn};
+use serde_json::{json, Value};
+
+use crate::error::StrifError;
+use crate::interruptions::{bases_match, is_iupac_code};
+use crate::plan::Plan;
+use crate::provenance;
+use crate::utils::{create_output, get_default_out_path};
+
+/// A locus of an ExpansionHunter-style catalog
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CatalogLocus {
+    pub locus_id: String,
+    pub locus_structure: String,
+    pub reference_region: String,
+    /// The bounds outside which `profile` skips the reads of the locus
+    pub read_filters: ReadFilters,
+    pub flanks: Flanks,
+    /// Other motifs the repeat may be made of, given by the optional
+    /// `CandidateMotifs` field of its catalog entry, e.g. for RFC1
+    pub candidate_motifs: Vec<String>,
+}
+
+/// The reference sequences next to the repeat of a locus, given by the
+/// optional `LeftFlank` and `RightFlank` fields of its catalog entry, which
+/// `profile` trims off repeat sequences that start or end with them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Flanks {
+    /// The bases before the repeat, ending where it starts
+    pub left: Option<String>,
+    /// The bases after the repeat, starting where it ends
+    pub right: Option<String>,
+}
+
+impl Flanks {
+    pub fn is_set(&self) -> bool {
+        self.left.is_some() || self.right.is_some()
+    }
+}
+
+/// Read-level bounds of a locus, given by the optional `MinRepeatLen`,
+/// `MaxRepeatLen` and `MinAnchor` fields of its catalog entry
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadFilters {
+    /// The shortest repeat sequence kept, in bp
+    pub min_repeat_len: Option<u32>,
+    /// The longest repeat sequence kept, in bp
+    pub max_repeat_len: Option<u32>,
+    /// The lowest anchoring score of the weaker flank of a read kept
+    pub min_anchor: Option<i32>,
+}
+
+impl ReadFilters {
+    pub fn is_set(&self) -> bool {
+        *self != ReadFilters::default()
+    }
+
+    /// Whether a repeat sequence of this length is within the bounds
+    pub fn keeps_len(&self, repeat_len: usize) -> bool {
+        self.min_repeat_len
+            .is_none_or(|min| repeat_len >= min as usize)
+            && self
+                .max_repeat_len
+                .is_none_or(|max| repeat_len <= max as usize)
+    }
+}
+
+impl CatalogLocus {
+    /// The repeat motif of a `(MOTIF)*` locus structure
+    pub fn motif(&self) -> &str {
+        let structure = &self.locus_structure;
+        &structure[1..structure.len() - 2]
+    }
+
+    /// The motif of the locus structure followed by the candidate motifs
+    /// that differ from it, in the order of the catalog
+    pub fn motifs(&self) -> Vec<&str> {
+        let mut motifs = vec![self.motif()];
+        for candidate in &self.candidate_motifs {
+            if !motifs.contains(&candidate.as_str()) {
+                motifs.push(candidate);
+            }
+        }
+        motifs
+    }
+}
+
+/// A catalog of repeat loci, as read by `profile` and written by `catalog build`
+#[derive(Debug, Clone, Default)]
+pub struct Catalog {
+    pub loci: Vec<CatalogLocus>,
+}
+
+impl Catalog {
+    /// Reads a catalog from a JSON array of loci. Fields other than the
+    /// string fields identifying a locus and its read filters are ignored.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let entries: Vec<serde_json::Map<String, Value>> = serde_json::from_reader(reader)?;
+        let mut loci: Vec<CatalogLocus> = Vec::new();
+        for mut entry in entries {
+            let mut field = |key: &str| -> Result<String> {
+                match entry.remove(key) {
+                    Some(Value::String(value)) => Ok(value),
+                    Some(_) => bail!("The {} of a locus of the catalog is not a string", key),
+                    None => bail!("A locus of the catalog is missing its {}", key),
+                }
+            };
+            let locus_id = field("LocusId")?;
+            let locus_structure = field("LocusStructure")?;
+            let reference_region = field("ReferenceRegion")?;
+            let read_filters = ReadFilters {
+                min_repeat_len: read_filter(&entry, "MinRepeatLen", &locus_id)?,
+                max_repeat_len: read_filter(&entry, "MaxRepeatLen", &locus_id)?,
+                min_anchor: read_filter(&entry, "MinAnchor", &locus_id)?,
+            };
+            let flanks = Flanks {
+                left: flank(&entry, "LeftFlank", &locus_id)?,
+                right: flank(&entry, "RightFlank", &locus_id)?,
+            };
+            let candidate_motifs = candidate_motifs(&entry, &locus_id)?;
+            if let (Some(min), Some(max)) =
+                (read_filters.min_repeat_len, read_filters.max_repeat_len)
+            {
+                if min > max {
+                    bail!(
+                        "Locus {} has a MinRepeatLen of {} above its MaxRepeatLen of {}",
+                        locus_id,
+                        min,
+                        max
+                    );
+                }
+            }
+            let locus = CatalogLocus {
+                locus_id,
+                locus_structure,
+                reference_region,
+                read_filters,
+                flanks,
+                candidate_motifs,
+            };
+            let structure = &locus.locus_structure;
+            if structure.len() < 4 || !structure.starts_with('(') || !structure.ends_with(")*") {
+                bail!(
+                    "Locus {} has an invalid LocusStructure '{}'",
+                    locus.locus_id,
+                    locus.locus_structure
+                );
+            }
+            if let Some(base) = locus
+                .motif()
+                .chars()
+                .find(|c| !c.is_ascii() || !is_iupac_code(*c as u8))
+            {
+                bail!(
+                    "Locus {} has an invalid base '{}' in its motif '{}', expected A, C, G, T or an IUPAC ambiguity code",
+                    locus.locus_id,
+                    base,
+                    locus.motif()
+                );
+            }
+            loci.push(locus);
+        }
+        Ok(Catalog { loci })
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let contents =
+            fs::read(path).with_context(|| format!("Could not open {}", path.display()))?;
+        provenance::record_catalog(&contents);
+        Catalog::from_reader(&contents[..]).map_err(|e| {
+            StrifError::Catalog {
+                path: path.display().to_string(),
+                message: format!("{:#}", e),
+            }
+            .into()
+        })
+    }
+}
+
+/// Parses an optional flank sequence of a catalog entry, in upper case
+fn flank(
+    entry: &serde_json::Map<String, Value>,
+    key: &str,
+    locus_id: &str,
+) -> Result<Option<String>> {
+    match entry.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(flank)) => {
+            if let Some(base) = flank
+                .chars()
+                .find(|c| !c.is_ascii() || !is_iupac_code(*c as u8))
+            {
+                bail!(
+                    "Locus {} has an invalid base '{}' in its {}",
+                    locus_id,
+                    base,
+                    key
+                );
+            }
+            Ok((!flank.is_empty()).then(|| flank.to_ascii_uppercase()))
+        }
+        Some(_) => bail!("The {} of locus {} is not a string", key, locus_id),
+    }
+}
+
+/// Parses the optional candidate motifs of a catalog entry, given as a JSON
+/// array of motifs or a comma-separated string of them as in TRGT's `MOTIFS`,
+/// in upper case
+fn candidate_motifs(entry: &serde_json::Map<String, Value>, locus_id: &str) -> Result<Vec<String>> {
+    let motifs: Vec<String> = match entry.get("CandidateMotifs") {
+        None | Some(Value::Null) => return Ok(Vec::new()),
+        Some(Value::String(motifs)) => motifs.split(',').map(str::to_string).collect(),
+        Some(Value::Array(motifs)) => motifs
+            .iter()
+            .map(|motif| match motif {
+                Value::String(motif) => Ok(motif.clone()),
+                _ => bail!(
+                    "The CandidateMotifs of locus {} are not all strings",
+                    locus_id
+                ),
+            })
+            .collect::<Result<_>>()?,
+        Some(_) => bail!(
+            "The CandidateMotifs of locus {} are not an array of motifs",
+            locus_id
+        ),
+    };
+    motifs
+        .into_iter()
+        .map(|motif| {
+            let motif = motif.trim().to_ascii_uppercase();
+            if motif.is_empty() {
+                bail!(
+                    "Locus {} has an empty motif in its CandidateMotifs",
+                    locus_id
+                );
+            }
+            if let Some(base) = motif
+                .chars()
+                .find(|c| !c.is_ascii() || !is_iupac_code(*c as u8))
+            {
+                bail!(
+                    "Locus {} has an invalid base '{}' in its candidate motif '{}'",
+                    locus_id,
+                    base,
+                    motif
+                );
+            }
+            Ok(motif)
+        })
+        .collect()
+}
+
+/// Parses an optional read filter of a catalog entry, given as a JSON number
+/// or a string holding one
+fn read_filter<T: std::str::FromStr>(
+    entry: &serde_json::Map<String, Value>,
+    key: &str,
+    locus_id: &str,
+) -> Result<Option<T>> {
+    let value = match entry.get(key) {
+        None | Some(Value::Null) => return Ok(None),
+        Some(Value::String(value)) => value.clone(),
+        Some(value) => value.to_string(),
+    };
+    match value.parse() {
+        Ok(value) => Ok(Some(value)),
+        Err(_) => bail!("Locus {} has an invalid {} '{}'", locus_id, key, value),
+    }
+}
+
+pub struct CatalogParams {
+    pub min_motif_len: usize,
+    pub max_motif_len: usize,
+    /// Minimum fraction of reference bases matching a pure repeat of the motif
+    pub min_purity: f64,
+    /// Minimum length of the repeat region in base pairs
+    pub min_length: u64,
+}
+
+/// A repeat annotation from the regions file
+struct RepeatRegion {
+    chrom: String,
+    start: u64,
+    end: u64,
+    motif: Option<String>,
+}
+
+/// Builds an ExpansionHunter-style catalog from repeat annotations (BED, UCSC
+/// simpleRepeat or Krait-style TSV) and a reference genome
+pub fn catalog_build(
+    reference: PathBuf,
+    regions: PathBuf,
+    out_path: PathBuf,
+    params: CatalogParams,
+) -> Result<()> {
+    if params.min_motif_len == 0 || params.min_motif_len > params.max_motif_len {
+        bail!(
+            "Invalid motif length range {}-{}",
+            params.min_motif_len,
+            params.max_motif_len
+        );
+    }
+
+    info!("Loading repeat regions...");
+    let repeat_regions = load_regions(&regions)?;
+    let mut reference = fasta::IndexedReader::from_file(&reference).with_context(|| {
+        format!(
+            "Could not open {}, it should be a FASTA file with a .fai index",
+            reference.display()
+        )
+    })?;
+
+    info!("Building catalog from {} regions...", repeat_regions.len());
+    let mut catalog: Vec<Value> = Vec::new();
+    let mut locus_ids: HashSet<String> = HashSet::new();
+    let mut n_filtered: usize = 0;
+    let mut seq: Vec<u8> = Vec::new();
+    for region in repeat_regions {
+        if region.end - region.start < params.min_length {
+            n_filtered += 1;
+            continue;
+        }
+        reference
+            .fetch(&region.chrom, region.start, region.end)
+            .with_context(|| {
+                format!(
+                    "Could not fetch {}:{}-{} from the reference",
+                    region.chrom, region.start, region.end
+                )
+            })?;
+        reference.read(&mut seq)?;
+        seq.make_ascii_uppercase();
+
+        let (motif, purity) = match &region.motif {
+            Some(motif) => phase_motif(&seq, motif.to_uppercase().as_bytes()),
+            None => match infer_motif(&seq, params.max_motif_len) {
+                Some(inferred) => inferred,
+                None => {
+                    n_filtered += 1;
+                    continue;
+                }
+            },
+        };
+        if motif.len() < params.min_motif_len
+            || motif.len() > params.max_motif_len
+            || purity < params.min_purity
+        {
+            n_filtered += 1;
+            continue;
+        }
+
+        let locus_id = region_locus_id(&region.chrom, region.start, region.end);
+        if !locus_ids.insert(locus_id.clone()) {
+            warn!("Skipping duplicate region {}", locus_id);
+            continue;
+        }
+        catalog.push(json!({
+            "LocusId": locus_id,
+            "LocusStructure": format!("({})*", motif),
+            "ReferenceRegion": format!("{}:{}-{}", region.chrom, region.start, region.end),
+            "VariantType": "Repeat",
+        }));
+    }
+    info!(
+        "Kept {} loci, filtered out {} regions",
+        catalog.len(),
+        n_filtered
+    );
+
+    let mut out_file = create_output(&out_path)?;
+    serde_json::to_writer_pretty(&mut out_file, &catalog)?;
+    writeln!(out_file)?;
+    out_file.finish()?;
+
+    info!("Done!");
+    Ok(())
+}
+
+/// Reads repeat annotations. Lines are either BED-like (chrom, start, end, ...)
+/// or UCSC simpleRepeat (bin, chrom, start, end, ...), and the motif is taken
+/// from the first extra column that is a DNA sequence, if any. Header, track
+/// and comment lines are skipped.
+fn load_regions(path: &Path) -> Result<Vec<RepeatRegion>> {
+    let mut regions: Vec<RepeatRegion> = Vec::new();
+    for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.is_empty() || line.starts_with('#') || line.starts_with("track") {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let is_int = |i: usize| fields.get(i).map(|f| f.parse::<u64>().is_ok()) == Some(true);
+        let offset = if is_int(1) && is_int(2) {
+            0
+        } else if is_int(0) && is_int(2) && is_int(3) {
+            1
+        } else if i == 0 {
+            // a header row
+            continue;
+        } else {
+            bail!(
+                "{} line {} should start with a chromosome, start and end",
+                path.display(),
+                i + 1
+            );
+        };
+
+        let start: u64 = fields[offset + 1].parse().unwrap();
+        let end: u64 = fields[offset + 2].parse().unwrap();
+        if start >= end {
+            bail!(
+                "{} line {} has a start that is not before its end",
+                path.display(),
+                i + 1
+            );
+        }
+        let motif = fields[offset + 3..]
+            .iter()
+            .find(|f| !f.is_empty() && f.bytes().all(|b| b"ACGTacgt".contains(&b)))
+            .map(|f| f.to_string());
+        regions.push(RepeatRegion {
+            chrom: fields[offset].to_string(),
+            start,
+            end,
+            motif,
+        });
+    }
+    Ok(regions)
+}
+
+/// Locus IDs only contain word characters so that they survive the BAMlet tags
+pub(crate) fn region_locus_id(chrom: &str, start: u64, end: u64) -> String {
+    let chrom: String = chrom
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("{}_{}_{}", chrom, start, end)
+}
+
+/// Fraction of bases matching a pure repeat of the motif starting at the given phase
+fn purity(seq: &[u8], motif: &[u8], phase: usize) -> f64 {
+    if seq.is_empty() {
+        return 0.0;
+    }
+    let matches = seq
+        .iter()
+        .enumerate()
+        .filter(|(i, base)| bases_match(**base, motif[(i + phase) % motif.len()]))
+        .count();
+    matches as f64 / seq.len() as f64
+}
+
+/// Rotates the motif to the phase that best matches the reference sequence,
+/// returning the rotated motif and its purity
+fn phase_motif(seq: &[u8], motif: &[u8]) -> (String, f64) {
+    let (phase, purity) = (0..motif.len())
+        .map(|phase| (phase, purity(seq, motif, phase)))
+        .fold((0, -1.0), |best, candidate| {
+            if candidate.1 > best.1 {
+                candidate
+            } else {
+                best
+            }
+        });
+    let rotated: Vec<u8> = motif[phase..]
+        .iter()
+        .chain(&motif[..phase])
+        .copied()
+        .collect();
+    (String::from_utf8(rotated).unwrap(), purity)
+}
+
+/// Infers the motif of a reference repeat as the shortest period whose
+/// consensus (the most common base at each position of the period) explains
+/// the sequence best
+fn infer_motif(seq: &[u8], max_motif_len: usize) -> Option<(String, f64)> {
+    let mut best: Option<(String, f64)> = None;
+    for period in 1..=max_motif_len.min(seq.len() / 2) {
+        let motif: Vec<u8> = (0..period)
+            .map(|j| {
+                let mut counts = [0usize; 4];
+                for base in seq.iter().skip(j).step_by(period) {
+                    if let Some(k) = b"ACGT".iter().position(|b| b == base) {
+                        counts[k] += 1;
+                    }
+                }
+                let k = (0..4).max_by_key(|&k| (counts[k], 4 - k)).unwrap();
+                b"ACGT"[k]
+            })
+            .collect();
+        let purity = purity(seq, &motif, 0);
+        // longer periods must be clearly better to avoid picking multiples of the motif
+        if best.as_ref().map(|b| purity > b.1 + 0.05) != Some(false) {
+            best = Some((String::from_utf8(motif).unwrap(), purity));
+        }
+    }
+    best
+}
+
+/// The command line arguments of `strif catalog build`
+#[derive(Debug, clap::Args)]
+pub struct CatalogBuildArgs {
+    /// The path to the indexed reference FASTA
+    #[clap(short = 'r', long)]
+    pub reference: PathBuf,
+
+    /// The path to the repeat annotations (BED, UCSC simpleRepeat or Krait-style TSV)
+    #[clap(short = 'R', long)]
+    pub regions: PathBuf,
+
+    /// The path to the catalog. Defaults to the same directory as the regions.
+    #[clap(short = 'o', long)]
+    pub output: Option<PathBuf>,
+
+    /// Minimum motif length
+    #[clap(long, default_value = "1")]
+    pub min_motif_len: usize,
+
+    /// Maximum motif length
+    #[clap(long, default_value = "6")]
+    pub max_motif_len: usize,
+
+    /// Minimum fraction of reference bases matching a pure repeat of the motif
+    #[clap(long, default_value = "0.8")]
+    pub min_purity: f64,
+
+    /// Minimum length of the repeat region in base pairs
+    #[clap(long, default_value = "0")]
+    pub min_length: u64,
+}
+
+impl CatalogBuildArgs {
+    /// Runs `strif catalog build`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let CatalogBuildArgs {
+            reference,
+            regions,
+            output,
+            min_motif_len,
+            max_motif_len,
+            min_purity,
+            min_length,
+        } = self;
+        let out_path: PathBuf =
+            output.unwrap_or_else(|| get_default_out_path(&regions, "catalog", "json"));
+        let params = CatalogParams {
+            min_motif_len,
+            max_motif_len,
+            min_purity,
+            min_length,
+        };
+        Plan::new("catalog build", dry_run, force)
+            .input("reference", &reference)
+            .input("regions", &regions)
+            .output("catalog", &out_path)
+            .execute(|| catalog_build(reference, regions, out_path, params))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn catalogs_are_built_from_pure_reference_repeats() {
+        let dir = tempfile::tempdir().unwrap();
+        // CAG at 20-50, AT at 70-90 and a non-repetitive stretch at 90-120
+        let seq = format!(
+            "{}{}{}{}{}",
+            "TGACTTGACCTAGGTCAACT",
+            "CAG".repeat(10),
+            "TGCATGGACTTACGGTACAA",
+            "AT".repeat(10),
+            "GATTACACGTCAGGTATCCTGAGCTAAGTC"
+        );
+        let reference = dir.path().join("ref.fa");
+        fs::write(&reference, format!(">chr1\n{}\n", seq)).unwrap();
+        fs::write(
+            dir.path().join("ref.fa.fai"),
+            format!("chr1\t{}\t6\t{}\t{}\n", seq.len(), seq.len(), seq.len() + 1),
+        )
+        .unwrap();
+        let regions = dir.path().join("regions.bed");
+        fs::write(
+            &regions,
+            "chrom\tstart\tend\tmotif\n\
+             chr1\t20\t50\n\
+             chr1\t70\t90\tta\n\
+             chr1\t90\t120\n\
+             chr1\t22\t28\tGCA\n",
+        )
+        .unwrap();
+
+        let out = dir.path().join("catalog.json");
+        let params = CatalogParams {
+            min_motif_len: 2,
+            max_motif_len: 6,
+            min_purity: 0.9,
+            min_length: 10,
+        };
+        catalog_build(reference, regions, out.clone(), params).unwrap();
+        let catalog: Value = serde_json::from_str(&fs::read_to_string(&out).unwrap()).unwrap();
+        assert_eq!(
+            catalog,
+            json!([
+                {
+                    "LocusId": "chr1_20_50",
+                    "LocusStructure": "(CAG)*",
+                    "ReferenceRegion": "chr1:20-50",
+                    "VariantType": "Repeat",
+                },
+                {
+                    "LocusId": "chr1_70_90",
+                    "LocusStructure": "(AT)*",
+                    "ReferenceRegion": "chr1:70-90",
+                    "VariantType": "Repeat",
+                },
+            ])
+        );
+        // the built catalog can be profiled against
+        let catalog = Catalog::from_path(&out).unwrap();
+        assert_eq!(catalog.loci.len(), 2);
+    }
+
+    #[test]
+    fn read_filters_are_read_from_catalog_fields() {
+        let catalog = Catalog::from_reader(
+            r#"[
+                {"LocusId": "HTT", "LocusStructure": "(CAG)*", "ReferenceRegion": "chr4:3074876-3074933",
+                 "MinRepeatLen": 12, "MaxRepeatLen": "30", "MinAnchor": -2},
+                {"LocusId": "FMR1", "LocusStructure": "(CGG)*", "ReferenceRegion": "chrX:147912050-147912110"}
+            ]"#
+            .as_bytes(),
+        )
+        .unwrap();
+        let htt = catalog.loci[0].read_filters;
+        assert_eq!(
+            htt,
+            ReadFilters {
+                min_repeat_len: Some(12),
+                max_repeat_len: Some(30),
+                min_anchor: Some(-2),
+            }
+        );
+        assert!(!htt.keeps_len(11) && htt.keeps_len(12) && htt.keeps_len(30) && !htt.keeps_len(31));
+        assert!(!catalog.loci[1].read_filters.is_set());
+
+        let error = |fields: &str| {
+            let catalog = format!(
+                r#"[{{"LocusId": "HTT", "LocusStructure": "(CAG)*", "ReferenceRegion": "chr4:1-2", {}}}]"#,
+                fields
+            );
+            Catalog::from_reader(catalog.as_bytes())
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error(r#""MinRepeatLen": 40, "MaxRepeatLen": 30"#),
+            "Locus HTT has a MinRepeatLen of 40 above its MaxRepeatLen of 30"
+        );
+        assert_eq!(
+            error(r#""MinRepeatLen": -1"#),
+            "Locus HTT has an invalid MinRepeatLen '-1'"
+        );
+    }
+
+    #[test]
+    fn motifs_are_inferred_and_phased() {
+        assert_eq!(
+            infer_motif(b"CAGCAGCAGCAG", 6),
+            Some(("CAG".to_string(), 1.0))
+        );
+        assert_eq!(infer_motif(b"AAAAAAAA", 6), Some(("A".to_string(), 1.0)));
+        assert_eq!(phase_motif(b"GCAGCAGCA", b"CAG"), ("GCA".to_string(), 1.0));
+        assert_eq!(
+            region_locus_id("chrUn_KI270742v1", 5, 10),
+            "chrUn_KI270742v1_5_10"
+        );
+        assert_eq!(region_locus_id("HLA-A*01", 5, 10), "HLA_A_01_5_10");
+    }
+}
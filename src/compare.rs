@@ -0,0 +1,230 @@
+use std::io::prelude::*;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::PathBuf,
+};
+
+use anyhow::{Ok, Result};
+use log::info;
+
+use crate::plan::Plan;
+use crate::profile::{read_profile, ProfileRecord};
+use crate::schema::{self, OutputKind};
+use crate::utils::{create_output, get_default_out_path};
+
+pub struct CompareParams {
+    /// The maximum relative difference in read counts for concordant loci
+    pub read_count_tolerance: f64,
+    /// The maximum difference in per-read interruption rates for concordant loci
+    pub rate_tolerance: f64,
+}
+
+pub fn compare(
+    profile_a: PathBuf,
+    profile_b: PathBuf,
+    out_path: PathBuf,
+    params: CompareParams,
+) -> Result<()> {
+    info!("Loading profiles...");
+    let records_a: HashMap<String, ProfileRecord> = read_profile(&profile_a)?
+        .into_iter()
+        .map(|r| (r.locus_id.clone(), r))
+        .collect();
+    let records_b: HashMap<String, ProfileRecord> = read_profile(&profile_b)?
+        .into_iter()
+        .map(|r| (r.locus_id.clone(), r))
+        .collect();
+
+    let loci: BTreeSet<&String> = records_a.keys().chain(records_b.keys()).collect();
+
+    info!("Comparing {} loci...", loci.len());
+    let mut out_file = create_output(&out_path)?;
+    schema::write_header(&mut out_file, OutputKind::Comparison)?;
+
+    let mut status_counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for locus_id in loci {
+        let record_a = records_a.get(locus_id);
+        let record_b = records_b.get(locus_id);
+        let record = record_a.or(record_b).unwrap();
+        let read_count_a = record_a.map(|r| r.read_count).unwrap_or(0);
+        let read_count_b = record_b.map(|r| r.read_count).unwrap_or(0);
+
+        let rates_a = interruption_rates(record_a);
+        let rates_b = interruption_rates(record_b);
+        let interruptions: BTreeSet<&String> = rates_a.keys().chain(rates_b.keys()).collect();
+        let rate_diffs: Vec<(&String, f64)> = interruptions
+            .into_iter()
+            .map(|i| {
+                let diff = rates_b.get(i).unwrap_or(&0.0) - rates_a.get(i).unwrap_or(&0.0);
+                (i, diff)
+            })
+            .collect();
+        let max_rate_diff = rate_diffs
+            .iter()
+            .map(|(_, diff)| diff.abs())
+            .fold(0.0, f64::max);
+
+        let read_count_rel_diff = relative_difference(read_count_a, read_count_b);
+
+        let status = match (record_a, record_b) {
+            (Some(_), None) => "only_a",
+            (None, Some(_)) => "only_b",
+            _ if read_count_rel_diff > params.read_count_tolerance => "discordant",
+            _ if max_rate_diff > params.rate_tolerance => "discordant",
+            _ => "concordant",
+        };
+        *status_counts.entry(status).or_default() += 1;
+
+        let rate_diffs_str = rate_diffs
+            .iter()
+            .map(|(interruption, diff)| format!("{}:{:.4}", interruption, diff))
+            .collect::<Vec<String>>()
+            .join(",");
+        writeln!(
+            out_file,
+            "{}\t{}\t{}\t{}\t{}\t{:.4}\t{:.4}\t{}\t{}",
+            locus_id,
+            record.reference_region,
+            record.motif,
+            read_count_a,
+            read_count_b,
+            read_count_rel_diff,
+            max_rate_diff,
+            rate_diffs_str,
+            status
+        )?;
+    }
+
+    for (status, count) in status_counts {
+        info!("{} loci are {}", count, status);
+    }
+
+    out_file.finish()?;
+    Ok(())
+}
+
+/// The fraction of reads carrying each interruption at a locus
+fn interruption_rates(record: Option<&ProfileRecord>) -> HashMap<String, f64> {
+    let mut rates: HashMap<String, f64> = HashMap::new();
+    let record = match record {
+        Some(record) if record.read_count > 0 => record,
+        _ => return rates,
+    };
+    for (interruption, _, count) in &record.interruption_counts {
+        *rates.entry(interruption.clone()).or_default() += *count / record.read_count as f64;
+    }
+    rates
+}
+
+/// The absolute difference between two counts relative to the larger one
+fn relative_difference(a: u32, b: u32) -> f64 {
+    let max = a.max(b);
+    if max == 0 {
+        0.0
+    } else {
+        (a as f64 - b as f64).abs() / max as f64
+    }
+}
+
+/// The command line arguments of `strif compare`
+#[derive(Debug, clap::Args)]
+pub struct CompareArgs {
+    /// The path to the first interruption profile
+    pub profile_a: PathBuf,
+
+    /// The path to the second interruption profile
+    pub profile_b: PathBuf,
+
+    /// The path to write the comparison to. Defaults to the same directory as the first profile.
+    pub output: Option<PathBuf>,
+
+    /// The maximum relative difference in read counts for a locus to be concordant
+    #[clap(short = 'r', long, default_value = "0.2")]
+    pub read_count_tolerance: f64,
+
+    /// The maximum difference in the fraction of reads carrying an interruption
+    /// for a locus to be concordant
+    #[clap(short = 't', long, default_value = "0.1")]
+    pub rate_tolerance: f64,
+}
+
+impl CompareArgs {
+    /// Runs `strif compare`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let CompareArgs {
+            profile_a,
+            profile_b,
+            output,
+            read_count_tolerance,
+            rate_tolerance,
+        } = self;
+        let out_path: PathBuf =
+            output.unwrap_or_else(|| get_default_out_path(&profile_a, "comparison", "tsv"));
+        let params = CompareParams {
+            read_count_tolerance,
+            rate_tolerance,
+        };
+        Plan::new("compare", dry_run, force)
+            .profile("profile A", &profile_a)
+            .profile("profile B", &profile_b)
+            .output("comparison", &out_path)
+            .execute(|| compare(profile_a, profile_b, out_path, params))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{data_rows, profile_record, write_profile_file};
+
+    fn params() -> CompareParams {
+        CompareParams {
+            read_count_tolerance: 0.2,
+            rate_tolerance: 0.1,
+        }
+    }
+
+    #[test]
+    fn loci_are_classified_by_read_counts_and_interruption_rates() {
+        let dir = tempfile::tempdir().unwrap();
+        let profile_a = write_profile_file(
+            dir.path(),
+            "a.tsv",
+            &[
+                profile_record("L1", 20, &[("CAT", 40, 10.0)]),
+                profile_record("L2", 20, &[("CAT", 40, 10.0)]),
+                profile_record("L3", 20, &[]),
+                profile_record("L4", 10, &[]),
+            ],
+        );
+        let profile_b = write_profile_file(
+            dir.path(),
+            "b.tsv",
+            &[
+                profile_record("L1", 18, &[("CAT", 40, 8.0), ("CAT", 41, 1.0)]),
+                profile_record("L2", 20, &[("CAT", 40, 4.0)]),
+                profile_record("L3", 10, &[]),
+                profile_record("L5", 10, &[]),
+            ],
+        );
+        let out = dir.path().join("comparison.tsv");
+        compare(profile_a, profile_b, out.clone(), params()).unwrap();
+        assert_eq!(
+            data_rows(&out),
+            [
+                "L1\tchr1:100-200\tCAG\t20\t18\t0.1000\t0.0000\tCAT:0.0000\tconcordant",
+                "L2\tchr1:100-200\tCAG\t20\t20\t0.0000\t0.3000\tCAT:-0.3000\tdiscordant",
+                "L3\tchr1:100-200\tCAG\t20\t10\t0.5000\t0.0000\t\tdiscordant",
+                "L4\tchr1:100-200\tCAG\t10\t0\t1.0000\t0.0000\t\tonly_a",
+                "L5\tchr1:100-200\tCAG\t0\t10\t1.0000\t0.0000\t\tonly_b",
+            ]
+        );
+    }
+
+    #[test]
+    fn relative_differences_are_relative_to_the_larger_count() {
+        assert_eq!(relative_difference(0, 0), 0.0);
+        assert_eq!(relative_difference(5, 10), 0.5);
+        assert_eq!(relative_difference(10, 5), 0.5);
+    }
+}
@@ -0,0 +1,35 @@
+use std::io::{self, Write};
+
+use crate::plan::Plan;
+use anyhow::Result;
+use clap::Command;
+use clap_complete::Shell;
+
+/// Prints a completion script for a shell to stdout, generated from the
+/// definitions of the command line
+pub fn completions(mut command: Command, shell: Shell) -> Result<()> {
+    let bin_name = command.get_name().to_string();
+    // buffered so that a closed pipe is an error rather than a panic
+    let mut script: Vec<u8> = Vec::new();
+    clap_complete::generate(shell, &mut command, bin_name, &mut script);
+    io::stdout().write_all(&script)?;
+    Ok(())
+}
+
+/// The command line arguments of `strif completions`
+#[derive(Debug, clap::Args)]
+pub struct CompletionsArgs {
+    /// The shell to generate completions for
+    #[clap(value_enum)]
+    pub shell: Shell,
+}
+
+impl CompletionsArgs {
+    /// Prints the completions of a command line, or the plan with `--dry-run`
+    pub fn execute(self, command: Command, dry_run: bool, force: bool) -> Result<()> {
+        Plan::new("completions", dry_run, force)
+            .note("shell", self.shell)
+            .note("output", "stdout")
+            .execute(|| completions(command, self.shell))
+    }
+}
@@ -0,0 +1,564 @@
+use std::io::prelude::*;
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use clap::{parser::ValueSource, Arg, ArgMatches, Command};
+use log::info;
+use serde::de::DeserializeOwned;
+use toml::{Table, Value};
+
+use crate::plan::Plan;
+use crate::project::PROJECT_ARG;
+use crate::utils::create_output;
+
+/// The name of the option pointing to a configuration file
+const CONFIG_ARG: &str = "config";
+
+/// Fills in the options of a command line that were not given explicitly from
+/// a TOML configuration file passed with `--config`, returning the new
+/// arguments to parse. Keys outside any table apply to every subcommand with
+/// an option of that name, and keys in a table named after a subcommand (e.g.
+/// `[merge]` or `[catalog.build]`) apply to it only and take precedence.
+pub fn apply_config(command: &Command, args: Vec<OsString>) -> Result<Vec<OsString>> {
+    let matches = match command
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches_from(&args)
+    {
+        Ok(matches) => matches,
+        // let clap report the problem when the arguments are parsed for real
+        Err(_) => return Ok(args),
+    };
+
+    // the chain of subcommands, outermost first
+    let mut levels: Vec<(&Command, &ArgMatches)> = vec![(command, &matches)];
+    while let Some((name, sub_matches)) = levels.last().unwrap().1.subcommand() {
+        match levels.last().unwrap().0.find_subcommand(name) {
+            Some(sub_command) => levels.push((sub_command, sub_matches)),
+            None => break,
+        }
+    }
+    let config_path = match levels
+        .iter()
+        .rev()
+        .find_map(|(_, m)| m.try_get_one::<PathBuf>(CONFIG_ARG).ok().flatten())
+    {
+        Some(path) => path.clone(),
+        None => return Ok(args),
+    };
+    let config = read_config(&config_path)?;
+    check_tables(command, &config, &config_path, "")?;
+
+    // options of the top-level command go before the subcommand, the others at the end
+    let mut leading: Vec<OsString> = Vec::new();
+    let mut trailing: Vec<OsString> = Vec::new();
+    let mut table: Option<&Table> = Some(&config);
+    for (depth, (level_command, level_matches)) in levels.iter().enumerate() {
+        if depth == 0 {
+            // top-level keys are shared, so only apply those the command has
+            options_from(
+                level_command,
+                level_matches,
+                &config,
+                false,
+                &config_path,
+                &mut leading,
+            )?;
+            continue;
+        }
+        table = table
+            .and_then(|t| t.get(level_command.get_name()))
+            .and_then(Value::as_table);
+        if let Some(section) = table {
+            options_from(
+                level_command,
+                level_matches,
+                section,
+                true,
+                &config_path,
+                &mut trailing,
+            )?;
+        }
+        // shared keys for the options not set in the subcommand's table
+        let shared: Table = config
+            .iter()
+            .filter(|(key, value)| {
+                !value.is_table() && table.is_none_or(|t| !t.contains_key(key.as_str()))
+            })
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        options_from(
+            level_command,
+            level_matches,
+            &shared,
+            false,
+            &config_path,
+            &mut trailing,
+        )?;
+    }
+
+    let mut args = args;
+    let end = args.iter().position(|a| a == "--").unwrap_or(args.len());
+    args.splice(end..end, trailing);
+    args.splice(1..1, leading);
+    Ok(args)
+}
+
+/// Loads the options of a subcommand for the library API, e.g. the
+/// [`ProfileOptions`](crate::profile::ProfileOptions) of `profile`, from a
+/// configuration file written for `--config`. The keys of the subcommand's
+/// table (`[catalog.build]` is named `catalog.build`) are read as for the
+/// command line, together with the keys outside any table that are options
+/// of it, and options in neither keep their defaults.
+pub fn load_options<T: DeserializeOwned>(config_path: &Path, subcommand: &str) -> Result<T> {
+    let config = read_config(config_path)?;
+    let parse =
+        |table: &Table| -> Result<T, toml::de::Error> { Value::Table(table.clone()).try_into() };
+    // keys are kebab-case like the options they stand for, and `_` is
+    // accepted as on the command line
+    let mut options = Table::new();
+    let section = subcommand.split('.').try_fold(&config, |table, name| {
+        table.get(name).and_then(Value::as_table)
+    });
+    for (key, value) in section.into_iter().flatten() {
+        if !value.is_table() {
+            options.insert(key.replace('_', "-"), value.clone());
+        }
+    }
+    if let Err(e) = parse(&options) {
+        bail!(
+            "{}: [{}] {}",
+            config_path.display(),
+            subcommand,
+            e.message()
+        );
+    }
+    for (key, value) in &config {
+        let key = key.replace('_', "-");
+        if value.is_table() || options.contains_key(&key) {
+            continue;
+        }
+        let mut with_key = options.clone();
+        with_key.insert(key.clone(), value.clone());
+        match parse(&with_key) {
+            Ok(_) => options = with_key,
+            // shared keys only apply to the subcommands with such an option
+            Err(e) if e.message().starts_with("unknown field") => {}
+            Err(e) => bail!("{}: '{}': {}", config_path.display(), key, e.message()),
+        }
+    }
+    Ok(parse(&options)?)
+}
+
+fn read_config(path: &Path) -> Result<Table> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read the config file {}", path.display()))?;
+    text.parse::<Table>()
+        .with_context(|| format!("Could not parse the config file {}", path.display()))
+}
+
+/// Converts the scalar keys of a table into command-line options of a
+/// command, skipping options given on the command line. Unknown keys are an
+/// error when `strict` and ignored otherwise.
+fn options_from(
+    command: &Command,
+    matches: &ArgMatches,
+    table: &Table,
+    strict: bool,
+    config_path: &Path,
+    out: &mut Vec<OsString>,
+) -> Result<()> {
+    for (key, value) in table {
+        if value.is_table() {
+            continue;
+        }
+        let arg = match find_arg(command, key) {
+            Some(arg) => arg,
+            None if strict => bail!(
+                "{}: '{}' is not an option of `{}`",
+                config_path.display(),
+                key,
+                command.get_name()
+            ),
+            None => continue,
+        };
+        if arg.is_positional() {
+            bail!(
+                "{}: '{}' is a positional argument of `{}` and must be given on the command line",
+                config_path.display(),
+                key,
+                command.get_name()
+            );
+        }
+        if matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+        let flag = match arg.get_long() {
+            Some(long) => format!("--{}", long),
+            None => format!("-{}", arg.get_short().unwrap_or_default()),
+        };
+        let values: Vec<&Value> = match value {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                Value::String(s) => s.clone(),
+                Value::Integer(i) => i.to_string(),
+                Value::Float(f) => f.to_string(),
+                Value::Boolean(b) if !arg.get_action().takes_values() => {
+                    if *b {
+                        out.push(flag.clone().into());
+                    }
+                    continue;
+                }
+                Value::Boolean(b) => b.to_string(),
+                other => bail!(
+                    "{}: '{}' has an unsupported value {}",
+                    config_path.display(),
+                    key,
+                    other
+                ),
+            };
+            out.push(format!("{}={}", flag, value).into());
+        }
+    }
+    Ok(())
+}
+
+/// Finds an option by its long name or field name, with `-` and `_` interchangeable
+fn find_arg<'a>(command: &'a Command, key: &str) -> Option<&'a Arg> {
+    if key == CONFIG_ARG || key == PROJECT_ARG {
+        return None;
+    }
+    command.get_arguments().find(|arg| {
+        arg.get_long() == Some(key.replace('_', "-").as_str())
+            || arg.get_id().as_str() == key.replace('-', "_")
+    })
+}
+
+/// Fails on tables that do not name a subcommand, which are most likely typos
+fn check_tables(command: &Command, table: &Table, config_path: &Path, prefix: &str) -> Result<()> {
+    for (key, value) in table {
+        let section = match value.as_table() {
+            Some(section) => section,
+            None => continue,
+        };
+        match command.find_subcommand(key) {
+            Some(sub_command) => check_tables(
+                sub_command,
+                section,
+                config_path,
+                &format!("{}{}.", prefix, key),
+            )?,
+            None => bail!(
+                "{}: [{}{}] is not a subcommand of strif",
+                config_path.display(),
+                prefix,
+                key
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Writes a configuration file listing every option of every subcommand with
+/// its default value, all commented out. An existing file is only replaced
+/// with `force`.
+pub fn config_init(command: &Command, out_path: PathBuf, force: bool) -> Result<()> {
+    if out_path.exists() && !force {
+        bail!(
+            "{} already exists, remove it, choose another path or pass --force",
+            out_path.display()
+        );
+    }
+    write_config(command, &out_path)?;
+    info!("Wrote {}", out_path.display());
+    info!("Done!");
+    Ok(())
+}
+
+/// Writes the configuration file listing every option of every subcommand
+pub(crate) fn write_config(command: &Command, out_path: &Path) -> Result<()> {
+    let mut out_file = create_output(out_path)?;
+    writeln!(
+        out_file,
+        "# strif configuration, passed with `strif --config {}`.",
+        out_path.display()
+    )?;
+    writeln!(
+        out_file,
+        "# Options given on the command line take precedence."
+    )?;
+    writeln!(
+        out_file,
+        "# Keys outside any table apply to every subcommand with an option of that"
+    )?;
+    writeln!(
+        out_file,
+        "# name, e.g. `filter = \"^chr1_\"` or `match-score = 2`."
+    )?;
+    write_options(&mut out_file, command)?;
+    for sub_command in command.get_subcommands() {
+        write_section(&mut out_file, sub_command, sub_command.get_name())?;
+    }
+    out_file.finish()?;
+    Ok(())
+}
+
+fn write_section<W: Write>(out: &mut W, command: &Command, name: &str) -> Result<()> {
+    if ["config", "init", "completions", "man", "help"].contains(&name) {
+        return Ok(());
+    }
+    let has_options = command.get_arguments().any(is_configurable);
+    if has_options {
+        writeln!(out)?;
+        if let Some(about) = command.get_about() {
+            writeln!(out, "# {}", about)?;
+        }
+        writeln!(out, "[{}]", name)?;
+        write_options(out, command)?;
+    }
+    for sub_command in command.get_subcommands() {
+        write_section(
+            out,
+            sub_command,
+            &format!("{}.{}", name, sub_command.get_name()),
+        )?;
+    }
+    Ok(())
+}
+
+fn is_configurable(arg: &Arg) -> bool {
+    !arg.is_positional()
+        && !arg.is_hide_set()
+        && !["help", "version", CONFIG_ARG, PROJECT_ARG].contains(&arg.get_id().as_str())
+}
+
+fn write_options<W: Write>(out: &mut W, command: &Command) -> Result<()> {
+    for arg in command.get_arguments().filter(|arg| is_configurable(arg)) {
+        let key = match arg.get_long() {
+            Some(long) => long.to_string(),
+            None => arg.get_id().as_str().replace('_', "-"),
+        };
+        if let Some(help) = arg.get_help() {
+            let help = help.to_string();
+            writeln!(out, "# {}", help.lines().next().unwrap_or_default())?;
+        }
+        let defaults: Vec<String> = arg
+            .get_default_values()
+            .iter()
+            .map(|v| toml_literal(&v.to_string_lossy()))
+            .collect();
+        let value = if !arg.get_action().takes_values() {
+            "false".to_string()
+        } else if defaults.is_empty() {
+            let name = arg
+                .get_value_names()
+                .and_then(|names| names.first())
+                .map(|name| name.to_string())
+                .unwrap_or_else(|| key.to_uppercase());
+            format!("\"<{}>\"", name)
+        } else {
+            defaults.join(", ")
+        };
+        writeln!(out, "# {} = {}", key, value)?;
+    }
+    Ok(())
+}
+
+/// Writes a default value as a TOML number or string
+fn toml_literal(value: &str) -> String {
+    if value.parse::<i64>().is_ok() || value.parse::<f64>().is_ok_and(|f| f.is_finite()) {
+        value.to_string()
+    } else {
+        Value::String(value.to_string()).to_string()
+    }
+}
+
+/// The command line arguments of `strif config init`
+#[derive(Debug, clap::Args)]
+pub struct ConfigInitArgs {
+    /// The path to the configuration file
+    #[clap(default_value = "strif.toml")]
+    pub output: PathBuf,
+}
+
+impl ConfigInitArgs {
+    /// Runs `strif config init` with the options of a command line, or
+    /// prints its plan with `--dry-run`
+    pub fn execute(self, command: Command, dry_run: bool, force: bool) -> Result<()> {
+        // the config file checks for an existing file itself
+        Plan::new("config init", dry_run, force)
+            .output("config", &self.output)
+            .execute(|| config_init(&command, self.output, force))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use clap::{CommandFactory, Parser, Subcommand};
+
+    use super::*;
+    use crate::merge::MergeOptions;
+
+    /// A command line shaped like strif's, with shared and nested subcommands
+    #[derive(Debug, Parser)]
+    struct Cli {
+        #[clap(long, global = true)]
+        config: Option<PathBuf>,
+        #[clap(long)]
+        threads: Option<usize>,
+        #[clap(subcommand)]
+        command: Commands,
+    }
+
+    #[derive(Debug, PartialEq, Subcommand)]
+    enum Commands {
+        Merge {
+            manifest: PathBuf,
+            #[clap(short = 'l', long, default_value = "150")]
+            read_length: u32,
+            #[clap(short = 'f', long)]
+            filter: Option<String>,
+            #[clap(long)]
+            pseudonymize: bool,
+        },
+        Catalog {
+            #[clap(subcommand)]
+            command: CatalogCommands,
+        },
+    }
+
+    #[derive(Debug, PartialEq, Subcommand)]
+    enum CatalogCommands {
+        Build {
+            #[clap(short = 'f', long)]
+            filter: Option<String>,
+        },
+    }
+
+    fn parse_with_config(dir: &Path, config: &str, args: &[&str]) -> Result<Cli> {
+        let config_path = dir.join("strif.toml");
+        fs::write(&config_path, config).unwrap();
+        let args: Vec<OsString> = ["strif"]
+            .iter()
+            .chain(args)
+            .map(OsString::from)
+            .chain(["--config".into(), config_path.into()])
+            .collect();
+        let args = apply_config(&Cli::command(), args)?;
+        Ok(Cli::try_parse_from(args)?)
+    }
+
+    #[test]
+    fn subcommand_tables_take_precedence_over_shared_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = "threads = 4\nfilter = \"^chr1_\"\n\n[merge]\nread_length = 100\npseudonymize = true\n\n[catalog.build]\nfilter = \"^chrX_\"\n";
+
+        let cli = parse_with_config(dir.path(), config, &["merge", "manifest.tsv"]).unwrap();
+        assert_eq!(cli.threads, Some(4));
+        assert_eq!(
+            cli.command,
+            Commands::Merge {
+                manifest: "manifest.tsv".into(),
+                read_length: 100,
+                filter: Some("^chr1_".to_string()),
+                pseudonymize: true,
+            }
+        );
+
+        let cli = parse_with_config(dir.path(), config, &["catalog", "build"]).unwrap();
+        assert_eq!(
+            cli.command,
+            Commands::Catalog {
+                command: CatalogCommands::Build {
+                    filter: Some("^chrX_".to_string())
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn command_line_options_override_the_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let cli = parse_with_config(
+            dir.path(),
+            "[merge]\nread-length = 100\n",
+            &["merge", "manifest.tsv", "-l", "120"],
+        )
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Commands::Merge {
+                read_length: 120,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn unknown_tables_and_options_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = parse_with_config(dir.path(), "[mrege]\nfilter = \"x\"\n", &["merge", "m.tsv"])
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("[mrege] is not a subcommand of strif"));
+
+        let err = parse_with_config(dir.path(), "[merge]\nread-len = 100\n", &["merge", "m.tsv"])
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("'read-len' is not an option of `merge`"));
+
+        let err = parse_with_config(
+            dir.path(),
+            "[merge]\nmanifest = \"m.tsv\"\n",
+            &["merge", "m.tsv"],
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("is a positional argument"));
+    }
+
+    #[test]
+    fn library_options_are_loaded_from_the_subcommand_table_and_shared_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("strif.toml");
+        fs::write(
+            &path,
+            "filter = \"^chr1_\"\nmatch-score = 2\n\n[merge]\nread_length = 100\n",
+        )
+        .unwrap();
+        let options: MergeOptions = load_options(&path, "merge").unwrap();
+        assert_eq!(
+            options,
+            MergeOptions::default().filter("^chr1_").read_length(100)
+        );
+
+        fs::write(&path, "[merge]\nread-length = \"long\"\n").unwrap();
+        assert!(load_options::<MergeOptions>(&path, "merge").is_err());
+    }
+
+    #[test]
+    fn generated_configs_list_every_option_commented_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("strif.toml");
+        write_config(&Cli::command(), &path).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+
+        assert!(text.contains("\n# threads = \"<THREADS>\"\n"));
+        assert!(text.contains("\n[merge]\n# read-length = 150\n"));
+        assert!(text.contains("\n[catalog.build]\n# filter = \"<FILTER>\"\n"));
+        assert!(!text.contains("manifest"));
+        // every line is a comment or a table header, so the file applies nothing
+        assert!(text
+            .lines()
+            .all(|line| line.is_empty() || line.starts_with('#') || line.starts_with('[')));
+    }
+}
@@ -0,0 +1,842 @@
+use std::io::prelude::*;
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use log::info;
+use serde_json::{json, Value};
+
+use crate::extract::{read_repeat_seqs, Anchoring, ReadCoordinates, RepeatSeqRecord};
+use crate::merge::{read_merged_profile, write_merged_profile, MergedProfileRecord};
+use crate::plan::Plan;
+use crate::profile::{json_str, read_profile, write_profile, ProfileRecord};
+use crate::schema::{self, OutputKind};
+use crate::utils::create_output;
+
+/// File formats understood by `convert`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// Tab-separated values with a schema line and packed count columns
+    Tsv,
+    /// A single JSON array of records
+    Json,
+    /// One JSON object per line
+    Jsonl,
+    /// Apache Parquet (requires the `parquet` feature)
+    Parquet,
+}
+
+impl Format {
+    pub(crate) fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        // a compressed TSV, such as the bgzipped profiles written by `index`
+        if extension == "gz" {
+            return match Format::from_path(Path::new(path.file_stem()?))? {
+                Format::Tsv => Some(Format::Tsv),
+                _ => None,
+            };
+        }
+        match extension.as_str() {
+            "tsv" | "txt" => Some(Format::Tsv),
+            "json" => Some(Format::Json),
+            "jsonl" | "ndjson" => Some(Format::Jsonl),
+            "parquet" | "pq" => Some(Format::Parquet),
+            _ => None,
+        }
+    }
+}
+
+/// The records of an extract, profile or merge output
+pub(crate) enum Records {
+    RepeatSeqs(Vec<RepeatSeqRecord>),
+    Profile(Vec<ProfileRecord>),
+    MergedProfile(Vec<MergedProfileRecord>),
+}
+
+impl Records {
+    pub(crate) fn kind(&self) -> OutputKind {
+        match self {
+            Records::RepeatSeqs(_) => OutputKind::RepeatSeqs,
+            Records::Profile(_) => OutputKind::Profile,
+            Records::MergedProfile(_) => OutputKind::MergedProfile,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Records::RepeatSeqs(records) => records.len(),
+            Records::Profile(records) => records.len(),
+            Records::MergedProfile(records) => records.len(),
+        }
+    }
+
+    fn to_json(&self) -> Vec<Value> {
+        match self {
+            Records::RepeatSeqs(records) => records
+                .iter()
+                .map(|r| {
+                    let mut value = json!({"locus_id": r.locus_id, "sequence": r.seq});
+                    if let Some(qualities) = &r.qualities {
+                        value["qualities"] = json!(qualities);
+                    }
+                    if let Some(multiplicity) = r.multiplicity {
+                        value["multiplicity"] = json!(multiplicity);
+                    }
+                    if let Some(anchoring) = r.anchoring {
+                        value["left_anchor"] = json!(anchoring.left);
+                        value["right_anchor"] = json!(anchoring.right);
+                    }
+                    if let Some(read_name) = &r.read_name {
+                        value["read_name"] = json!(read_name);
+                    }
+                    if let Some(coordinates) = &r.coordinates {
+                        value["chrom"] = json!(coordinates.chrom);
+                        value["pos"] = json!(coordinates.pos);
+                        value["reference_region"] = json!(coordinates.reference_region);
+                    }
+                    value
+                })
+                .collect(),
+            Records::Profile(records) => records.iter().map(|r| r.to_json()).collect(),
+            Records::MergedProfile(records) => records.iter().map(|r| r.to_json()).collect(),
+        }
+    }
+
+    /// Builds records from JSON objects, detecting the kind from the fields of the first one
+    fn from_json(values: Vec<Value>, path: &Path) -> Result<Self> {
+        let first = match values.first() {
+            Some(first) => first,
+            None => bail!("{} has no records to convert", path.display()),
+        };
+        let records = if first.get("sequence").is_some() {
+            let mut records: Vec<RepeatSeqRecord> = Vec::new();
+            for value in &values {
+                records.push(RepeatSeqRecord {
+                    locus_id: json_str(value, "locus_id")?,
+                    seq: json_str(value, "sequence")?,
+                    qualities: match value.get("qualities") {
+                        Some(_) => Some(json_str(value, "qualities")?),
+                        None => None,
+                    },
+                    multiplicity: match value.get("multiplicity") {
+                        Some(multiplicity) => match multiplicity.as_u64() {
+                            Some(multiplicity @ 1..=0xffff_ffff) => Some(multiplicity as u32),
+                            _ => bail!(
+                                "{} has an invalid multiplicity {}",
+                                path.display(),
+                                multiplicity
+                            ),
+                        },
+                        None => None,
+                    },
+                    anchoring: match value.get("left_anchor") {
+                        Some(_) => Some(Anchoring {
+                            left: json_i32(value, "left_anchor", path)?,
+                            right: json_i32(value, "right_anchor", path)?,
+                        }),
+                        None => None,
+                    },
+                    read_name: match value.get("read_name") {
+                        Some(_) => Some(json_str(value, "read_name")?),
+                        None => None,
+                    },
+                    coordinates: match value.get("chrom") {
+                        Some(_) => Some(ReadCoordinates {
+                            chrom: json_str(value, "chrom")?,
+                            pos: match value.get("pos").and_then(Value::as_u64) {
+                                Some(pos) => pos,
+                                None => {
+                                    bail!("{} has a read without an integer pos", path.display())
+                                }
+                            },
+                            reference_region: value
+                                .get("reference_region")
+                                .and_then(Value::as_str)
+                                .map(str::to_string),
+                        }),
+                        None => None,
+                    },
+                });
+            }
+            Records::RepeatSeqs(records)
+        } else if first.get("read_count").is_some() {
+            let records = values
+                .iter()
+                .map(ProfileRecord::from_json)
+                .collect::<Result<Vec<ProfileRecord>>>()?;
+            Records::Profile(records)
+        } else if first.get("read_counts").is_some() {
+            let records = values
+                .iter()
+                .map(MergedProfileRecord::from_json)
+                .collect::<Result<Vec<MergedProfileRecord>>>()?;
+            Records::MergedProfile(records)
+        } else {
+            bail!(
+                "Could not detect the kind of strif output in {}",
+                path.display()
+            );
+        };
+        Ok(records)
+    }
+}
+
+/// Reads an integer field of a JSON object
+fn json_i32(value: &Value, name: &str, path: &Path) -> Result<i32> {
+    match value.get(name).and_then(Value::as_i64) {
+        Some(n) => i32::try_from(n)
+            .with_context(|| format!("{} has an out of range {} {}", path.display(), name, n)),
+        None => bail!(
+            "{} has a record without an integer {}",
+            path.display(),
+            name
+        ),
+    }
+}
+
+/// Converts an extract, profile or merge output between formats. Converting a
+/// TSV file to TSV upgrades it to the current schema.
+pub fn convert(
+    input: PathBuf,
+    out_path: PathBuf,
+    from: Option<Format>,
+    to: Option<Format>,
+) -> Result<()> {
+    let from = match from.or_else(|| Format::from_path(&input)) {
+        Some(from) => from,
+        None => bail!(
+            "Could not infer the format of {}, use --from",
+            input.display()
+        ),
+    };
+    let to = match to.or_else(|| Format::from_path(&out_path)) {
+        Some(to) => to,
+        None => bail!(
+            "Could not infer the format of {}, use --to",
+            out_path.display()
+        ),
+    };
+
+    info!("Reading {}...", input.display());
+    let records = read_records(&input, from)?;
+
+    info!(
+        "Writing {} '{}' records to {}...",
+        records.len(),
+        records.kind().name(),
+        out_path.display()
+    );
+    match to {
+        Format::Tsv => write_tsv(&records, &out_path)?,
+        Format::Json => {
+            let mut out_file = create_output(&out_path)?;
+            serde_json::to_writer_pretty(&mut out_file, &records.to_json())?;
+            writeln!(out_file)?;
+            out_file.finish()?;
+        }
+        Format::Jsonl => {
+            let mut out_file = create_output(&out_path)?;
+            for value in records.to_json() {
+                writeln!(out_file, "{}", value)?;
+            }
+            out_file.finish()?;
+        }
+        Format::Parquet => parquet_io::write(&records, &out_path)?,
+    }
+
+    info!("Done!");
+    Ok(())
+}
+
+/// Reads a merged profile in any format understood by `convert`, inferring
+/// the format from the extension
+pub(crate) fn read_merged_profile_any(input: &Path) -> Result<Vec<MergedProfileRecord>> {
+    let format = match Format::from_path(input) {
+        Some(format) => format,
+        None => bail!("Could not infer the format of {}", input.display()),
+    };
+    match read_records(input, format)? {
+        Records::MergedProfile(records) => Ok(records),
+        records => bail!(
+            "{} is a '{}' file, expected a merged profile",
+            input.display(),
+            records.kind().name()
+        ),
+    }
+}
+
+fn read_records(input: &Path, format: Format) -> Result<Records> {
+    let records = match format {
+        Format::Tsv => read_tsv(input)?,
+        Format::Json => {
+            let values: Vec<Value> = serde_json::from_reader(BufReader::new(File::open(input)?))
+                .with_context(|| format!("{} is not a JSON array", input.display()))?;
+            Records::from_json(values, input)?
+        }
+        Format::Jsonl => {
+            let mut values: Vec<Value> = Vec::new();
+            for (i, line) in BufReader::new(File::open(input)?).lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                values.push(serde_json::from_str(&line).with_context(|| {
+                    format!("Invalid JSON in {} line {}", input.display(), i + 1)
+                })?);
+            }
+            Records::from_json(values, input)?
+        }
+        Format::Parquet => parquet_io::read(input)?,
+    };
+    Ok(records)
+}
+
+pub(crate) fn read_tsv(input: &Path) -> Result<Records> {
+    let records = match schema::detect(input)? {
+        OutputKind::RepeatSeqs => Records::RepeatSeqs(read_repeat_seqs(input)?),
+        OutputKind::Profile => Records::Profile(read_profile(input)?),
+        OutputKind::MergedProfile => Records::MergedProfile(read_merged_profile(input)?),
+        kind => bail!(
+            "{} is a '{}' file, only extract, profile and merge outputs can be converted",
+            input.display(),
+            kind.name()
+        ),
+    };
+    Ok(records)
+}
+
+pub(crate) fn write_tsv(records: &Records, out_path: &Path) -> Result<()> {
+    let mut out_file = create_output(out_path)?;
+    match records {
+        Records::RepeatSeqs(records) => {
+            schema::write_header(&mut out_file, OutputKind::RepeatSeqs)?;
+            for record in records {
+                record.write_to(&mut out_file)?;
+            }
+        }
+        Records::Profile(records) => write_profile(&mut out_file, records)?,
+        Records::MergedProfile(records) => write_merged_profile(&mut out_file, records)?,
+    }
+    out_file.finish()?;
+    Ok(())
+}
+
+#[cfg(feature = "parquet")]
+mod parquet_io {
+    use std::{fs::File, path::Path, sync::Arc};
+
+    use anyhow::{bail, Result};
+    use arrow_array::{
+        Array, ArrayRef, Float64Array, Int32Array, RecordBatch, StringArray, UInt32Array,
+        UInt64Array,
+    };
+    use arrow_schema::{DataType, Field, Schema};
+    use parquet::arrow::{arrow_reader::ParquetRecordBatchReaderBuilder, ArrowWriter};
+
+    use super::Records;
+    use crate::extract::{Anchoring, ReadCoordinates, RepeatSeqRecord};
+    use crate::merge::{parse_merged_interruption_counts, parse_read_counts, MergedProfileRecord};
+    use crate::profile::{parse_interruption_counts, ProfileRecord};
+    use crate::utils::create_output;
+
+    /// Parquet files use the TSV columns, with packed count columns kept as strings
+    pub fn write(records: &Records, out_path: &Path) -> Result<()> {
+        let strings = |values: Vec<String>| -> ArrayRef { Arc::new(StringArray::from(values)) };
+        let (fields, columns): (Vec<Field>, Vec<ArrayRef>) = match records {
+            Records::RepeatSeqs(records) => {
+                let mut fields = vec![
+                    Field::new("locus_id", DataType::Utf8, false),
+                    Field::new("sequence", DataType::Utf8, false),
+                ];
+                let mut columns = vec![
+                    strings(records.iter().map(|r| r.locus_id.clone()).collect()),
+                    strings(records.iter().map(|r| r.seq.clone()).collect()),
+                ];
+                // the qualities column is only written for files extracted with them
+                if records.iter().any(|r| r.qualities.is_some()) {
+                    fields.push(Field::new("qualities", DataType::Utf8, true));
+                    columns.push(Arc::new(StringArray::from(
+                        records
+                            .iter()
+                            .map(|r| r.qualities.clone())
+                            .collect::<Vec<Option<String>>>(),
+                    )));
+                }
+                // and the multiplicity column for files collapsed by `dedup`
+                if records.iter().any(|r| r.multiplicity.is_some()) {
+                    fields.push(Field::new("multiplicity", DataType::UInt32, true));
+                    columns.push(Arc::new(UInt32Array::from(
+                        records
+                            .iter()
+                            .map(|r| r.multiplicity)
+                            .collect::<Vec<Option<u32>>>(),
+                    )));
+                }
+                // and the anchoring columns for files extracted with them
+                if records.iter().any(|r| r.anchoring.is_some()) {
+                    for (name, side) in [
+                        (
+                            "left_anchor",
+                            (|a: Anchoring| a.left) as fn(Anchoring) -> i32,
+                        ),
+                        ("right_anchor", |a: Anchoring| a.right),
+                    ] {
+                        fields.push(Field::new(name, DataType::Int32, true));
+                        columns.push(Arc::new(Int32Array::from(
+                            records
+                                .iter()
+                                .map(|r| r.anchoring.map(side))
+                                .collect::<Vec<Option<i32>>>(),
+                        )));
+                    }
+                }
+                // and the read name column for files extracted with them
+                if records.iter().any(|r| r.read_name.is_some()) {
+                    fields.push(Field::new("read_name", DataType::Utf8, true));
+                    columns.push(Arc::new(StringArray::from(
+                        records
+                            .iter()
+                            .map(|r| r.read_name.clone())
+                            .collect::<Vec<Option<String>>>(),
+                    )));
+                }
+                // and the coordinates columns for files extracted with them
+                if records.iter().any(|r| r.coordinates.is_some()) {
+                    fields.push(Field::new("chrom", DataType::Utf8, true));
+                    columns.push(Arc::new(StringArray::from(
+                        records
+                            .iter()
+                            .map(|r| r.coordinates.as_ref().map(|c| c.chrom.clone()))
+                            .collect::<Vec<Option<String>>>(),
+                    )));
+                    fields.push(Field::new("pos", DataType::UInt64, true));
+                    columns.push(Arc::new(UInt64Array::from(
+                        records
+                            .iter()
+                            .map(|r| r.coordinates.as_ref().map(|c| c.pos))
+                            .collect::<Vec<Option<u64>>>(),
+                    )));
+                    fields.push(Field::new("reference_region", DataType::Utf8, true));
+                    columns.push(Arc::new(StringArray::from(
+                        records
+                            .iter()
+                            .map(|r| {
+                                r.coordinates
+                                    .as_ref()
+                                    .and_then(|c| c.reference_region.clone())
+                            })
+                            .collect::<Vec<Option<String>>>(),
+                    )));
+                }
+                (fields, columns)
+            }
+            Records::Profile(records) => (
+                vec![
+                    Field::new("locus_id", DataType::Utf8, false),
+                    Field::new("reference_region", DataType::Utf8, false),
+                    Field::new("motif", DataType::Utf8, false),
+                    Field::new("read_count", DataType::UInt32, false),
+                    Field::new("interruption_counts", DataType::Utf8, false),
+                    Field::new("mean_purity", DataType::Float64, true),
+                    Field::new("median_purity", DataType::Float64, true),
+                    Field::new("mean_confidence", DataType::Float64, true),
+                    Field::new("max_confidence", DataType::Float64, true),
+                    Field::new("median_copy_number", DataType::Float64, true),
+                ],
+                vec![
+                    strings(records.iter().map(|r| r.locus_id.clone()).collect()),
+                    strings(records.iter().map(|r| r.reference_region.clone()).collect()),
+                    strings(records.iter().map(|r| r.motif.clone()).collect()),
+                    Arc::new(UInt32Array::from(
+                        records.iter().map(|r| r.read_count).collect::<Vec<u32>>(),
+                    )),
+                    strings(
+                        records
+                            .iter()
+                            .map(|r| r.packed_interruption_counts())
+                            .collect(),
+                    ),
+                    Arc::new(Float64Array::from(
+                        records.iter().map(|r| r.mean_purity).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(Float64Array::from(
+                        records.iter().map(|r| r.median_purity).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(Float64Array::from(
+                        records
+                            .iter()
+                            .map(|r| r.mean_confidence)
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(Float64Array::from(
+                        records.iter().map(|r| r.max_confidence).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(Float64Array::from(
+                        records
+                            .iter()
+                            .map(|r| r.median_copy_number)
+                            .collect::<Vec<_>>(),
+                    )),
+                ],
+            ),
+            Records::MergedProfile(records) => (
+                vec![
+                    Field::new("locus_id", DataType::Utf8, false),
+                    Field::new("reference_region", DataType::Utf8, false),
+                    Field::new("motif", DataType::Utf8, false),
+                    Field::new("read_counts", DataType::Utf8, false),
+                    Field::new("interruption_counts", DataType::Utf8, false),
+                ],
+                vec![
+                    strings(records.iter().map(|r| r.locus_id.clone()).collect()),
+                    strings(records.iter().map(|r| r.reference_region.clone()).collect()),
+                    strings(records.iter().map(|r| r.motif.clone()).collect()),
+                    strings(records.iter().map(|r| r.packed_read_counts()).collect()),
+                    strings(
+                        records
+                            .iter()
+                            .map(|r| r.packed_interruption_counts())
+                            .collect(),
+                    ),
+                ],
+            ),
+        };
+        let schema = Arc::new(Schema::new(fields));
+        let batch = RecordBatch::try_new(schema.clone(), columns)?;
+        let mut writer = ArrowWriter::try_new(create_output(out_path)?, schema, None)?;
+        writer.write(&batch)?;
+        writer.into_inner()?.finish()?;
+        Ok(())
+    }
+
+    pub fn read(input: &Path) -> Result<Records> {
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(input)?)?.build()?;
+        let mut records: Option<Records> = None;
+        for batch in reader {
+            let batch = batch?;
+            let records = match &mut records {
+                Some(records) => records,
+                None => records.insert(detect(&batch, input)?),
+            };
+            let locus_ids = string_column(&batch, "locus_id", input)?;
+            match records {
+                Records::RepeatSeqs(records) => {
+                    let seqs = string_column(&batch, "sequence", input)?;
+                    let qualities = match batch.column_by_name("qualities") {
+                        Some(_) => Some(string_column(&batch, "qualities", input)?),
+                        None => None,
+                    };
+                    let multiplicities = match batch.column_by_name("multiplicity") {
+                        Some(column) => match column.as_any().downcast_ref::<UInt32Array>() {
+                            Some(multiplicities) => Some(multiplicities),
+                            None => {
+                                bail!("{} has a non-integer multiplicity column", input.display())
+                            }
+                        },
+                        None => None,
+                    };
+                    let anchors = |name: &str| match batch.column_by_name(name) {
+                        Some(column) => match column.as_any().downcast_ref::<Int32Array>() {
+                            Some(anchors) => Ok(Some(anchors.clone())),
+                            None => bail!("{} has a non-integer {} column", input.display(), name),
+                        },
+                        None => Ok(None),
+                    };
+                    let (left_anchors, right_anchors) =
+                        (anchors("left_anchor")?, anchors("right_anchor")?);
+                    let read_names = match batch.column_by_name("read_name") {
+                        Some(_) => Some(string_column(&batch, "read_name", input)?),
+                        None => None,
+                    };
+                    let chroms = match batch.column_by_name("chrom") {
+                        Some(_) => Some(string_column(&batch, "chrom", input)?),
+                        None => None,
+                    };
+                    let positions = match batch.column_by_name("pos") {
+                        Some(column) => match column.as_any().downcast_ref::<UInt64Array>() {
+                            Some(positions) => Some(positions),
+                            None => bail!("{} has a non-integer pos column", input.display()),
+                        },
+                        None => None,
+                    };
+                    let reference_regions = match batch.column_by_name("reference_region") {
+                        Some(_) => Some(string_column(&batch, "reference_region", input)?),
+                        None => None,
+                    };
+                    for i in 0..batch.num_rows() {
+                        records.push(RepeatSeqRecord {
+                            locus_id: locus_ids.value(i).to_string(),
+                            seq: seqs.value(i).to_string(),
+                            qualities: qualities
+                                .filter(|qualities| qualities.is_valid(i))
+                                .map(|qualities| qualities.value(i).to_string()),
+                            multiplicity: multiplicities
+                                .filter(|multiplicities| multiplicities.is_valid(i))
+                                .map(|multiplicities| multiplicities.value(i)),
+                            anchoring: match (&left_anchors, &right_anchors) {
+                                (Some(left), Some(right))
+                                    if left.is_valid(i) && right.is_valid(i) =>
+                                {
+                                    Some(Anchoring {
+                                        left: left.value(i),
+                                        right: right.value(i),
+                                    })
+                                }
+                                _ => None,
+                            },
+                            read_name: read_names
+                                .filter(|read_names| read_names.is_valid(i))
+                                .map(|read_names| read_names.value(i).to_string()),
+                            coordinates: match (chroms, positions) {
+                                (Some(chroms), Some(positions))
+                                    if chroms.is_valid(i) && positions.is_valid(i) =>
+                                {
+                                    Some(ReadCoordinates {
+                                        chrom: chroms.value(i).to_string(),
+                                        pos: positions.value(i),
+                                        reference_region: reference_regions
+                                            .filter(|regions| regions.is_valid(i))
+                                            .map(|regions| regions.value(i).to_string()),
+                                    })
+                                }
+                                _ => None,
+                            },
+                        });
+                    }
+                }
+                Records::Profile(records) => {
+                    let regions = string_column(&batch, "reference_region", input)?;
+                    let motifs = string_column(&batch, "motif", input)?;
+                    let read_counts = match batch
+                        .column_by_name("read_count")
+                        .and_then(|c| c.as_any().downcast_ref::<UInt32Array>())
+                    {
+                        Some(column) => column,
+                        None => bail!("{} has no integer read_count column", input.display()),
+                    };
+                    let interruptions = string_column(&batch, "interruption_counts", input)?;
+                    // files converted before the purity, confidence or copy
+                    // number columns have none
+                    let optional = |name: &str, i: usize| {
+                        batch
+                            .column_by_name(name)
+                            .and_then(|c| c.as_any().downcast_ref::<Float64Array>())
+                            .filter(|c| c.is_valid(i))
+                            .map(|c| c.value(i))
+                    };
+                    for i in 0..batch.num_rows() {
+                        records.push(ProfileRecord {
+                            locus_id: locus_ids.value(i).to_string(),
+                            reference_region: regions.value(i).to_string(),
+                            motif: motifs.value(i).to_string(),
+                            read_count: read_counts.value(i),
+                            interruption_counts: parse_interruption_counts(interruptions.value(i))?,
+                            mean_purity: optional("mean_purity", i),
+                            median_purity: optional("median_purity", i),
+                            mean_confidence: optional("mean_confidence", i),
+                            max_confidence: optional("max_confidence", i),
+                            median_copy_number: optional("median_copy_number", i),
+                        });
+                    }
+                }
+                Records::MergedProfile(records) => {
+                    let regions = string_column(&batch, "reference_region", input)?;
+                    let motifs = string_column(&batch, "motif", input)?;
+                    let read_counts = string_column(&batch, "read_counts", input)?;
+                    let interruptions = string_column(&batch, "interruption_counts", input)?;
+                    for i in 0..batch.num_rows() {
+                        records.push(MergedProfileRecord {
+                            locus_id: locus_ids.value(i).to_string(),
+                            reference_region: regions.value(i).to_string(),
+                            motif: motifs.value(i).to_string(),
+                            read_counts: parse_read_counts(read_counts.value(i))?,
+                            interruption_counts: parse_merged_interruption_counts(
+                                interruptions.value(i),
+                            )?,
+                        });
+                    }
+                }
+            }
+        }
+        match records {
+            Some(records) => Ok(records),
+            None => bail!("{} has no records to convert", input.display()),
+        }
+    }
+
+    fn detect(batch: &RecordBatch, input: &Path) -> Result<Records> {
+        let has = |name: &str| batch.column_by_name(name).is_some();
+        if has("sequence") {
+            Ok(Records::RepeatSeqs(Vec::new()))
+        } else if has("read_count") {
+            Ok(Records::Profile(Vec::new()))
+        } else if has("read_counts") {
+            Ok(Records::MergedProfile(Vec::new()))
+        } else {
+            bail!(
+                "Could not detect the kind of strif output in {}",
+                input.display()
+            )
+        }
+    }
+
+    fn string_column<'a>(
+        batch: &'a RecordBatch,
+        name: &str,
+        input: &Path,
+    ) -> Result<&'a StringArray> {
+        match batch
+            .column_by_name(name)
+            .and_then(|c| c.as_any().downcast_ref::<StringArray>())
+        {
+            Some(column) => Ok(column),
+            None => bail!("{} has no string {} column", input.display(), name),
+        }
+    }
+}
+
+#[cfg(not(feature = "parquet"))]
+mod parquet_io {
+    use std::path::Path;
+
+    use anyhow::{bail, Result};
+
+    use super::Records;
+
+    pub fn write(_records: &Records, _out_path: &Path) -> Result<()> {
+        bail!("Parquet support requires strif to be built with the `parquet` feature")
+    }
+
+    pub fn read(_input: &Path) -> Result<Records> {
+        bail!("Parquet support requires strif to be built with the `parquet` feature")
+    }
+}
+
+/// The command line arguments of `strif convert`
+#[derive(Debug, clap::Args)]
+pub struct ConvertArgs {
+    /// The path to the file to convert
+    pub input: PathBuf,
+
+    /// The path to the converted output. Its format is inferred from the extension unless --to is given.
+    pub output: PathBuf,
+
+    /// The format of the input. Inferred from the extension by default.
+    #[clap(long, value_enum)]
+    pub from: Option<Format>,
+
+    /// The format of the output. Inferred from the extension by default.
+    #[clap(long, value_enum)]
+    pub to: Option<Format>,
+}
+
+impl ConvertArgs {
+    /// Runs `strif convert`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let ConvertArgs {
+            input,
+            output,
+            from,
+            to,
+        } = self;
+        Plan::new("convert", dry_run, force)
+            .input("input", &input)
+            .output("output", &output)
+            .execute(|| convert(input, output, from, to))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::testing::{
+        data_rows, merged_record, profile_record, write_merged_profile_file, write_profile_file,
+    };
+
+    /// Converts a TSV file through each other format and back to TSV,
+    /// returning the data rows of the round-tripped file
+    fn round_trip(input: &Path, formats: &[&str]) -> Vec<String> {
+        let dir = input.parent().unwrap();
+        let mut from = input.to_path_buf();
+        for (i, extension) in formats.iter().chain(["tsv"].iter()).enumerate() {
+            let to = dir.join(format!("converted{}.{}", i, extension));
+            convert(from, to.clone(), None, None).unwrap();
+            from = to;
+        }
+        data_rows(&from)
+    }
+
+    #[test]
+    fn profiles_round_trip_through_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut htt = profile_record("HTT", 20, &[("CAT", 40, 2.0), ("A", 41, 0.5)]);
+        htt.mean_purity = Some(0.95);
+        htt.median_purity = Some(1.0);
+        htt.median_copy_number = Some(13.5);
+        let input = write_profile_file(
+            dir.path(),
+            "profile.tsv",
+            &[htt, profile_record("FMR1", 0, &[])],
+        );
+        assert_eq!(round_trip(&input, &["json", "jsonl"]), data_rows(&input));
+
+        let json: Value =
+            serde_json::from_str(&fs::read_to_string(dir.path().join("converted0.json")).unwrap())
+                .unwrap();
+        assert_eq!(json[0]["locus_id"], json!("HTT"));
+        assert_eq!(json.as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn merged_profiles_round_trip_through_jsonl() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = write_merged_profile_file(
+            dir.path(),
+            "merged.tsv",
+            &[merged_record(
+                "L1",
+                &[("s1", 20), ("s2", 5)],
+                &[("s1", "CAT", 0.1), ("s2", "A", 0.25)],
+            )],
+        );
+        assert_eq!(round_trip(&input, &["jsonl"]), data_rows(&input));
+        assert_eq!(
+            read_merged_profile_any(&dir.path().join("converted0.jsonl"))
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn merged_profiles_round_trip_through_parquet() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = write_merged_profile_file(
+            dir.path(),
+            "merged.tsv",
+            &[merged_record("L1", &[("s1", 20)], &[("s1", "CAT", 0.1)])],
+        );
+        assert_eq!(round_trip(&input, &["parquet"]), data_rows(&input));
+    }
+
+    #[test]
+    fn formats_are_inferred_from_extensions() {
+        assert_eq!(Format::from_path(Path::new("a.tsv.gz")), Some(Format::Tsv));
+        assert_eq!(
+            Format::from_path(Path::new("a.ndjson")),
+            Some(Format::Jsonl)
+        );
+        assert_eq!(Format::from_path(Path::new("a.json.gz")), None);
+        let error = convert("a.csv".into(), "b.tsv".into(), None, None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Could not infer the format of a.csv, use --from"
+        );
+    }
+}
@@ -0,0 +1,354 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use log::{info, warn};
+
+use crate::error::StrifError;
+use crate::extract::{
+    decode_qualities, encode_qualities, for_each_repeat_seq, read_repeat_seqs, Anchoring,
+    ExtractOptions, RepeatSeqRecord,
+};
+use crate::plan::Plan;
+use crate::schema::{self, OutputKind};
+use crate::utils::{create_output, get_default_out_path};
+
+/// The reads collapsed into a row of the deduplicated repeat sequences
+#[derive(Default)]
+struct Collapsed {
+    multiplicity: u32,
+    /// The highest quality of each base over the reads with qualities
+    qualities: Option<Vec<u8>>,
+    /// The best anchoring scores over the reads with them
+    anchoring: Option<Anchoring>,
+}
+
+impl Collapsed {
+    fn add(&mut self, multiplicity: u32, qualities: Option<&[u8]>, anchoring: Option<Anchoring>) {
+        self.multiplicity += multiplicity;
+        if let Some(anchoring) = anchoring {
+            self.anchoring = Some(match self.anchoring {
+                Some(best) => Anchoring {
+                    left: best.left.max(anchoring.left),
+                    right: best.right.max(anchoring.right),
+                },
+                None => anchoring,
+            });
+        }
+        if let Some(qualities) = qualities {
+            match &mut self.qualities {
+                Some(best) => {
+                    for (best, q) in best.iter_mut().zip(qualities) {
+                        *best = (*best).max(*q);
+                    }
+                }
+                None => self.qualities = Some(qualities.to_vec()),
+            }
+        }
+    }
+}
+
+/// The reads of a locus sharing a UMI, which are copies of a single molecule
+#[derive(Default)]
+struct Molecule {
+    /// The reads of each repeat sequence found among the copies
+    seqs: HashMap<String, Collapsed>,
+}
+
+impl Molecule {
+    /// The repeat sequence of the molecule, which is the one most of its
+    /// copies have, preferring the first in sort order on ties
+    fn consensus(self) -> (String, Collapsed) {
+        self.seqs
+            .into_iter()
+            .max_by(|(a_seq, a), (b_seq, b)| {
+                a.multiplicity
+                    .cmp(&b.multiplicity)
+                    .then_with(|| b_seq.cmp(a_seq))
+            })
+            .expect("a molecule has at least one read")
+    }
+}
+
+/// Collapses the identical repeat sequences of each locus into a single row
+/// with the number of reads it stands for. With a UMI tag, the input is a
+/// BAMlet whose reads sharing a locus and a UMI are collapsed into a single
+/// molecule first, so that each row counts molecules rather than reads.
+pub fn dedup(
+    input: PathBuf,
+    out_path: PathBuf,
+    umi_tag: Option<String>,
+    qualities: bool,
+) -> Result<()> {
+    let mut rows: BTreeMap<(String, String), Collapsed> = BTreeMap::new();
+    let reads = match umi_tag {
+        Some(umi_tag) => {
+            let umi_tag: [u8; 2] = match umi_tag.as_bytes().try_into() {
+                Ok(umi_tag) => umi_tag,
+                Err(_) => bail!("The UMI tag must have two characters, found '{}'", umi_tag),
+            };
+            collapse_molecules(&input, qualities, umi_tag, &mut rows)?
+        }
+        None => collapse_reads(&input, &mut rows)?,
+    };
+
+    info!(
+        reads, rows = rows.len();
+        "Collapsed {} reads into {} rows, writing to output file...",
+        reads,
+        rows.len()
+    );
+    let mut out_file = create_output(&out_path)?;
+    schema::write_header(&mut out_file, OutputKind::RepeatSeqs)?;
+    for ((locus_id, seq), collapsed) in &rows {
+        RepeatSeqRecord {
+            locus_id: locus_id.clone(),
+            seq: seq.clone(),
+            qualities: collapsed.qualities.as_deref().map(encode_qualities),
+            multiplicity: Some(collapsed.multiplicity),
+            anchoring: collapsed.anchoring,
+            read_name: None,
+            coordinates: None,
+        }
+        .write_to(&mut out_file)?;
+    }
+    out_file.finish()?;
+    if rows.is_empty() {
+        return Err(StrifError::EmptyOutput {
+            path: out_path.display().to_string(),
+            message: format!("{} has no repeat sequences", input.display()),
+        }
+        .into());
+    }
+
+    info!("Done!");
+    Ok(())
+}
+
+/// Collapses the rows of a file of repeat sequences, returning how many reads
+/// they stand for
+fn collapse_reads(
+    repeat_seqs: &Path,
+    rows: &mut BTreeMap<(String, String), Collapsed>,
+) -> Result<u64> {
+    info!("Collapsing identical repeat sequences...");
+    let mut reads: u64 = 0;
+    for record in read_repeat_seqs(repeat_seqs)? {
+        let multiplicity = record.multiplicity.unwrap_or(1);
+        let qualities = record.qualities.as_deref().and_then(decode_qualities);
+        if qualities
+            .as_ref()
+            .is_some_and(|qualities| qualities.len() != record.seq.len())
+        {
+            bail!(
+                "A repeat sequence of locus {} in {} and its qualities differ in length",
+                record.locus_id,
+                repeat_seqs.display()
+            );
+        }
+        rows.entry((record.locus_id, record.seq)).or_default().add(
+            multiplicity,
+            qualities.as_deref(),
+            record.anchoring,
+        );
+        reads += multiplicity as u64;
+    }
+    Ok(reads)
+}
+
+/// Collapses the reads of a BAMlet into molecules by their UMIs and then the
+/// molecules into rows, returning how many reads there were
+fn collapse_molecules(
+    bamlet: &Path,
+    qualities: bool,
+    umi_tag: [u8; 2],
+    rows: &mut BTreeMap<(String, String), Collapsed>,
+) -> Result<u64> {
+    info!(
+        "Collapsing the reads of each UMI in the {} tag...",
+        String::from_utf8_lossy(&umi_tag)
+    );
+    let mut molecules: HashMap<(String, String), Molecule> = HashMap::new();
+    let mut without_umi: u64 = 0;
+    let options = ExtractOptions::default().qualities(qualities);
+    let reads = for_each_repeat_seq(bamlet, &options, Some(umi_tag), None, |record, umi| {
+        let qualities = record.qualities.as_deref().and_then(decode_qualities);
+        match umi {
+            Some(umi) => {
+                molecules
+                    .entry((record.locus_id, umi.to_string()))
+                    .or_default()
+                    .seqs
+                    .entry(record.seq)
+                    .or_default()
+                    .add(1, qualities.as_deref(), None);
+            }
+            // a read without a UMI is a molecule of its own
+            None => {
+                without_umi += 1;
+                rows.entry((record.locus_id, record.seq)).or_default().add(
+                    1,
+                    qualities.as_deref(),
+                    None,
+                );
+            }
+        }
+        Ok(())
+    })?;
+    if without_umi > 0 {
+        warn!(
+            without_umi;
+            "{} reads have no {} tag and were counted as molecules of their own",
+            without_umi,
+            String::from_utf8_lossy(&umi_tag)
+        );
+    }
+
+    let molecule_count = molecules.len();
+    for ((locus_id, _), molecule) in molecules {
+        let (seq, copies) = molecule.consensus();
+        rows.entry((locus_id, seq))
+            .or_default()
+            .add(1, copies.qualities.as_deref(), None);
+    }
+    info!(
+        molecules = molecule_count;
+        "Found {} molecules with UMIs",
+        molecule_count
+    );
+    Ok(reads as u64)
+}
+
+/// The command line arguments of `strif dedup`
+#[derive(Debug, clap::Args)]
+pub struct DedupArgs {
+    /// The path to the repeat sequences, or with --umi-tag to the ExpansionHunter BAMlet
+    pub input: PathBuf,
+
+    /// The path to write the collapsed repeat sequences to. Defaults to the same directory as the input.
+    pub output: Option<PathBuf>,
+
+    /// Collapse the reads of a locus sharing the UMI in this tag (e.g. RX)
+    /// into a single molecule first, reading them from a BAMlet
+    #[clap(long)]
+    pub umi_tag: Option<String>,
+
+    /// With --umi-tag, also write the base qualities of each repeat sequence
+    #[clap(long, requires = "umi_tag")]
+    pub qualities: bool,
+}
+
+impl DedupArgs {
+    /// Runs `strif dedup`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let DedupArgs {
+            input,
+            output,
+            umi_tag,
+            qualities,
+        } = self;
+        let out_path: PathBuf =
+            output.unwrap_or_else(|| get_default_out_path(&input, "dedup_repeat_seqs", "tsv"));
+        let mut plan = Plan::new("dedup", dry_run, force);
+        if umi_tag.is_some() {
+            plan.bamlet(&input);
+        } else {
+            plan.records("repeat sequences", &input, "rows");
+        }
+        plan.output("collapsed repeat sequences", &out_path)
+            .execute(|| dedup(input, out_path, umi_tag, qualities))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::extract::MISSING_QUALITIES;
+    use crate::profile::{profile, ProfileOptions};
+    use crate::testing::write_catalog;
+
+    #[test]
+    fn identical_repeat_seqs_collapse_into_their_multiplicity() {
+        let dir = tempfile::tempdir().unwrap();
+        let repeat_seqs = dir.path().join("sample.repeat_seqs.tsv");
+        fs::write(
+            &repeat_seqs,
+            [
+                "HTT\tCAGCATCAG\t+++++5555",
+                "HTT\tCAGCAGCAG",
+                "HTT\tCAGCATCAG\t5555+5555\t2",
+                "FMR1\tCAGCAGCAG",
+                "HTT\tCAGCAGCAG",
+            ]
+            .join("\n")
+                + "\n",
+        )
+        .unwrap();
+        let out = dir.path().join("sample.dedup_repeat_seqs.tsv");
+        dedup(repeat_seqs.clone(), out.clone(), None, false).unwrap();
+        let rows: Vec<(String, String, Option<u32>, Option<String>)> = read_repeat_seqs(&out)
+            .unwrap()
+            .into_iter()
+            .map(|record| {
+                (
+                    record.locus_id,
+                    record.seq,
+                    record.multiplicity,
+                    record.qualities,
+                )
+            })
+            .collect();
+        let row = |locus_id: &str, seq: &str, multiplicity, qualities: &str| {
+            (
+                locus_id.to_string(),
+                seq.to_string(),
+                Some(multiplicity),
+                Some(qualities.to_string()),
+            )
+        };
+        // rows keep the best quality of each base over their reads
+        assert_eq!(
+            rows,
+            [
+                row("FMR1", "CAGCAGCAG", 1, MISSING_QUALITIES),
+                row("HTT", "CAGCAGCAG", 2, MISSING_QUALITIES),
+                row("HTT", "CAGCATCAG", 3, "5555+5555"),
+            ]
+        );
+
+        // the collapsed rows profile like the reads they stand for
+        let catalog = write_catalog(dir.path());
+        let options = ProfileOptions::default();
+        let (reads_profile, rows_profile) = (dir.path().join("a"), dir.path().join("b"));
+        profile(
+            repeat_seqs,
+            catalog.clone(),
+            reads_profile.clone(),
+            &options,
+        )
+        .unwrap();
+        profile(out, catalog, rows_profile.clone(), &options).unwrap();
+        assert_eq!(
+            fs::read_to_string(reads_profile).unwrap(),
+            fs::read_to_string(rows_profile).unwrap()
+        );
+    }
+
+    #[test]
+    fn molecules_take_the_repeat_seq_of_most_of_their_copies() {
+        let mut molecule = Molecule::default();
+        for (seq, copies) in [("CAGCATCAG", 1), ("CAGCAGCAG", 2), ("CAGCAACAG", 2)] {
+            molecule
+                .seqs
+                .entry(seq.to_string())
+                .or_default()
+                .add(copies, None, None);
+        }
+        // ties go to the first in sort order
+        let (seq, copies) = molecule.consensus();
+        assert_eq!(seq, "CAGCAACAG");
+        assert_eq!(copies.multiplicity, 2);
+    }
+}
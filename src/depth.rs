@@ -0,0 +1,430 @@
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use regex::Regex;
+use rust_htslib::bam::{
+    self,
+    record::{Aux, Cigar},
+    Read,
+};
+
+use crate::merge::load_manifest;
+use crate::plan::Plan;
+use crate::profile::{CountUnit, COUNT_UNIT_PREFIX};
+use crate::threads::threads;
+use crate::utils::{create_output, get_default_out_path};
+
+pub struct DepthParams {
+    /// Number of autosomal windows to sample
+    pub windows: usize,
+    pub window_size: u64,
+    pub min_mapq: u8,
+    /// Reference FASTA, required for CRAM files
+    pub reference: Option<PathBuf>,
+    pub seed: u64,
+    /// Whether to count the bases where the mates of a fragment overlap once,
+    /// to normalize profiles of `--count-unit fragment`
+    pub fragments: bool,
+}
+
+/// Estimates the global average read depth of each alignment file and writes
+/// the sample ID / read depth table expected by `merge`
+pub fn depth(
+    alignments: Vec<PathBuf>,
+    manifest: Option<PathBuf>,
+    out_path: PathBuf,
+    params: DepthParams,
+) -> Result<()> {
+    if params.windows == 0 || params.window_size == 0 {
+        bail!("The number of windows and the window size must be positive");
+    }
+
+    // (sample ID if known, alignment file)
+    let mut inputs: Vec<(Option<String>, PathBuf)> =
+        alignments.into_iter().map(|path| (None, path)).collect();
+    if let Some(manifest) = &manifest {
+        for entry in load_manifest(manifest)? {
+            inputs.push((Some(entry.sample_id), entry.path));
+        }
+    }
+    if inputs.is_empty() {
+        bail!("No alignment files given, provide BAM/CRAM paths or a --manifest");
+    }
+
+    let mut out_file = create_output(&out_path)?;
+    if params.fragments {
+        writeln!(
+            out_file,
+            "{}{}",
+            COUNT_UNIT_PREFIX,
+            CountUnit::Fragment.name()
+        )?;
+    }
+    for (sample_id, path) in inputs {
+        let sample_id = match sample_id {
+            Some(sample_id) => sample_id,
+            None => sample_id_from_header(&path)?,
+        };
+        info!(
+            "Estimating read depth of {} from {}...",
+            sample_id,
+            path.display()
+        );
+        let read_depth = estimate_depth(&path, &params)
+            .with_context(|| format!("Could not estimate the read depth of {}", path.display()))?;
+        info!(
+            sample = sample_id.as_str(), read_depth;
+            "{} has an estimated read depth of {:.2}",
+            sample_id, read_depth
+        );
+        writeln!(out_file, "{}\t{}", sample_id, read_depth)?;
+    }
+
+    out_file.finish()?;
+
+    info!("Done!");
+    Ok(())
+}
+
+/// Uses the SM tag of the first read group, falling back to the file name
+fn sample_id_from_header(path: &Path) -> Result<String> {
+    let reader = bam::Reader::from_path(path)?;
+    let header = bam::Header::from_template(reader.header()).to_hashmap();
+    if let Some(sample_id) = header
+        .get("RG")
+        .and_then(|read_groups| read_groups.iter().find_map(|rg| rg.get("SM")))
+    {
+        return Ok(sample_id.clone());
+    }
+    let file_stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let sample_id = file_stem.split('.').next().unwrap_or_default().to_string();
+    warn!(
+        "{} has no read group sample name, using {}",
+        path.display(),
+        sample_id
+    );
+    Ok(sample_id)
+}
+
+fn is_autosome(name: &str) -> bool {
+    lazy_static! {
+        static ref RE_AUTOSOME: Regex = Regex::new(r"^(?:chr)?(?:[1-9]|1\d|2[0-2])$").unwrap();
+    }
+    RE_AUTOSOME.is_match(name)
+}
+
+/// Mean depth over randomly placed autosomal windows. Windows without any
+/// coverage (e.g. assembly gaps) are ignored.
+fn estimate_depth(path: &Path, params: &DepthParams) -> Result<f64> {
+    let mut reader = bam::IndexedReader::from_path(path)
+        .with_context(|| format!("{} should be indexed", path.display()))?;
+    if threads() > 1 {
+        reader.set_threads(threads())?;
+    }
+    if let Some(reference) = &params.reference {
+        reader.set_reference(reference)?;
+    }
+
+    // (target ID, length) of autosomes long enough to hold a window
+    let header = reader.header().clone();
+    let autosomes: Vec<(u32, u64)> = (0..header.target_count())
+        .filter(|&tid| is_autosome(std::str::from_utf8(header.tid2name(tid)).unwrap_or("")))
+        .filter_map(|tid| header.target_len(tid).map(|len| (tid, len)))
+        .filter(|(_, len)| *len >= params.window_size)
+        .collect();
+    let total_len: u64 = autosomes
+        .iter()
+        .map(|(_, len)| len - params.window_size + 1)
+        .sum();
+    if total_len == 0 {
+        bail!(
+            "{} has no autosomes of at least {} bp",
+            path.display(),
+            params.window_size
+        );
+    }
+
+    let mut rng = StdRng::seed_from_u64(params.seed);
+    let mut depths: Vec<f64> = Vec::with_capacity(params.windows);
+    let mut record = bam::Record::new();
+    for _ in 0..params.windows {
+        // pick a window start uniformly across the autosomes
+        let mut offset: u64 = rng.gen_range(0..total_len);
+        let (tid, start) = autosomes
+            .iter()
+            .find_map(|(tid, len)| {
+                let starts = len - params.window_size + 1;
+                if offset < starts {
+                    Some((*tid, offset))
+                } else {
+                    offset -= starts;
+                    None
+                }
+            })
+            .unwrap();
+        let end = start + params.window_size;
+
+        reader.fetch((tid, start as i64, end as i64))?;
+        let mut aligned_bases: u64 = 0;
+        while let Some(result) = reader.read(&mut record) {
+            result?;
+            if record.is_unmapped()
+                || record.is_secondary()
+                || record.is_supplementary()
+                || record.is_duplicate()
+                || record.is_quality_check_failed()
+                || record.mapq() < params.min_mapq
+            {
+                continue;
+            }
+            // the bases of the later mate that its mate already covers
+            // are counted once, with the mate
+            let start = match params.fragments.then(|| mate_end(&record)).flatten() {
+                Some(mate_end) => start.max(mate_end),
+                None => start,
+            };
+            aligned_bases += aligned_bases_in_window(&record, start, end);
+        }
+        if aligned_bases > 0 {
+            depths.push(aligned_bases as f64 / params.window_size as f64);
+        }
+    }
+
+    if depths.is_empty() {
+        bail!(
+            "None of the {} sampled windows in {} have coverage",
+            params.windows,
+            path.display()
+        );
+    }
+    Ok(depths.iter().sum::<f64>() / depths.len() as f64)
+}
+
+/// The reference end of the mate of a read if the mate is on the same
+/// contig and starts first, so that the overlap of the two is the read's
+/// bases before it. The end is from the MC tag, or assumes the mate spans
+/// as much of the reference as the read without one.
+fn mate_end(record: &bam::Record) -> Option<u64> {
+    if !record.is_paired() || record.is_mate_unmapped() || record.tid() != record.mtid() {
+        return None;
+    }
+    let mate_first = record.mpos() < record.pos()
+        || (record.mpos() == record.pos() && record.is_last_in_template());
+    if !mate_first {
+        return None;
+    }
+    let mate_ref_len = match record.aux(b"MC") {
+        Ok(Aux::String(cigar)) => cigar_ref_len(cigar)?,
+        _ => (record.cigar().end_pos() - record.pos()) as u64,
+    };
+    Some(record.mpos() as u64 + mate_ref_len)
+}
+
+/// The number of reference bases spanned by a CIGAR string, or None if it is
+/// malformed
+fn cigar_ref_len(cigar: &str) -> Option<u64> {
+    let mut ref_len = 0;
+    let mut len = 0;
+    for c in cigar.chars() {
+        match c {
+            '0'..='9' => len = len * 10 + c.to_digit(10)? as u64,
+            'M' | 'D' | 'N' | '=' | 'X' => {
+                ref_len += len;
+                len = 0;
+            }
+            'I' | 'S' | 'H' | 'P' => len = 0,
+            _ => return None,
+        }
+    }
+    Some(ref_len)
+}
+
+/// Number of aligned (M/=/X) bases of a read within [start, end)
+fn aligned_bases_in_window(record: &bam::Record, start: u64, end: u64) -> u64 {
+    let mut ref_pos: u64 = record.pos() as u64;
+    let mut bases: u64 = 0;
+    for op in record.cigar().iter() {
+        match op {
+            Cigar::Match(len) | Cigar::Equal(len) | Cigar::Diff(len) => {
+                let op_end = ref_pos + *len as u64;
+                if op_end > start && ref_pos < end {
+                    bases += op_end.min(end) - ref_pos.max(start);
+                }
+                ref_pos = op_end;
+            }
+            Cigar::Del(len) | Cigar::RefSkip(len) => ref_pos += *len as u64,
+            _ => {}
+        }
+        if ref_pos >= end {
+            break;
+        }
+    }
+    bases
+}
+
+/// The command line arguments of `strif depth`
+#[derive(Debug, clap::Args)]
+pub struct DepthArgs {
+    /// The paths to indexed BAM/CRAM files. Sample IDs are taken from the read groups.
+    pub alignments: Vec<PathBuf>,
+
+    /// The path to a manifest with columns: sample ID, case-control status and BAM/CRAM path
+    #[clap(short = 'm', long)]
+    pub manifest: Option<PathBuf>,
+
+    /// The path to the read depths file. Defaults to the same directory as the manifest, or read_depths.tsv.
+    #[clap(short = 'o', long)]
+    pub output: Option<PathBuf>,
+
+    /// The reference FASTA, required for CRAM files
+    #[clap(short = 'r', long)]
+    pub reference: Option<PathBuf>,
+
+    /// The number of randomly placed autosomal windows to sample
+    #[clap(short = 'w', long, default_value = "1000")]
+    pub windows: usize,
+
+    /// The size of each window in base pairs
+    #[clap(long, default_value = "1000")]
+    pub window_size: u64,
+
+    /// Ignore reads with a lower mapping quality
+    #[clap(short = 'q', long, default_value = "20")]
+    pub min_mapq: u8,
+
+    /// The seed used to place windows
+    #[clap(long, default_value = "0")]
+    pub seed: u64,
+
+    /// Count the bases where the mates of a pair overlap once, giving the fragment depth
+    /// that profiles of `--count-unit fragment` are normalized by
+    #[clap(long)]
+    pub fragments: bool,
+}
+
+impl DepthArgs {
+    /// Runs `strif depth`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let DepthArgs {
+            alignments,
+            manifest,
+            output,
+            reference,
+            windows,
+            window_size,
+            min_mapq,
+            seed,
+            fragments,
+        } = self;
+        let out_path: PathBuf = output.unwrap_or_else(|| match &manifest {
+            Some(manifest) => get_default_out_path(manifest, "read_depths", "tsv"),
+            None => PathBuf::from("read_depths.tsv"),
+        });
+        let params = DepthParams {
+            windows,
+            window_size,
+            min_mapq,
+            reference,
+            seed,
+            fragments,
+        };
+        Plan::new("depth", dry_run, force)
+            .inputs("alignments", &alignments)
+            .maybe_input("manifest", manifest.as_deref())
+            .maybe_input("reference", params.reference.as_deref())
+            .output("read depths", &out_path)
+            .execute(|| depth(alignments, manifest, out_path, params))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_htslib::bam::{header::HeaderRecord, record::CigarString};
+
+    use super::*;
+
+    /// A read spanning the whole of a 1 kb chr1
+    fn read(name: &str, flags: u16, mapq: u8) -> bam::Record {
+        let mut record = bam::Record::new();
+        let cigar = CigarString(vec![Cigar::Match(1000)]);
+        record.set(name.as_bytes(), Some(&cigar), &[b'A'; 1000], &[30; 1000]);
+        record.set_tid(0);
+        record.set_pos(0);
+        record.set_mtid(-1);
+        record.set_mpos(-1);
+        record.set_mapq(mapq);
+        record.set_flags(flags);
+        record
+    }
+
+    /// Writes and indexes a BAM of reads on a 1 kb chr1 of sample HG002
+    fn write_bam(path: &Path, records: &[bam::Record]) {
+        let mut header = bam::Header::new();
+        let mut contig = HeaderRecord::new(b"SQ");
+        contig.push_tag(b"SN", "chr1").push_tag(b"LN", 1000);
+        header.push_record(&contig);
+        let mut read_group = HeaderRecord::new(b"RG");
+        read_group.push_tag(b"ID", "rg1").push_tag(b"SM", "HG002");
+        header.push_record(&read_group);
+        let mut writer = bam::Writer::from_path(path, &header, bam::Format::Bam).unwrap();
+        for record in records {
+            writer.write(record).unwrap();
+        }
+        drop(writer);
+        bam::index::build(path, None, bam::index::Type::Bai, 1).unwrap();
+    }
+
+    fn params(fragments: bool) -> DepthParams {
+        DepthParams {
+            windows: 20,
+            window_size: 100,
+            min_mapq: 20,
+            reference: None,
+            seed: 1,
+            fragments,
+        }
+    }
+
+    #[test]
+    fn depths_count_the_bases_of_reads_passing_the_filters() {
+        let dir = tempfile::tempdir().unwrap();
+        let bam_path = dir.path().join("sample.bam");
+        let mut records: Vec<bam::Record> =
+            (0..4).map(|i| read(&format!("r{}", i), 0, 60)).collect();
+        records.push(read("dup", 0x400, 60));
+        records.push(read("low_mapq", 0, 5));
+        // the mates of a fragment overlapping completely
+        let mut first = read("pair", 0x1 | 0x40, 60);
+        let mut second = read("pair", 0x1 | 0x80, 60);
+        for mate in [&mut first, &mut second] {
+            mate.set_mtid(0);
+            mate.set_mpos(0);
+        }
+        second.push_aux(b"MC", Aux::String("1000M")).unwrap();
+        records.extend([first, second]);
+        write_bam(&bam_path, &records);
+
+        assert_eq!(estimate_depth(&bam_path, &params(false)).unwrap(), 6.0);
+        assert_eq!(estimate_depth(&bam_path, &params(true)).unwrap(), 5.0);
+
+        let out = dir.path().join("read_depths.tsv");
+        depth(vec![bam_path], None, out.clone(), params(true)).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(out).unwrap(),
+            format!("{}fragment\nHG002\t5\n", COUNT_UNIT_PREFIX)
+        );
+    }
+
+    #[test]
+    fn only_autosomes_are_sampled() {
+        assert!(is_autosome("chr1") && is_autosome("22") && is_autosome("chr19"));
+        assert!(!is_autosome("chrX") && !is_autosome("chr23") && !is_autosome("chr1_random"));
+        assert_eq!(cigar_ref_len("5S10M2I3D4N1="), Some(18));
+        assert_eq!(cigar_ref_len("10Q"), None);
+    }
+}
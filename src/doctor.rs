@@ -0,0 +1,674 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use bio::io::fasta;
+use log::info;
+
+use crate::extract::probe_bamlet;
+use crate::merge::load_manifest;
+use crate::plan::Plan;
+use crate::profile::load_str_catalog;
+use crate::scratch::{self, ScratchDir};
+use crate::threads::{available_cores, threads};
+use crate::utils::ByteSize;
+use crate::validate::{load_fai, validate_catalog, validate_manifest, validate_read_depths};
+
+/// The reads of a BAMlet that `doctor` looks at
+const PROBED_READS: usize = 1000;
+
+/// Below this much memory, merging a large cohort may run out of memory
+const LOW_MEMORY: u64 = 2 * 1024 * 1024 * 1024;
+
+/// Below this much free space, spilling a merge or writing outputs may fail
+const LOW_DISK_SPACE: u64 = 1024 * 1024 * 1024;
+
+/// The problems of a kind listed one by one before the rest are counted
+const MAX_LISTED_PROBLEMS: usize = 5;
+
+/// The inputs `doctor` probes, all optional
+pub struct DoctorParams {
+    pub bamlet: Option<PathBuf>,
+    pub str_catalog: Option<PathBuf>,
+    pub reference: Option<PathBuf>,
+    pub manifest: Option<PathBuf>,
+    pub read_depths: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+impl Status {
+    fn name(&self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        }
+    }
+}
+
+/// The outcome of a check, with how to fix it unless it passed
+struct Check {
+    status: Status,
+    message: String,
+    fix: Option<String>,
+}
+
+/// The checks of a section of the report
+#[derive(Default)]
+struct Checks(Vec<Check>);
+
+impl Checks {
+    fn ok(&mut self, message: impl Into<String>) {
+        self.0.push(Check {
+            status: Status::Ok,
+            message: message.into(),
+            fix: None,
+        });
+    }
+
+    fn warn(&mut self, message: impl Into<String>, fix: impl Into<String>) {
+        self.0.push(Check {
+            status: Status::Warn,
+            message: message.into(),
+            fix: Some(fix.into()),
+        });
+    }
+
+    fn fail(&mut self, message: impl Into<String>, fix: impl Into<String>) {
+        self.0.push(Check {
+            status: Status::Fail,
+            message: message.into(),
+            fix: Some(fix.into()),
+        });
+    }
+
+    /// Fails for each of the problems found by `validate`, listing the first
+    /// few and counting the rest
+    fn problems(&mut self, problems: &[String], fix: &str) {
+        for problem in problems.iter().take(MAX_LISTED_PROBLEMS) {
+            self.fail(problem.clone(), fix);
+        }
+        if problems.len() > MAX_LISTED_PROBLEMS {
+            self.fail(
+                format!(
+                    "... and {} more problem(s)",
+                    problems.len() - MAX_LISTED_PROBLEMS
+                ),
+                fix,
+            );
+        }
+    }
+}
+
+/// Checks the environment strif runs in and probes the given inputs,
+/// printing each check with how to fix those that failed or raised a
+/// warning. Fails if any check failed.
+pub fn doctor(params: DoctorParams) -> Result<()> {
+    info!("Checking the environment...");
+    let environment = check_environment(&params);
+
+    info!("Probing the inputs...");
+    let mut inputs = Checks::default();
+    let contig_lengths = params
+        .reference
+        .as_deref()
+        .and_then(|reference| check_reference(reference, &mut inputs));
+    let catalog_loci = params
+        .str_catalog
+        .as_deref()
+        .and_then(|str_catalog| check_catalog(str_catalog, contig_lengths.as_ref(), &mut inputs));
+    if let Some(bamlet) = &params.bamlet {
+        check_bamlet(bamlet, catalog_loci.as_ref(), &mut inputs);
+    }
+    let manifest_samples = params
+        .manifest
+        .as_deref()
+        .and_then(|manifest| check_manifest(manifest, &mut inputs));
+    if let Some(read_depths) = &params.read_depths {
+        check_read_depths(read_depths, manifest_samples.as_deref(), &mut inputs);
+    }
+    if inputs.0.is_empty() {
+        inputs.ok("No inputs given, pass --bamlet, --catalog, --reference, --manifest or --read-depths to probe them");
+    }
+
+    let mut failed = 0;
+    let mut warnings = 0;
+    for (section, checks) in [("Environment", &environment), ("Inputs", &inputs)] {
+        println!("{}", section);
+        for check in &checks.0 {
+            println!("  {:<5} {}", check.status.name(), check.message);
+            if let Some(fix) = &check.fix {
+                println!("        fix: {}", fix);
+            }
+            match check.status {
+                Status::Ok => {}
+                Status::Warn => warnings += 1,
+                Status::Fail => failed += 1,
+            }
+        }
+    }
+    if failed > 0 {
+        bail!("Found {} problem(s) and {} warning(s)", failed, warnings);
+    }
+    info!(warnings; "No problems found, {} warning(s)", warnings);
+    Ok(())
+}
+
+fn check_environment(params: &DoctorParams) -> Checks {
+    let mut checks = Checks::default();
+    let version = env!("CARGO_PKG_VERSION");
+    if cfg!(feature = "noodles") {
+        checks.ok(format!(
+            "strif {} built with noodles, which reads BAM and CRAM BAMlets in pure Rust",
+            version
+        ));
+    } else if cfg!(feature = "htslib") {
+        checks.ok(format!(
+            "strif {} built with htslib, which reads BAM and CRAM BAMlets",
+            version
+        ));
+    } else {
+        checks.fail(
+            format!(
+                "strif {} built without htslib or noodles, so it cannot read BAMlets",
+                version
+            ),
+            "install a build with the default features, e.g. `cargo install strif`, or with the `noodles` feature where htslib cannot be built",
+        );
+    }
+    if !cfg!(feature = "htslib") {
+        checks.warn(
+            format!(
+                "strif {} built without htslib, so it has no `depth` subcommand",
+                version
+            ),
+            "install a build with the default features to estimate read depths with `depth`",
+        );
+    }
+
+    let cores = available_cores();
+    if threads() > cores {
+        checks.warn(
+            format!(
+                "--threads {} exceeds the {} core(s) available to strif",
+                threads(),
+                cores
+            ),
+            format!(
+                "pass --threads {} or fewer, or request more cores from the batch scheduler",
+                cores
+            ),
+        );
+    } else {
+        checks.ok(format!(
+            "{} core(s) available, using {} thread(s)",
+            cores,
+            threads()
+        ));
+    }
+
+    match available_memory() {
+        Some(memory) if memory < LOW_MEMORY => checks.warn(
+            format!("Only {} of memory available", ByteSize(memory)),
+            "pass `merge --max-memory` to spill large merges to disk, or request more memory from the batch scheduler",
+        ),
+        Some(memory) => checks.ok(format!("{} of memory available", ByteSize(memory))),
+        None => checks.ok("The available memory cannot be told on this system"),
+    }
+
+    let tmp_dir = scratch::tmp_dir().unwrap_or_else(|| PathBuf::from("."));
+    match ScratchDir::create("doctor", &tmp_dir.join("doctor")) {
+        Ok(scratch) => match scratch.free_space() {
+            Some(free) if free < LOW_DISK_SPACE => checks.warn(
+                format!(
+                    "Only {} free in the temporary directory {}",
+                    ByteSize(free),
+                    tmp_dir.display()
+                ),
+                "pass --tmp-dir with a directory on a larger disk",
+            ),
+            Some(free) => checks.ok(format!(
+                "{} free in the temporary directory {}",
+                ByteSize(free),
+                tmp_dir.display()
+            )),
+            None => checks.ok(format!(
+                "The temporary directory {} is writable",
+                tmp_dir.display()
+            )),
+        },
+        Err(e) => checks.fail(
+            format!("Cannot write to the temporary directory: {:#}", e),
+            "pass --tmp-dir with a writable directory",
+        ),
+    }
+
+    let is_cram = params
+        .bamlet
+        .as_ref()
+        .and_then(|bamlet| bamlet.extension())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("cram"));
+    if is_cram && cfg!(feature = "noodles") && params.reference.is_none() {
+        checks.warn(
+            "No reference is given, so CRAM BAMlets can only be decoded if their reference is embedded",
+            "pass the reference the BAMlets were compressed against to `extract --reference` (and check it here with --reference)",
+        );
+    } else if is_cram && cfg!(feature = "htslib") && std::env::var_os("REF_PATH").is_none() {
+        checks.warn(
+            "REF_PATH is not set, so htslib downloads the reference of CRAM BAMlets from the ENA, which fails without network access",
+            "set REF_PATH (and REF_CACHE) to a local reference cache, e.g. one built with `seq_cache_populate.pl`",
+        );
+    }
+    checks
+}
+
+/// The memory available to strif: what the kernel reports as available,
+/// capped by the memory limit of the cgroup of a container or batch job
+fn available_memory() -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let available: u64 = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<u64>()
+        .ok()?
+        * 1024;
+    // the limit is "max" when there is none
+    let limit = fs::read_to_string("/sys/fs/cgroup/memory.max")
+        .ok()
+        .and_then(|limit| limit.trim().parse::<u64>().ok());
+    Some(limit.map_or(available, |limit| limit.min(available)))
+}
+
+/// Checks that a reference FASTA has an index and can be read, returning the
+/// lengths of its contigs
+fn check_reference(reference: &Path, checks: &mut Checks) -> Option<HashMap<String, u64>> {
+    if !reference.is_file() {
+        checks.fail(
+            format!("The reference {} does not exist", reference.display()),
+            "pass the path to the FASTA the BAMlets were aligned to",
+        );
+        return None;
+    }
+    let mut fai = reference.as_os_str().to_owned();
+    fai.push(".fai");
+    let fai = PathBuf::from(fai);
+    if !fai.is_file() {
+        checks.fail(
+            format!("The reference {} has no index", reference.display()),
+            format!("index it with `samtools faidx {}`", reference.display()),
+        );
+        return None;
+    }
+    if let Err(e) = fasta::IndexedReader::from_file(&reference) {
+        checks.fail(
+            format!("Cannot read the reference {}: {}", reference.display(), e),
+            "check that it is an uncompressed or bgzipped FASTA file and that its index is up to date",
+        );
+        return None;
+    }
+    match load_fai(&fai) {
+        Ok(contig_lengths) => {
+            checks.ok(format!(
+                "The reference {} has {} contig(s)",
+                reference.display(),
+                contig_lengths.len()
+            ));
+            Some(contig_lengths)
+        }
+        Err(e) => {
+            checks.fail(
+                format!("Cannot read the index of {}: {:#}", reference.display(), e),
+                format!("reindex it with `samtools faidx {}`", reference.display()),
+            );
+            None
+        }
+    }
+}
+
+/// Checks that a catalog parses and that its loci are well-formed, and within
+/// the contigs of the reference if given, returning its locus IDs
+fn check_catalog(
+    str_catalog: &Path,
+    contig_lengths: Option<&HashMap<String, u64>>,
+    checks: &mut Checks,
+) -> Option<HashMap<String, String>> {
+    let (motifs, _, _, _, _) = match load_str_catalog(str_catalog.to_path_buf(), None) {
+        Ok(catalog) => catalog,
+        Err(e) => {
+            checks.fail(
+                format!("Cannot load the catalog {}: {:#}", str_catalog.display(), e),
+                "pass the JSON variant catalog ExpansionHunter was run with",
+            );
+            return None;
+        }
+    };
+    let mut problems: Vec<String> = Vec::new();
+    if let Err(e) = validate_catalog(str_catalog, contig_lengths, &mut problems) {
+        problems.push(format!("{}: {:#}", str_catalog.display(), e));
+    }
+    if problems.is_empty() {
+        checks.ok(format!(
+            "The catalog {} has {} loci",
+            str_catalog.display(),
+            motifs.len()
+        ));
+    } else {
+        checks.problems(
+            &problems,
+            "fix the entry in the catalog, or check that the reference is the build the catalog is for",
+        );
+    }
+    Some(motifs)
+}
+
+/// Checks that the first reads of a BAMlet were realigned by ExpansionHunter
+/// to loci of the catalog
+fn check_bamlet(
+    bamlet: &Path,
+    catalog_loci: Option<&HashMap<String, String>>,
+    checks: &mut Checks,
+) {
+    let probe = match probe_bamlet(bamlet, PROBED_READS) {
+        Ok(probe) => probe,
+        Err(e) => {
+            checks.fail(
+                format!("Cannot read the BAMlet {}: {:#}", bamlet.display(), e),
+                "check that it is a BAM or CRAM file and that it is not truncated",
+            );
+            return;
+        }
+    };
+    if probe.reads == 0 {
+        checks.fail(
+            format!("The BAMlet {} has no reads", bamlet.display()),
+            "check that ExpansionHunter found reads at the loci of the catalog, e.g. that the sample was aligned to the build of the catalog",
+        );
+        return;
+    }
+    if probe.with_graph_alignment == 0 {
+        checks.fail(
+            format!(
+                "None of the first {} reads of {} has an XG tag",
+                probe.reads,
+                bamlet.display()
+            ),
+            "pass the realigned BAMlet ExpansionHunter writes (<prefix>_realigned.bam), not the alignments it was given",
+        );
+        return;
+    }
+    checks.ok(format!(
+        "{} of the first {} reads of {} have an XG tag, covering {} loci",
+        probe.with_graph_alignment,
+        probe.reads,
+        bamlet.display(),
+        probe.loci.len()
+    ));
+    if probe.with_qualities < probe.reads {
+        checks.warn(
+            format!(
+                "{} of the first {} reads of {} have no base qualities",
+                probe.reads - probe.with_qualities,
+                probe.reads,
+                bamlet.display()
+            ),
+            "the quality options of extract and profile (--qualities, --min-base-quality, --quality-weighted) treat these reads as unweighted",
+        );
+    }
+    if let Some(catalog_loci) = catalog_loci {
+        let unknown = probe
+            .loci
+            .iter()
+            .filter(|locus_id| !catalog_loci.contains_key(*locus_id))
+            .count();
+        if unknown > 0 {
+            checks.fail(
+                format!(
+                    "{} of the {} loci of the first reads of {} are not in the catalog",
+                    unknown,
+                    probe.loci.len(),
+                    bamlet.display()
+                ),
+                "pass the catalog ExpansionHunter was run with, whose locus IDs the XG tags name",
+            );
+        }
+    }
+}
+
+/// Checks that a manifest has a unique ID and an existing file for each
+/// sample, returning its sample IDs
+fn check_manifest(manifest: &Path, checks: &mut Checks) -> Option<Vec<String>> {
+    let mut problems: Vec<String> = Vec::new();
+    let samples = match validate_manifest(manifest, &mut problems) {
+        Ok(samples) => samples,
+        Err(e) => {
+            checks.fail(
+                format!("Cannot read the manifest {}: {:#}", manifest.display(), e),
+                "pass a tab-separated file without a header, with the sample ID, status and path of a sample on each line",
+            );
+            return None;
+        }
+    };
+    if !problems.is_empty() {
+        checks.problems(
+            &problems,
+            "give each sample a unique ID and the path to its file, relative to the directory strif runs in",
+        );
+        return Some(samples);
+    }
+    let mut statuses: Vec<(String, usize)> = Vec::new();
+    for entry in load_manifest(manifest).ok()? {
+        match statuses
+            .iter_mut()
+            .find(|(status, _)| *status == entry.status)
+        {
+            Some((_, count)) => *count += 1,
+            None => statuses.push((entry.status, 1)),
+        }
+    }
+    let statuses: Vec<String> = statuses
+        .iter()
+        .map(|(status, count)| format!("{} {}", count, status))
+        .collect();
+    checks.ok(format!(
+        "The manifest {} has {} samples ({})",
+        manifest.display(),
+        samples.len(),
+        statuses.join(", ")
+    ));
+    Some(samples)
+}
+
+/// Checks that a read depths file has a valid depth for each sample, of the
+/// manifest if given
+fn check_read_depths(read_depths: &Path, manifest_samples: Option<&[String]>, checks: &mut Checks) {
+    let mut problems: Vec<String> = Vec::new();
+    let depth_samples = match validate_read_depths(read_depths, &mut problems) {
+        Ok(depth_samples) => depth_samples,
+        Err(e) => {
+            checks.fail(
+                format!(
+                    "Cannot read the read depths {}: {:#}",
+                    read_depths.display(),
+                    e
+                ),
+                "pass a tab-separated file without a header, with the sample ID and read depth of a sample on each line, e.g. from `strif depth`",
+            );
+            return;
+        }
+    };
+    for sample_id in manifest_samples.unwrap_or_default() {
+        if !depth_samples.contains(sample_id) {
+            problems.push(format!(
+                "{}: sample {} from the manifest has no read depth",
+                read_depths.display(),
+                sample_id
+            ));
+        }
+    }
+    if problems.is_empty() {
+        checks.ok(format!(
+            "The read depths {} cover {} samples",
+            read_depths.display(),
+            depth_samples.len()
+        ));
+    } else {
+        checks.problems(
+            &problems,
+            "estimate the read depth of every sample, e.g. with `strif depth --manifest`",
+        );
+    }
+}
+
+/// The command line arguments of `strif doctor`
+#[derive(Debug, clap::Args)]
+pub struct DoctorArgs {
+    /// The path to a BAMlet, whose first reads are probed
+    #[clap(short = 'b', long)]
+    pub bamlet: Option<PathBuf>,
+
+    /// The path to a JSON file containing the catalog of repeat loci
+    #[clap(short = 'c', long = "catalog")]
+    pub str_catalog: Option<PathBuf>,
+
+    /// The path to the reference FASTA, which must be indexed
+    #[clap(short = 'r', long)]
+    pub reference: Option<PathBuf>,
+
+    /// The path to the manifest file used by merge
+    #[clap(short = 'm', long)]
+    pub manifest: Option<PathBuf>,
+
+    /// The path to the read depths file used by merge
+    #[clap(short = 'd', long)]
+    pub read_depths: Option<PathBuf>,
+}
+
+impl DoctorArgs {
+    /// Runs `strif doctor`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let DoctorArgs {
+            bamlet,
+            str_catalog,
+            reference,
+            manifest,
+            read_depths,
+        } = self;
+        Plan::new("doctor", dry_run, force)
+            .maybe_input("BAMlet", bamlet.as_deref())
+            .maybe_input("catalog", str_catalog.as_deref())
+            .maybe_input("reference", reference.as_deref())
+            .maybe_input("manifest", manifest.as_deref())
+            .maybe_input("read depths", read_depths.as_deref())
+            .execute(|| {
+                doctor(DoctorParams {
+                    bamlet,
+                    str_catalog,
+                    reference,
+                    manifest,
+                    read_depths,
+                })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::testing::write_catalog;
+
+    fn statuses(checks: &Checks) -> Vec<Status> {
+        checks.0.iter().map(|check| check.status).collect()
+    }
+
+    #[test]
+    fn inputs_are_probed_with_a_fix_for_each_problem() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut checks = Checks::default();
+        let catalog_loci = check_catalog(&write_catalog(dir.path()), None, &mut checks).unwrap();
+        assert_eq!(catalog_loci.len(), 2);
+        let broken = dir.path().join("broken.json");
+        fs::write(&broken, "[{\"LocusId\": \"HTT\"}]").unwrap();
+        assert!(check_catalog(&broken, None, &mut checks).is_none());
+        check_reference(&dir.path().join("missing.fa"), &mut checks);
+        let reference = dir.path().join("ref.fa");
+        fs::write(&reference, ">chr1\nACGT\n").unwrap();
+        check_reference(&reference, &mut checks);
+        assert_eq!(
+            statuses(&checks),
+            [Status::Ok, Status::Fail, Status::Fail, Status::Fail]
+        );
+        assert!(checks.0[3].fix.as_ref().unwrap().contains("samtools faidx"));
+
+        let mut checks = Checks::default();
+        let profile = dir.path().join("s1.profile.tsv");
+        fs::write(&profile, "").unwrap();
+        let manifest = dir.path().join("manifest.tsv");
+        fs::write(
+            &manifest,
+            format!(
+                "s1\tcase\t{}\ns2\tcontrol\t{}\n",
+                profile.display(),
+                profile.display()
+            ),
+        )
+        .unwrap();
+        let samples = check_manifest(&manifest, &mut checks).unwrap();
+        assert_eq!(
+            checks.0[0].message,
+            format!(
+                "The manifest {} has 2 samples (1 case, 1 control)",
+                manifest.display()
+            )
+        );
+        let read_depths = dir.path().join("read_depths.tsv");
+        fs::write(&read_depths, "s1\t30\n").unwrap();
+        check_read_depths(&read_depths, Some(&samples), &mut checks);
+        assert_eq!(statuses(&checks), [Status::Ok, Status::Fail]);
+        assert!(checks.0[1]
+            .message
+            .ends_with("sample s2 from the manifest has no read depth"));
+    }
+
+    #[test]
+    fn only_the_first_problems_are_listed() {
+        let mut checks = Checks::default();
+        let problems: Vec<String> = (1..=8).map(|i| format!("problem {}", i)).collect();
+        checks.problems(&problems, "fix it");
+        assert_eq!(checks.0.len(), MAX_LISTED_PROBLEMS + 1);
+        assert_eq!(
+            checks.0[MAX_LISTED_PROBLEMS].message,
+            "... and 3 more problem(s)"
+        );
+    }
+
+    #[cfg(feature = "htslib")]
+    #[test]
+    fn bamlet_loci_missing_from_the_catalog_fail() {
+        let dir = tempfile::tempdir().unwrap();
+        let bamlet = crate::testing::write_bamlet(
+            dir.path(),
+            &[
+                ("read1", "AAACAGCAGCAGTTT", "HTT,100,0[3M]1[9M]2[3M]"),
+                ("read2", "AAACAGCAGCAGTTT", "ATXN1,100,0[3M]1[9M]2[3M]"),
+            ],
+        );
+        let catalog_loci: HashMap<String, String> =
+            HashMap::from([("HTT".to_string(), "CAG".to_string())]);
+        let mut checks = Checks::default();
+        check_bamlet(&bamlet, Some(&catalog_loci), &mut checks);
+        assert_eq!(statuses(&checks), [Status::Ok, Status::Fail]);
+        assert!(checks.0[1].message.starts_with("1 of the 2 loci"));
+    }
+}
@@ -0,0 +1,131 @@
+use std::fmt::Display;
+
+use thiserror::Error;
+
+/// Errors in the inputs of strif, with enough context to find the offending
+/// file, line or sample in a large cohort. They are returned wrapped in an
+/// `anyhow::Error` and can be recovered with `downcast_ref`.
+#[derive(Debug, Error)]
+pub enum StrifError {
+    /// A catalog that cannot be parsed or has an invalid locus
+    #[error("Invalid catalog {path}: {message}")]
+    Catalog { path: String, message: String },
+    /// A row of a tabular input with missing columns or unparseable values
+    #[error("Malformed row in {path} line {line}: {message}")]
+    MalformedRow {
+        path: String,
+        line: u64,
+        message: String,
+    },
+    /// A sample of one input that is absent from another
+    #[error("Sample {sample_id} is missing from {path}")]
+    MissingSample { sample_id: String, path: String },
+    /// A BAMlet record that cannot be read or whose graph alignment is inconsistent
+    #[error("Could not read the alignment of {read_name} in {path}: {message}")]
+    Alignment {
+        path: String,
+        read_name: String,
+        message: String,
+    },
+    /// An output without any data, which usually means the inputs do not match
+    #[error("{path} is empty: {message}")]
+    EmptyOutput { path: String, message: String },
+}
+
+impl StrifError {
+    /// A malformed row error at the line of a CSV record
+    pub fn malformed_row(
+        path: impl Display,
+        record: &csv::StringRecord,
+        message: impl Into<String>,
+    ) -> Self {
+        StrifError::MalformedRow {
+            path: path.to_string(),
+            line: record.position().map(|p| p.line()).unwrap_or(0),
+            message: message.into(),
+        }
+    }
+}
+
+/// Returns a column of a CSV record, or a malformed row error if it is missing
+pub(crate) fn field(
+    record: &csv::StringRecord,
+    i: usize,
+    path: impl Display,
+) -> Result<&str, StrifError> {
+    record.get(i).ok_or_else(|| {
+        StrifError::malformed_row(
+            path,
+            record,
+            format!(
+                "expected at least {} columns, found {}",
+                i + 1,
+                record.len()
+            ),
+        )
+    })
+}
+
+/// Parses a column of a CSV record, or returns a malformed row error naming it
+pub(crate) fn parse_field<T: std::str::FromStr>(
+    record: &csv::StringRecord,
+    i: usize,
+    name: &str,
+    path: impl Display + Copy,
+) -> Result<T, StrifError> {
+    let value = field(record, i, path)?;
+    value.parse().map_err(|_| {
+        StrifError::malformed_row(path, record, format!("invalid {} '{}'", name, value))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::merge::{load_manifest, load_read_depths};
+
+    #[test]
+    fn unparseable_values_name_their_file_line_and_column() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("depths.tsv");
+        fs::write(&path, "s1\t30\ns2\tthirty\n").unwrap();
+
+        let err = load_read_depths(&path).unwrap_err();
+        match err.downcast_ref::<StrifError>() {
+            Some(StrifError::MalformedRow { line, message, .. }) => {
+                assert_eq!(*line, 2);
+                assert_eq!(message, "invalid read depth 'thirty'");
+            }
+            _ => panic!("expected a malformed row, got {:?}", err),
+        }
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Malformed row in {} line 2: invalid read depth 'thirty'",
+                path.display()
+            )
+        );
+    }
+
+    #[test]
+    fn missing_columns_are_malformed_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("manifest.tsv");
+        fs::write(&path, "s1\tcase\n").unwrap();
+
+        let err = load_manifest(&path).err().unwrap();
+        assert!(matches!(
+            err.downcast_ref::<StrifError>(),
+            Some(StrifError::MalformedRow { line: 1, .. })
+        ));
+
+        let record = csv::StringRecord::from(vec!["s1"]);
+        assert_eq!(
+            field(&record, 1, "depths.tsv").unwrap_err().to_string(),
+            "Malformed row in depths.tsv line 0: expected at least 2 columns, found 1"
+        );
+        assert_eq!(parse_field::<u32>(&record, 0, "count", "x").ok(), None);
+    }
+}
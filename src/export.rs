@@ -0,0 +1,539 @@
+use std::collections::HashMap;
+use std::io::{prelude::*, Cursor};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use chrono::{Datelike, Local, Timelike};
+use log::info;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, DateTime, ZipWriter};
+
+use crate::convert::read_merged_profile_any;
+use crate::merge::{load_manifest, MergedProfileRecord};
+use crate::plan::Plan;
+use crate::schema::{self, OutputKind};
+use crate::track::{mean_burdens, GROUPS};
+use crate::utils::{create_output, get_default_out_path, reference_span};
+
+/// Written at the start of each CSV so that Excel reads it as UTF-8
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Placeholders of missing values in strif outputs, left empty in the CSVs
+const MISSING: [&str; 3] = [".", "NA", "NaN"];
+
+/// A CSV of the export, with the descriptions of its columns listed in the
+/// data dictionary
+struct Table {
+    name: &'static str,
+    description: &'static str,
+    columns: Vec<(&'static str, &'static str)>,
+}
+
+impl Table {
+    fn headers(&self) -> Vec<&'static str> {
+        self.columns.iter().map(|(name, _)| *name).collect()
+    }
+}
+
+/// The description of a column of the output of `test`
+fn association_column(name: &str) -> &'static str {
+    match name {
+        "locus_id" => "The ID of the locus",
+        "reference_region" => "The reference region of the locus (0-based, end-exclusive)",
+        "motif" => "The repeat motif of the locus",
+        "interruption" => "The interruption tested, empty for tests of the total burden",
+        "model" => "The statistical model of the test",
+        "n_samples" => "The number of samples tested",
+        "effect" => "The effect size, e.g. the log odds ratio of logistic regression",
+        "std_error" => "The standard error of the effect size",
+        "statistic" => "The test statistic",
+        "p_value" => "The nominal p-value",
+        "adjusted_p_value" => "The p-value corrected for multiple testing",
+        "empirical_p_value" => "The p-value estimated by permutation",
+        "fwer_p_value" => "The family-wise error rate p-value estimated by permutation",
+        "comparison" => "The groups compared by a pairwise test",
+        "group_sizes" => "The number of samples of each group, as group:size",
+        "group_means" => "The mean burden of each group, as group:mean",
+        _ => "See the output of `strif test`",
+    }
+}
+
+/// The current local time, given to the files of the archive rather than the
+/// 1980 epoch of zip archives
+fn modified_time() -> DateTime {
+    let now = Local::now();
+    DateTime::from_date_and_time(
+        now.year() as u16,
+        now.month() as u8,
+        now.day() as u8,
+        now.hour() as u8,
+        now.minute() as u8,
+        now.second() as u8,
+    )
+    .unwrap_or_default()
+}
+
+/// Starts a CSV in the archive with its header row
+fn start_table<'a, W: Write + Seek>(
+    archive: &'a mut ZipWriter<W>,
+    table: &Table,
+) -> Result<csv::Writer<&'a mut ZipWriter<W>>> {
+    let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .last_modified_time(modified_time());
+    archive.start_file(table.name, options)?;
+    archive.write_all(BOM)?;
+    let mut writer = csv::Writer::from_writer(archive);
+    writer.write_record(table.headers())?;
+    Ok(writer)
+}
+
+fn format_optional(value: Option<f64>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+/// Writes the loci summary, one row per locus
+fn write_loci<W: Write + Seek>(
+    archive: &mut ZipWriter<W>,
+    records: &[MergedProfileRecord],
+    groups: Option<&HashMap<String, usize>>,
+) -> Result<Table> {
+    let mut columns = vec![
+        ("locus_id", "The ID of the locus"),
+        ("chrom", "The chromosome of the locus"),
+        ("start", "The start of the locus (0-based)"),
+        ("end", "The end of the locus (exclusive)"),
+        ("reference_region", "The reference region of the locus, as in the catalog"),
+        ("motif", "The repeat motif of the locus"),
+        ("samples", "The number of samples with reads at the locus"),
+        ("reads", "The number of reads at the locus over all samples"),
+        ("interruptions", "The number of distinct interruptions found at the locus"),
+        (
+            "mean_burden",
+            "The mean over the samples with reads of their normalized interruption burden, i.e. the sum of their normalized interruption counts",
+        ),
+    ];
+    if groups.is_some() {
+        columns.extend([
+            (
+                "case_mean_burden",
+                "The mean normalized interruption burden of the cases",
+            ),
+            (
+                "control_mean_burden",
+                "The mean normalized interruption burden of the controls",
+            ),
+        ]);
+    }
+    let table = Table {
+        name: "loci.csv",
+        description: "A summary of each locus",
+        columns,
+    };
+
+    let mut writer = start_table(archive, &table)?;
+    for record in records {
+        let span = reference_span(&record.reference_region);
+        let mut interruptions: Vec<&str> = record
+            .interruption_counts
+            .iter()
+            .map(|(_, interruption, _)| interruption.as_str())
+            .collect();
+        interruptions.sort_unstable();
+        interruptions.dedup();
+        let reads: u64 = record
+            .read_counts
+            .iter()
+            .map(|(_, count)| *count as u64)
+            .sum();
+        let mut row = vec![
+            record.locus_id.clone(),
+            span.as_ref()
+                .map(|span| span.chrom.clone())
+                .unwrap_or_default(),
+            span.as_ref()
+                .map(|span| span.start.to_string())
+                .unwrap_or_default(),
+            span.as_ref()
+                .map(|span| span.end.to_string())
+                .unwrap_or_default(),
+            record.reference_region.clone(),
+            record.motif.clone(),
+            record.read_counts.len().to_string(),
+            reads.to_string(),
+            interruptions.len().to_string(),
+            format_optional(mean_burdens(record, None)[0]),
+        ];
+        if groups.is_some() {
+            row.extend(
+                mean_burdens(record, groups)
+                    .into_iter()
+                    .map(format_optional),
+            );
+        }
+        writer.write_record(&row)?;
+    }
+    writer.flush()?;
+    Ok(table)
+}
+
+/// Writes the read count and burden of each sample at each locus, and the
+/// normalized count of each interruption of each sample at each locus
+fn write_counts<W: Write + Seek>(
+    archive: &mut ZipWriter<W>,
+    records: &[MergedProfileRecord],
+    statuses: Option<&HashMap<String, String>>,
+) -> Result<[Table; 2]> {
+    let status_column = (
+        "status",
+        "The case-control status of the sample, from the manifest",
+    );
+    let mut columns = vec![
+        ("locus_id", "The ID of the locus"),
+        ("sample_id", "The ID of the sample"),
+    ];
+    if statuses.is_some() {
+        columns.push(status_column);
+    }
+    columns.extend([
+        ("read_count", "The number of reads of the sample at the locus"),
+        (
+            "burden",
+            "The normalized interruption burden of the sample at the locus, i.e. the sum of its normalized interruption counts",
+        ),
+    ]);
+    let samples = Table {
+        name: "sample_counts.csv",
+        description: "The reads and burden of each sample with reads at each locus",
+        columns,
+    };
+    let mut columns = vec![
+        ("locus_id", "The ID of the locus"),
+        ("sample_id", "The ID of the sample"),
+    ];
+    if statuses.is_some() {
+        columns.push(status_column);
+    }
+    columns.extend([
+        (
+            "interruption",
+            "The sequence of the interruption, as in the merged profile",
+        ),
+        (
+            "normalized_count",
+            "The count of the interruption in the sample normalized by its read depth",
+        ),
+    ]);
+    let interruptions = Table {
+        name: "interruption_counts.csv",
+        description: "The normalized count of each interruption of each sample at each locus",
+        columns,
+    };
+
+    let status = |sample_id: &str| -> Option<String> {
+        statuses.map(|statuses| statuses.get(sample_id).cloned().unwrap_or_default())
+    };
+
+    let mut writer = start_table(archive, &samples)?;
+    for record in records {
+        let mut burdens: HashMap<&str, f64> = HashMap::new();
+        for (sample_id, _, count) in &record.interruption_counts {
+            *burdens.entry(sample_id).or_default() += count;
+        }
+        for (sample_id, read_count) in &record.read_counts {
+            let mut row = vec![record.locus_id.clone(), sample_id.clone()];
+            row.extend(status(sample_id));
+            row.push(read_count.to_string());
+            row.push(
+                burdens
+                    .get(sample_id.as_str())
+                    .copied()
+                    .unwrap_or(0.0)
+                    .to_string(),
+            );
+            writer.write_record(&row)?;
+        }
+    }
+    writer.flush()?;
+    drop(writer);
+
+    let mut writer = start_table(archive, &interruptions)?;
+    for record in records {
+        for (sample_id, interruption, count) in &record.interruption_counts {
+            let mut row = vec![record.locus_id.clone(), sample_id.clone()];
+            row.extend(status(sample_id));
+            row.push(interruption.clone());
+            row.push(count.to_string());
+            writer.write_record(&row)?;
+        }
+    }
+    writer.flush()?;
+    Ok([samples, interruptions])
+}
+
+/// Copies the output of `test`, leaving missing values empty
+fn write_statistics<W: Write + Seek>(
+    archive: &mut ZipWriter<W>,
+    association: &Path,
+) -> Result<Table> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(true)
+        .comment(Some(b'#'))
+        .from_path(association)?;
+    let headers = reader.headers()?.clone();
+    schema::validate(association, &headers, OutputKind::Association)?;
+    let mut columns: Vec<(&'static str, &'static str)> = Vec::new();
+    for header in &headers {
+        match OutputKind::Association
+            .columns()
+            .and_then(|known| known.iter().find(|name| **name == header))
+        {
+            Some(name) => columns.push((name, association_column(name))),
+            None => bail!(
+                "{} has an unknown column '{}'",
+                association.display(),
+                header
+            ),
+        }
+    }
+    let table = Table {
+        name: "statistics.csv",
+        description: "The association tests of `strif test`, one row per test",
+        columns,
+    };
+
+    let mut writer = start_table(archive, &table)?;
+    for result in reader.records() {
+        let record = result?;
+        writer.write_record(record.iter().map(|value| {
+            if MISSING.contains(&value) {
+                ""
+            } else {
+                value
+            }
+        }))?;
+    }
+    writer.flush()?;
+    Ok(table)
+}
+
+/// Writes the data dictionary describing the files and columns of the export
+fn write_dictionary<W: Write + Seek>(archive: &mut ZipWriter<W>, tables: &[Table]) -> Result<()> {
+    let dictionary = Table {
+        name: "data_dictionary.csv",
+        description: "The files of the export and their columns",
+        columns: vec![
+            ("file", "The name of the file"),
+            (
+                "column",
+                "The name of the column, empty for the file itself",
+            ),
+            ("description", "The description of the file or column"),
+        ],
+    };
+    let mut writer = start_table(archive, &dictionary)?;
+    for table in tables.iter().chain([&dictionary]) {
+        writer.write_record([table.name, "", table.description])?;
+        for (column, description) in &table.columns {
+            writer.write_record([table.name, column, description])?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Exports a merged profile as a zip of flat CSVs that open as they are in
+/// Excel: a summary of each locus, the read count and burden of each sample at
+/// each locus, the normalized count of each interruption of each sample at
+/// each locus, with association results the tests of `test`, and a data
+/// dictionary describing every column. With a manifest, the case-control
+/// status of samples is added. The archive is built in memory, compressed.
+pub fn export(
+    input: PathBuf,
+    out_path: PathBuf,
+    manifest: Option<PathBuf>,
+    association: Option<PathBuf>,
+) -> Result<()> {
+    let statuses: Option<HashMap<String, String>> = match &manifest {
+        Some(manifest) => Some(
+            load_manifest(manifest)?
+                .into_iter()
+                .map(|entry| (entry.sample_id, entry.status))
+                .collect(),
+        ),
+        None => None,
+    };
+    let groups: Option<HashMap<String, usize>> = statuses.as_ref().map(|statuses| {
+        statuses
+            .iter()
+            .filter_map(|(sample_id, status)| {
+                let status = status.to_lowercase();
+                let group = GROUPS.iter().position(|group| *group == status)?;
+                Some((sample_id.clone(), group))
+            })
+            .collect()
+    });
+
+    info!("Reading {}...", input.display());
+    let records = read_merged_profile_any(&input)?;
+
+    let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+    let mut tables = vec![write_loci(&mut archive, &records, groups.as_ref())?];
+    tables.extend(write_counts(&mut archive, &records, statuses.as_ref())?);
+    if let Some(association) = &association {
+        info!("Reading {}...", association.display());
+        tables.push(write_statistics(&mut archive, association)?);
+    }
+    write_dictionary(&mut archive, &tables)?;
+    let archive = archive.finish()?.into_inner();
+
+    let mut out_file = create_output(&out_path)?;
+    out_file.write_all(&archive)?;
+    out_file.finish()?;
+
+    info!(
+        "Exported {} loci and {} files to {}",
+        records.len(),
+        tables.len() + 1,
+        out_path.display()
+    );
+    Ok(())
+}
+
+/// The command line arguments of `strif export`
+#[derive(Debug, clap::Args)]
+pub struct ExportArgs {
+    /// The path to the merged profile, in any format supported by `convert`
+    pub input: PathBuf,
+
+    /// The path to the zip archive. Defaults to the same directory as the input.
+    pub output: Option<PathBuf>,
+
+    /// The path to the manifest, used to add the case-control status of samples
+    #[clap(short = 'm', long)]
+    pub manifest: Option<PathBuf>,
+
+    /// The path to the output of `test`, exported as the statistics
+    #[clap(short = 'a', long)]
+    pub association: Option<PathBuf>,
+}
+
+impl ExportArgs {
+    /// Runs `strif export`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let ExportArgs {
+            input,
+            output,
+            manifest,
+            association,
+        } = self;
+        let out_path: PathBuf =
+            output.unwrap_or_else(|| get_default_out_path(&input, "export", "zip"));
+        Plan::new("export", dry_run, force)
+            .records("input", &input, "loci")
+            .maybe_input("manifest", manifest.as_deref())
+            .maybe_input("association results", association.as_deref())
+            .output("archive", &out_path)
+            .execute(|| export(input, out_path, manifest, association))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::testing::{merged_record, write_merged_profile_file};
+
+    /// The rows of a CSV of an archive, without its byte order mark
+    fn read_table(archive: &Path, name: &str) -> Vec<String> {
+        let mut archive = zip::ZipArchive::new(fs::File::open(archive).unwrap()).unwrap();
+        let mut contents = Vec::new();
+        archive
+            .by_name(name)
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        assert!(contents.starts_with(BOM));
+        String::from_utf8(contents[BOM.len()..].to_vec())
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn merged_profiles_are_exported_as_flat_csvs() {
+        let dir = tempfile::tempdir().unwrap();
+        let records = [
+            merged_record(
+                "HTT",
+                &[("s1", 10), ("s2", 20)],
+                &[("s1", "T", 2.0), ("s1", "A", 1.0), ("s2", "T", 0.5)],
+            ),
+            merged_record("FMR1", &[("s2", 5)], &[]),
+        ];
+        let input = write_merged_profile_file(dir.path(), "merged.tsv", &records);
+        let manifest = dir.path().join("manifest.tsv");
+        fs::write(&manifest, "s1\tcase\ts1.tsv\ns2\tcontrol\ts2.tsv\n").unwrap();
+        let columns = OutputKind::Association.columns().unwrap();
+        let association = dir.path().join("association.tsv");
+        let row: Vec<&str> = columns
+            .iter()
+            .map(|column| match *column {
+                "locus_id" => "HTT",
+                "p_value" => "0.01",
+                _ => ".",
+            })
+            .collect();
+        let mut out = Vec::new();
+        schema::write_header(&mut out, OutputKind::Association).unwrap();
+        writeln!(out, "{}", row.join("\t")).unwrap();
+        fs::write(&association, out).unwrap();
+        let out = dir.path().join("export.zip");
+        export(input, out.clone(), Some(manifest), Some(association)).unwrap();
+
+        // FMR1 has no reads of cases, so its case mean burden is empty
+        assert_eq!(
+            read_table(&out, "loci.csv")[1..],
+            [
+                "HTT,chr1,100,200,chr1:100-200,CAG,2,30,2,1.75,3,0.5",
+                "FMR1,chr1,100,200,chr1:100-200,CAG,1,5,0,0,,0",
+            ]
+        );
+        assert_eq!(
+            read_table(&out, "sample_counts.csv"),
+            [
+                "locus_id,sample_id,status,read_count,burden",
+                "HTT,s1,case,10,3",
+                "HTT,s2,control,20,0.5",
+                "FMR1,s2,control,5,0",
+            ]
+        );
+        assert_eq!(
+            read_table(&out, "interruption_counts.csv")[1..],
+            ["HTT,s1,case,T,2", "HTT,s1,case,A,1", "HTT,s2,control,T,0.5"]
+        );
+        // missing values of the association results are left empty
+        assert_eq!(
+            read_table(&out, "statistics.csv")[1],
+            "HTT,,,,,,,,,0.01,,,,,,"
+        );
+
+        // every column of every file is described in the data dictionary
+        let dictionary = read_table(&out, "data_dictionary.csv");
+        for name in [
+            "loci.csv",
+            "sample_counts.csv",
+            "interruption_counts.csv",
+            "statistics.csv",
+            "data_dictionary.csv",
+        ] {
+            for column in read_table(&out, name)[0].split(',') {
+                let prefix = format!("{},{},", name, column);
+                assert!(dictionary.iter().any(|row| row.starts_with(&prefix)));
+            }
+        }
+    }
+}
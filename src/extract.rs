@@ -1,74 +1,1219 @@
 use lazy_static::lazy_static;
-use std::{
-    fs::File,
-    path::{Path, PathBuf},
-};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::num::NonZeroU32;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use anyhow::{Ok, Result};
-use log::{info, warn};
+use anyhow::{bail, Context, Ok, Result};
+use log::{debug, info, warn};
 use regex::Regex;
-use rust_htslib::{
-    bam,
-    bam::{record::Aux, Read},
-};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::io::prelude::*;
 
-pub fn extract(bamlet: PathBuf, out_path: PathBuf) -> Result<()> {
+use crate::catalog::Catalog;
+use crate::config::load_options;
+use crate::error::{parse_field, StrifError};
+use crate::plan::Plan;
+use crate::schema::{self, OutputKind};
+use crate::utils::{
+    builder_methods, create_output, get_default_out_path, OutputFile, Region, Shard,
+};
+
+/// The options of `extract`: the optional columns written, how repeat
+/// sequences are trimmed and where skipped reads go. Its fields are the
+/// options of the command line, and it can be built with its builder methods,
+/// e.g. `ExtractOptions::default().qualities(true).rejects("rejects.tsv")`,
+/// or loaded from the `[extract]` table of a configuration file with
+/// [`ExtractOptions::from_config`].
+#[derive(Debug, Clone, Default, PartialEq, clap::Args, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ExtractOptions {
+    /// Also write the base qualities of each repeat sequence, for the
+    /// qualities of interruptions in the per-read output of `profile`
+    #[clap(long)]
+    pub qualities: bool,
+
+    /// Also write the anchoring scores of the left and right flanks of each
+    /// read, to identify poorly anchored reads with unreliable repeat boundaries
+    #[clap(long)]
+    pub anchoring: bool,
+
+    /// Trim the tails of each read whose base qualities fall below this Phred
+    /// quality off its repeat sequence, so that degraded read ends do not show up
+    /// as clusters of interruptions
+    #[clap(long)]
+    pub trim_tail_quality: Option<u8>,
+
+    /// Fuse the two mates of a pair that each cover one flank and part of the repeat
+    /// into a single repeat sequence where they overlap, resolving conflicting bases
+    /// by their qualities, rather than skipping them
+    #[clap(long)]
+    pub fuse_mates: bool,
+
+    /// Write the name of each read's fragment, without a `/1` or `/2` suffix, in a
+    /// seventh column, for `profile --count-unit fragment`
+    #[clap(long)]
+    pub read_names: bool,
+
+    /// Write the contig and 1-based position each read is aligned to in the BAMlet in
+    /// an eighth and ninth column, and with --catalog the ReferenceRegion of its locus in
+    /// a tenth, to check that reads were assigned to the expected locus
+    #[clap(long)]
+    pub coordinates: bool,
+
+    /// The path to the JSON catalog of repeat loci ExpansionHunter was run with, whose
+    /// ReferenceRegion of each locus --coordinates writes
+    #[clap(short = 'c', long, requires = "coordinates")]
+    pub catalog: Option<PathBuf>,
+
+    /// With --shard-by-locus, write this many files each holding the loci of
+    /// the matching `merge --shard` rather than a file per locus
+    #[clap(long, requires = "shard_by_locus")]
+    pub buckets: Option<NonZeroU32>,
+
+    /// Write the reads that were skipped to this file, with the reason for each (missing_xg,
+    /// xg_not_string, xg_mismatch, trimmed_away or unfused_mate). Reads without an XG tag are then skipped
+    /// instead of failing.
+    #[clap(long)]
+    pub rejects: Option<PathBuf>,
+
+    /// Replace the read names written by --read-names and --rejects with hashes of the names
+    /// salted with this secret, so that the outputs can be shared without the sequencing
+    /// identifiers. Runs hashed with the same salt give a read the same name.
+    #[clap(long, value_name = "SALT")]
+    pub anonymize_reads: Option<String>,
+
+    /// An indexed reference FASTA to decode CRAM BAMlets with, which otherwise need the
+    /// reference they were compressed against to be found through REF_PATH (htslib) or to
+    /// be embedded in them
+    #[clap(long)]
+    pub reference: Option<PathBuf>,
+}
+
+impl ExtractOptions {
+    builder_methods! {
+        qualities: bool,
+        anchoring: bool,
+        trim_tail_quality: Option<u8>,
+        fuse_mates: bool,
+        read_names: bool,
+        coordinates: bool,
+        catalog: Option<PathBuf>,
+        buckets: Option<NonZeroU32>,
+        rejects: Option<PathBuf>,
+        anonymize_reads: Option<String>,
+        reference: Option<PathBuf>,
+    }
+
+    /// Loads the options from the `[extract]` table of a configuration file
+    pub fn from_config(path: &Path) -> Result<Self> {
+        load_options(path, "extract")
+    }
+
+    /// A read name as written to the outputs, hashed with `anonymize_reads`
+    fn written_name(&self, read_name: &str) -> String {
+        match &self.anonymize_reads {
+            Some(salt) => anonymize_read_name(salt, read_name),
+            None => read_name.to_string(),
+        }
+    }
+}
+
+/// The salted hash standing in for a read name: the first 16 hex digits of
+/// the SHA-256 of the salt and the name. It is stable across runs and
+/// platforms, so the mates and lanes of a fragment hashed with the same salt
+/// still share a name.
+pub fn anonymize_read_name(salt: &str, read_name: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update([0]);
+    hasher.update(read_name.as_bytes());
+    hasher.finalize()[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// The path the repeat sequences of a BAMlet are written to if no output is
+/// given: a file, or a directory of shards, next to the BAMlet
+pub fn default_out_path(bamlet: &Path, shard_by_locus: bool) -> PathBuf {
+    let out_path = get_default_out_path(bamlet, "repeat_seqs", "tsv");
+    match shard_by_locus {
+        true => out_path.with_extension(""),
+        false => out_path,
+    }
+}
+
+/// Writes the repeat sequences of a BAMlet's reads. With `coordinates` and
+/// a catalog, the reference region of the locus of each read is written
+/// next to its position.
+pub fn extract(bamlet: PathBuf, out_path: PathBuf, options: &ExtractOptions) -> Result<()> {
+    let mut regions = LocusRegions::load(options.catalog.as_deref())?;
     info!("Extracting repeat sequences from BAMlet...");
-    let mut out_file: File = File::create(out_path)?;
-    extract_repeat_seqs(&bamlet, &mut out_file)?;
+    let mut out_file = create_output(&out_path)?;
+    let mut rejects = open_rejects(options.rejects.as_deref())?;
+    schema::write_header(&mut out_file, OutputKind::RepeatSeqs)?;
+    let reads = for_each_repeat_seq(
+        &bamlet,
+        options,
+        None,
+        rejects.as_mut().map(|file| file as &mut dyn Write),
+        |mut record, _| {
+            if let Some(regions) = regions.as_mut() {
+                regions.annotate(&mut record);
+            }
+            record.write_to(&mut out_file)
+        },
+    )?;
+    out_file.finish()?;
+    if let Some(rejects) = rejects {
+        rejects.finish()?;
+    }
+    if let Some(regions) = regions {
+        regions.report();
+    }
+    if reads == 0 {
+        return Err(StrifError::EmptyOutput {
+            path: out_path.display().to_string(),
+            message: "no reads of the BAMlet have a repeat".to_string(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Counts the reads of a BAMlet and checks that each can be decoded
+pub fn count_bamlet_reads(bamlet: &Path) -> Result<usize> {
+    let mut reads = 0;
+    for record in backend::read_bamlet(bamlet, None, None)? {
+        record?;
+        reads += 1;
+    }
+    Ok(reads)
+}
+
+/// Writes the repeat sequences of a BAMlet's reads, returning how many were written.
+/// With `qualities`, the base qualities of each repeat sequence are written in
+/// a third column, Phred+33 encoded as in FASTQ, or `*` if the read has none.
+/// With `anchoring`, the anchoring scores of the left and right flanks are
+/// written in the fifth and sixth columns. Reads without a repeat sequence
+/// are written to `rejects` with the reason, if given.
+pub fn extract_repeat_seqs<W: Write>(
+    bamlet: &Path,
+    out_file: &mut W,
+    options: &ExtractOptions,
+    rejects: Option<&mut dyn Write>,
+) -> Result<usize> {
+    schema::write_header(out_file, OutputKind::RepeatSeqs)?;
+    for_each_repeat_seq(bamlet, options, None, rejects, |record, _| {
+        record.write_to(out_file)
+    })
+}
+
+/// Creates the file of rejected reads of `--rejects`, if given
+fn open_rejects(path: Option<&Path>) -> Result<Option<OutputFile>> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    let mut file = create_output(path)?;
+    schema::write_header(&mut file, OutputKind::RejectedReads)?;
+    Ok(Some(file))
+}
+
+/// Reads mapped further than this from the reference region of their locus
+/// are reported, as they may have been assigned to the wrong locus
+const MAX_LOCUS_DISTANCE: u64 = 1000;
+
+/// The reference regions of the loci of a catalog, written next to the
+/// position of each read with `--coordinates`
+struct LocusRegions {
+    regions: HashMap<String, (String, Option<Region>)>,
+    /// The reads mapped far from the reference region of their locus
+    far: usize,
+    /// The reads at loci that are not in the catalog
+    unknown: usize,
+}
+
+impl LocusRegions {
+    /// Loads the reference regions of the loci of a catalog, if given
+    fn load(str_catalog: Option<&Path>) -> Result<Option<Self>> {
+        let Some(str_catalog) = str_catalog else {
+            return Ok(None);
+        };
+        info!("Loading the reference regions of the catalog...");
+        let regions = Catalog::from_path(str_catalog)?
+            .loci
+            .into_iter()
+            .map(|locus| {
+                let region = locus.reference_region.parse::<Region>().ok();
+                (locus.locus_id, (locus.reference_region, region))
+            })
+            .collect();
+        Ok(Some(Self {
+            regions,
+            far: 0,
+            unknown: 0,
+        }))
+    }
+
+    /// Writes the reference region of the locus of a read next to its
+    /// position, tallying the reads mapped far from it
+    fn annotate(&mut self, record: &mut RepeatSeqRecord) {
+        let Some(coordinates) = record.coordinates.as_mut() else {
+            return;
+        };
+        let Some((reference_region, region)) = self.regions.get(&record.locus_id) else {
+            self.unknown += 1;
+            return;
+        };
+        coordinates.reference_region = Some(reference_region.clone());
+        if region
+            .as_ref()
+            .is_some_and(|region| coordinates.is_far_from(region, MAX_LOCUS_DISTANCE))
+        {
+            debug!(
+                "A read of {} is mapped to {}:{}, away from {}",
+                record.locus_id, coordinates.chrom, coordinates.pos, reference_region
+            );
+            self.far += 1;
+        }
+    }
+
+    fn report(&self) {
+        if self.unknown > 0 {
+            warn!(
+                unknown_locus_reads = self.unknown;
+                "{} reads are at loci that are not in the catalog, pass the catalog ExpansionHunter was run with",
+                self.unknown
+            );
+        }
+        if self.far > 0 {
+            warn!(
+                far_from_locus_reads = self.far;
+                "{} reads are mapped more than {} bp from the reference region of their locus, check that they were assigned to the expected locus",
+                self.far,
+                MAX_LOCUS_DISTANCE
+            );
+        }
+    }
+}
+
+/// Why `extract` skipped a read, written to the file of rejected reads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RejectReason {
+    /// The read has no XG tag with its graph alignment
+    MissingXg,
+    /// The XG tag of the read is not a string
+    XgNotString,
+    /// The XG tag is not the alignment of a simple repeat locus
+    XgMismatch,
+    /// `--trim-tail-quality` trimmed away the whole repeat sequence
+    TrimmedAway,
+    /// With `--fuse-mates`, the read covers a single flank and could not be
+    /// fused with its mate
+    UnfusedMate,
+}
+
+impl RejectReason {
+    fn code(&self) -> &'static str {
+        match self {
+            RejectReason::MissingXg => "missing_xg",
+            RejectReason::XgNotString => "xg_not_string",
+            RejectReason::XgMismatch => "xg_mismatch",
+            RejectReason::TrimmedAway => "trimmed_away",
+            RejectReason::UnfusedMate => "unfused_mate",
+        }
+    }
+}
+
+/// Writes the repeat sequences of a BAMlet into a file per locus, or per
+/// bucket of loci, in a directory. Buckets are the shards of `merge --shard`,
+/// so `shard2of8.repeat_seqs.tsv` holds the loci of `--shard 2/8`. Shards of
+/// an earlier extraction in the directory are only replaced with `force`.
+pub fn extract_shards(
+    bamlet: PathBuf,
+    out_dir: PathBuf,
+    options: &ExtractOptions,
+    force: bool,
+) -> Result<()> {
+    let buckets = options.buckets;
+    fs::create_dir_all(&out_dir)
+        .with_context(|| format!("Could not create {}", out_dir.display()))?;
+    // shards of an earlier extraction would be mistaken for loci of this one
+    let stale_shards = shard_paths(&out_dir)?;
+    if !stale_shards.is_empty() {
+        if !force {
+            bail!(
+                "{} already has {} repeat sequence shards, pass --force to replace them",
+                out_dir.display(),
+                stale_shards.len()
+            );
+        }
+        warn!(
+            "Removing {} repeat sequence shards from {}",
+            stale_shards.len(),
+            out_dir.display()
+        );
+        for path in stale_shards {
+            fs::remove_file(&path)
+                .with_context(|| format!("Could not remove {}", path.display()))?;
+        }
+    }
+
+    let mut regions = LocusRegions::load(options.catalog.as_deref())?;
+    info!("Extracting repeat sequences from BAMlet...");
+    // the records are grouped in memory, as a file per locus could exceed the
+    // limit on open files
+    let mut shards: BTreeMap<String, Vec<RepeatSeqRecord>> = BTreeMap::new();
+    // every bucket is written, even without reads, so that each job of an
+    // array over the buckets has an input
+    if let Some(buckets) = buckets {
+        for index in 1..=buckets.get() {
+            shards.insert(bucket_name(index, buckets.get()), Vec::new());
+        }
+    }
+    let mut rejects = open_rejects(options.rejects.as_deref())?;
+    let rejects_out = rejects.as_mut().map(|file| file as &mut dyn Write);
+    let reads = for_each_repeat_seq(&bamlet, options, None, rejects_out, |mut record, _| {
+        if let Some(regions) = regions.as_mut() {
+            regions.annotate(&mut record);
+        }
+        let shard = match buckets {
+            Some(buckets) => {
+                let shard = Shard::of(&record.locus_id, buckets.get());
+                bucket_name(shard.index, shard.count)
+            }
+            None => record.locus_id.clone(),
+        };
+        shards.entry(shard).or_default().push(record);
+        Ok(())
+    })?;
+    if let Some(rejects) = rejects {
+        rejects.finish()?;
+    }
+    if let Some(regions) = regions {
+        regions.report();
+    }
+    if reads == 0 {
+        return Err(StrifError::EmptyOutput {
+            path: out_dir.display().to_string(),
+            message: "no reads of the BAMlet have a repeat".to_string(),
+        }
+        .into());
+    }
+
+    info!(shards = shards.len(); "Writing {} shards to {}...", shards.len(), out_dir.display());
+    for (shard, records) in shards {
+        let mut out_file = create_output(&out_dir.join(format!("{}{}", shard, SHARD_SUFFIX)))?;
+        schema::write_header(&mut out_file, OutputKind::RepeatSeqs)?;
+        for record in records {
+            record.write_to(&mut out_file)?;
+        }
+        out_file.finish()?;
+    }
     Ok(())
 }
 
-pub fn extract_repeat_seqs(bamlet: &Path, out_file: &mut File) -> Result<()> {
+fn bucket_name(index: u32, count: u32) -> String {
+    format!("shard{}of{}", index, count)
+}
+
+/// The file name suffix of the shards written by `extract --shard-by-locus`
+pub const SHARD_SUFFIX: &str = ".repeat_seqs.tsv";
+
+/// The repeat sequence shards in a directory, in order of their names
+pub fn shard_paths(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("Could not read {}", dir.display()))? {
+        let path = entry?.path();
+        let is_shard = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.ends_with(SHARD_SUFFIX) && !name.starts_with('.'));
+        if is_shard && path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Calls a function with the repeat sequence of each of a BAMlet's reads and,
+/// if a UMI tag is given, the read's UMI, returning how many reads there were.
+/// Skipped reads are written to `rejects` with the reason, if given, in which
+/// case reads without an XG tag are skipped too rather than failing. With
+/// `fuse_mates`, the two mates of a pair that each cover a single flank give
+/// a single repeat sequence, counted as one read.
+pub(crate) fn for_each_repeat_seq<F>(
+    bamlet: &Path,
+    options: &ExtractOptions,
+    umi_tag: Option<[u8; 2]>,
+    mut rejects: Option<&mut dyn Write>,
+    mut f: F,
+) -> Result<usize>
+where
+    F: FnMut(RepeatSeqRecord, Option<&str>) -> Result<()>,
+{
     // the node id of the right flank of the repeat locus (simple repeats are 2)
     let right_flank_node_id = 2;
+    let cigar = r"(?:\d+[MIDNSHPX=])+";
 
     // captures the auxiliary tag for the repeat locus id and the cigar strings
-    // for the left flank and repeat
+    // for the left flank, repeat and right flank
     let formatted_regex: String = format!(
-        r"^(?P<locus_id>\w+),\d+,0\[(?P<flank>(?:\d+[MIDNSHPX=])+)\](?P<repeat>(?:\d+\[(?:\d+[MIDNSHPX=])+\])+){}\[(?:\d+[MIDNSHPX=])+\]$",
+        r"^(?P<locus_id>\w+),\d+,0\[(?P<flank>{cigar})\](?P<repeat>(?:\d+\[{cigar}\])+){}\[(?P<right_flank>{cigar})\]$",
         right_flank_node_id
     );
     let re_parse_tag: Regex = Regex::new(&formatted_regex).unwrap();
+    // the tags of reads that end or start within the repeat, which only
+    // cover its left or right flank
+    let re_parse_left_tag: Regex = Regex::new(&format!(
+        r"^(?P<locus_id>\w+),\d+,0\[(?P<flank>{cigar})\](?P<repeat>(?:1\[{cigar}\])+)$"
+    ))
+    .unwrap();
+    let re_parse_right_tag: Regex = Regex::new(&format!(
+        r"^(?P<locus_id>\w+),\d+,(?P<repeat>(?:1\[{cigar}\])+){}\[(?P<right_flank>{cigar})\]$",
+        right_flank_node_id
+    ))
+    .unwrap();
 
-    let mut bam = bam::Reader::from_path(bamlet).unwrap();
+    let bam = backend::read_bamlet(bamlet, options.reference.as_deref(), umi_tag)?;
 
-    for (i, record) in bam.records().enumerate() {
-        let record = record.unwrap();
-        let tag: Aux = record.aux(b"XG")?;
+    let mut reads: usize = 0;
+    let mut skipped: usize = 0;
+    // reads whose repeat sequence was shortened, or entirely trimmed away,
+    // by --trim-tail-quality
+    let (mut trimmed, mut trimmed_away): (usize, usize) = (0, 0);
+    let mut rejected: usize = 0;
+    // reads covering a single flank waiting for their mate, by read name
+    let mut pending: HashMap<String, PendingMate> = HashMap::new();
+    let (mut fused, mut unfused): (usize, usize) = (0, 0);
+    // reads without an XG tag are only skipped if they can be audited
+    let skip_missing = rejects.is_some();
+    let mut reject = |i: usize, read_name: &str, reason: RejectReason, locus_id: Option<&str>| {
+        if let Some(rejects) = rejects.as_mut() {
+            rejected += 1;
+            writeln!(
+                rejects,
+                "{}\t{}\t{}\t{}",
+                i + 1,
+                options.written_name(read_name),
+                reason.code(),
+                locus_id.unwrap_or(".")
+            )?;
+        }
+        Ok(())
+    };
+    for (i, record) in bam.enumerate() {
+        let record = record.map_err(|e| StrifError::Alignment {
+            path: bamlet.display().to_string(),
+            read_name: format!("record {}", i + 1),
+            message: format!("{:#}", e),
+        })?;
+        let alignment_error = |message: String| StrifError::Alignment {
+            path: bamlet.display().to_string(),
+            read_name: record.read_name.clone(),
+            message,
+        };
+        let tag_str: &str = match &record.graph_alignment {
+            GraphAlignment::Missing(_) if skip_missing => {
+                reject(i, &record.read_name, RejectReason::MissingXg, None)?;
+                skipped += 1;
+                continue;
+            }
+            GraphAlignment::Missing(e) => {
+                return Err(alignment_error(format!("no XG tag ({})", e)).into())
+            }
+            GraphAlignment::NotString => {
+                warn!(read = i; "Auxiliary tag for read {} is not a string, skipping...", i);
+                reject(i, &record.read_name, RejectReason::XgNotString, None)?;
+                skipped += 1;
+                continue;
+            }
+            GraphAlignment::Tag(tag_str) => tag_str,
+        };
 
-        let tag_str = if let Aux::String(tag_str) = tag {
-            tag_str
+        // the side of the repeat covered by a read with a single flank
+        let (parsed_tag, side) = if let Some(parsed_tag) = re_parse_tag.captures(tag_str) {
+            (parsed_tag, None)
+        } else if let Some(parsed_tag) = re_parse_left_tag
+            .captures(tag_str)
+            .filter(|_| options.fuse_mates)
+        {
+            (parsed_tag, Some(MateSide::Left))
+        } else if let Some(parsed_tag) = re_parse_right_tag
+            .captures(tag_str)
+            .filter(|_| options.fuse_mates)
+        {
+            (parsed_tag, Some(MateSide::Right))
         } else {
-            warn!("Auxiliary tag for read {} is not a string, skipping...", i);
+            reject(i, &record.read_name, RejectReason::XgMismatch, None)?;
+            skipped += 1;
             continue;
         };
 
-        let parsed_tag = {
-            if let Some(parsed_tag) = re_parse_tag.captures(tag_str) {
-                parsed_tag
+        let locus_id: &str = &parsed_tag["locus_id"];
+        let left_flank_cigar = parsed_tag.name("flank").map(|m| m.as_str());
+        let repeat_cigar = &parsed_tag["repeat"];
+
+        // start is equal to the sum of the operation counts in the left flank
+        let repeat_start = left_flank_cigar.map_or(0, sum_operation_counts) as usize;
+        // stop is equal to the start plus the sum of operation counts in the repeat
+        let repeat_stop = repeat_start + sum_operation_counts(repeat_cigar) as usize;
+
+        let seq_raw = &record.seq;
+        let mut repeat_seq = seq_raw.get(repeat_start..repeat_stop).ok_or_else(|| {
+            alignment_error(format!(
+                "the repeat at {}..{} of the XG tag extends past the read of length {}",
+                repeat_start,
+                repeat_stop,
+                seq_raw.len()
+            ))
+        })?;
+        // the repeat keeps its boundaries unless a low-quality tail of the
+        // read reaches into it
+        let (mut repeat_start, mut repeat_stop) = (repeat_start, repeat_stop);
+        if let Some(min_quality) = options.trim_tail_quality {
+            if record.qual.len() == seq_raw.len() {
+                let kept = trim_low_quality_tails(&record.qual, min_quality);
+                let (start, stop) = (repeat_start.max(kept.start), repeat_stop.min(kept.end));
+                if start >= stop {
+                    reject(
+                        i,
+                        &record.read_name,
+                        RejectReason::TrimmedAway,
+                        Some(locus_id),
+                    )?;
+                    trimmed_away += 1;
+                    continue;
+                }
+                if (start, stop) != (repeat_start, repeat_stop) {
+                    trimmed += 1;
+                    (repeat_start, repeat_stop) = (start, stop);
+                    repeat_seq = &seq_raw[start..stop];
+                }
+            }
+        }
+
+        if let Some(side) = side {
+            let flank_cigar = match side {
+                MateSide::Left => left_flank_cigar.unwrap_or_default(),
+                MateSide::Right => &parsed_tag["right_flank"],
+            };
+            let mate = PendingMate {
+                index: i,
+                read_name: record.read_name.clone(),
+                locus_id: locus_id.to_string(),
+                side,
+                seq: repeat_seq.to_vec(),
+                qual: record
+                    .qual
+                    .get(repeat_start..repeat_stop)
+                    .unwrap_or_default()
+                    .to_vec(),
+                anchor_score: anchoring_score(flank_cigar),
+                umi: record.umi.clone(),
+                position: record.position.clone(),
+            };
+            let Some(first) = pending.remove(&record.read_name) else {
+                pending.insert(record.read_name.clone(), mate);
+                continue;
+            };
+            let (left, right) = match (first.side, mate.side) {
+                (MateSide::Left, MateSide::Right) => (first, mate),
+                (MateSide::Right, MateSide::Left) => (mate, first),
+                _ => (first, mate),
+            };
+            let fused_seq = if left.locus_id == right.locus_id && left.side != right.side {
+                fuse_mates(&left, &right)
             } else {
+                None
+            };
+            let Some((seq, qual)) = fused_seq else {
+                for mate in [&left, &right] {
+                    reject(
+                        mate.index,
+                        &mate.read_name,
+                        RejectReason::UnfusedMate,
+                        Some(&mate.locus_id),
+                    )?;
+                }
+                unfused += 2;
                 continue;
+            };
+            let qualities = options.qualities.then(|| {
+                if qual.len() == seq.len() {
+                    encode_qualities(&qual)
+                } else {
+                    MISSING_QUALITIES.to_string()
+                }
+            });
+            let anchoring = options.anchoring.then_some(Anchoring {
+                left: left.anchor_score,
+                right: right.anchor_score,
+            });
+            f(
+                RepeatSeqRecord {
+                    locus_id: left.locus_id.clone(),
+                    seq: String::from_utf8_lossy(&seq).into_owned(),
+                    qualities,
+                    multiplicity: None,
+                    anchoring,
+                    read_name: options
+                        .read_names
+                        .then(|| options.written_name(&fragment_name(&left.read_name))),
+                    // the fused sequence starts with the left mate
+                    coordinates: options
+                        .coordinates
+                        .then(|| ReadCoordinates::of(left.position.as_ref())),
+                },
+                left.umi.as_deref(),
+            )?;
+            fused += 1;
+            reads += 1;
+            continue;
+        }
+
+        let repeat_qual =
+            options
+                .qualities
+                .then(|| match record.qual.get(repeat_start..repeat_stop) {
+                    Some(qual) => encode_qualities(qual),
+                    None => MISSING_QUALITIES.to_string(),
+                });
+        let anchoring = options.anchoring.then(|| Anchoring {
+            left: anchoring_score(left_flank_cigar.unwrap_or_default()),
+            right: anchoring_score(&parsed_tag["right_flank"]),
+        });
+        f(
+            RepeatSeqRecord {
+                locus_id: locus_id.to_string(),
+                seq: String::from_utf8_lossy(repeat_seq).into_owned(),
+                qualities: repeat_qual,
+                multiplicity: None,
+                anchoring,
+                read_name: options
+                    .read_names
+                    .then(|| options.written_name(&fragment_name(&record.read_name))),
+                coordinates: options
+                    .coordinates
+                    .then(|| ReadCoordinates::of(record.position.as_ref())),
+            },
+            record.umi.as_deref(),
+        )?;
+        reads += 1;
+    }
+    // reads whose mate never came, e.g. because it has no repeat
+    let mut pending: Vec<PendingMate> = pending.into_values().collect();
+    pending.sort_unstable_by_key(|mate| mate.index);
+    for mate in &pending {
+        reject(
+            mate.index,
+            &mate.read_name,
+            RejectReason::UnfusedMate,
+            Some(&mate.locus_id),
+        )?;
+    }
+    unfused += pending.len();
+    info!(reads, skipped; "Extracted {} repeat sequences, skipped {} reads", reads, skipped);
+    if let Some(min_quality) = options.trim_tail_quality {
+        info!(
+            trimmed, trimmed_away;
+            "Trimmed tails below quality {} off {} repeat sequences, dropped {} reads whose repeat was trimmed away",
+            min_quality, trimmed, trimmed_away
+        );
+    }
+    if options.fuse_mates {
+        info!(
+            fused_pairs = fused, unfused_mates = unfused;
+            "Fused {} mate pairs into single repeat sequences, dropped {} reads covering a single flank whose mate was missing or did not overlap them unambiguously",
+            fused, unfused
+        );
+    }
+    if rejected > 0 {
+        info!(rejected; "Wrote {} rejected reads with the reason of each", rejected);
+    }
+
+    Ok(reads)
+}
+
+/// The name of the fragment of a read, which is its name without the `/1` or
+/// `/2` suffix some aligners keep on the names of mates
+pub(crate) fn fragment_name(read_name: &str) -> String {
+    read_name
+        .strip_suffix("/1")
+        .or_else(|| read_name.strip_suffix("/2"))
+        .unwrap_or(read_name)
+        .to_string()
+}
+
+/// The flank of a repeat locus covered by a read that ends or starts within
+/// the repeat
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MateSide {
+    Left,
+    Right,
+}
+
+/// A read covering a single flank, kept until its mate is read to fuse them
+struct PendingMate {
+    /// The number of the read in the BAMlet, from 0
+    index: usize,
+    read_name: String,
+    locus_id: String,
+    side: MateSide,
+    /// The part of the read within the repeat and its base qualities, which
+    /// are empty if the read has none
+    seq: Vec<u8>,
+    qual: Vec<u8>,
+    /// The anchoring score of the flank the read covers
+    anchor_score: i32,
+    umi: Option<String>,
+    position: Option<(Arc<str>, u64)>,
+}
+
+/// The fewest bases by which the repeat sequences of two mates must overlap
+/// to be fused
+const MIN_MATE_OVERLAP: usize = 10;
+
+/// Fuses the repeat sequences of the mates covering the left and right flank
+/// into one, where the end of the left mate's overlaps the start of the right
+/// mate's with the fewest mismatches, at most one in ten bases. Where the
+/// mates disagree, the base with the higher quality is kept with the
+/// difference of their qualities, or the left mate's base without qualities.
+/// Returns None if the mates do not overlap, or overlap equally well at
+/// several offsets as within a pure repeat, where the length of the repeat
+/// between them is unknown.
+fn fuse_mates(left: &PendingMate, right: &PendingMate) -> Option<(Vec<u8>, Vec<u8>)> {
+    let mut best: Option<(usize, usize)> = None;
+    let mut tied = false;
+    for overlap in MIN_MATE_OVERLAP..=left.seq.len().min(right.seq.len()) {
+        let mismatches = left.seq[left.seq.len() - overlap..]
+            .iter()
+            .zip(&right.seq[..overlap])
+            .filter(|(a, b)| !a.eq_ignore_ascii_case(b))
+            .count();
+        if mismatches * 10 > overlap {
+            continue;
+        }
+        match best {
+            Some((fewest, _)) if mismatches > fewest => {}
+            Some((fewest, _)) if mismatches == fewest => tied = true,
+            _ => {
+                best = Some((mismatches, overlap));
+                tied = false;
+            }
+        }
+    }
+    let (_, overlap) = best.filter(|_| !tied)?;
+
+    let with_qualities = left.qual.len() == left.seq.len() && right.qual.len() == right.seq.len();
+    let start = left.seq.len() - overlap;
+    let mut seq = left.seq[..start].to_vec();
+    let mut qual = Vec::new();
+    if with_qualities {
+        qual.extend_from_slice(&left.qual[..start]);
+    }
+    for (j, (&a, &b)) in left.seq[start..]
+        .iter()
+        .zip(&right.seq[..overlap])
+        .enumerate()
+    {
+        if !with_qualities {
+            seq.push(a);
+            continue;
+        }
+        let (qa, qb) = (left.qual[start + j], right.qual[j]);
+        if a.eq_ignore_ascii_case(&b) {
+            seq.push(a);
+            qual.push(qa.max(qb));
+        } else if qb > qa {
+            seq.push(b);
+            qual.push(qb - qa);
+        } else {
+            seq.push(a);
+            qual.push(qa - qb);
+        }
+    }
+    seq.extend_from_slice(&right.seq[overlap..]);
+    if with_qualities {
+        qual.extend_from_slice(&right.qual[overlap..]);
+    }
+    Some((seq, qual))
+}
+
+/// The fields of a BAMlet record used to extract its repeat sequence
+struct BamletRead {
+    read_name: String,
+    /// The contig and 1-based position of the read, if it is mapped
+    position: Option<(Arc<str>, u64)>,
+    /// The bases as ASCII characters
+    seq: Vec<u8>,
+    /// The Phred base qualities, or empty if the read has none
+    qual: Vec<u8>,
+    graph_alignment: GraphAlignment,
+    /// The UMI in the requested tag, if the read has one
+    umi: Option<String>,
+}
+
+/// The XG tag of a BAMlet record, holding its alignment to the locus graph
+#[cfg_attr(not(any(feature = "htslib", feature = "noodles")), allow(dead_code))]
+enum GraphAlignment {
+    /// The tag is missing, with the reason given by the reader
+    Missing(String),
+    NotString,
+    Tag(String),
+}
+
+type BamletReads = Box<dyn Iterator<Item = Result<BamletRead>>>;
+
+/// What the first reads of a BAMlet tell about it, for `doctor`
+pub(crate) struct BamletProbe {
+    pub reads: usize,
+    /// Reads with a string XG tag, i.e. realigned by ExpansionHunter
+    pub with_graph_alignment: usize,
+    pub with_qualities: usize,
+    /// The loci of the XG tags
+    pub loci: HashSet<String>,
+}
+
+/// Reads up to `max_reads` reads of a BAMlet and tallies their tags
+pub(crate) fn probe_bamlet(bamlet: &Path, max_reads: usize) -> Result<BamletProbe> {
+    let mut probe = BamletProbe {
+        reads: 0,
+        with_graph_alignment: 0,
+        with_qualities: 0,
+        loci: HashSet::new(),
+    };
+    for read in backend::read_bamlet(bamlet, None, None)?.take(max_reads) {
+        let read = read?;
+        probe.reads += 1;
+        if !read.qual.is_empty() {
+            probe.with_qualities += 1;
+        }
+        if let GraphAlignment::Tag(tag) = read.graph_alignment {
+            probe.with_graph_alignment += 1;
+            if let Some((locus_id, _)) = tag.split_once(',') {
+                probe.loci.insert(locus_id.to_string());
             }
+        }
+    }
+    Ok(probe)
+}
+
+/// Reads BAMlets with htslib, which also supports CRAM
+#[cfg(all(feature = "htslib", not(feature = "noodles")))]
+mod backend {
+    use std::path::Path;
+
+    use std::sync::Arc;
+
+    use anyhow::{Context, Result};
+    use rust_htslib::bam::{self, record::Aux, Read};
+
+    use super::{BamletRead, BamletReads, GraphAlignment};
+    use crate::threads::threads;
+
+    pub fn read_bamlet(
+        bamlet: &Path,
+        reference: Option<&Path>,
+        umi_tag: Option<[u8; 2]>,
+    ) -> Result<BamletReads> {
+        let mut bam = bam::Reader::from_path(bamlet)
+            .with_context(|| format!("Could not open the BAMlet {}", bamlet.display()))?;
+        if let Some(reference) = reference {
+            bam.set_reference(reference)?;
+        }
+        if threads() > 1 {
+            bam.set_threads(threads())?;
+        }
+        let contigs: Vec<Arc<str>> = bam
+            .header()
+            .target_names()
+            .into_iter()
+            .map(|name| Arc::from(String::from_utf8_lossy(name)))
+            .collect();
+        Ok(Box::new(
+            std::iter::from_fn(move || {
+                let mut record = bam::Record::new();
+                match bam.read(&mut record)? {
+                    Ok(()) => Some(Ok(record)),
+                    Err(e) => Some(Err(e)),
+                }
+            })
+            .map(move |record| {
+                let record = record?;
+                let graph_alignment = match record.aux(b"XG") {
+                    Ok(Aux::String(tag)) => GraphAlignment::Tag(tag.to_string()),
+                    Ok(_) => GraphAlignment::NotString,
+                    Err(e) => GraphAlignment::Missing(e.to_string()),
+                };
+                let umi = match umi_tag.map(|tag| record.aux(&tag)) {
+                    Some(Ok(Aux::String(umi))) => Some(umi.to_string()),
+                    _ => None,
+                };
+                // missing qualities are stored as 0xff
+                let qual = match record.qual().first() {
+                    Some(0xff) | None => Vec::new(),
+                    Some(_) => record.qual().to_vec(),
+                };
+                let position = usize::try_from(record.tid())
+                    .ok()
+                    .and_then(|tid| contigs.get(tid))
+                    .filter(|_| !record.is_unmapped() && record.pos() >= 0)
+                    .map(|contig| (contig.clone(), record.pos() as u64 + 1));
+                Ok(BamletRead {
+                    read_name: String::from_utf8_lossy(record.qname()).into_owned(),
+                    position,
+                    seq: record.seq().as_bytes(),
+                    qual,
+                    graph_alignment,
+                    umi,
+                })
+            }),
+        ))
+    }
+}
+
+/// Reads BAMlets and CRAM BAMlets in pure Rust with noodles, which needs no C
+/// toolchain. It is used instead of htslib when strif is built with the
+/// `noodles` feature.
+#[cfg(feature = "noodles")]
+mod backend {
+    use std::collections::VecDeque;
+    use std::fs::File;
+    use std::io::{self, Read};
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use anyhow::{Context, Result};
+    use noodles_bam as bam;
+    use noodles_cram as cram;
+    use noodles_fasta as fasta;
+    use noodles_sam::{
+        self as sam,
+        alignment::{
+            record::data::field::{Tag, Value},
+            Record,
+        },
+    };
+
+    use super::{BamletRead, BamletReads, GraphAlignment};
+
+    pub fn read_bamlet(
+        bamlet: &Path,
+        reference: Option<&Path>,
+        umi_tag: Option<[u8; 2]>,
+    ) -> Result<BamletReads> {
+        let open = || {
+            File::open(bamlet)
+                .with_context(|| format!("Could not open the BAMlet {}", bamlet.display()))
         };
+        let mut magic = [0u8; 4];
+        let is_cram = open()?.read_exact(&mut magic).is_ok() && &magic == b"CRAM";
+        if !is_cram {
+            let mut reader = bam::io::Reader::new(open()?);
+            let header = reader
+                .read_header()
+                .with_context(|| format!("Could not read the header of {}", bamlet.display()))?;
+            let contigs = contig_names(&header);
+            return Ok(Box::new(std::iter::from_fn(move || {
+                let mut record = bam::Record::default();
+                match reader.read_record(&mut record) {
+                    Ok(0) => None,
+                    Ok(_) => Some(bamlet_read(&record, &header, &contigs, umi_tag)),
+                    Err(e) => Some(Err(e.into())),
+                }
+            })));
+        }
 
-        let locus_id: &str = parsed_tag.name("locus_id").unwrap().as_str();
-        let left_flank_cigar = parsed_tag.name("flank").unwrap().as_str();
-        let repeat_cigar = parsed_tag.name("repeat").unwrap().as_str();
+        let repository = match reference {
+            Some(reference) => {
+                let reader = fasta::io::indexed_reader::Builder::default()
+                    .build_from_path(reference)
+                    .with_context(|| {
+                        format!(
+                            "Could not open the indexed reference {}",
+                            reference.display()
+                        )
+                    })?;
+                fasta::Repository::new(fasta::repository::adapters::IndexedReader::new(reader))
+            }
+            None => fasta::Repository::default(),
+        };
+        let mut reader = cram::io::reader::Builder::default()
+            .set_reference_sequence_repository(repository.clone())
+            .build_from_reader(open()?);
+        let header = reader
+            .read_header()
+            .with_context(|| format!("Could not read the header of {}", bamlet.display()))?;
+        let contigs = contig_names(&header);
+        // the reads of a container are decoded together, as they share its
+        // compression header
+        let mut container = cram::io::reader::Container::default();
+        let mut reads: VecDeque<Result<BamletRead>> = VecDeque::new();
+        Ok(Box::new(std::iter::from_fn(move || loop {
+            if let Some(read) = reads.pop_front() {
+                return Some(read);
+            }
+            match reader.read_container(&mut container) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(e) => return Some(Err(e.into())),
+            }
+            let decoded = (|| -> io::Result<Vec<Result<BamletRead>>> {
+                let compression_header = container.compression_header()?;
+                let mut decoded = Vec::new();
+                for slice in container.slices() {
+                    let slice = slice?;
+                    let (core_data, external_data) = slice.decode_blocks()?;
+                    let records = slice.records(
+                        repository.clone(),
+                        &header,
+                        &compression_header,
+                        &core_data,
+                        &external_data,
+                    )?;
+                    decoded.extend(
+                        records
+                            .iter()
+                            .map(|record| bamlet_read(record, &header, &contigs, umi_tag)),
+                    );
+                }
+                Ok(decoded)
+            })();
+            match decoded {
+                Ok(decoded) => reads.extend(decoded),
+                Err(e) => return Some(Err(e.into())),
+            }
+        })))
+    }
 
-        // start is equal to the sum of the operation counts in the left flank
-        let repeat_start = sum_operation_counts(left_flank_cigar) as usize;
-        // stop is equal to the start plus the sum of operation counts in the repeat
-        let repeat_stop = repeat_start + sum_operation_counts(repeat_cigar) as usize;
+    fn contig_names(header: &sam::Header) -> Vec<Arc<str>> {
+        header
+            .reference_sequences()
+            .keys()
+            .map(|name| Arc::from(String::from_utf8_lossy(name)))
+            .collect()
+    }
+
+    /// The fields `extract` needs of a BAM or CRAM record
+    fn bamlet_read(
+        record: &dyn Record,
+        header: &sam::Header,
+        contigs: &[Arc<str>],
+        umi_tag: Option<[u8; 2]>,
+    ) -> Result<BamletRead> {
+        let data = record.data();
+        let graph_alignment = match data.get(&Tag::from(*b"XG")).transpose()? {
+            Some(Value::String(tag)) => GraphAlignment::Tag(tag.to_string()),
+            Some(_) => GraphAlignment::NotString,
+            None => GraphAlignment::Missing("tag not present".to_string()),
+        };
+        let umi = match umi_tag.map(|tag| data.get(&Tag::from(tag)).transpose()) {
+            Some(Ok(Some(Value::String(umi)))) => Some(umi.to_string()),
+            _ => None,
+        };
+        let mut qual = record
+            .quality_scores()
+            .iter()
+            .collect::<io::Result<Vec<u8>>>()?;
+        // missing qualities are stored as 0xff
+        if qual.first() == Some(&0xff) {
+            qual.clear();
+        }
+        let position = match (
+            record.reference_sequence_id(header).transpose()?,
+            record.alignment_start().transpose()?,
+        ) {
+            // unmapped reads may be placed at the position of their mate
+            (Some(id), Some(start)) if !record.flags()?.is_unmapped() => contigs
+                .get(id)
+                .map(|contig| (contig.clone(), usize::from(start) as u64)),
+            _ => None,
+        };
+        Ok(BamletRead {
+            read_name: record
+                .name()
+                .map(|name| name.to_string())
+                .unwrap_or_default(),
+            position,
+            seq: record.sequence().iter().collect(),
+            qual,
+            graph_alignment,
+            umi,
+        })
+    }
+}
+
+/// Fails to read BAMlets when strif is built without a BAM reader
+#[cfg(not(any(feature = "htslib", feature = "noodles")))]
+mod backend {
+    use std::path::Path;
+
+    use anyhow::{bail, Result};
 
-        let seq_raw = record.seq().as_bytes();
-        let repeat_seq = std::str::from_utf8(&seq_raw[repeat_start..repeat_stop]).unwrap();
+    use super::BamletReads;
 
-        writeln!(out_file, "{}\t{}", locus_id, repeat_seq)?;
+    pub fn read_bamlet(
+        bamlet: &Path,
+        _reference: Option<&Path>,
+        _umi_tag: Option<[u8; 2]>,
+    ) -> Result<BamletReads> {
+        bail!(
+            "Could not read the BAMlet {}: strif was built without a BAM reader, build it with the `htslib` or `noodles` feature",
+            bamlet.display()
+        )
     }
+}
 
-    Ok(())
+/// The part of a read left after trimming its low-quality tails as BWA does:
+/// each end is cut where the sum of `min_quality` minus the qualities of the
+/// bases beyond the cut is highest, so that a few good bases within a degraded
+/// tail do not stop the trimming
+fn trim_low_quality_tails(qual: &[u8], min_quality: u8) -> Range<usize> {
+    let cut = |quals: &mut dyn Iterator<Item = &u8>| -> usize {
+        let (mut sum, mut best, mut cut) = (0i64, 0i64, 0usize);
+        for (i, q) in quals.enumerate() {
+            sum += min_quality as i64 - *q as i64;
+            if sum < 0 {
+                break;
+            }
+            if sum > best {
+                best = sum;
+                cut = i + 1;
+            }
+        }
+        cut
+    };
+    let start = cut(&mut qual.iter());
+    let end = qual.len() - cut(&mut qual.iter().rev());
+    start..end.max(start)
+}
+
+/// The anchoring score of the alignment of a flank to the locus graph: its
+/// matched bases minus its mismatched, inserted, deleted and clipped bases. A
+/// read whose flank barely aligns has a low or negative score, and the
+/// boundaries of its repeat are unreliable.
+fn anchoring_score(cigar: &str) -> i32 {
+    lazy_static! {
+        static ref RE_PARSE_OPS: Regex = Regex::new(r"(\d+)([MIDNSHPX=])").unwrap();
+    }
+
+    RE_PARSE_OPS
+        .captures_iter(cigar)
+        .map(|op| {
+            let count = op[1].parse::<i32>().unwrap();
+            match &op[2] {
+                "M" | "=" => count,
+                "X" | "I" | "D" | "S" => -count,
+                _ => 0,
+            }
+        })
+        .sum()
 }
 
 fn sum_operation_counts(cigar: &str) -> u32 {
@@ -82,3 +1227,637 @@ fn sum_operation_counts(cigar: &str) -> u32 {
         .map(|n| n[1].to_string().parse::<u32>().unwrap())
         .sum()
 }
+
+/// The qualities column of reads without base qualities, as in SAM
+pub const MISSING_QUALITIES: &str = "*";
+
+/// A row of the repeat sequences written by `extract`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepeatSeqRecord {
+    pub locus_id: String,
+    pub seq: String,
+    /// The Phred+33 encoded base qualities, if extracted with `--qualities`
+    pub qualities: Option<String>,
+    /// The number of reads collapsed into this row by `dedup`
+    pub multiplicity: Option<u32>,
+    /// The anchoring scores of the flanks, if extracted with `--anchoring`
+    pub anchoring: Option<Anchoring>,
+    /// The name of the read, shared by the mates of a fragment, if extracted
+    /// with `--read-names`
+    pub read_name: Option<String>,
+    /// Where the read was aligned, if extracted with `--coordinates`
+    pub coordinates: Option<ReadCoordinates>,
+}
+
+/// The contig and position a read was aligned to, as in its BAM record, and
+/// the reference region of its locus in the catalog
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadCoordinates {
+    /// The contig of the read, or `*` if it is unmapped
+    pub chrom: String,
+    /// The 1-based position of the read, or 0 if it is unmapped
+    pub pos: u64,
+    /// The reference region of the locus, if extracted with `--catalog`
+    pub reference_region: Option<String>,
+}
+
+impl ReadCoordinates {
+    /// The coordinates of a read from the contig and position of its record
+    fn of(position: Option<&(Arc<str>, u64)>) -> Self {
+        match position {
+            Some((chrom, pos)) => Self {
+                chrom: chrom.to_string(),
+                pos: *pos,
+                reference_region: None,
+            },
+            None => Self {
+                chrom: "*".to_string(),
+                pos: 0,
+                reference_region: None,
+            },
+        }
+    }
+
+    /// Whether the read is mapped more than `max_distance` bp away from the
+    /// reference region of its locus, or to another contig
+    fn is_far_from(&self, region: &Region, max_distance: u64) -> bool {
+        if self.pos == 0 {
+            return false;
+        }
+        self.chrom != region.chrom
+            || self.pos + max_distance < region.start
+            || self.pos > region.end + max_distance
+    }
+}
+
+/// The anchoring scores of the left and right flanks of a read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Anchoring {
+    pub left: i32,
+    pub right: i32,
+}
+
+impl RepeatSeqRecord {
+    pub fn write_to<W: Write>(&self, out: &mut W) -> Result<()> {
+        // the columns are positional, so the optional columns before the
+        // anchoring scores, read name and coordinates are filled in when they
+        // are written
+        let anchor = |side: fn(Anchoring) -> i32| {
+            self.anchoring
+                .map_or(".".to_string(), |anchoring| side(anchoring).to_string())
+        };
+        if let Some(coordinates) = &self.coordinates {
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                self.locus_id,
+                self.seq,
+                self.qualities.as_deref().unwrap_or(MISSING_QUALITIES),
+                self.multiplicity.unwrap_or(1),
+                anchor(|anchoring| anchoring.left),
+                anchor(|anchoring| anchoring.right),
+                self.read_name.as_deref().unwrap_or("."),
+                coordinates.chrom,
+                coordinates.pos,
+                coordinates.reference_region.as_deref().unwrap_or(".")
+            )?;
+            return Ok(());
+        }
+        if let Some(read_name) = &self.read_name {
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                self.locus_id,
+                self.seq,
+                self.qualities.as_deref().unwrap_or(MISSING_QUALITIES),
+                self.multiplicity.unwrap_or(1),
+                anchor(|anchoring| anchoring.left),
+                anchor(|anchoring| anchoring.right),
+                read_name
+            )?;
+            return Ok(());
+        }
+        match (&self.qualities, self.multiplicity, self.anchoring) {
+            (qualities, multiplicity, Some(anchoring)) => writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                self.locus_id,
+                self.seq,
+                qualities.as_deref().unwrap_or(MISSING_QUALITIES),
+                multiplicity.unwrap_or(1),
+                anchoring.left,
+                anchoring.right
+            )?,
+            (qualities, Some(multiplicity), None) => writeln!(
+                out,
+                "{}\t{}\t{}\t{}",
+                self.locus_id,
+                self.seq,
+                qualities.as_deref().unwrap_or(MISSING_QUALITIES),
+                multiplicity
+            )?,
+            (Some(qualities), None, None) => {
+                writeln!(out, "{}\t{}\t{}", self.locus_id, self.seq, qualities)?
+            }
+            (None, None, None) => writeln!(out, "{}\t{}", self.locus_id, self.seq)?,
+        }
+        Ok(())
+    }
+}
+
+/// The anchoring scores of a row of repeat sequences, if extracted with them.
+/// Rows extracted with read names but not anchoring scores have `.` instead.
+pub(crate) fn parse_anchoring(
+    record: &csv::StringRecord,
+    source: &str,
+) -> Result<Option<Anchoring>> {
+    if matches!(record.get(4), None | Some(".")) {
+        return Ok(None);
+    }
+    Ok(Some(Anchoring {
+        left: parse_field(record, 4, "left anchoring score", source)?,
+        right: parse_field(record, 5, "right anchoring score", source)?,
+    }))
+}
+
+/// The name of the read of a row of repeat sequences, if extracted with
+/// them. Rows extracted with coordinates but not read names have `.` instead.
+pub(crate) fn parse_read_name(record: &csv::StringRecord) -> Option<&str> {
+    record
+        .get(6)
+        .filter(|read_name| !read_name.is_empty() && *read_name != ".")
+}
+
+/// The coordinates of the read of a row of repeat sequences, if extracted
+/// with them
+pub(crate) fn parse_coordinates(
+    record: &csv::StringRecord,
+    source: &str,
+) -> Result<Option<ReadCoordinates>> {
+    let Some(chrom) = record.get(7) else {
+        return Ok(None);
+    };
+    Ok(Some(ReadCoordinates {
+        chrom: chrom.to_string(),
+        pos: parse_field(record, 8, "read position", source)?,
+        reference_region: record
+            .get(9)
+            .filter(|region| *region != ".")
+            .map(str::to_string),
+    }))
+}
+
+/// The number of reads a row of repeat sequences stands for, which is 1 unless
+/// `dedup` collapsed several reads into it
+pub(crate) fn parse_multiplicity(record: &csv::StringRecord, source: &str) -> Result<u32> {
+    if record.get(3).is_none() {
+        return Ok(1);
+    }
+    let multiplicity: NonZeroU32 = parse_field(record, 3, "multiplicity", source)?;
+    Ok(multiplicity.get())
+}
+
+/// Encodes Phred base qualities as printable characters, as in FASTQ
+pub(crate) fn encode_qualities(qual: &[u8]) -> String {
+    qual.iter().map(|q| (q.min(&93) + 33) as char).collect()
+}
+
+/// Decodes a qualities column into Phred base qualities, or None if the read has none
+pub fn decode_qualities(qualities: &str) -> Option<Vec<u8>> {
+    if qualities == MISSING_QUALITIES {
+        return None;
+    }
+    Some(qualities.bytes().map(|q| q.saturating_sub(33)).collect())
+}
+
+/// Reads repeat sequences written by `extract`
+pub fn read_repeat_seqs(path: &Path) -> Result<Vec<RepeatSeqRecord>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(false)
+        .flexible(true)
+        .comment(Some(b'#'))
+        .from_path(path)?;
+    let source = path.display().to_string();
+    let mut repeat_seqs: Vec<RepeatSeqRecord> = Vec::new();
+    for result in reader.records() {
+        let record = result?;
+        if record.len() < 2 {
+            return Err(StrifError::malformed_row(
+                path.display(),
+                &record,
+                "expected a locus ID and a repeat sequence",
+            )
+            .into());
+        }
+        repeat_seqs.push(RepeatSeqRecord {
+            locus_id: record[0].to_string(),
+            seq: record[1].to_string(),
+            qualities: record.get(2).map(str::to_string),
+            multiplicity: match record.get(3) {
+                Some(_) => Some(parse_multiplicity(&record, &source)?),
+                None => None,
+            },
+            anchoring: parse_anchoring(&record, &source)?,
+            read_name: parse_read_name(&record).map(str::to_string),
+            coordinates: parse_coordinates(&record, &source)?,
+        });
+    }
+    Ok(repeat_seqs)
+}
+
+/// The command line arguments of `strif extract`
+#[derive(Debug, clap::Args)]
+pub struct ExtractArgs {
+    /// The path to the ExpansionHunter BAMlet
+    pub bamlet: PathBuf,
+
+    /// The path to write the repeat sequences to. Defaults to the same directory as the BAMlet.
+    pub output: Option<PathBuf>,
+
+    /// Write a file of repeat sequences per locus into the output directory,
+    /// which defaults to the BAMlet's name with a `.repeat_seqs` suffix
+    #[clap(long)]
+    pub shard_by_locus: bool,
+
+    #[clap(flatten)]
+    pub options: ExtractOptions,
+}
+
+impl ExtractArgs {
+    /// Runs `strif extract`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let ExtractArgs {
+            bamlet,
+            output,
+            shard_by_locus,
+            options,
+        } = self;
+        let out_path: PathBuf = output.unwrap_or_else(|| default_out_path(&bamlet, shard_by_locus));
+        let label = match shard_by_locus {
+            true => "repeat sequence shards",
+            false => "repeat sequences",
+        };
+        Plan::new("extract", dry_run, force)
+            .bamlet(&bamlet)
+            .maybe_input("catalog", options.catalog.as_deref())
+            .output(label, &out_path)
+            .maybe_output("rejected reads", options.rejects.as_deref())
+            .execute(|| match shard_by_locus {
+                true => extract_shards(bamlet, out_path, &options, force),
+                false => extract(bamlet, out_path, &options),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anonymized_read_names_are_stable_salted_hashes() {
+        let name = "A00123:8:H7KLMDSXY:1:1101:1000:2000";
+        assert_eq!(anonymize_read_name("pepper", name), "2cdca459482de8de");
+        assert_ne!(
+            anonymize_read_name("salt", name),
+            anonymize_read_name("pepper", name)
+        );
+        // the mates of a fragment keep sharing a name
+        let options = ExtractOptions::default().anonymize_reads("pepper");
+        assert_eq!(
+            options.written_name(&fragment_name(&format!("{}/1", name))),
+            options.written_name(&fragment_name(&format!("{}/2", name)))
+        );
+    }
+
+    #[test]
+    fn read_names_are_written_after_placeholder_anchoring_scores() {
+        let record = RepeatSeqRecord {
+            locus_id: "L1".to_string(),
+            seq: "CAGCAG".to_string(),
+            qualities: None,
+            multiplicity: None,
+            anchoring: None,
+            read_name: Some("2cdca459482de8de".to_string()),
+            coordinates: None,
+        };
+        let mut out = Vec::new();
+        record.write_to(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "L1\tCAGCAG\t*\t1\t.\t.\t2cdca459482de8de\n"
+        );
+    }
+
+    #[test]
+    fn coordinates_are_written_with_the_reference_region_of_their_locus() {
+        let dir = tempfile::tempdir().unwrap();
+        let catalog = crate::testing::write_catalog(dir.path());
+        let mut regions = LocusRegions::load(Some(&catalog)).unwrap().unwrap();
+        let record = |locus_id: &str, chrom: &str, pos: u64| RepeatSeqRecord {
+            locus_id: locus_id.to_string(),
+            seq: "CAGCAG".to_string(),
+            qualities: None,
+            multiplicity: None,
+            anchoring: None,
+            read_name: None,
+            coordinates: Some(ReadCoordinates {
+                chrom: chrom.to_string(),
+                pos,
+                reference_region: None,
+            }),
+        };
+        let mut records = [
+            record("HTT", "chr4", 3074800),
+            record("HTT", "chr4", 3076000),
+            record("FMR1", "chr4", 3074800),
+            record("HTT", "*", 0),
+            record("RFC1", "chr4", 39348400),
+        ];
+        for record in &mut records {
+            regions.annotate(record);
+        }
+        // reads on another contig or past the distance are far, unmapped ones are not
+        assert_eq!(regions.far, 2);
+        assert_eq!(regions.unknown, 1);
+
+        let path = dir.path().join("sample.repeat_seqs.tsv");
+        let mut out = Vec::new();
+        for record in &records {
+            record.write_to(&mut out).unwrap();
+        }
+        std::fs::write(&path, &out).unwrap();
+        let lines: Vec<&str> = std::str::from_utf8(&out).unwrap().lines().collect();
+        assert_eq!(
+            lines[0],
+            "HTT\tCAGCAG\t*\t1\t.\t.\t.\tchr4\t3074800\tchr4:3074876-3074933"
+        );
+        assert_eq!(lines[4], "RFC1\tCAGCAG\t*\t1\t.\t.\t.\tchr4\t39348400\t.");
+        let read: Vec<Option<ReadCoordinates>> = read_repeat_seqs(&path)
+            .unwrap()
+            .into_iter()
+            .map(|record| record.coordinates)
+            .collect();
+        let written: Vec<Option<ReadCoordinates>> = records
+            .into_iter()
+            .map(|record| record.coordinates)
+            .collect();
+        assert_eq!(read, written);
+    }
+
+    /// Reads of HTT and FMR1 with three-base flanks
+    #[cfg(feature = "htslib")]
+    const READS: [(&str, &str, &str); 3] = [
+        ("read1", "AAACAGCAGCAGTTT", "HTT,100,0[3M]1[9M]2[3M]"),
+        ("read2", "AAACAGCATCAGTTT", "HTT,100,0[3M]1[9M]2[3M]"),
+        ("read3", "AAACGGCGGTTT", "FMR1,100,0[3M]1[6M]2[3M]"),
+    ];
+
+    #[cfg(feature = "htslib")]
+    #[test]
+    fn shards_hold_the_reads_of_their_loci() {
+        let dir = tempfile::tempdir().unwrap();
+        let bamlet = crate::testing::write_bamlet(dir.path(), &READS);
+        let out_dir = dir.path().join("shards");
+        let options = ExtractOptions::default();
+        extract_shards(bamlet.clone(), out_dir.clone(), &options, false).unwrap();
+        let names: Vec<String> = shard_paths(&out_dir)
+            .unwrap()
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, ["FMR1.repeat_seqs.tsv", "HTT.repeat_seqs.tsv"]);
+        let htt = read_repeat_seqs(&out_dir.join("HTT.repeat_seqs.tsv")).unwrap();
+        let seqs: Vec<&str> = htt.iter().map(|record| record.seq.as_str()).collect();
+        assert_eq!(seqs, ["CAGCAGCAG", "CAGCATCAG"]);
+
+        // the shards of an earlier extraction are only replaced with force
+        let buckets = options.buckets(NonZeroU32::new(3).unwrap());
+        assert!(extract_shards(bamlet.clone(), out_dir.clone(), &buckets, false).is_err());
+        extract_shards(bamlet, out_dir.clone(), &buckets, true).unwrap();
+        let paths = shard_paths(&out_dir).unwrap();
+        assert_eq!(paths.len(), 3);
+        for (index, path) in paths.iter().enumerate() {
+            for record in read_repeat_seqs(path).unwrap() {
+                assert_eq!(Shard::of(&record.locus_id, 3).index, index as u32 + 1);
+            }
+        }
+    }
+
+    #[cfg(feature = "htslib")]
+    #[test]
+    fn skipped_reads_are_written_to_rejects_with_their_reason() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut reads = READS.to_vec();
+        reads.push(("read4", "AAACAGCAGTTT", ""));
+        reads.push(("read5", "AAACAGCAGTTT", "HTT,100,0[3M]1[6M]"));
+        let bamlet = crate::testing::write_bamlet(dir.path(), &reads);
+        let out = dir.path().join("sample.repeat_seqs.tsv");
+
+        // reads without an XG tag are only skipped if they can be audited
+        assert!(extract(bamlet.clone(), out.clone(), &ExtractOptions::default()).is_err());
+
+        let rejects = dir.path().join("rejects.tsv");
+        let options = ExtractOptions::default().rejects(rejects.clone());
+        extract(bamlet, out.clone(), &options).unwrap();
+        assert_eq!(read_repeat_seqs(&out).unwrap().len(), 3);
+        assert_eq!(
+            crate::testing::data_rows(&rejects),
+            ["4\tread4\tmissing_xg\t.", "5\tread5\txg_mismatch\t."]
+        );
+    }
+
+    fn mate(side: MateSide, seq: &str, qual: &[u8]) -> PendingMate {
+        PendingMate {
+            index: 0,
+            read_name: "read1".to_string(),
+            locus_id: "HTT".to_string(),
+            side,
+            seq: seq.as_bytes().to_vec(),
+            qual: qual.to_vec(),
+            anchor_score: 3,
+            umi: None,
+            position: None,
+        }
+    }
+
+    #[test]
+    fn mates_are_fused_where_they_overlap_best() {
+        let mut left_qual = vec![30; 15];
+        left_qual[10] = 10;
+        let left = mate(MateSide::Left, "AAGTCCAGCAGCAGC", &left_qual);
+        let right = mate(MateSide::Right, "CAGCATCAGCTTGCA", &[30; 15]);
+        let (seq, qual) = fuse_mates(&left, &right).unwrap();
+        // the better called T of the right mate wins with the difference of the qualities
+        assert_eq!(seq, b"AAGTCCAGCATCAGCTTGCA");
+        assert_eq!(qual[10], 20);
+        assert_eq!(qual.len(), seq.len());
+
+        // without qualities, the left mate wins
+        let left = mate(MateSide::Left, "AAGTCCAGCAGCAGC", &[]);
+        let (seq, qual) = fuse_mates(&left, &right).unwrap();
+        assert_eq!(seq, b"AAGTCCAGCAGCAGCTTGCA");
+        assert!(qual.is_empty());
+
+        // pure repeats overlap equally well at every offset
+        let left = mate(MateSide::Left, "CAGCAGCAGCAGCAG", &[]);
+        let right = mate(MateSide::Right, "CAGCAGCAGCAGCAG", &[]);
+        assert_eq!(fuse_mates(&left, &right), None);
+    }
+
+    #[cfg(feature = "htslib")]
+    #[test]
+    fn mates_covering_one_flank_each_are_fused() {
+        let dir = tempfile::tempdir().unwrap();
+        let reads = [
+            ("pair1", "GGGAAGTCCAGCATCAGC", "HTT,100,0[3M]1[15M]"),
+            ("lone", "GGGCAGCAGCAG", "HTT,100,0[3M]1[9M]"),
+            ("pair1", "CAGCATCAGCTTGCATTT", "HTT,100,1[15M]2[3M]"),
+        ];
+        let bamlet = crate::testing::write_bamlet(dir.path(), &reads);
+        let out = dir.path().join("sample.repeat_seqs.tsv");
+        let rejects = dir.path().join("rejects.tsv");
+        let options = ExtractOptions::default()
+            .fuse_mates(true)
+            .qualities(true)
+            .rejects(rejects.clone());
+        extract(bamlet, out.clone(), &options).unwrap();
+        let records = read_repeat_seqs(&out).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].seq, "AAGTCCAGCATCAGCTTGCA");
+        assert_eq!(
+            records[0].qualities.as_deref(),
+            Some("?".repeat(20).as_str())
+        );
+        assert_eq!(
+            crate::testing::data_rows(&rejects),
+            ["2\tlone\tunfused_mate\tHTT"]
+        );
+    }
+
+    #[test]
+    fn low_quality_tails_are_trimmed_past_a_few_good_bases() {
+        let qual = [2, 2, 30, 2, 30, 30, 30, 30, 5, 2];
+        assert_eq!(trim_low_quality_tails(&qual, 20), 4..8);
+        assert_eq!(trim_low_quality_tails(&qual, 2), 0..10);
+        // reads without a good base are trimmed away
+        assert!(trim_low_quality_tails(&qual, 40).is_empty());
+    }
+
+    #[test]
+    fn anchoring_scores_count_matches_against_other_operations() {
+        assert_eq!(anchoring_score("20M"), 20);
+        assert_eq!(anchoring_score("5S10M1X2I"), 2);
+        assert_eq!(anchoring_score("3S1M"), -2);
+    }
+
+    #[cfg(feature = "htslib")]
+    #[test]
+    fn anchoring_scores_are_extracted_for_each_flank() {
+        let dir = tempfile::tempdir().unwrap();
+        let bamlet = crate::testing::write_bamlet(
+            dir.path(),
+            &[
+                ("read1", "AAACAGCAGCAGTTT", "HTT,100,0[3M]1[9M]2[1X2M]"),
+                ("read2", "AAACAGCAGCAGTTT", "HTT,100,0[2S1M]1[9M]2[3M]"),
+            ],
+        );
+        let out = dir.path().join("sample.repeat_seqs.tsv");
+        extract(
+            bamlet,
+            out.clone(),
+            &ExtractOptions::default().anchoring(true),
+        )
+        .unwrap();
+        let anchoring: Vec<(i32, i32)> = read_repeat_seqs(&out)
+            .unwrap()
+            .iter()
+            .map(|record| {
+                let anchoring = record.anchoring.unwrap();
+                (anchoring.left, anchoring.right)
+            })
+            .collect();
+        assert_eq!(anchoring, [(3, 1), (-1, 3)]);
+    }
+
+    /// Reads back a BAM written by noodles with the fields a BAMlet read can
+    /// have: aux tags of several types, an odd number of bases, missing base
+    /// qualities and more records than fit in a BGZF block
+    #[cfg(feature = "noodles")]
+    #[test]
+    fn noodles_backend_reads_bam_records() -> Result<()> {
+        use std::num::NonZeroUsize;
+
+        use noodles_bam as bam;
+        use noodles_sam::{
+            self as sam,
+            alignment::{
+                io::Write as _,
+                record::{
+                    cigar::{op::Kind, Op},
+                    data::field::Tag,
+                    Flags,
+                },
+                record_buf::{data::field::Value, RecordBuf},
+            },
+            header::record::value::{map::ReferenceSequence, Map},
+        };
+
+        let header = sam::Header::builder()
+            .add_reference_sequence(
+                "chr1",
+                Map::<ReferenceSequence>::new(NonZeroUsize::new(1000).unwrap()),
+            )
+            .build();
+        let path = std::env::temp_dir().join(format!("strif-noodles-{}.bam", std::process::id()));
+        let mut writer = bam::io::Writer::new(fs::File::create(&path)?);
+        writer.write_header(&header)?;
+        let n = 2000;
+        for i in 0..n {
+            let data = [
+                (Tag::from(*b"XG"), Value::from("HTT,100,0[5M]1[5M]2[5M]")),
+                (Tag::from(*b"NM"), Value::from(i as i32)),
+                (Tag::from(*b"RX"), Value::from(format!("UMI{}", i % 3))),
+                (Tag::from(*b"XF"), Value::from(vec![1u8, 2, 3])),
+            ];
+            let mut record = RecordBuf::builder()
+                .set_name(format!("read{}", i))
+                .set_sequence(b"ACGTA".to_vec().into())
+                .set_data(data.into_iter().collect());
+            record = if i % 2 == 0 {
+                record
+                    .set_flags(Flags::empty())
+                    .set_reference_sequence_id(0)
+                    .set_alignment_start(noodles_core::Position::try_from(10).unwrap())
+                    .set_cigar([Op::new(Kind::Match, 5)].into_iter().collect())
+                    .set_quality_scores(vec![30, 31, 32, 33, 34].into())
+            } else {
+                record.set_flags(Flags::UNMAPPED)
+            };
+            writer.write_alignment_record(&header, &record.build())?;
+        }
+        writer.try_finish()?;
+        drop(writer);
+
+        let reads = backend::read_bamlet(&path, None, Some(*b"RX"))?
+            .collect::<Result<Vec<BamletRead>>>()?;
+        fs::remove_file(&path)?;
+        assert_eq!(reads.len(), n);
+        for (i, read) in reads.iter().enumerate() {
+            assert_eq!(read.read_name, format!("read{}", i));
+            assert_eq!(read.seq, b"ACGTA");
+            assert!(
+                matches!(&read.graph_alignment, GraphAlignment::Tag(tag) if tag == "HTT,100,0[5M]1[5M]2[5M]")
+            );
+            assert_eq!(read.umi.as_deref(), Some(format!("UMI{}", i % 3).as_str()));
+            if i % 2 == 0 {
+                assert_eq!(read.qual, [30, 31, 32, 33, 34]);
+                assert_eq!(read.position, Some((Arc::from("chr1"), 10)));
+            } else {
+                assert!(read.qual.is_empty());
+                assert_eq!(read.position, None);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+use std::io;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde_json::{json, Map, Value};
+
+use crate::error::StrifError;
+
+/// The kinds of failures, each with its own exit code so that workflow
+/// managers can tell failures worth retrying from those that will recur
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// A bug in strif, such as a panic
+    Internal,
+    /// Inputs or arguments that are missing, malformed or inconsistent
+    BadInput,
+    /// A catalog that cannot be parsed or has an invalid locus
+    Catalog,
+    /// An output without any data, usually because the inputs do not match
+    EmptyOutput,
+    /// A failure to read or write a file that was not caused by its contents,
+    /// such as a full disk or an unavailable network file system
+    Io,
+}
+
+impl FailureKind {
+    /// The exit code of the process. 2 is left to command-line usage errors.
+    pub fn exit_code(self) -> u8 {
+        match self {
+            FailureKind::Internal => 1,
+            FailureKind::BadInput => 3,
+            FailureKind::Catalog => 4,
+            FailureKind::EmptyOutput => 5,
+            FailureKind::Io => 6,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FailureKind::Internal => "internal",
+            FailureKind::BadInput => "bad_input",
+            FailureKind::Catalog => "catalog",
+            FailureKind::EmptyOutput => "empty_output",
+            FailureKind::Io => "io",
+        }
+    }
+
+    /// Whether rerunning the same command may succeed
+    pub fn is_retryable(self) -> bool {
+        matches!(self, FailureKind::Io)
+    }
+
+    /// Classifies an error by the first cause of a known type. Errors raised
+    /// by strif's own checks without a type are about its inputs or arguments.
+    pub fn of(error: &anyhow::Error) -> Self {
+        for cause in error.chain() {
+            if let Some(e) = cause.downcast_ref::<StrifError>() {
+                return match e {
+                    StrifError::Catalog { .. } => FailureKind::Catalog,
+                    StrifError::EmptyOutput { .. } => FailureKind::EmptyOutput,
+                    _ => FailureKind::BadInput,
+                };
+            }
+            if let Some(e) = cause.downcast_ref::<csv::Error>() {
+                return match e.kind() {
+                    csv::ErrorKind::Io(e) => Self::of_io(e),
+                    _ => FailureKind::BadInput,
+                };
+            }
+            // the source of a JSON I/O error is the io::Error, classified next
+            if let Some(e) = cause.downcast_ref::<serde_json::Error>() {
+                if !e.is_io() {
+                    return FailureKind::BadInput;
+                }
+            }
+            if let Some(e) = cause.downcast_ref::<io::Error>() {
+                return Self::of_io(e);
+            }
+        }
+        FailureKind::BadInput
+    }
+
+    fn of_io(e: &io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::NotFound
+            | io::ErrorKind::AlreadyExists
+            | io::ErrorKind::PermissionDenied
+            | io::ErrorKind::InvalidInput
+            | io::ErrorKind::InvalidData
+            | io::ErrorKind::UnexpectedEof => FailureKind::BadInput,
+            _ => FailureKind::Io,
+        }
+    }
+}
+
+/// Writes the details of a failure as JSON, for workflow managers to decide
+/// whether to retry a task
+pub fn write_error_json(
+    path: &Path,
+    kind: FailureKind,
+    message: &str,
+    error: Option<&anyhow::Error>,
+) -> Result<()> {
+    let mut report = Map::new();
+    report.insert("exit_code".to_string(), json!(kind.exit_code()));
+    report.insert("kind".to_string(), json!(kind.name()));
+    report.insert("retryable".to_string(), json!(kind.is_retryable()));
+    report.insert("message".to_string(), json!(message));
+    if let Some(error) = error {
+        let causes: Vec<String> = error.chain().skip(1).map(|c| c.to_string()).collect();
+        report.insert("causes".to_string(), json!(causes));
+        if let Some(e) = error.chain().find_map(|c| c.downcast_ref::<StrifError>()) {
+            report.insert("details".to_string(), error_details(e));
+        }
+    }
+    report.insert("version".to_string(), json!(env!("CARGO_PKG_VERSION")));
+
+    let text = serde_json::to_string_pretty(&Value::Object(report))?;
+    std::fs::write(path, text + "\n")
+        .with_context(|| format!("Could not write the error report {}", path.display()))
+}
+
+/// The fields of a typed error, e.g. the file and line of a malformed row
+fn error_details(error: &StrifError) -> Value {
+    match error {
+        StrifError::Catalog { path, message } | StrifError::EmptyOutput { path, message } => {
+            json!({ "path": path, "message": message })
+        }
+        StrifError::MalformedRow {
+            path,
+            line,
+            message,
+        } => json!({ "path": path, "line": line, "message": message }),
+        StrifError::MissingSample { sample_id, path } => {
+            json!({ "path": path, "sample_id": sample_id })
+        }
+        StrifError::Alignment {
+            path,
+            read_name,
+            message,
+        } => json!({ "path": path, "read_name": read_name, "message": message }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use anyhow::anyhow;
+
+    use super::*;
+
+    #[test]
+    fn failures_are_classified_by_their_first_known_cause() {
+        let catalog = anyhow::Error::new(StrifError::Catalog {
+            path: "catalog.json".to_string(),
+            message: "no loci".to_string(),
+        })
+        .context("Could not run profile");
+        assert_eq!(FailureKind::of(&catalog), FailureKind::Catalog);
+
+        let missing = anyhow::Error::new(io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(FailureKind::of(&missing), FailureKind::BadInput);
+        let disk_full = anyhow::Error::new(io::Error::from(io::ErrorKind::StorageFull))
+            .context("Could not write the merged profile");
+        assert_eq!(FailureKind::of(&disk_full), FailureKind::Io);
+        assert!(FailureKind::of(&disk_full).is_retryable());
+
+        let json = anyhow::Error::new(serde_json::from_str::<Value>("{").unwrap_err());
+        assert_eq!(FailureKind::of(&json), FailureKind::BadInput);
+        assert_eq!(
+            FailureKind::of(&anyhow!("--min-match must be between 0 and 1")),
+            FailureKind::BadInput
+        );
+    }
+
+    #[test]
+    fn exit_codes_are_distinct_and_leave_two_to_usage_errors() {
+        let kinds = [
+            FailureKind::Internal,
+            FailureKind::BadInput,
+            FailureKind::Catalog,
+            FailureKind::EmptyOutput,
+            FailureKind::Io,
+        ];
+        let mut codes: Vec<u8> = kinds.iter().map(|kind| kind.exit_code()).collect();
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes.len(), kinds.len());
+        assert!(!codes.contains(&0) && !codes.contains(&2));
+    }
+
+    #[test]
+    fn error_reports_detail_typed_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("error.json");
+        let error = anyhow::Error::new(StrifError::MalformedRow {
+            path: "depths.tsv".to_string(),
+            line: 7,
+            message: "invalid read depth 'x'".to_string(),
+        })
+        .context("Could not run merge");
+        let kind = FailureKind::of(&error);
+        write_error_json(&path, kind, &format!("{:#}", error), Some(&error)).unwrap();
+
+        let report: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(report["exit_code"], 3);
+        assert_eq!(report["kind"], "bad_input");
+        assert_eq!(report["retryable"], false);
+        assert_eq!(
+            report["causes"],
+            json!(["Malformed row in depths.tsv line 7: invalid read depth 'x'"])
+        );
+        assert_eq!(
+            report["details"],
+            json!({"path": "depths.tsv", "line": 7, "message": "invalid read depth 'x'"})
+        );
+    }
+}
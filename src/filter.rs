@@ -0,0 +1,293 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Ok, Result};
+use log::info;
+use regex::Regex;
+
+use crate::merge::{read_merged_profile, split_length_stratum};
+use crate::plan::Plan;
+use crate::profile::read_profile;
+use crate::schema::{self, OutputKind};
+use crate::utils::{create_output, get_default_out_path, Region};
+
+/// Criteria a locus must meet to be kept
+pub struct FilterCriteria {
+    pub locus_regex: Option<Regex>,
+    pub regions: Vec<Region>,
+    pub motifs: Vec<String>,
+    /// Loci (or, in merged profiles, samples at a locus) with fewer reads are removed
+    pub min_read_count: u32,
+    /// Only keep loci with at least one interruption matching this pattern
+    pub interruption_regex: Option<Regex>,
+}
+
+impl FilterCriteria {
+    fn keep_locus(&self, locus_id: &str, reference_region: &str, motif: &str) -> bool {
+        if let Some(locus_regex) = &self.locus_regex {
+            if !locus_regex.is_match(locus_id) {
+                return false;
+            }
+        }
+        if !self.motifs.is_empty() && !self.motifs.iter().any(|m| m.eq_ignore_ascii_case(motif)) {
+            return false;
+        }
+        if !self.regions.is_empty() {
+            let region = match reference_region.parse::<Region>().ok() {
+                Some(region) => region,
+                None => return false,
+            };
+            if !self.regions.iter().any(|r| r.overlaps(&region)) {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn keep_interruptions<'a>(&self, mut interruptions: impl Iterator<Item = &'a str>) -> bool {
+        match &self.interruption_regex {
+            // the strata of `merge --length-bin` match as the interruption
+            // they split
+            Some(interruption_regex) => interruptions.any(|i| {
+                interruption_regex.is_match(i)
+                    || interruption_regex.is_match(split_length_stratum(i).0)
+            }),
+            None => true,
+        }
+    }
+}
+
+/// Writes the loci of a profile or merged profile that meet the criteria in
+/// the same format as the input
+pub fn filter(input: PathBuf, out_path: PathBuf, criteria: FilterCriteria) -> Result<()> {
+    let kind = schema::detect(&input)?;
+    let mut out_file = create_output(&out_path)?;
+    schema::write_header(&mut out_file, kind)?;
+
+    let (mut kept, mut total) = (0, 0);
+    match kind {
+        OutputKind::Profile => {
+            for record in read_profile(&input)? {
+                total += 1;
+                if !criteria.keep_locus(&record.locus_id, &record.reference_region, &record.motif)
+                    || record.read_count < criteria.min_read_count
+                    || !criteria.keep_interruptions(
+                        record
+                            .interruption_counts
+                            .iter()
+                            .map(|(i, _, _)| i.as_str()),
+                    )
+                {
+                    continue;
+                }
+                record.write_to(&mut out_file)?;
+                kept += 1;
+            }
+        }
+        OutputKind::MergedProfile => {
+            for mut record in read_merged_profile(&input)? {
+                total += 1;
+                if !criteria.keep_locus(&record.locus_id, &record.reference_region, &record.motif) {
+                    continue;
+                }
+
+                // drop samples below the minimum read count along with their interruptions
+                record
+                    .read_counts
+                    .retain(|(_, count)| *count >= criteria.min_read_count);
+                let samples: Vec<String> =
+                    record.read_counts.iter().map(|(s, _)| s.clone()).collect();
+                record
+                    .interruption_counts
+                    .retain(|(sample_id, _, _)| samples.contains(sample_id));
+
+                if record.read_counts.is_empty()
+                    || !criteria.keep_interruptions(
+                        record
+                            .interruption_counts
+                            .iter()
+                            .map(|(_, i, _)| i.as_str()),
+                    )
+                {
+                    continue;
+                }
+                record.write_to(&mut out_file)?;
+                kept += 1;
+            }
+        }
+        kind => bail!(
+            "{} is a '{}' file, expected a profile or merged profile",
+            input.display(),
+            kind.name()
+        ),
+    }
+
+    info!("Kept {} of {} loci", kept, total);
+
+    out_file.finish()?;
+    Ok(())
+}
+
+/// The command line arguments of `strif filter`
+#[derive(Debug, clap::Args)]
+pub struct FilterArgs {
+    /// The path to the profile or merged profile
+    pub input: PathBuf,
+
+    /// The path to the filtered output. Defaults to the same directory as the input.
+    pub output: Option<PathBuf>,
+
+    /// Only keep loci whose IDs match this regular expression
+    #[clap(short = 'f', long)]
+    pub filter: Option<String>,
+
+    /// Only keep loci overlapping this region (chrom:start-end). Can be repeated.
+    #[clap(short = 'r', long)]
+    pub region: Vec<Region>,
+
+    /// Only keep loci with this motif. Can be repeated.
+    #[clap(long)]
+    pub motif: Vec<String>,
+
+    /// Remove loci (or samples at a locus in merged profiles) with fewer reads
+    #[clap(short = 'm', long, default_value = "0")]
+    pub min_read_count: u32,
+
+    /// Only keep loci with an interruption matching this regular expression
+    #[clap(short = 'i', long)]
+    pub interruption: Option<String>,
+}
+
+impl FilterArgs {
+    /// Runs `strif filter`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let FilterArgs {
+            input,
+            output,
+            filter: locus_filter,
+            region,
+            motif,
+            min_read_count,
+            interruption,
+        } = self;
+        let out_path: PathBuf =
+            output.unwrap_or_else(|| get_default_out_path(&input, "filtered", "tsv"));
+        let criteria = FilterCriteria {
+            locus_regex: locus_filter.map(|f| regex::Regex::new(&f)).transpose()?,
+            regions: region,
+            motifs: motif,
+            min_read_count,
+            interruption_regex: interruption.map(|i| regex::Regex::new(&i)).transpose()?,
+        };
+        Plan::new("filter", dry_run, force)
+            .records("input", &input, "loci")
+            .output("filtered output", &out_path)
+            .execute(|| filter(input, out_path, criteria))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::read_merged_profile;
+    use crate::testing::{
+        merged_record, profile_record, write_merged_profile_file, write_profile_file,
+    };
+
+    fn criteria() -> FilterCriteria {
+        FilterCriteria {
+            locus_regex: None,
+            regions: Vec::new(),
+            motifs: Vec::new(),
+            min_read_count: 0,
+            interruption_regex: None,
+        }
+    }
+
+    /// The loci of a profile kept by the criteria
+    fn kept_loci(criteria: FilterCriteria) -> Vec<String> {
+        let dir = tempfile::tempdir().unwrap();
+        let mut fmr1 = profile_record("FMR1", 20, &[("CGA", 60, 2.0)]);
+        fmr1.motif = "CGG".to_string();
+        fmr1.reference_region = "chrX:147912050-147912110".to_string();
+        let input = write_profile_file(
+            dir.path(),
+            "profile.tsv",
+            &[
+                profile_record("HTT", 20, &[("CAT", 40, 2.0)]),
+                profile_record("ATXN1", 5, &[("CAT", 40, 1.0)]),
+                fmr1,
+            ],
+        );
+        let out = dir.path().join("filtered.tsv");
+        filter(input, out.clone(), criteria).unwrap();
+        read_profile(&out)
+            .unwrap()
+            .into_iter()
+            .map(|record| record.locus_id)
+            .collect()
+    }
+
+    #[test]
+    fn profile_loci_must_meet_every_criterion() {
+        assert_eq!(kept_loci(criteria()), ["HTT", "ATXN1", "FMR1"]);
+        let by_id = FilterCriteria {
+            locus_regex: Some(Regex::new("^(HTT|FMR1)$").unwrap()),
+            ..criteria()
+        };
+        assert_eq!(kept_loci(by_id), ["HTT", "FMR1"]);
+        let by_region = FilterCriteria {
+            regions: vec!["chrX:147912100-147912200".parse().unwrap()],
+            ..criteria()
+        };
+        assert_eq!(kept_loci(by_region), ["FMR1"]);
+        let by_motif_and_reads = FilterCriteria {
+            motifs: vec!["cag".to_string()],
+            min_read_count: 10,
+            ..criteria()
+        };
+        assert_eq!(kept_loci(by_motif_and_reads), ["HTT"]);
+        let by_interruption = FilterCriteria {
+            interruption_regex: Some(Regex::new("^CG").unwrap()),
+            ..criteria()
+        };
+        assert_eq!(kept_loci(by_interruption), ["FMR1"]);
+    }
+
+    #[test]
+    fn merged_samples_below_the_minimum_read_count_are_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = write_merged_profile_file(
+            dir.path(),
+            "merged.tsv",
+            &[
+                merged_record(
+                    "L1",
+                    &[("s1", 20), ("s2", 5)],
+                    &[("s1", "CAT", 0.1), ("s2", "A", 0.2)],
+                ),
+                merged_record("L2", &[("s2", 5)], &[]),
+            ],
+        );
+        let out = dir.path().join("filtered.tsv");
+        let criteria = FilterCriteria {
+            min_read_count: 10,
+            ..criteria()
+        };
+        filter(input, out.clone(), criteria).unwrap();
+        let records = read_merged_profile(&out).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].packed_read_counts(), "s1:20");
+        assert_eq!(records[0].packed_interruption_counts(), "s1:CAT:0.1");
+    }
+
+    #[test]
+    fn interruption_patterns_match_length_strata() {
+        let criteria = FilterCriteria {
+            interruption_regex: Some(Regex::new("^CAT$").unwrap()),
+            ..criteria()
+        };
+        assert!(criteria.keep_interruptions(["CAT"].into_iter()));
+        assert!(criteria.keep_interruptions(["AG@30-39", "CAT@30-39"].into_iter()));
+        assert!(!criteria.keep_interruptions(["CATT@30"].into_iter()));
+    }
+}
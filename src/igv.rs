@@ -0,0 +1,401 @@
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use log::{info, warn};
+
+use crate::convert::read_merged_profile_any;
+use crate::plan::Plan;
+use crate::subset::read_id_list;
+use crate::track::mean_burdens;
+use crate::utils::{create_output, get_default_out_path, reference_span, Region};
+
+/// What `igv` writes for reviewing loci in IGV
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum IgvFormat {
+    /// A batch script taking a snapshot of each locus, run with `igv.sh -b`
+    Batch,
+    /// A session with the BAMs loaded and a panel per locus, opened with
+    /// File > Open Session
+    Session,
+}
+
+impl IgvFormat {
+    /// The format matching the extension of an output path: a session for
+    /// `.xml`, otherwise a batch script
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("xml") => Self::Session,
+            _ => Self::Batch,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Batch => "txt",
+            Self::Session => "xml",
+        }
+    }
+}
+
+/// The loci to review and how IGV shows them
+pub struct IgvParams {
+    /// The BAMs loaded as tracks, in order
+    pub bams: Vec<PathBuf>,
+    /// Review these loci, in this order, instead of the top loci
+    pub locus_ids: Vec<String>,
+    /// The number of loci with the highest mean normalized interruption
+    /// burden reviewed without `locus_ids`
+    pub top: usize,
+    /// The genome ID or path loaded by IGV
+    pub genome: String,
+    /// The bases shown on each side of a locus
+    pub padding: u64,
+    /// The directory of the snapshots taken by a batch script
+    pub snapshot_dir: PathBuf,
+    pub format: IgvFormat,
+}
+
+/// The 1-based, inclusive locus of a region widened by the padding, as IGV
+/// expects
+fn igv_locus(region: &Region, padding: u64) -> String {
+    format!(
+        "{}:{}-{}",
+        region.chrom,
+        region.start.saturating_sub(padding) + 1,
+        region.end + padding
+    )
+}
+
+/// The name of the snapshot of a locus, with characters that are not safe in
+/// file names replaced
+fn snapshot_name(locus_id: &str) -> String {
+    let name: String = locus_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}.png", name)
+}
+
+/// Escapes a value for an XML attribute
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Picks the loci of a merged profile to review with their reference spans:
+/// the listed loci in order, or the loci with the highest mean normalized
+/// interruption burden over all samples. Loci without a parseable reference
+/// region are left out.
+fn select_loci(input: &Path, params: &IgvParams) -> Result<Vec<(String, Region)>> {
+    info!("Reading {}...", input.display());
+    let records = read_merged_profile_any(input)?;
+    let mut loci: Vec<(String, Region)> = Vec::new();
+    if params.locus_ids.is_empty() {
+        let mut ranked: Vec<(f64, String, Region)> = records
+            .iter()
+            .filter_map(|record| {
+                let span = reference_span(&record.reference_region)?;
+                let burden = mean_burdens(record, None)[0]?;
+                Some((burden, record.locus_id.clone(), span))
+            })
+            .collect();
+        ranked.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        loci.extend(
+            ranked
+                .into_iter()
+                .take(params.top)
+                .map(|(_, locus_id, span)| (locus_id, span)),
+        );
+    } else {
+        let mut missing: Vec<&str> = Vec::new();
+        let mut unplaced: Vec<&str> = Vec::new();
+        for locus_id in &params.locus_ids {
+            match records.iter().find(|record| &record.locus_id == locus_id) {
+                Some(record) => match reference_span(&record.reference_region) {
+                    Some(span) => loci.push((locus_id.clone(), span)),
+                    None => unplaced.push(locus_id),
+                },
+                None => missing.push(locus_id),
+            }
+        }
+        if !missing.is_empty() {
+            warn!(
+                "Left out {} loci not in {}: {}",
+                missing.len(),
+                input.display(),
+                missing.join(", ")
+            );
+        }
+        if !unplaced.is_empty() {
+            warn!(
+                "Left out {} loci without a parseable reference region: {}",
+                unplaced.len(),
+                unplaced.join(", ")
+            );
+        }
+    }
+    if loci.is_empty() {
+        bail!("No loci of {} to review", input.display());
+    }
+    Ok(loci)
+}
+
+/// Writes an IGV batch script or session for reviewing the reads of the
+/// original BAMs at loci of a merged profile, either the listed loci or the
+/// top loci by mean normalized interruption burden. A batch script takes a
+/// snapshot of each locus; a session opens a panel per locus. BAMs are
+/// loaded by their absolute paths.
+pub fn igv(input: PathBuf, out_path: PathBuf, params: IgvParams) -> Result<()> {
+    // IGV resolves relative paths against its own working directory, or the
+    // directory of the session, so the BAMs are loaded by their absolute paths
+    let mut bams: Vec<PathBuf> = Vec::with_capacity(params.bams.len());
+    for bam in &params.bams {
+        if !bam.exists() {
+            warn!("{} does not exist, IGV will fail to load it", bam.display());
+        }
+        bams.push(std::path::absolute(bam)?);
+    }
+    let loci = select_loci(&input, &params)?;
+
+    let mut out_file = create_output(&out_path)?;
+    match params.format {
+        IgvFormat::Batch => {
+            writeln!(out_file, "new")?;
+            writeln!(out_file, "genome {}", params.genome)?;
+            for bam in &bams {
+                writeln!(out_file, "load {}", bam.display())?;
+            }
+            writeln!(
+                out_file,
+                "snapshotDirectory {}",
+                params.snapshot_dir.display()
+            )?;
+            for (locus_id, span) in &loci {
+                writeln!(out_file, "goto {}", igv_locus(span, params.padding))?;
+                writeln!(out_file, "snapshot {}", snapshot_name(locus_id))?;
+            }
+        }
+        IgvFormat::Session => {
+            let locus = loci
+                .iter()
+                .map(|(_, span)| igv_locus(span, params.padding))
+                .collect::<Vec<_>>()
+                .join(" ");
+            writeln!(
+                out_file,
+                "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>"
+            )?;
+            writeln!(
+                out_file,
+                "<Session genome=\"{}\" locus=\"{}\" version=\"8\">",
+                xml_escape(&params.genome),
+                xml_escape(&locus)
+            )?;
+            writeln!(out_file, "    <Resources>")?;
+            for bam in &bams {
+                writeln!(
+                    out_file,
+                    "        <Resource path=\"{}\"/>",
+                    xml_escape(&bam.display().to_string())
+                )?;
+            }
+            writeln!(out_file, "    </Resources>")?;
+            writeln!(out_file, "</Session>")?;
+        }
+    }
+    out_file.finish()?;
+
+    info!(
+        "Wrote {} loci and {} BAMs to {}",
+        loci.len(),
+        bams.len(),
+        out_path.display()
+    );
+    Ok(())
+}
+
+/// The command line arguments of `strif igv`
+#[derive(Debug, clap::Args)]
+pub struct IgvArgs {
+    /// The path to the merged profile, in any format supported by `convert`
+    pub input: PathBuf,
+
+    /// The path to the batch script or session. Defaults to the same directory as the
+    /// input.
+    pub output: Option<PathBuf>,
+
+    /// Load this BAM or CRAM. Can be repeated.
+    #[clap(short = 'b', long)]
+    pub bam: Vec<PathBuf>,
+
+    /// Load the BAMs or CRAMs listed in this file, one per line
+    #[clap(long)]
+    pub bams: Option<PathBuf>,
+
+    /// Review this locus. Can be repeated. Defaults to the top loci.
+    #[clap(short = 'l', long)]
+    pub locus: Vec<String>,
+
+    /// Review the loci listed in this file, one per line
+    #[clap(long)]
+    pub loci: Option<PathBuf>,
+
+    /// The number of loci with the highest mean normalized interruption burden to review
+    #[clap(short = 'n', long, default_value_t = 20)]
+    pub top: usize,
+
+    /// The genome ID or path loaded by IGV
+    #[clap(short = 'g', long, default_value = "hg38")]
+    pub genome: String,
+
+    /// The bases shown on each side of a locus
+    #[clap(long, default_value_t = 100)]
+    pub padding: u64,
+
+    /// The directory of the snapshots taken by the batch script
+    #[clap(long, default_value = "snapshots")]
+    pub snapshot_dir: PathBuf,
+
+    /// Whether to write a batch script or a session. Defaults to a session for
+    /// .xml outputs and a batch script otherwise.
+    #[clap(short = 'f', long, value_enum)]
+    pub format: Option<IgvFormat>,
+}
+
+impl IgvArgs {
+    /// Runs `strif igv`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let IgvArgs {
+            input,
+            output,
+            mut bam,
+            bams,
+            mut locus,
+            loci,
+            top,
+            genome,
+            padding,
+            snapshot_dir,
+            format,
+        } = self;
+        if let Some(bams) = &bams {
+            bam.extend(read_id_list(bams)?.into_iter().map(PathBuf::from));
+        }
+        if let Some(loci) = &loci {
+            locus.extend(read_id_list(loci)?);
+        }
+        if bam.is_empty() {
+            bail!("No BAMs to load, pass them with --bam or --bams");
+        }
+        let format = format.unwrap_or_else(|| match &output {
+            Some(output) => IgvFormat::from_path(output),
+            None => IgvFormat::Batch,
+        });
+        let out_path: PathBuf =
+            output.unwrap_or_else(|| get_default_out_path(&input, "igv", format.extension()));
+        Plan::new("igv", dry_run, force)
+            .records("input", &input, "loci")
+            .maybe_input("BAM list", bams.as_deref())
+            .maybe_input("locus list", loci.as_deref())
+            .note("BAMs", bam.len())
+            .output("IGV file", &out_path)
+            .execute(|| {
+                let params = IgvParams {
+                    bams: bam,
+                    locus_ids: locus,
+                    top,
+                    genome,
+                    padding,
+                    snapshot_dir,
+                    format,
+                };
+                igv(input, out_path, params)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::testing::{merged_record, write_merged_profile_file};
+
+    fn params(dir: &Path, locus_ids: &[&str], format: IgvFormat) -> IgvParams {
+        IgvParams {
+            bams: vec![dir.join("s1.bam")],
+            locus_ids: locus_ids
+                .iter()
+                .map(|locus_id| locus_id.to_string())
+                .collect(),
+            top: 2,
+            genome: "hg38".to_string(),
+            padding: 10,
+            snapshot_dir: PathBuf::from("snapshots"),
+            format,
+        }
+    }
+
+    #[test]
+    fn loci_are_reviewed_by_burden_or_in_the_listed_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut high = merged_record("B:1", &[("s1", 10)], &[("s1", "T", 6.0)]);
+        high.reference_region = "chr2:1000-1030".to_string();
+        let mut unplaced = merged_record("C", &[("s1", 10)], &[("s1", "T", 10.0)]);
+        unplaced.reference_region = "chr1:100-200,chr2:300-400".to_string();
+        let records = [
+            merged_record("A", &[("s1", 10)], &[("s1", "T", 2.0)]),
+            high,
+            merged_record("D", &[("s1", 10)], &[]),
+            unplaced,
+        ];
+        let input = write_merged_profile_file(dir.path(), "merged.tsv", &records);
+        let bam = dir.path().join("s1.bam").display().to_string();
+
+        // the top loci by burden, without the unplaced locus
+        let batch = dir.path().join("review.txt");
+        igv(
+            input.clone(),
+            batch.clone(),
+            params(dir.path(), &[], IgvFormat::Batch),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(&batch).unwrap(),
+            format!(
+                "new\ngenome hg38\nload {}\nsnapshotDirectory snapshots\n\
+                 goto chr2:991-1040\nsnapshot B_1.png\n\
+                 goto chr1:91-210\nsnapshot A.png\n",
+                bam
+            )
+        );
+
+        // listed loci keep their order, leaving out missing and unplaced ones
+        let session = dir.path().join("review.xml");
+        let mut session_params = params(
+            dir.path(),
+            &["B:1", "missing", "C", "A"],
+            IgvFormat::Session,
+        );
+        session_params.genome = "genomes/a&b.json".to_string();
+        igv(input.clone(), session.clone(), session_params).unwrap();
+        let session = fs::read_to_string(&session).unwrap();
+        assert!(session.contains(
+            "<Session genome=\"genomes/a&amp;b.json\" locus=\"chr2:991-1040 chr1:91-210\" version=\"8\">"
+        ));
+        assert!(session.contains(&format!("<Resource path=\"{}\"/>", bam)));
+
+        let none = params(dir.path(), &["missing", "C"], IgvFormat::Batch);
+        assert!(igv(input, dir.path().join("none.txt"), none).is_err());
+    }
+}
@@ -0,0 +1,362 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, prelude::*, BufReader, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+
+use crate::convert::read_merged_profile_any;
+use crate::merge::{parse_merged_record, MergedProfileRecord};
+use crate::plan::Plan;
+use crate::schema::{self, OutputKind};
+use crate::utils::{
+    create_output, is_gzipped_path, reference_span, sort_by_coordinates, BgzfWriter,
+};
+
+/// The extension of the sidecar index written next to an indexed profile
+pub const INDEX_EXTENSION: &str = "sidx";
+
+/// The first field of the header line of a sidecar index
+const INDEX_MAGIC: &str = "#strif_index";
+
+/// The version of the sidecar index format
+const INDEX_VERSION: u32 = 1;
+
+/// The totals of a sample over the loci of a merged profile
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct SampleSummary {
+    pub loci: usize,
+    pub reads: u64,
+    pub burden: f64,
+}
+
+/// Sums the loci, reads and interruption counts of each sample
+pub(crate) fn sample_summaries<'a>(
+    records: impl IntoIterator<Item = &'a MergedProfileRecord>,
+) -> BTreeMap<String, SampleSummary> {
+    let mut samples: BTreeMap<String, SampleSummary> = BTreeMap::new();
+    for record in records {
+        for (sample_id, read_count) in &record.read_counts {
+            let summary = samples.entry(sample_id.clone()).or_default();
+            summary.loci += 1;
+            summary.reads += *read_count as u64;
+        }
+        for (sample_id, _, count) in &record.interruption_counts {
+            samples.entry(sample_id.clone()).or_default().burden += count;
+        }
+    }
+    samples
+}
+
+/// The path of the sidecar index of a bgzipped profile
+pub fn index_path(profile: &Path) -> PathBuf {
+    let mut path = profile.as_os_str().to_owned();
+    path.push(".");
+    path.push(INDEX_EXTENSION);
+    PathBuf::from(path)
+}
+
+/// A locus of an indexed profile
+pub(crate) struct IndexEntry {
+    pub locus_id: String,
+    pub reference_region: String,
+    pub motif: String,
+    /// The virtual offset of the row of the locus in the bgzipped profile
+    offset: u64,
+}
+
+/// The sidecar index of a bgzipped merged profile, which lists the loci in
+/// file order with the position of their rows, along with the totals of each
+/// sample, so that lookups only decompress the blocks holding their rows
+pub(crate) struct ProfileIndex {
+    profile: PathBuf,
+    pub entries: Vec<IndexEntry>,
+    pub samples: BTreeMap<String, SampleSummary>,
+}
+
+impl ProfileIndex {
+    /// Loads the sidecar index of a profile, or returns None if it has none
+    pub fn open(profile: &Path) -> Result<Option<Self>> {
+        let index_path = index_path(profile);
+        if !index_path.exists() {
+            return Ok(None);
+        }
+        let source = index_path.display().to_string();
+        let file = File::open(&index_path).with_context(|| format!("Could not open {}", source))?;
+        let mut lines = BufReader::new(file).lines();
+
+        let header = lines.next().transpose()?.unwrap_or_default();
+        let fields: Vec<&str> = header.split('\t').collect();
+        let (version, indexed_size) = match fields[..] {
+            [INDEX_MAGIC, version, size] => (version.parse::<u32>(), size.parse::<u64>()),
+            _ => bail!("{} is not a strif index", source),
+        };
+        match version {
+            Ok(INDEX_VERSION) => {}
+            _ => bail!(
+                "{} has an unsupported index version '{}', rerun `strif index`",
+                source,
+                fields[1]
+            ),
+        }
+        let size = fs::metadata(profile)
+            .with_context(|| format!("Could not open {}", profile.display()))?
+            .len();
+        if indexed_size != Ok(size) {
+            bail!(
+                "{} has changed since {} was written, rerun `strif index`",
+                profile.display(),
+                source
+            );
+        }
+
+        let mut entries: Vec<IndexEntry> = Vec::new();
+        let mut samples: BTreeMap<String, SampleSummary> = BTreeMap::new();
+        for (i, line) in lines.enumerate() {
+            let line = line?;
+            let fields: Vec<&str> = line.split('\t').collect();
+            let malformed = || format!("Line {} of {} is malformed", i + 2, source);
+            match fields[..] {
+                ["#sample", sample_id, loci, reads, burden] => {
+                    samples.insert(
+                        sample_id.to_string(),
+                        SampleSummary {
+                            loci: loci.parse().with_context(malformed)?,
+                            reads: reads.parse().with_context(malformed)?,
+                            burden: burden.parse().with_context(malformed)?,
+                        },
+                    );
+                }
+                [locus_id, reference_region, motif, offset] => entries.push(IndexEntry {
+                    locus_id: locus_id.to_string(),
+                    reference_region: reference_region.to_string(),
+                    motif: motif.to_string(),
+                    offset: offset.parse().with_context(malformed)?,
+                }),
+                _ => bail!(malformed()),
+            }
+        }
+        Ok(Some(ProfileIndex {
+            profile: profile.to_path_buf(),
+            entries,
+            samples,
+        }))
+    }
+
+    /// Reads the records of the loci at the given indices. Each run of
+    /// consecutive loci is read with a single seek.
+    pub fn read_records(
+        &self,
+        indices: &[usize],
+        mut f: impl FnMut(MergedProfileRecord) -> Result<()>,
+    ) -> Result<()> {
+        let source = self.profile.display().to_string();
+        let mut file =
+            File::open(&self.profile).with_context(|| format!("Could not open {}", source))?;
+        let mut run_start = 0;
+        while run_start < indices.len() {
+            let mut run_end = run_start + 1;
+            while run_end < indices.len() && indices[run_end] == indices[run_end - 1] + 1 {
+                run_end += 1;
+            }
+
+            let offset = self.entries[indices[run_start]].offset;
+            file.seek(SeekFrom::Start(offset >> 16))?;
+            let mut reader = flate2::read::MultiGzDecoder::new(BufReader::new(&mut file));
+            io::copy(&mut (&mut reader).take(offset & 0xffff), &mut io::sink())?;
+            let mut rows = csv::ReaderBuilder::new()
+                .delimiter(b'\t')
+                .has_headers(false)
+                .comment(Some(b'#'))
+                .from_reader(reader);
+            let mut row = csv::StringRecord::new();
+            for &i in &indices[run_start..run_end] {
+                if !rows.read_record(&mut row)? {
+                    bail!(
+                        "{} ends before locus {}, rerun `strif index`",
+                        source,
+                        self.entries[i].locus_id
+                    );
+                }
+                let record = parse_merged_record(&row, &source)?;
+                if record.locus_id != self.entries[i].locus_id {
+                    bail!(
+                        "{} does not match its index at locus {}, rerun `strif index`",
+                        source,
+                        self.entries[i].locus_id
+                    );
+                }
+                f(record)?;
+            }
+            run_start = run_end;
+        }
+        Ok(())
+    }
+}
+
+/// The path `index` writes the bgzipped profile to by default: the input
+/// with a `.tsv.gz` extension, which replaces a bgzipped input in place
+pub fn default_out_path(input: &Path) -> PathBuf {
+    let mut stem = input.to_path_buf();
+    if is_gzipped_path(&stem) {
+        stem.set_extension("");
+    }
+    stem.with_extension("tsv.gz")
+}
+
+/// Sorts a merged profile by coordinates, compresses it with BGZF and writes
+/// a sidecar index next to it, which `query` and `serve` use to look up loci
+/// and regions without reading the whole file. Loci whose reference region
+/// cannot be parsed are written last and can only be looked up by ID.
+pub fn index(input: PathBuf, out_path: PathBuf) -> Result<()> {
+    info!("Reading {}...", input.display());
+    let mut records = read_merged_profile_any(&input)?;
+
+    let unplaced = records
+        .iter()
+        .filter(|record| reference_span(&record.reference_region).is_none())
+        .count();
+    if unplaced > 0 {
+        warn!(
+            unplaced;
+            "{} loci have no parseable reference region and can only be looked up by locus ID",
+            unplaced
+        );
+    }
+    records.sort_by(|a, b| a.locus_id.cmp(&b.locus_id));
+    sort_by_coordinates(&mut records, |record| &record.reference_region);
+
+    info!(
+        "Writing {} loci to {}...",
+        records.len(),
+        out_path.display()
+    );
+    let mut out = BgzfWriter::new(create_output(&out_path)?);
+    schema::write_header(&mut out, OutputKind::MergedProfile)?;
+    let mut offsets: Vec<u64> = Vec::with_capacity(records.len());
+    for record in &records {
+        offsets.push(out.virtual_offset());
+        record.write_to(&mut out)?;
+    }
+    out.finish()?.finish()?;
+
+    let index_path = index_path(&out_path);
+    info!("Writing the index to {}...", index_path.display());
+    let size = fs::metadata(&out_path)?.len();
+    let mut index_file = create_output(&index_path)?;
+    writeln!(index_file, "{}\t{}\t{}", INDEX_MAGIC, INDEX_VERSION, size)?;
+    for (sample_id, summary) in sample_summaries(&records) {
+        writeln!(
+            index_file,
+            "#sample\t{}\t{}\t{}\t{}",
+            sample_id, summary.loci, summary.reads, summary.burden
+        )?;
+    }
+    for (record, offset) in records.iter().zip(offsets) {
+        writeln!(
+            index_file,
+            "{}\t{}\t{}\t{}",
+            record.locus_id, record.reference_region, record.motif, offset
+        )?;
+    }
+    index_file.finish()?;
+
+    info!("Done!");
+    Ok(())
+}
+
+/// The command line arguments of `strif index`
+#[derive(Debug, clap::Args)]
+pub struct IndexArgs {
+    /// The path to the merged profile, in any format supported by `convert`
+    pub input: PathBuf,
+
+    /// The path to write the bgzipped profile to, with its index next to it
+    /// under an added .sidx extension. Defaults to the input with a .tsv.gz extension.
+    #[clap(short = 'o', long)]
+    pub output: Option<PathBuf>,
+}
+
+impl IndexArgs {
+    /// Runs `strif index`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let IndexArgs { input, output } = self;
+        let out_path = output.unwrap_or_else(|| default_out_path(&input));
+        let index_path = index_path(&out_path);
+        Plan::new("index", dry_run, force)
+            .input("merged profile", &input)
+            .output("bgzipped profile", &out_path)
+            .output("index", &index_path)
+            .execute(|| index(input, out_path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{merged_record, write_merged_profile_file};
+
+    /// Loci spread over two chromosomes, in an order other than that of
+    /// their coordinates, and enough of them to fill several BGZF blocks
+    fn records() -> Vec<MergedProfileRecord> {
+        (0..3000)
+            .map(|i| {
+                let mut record = merged_record(
+                    &format!("L{}", i),
+                    &[("s1", 10), ("s2", i)],
+                    &[("s1", "A", 0.5)],
+                );
+                record.reference_region =
+                    format!("chr{}:{}-{}", 2 - i % 2, 100 * i + 1, 100 * i + 50);
+                record
+            })
+            .collect()
+    }
+
+    #[test]
+    fn indexed_loci_are_read_from_their_offsets() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = write_merged_profile_file(dir.path(), "cohort.tsv", &records());
+        let out = default_out_path(&input);
+        assert_eq!(out, dir.path().join("cohort.tsv.gz"));
+        index(input, out.clone()).unwrap();
+
+        let index = ProfileIndex::open(&out).unwrap().unwrap();
+        assert_eq!(index.entries.len(), 3000);
+        // loci are sorted by coordinates
+        assert_eq!(index.entries[0].reference_region, "chr1:101-150");
+        assert_eq!(index.entries[1500].reference_region, "chr2:1-50");
+        assert_eq!(index.samples["s1"].loci, 3000);
+        assert_eq!(index.samples["s1"].burden, 1500.0);
+        assert_eq!(index.samples["s2"].reads, (0..3000).sum::<u64>());
+
+        let indices = [0, 1, 2, 1499, 1500, 2999];
+        let mut locus_ids = Vec::new();
+        index
+            .read_records(&indices, |record| {
+                locus_ids.push(record.locus_id);
+                Ok(())
+            })
+            .unwrap();
+        let expected: Vec<&str> = indices
+            .iter()
+            .map(|&i| index.entries[i].locus_id.as_str())
+            .collect();
+        assert_eq!(locus_ids, expected);
+        assert_eq!(locus_ids[..2], ["L1", "L3"]);
+    }
+
+    #[test]
+    fn indexes_of_changed_profiles_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = write_merged_profile_file(dir.path(), "cohort.tsv", &records()[..10]);
+        let out = dir.path().join("cohort.tsv.gz");
+        assert!(ProfileIndex::open(&out).unwrap().is_none());
+        index(input, out.clone()).unwrap();
+        let mut file = fs::OpenOptions::new().append(true).open(&out).unwrap();
+        file.write_all(b"\n").unwrap();
+        let error = ProfileIndex::open(&out).err().unwrap();
+        assert!(error.to_string().contains("has changed"));
+    }
+}
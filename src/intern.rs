@@ -0,0 +1,74 @@
+use std::sync::Arc;
+
+use rustc_hash::FxHashMap;
+
+/// A handle to a string stored in an [`Interner`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+/// Stores each distinct string once and hands out `u32` handles to it, so
+/// that maps keyed by locus, sample or interruption hold 4 bytes per key
+/// instead of a copy of the string. Strings are restored at write time with
+/// [`Interner::resolve`].
+#[derive(Debug, Default)]
+pub struct Interner {
+    symbols: FxHashMap<Arc<str>, Symbol>,
+    strings: Vec<Arc<str>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the handle of a string, storing it if it is new
+    pub fn intern(&mut self, s: &str) -> Symbol {
+        if let Some(symbol) = self.symbols.get(s) {
+            return *symbol;
+        }
+        let symbol = Symbol(
+            u32::try_from(self.strings.len()).expect("more than 2^32 distinct strings interned"),
+        );
+        let s: Arc<str> = Arc::from(s);
+        self.strings.push(Arc::clone(&s));
+        self.symbols.insert(s, symbol);
+        symbol
+    }
+
+    /// Returns the handle of a string that was interned before
+    pub fn get(&self, s: &str) -> Option<Symbol> {
+        self.symbols.get(s).copied()
+    }
+
+    /// Returns the string of a handle from this interner
+    pub fn resolve(&self, symbol: Symbol) -> &str {
+        &self.strings[symbol.0 as usize]
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_distinct_string_gets_one_symbol() {
+        let mut interner = Interner::new();
+        let htt = interner.intern("HTT");
+        let fmr1 = interner.intern("FMR1");
+        assert_ne!(htt, fmr1);
+        assert_eq!(interner.intern("HTT"), htt);
+        assert_eq!(interner.len(), 2);
+        assert_eq!(interner.resolve(fmr1), "FMR1");
+        assert_eq!(interner.get("FMR1"), Some(fmr1));
+        assert_eq!(interner.get("ATXN3"), None);
+        assert!(Interner::new().is_empty());
+    }
+}
@@ -0,0 +1,898 @@
+//! The alignment of repeat sequences to pure repeats of their motif and the
+//! detection of interruptions from those alignments. This module does no I/O
+//! so that it can be embedded anywhere, including in WebAssembly.
+
+use std::ops::Range;
+use std::str::FromStr;
+
+use bio::alignment::pairwise::{Aligner, MatchFunc};
+use bio::alignment::{Alignment, AlignmentOperation};
+use rustc_hash::FxHashMap;
+
+use crate::utils::AlignmentScoreParams;
+
+/// The copies of the motif appended to a pure repeat beyond the read length,
+/// unless a preset sets another padding
+const PURE_SEQ_PAD: usize = 4;
+
+const A: u8 = 0b0001;
+const C: u8 = 0b0010;
+const G: u8 = 0b0100;
+const T: u8 = 0b1000;
+
+/// The set of bases of each IUPAC nucleotide code as a bit mask, or 0 for
+/// other characters
+const IUPAC_BASES: [u8; 256] = {
+    let mut table = [0; 256];
+    let codes: [(u8, u8); 15] = [
+        (b'A', A),
+        (b'C', C),
+        (b'G', G),
+        (b'T', T),
+        (b'R', A | G),
+        (b'Y', C | T),
+        (b'S', C | G),
+        (b'W', A | T),
+        (b'K', G | T),
+        (b'M', A | C),
+        (b'B', C | G | T),
+        (b'D', A | G | T),
+        (b'H', A | C | T),
+        (b'V', A | C | G),
+        (b'N', A | C | G | T),
+    ];
+    let mut i = 0;
+    while i < codes.len() {
+        let (code, bases) = codes[i];
+        table[code as usize] = bases;
+        table[code.to_ascii_lowercase() as usize] = bases;
+        i += 1;
+    }
+    table
+};
+
+/// Returns true if the character is an IUPAC nucleotide code, e.g. `A` or `R`
+pub fn is_iupac_code(code: u8) -> bool {
+    IUPAC_BASES[code as usize] != 0
+}
+
+/// Returns true if a base of a read matches a base of a motif, which can be
+/// an ambiguity code matching any base of its set (e.g. `R` matches `A` and
+/// `G`). An ambiguous base of the read, such as `N`, only matches itself.
+pub fn bases_match(read_base: u8, motif_base: u8) -> bool {
+    if read_base == motif_base {
+        return true;
+    }
+    let read_bases = IUPAC_BASES[read_base as usize];
+    read_bases.count_ones() == 1 && IUPAC_BASES[motif_base as usize] & read_bases != 0
+}
+
+/// The bases of a substitution matrix, in the order of its rows and columns
+const MATRIX_BASES: [u8; 5] = *b"ACGTN";
+
+/// The scores of aligning each base of a read (rows) to each base of a motif
+/// (columns), replacing single match and mismatch scores, e.g. to penalize
+/// transversions more than transitions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ScoreMatrix {
+    scores: [[i32; 5]; 5],
+}
+
+impl ScoreMatrix {
+    /// The row or column of a base, with other characters scored as N
+    fn index(base: u8) -> usize {
+        match base.to_ascii_uppercase() {
+            b'A' => 0,
+            b'C' => 1,
+            b'G' => 2,
+            b'T' => 3,
+            _ => 4,
+        }
+    }
+
+    /// The score of a read base against a motif base. An ambiguity code of
+    /// the motif scores as the best of its bases.
+    pub fn score(&self, read_base: u8, motif_base: u8) -> i32 {
+        let row = &self.scores[Self::index(read_base)];
+        let motif_bases = IUPAC_BASES[motif_base as usize];
+        if motif_bases.count_ones() <= 1 || motif_bases == A | C | G | T {
+            return row[Self::index(motif_base)];
+        }
+        [A, C, G, T]
+            .iter()
+            .zip(row)
+            .filter(|(base, _)| motif_bases & **base != 0)
+            .map(|(_, score)| *score)
+            .max()
+            .unwrap()
+    }
+}
+
+/// Parses a substitution matrix whose first line names the columns and whose
+/// other lines start with the base of the row, separated by whitespace, as in
+/// the matrices of BLAST. A, C, G and T are required, and N, if missing,
+/// scores as the lowest score of the matrix. Lines starting with `#` are
+/// comments.
+impl FromStr for ScoreMatrix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        let columns: Vec<usize> = match lines.next() {
+            Some(header) => header
+                .split_whitespace()
+                .map(|base| matrix_base(base, "column"))
+                .collect::<Result<_, _>>()?,
+            None => return Err("the substitution matrix is empty".to_string()),
+        };
+        let mut scores: [[Option<i32>; 5]; 5] = [[None; 5]; 5];
+        for line in lines {
+            let mut fields = line.split_whitespace();
+            let row = matrix_base(fields.next().unwrap(), "row")?;
+            let values: Vec<&str> = fields.collect();
+            if values.len() != columns.len() {
+                return Err(format!(
+                    "row {} has {} scores, expected one per column ({})",
+                    MATRIX_BASES[row] as char,
+                    values.len(),
+                    columns.len()
+                ));
+            }
+            for (&column, value) in columns.iter().zip(values) {
+                let score: i32 = value
+                    .parse()
+                    .map_err(|_| format!("invalid score '{}'", value))?;
+                if scores[row][column].replace(score).is_some() {
+                    return Err(format!(
+                        "the score of {} against {} is given twice",
+                        MATRIX_BASES[row] as char, MATRIX_BASES[column] as char
+                    ));
+                }
+            }
+        }
+        let lowest = scores[..4]
+            .iter()
+            .flat_map(|row| &row[..4])
+            .flatten()
+            .min()
+            .copied();
+        let mut matrix = ScoreMatrix {
+            scores: [[0; 5]; 5],
+        };
+        for (i, row) in scores.iter().enumerate() {
+            for (j, score) in row.iter().enumerate() {
+                matrix.scores[i][j] = match (score, lowest) {
+                    (Some(score), _) => *score,
+                    (None, Some(lowest)) if i == 4 || j == 4 => lowest,
+                    _ => {
+                        return Err(format!(
+                            "the score of {} against {} is missing",
+                            MATRIX_BASES[i] as char, MATRIX_BASES[j] as char
+                        ))
+                    }
+                };
+            }
+        }
+        Ok(matrix)
+    }
+}
+
+fn matrix_base(base: &str, what: &str) -> Result<usize, String> {
+    match MATRIX_BASES
+        .iter()
+        .position(|b| base.eq_ignore_ascii_case(&(*b as char).to_string()))
+    {
+        Some(i) => Ok(i),
+        None => Err(format!(
+            "'{}' is not a {} of the substitution matrix, expected A, C, G, T or N",
+            base, what
+        )),
+    }
+}
+
+/// Match and mismatch scores where the ambiguity codes of the motif match
+/// their sets of bases, or the scores of a substitution matrix
+#[derive(Debug, Clone, Copy)]
+struct IupacMatch {
+    match_score: i32,
+    mismatch_score: i32,
+    matrix: Option<ScoreMatrix>,
+}
+
+impl From<&LocusParams> for IupacMatch {
+    fn from(params: &LocusParams) -> Self {
+        IupacMatch {
+            match_score: params.scores.match_score,
+            mismatch_score: -params.scores.mismatch_penalty,
+            matrix: params.matrix,
+        }
+    }
+}
+
+impl MatchFunc for IupacMatch {
+    fn score(&self, a: u8, b: u8) -> i32 {
+        if let Some(matrix) = &self.matrix {
+            return matrix.score(a, b);
+        }
+        if bases_match(a, b) {
+            self.match_score
+        } else {
+            self.mismatch_score
+        }
+    }
+}
+
+/// The settings used to find the interruptions of the reads of a locus
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LocusParams {
+    pub scores: AlignmentScoreParams,
+    /// The copies of the motif appended to a pure repeat beyond the read length
+    pub padding: usize,
+    /// Interruptions separated by at most this many matching bases are
+    /// reported as one interruption spanning both
+    pub merge_distance: usize,
+    /// Interruptions made of inserted bases only are reported if at least
+    /// this long, as shorter insertions are sequencing errors on some platforms
+    pub min_insertion_len: usize,
+    /// Scores replacing the match and mismatch scores, if given
+    pub matrix: Option<ScoreMatrix>,
+}
+
+impl From<AlignmentScoreParams> for LocusParams {
+    fn from(scores: AlignmentScoreParams) -> Self {
+        LocusParams {
+            scores,
+            padding: PURE_SEQ_PAD,
+            merge_distance: 0,
+            min_insertion_len: 1,
+            matrix: None,
+        }
+    }
+}
+
+impl LocusParams {
+    /// Whether the alignment of an exact repeat of a motif is known without
+    /// aligning it: all matches, which holds when a match outscores any other
+    /// operation and gaps are not free
+    pub(crate) fn aligns_pure_repeats_exactly(&self, motif: &[u8]) -> bool {
+        let scores = &self.scores;
+        self.matrix.is_none()
+            && motif.iter().all(|base| b"ACGT".contains(base))
+            && scores.match_score > 0
+            && scores.mismatch_penalty > -scores.match_score
+            && scores.gap_extend_penalty >= 0
+            && scores.gap_open_penalty + scores.gap_extend_penalty > 0
+    }
+}
+
+/// Finds the interruptions of repeat sequences by aligning them to pure
+/// repeats of their motif, reusing the aligner's buffers across reads
+pub struct InterruptionFinder {
+    aligner: Aligner<IupacMatch>,
+    /// A pure repeat of each motif seen, as long as needed by its longest read
+    /// so far. Shorter reads are aligned to a prefix of it, so it is not
+    /// rebuilt for every read.
+    pure_seqs: FxHashMap<Vec<u8>, Vec<u8>>,
+    padding: usize,
+    merge_distance: usize,
+    min_insertion_len: usize,
+}
+
+impl InterruptionFinder {
+    pub fn new(params: AlignmentScoreParams) -> Self {
+        Self::with_locus_params(params.into())
+    }
+
+    pub fn with_locus_params(params: LocusParams) -> Self {
+        let scoring = IupacMatch::from(&params);
+        InterruptionFinder {
+            aligner: Aligner::new(
+                -params.scores.gap_open_penalty,
+                -params.scores.gap_extend_penalty,
+                scoring,
+            ),
+            pure_seqs: FxHashMap::default(),
+            padding: params.padding,
+            merge_distance: params.merge_distance,
+            min_insertion_len: params.min_insertion_len,
+        }
+    }
+
+    /// Aligns a repeat sequence semi-globally to a pure repeat of the motif,
+    /// returning the alignment and the pure repeat. Of the alignments with the
+    /// best score, the one returned has its gaps placed canonically (see
+    /// [`canonicalize_gaps`]), so that it does not depend on the order in which
+    /// the aligner breaks ties.
+    pub fn align(&mut self, repeat_seq: &[u8], motif: &[u8]) -> (Alignment, &[u8]) {
+        let copies = repeat_seq.len() / motif.len() + 1 + self.padding;
+        if !self.pure_seqs.contains_key(motif) {
+            self.pure_seqs.insert(motif.to_vec(), Vec::new());
+        }
+        let pure_seq = self.pure_seqs.get_mut(motif).unwrap();
+        if pure_seq.len() < copies * motif.len() {
+            *pure_seq = motif.repeat(copies);
+        }
+        let pure_seq = &pure_seq[..copies * motif.len()];
+        let mut alignment = self.aligner.semiglobal(repeat_seq, pure_seq);
+        canonicalize_gaps(&mut alignment, repeat_seq, pure_seq);
+        // the aligner only reports identical bases as matches, so bases
+        // matching an ambiguity code of the motif would be interruptions
+        if !motif.iter().all(|base| b"ACGT".contains(base)) {
+            for (i, (x, y, op)) in alignment.path().into_iter().enumerate() {
+                if op == AlignmentOperation::Subst
+                    && bases_match(repeat_seq[x - 1], pure_seq[y - 1])
+                {
+                    alignment.operations[i] = AlignmentOperation::Match;
+                }
+            }
+        }
+        (alignment, pure_seq)
+    }
+
+    /// Returns the interruptions of a repeat sequence in the order they occur
+    pub fn find(&mut self, repeat_seq: &[u8], motif: &[u8]) -> Vec<String> {
+        let (merge_distance, min_insertion_len) = (self.merge_distance, self.min_insertion_len);
+        let (alignment, _) = self.align(repeat_seq, motif);
+        find_interruptions(alignment, repeat_seq, merge_distance, min_insertion_len)
+    }
+}
+
+/// Places the gaps of an alignment canonically without changing its score,
+/// since equally good alignments of a repeat often differ only in where an
+/// insertion or deletion goes, and which of them an aligner returns depends
+/// on its traceback order. Every run of insertions or deletions is shifted
+/// left past the matches and substitutions before it for as long as that
+/// pairs the same bases, i.e. indels are left-aligned, and a run of
+/// insertions directly before a run of deletions is moved after it.
+pub fn canonicalize_gaps(alignment: &mut Alignment, observed: &[u8], pure: &[u8]) {
+    use AlignmentOperation::{Del, Ins, Match, Subst};
+
+    let mut start = 0;
+    while start < alignment.operations.len() {
+        let op = alignment.operations[start];
+        if op != Ins && op != Del {
+            start += 1;
+            continue;
+        }
+        let mut end = start;
+        while end < alignment.operations.len() && alignment.operations[end] == op {
+            end += 1;
+        }
+        let len = end - start;
+        // the operations before the run are not moved, so their coordinates
+        // stay valid as it shifts
+        let path = alignment.path();
+        while start > 0 && matches!(alignment.operations[start - 1], Match | Subst) {
+            // the base the operation before the run pairs, and the base it
+            // would pair after the run
+            let (x, y, _) = path[start - 1];
+            let keeps_pairs = match op {
+                Ins => observed[x - 1 + len] == observed[x - 1],
+                _ => pure[y - 1 + len] == pure[y - 1],
+            };
+            if !keeps_pairs {
+                break;
+            }
+            alignment.operations[start - 1..end].rotate_left(1);
+            start -= 1;
+            end -= 1;
+        }
+        if op == Ins && alignment.operations.get(end) == Some(&Del) {
+            let mut del_end = end;
+            while del_end < alignment.operations.len() && alignment.operations[del_end] == Del {
+                del_end += 1;
+            }
+            alignment.operations[start..del_end].rotate_left(len);
+            // the deletions may now shift further left
+            continue;
+        }
+        start = end;
+    }
+}
+
+pub(crate) fn find_interruptions(
+    alignment: Alignment,
+    observed: &[u8],
+    merge_distance: usize,
+    min_insertion_len: usize,
+) -> Vec<String> {
+    find_interruption_spans(&alignment, merge_distance, min_insertion_len)
+        .into_iter()
+        .map(|span| String::from_utf8_lossy(&observed[span]).into_owned())
+        .collect()
+}
+
+/// The fraction of the columns of an alignment that are matches to the pure
+/// repeat, or None if nothing was aligned
+pub(crate) fn alignment_purity(alignment: &Alignment) -> Option<f64> {
+    let (mut matches, mut columns) = (0usize, 0usize);
+    for op in &alignment.operations {
+        match op {
+            AlignmentOperation::Match => {
+                matches += 1;
+                columns += 1;
+            }
+            AlignmentOperation::Subst | AlignmentOperation::Ins | AlignmentOperation::Del => {
+                columns += 1
+            }
+            _ => {}
+        }
+    }
+    (columns > 0).then(|| matches as f64 / columns as f64)
+}
+
+/// Whether a repeat sequence is an exact tandem repeat of a motif, starting
+/// at any offset into it. Its first bases must be a rotation of the motif and
+/// every later base must repeat the base a motif length before it.
+pub(crate) fn is_pure_repeat(repeat_seq: &[u8], motif: &[u8]) -> bool {
+    let period = motif.len();
+    if repeat_seq.is_empty() || period == 0 {
+        return false;
+    }
+    let head = &repeat_seq[..period.min(repeat_seq.len())];
+    let in_phase = (0..period).any(|offset| {
+        head.iter()
+            .enumerate()
+            .all(|(i, base)| *base == motif[(offset + i) % period])
+    });
+    in_phase
+        && repeat_seq[period.min(repeat_seq.len())..]
+            .iter()
+            .zip(repeat_seq)
+            .all(|(base, earlier)| base == earlier)
+}
+
+/// The fewest bases at an end of a repeat sequence that must equal its flank
+/// to be trimmed as flank, so that a few bases matching it by chance are kept
+const MIN_FLANK_MATCH: usize = 6;
+
+/// The number of bases at the start and end of a repeat sequence that are
+/// its left and right flanks rather than repeat, as when the boundaries of a
+/// locus are set inside its flanks. Each is the longest stretch at its end
+/// of at least `MIN_FLANK_MATCH` bases that equals the adjoining end of the
+/// flank and is not itself a repeat of the motif, which would fit either.
+/// At least one base of the repeat sequence is always left.
+pub(crate) fn flank_overlaps(
+    repeat_seq: &[u8],
+    left_flank: Option<&[u8]>,
+    right_flank: Option<&[u8]>,
+    motif: &[u8],
+) -> (usize, usize) {
+    let is_flank = |stretch: &[u8], flank: &[u8]| {
+        stretch.eq_ignore_ascii_case(flank) && !is_pure_repeat(&stretch.to_ascii_uppercase(), motif)
+    };
+    let max_left = repeat_seq.len().saturating_sub(1);
+    let left = left_flank.map_or(0, |flank| {
+        (MIN_FLANK_MATCH..=max_left.min(flank.len()))
+            .rev()
+            .find(|&len| is_flank(&repeat_seq[..len], &flank[flank.len() - len..]))
+            .unwrap_or(0)
+    });
+    let max_right = repeat_seq.len().saturating_sub(left + 1);
+    let right = right_flank.map_or(0, |flank| {
+        (MIN_FLANK_MATCH..=max_right.min(flank.len()))
+            .rev()
+            .find(|&len| is_flank(&repeat_seq[repeat_seq.len() - len..], &flank[..len]))
+            .unwrap_or(0)
+    });
+    (left, right)
+}
+
+/// Estimates the number of copies of a motif in a repeat sequence from its
+/// periodicity alone, without aligning it: the number of bases that repeat
+/// the base a motif length before or after them, over the motif length.
+/// Interruptions and flanking sequence that do not repeat are left out, so
+/// the estimate is the length of the repeat rather than of the sequence.
+pub fn estimate_copy_number(repeat_seq: &[u8], motif_len: usize) -> f64 {
+    if motif_len == 0 {
+        return 0.0;
+    }
+    let periodic = (0..repeat_seq.len())
+        .filter(|&i| {
+            let base = repeat_seq[i].to_ascii_uppercase();
+            let repeats = |j: usize| repeat_seq[j].to_ascii_uppercase() == base;
+            (i >= motif_len && repeats(i - motif_len))
+                || (i + motif_len < repeat_seq.len() && repeats(i + motif_len))
+        })
+        .count();
+    periodic as f64 / motif_len as f64
+}
+
+/// Returns the positions of the interruptions in the repeat sequence in the
+/// order they occur
+pub(crate) fn find_interruption_spans(
+    alignment: &Alignment,
+    merge_distance: usize,
+    min_insertion_len: usize,
+) -> Vec<Range<usize>> {
+    // Given an alignment, find the interruptions in the repeat sequence
+    // by looking at the path and finding consecutive insertions or substitutions
+    let mut spans: Vec<Range<usize>> = Vec::new();
+    let mut span: Option<Range<usize>> = None;
+    // matching bases after an interruption, which become part of it if
+    // another interruption follows within the merge distance
+    let mut gap: usize = 0;
+    // the inserted bases of the current interruption, or None once it has a
+    // substitution
+    let mut inserted: Option<usize> = Some(0);
+    for (observed_idx, _, op) in alignment.path() {
+        if op == AlignmentOperation::Subst || op == AlignmentOperation::Ins {
+            match span.as_mut() {
+                Some(span) => span.end = observed_idx,
+                None => span = Some(observed_idx - 1..observed_idx),
+            }
+            inserted = match op {
+                AlignmentOperation::Ins => inserted.map(|len| len + 1),
+                _ => None,
+            };
+            gap = 0;
+        } else if let Some(current) = span.take() {
+            if op == AlignmentOperation::Match && gap < merge_distance {
+                gap += 1;
+                span = Some(current);
+                continue;
+            }
+            if inserted.is_none_or(|len| len >= min_insertion_len) {
+                spans.push(current);
+            }
+            inserted = Some(0);
+            gap = 0;
+        }
+    }
+    spans
+}
+
+/// The alignment score an interruption costs: the score its substituted bases
+/// would have as matches of the pure repeat less their score, plus the gap
+/// penalties of its inserted bases and of bases of the pure repeat deleted
+/// within it. Interruptions that cost more are less likely to be alignment
+/// artifacts.
+pub(crate) fn span_penalty(
+    alignment: &Alignment,
+    observed: &[u8],
+    pure: &[u8],
+    span: &Range<usize>,
+    params: &LocusParams,
+) -> i32 {
+    let scoring = IupacMatch::from(params);
+    let gaps = &params.scores;
+    let mut penalty = 0;
+    let mut last_op = AlignmentOperation::Match;
+    for (x, y, op) in alignment.path() {
+        let inside = match op {
+            AlignmentOperation::Subst | AlignmentOperation::Ins => span.contains(&(x - 1)),
+            AlignmentOperation::Del => span.start < x && x < span.end,
+            _ => false,
+        };
+        if inside {
+            penalty += match op {
+                AlignmentOperation::Subst => {
+                    scoring.score(pure[y - 1], pure[y - 1])
+                        - scoring.score(observed[x - 1], pure[y - 1])
+                }
+                _ if op == last_op => gaps.gap_extend_penalty,
+                _ => gaps.gap_open_penalty + gaps.gap_extend_penalty,
+            };
+        }
+        last_op = op;
+    }
+    penalty
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bio::alignment::AlignmentMode;
+    use AlignmentOperation::{Del, Ins, Match, Subst};
+
+    /// An alignment of all of `observed` to all of `pure` with the given
+    /// operations
+    fn alignment(observed: &[u8], pure: &[u8], operations: &[AlignmentOperation]) -> Alignment {
+        Alignment {
+            score: 0,
+            ystart: 0,
+            xstart: 0,
+            yend: pure.len(),
+            xend: observed.len(),
+            ylen: pure.len(),
+            xlen: observed.len(),
+            operations: operations.to_vec(),
+            mode: AlignmentMode::Semiglobal,
+        }
+    }
+
+    fn canonical(
+        observed: &[u8],
+        pure: &[u8],
+        operations: &[AlignmentOperation],
+    ) -> Vec<AlignmentOperation> {
+        let mut alignment = alignment(observed, pure, operations);
+        canonicalize_gaps(&mut alignment, observed, pure);
+        alignment.operations
+    }
+
+    #[test]
+    fn equally_good_gap_placements_are_made_one() {
+        // an extra A in a run of As can be any of them
+        let placements = [
+            [Match, Ins, Match, Match, Match],
+            [Match, Match, Ins, Match, Match],
+            [Match, Match, Match, Ins, Match],
+        ];
+        for operations in placements {
+            assert_eq!(
+                canonical(b"CAAAG", b"CAAG", &operations),
+                [Match, Ins, Match, Match, Match]
+            );
+        }
+        // a missing copy of a motif can be any of them
+        let placements = [
+            [Match, Match, Match, Del, Del, Del, Match, Match, Match],
+            [Del, Del, Del, Match, Match, Match, Match, Match, Match],
+            [Match, Del, Del, Del, Match, Match, Match, Match, Match],
+        ];
+        for operations in placements {
+            assert_eq!(
+                canonical(b"CAGCAG", b"CAGCAGCAG", &operations),
+                [Del, Del, Del, Match, Match, Match, Match, Match, Match]
+            );
+        }
+        // a substitution can be an insertion next to a deletion either way round
+        for operations in [[Match, Ins, Del, Match], [Match, Del, Ins, Match]] {
+            assert_eq!(
+                canonical(b"CTG", b"CAG", &operations),
+                [Match, Del, Ins, Match]
+            );
+        }
+    }
+
+    #[test]
+    fn gaps_at_the_ends_of_an_alignment() {
+        // runs at the start have nothing to shift past
+        let operations = [Ins, Match, Match, Match];
+        assert_eq!(canonical(b"ACAG", b"CAG", &operations), operations);
+        let operations = [Del, Match, Match, Match];
+        assert_eq!(canonical(b"CAG", b"ACAG", &operations), operations);
+        // runs at the end shift left within the run of bases they repeat
+        assert_eq!(
+            canonical(b"CAGG", b"CAG", &[Match, Match, Match, Ins]),
+            [Match, Match, Ins, Match]
+        );
+        assert_eq!(
+            canonical(b"CAG", b"CAGG", &[Match, Match, Match, Del]),
+            [Match, Match, Del, Match]
+        );
+        assert_eq!(
+            canonical(
+                b"CAGCAGCAG",
+                b"CAGCAG",
+                &[Match, Match, Match, Match, Match, Match, Ins, Ins, Ins]
+            ),
+            [Ins, Ins, Ins, Match, Match, Match, Match, Match, Match]
+        );
+    }
+
+    #[test]
+    fn gaps_in_repeats_of_ambiguous_motifs() {
+        // bases matching an ambiguity code are substitutions until `align`
+        // turns them into matches, and gaps shift past them as past matches
+        let placements = [
+            [Match, Match, Subst, Match, Match, Subst, Del, Del, Del],
+            [Match, Match, Subst, Del, Del, Del, Match, Match, Subst],
+            [Del, Del, Del, Match, Match, Subst, Match, Match, Subst],
+        ];
+        for operations in placements {
+            assert_eq!(
+                canonical(b"GCAGCT", b"GCNGCNGCN", &operations),
+                [Del, Del, Del, Match, Match, Subst, Match, Match, Subst]
+            );
+        }
+
+        let mut finder = InterruptionFinder::new(AlignmentScoreParams::default());
+        let (alignment, _) = finder.align(b"GCAGCTGCCGCG", b"GCN");
+        assert!(alignment.operations.iter().all(|op| *op == Match));
+        assert!(finder.find(b"GCAGCTGCCGCG", b"GCN").is_empty());
+        assert_eq!(finder.find(b"GCAGCTTTTGCCGCG", b"GCN"), ["TTT"]);
+    }
+
+    #[test]
+    fn pure_repeats_start_at_any_offset_into_the_motif() {
+        assert!(is_pure_repeat(b"CAGCAGCAG", b"CAG"));
+        assert!(is_pure_repeat(b"AGCAGCA", b"CAG"));
+        assert!(is_pure_repeat(b"GC", b"CAG"));
+        assert!(is_pure_repeat(b"AAAAA", b"A"));
+        assert!(!is_pure_repeat(b"CAGCTGCAG", b"CAG"));
+        assert!(!is_pure_repeat(b"CTGCTG", b"CAG"));
+        assert!(!is_pure_repeat(b"CGA", b"CAG"));
+        assert!(!is_pure_repeat(b"", b"CAG"));
+        assert!(!is_pure_repeat(b"CAG", b""));
+    }
+
+    #[test]
+    fn flank_overlaps_trim_the_flanking_bases_of_a_repeat_sequence() {
+        let motif = b"CAG";
+        let left: &[u8] = b"CCCCGATTACAT";
+        let right: &[u8] = b"TGCATGCCCC";
+        let repeat_seq = b"GATTACATCAGCAGCAGCAGTGCATGC";
+        assert_eq!(
+            flank_overlaps(repeat_seq, Some(left), Some(right), motif),
+            (8, 7)
+        );
+        assert_eq!(flank_overlaps(repeat_seq, None, None, motif), (0, 0));
+        // flanks are matched regardless of case
+        assert_eq!(
+            flank_overlaps(repeat_seq, Some(b"ccccgattacat"), None, motif),
+            (8, 0)
+        );
+        // fewer than MIN_FLANK_MATCH bases may match the flank by chance
+        assert_eq!(
+            flank_overlaps(b"ACATCAGCAGCAG", Some(left), None, motif),
+            (0, 0)
+        );
+        // bases that repeat the motif could be either, so they are kept
+        assert_eq!(
+            flank_overlaps(b"CAGCAGCAGCAG", Some(b"TTTTCAGCAGCAG"), None, motif),
+            (0, 0)
+        );
+    }
+
+    #[test]
+    fn finders_report_substitutions_and_insertions_in_order() {
+        let mut finder = InterruptionFinder::new(AlignmentScoreParams::default());
+        assert!(finder.find(b"CAGCAGCAG", b"CAG").is_empty());
+        // a pure repeat can start and end anywhere in the motif
+        assert!(finder.find(b"GCAGCAGCA", b"CAG").is_empty());
+        assert_eq!(finder.find(b"CAGCAGCAACAGCAG", b"CAG"), ["A"]);
+        assert_eq!(finder.find(b"CAGCAACAGCAGCAACAG", b"CAG"), ["A", "A"]);
+        assert_eq!(finder.find(b"CAGCAGCAGTTTTCAGCAGCAG", b"CAG"), ["TTTT"]);
+    }
+
+    #[test]
+    fn finders_match_the_ambiguity_codes_of_motifs() {
+        let mut finder = InterruptionFinder::new(AlignmentScoreParams::default());
+        assert!(finder.find(b"CAGCAACAGCAACAG", b"CAR").is_empty());
+        assert_eq!(finder.find(b"CAGCAACAGCATCAG", b"CAR"), ["T"]);
+
+        let (alignment, pure_seq) = finder.align(b"CAGCAACAG", b"CAR");
+        assert!(alignment.operations.iter().all(|op| *op == Match));
+        assert!(pure_seq.starts_with(b"CARCAR"));
+    }
+
+    #[test]
+    fn one_pure_repeat_per_motif_grows_with_the_longest_read() {
+        let mut finder = InterruptionFinder::new(AlignmentScoreParams::default());
+        let short = finder.align(b"CAGCAG", b"CAG").1.len();
+        let long = finder.align(&b"CAG".repeat(20), b"CAG").1.len();
+        // copies for the read, one more and the padding
+        assert_eq!(short, (2 + 1 + PURE_SEQ_PAD) * 3);
+        assert_eq!(long, (20 + 1 + PURE_SEQ_PAD) * 3);
+        assert_eq!(finder.align(b"CAGCAG", b"CAG").1.len(), short);
+        finder.align(b"CGGCGG", b"CGG");
+        assert_eq!(finder.pure_seqs.len(), 2);
+        assert_eq!(finder.pure_seqs[b"CAG".as_slice()].len(), long);
+    }
+
+    #[test]
+    fn read_bases_match_the_base_sets_of_ambiguity_codes() {
+        assert!(bases_match(b'A', b'R') && bases_match(b'G', b'R'));
+        assert!(!bases_match(b'C', b'R'));
+        assert!(bases_match(b'T', b'N'));
+        assert!(bases_match(b'a', b'A'));
+        // ambiguous read bases only match themselves
+        assert!(bases_match(b'N', b'N'));
+        assert!(!bases_match(b'N', b'A') && !bases_match(b'R', b'N'));
+        assert!(is_iupac_code(b'Y') && is_iupac_code(b'k'));
+        assert!(!is_iupac_code(b'X') && !is_iupac_code(b'-'));
+    }
+
+    /// Transitions (A-G, C-T) score 0 and transversions -8
+    const TRANSITIONS: &str = "# a transition-transversion matrix
+   A  C  G  T
+A  1 -8  0 -8
+C -8  1 -8  0
+G  0 -8  1 -8
+T -8  0 -8  1
+";
+
+    #[test]
+    fn score_matrices_are_parsed_with_n_as_their_lowest_score() {
+        let matrix: ScoreMatrix = TRANSITIONS.parse().unwrap();
+        assert_eq!(matrix.score(b'A', b'A'), 1);
+        assert_eq!(matrix.score(b'g', b'a'), 0);
+        assert_eq!(matrix.score(b'C', b'A'), -8);
+        assert_eq!(matrix.score(b'N', b'A'), -8);
+        assert_eq!(matrix.score(b'A', b'N'), -8);
+        // an ambiguity code of the motif scores as the best of its bases
+        assert_eq!(matrix.score(b'G', b'R'), 1);
+        assert_eq!(matrix.score(b'C', b'R'), -8);
+        // columns can be in any order, and N can be given
+        let matrix: ScoreMatrix =
+            "T G C A N\nA 0 1 2 3 4\nC 5 6 7 8 9\nG 1 1 1 1 1\nT 2 2 2 2 2\nN 0 0 0 0 0"
+                .parse()
+                .unwrap();
+        assert_eq!(matrix.score(b'A', b'T'), 0);
+        assert_eq!(matrix.score(b'C', b'A'), 8);
+        assert_eq!(matrix.score(b'A', b'N'), 4);
+    }
+
+    #[test]
+    fn malformed_score_matrices_are_rejected() {
+        let error = |text: &str| text.parse::<ScoreMatrix>().unwrap_err();
+        assert_eq!(error("# nothing\n\n"), "the substitution matrix is empty");
+        assert!(error("A C G U\n").contains("'U' is not a column"));
+        assert!(error("A C G T\nU 1 1 1 1\n").contains("'U' is not a row"));
+        assert_eq!(
+            error("A C G T\nA 1 1 1\n"),
+            "row A has 3 scores, expected one per column (4)"
+        );
+        assert_eq!(error("A C G T\nA 1 x 1 1\n"), "invalid score 'x'");
+        assert_eq!(
+            error("A C G T\nA 1 1 1 1\na 1 1 1 1\n"),
+            "the score of A against A is given twice"
+        );
+        assert_eq!(
+            error("A C G T\nA 1 1 1 1\nC 1 1 1 1\nG 1 1 1 1\n"),
+            "the score of T against A is missing"
+        );
+    }
+
+    #[test]
+    fn score_matrices_score_the_alignments_of_reads() {
+        let finder = |matrix: Option<ScoreMatrix>| {
+            InterruptionFinder::with_locus_params(LocusParams {
+                matrix,
+                ..AlignmentScoreParams::default().into()
+            })
+        };
+        // a transition (G to A) and a transversion (A to T) among 19 matches
+        let read = b"CAGCAGCAACAGCAGCTGCAG";
+        let mut plain = finder(None);
+        assert_eq!(plain.align(read, b"CAG").0.score, 19 - 8 - 8);
+        assert_eq!(plain.find(read, b"CAG"), ["A", "T"]);
+        let mut transitions = finder(Some(TRANSITIONS.parse().unwrap()));
+        assert_eq!(transitions.align(read, b"CAG").0.score, 19 - 8);
+        assert_eq!(transitions.find(read, b"CAG"), ["A", "T"]);
+    }
+
+    #[test]
+    fn pure_repeats_are_recognized_at_any_offset() {
+        assert!(is_pure_repeat(b"CAGCAGCAG", b"CAG"));
+        assert!(is_pure_repeat(b"AGCAGCA", b"CAG"));
+        assert!(is_pure_repeat(b"GC", b"CAG"));
+        assert!(!is_pure_repeat(b"CAGCATCAG", b"CAG"));
+        assert!(!is_pure_repeat(b"CAGCAGCA", b"CGG"));
+        assert!(!is_pure_repeat(b"CACCAC", b"CAG"));
+        assert!(!is_pure_repeat(b"", b"CAG"));
+    }
+
+    #[test]
+    fn pure_repeats_skip_alignment_only_when_it_would_be_all_matches() {
+        let params = LocusParams::from(AlignmentScoreParams::default());
+        assert!(params.aligns_pure_repeats_exactly(b"CAG"));
+        assert!(!params.aligns_pure_repeats_exactly(b"CNG"));
+        let free_gaps = LocusParams::from(AlignmentScoreParams {
+            gap_open_penalty: 0,
+            gap_extend_penalty: 0,
+            ..Default::default()
+        });
+        assert!(!free_gaps.aligns_pure_repeats_exactly(b"CAG"));
+        let no_match_score = LocusParams::from(AlignmentScoreParams {
+            match_score: 0,
+            ..Default::default()
+        });
+        assert!(!no_match_score.aligns_pure_repeats_exactly(b"CAG"));
+    }
+
+    #[test]
+    fn copy_numbers_count_the_bases_that_repeat_a_motif_length_away() {
+        assert_eq!(estimate_copy_number(b"CAGCAGCAGCAG", 3), 4.0);
+        assert_eq!(estimate_copy_number(b"cagCAGcag", 3), 3.0);
+        // the T repeats neither the G before it nor the G after it
+        assert_eq!(estimate_copy_number(b"CAGCAGCATCAGCAGCAG", 3), 17.0 / 3.0);
+        assert_eq!(estimate_copy_number(b"CAG", 3), 0.0);
+        assert_eq!(estimate_copy_number(b"CAGCAG", 0), 0.0);
+    }
+}
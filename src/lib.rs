@@ -0,0 +1,100 @@
+//! strif identifies interruptions in short tandem repeats (STRs) across the
+//! genome from ExpansionHunter BAMlets.
+//!
+//! Each subcommand of the `strif` binary is a module of this library with a
+//! function of the same name taking the paths of its inputs and outputs, and
+//! an `Args` struct of its command line whose `execute` method runs it as the
+//! binary does, with its default output paths and `--dry-run` plan. The
+//! data structures of the catalogs, profiles and merged profiles, and readers
+//! and writers for them, are re-exported at the crate root so that other tools
+//! can embed strif's logic. Errors caused by malformed inputs are
+//! [`StrifError`]s wrapped in an `anyhow::Error`:
+//!
+//! ```no_run
+//! use std::path::Path;
+//!
+//! let records = strif::read_merged_profile(Path::new("cohort.merged_profile.tsv"))?;
+//! for record in &records {
+//!     println!("{} has {} samples", record.locus_id, record.read_counts.len());
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+pub mod annotate;
+pub mod arrow_stream;
+pub mod association;
+pub mod benchmark;
+#[cfg(feature = "tui")]
+pub mod browse;
+pub mod burden;
+pub mod call;
+pub mod cat;
+pub mod catalog;
+pub mod compare;
+pub mod completions;
+pub mod config;
+pub mod convert;
+pub mod dedup;
+#[cfg(feature = "htslib")]
+pub mod depth;
+pub mod doctor;
+pub mod error;
+pub mod export;
+pub mod extract;
+pub mod failure;
+pub mod filter;
+pub mod igv;
+pub mod index;
+pub mod intern;
+pub mod interruptions;
+pub mod liftover;
+pub mod logging;
+pub mod man;
+pub mod mask;
+pub mod matrix;
+pub mod merge;
+pub mod migrate;
+pub mod outlier;
+pub mod overrides;
+pub mod pca;
+pub mod pedigree;
+pub mod plan;
+pub mod plot;
+pub mod presets;
+pub mod profile;
+pub mod project;
+pub mod provenance;
+pub mod pseudonym;
+pub mod query;
+pub mod report;
+pub mod run;
+pub mod schema;
+pub mod scratch;
+pub mod serve;
+pub mod sidecar;
+pub mod simulate;
+pub mod stats;
+pub mod subset;
+#[cfg(test)]
+mod testing;
+pub mod threads;
+pub mod track;
+pub mod utils;
+pub mod validate;
+pub mod vcf;
+pub mod verify;
+pub mod watch;
+
+pub use catalog::{Catalog, CatalogLocus, Flanks, ReadFilters};
+pub use error::StrifError;
+pub use extract::ExtractOptions;
+pub use interruptions::InterruptionFinder;
+pub use merge::{
+    read_merged_profile, read_merged_profile_from, write_merged_profile, MergeOptions,
+    MergedProfile, MergedProfileRecord,
+};
+pub use profile::{
+    read_profile, read_profile_from, write_profile, Profile, ProfileOptions, ProfileRecord,
+};
+pub use schema::OutputKind;
+pub use utils::Region;
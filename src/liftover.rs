@@ -0,0 +1,620 @@
+use std::io::prelude::*;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use bio::alphabets::dna::revcomp;
+use lazy_static::lazy_static;
+use log::{info, warn};
+use serde_json::Value;
+
+use crate::catalog::region_locus_id;
+use crate::merge::read_merged_profile;
+use crate::plan::Plan;
+use crate::profile::read_profile;
+use crate::schema::{self, OutputKind};
+use crate::utils::{create_output, get_default_out_path, open_maybe_gzipped, Region};
+
+pub struct LiftoverParams {
+    /// Recalculate locus IDs from the lifted regions
+    pub rename: bool,
+    /// Where to write a table mapping the original loci to the lifted ones
+    pub mapping: Option<PathBuf>,
+    /// Minimum fraction of a region's bases that must be aligned in the chain
+    pub min_match: f64,
+}
+
+/// An alignment chain between two genome builds
+struct Chain {
+    target_start: u64,
+    target_end: u64,
+    query_name: String,
+    query_size: u64,
+    query_reverse: bool,
+    /// Ungapped blocks as (target start, query start, size), sorted by target start
+    blocks: Vec<(u64, u64, u64)>,
+}
+
+/// The result of lifting a region
+struct Lifted {
+    region: Region,
+    reverse: bool,
+    mapped_fraction: f64,
+}
+
+/// Remaps the reference regions of a catalog, profile or merged profile to
+/// another genome build using a UCSC chain file
+pub fn liftover(
+    input: PathBuf,
+    chain: PathBuf,
+    out_path: PathBuf,
+    params: LiftoverParams,
+) -> Result<()> {
+    if !(0.0..=1.0).contains(&params.min_match) {
+        bail!("--min-match must be between 0 and 1");
+    }
+
+    info!("Loading chain file...");
+    let chains = load_chains(&chain)?;
+    let mut mapping: Vec<String> = Vec::new();
+    let mut locus_ids: HashSet<String> = HashSet::new();
+    let mut lifter = |locus_id: &str, reference_region: &str| -> Option<(String, Lifted)> {
+        let lifted = reference_region
+            .parse::<Region>()
+            .ok()
+            .and_then(|region| lift_region(&chains, &region));
+        let (new_locus_id, status) = match &lifted {
+            Some(lifted) if lifted.mapped_fraction >= params.min_match => {
+                let new_locus_id = if params.rename {
+                    region_locus_id(&lifted.region.chrom, lifted.region.start, lifted.region.end)
+                } else {
+                    locus_id.to_string()
+                };
+                if locus_ids.insert(new_locus_id.clone()) {
+                    (new_locus_id, "mapped")
+                } else {
+                    warn!("Skipping {}, it lifts onto another locus", locus_id);
+                    (new_locus_id, "duplicate")
+                }
+            }
+            Some(_) => (".".to_string(), "partial"),
+            None => (".".to_string(), "unmapped"),
+        };
+        mapping.push(format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            locus_id,
+            reference_region,
+            new_locus_id,
+            lifted
+                .as_ref()
+                .map_or(".".to_string(), |l| l.region.to_string()),
+            lifted
+                .as_ref()
+                .map_or(".", |l| if l.reverse { "-" } else { "+" }),
+            lifted
+                .as_ref()
+                .map_or(".".to_string(), |l| l.mapped_fraction.to_string()),
+            status
+        ));
+        match status {
+            "mapped" => Some((new_locus_id, lifted.unwrap())),
+            _ => None,
+        }
+    };
+
+    let is_catalog = input
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("json"))
+        .unwrap_or(false);
+    let (n_lifted, n_total) = if is_catalog {
+        lift_catalog(&input, &out_path, &mut lifter)?
+    } else {
+        lift_profile(&input, &out_path, &mut lifter)?
+    };
+    info!(
+        "Lifted {} of {} loci, {} could not be lifted",
+        n_lifted,
+        n_total,
+        n_total - n_lifted
+    );
+
+    if let Some(mapping_path) = params.mapping {
+        let mut mapping_file = create_output(&mapping_path)?;
+        schema::write_header(&mut mapping_file, OutputKind::LiftoverMapping)?;
+        for row in mapping {
+            writeln!(mapping_file, "{}", row)?;
+        }
+        mapping_file.finish()?;
+    }
+
+    info!("Done!");
+    Ok(())
+}
+
+/// Lifts each locus of an ExpansionHunter-style catalog, keeping all other
+/// fields. Motifs of loci that land on the reverse strand are reverse complemented.
+fn lift_catalog(
+    input: &Path,
+    out_path: &Path,
+    lifter: &mut impl FnMut(&str, &str) -> Option<(String, Lifted)>,
+) -> Result<(usize, usize)> {
+    let catalog: Vec<Value> = serde_json::from_reader(BufReader::new(File::open(input)?))
+        .with_context(|| format!("Could not parse the catalog {}", input.display()))?;
+    let n_total = catalog.len();
+    let mut lifted_catalog: Vec<Value> = Vec::new();
+    for mut entry in catalog {
+        let locus_id = match entry.get("LocusId").and_then(|v| v.as_str()) {
+            Some(locus_id) => locus_id.to_string(),
+            None => bail!("A locus of {} is missing its LocusId", input.display()),
+        };
+        let reference_region = match entry.get("ReferenceRegion").and_then(|v| v.as_str()) {
+            Some(reference_region) => reference_region.to_string(),
+            None => bail!(
+                "Locus {} of {} should have a single ReferenceRegion string",
+                locus_id,
+                input.display()
+            ),
+        };
+        let (new_locus_id, lifted) = match lifter(&locus_id, &reference_region) {
+            Some(lifted) => lifted,
+            None => continue,
+        };
+        if lifted.reverse {
+            let structure = entry
+                .get("LocusStructure")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default();
+            match reverse_structure(structure) {
+                Some(structure) => entry["LocusStructure"] = Value::String(structure),
+                None => {
+                    warn!(
+                        "Skipping {}, its LocusStructure '{}' cannot be reverse complemented",
+                        locus_id, structure
+                    );
+                    continue;
+                }
+            }
+        }
+        entry["LocusId"] = Value::String(new_locus_id);
+        entry["ReferenceRegion"] = Value::String(lifted.region.to_string());
+        lifted_catalog.push(entry);
+    }
+
+    let mut out_file = create_output(out_path)?;
+    serde_json::to_writer_pretty(&mut out_file, &lifted_catalog)?;
+    writeln!(out_file)?;
+    out_file.finish()?;
+    Ok((lifted_catalog.len(), n_total))
+}
+
+/// Lifts each locus of a profile or merged profile. Motifs and interruptions
+/// of loci that land on the reverse strand are reverse complemented.
+fn lift_profile(
+    input: &Path,
+    out_path: &Path,
+    lifter: &mut impl FnMut(&str, &str) -> Option<(String, Lifted)>,
+) -> Result<(usize, usize)> {
+    let rc = |seq: &str| String::from_utf8(revcomp(seq.as_bytes())).unwrap();
+    let mut out_file = create_output(out_path)?;
+    let mut n_lifted: usize = 0;
+    let n_total: usize;
+    match schema::detect(input)? {
+        OutputKind::Profile => {
+            let records = read_profile(input)?;
+            n_total = records.len();
+            schema::write_header(&mut out_file, OutputKind::Profile)?;
+            for mut record in records {
+                let (new_locus_id, lifted) =
+                    match lifter(&record.locus_id, &record.reference_region) {
+                        Some(lifted) => lifted,
+                        None => continue,
+                    };
+                if lifted.reverse {
+                    record.motif = rc(&record.motif);
+                    for (interruption, _, _) in record.interruption_counts.iter_mut() {
+                        *interruption = rc(interruption);
+                    }
+                }
+                record.locus_id = new_locus_id;
+                record.reference_region = lifted.region.to_string();
+                record.write_to(&mut out_file)?;
+                n_lifted += 1;
+            }
+        }
+        OutputKind::MergedProfile => {
+            let records = read_merged_profile(input)?;
+            n_total = records.len();
+            schema::write_header(&mut out_file, OutputKind::MergedProfile)?;
+            for mut record in records {
+                let (new_locus_id, lifted) =
+                    match lifter(&record.locus_id, &record.reference_region) {
+                        Some(lifted) => lifted,
+                        None => continue,
+                    };
+                if lifted.reverse {
+                    record.motif = rc(&record.motif);
+                    for (_, interruption, _) in record.interruption_counts.iter_mut() {
+                        *interruption = rc(interruption);
+                    }
+                }
+                record.locus_id = new_locus_id;
+                record.reference_region = lifted.region.to_string();
+                record.write_to(&mut out_file)?;
+                n_lifted += 1;
+            }
+        }
+        kind => bail!(
+            "{} is a '{}' file, expected a catalog, profile or merged profile",
+            input.display(),
+            kind.name()
+        ),
+    }
+    out_file.finish()?;
+    Ok((n_lifted, n_total))
+}
+
+/// Reverse complements a LocusStructure made of `(MOTIF)*` units, also
+/// reversing the order of the units
+fn reverse_structure(structure: &str) -> Option<String> {
+    lazy_static! {
+        static ref UNIT: regex::Regex =
+            regex::Regex::new(r"^\(([ACGTRYSWKMBDHVN]+)\)([*+?])").unwrap();
+    }
+    let mut units: Vec<String> = Vec::new();
+    let mut rest = structure;
+    while !rest.is_empty() {
+        let captures = UNIT.captures(rest)?;
+        let motif = String::from_utf8(revcomp(captures[1].as_bytes())).unwrap();
+        units.push(format!("({}){}", motif, &captures[2]));
+        rest = &rest[captures[0].len()..];
+    }
+    if units.is_empty() {
+        return None;
+    }
+    units.reverse();
+    Some(units.concat())
+}
+
+/// Reads a UCSC chain file (optionally gzipped), indexed by target chromosome
+fn load_chains(path: &Path) -> Result<HashMap<String, Vec<Chain>>> {
+    let reader = open_maybe_gzipped(path)
+        .with_context(|| format!("Could not open the chain file {}", path.display()))?;
+    let mut chains: HashMap<String, Vec<Chain>> = HashMap::new();
+    // the chain being read, with its target chromosome and current offsets
+    let mut current: Option<(String, Chain, u64, u64)> = None;
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() || fields[0].starts_with('#') {
+            continue;
+        }
+        let parse = |field: &str| -> Result<u64> {
+            field
+                .parse()
+                .with_context(|| format!("{} line {} is malformed", path.display(), i + 1))
+        };
+        if fields[0] == "chain" {
+            if fields.len() < 12 {
+                bail!(
+                    "{} line {} is a malformed chain header",
+                    path.display(),
+                    i + 1
+                );
+            }
+            if let Some((target_name, chain, _, _)) = current.take() {
+                chains.entry(target_name).or_default().push(chain);
+            }
+            if fields[4] != "+" {
+                bail!(
+                    "{} line {} has a reverse target strand, which is not supported",
+                    path.display(),
+                    i + 1
+                );
+            }
+            let target_start = parse(fields[5])?;
+            let query_start = parse(fields[10])?;
+            current = Some((
+                fields[2].to_string(),
+                Chain {
+                    target_start,
+                    target_end: parse(fields[6])?,
+                    query_name: fields[7].to_string(),
+                    query_size: parse(fields[8])?,
+                    query_reverse: fields[9] == "-",
+                    blocks: Vec::new(),
+                },
+                target_start,
+                query_start,
+            ));
+        } else {
+            let (_, chain, target_pos, query_pos) = match current.as_mut() {
+                Some(current) => current,
+                None => bail!(
+                    "{} line {} has an alignment block outside of a chain",
+                    path.display(),
+                    i + 1
+                ),
+            };
+            let size = parse(fields[0])?;
+            chain.blocks.push((*target_pos, *query_pos, size));
+            *target_pos += size;
+            *query_pos += size;
+            if fields.len() >= 3 {
+                *target_pos += parse(fields[1])?;
+                *query_pos += parse(fields[2])?;
+            }
+        }
+    }
+    if let Some((target_name, chain, _, _)) = current.take() {
+        chains.entry(target_name).or_default().push(chain);
+    }
+    Ok(chains)
+}
+
+/// Lifts a region through the chain that aligns most of its bases. The lifted
+/// region spans from the first to the last aligned base of the region.
+fn lift_region(chains: &HashMap<String, Vec<Chain>>, region: &Region) -> Option<Lifted> {
+    if region.end <= region.start {
+        return None;
+    }
+    let mut best: Option<(u64, &Chain, u64, u64)> = None;
+    for chain in chains.get(&region.chrom)? {
+        if chain.target_end <= region.start || region.end <= chain.target_start {
+            continue;
+        }
+        let first = chain
+            .blocks
+            .partition_point(|(t_start, _, size)| t_start + size <= region.start);
+        let mut aligned: u64 = 0;
+        let mut query_start: Option<u64> = None;
+        let mut query_end: u64 = 0;
+        for &(t_start, q_start, size) in &chain.blocks[first..] {
+            if t_start >= region.end {
+                break;
+            }
+            let start = t_start.max(region.start);
+            let end = (t_start + size).min(region.end);
+            aligned += end - start;
+            query_start.get_or_insert(q_start + start - t_start);
+            query_end = q_start + end - t_start;
+        }
+        if let Some(query_start) = query_start {
+            if best.is_none_or(|(best_aligned, ..)| aligned > best_aligned) {
+                best = Some((aligned, chain, query_start, query_end));
+            }
+        }
+    }
+
+    let (aligned, chain, query_start, query_end) = best?;
+    let (start, end) = if chain.query_reverse {
+        (chain.query_size - query_end, chain.query_size - query_start)
+    } else {
+        (query_start, query_end)
+    };
+    Some(Lifted {
+        region: Region {
+            chrom: chain.query_name.clone(),
+            start,
+            end,
+        },
+        reverse: chain.query_reverse,
+        mapped_fraction: aligned as f64 / (region.end - region.start) as f64,
+    })
+}
+
+/// The command line arguments of `strif liftover`
+#[derive(Debug, clap::Args)]
+pub struct LiftoverArgs {
+    /// The path to the catalog (.json), profile or merged profile
+    pub input: PathBuf,
+
+    /// The path to a UCSC chain file (optionally gzipped) from the input's build to the target build
+    pub chain: PathBuf,
+
+    /// The path to the lifted output. Defaults to the same directory as the input.
+    pub output: Option<PathBuf>,
+
+    /// Recalculate locus IDs as chrom_start_end from the lifted regions
+    #[clap(short = 'r', long, action)]
+    pub rename: bool,
+
+    /// The path to a table mapping the original loci to the lifted ones
+    #[clap(short = 'm', long)]
+    pub mapping: Option<PathBuf>,
+
+    /// Minimum fraction of a region's bases that must be aligned to lift it
+    #[clap(long, default_value = "0.95")]
+    pub min_match: f64,
+}
+
+impl LiftoverArgs {
+    /// Runs `strif liftover`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let LiftoverArgs {
+            input,
+            chain,
+            output,
+            rename,
+            mapping,
+            min_match,
+        } = self;
+        let ext = input.extension().and_then(|e| e.to_str()).unwrap_or("tsv");
+        let out_path: PathBuf =
+            output.unwrap_or_else(|| get_default_out_path(&input, "lifted", ext));
+        let params = LiftoverParams {
+            rename,
+            mapping,
+            min_match,
+        };
+        Plan::new("liftover", dry_run, force)
+            .input("input", &input)
+            .input("chain", &chain)
+            .output("lifted output", &out_path)
+            .maybe_output("mapping", params.mapping.as_deref())
+            .execute(|| liftover(input, chain, out_path, params))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::testing::{data_rows, merged_record, write_merged_profile_file};
+
+    /// chr1 maps forward onto chrA with a 100 bp gap after its first 600 bp,
+    /// and chr2 maps onto the reverse strand of chrB
+    const CHAIN: &str = "\
+chain 1000 chr1 1000 + 0 1000 chrA 2000 + 500 1400 1
+600 100 0
+300
+
+chain 100 chr2 100 + 0 100 chrB 1000 - 0 100 2
+100
+";
+
+    fn write_chain(dir: &Path) -> PathBuf {
+        let path = dir.join("chain.txt");
+        fs::write(&path, CHAIN).unwrap();
+        path
+    }
+
+    fn lift(chains: &HashMap<String, Vec<Chain>>, region: &str) -> Option<(String, bool, f64)> {
+        lift_region(chains, &region.parse().unwrap())
+            .map(|l| (l.region.to_string(), l.reverse, l.mapped_fraction))
+    }
+
+    #[test]
+    fn regions_are_lifted_through_chain_blocks() {
+        let dir = tempfile::tempdir().unwrap();
+        let chains = load_chains(&write_chain(dir.path())).unwrap();
+
+        assert_eq!(
+            lift(&chains, "chr1:100-200"),
+            Some(("chrA:600-700".to_string(), false, 1.0))
+        );
+        // half of the region falls in the gap between the blocks
+        assert_eq!(
+            lift(&chains, "chr1:550-750"),
+            Some(("chrA:1050-1150".to_string(), false, 0.5))
+        );
+        assert_eq!(
+            lift(&chains, "chr2:10-40"),
+            Some(("chrB:960-990".to_string(), true, 1.0))
+        );
+        assert_eq!(lift(&chains, "chr3:10-40"), None);
+    }
+
+    #[test]
+    fn locus_structures_are_reverse_complemented_unit_by_unit() {
+        assert_eq!(
+            reverse_structure("(CAG)*(CCG)+"),
+            Some("(CGG)+(CTG)*".to_string())
+        );
+        assert_eq!(reverse_structure("(CAG)*GG"), None);
+        assert_eq!(reverse_structure(""), None);
+    }
+
+    #[test]
+    fn catalogs_keep_only_loci_that_lift_well_enough() {
+        let dir = tempfile::tempdir().unwrap();
+        let catalog = dir.path().join("catalog.json");
+        fs::write(
+            &catalog,
+            r#"[
+                {"LocusId": "A", "LocusStructure": "(CAG)*", "ReferenceRegion": "chr1:100-200"},
+                {"LocusId": "B", "LocusStructure": "(CAG)*", "ReferenceRegion": "chr2:10-40"},
+                {"LocusId": "C", "LocusStructure": "(CAG)*", "ReferenceRegion": "chr1:550-750"},
+                {"LocusId": "D", "LocusStructure": "(CAG)*", "ReferenceRegion": "chr3:10-40"}
+            ]"#,
+        )
+        .unwrap();
+        let out_path = dir.path().join("lifted.json");
+        let mapping = dir.path().join("mapping.tsv");
+        liftover(
+            catalog,
+            write_chain(dir.path()),
+            out_path.clone(),
+            LiftoverParams {
+                rename: false,
+                mapping: Some(mapping.clone()),
+                min_match: 0.9,
+            },
+        )
+        .unwrap();
+
+        let lifted: Vec<Value> =
+            serde_json::from_str(&fs::read_to_string(&out_path).unwrap()).unwrap();
+        assert_eq!(lifted.len(), 2);
+        assert_eq!(lifted[0]["ReferenceRegion"], "chrA:600-700");
+        assert_eq!(lifted[1]["LocusId"], "B");
+        assert_eq!(lifted[1]["LocusStructure"], "(CTG)*");
+        assert_eq!(lifted[1]["ReferenceRegion"], "chrB:960-990");
+        assert_eq!(
+            data_rows(&mapping),
+            [
+                "A\tchr1:100-200\tA\tchrA:600-700\t+\t1\tmapped",
+                "B\tchr2:10-40\tB\tchrB:960-990\t-\t1\tmapped",
+                "C\tchr1:550-750\t.\tchrA:1050-1150\t+\t0.5\tpartial",
+                "D\tchr3:10-40\t.\t.\t.\t.\tunmapped",
+            ]
+        );
+    }
+
+    #[test]
+    fn merged_profiles_are_renamed_and_reverse_complemented() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut reverse = merged_record("L1", &[("s1", 10)], &[("s1", "CAT", 0.5)]);
+        reverse.reference_region = "chr2:10-40".to_string();
+        let merged = write_merged_profile_file(
+            dir.path(),
+            "merged.tsv",
+            &[
+                reverse,
+                merged_record("L2", &[("s1", 10)], &[]),
+                merged_record("L3", &[("s1", 10)], &[]),
+            ],
+        );
+        let out_path = dir.path().join("lifted.tsv");
+        liftover(
+            merged,
+            write_chain(dir.path()),
+            out_path.clone(),
+            LiftoverParams {
+                rename: true,
+                mapping: None,
+                min_match: 0.9,
+            },
+        )
+        .unwrap();
+
+        let records = read_merged_profile(&out_path).unwrap();
+        // L3 lifts onto the same locus as L2 and is skipped
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].locus_id, "chrB_960_990");
+        assert_eq!(records[0].motif, "CTG");
+        assert_eq!(
+            records[0].interruption_counts,
+            [("s1".to_string(), "ATG".to_string(), 0.5)]
+        );
+        assert_eq!(records[1].locus_id, "chrA_600_700");
+        assert_eq!(records[1].reference_region, "chrA:600-700");
+    }
+
+    #[test]
+    fn chains_with_reverse_target_strands_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chain.txt");
+        fs::write(
+            &path,
+            "chain 100 chr1 100 - 0 100 chrA 100 + 0 100 1\n100\n",
+        )
+        .unwrap();
+        let err = load_chains(&path).err().unwrap();
+        assert!(err.to_string().contains("reverse target strand"));
+    }
+}
@@ -0,0 +1,170 @@
+use std::io::{self, Write};
+
+use anyhow::{anyhow, Result};
+use log::kv::{self, Key, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde_json::{Map, Value};
+
+/// Formats of the log written to stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines with a timestamp and level
+    Text,
+    /// One JSON object per event with the stage and structured fields
+    Json,
+}
+
+/// Sets up logging of strif's events to stderr. Verbosity 0 only logs errors,
+/// 1 warnings, 2 progress, 3 debugging and 4 or more tracing information.
+pub fn init_logging(verbosity: usize, format: LogFormat) -> Result<()> {
+    match format {
+        LogFormat::Text => stderrlog::new()
+            .module("strif")
+            .verbosity(verbosity)
+            .timestamp(stderrlog::Timestamp::Second)
+            .color(stderrlog::ColorChoice::Never)
+            .init()?,
+        LogFormat::Json => {
+            let level = match verbosity {
+                0 => LevelFilter::Error,
+                1 => LevelFilter::Warn,
+                2 => LevelFilter::Info,
+                3 => LevelFilter::Debug,
+                _ => LevelFilter::Trace,
+            };
+            log::set_boxed_logger(Box::new(JsonLogger { level }))
+                .map_err(|e| anyhow!("Could not set up logging: {}", e))?;
+            log::set_max_level(level);
+        }
+    }
+    Ok(())
+}
+
+/// Writes each event as a JSON line with its time, level, stage (the
+/// subcommand's module), message and key-value fields
+struct JsonLogger {
+    level: LevelFilter,
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+            && (metadata.target() == "strif" || metadata.target().starts_with("strif::"))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut stderr = io::stderr().lock();
+        let _ = writeln!(stderr, "{}", Value::Object(event(record)));
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+/// The JSON object of an event
+fn event(record: &Record) -> Map<String, Value> {
+    let mut event = Map::new();
+    event.insert(
+        "time".to_string(),
+        Value::from(chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)),
+    );
+    event.insert("level".to_string(), Value::from(level_name(record.level())));
+    let stage = record
+        .target()
+        .strip_prefix("strif::")
+        .and_then(|module| module.split("::").next())
+        .unwrap_or("main");
+    event.insert("stage".to_string(), Value::from(stage));
+    event.insert(
+        "message".to_string(),
+        Value::from(record.args().to_string()),
+    );
+    let mut fields = FieldVisitor(&mut event);
+    let _ = record.key_values().visit(&mut fields);
+    event
+}
+
+fn level_name(level: Level) -> &'static str {
+    match level {
+        Level::Error => "error",
+        Level::Warn => "warn",
+        Level::Info => "info",
+        Level::Debug => "debug",
+        Level::Trace => "trace",
+    }
+}
+
+/// Adds the key-value fields of an event to its JSON object, keeping numbers
+/// and booleans as JSON values
+struct FieldVisitor<'a>(&'a mut Map<String, Value>);
+
+impl<'kvs> VisitSource<'kvs> for FieldVisitor<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(n) = value.to_u64() {
+            Value::from(n)
+        } else if let Some(n) = value.to_i64() {
+            Value::from(n)
+        } else if let Some(x) = value.to_f64() {
+            serde_json::Number::from_f64(x)
+                .map(Value::Number)
+                .unwrap_or_else(|| Value::from(x.to_string()))
+        } else if let Some(b) = value.to_bool() {
+            Value::from(b)
+        } else {
+            Value::from(value.to_string())
+        };
+        self.0.insert(key.as_str().to_string(), value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_carry_their_stage_and_typed_fields() {
+        let fields: &[(&str, kv::Value)] = &[
+            ("loci", kv::Value::from(12u64)),
+            ("fraction", kv::Value::from(0.5)),
+            ("resumed", kv::Value::from(true)),
+            ("sample", kv::Value::from("HG002")),
+        ];
+        let event = event(
+            &Record::builder()
+                .level(Level::Info)
+                .target("strif::merge::spill")
+                .args(format_args!("Merged {} loci", 12))
+                .key_values(&fields)
+                .build(),
+        );
+
+        assert_eq!(event["level"], "info");
+        assert_eq!(event["stage"], "merge");
+        assert_eq!(event["message"], "Merged 12 loci");
+        assert_eq!(event["loci"], 12);
+        assert_eq!(event["fraction"], 0.5);
+        assert_eq!(event["resumed"], true);
+        assert_eq!(event["sample"], "HG002");
+        assert!(event["time"].as_str().unwrap().ends_with('Z'));
+    }
+
+    #[test]
+    fn only_events_of_strif_are_logged_at_the_chosen_level() {
+        let logger = JsonLogger {
+            level: LevelFilter::Info,
+        };
+        let metadata = |level: Level, target: &'static str| {
+            Metadata::builder().level(level).target(target).build()
+        };
+        assert!(logger.enabled(&metadata(Level::Warn, "strif::profile")));
+        assert!(logger.enabled(&metadata(Level::Info, "strif")));
+        assert!(!logger.enabled(&metadata(Level::Debug, "strif::profile")));
+        assert!(!logger.enabled(&metadata(Level::Info, "strifx")));
+        assert!(!logger.enabled(&metadata(Level::Error, "rust_htslib")));
+    }
+}
@@ -1,15 +1,64 @@
-use clap::{Parser, Subcommand};
-use extract::extract;
-use merge::merge;
-use profile::profile;
+use std::ffi::OsString;
+use std::num::NonZeroUsize;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::PathBuf;
+use std::process::ExitCode;
 
-use crate::utils::get_default_out_path;
+use anyhow::Result;
+use clap::{CommandFactory, Parser, Subcommand};
+use log::{debug, info};
 
-pub mod extract;
-pub mod merge;
-pub mod profile;
-pub mod utils;
+use strif::annotate::AnnotateArgs;
+use strif::association::AssociationArgs;
+use strif::benchmark::BenchmarkArgs;
+#[cfg(feature = "tui")]
+use strif::browse::BrowseArgs;
+use strif::burden::BurdenArgs;
+use strif::call::CallArgs;
+use strif::cat::CatArgs;
+use strif::catalog::CatalogBuildArgs;
+use strif::compare::CompareArgs;
+use strif::completions::CompletionsArgs;
+use strif::config::{apply_config, ConfigInitArgs};
+use strif::convert::ConvertArgs;
+use strif::dedup::DedupArgs;
+#[cfg(feature = "htslib")]
+use strif::depth::DepthArgs;
+use strif::doctor::DoctorArgs;
+use strif::export::ExportArgs;
+use strif::extract::ExtractArgs;
+use strif::failure::{write_error_json, FailureKind};
+use strif::filter::FilterArgs;
+use strif::igv::IgvArgs;
+use strif::index::IndexArgs;
+use strif::liftover::LiftoverArgs;
+use strif::logging::{init_logging, LogFormat};
+use strif::man::ManArgs;
+use strif::mask::MaskArgs;
+use strif::matrix::MatrixArgs;
+use strif::merge::{MergeArgs, MergeCombineArgs};
+use strif::migrate::MigrateArgs;
+use strif::outlier::OutlierArgs;
+use strif::pca::PcaArgs;
+use strif::plot::{PlotArgs, PlotLocusArgs};
+use strif::profile::{ExtractProfileArgs, ProfileArgs};
+use strif::project::{apply_project, record_run, InitArgs};
+use strif::provenance;
+use strif::query::QueryArgs;
+use strif::report::ReportArgs;
+use strif::run::RunArgs;
+use strif::scratch;
+use strif::serve::ServeArgs;
+use strif::sidecar;
+use strif::simulate::SimulateArgs;
+use strif::stats::StatsArgs;
+use strif::subset::SubsetArgs;
+use strif::threads;
+use strif::track::TrackArgs;
+use strif::validate::ValidateArgs;
+use strif::vcf::VcfArgs;
+use strif::verify::VerifyArgs;
+use strif::watch::WatchArgs;
 
 #[derive(Debug, Parser)]
 #[clap(author, version, about, long_about = None)]
@@ -17,156 +66,327 @@ pub struct App {
     #[clap(short, long, default_value = "2")]
     verbosity: usize,
 
-    #[clap(subcommand)]
-    command: Command,
-}
+    /// The format of the log written to stderr
+    #[clap(long, value_enum, default_value = "text")]
+    log_format: LogFormat,
 
-#[derive(Debug, Subcommand)]
-enum Command {
-    /// Extracts repeat sequences from an ExpansionHunter BAMlet
-    Extract {
-        /// The path to the ExpansionHunter BAMlet
-        bamlet: PathBuf,
+    /// The path to a TOML file with default options, overridden by those on the command line.
+    /// See `strif config init`.
+    #[clap(long, global = true)]
+    config: Option<PathBuf>,
 
-        /// The path to write the repeat sequences to. Defaults to the same directory as the BAMlet.
-        output: Option<PathBuf>,
-    },
-    /// Profiles extracted repeat sequences for interruptions
-    Profile {
-        /// The path to the file containing repeat sequences
-        repeat_seqs: PathBuf,
+    /// Run in a project created by `strif init`, which gives the inputs and outputs and applies
+    /// the project's configuration. Per-sample subcommands (extract, profile, extract-profile,
+    /// call) take the sample with --sample. Each run is recorded in the project's runs.jsonl.
+    #[clap(long, global = true)]
+    project: Option<PathBuf>,
 
-        /// The path to a JSON file containing the catalog of repeat loci
-        str_catalog: PathBuf,
+    /// The path to write the details of a failure to as JSON, e.g. for the retry logic of a
+    /// workflow manager. The exit code also tells the kind of failure.
+    #[clap(long, global = true)]
+    error_json: Option<PathBuf>,
 
-        /// Output visual alignments. Default is false.
-        #[clap(short = 'z', action)]
-        visual_alignments: bool,
+    /// Validate the inputs, count what would be processed and print the plan without doing any
+    /// work
+    #[clap(long, global = true)]
+    dry_run: bool,
 
-        /// The path to the interruption profile output file. Defaults to the same directory as the repeat sequences.
-        output: Option<PathBuf>,
+    /// The number of threads to use, e.g. for decompressing BAM/CRAM files. Defaults to the
+    /// cores available to strif, which respects the CPU quota of containers and batch jobs.
+    #[clap(long, global = true)]
+    threads: Option<NonZeroUsize>,
 
-        /// The path to visual alignments file. Defaults to the same directory as the repeat sequences.
-        output_alignments: Option<PathBuf>,
+    /// The directory to write temporary files to, e.g. the parts `merge --max-memory` spills to
+    /// disk. Each run gets a subdirectory of its own that is removed when it ends. Defaults to
+    /// the directory of the output.
+    #[clap(long, global = true)]
+    tmp_dir: Option<PathBuf>,
 
-        /// Filter locus IDs using a regular expression. Defaults to None.
-        /// This is useful for filtering out loci that are not of interest.
-        #[clap(short = 'f', long)]
-        filter: Option<String>,
+    /// Overwrite outputs that already exist instead of refusing to run
+    #[clap(long, global = true)]
+    force: bool,
 
-        #[clap(short = 'A', default_value = "1")]
-        match_score: i32,
+    /// Leave out the comment lines recording the strif version, command line, catalog checksum
+    /// and times at the top of outputs, e.g. to compare outputs byte for byte
+    #[clap(long, global = true)]
+    no_provenance: bool,
 
-        #[clap(short = 'B', default_value = "8")]
-        mismatch_penalty: i32,
+    /// Write a JSON sidecar next to each output with its SHA-256 checksum, size, number of rows
+    /// and the command line, which `strif verify` checks the outputs against
+    #[clap(long, global = true)]
+    checksums: bool,
 
-        #[clap(short = 'O', default_value = "10")]
-        gap_open_penalty: i32,
+    #[clap(subcommand)]
+    command: Command,
+}
 
-        #[clap(short = 'E', default_value = "1")]
-        gap_extend_penalty: i32,
-    },
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Extracts repeat sequences from an ExpansionHunter BAMlet
+    Extract(ExtractArgs),
+    /// Concatenates the repeat sequences of several extraction runs of a sample, e.g. of its
+    /// per-lane BAMlets, into a single file for profiling
+    Cat(CatArgs),
+    /// Collapses identical repeat sequences of each locus into a single row with a multiplicity
+    Dedup(DedupArgs),
+    /// Masks low-quality bases, low-complexity stretches and adapter residue in repeat sequences
+    Mask(MaskArgs),
+    /// Profiles extracted repeat sequences for interruptions
+    Profile(ProfileArgs),
+    /// Profiles STR interruptions straight from a BAMlet, without writing the repeat sequences
+    ExtractProfile(ExtractProfileArgs),
     /// Merges profiles from multiple BAMlets partioned by case-control status
-    Merge {
-        /// The path to the manifest file containing paths to BAMlets and case-control status
-        manifest: PathBuf,
-
-        /// The path to a TSV file containing the global average read depth for each sample
-        /// This is needed for normalizing the interription counts.
-        /// The file should have two columns: sample ID and read depth.
-        /// The sample ID should match the sample ID in the manifest.
-        read_depths: PathBuf,
-
-        /// The path to the merged profile. Defaults to the same directory as the manifest.
-        output: Option<PathBuf>,
-
-        /// Filter locus IDs using a regular expression. Defaults to None.
-        /// This is useful for filtering out loci that are not of interest.
-        #[clap(short = 'f', long)]
-        filter: Option<String>,
-
-        /// Minimum read count to include in the merged profile. Defaults to 1.
-        /// This is useful for filtering out loci with low coverage.
-        #[clap(short = 'm', long, default_value = "1")]
-        min_read_count: u32,
-
-        /// The sequencing read length. Used for normalizing the interruption counts.
-        #[clap(short = 'l', long, default_value = "150")]
-        read_length: u32,
+    Merge(MergeArgs),
+    /// Calls per-locus interruption genotypes from a per-sample profile
+    Call(CallArgs),
+    /// Compares the interruption profiles of two samples or runs
+    Compare(CompareArgs),
+    /// Annotates a profile or merged profile with genes, genic context and disease loci
+    Annotate(AnnotateArgs),
+    /// Prints summary statistics for repeat sequences, a profile or a merged profile
+    Stats(StatsArgs),
+    /// Validates a catalog, manifest and read depths file before a run
+    Validate(ValidateArgs),
+    /// Checks the environment and probes the inputs of a run, suggesting how to fix the problems found
+    Doctor(DoctorArgs),
+    /// Checks a directory of outputs against the sidecars written with --checksums
+    Verify(VerifyArgs),
+    /// Plots interruption spectra and burden distributions as SVG figures
+    Plot(PlotArgs),
+    /// Plots the reads of a locus as an SVG waterfall, each read colored by how its bases align
+    /// to the pure repeat
+    PlotLocus(PlotLocusArgs),
+    /// Writes the mean interruption burden of each locus of a merged profile as a bedGraph track
+    Track(TrackArgs),
+    /// Writes an IGV batch script or session for reviewing the reads of loci
+    Igv(IgvArgs),
+    /// Runs extract and profile for every sample in a manifest and merges the profiles
+    Run(RunArgs),
+    /// Monitors a directory for new BAMlets or repeat sequences, profiles them as they arrive
+    /// and keeps a merged profile of all samples processed so far
+    Watch(WatchArgs),
+    /// Filters the loci of a profile or merged profile
+    Filter(FilterArgs),
+    /// Writes some of the samples and loci of a merged profile to a new merged profile
+    Subset(SubsetArgs),
+    /// Sorts a merged profile by coordinates, bgzips it and indexes it for fast lookups by `query` and `serve`
+    Index(IndexArgs),
+    /// Prints the loci of a merged profile matching a locus, region, motif or sample
+    Query(QueryArgs),
+    /// Estimates the global average read depth of BAM/CRAM files for use with `merge`
+    #[cfg(feature = "htslib")]
+    Depth(DepthArgs),
+    /// Tests each locus of a merged profile for association between interruption burden and a phenotype
+    Test(AssociationArgs),
+    /// Renders a merged profile into a standalone HTML report
+    Report(ReportArgs),
+    /// Exports a merged profile as a zip of flat CSVs with a data dictionary, for Excel
+    Export(ExportArgs),
+    /// Screens a single sample against a background cohort and ranks loci by outlier score
+    Outlier(OutlierArgs),
+    /// Aggregates interruption burden per gene (or gene set) and tests cases against controls
+    Burden(BurdenArgs),
+    /// Exports a merged profile as a samples x features matrix for machine learning
+    Matrix(MatrixArgs),
+    /// Computes principal components and sample similarities of a merged profile, e.g. to find
+    /// batch effects and sample swaps
+    Pca(PcaArgs),
+    /// Exports a merged profile as a multi-sample VCF with a site per locus
+    Vcf(VcfArgs),
+    /// Remaps the reference regions of a catalog, profile or merged profile to another genome build
+    Liftover(LiftoverArgs),
+    /// Opens an interactive terminal browser to search, sort and inspect the loci of a merged profile
+    #[cfg(feature = "tui")]
+    Browse(BrowseArgs),
+    /// Serves a read-only HTTP/JSON API to query a merged profile by locus, region, sample or motif
+    Serve(ServeArgs),
+    /// Builds and manages catalogs of repeat loci
+    Catalog {
+        #[clap(subcommand)]
+        command: CatalogCommand,
+    },
+    /// Converts an extract, profile or merge output between TSV, JSON, JSONL and Parquet
+    Convert(ConvertArgs),
+    /// Rewrites extract, profile and merge outputs of earlier strif releases in the current schema
+    Migrate(MigrateArgs),
+    /// Simulates repeat sequences with known interruptions from a catalog of repeat loci
+    Simulate(SimulateArgs),
+    /// Profiles simulated reads with known interruptions under a grid of alignment scores,
+    /// reporting the sensitivity, false positive rate and throughput of each
+    Benchmark(BenchmarkArgs),
+    /// Creates a project directory with a configuration, a manifest template and an output layout
+    Init(InitArgs),
+    /// Manages configuration files
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
     },
+    /// Prints a shell completion script to stdout
+    Completions(CompletionsArgs),
+    /// Writes manual pages for strif and each of its subcommands
+    Man(ManArgs),
+    /// Combines merged profiles produced by `merge --shard` into a single merged profile
+    MergeCombine(MergeCombineArgs),
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let app: App = App::parse();
+#[derive(Debug, Subcommand)]
+enum ConfigCommand {
+    /// Writes a configuration file listing every option with its default value
+    Init(ConfigInitArgs),
+}
 
-    // Set up logging
-    stderrlog::new()
-        .module(module_path!())
-        .verbosity(app.verbosity)
-        .timestamp(stderrlog::Timestamp::Second)
-        .color(stderrlog::ColorChoice::Never)
-        .init()
-        .unwrap();
-
-    // Match the subcommand and call relevant function with arguments
-    match app.command {
-        Command::Extract { bamlet, output } => {
-            let out_path: PathBuf =
-                output.unwrap_or_else(|| get_default_out_path(&bamlet, "repeat_seqs", "tsv"));
-            extract(bamlet, out_path)?;
+#[derive(Debug, Subcommand)]
+enum CatalogCommand {
+    /// Builds an ExpansionHunter-compatible catalog from repeat annotations and a reference genome
+    Build(CatalogBuildArgs),
+}
+
+fn main() -> ExitCode {
+    let raw_args: Vec<OsString> = std::env::args_os().collect();
+    let args = match apply_project(&App::command(), raw_args.clone())
+        .and_then(|args| apply_config(&App::command(), args))
+    {
+        Ok(args) => args,
+        Err(e) => return fail(&e, find_error_json(&raw_args)),
+    };
+    let app: App = App::parse_from(&args);
+    let error_json = app.error_json.clone();
+    let project_dir = app.project.clone().filter(|_| !app.dry_run);
+    if !app.no_provenance {
+        provenance::record_command(&args);
+    }
+    if app.checksums {
+        sidecar::enable();
+    }
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| run_command(app)));
+    if let Some(project_dir) = project_dir {
+        let succeeded = matches!(result, Ok(Ok(())));
+        if let Err(e) = record_run(&project_dir, &args, succeeded) {
+            eprintln!("Error: {:?}", e);
         }
-        Command::Profile {
-            repeat_seqs,
-            str_catalog,
-            visual_alignments,
-            output,
-            output_alignments,
-            match_score,
-            mismatch_penalty,
-            gap_open_penalty,
-            gap_extend_penalty,
-            filter,
-        } => {
-            let align_parms = utils::AlignmentScoreParams {
-                match_score,
-                mismatch_penalty,
-                gap_open_penalty,
-                gap_extend_penalty,
-            };
-            let out_path: PathBuf = output
-                .unwrap_or_else(|| get_default_out_path(&repeat_seqs, "strif_profile", "tsv"));
-            let output_alns_path: PathBuf = output_alignments
-                .unwrap_or_else(|| get_default_out_path(&repeat_seqs, "viz_align", "txt"));
-            profile(
-                repeat_seqs,
-                str_catalog,
-                out_path,
-                output_alns_path,
-                align_parms,
-                visual_alignments,
-                filter,
-            )?;
+    }
+    match result {
+        Ok(Ok(())) => ExitCode::SUCCESS,
+        Ok(Err(e)) => fail(&e, error_json),
+        // the panic hook has already printed the message
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "strif panicked".to_string());
+            report_failure(FailureKind::Internal, &message, None, error_json)
         }
-        Command::Merge {
-            manifest,
-            read_depths,
-            output,
-            filter,
-            min_read_count,
-            read_length,
-        } => {
-            let out_path: PathBuf =
-                output.unwrap_or_else(|| get_default_out_path(&manifest, "merged_profile", "tsv"));
-            merge(
-                manifest,
-                read_depths,
-                out_path,
-                filter,
-                min_read_count,
-                read_length,
-            )?;
+    }
+}
+
+/// Prints an error and returns the exit code of its kind of failure
+fn fail(error: &anyhow::Error, error_json: Option<PathBuf>) -> ExitCode {
+    eprintln!("Error: {:?}", error);
+    report_failure(
+        FailureKind::of(error),
+        &error.to_string(),
+        Some(error),
+        error_json,
+    )
+}
+
+fn report_failure(
+    kind: FailureKind,
+    message: &str,
+    error: Option<&anyhow::Error>,
+    error_json: Option<PathBuf>,
+) -> ExitCode {
+    if let Some(path) = error_json {
+        if let Err(e) = write_error_json(&path, kind, message, error) {
+            eprintln!("Error: {:?}", e);
         }
     }
+    ExitCode::from(kind.exit_code())
+}
+
+/// Finds the path given to `--error-json` in arguments that could not be
+/// processed, e.g. because of an invalid config file
+fn find_error_json(args: &[OsString]) -> Option<PathBuf> {
+    App::command()
+        .ignore_errors(true)
+        .try_get_matches_from(args)
+        .ok()?
+        .get_one::<PathBuf>("error_json")
+        .cloned()
+}
+
+fn run_command(app: App) -> Result<()> {
+    // Set up logging
+    init_logging(app.verbosity, app.log_format)?;
+    if let Some(config) = &app.config {
+        info!("Using defaults from {}", config.display());
+    }
+    if let Some(threads) = app.threads {
+        threads::set_threads(threads);
+    }
+    debug!("Using {} thread(s)", threads::threads());
+    if let Some(tmp_dir) = app.tmp_dir {
+        scratch::set_tmp_dir(tmp_dir);
+    }
 
-    Ok(())
+    // Run the subcommand, or print what it would do
+    let dry_run = app.dry_run;
+    let force = app.force;
+    match app.command {
+        Command::Extract(args) => args.execute(dry_run, force),
+        Command::Cat(args) => args.execute(dry_run, force),
+        Command::Dedup(args) => args.execute(dry_run, force),
+        Command::Mask(args) => args.execute(dry_run, force),
+        Command::Profile(args) => args.execute(dry_run, force),
+        Command::ExtractProfile(args) => args.execute(dry_run, force),
+        Command::Merge(args) => args.execute(dry_run, force),
+        Command::Call(args) => args.execute(dry_run, force),
+        Command::Compare(args) => args.execute(dry_run, force),
+        Command::Annotate(args) => args.execute(dry_run, force),
+        Command::Stats(args) => args.execute(dry_run, force),
+        Command::Validate(args) => args.execute(dry_run, force),
+        Command::Doctor(args) => args.execute(dry_run, force),
+        Command::Verify(args) => args.execute(dry_run, force),
+        Command::Plot(args) => args.execute(dry_run, force),
+        Command::PlotLocus(args) => args.execute(dry_run, force),
+        Command::Track(args) => args.execute(dry_run, force),
+        Command::Igv(args) => args.execute(dry_run, force),
+        Command::Run(args) => args.execute(dry_run, force),
+        Command::Watch(args) => args.execute(dry_run, force),
+        Command::Index(args) => args.execute(dry_run, force),
+        Command::Query(args) => args.execute(dry_run, force),
+        Command::Filter(args) => args.execute(dry_run, force),
+        Command::Subset(args) => args.execute(dry_run, force),
+        #[cfg(feature = "htslib")]
+        Command::Depth(args) => args.execute(dry_run, force),
+        Command::Test(args) => args.execute(dry_run, force),
+        Command::Report(args) => args.execute(dry_run, force),
+        Command::Export(args) => args.execute(dry_run, force),
+        Command::Outlier(args) => args.execute(dry_run, force),
+        Command::Burden(args) => args.execute(dry_run, force),
+        Command::Matrix(args) => args.execute(dry_run, force),
+        Command::Pca(args) => args.execute(dry_run, force),
+        Command::Vcf(args) => args.execute(dry_run, force),
+        Command::Liftover(args) => args.execute(dry_run, force),
+        #[cfg(feature = "tui")]
+        Command::Browse(args) => args.execute(dry_run, force),
+        Command::Serve(args) => args.execute(dry_run, force),
+        Command::Catalog { command } => match command {
+            CatalogCommand::Build(args) => args.execute(dry_run, force),
+        },
+        // these describe the command line itself
+        Command::Init(args) => args.execute(App::command(), dry_run, force),
+        Command::Config { command } => match command {
+            ConfigCommand::Init(args) => args.execute(App::command(), dry_run, force),
+        },
+        Command::Completions(args) => args.execute(App::command(), dry_run, force),
+        Command::Man(args) => args.execute(App::command(), dry_run, force),
+        Command::Convert(args) => args.execute(dry_run, force),
+        Command::Migrate(args) => args.execute(dry_run, force),
+        Command::Simulate(args) => args.execute(dry_run, force),
+        Command::Benchmark(args) => args.execute(dry_run, force),
+        Command::MergeCombine(args) => args.execute(dry_run, force),
+    }
 }
@@ -0,0 +1,70 @@
+use std::{fs, path::PathBuf};
+
+use crate::plan::Plan;
+use anyhow::{Context, Result};
+use clap::Command;
+use log::info;
+
+/// Writes a manual page for the command and one for each of its subcommands
+/// (e.g. `strif.1` and `strif-merge.1`) to a directory
+pub fn man(command: Command, out_dir: PathBuf) -> Result<()> {
+    fs::create_dir_all(&out_dir)
+        .with_context(|| format!("Could not create {}", out_dir.display()))?;
+    clap_mangen::generate_to(command, &out_dir)
+        .with_context(|| format!("Could not write manual pages to {}", out_dir.display()))?;
+    info!("Wrote manual pages to {}", out_dir.display());
+    info!("Done!");
+    Ok(())
+}
+
+/// The command line arguments of `strif man`
+#[derive(Debug, clap::Args)]
+pub struct ManArgs {
+    /// The directory to write the manual pages to
+    #[clap(short, long, default_value = ".")]
+    pub output_dir: PathBuf,
+}
+
+impl ManArgs {
+    /// Writes the manual pages of a command line, or prints the plan with
+    /// `--dry-run`
+    pub fn execute(self, command: Command, dry_run: bool, force: bool) -> Result<()> {
+        Plan::new("man", dry_run, force)
+            .note("manual pages", self.output_dir.join("strif.1").display())
+            .execute(|| man(command, self.output_dir))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_subcommand_gets_a_manual_page() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = Command::new("strif")
+            .about("Profiles repeat interruptions")
+            .subcommand(Command::new("merge").about("Merges profiles"))
+            .subcommand(
+                Command::new("catalog").subcommand(Command::new("build").about("Builds a catalog")),
+            );
+        man(command, dir.path().join("man")).unwrap();
+
+        let mut pages: Vec<String> = fs::read_dir(dir.path().join("man"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        pages.sort();
+        assert_eq!(
+            pages,
+            [
+                "strif-catalog-build.1",
+                "strif-catalog.1",
+                "strif-merge.1",
+                "strif.1"
+            ]
+        );
+        let page = fs::read_to_string(dir.path().join("man/strif-merge.1")).unwrap();
+        assert!(page.contains("Merges profiles"));
+    }
+}
@@ -0,0 +1,356 @@
+use std::ops::Range;
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use log::{debug, info};
+
+use crate::error::StrifError;
+use crate::extract::{decode_qualities, encode_qualities, read_repeat_seqs};
+use crate::interruptions::is_pure_repeat;
+use crate::plan::Plan;
+use crate::profile::load_str_catalog;
+use crate::schema::{self, OutputKind};
+use crate::utils::{create_output, get_default_out_path};
+
+/// The base that masked bases are replaced with
+const MASK_BASE: u8 = b'N';
+
+/// The quality of masked bases, so that `profile --quality-weighted` gives
+/// the interruptions they make no weight
+const MASK_QUALITY: u8 = 0;
+
+/// The periods of the stretches `--low-complexity` looks for: homopolymers
+/// and dinucleotide repeats, the usual leftovers of adapters, poly-A tails
+/// and polymerase slippage
+const LOW_COMPLEXITY_PERIODS: [usize; 2] = [1, 2];
+
+/// What `mask` cleans up in each repeat sequence
+pub struct MaskParams {
+    /// Adapter sequences whose residues are trimmed off the 3' end of reads
+    pub adapters: Vec<String>,
+    /// The fewest bases of an adapter a sequence must end in to be trimmed
+    pub min_adapter_overlap: usize,
+    /// Bases below this Phred quality are masked
+    pub min_quality: Option<u8>,
+    /// Homopolymer and dinucleotide stretches of at least this many bases
+    /// that are not the motif of their locus are masked
+    pub low_complexity: Option<usize>,
+}
+
+impl MaskParams {
+    fn is_set(&self) -> bool {
+        !self.adapters.is_empty() || self.min_quality.is_some() || self.low_complexity.is_some()
+    }
+}
+
+/// The reads and bases that `mask` changed
+#[derive(Debug, Default)]
+struct MaskTally {
+    /// Reads with adapter residue trimmed off
+    trimmed_reads: u64,
+    /// Reads dropped because their whole repeat sequence was adapter
+    dropped_reads: u64,
+    low_quality_bases: u64,
+    low_complexity_bases: u64,
+    /// Reads of loci missing from the catalog, which are written unmasked
+    /// except for adapters and qualities
+    unknown_locus_reads: u64,
+}
+
+/// Cleans the repeat sequences of a file before profiling: trims the residue
+/// of adapters off their ends, and replaces bases below a quality and
+/// low-complexity stretches that are not the motif of their locus with `N`.
+/// The output has the columns of the input, so `profile` reads it as it
+/// would the repeat sequences of `extract`.
+pub fn mask(
+    repeat_seqs: PathBuf,
+    str_catalog: PathBuf,
+    out_path: PathBuf,
+    params: MaskParams,
+) -> Result<()> {
+    if !params.is_set() {
+        bail!("Nothing to mask, pass --adapter, --min-quality or --low-complexity");
+    }
+    info!("Loading STR catalog...");
+    let (motifs, _, _, _, _) = load_str_catalog(str_catalog, None)?;
+    let adapters: Vec<Vec<u8>> = params
+        .adapters
+        .iter()
+        .map(|adapter| adapter.to_ascii_uppercase().into_bytes())
+        .collect();
+
+    info!("Masking repeat sequences...");
+    let mut tally = MaskTally::default();
+    let mut reads: u64 = 0;
+    let mut out_file = create_output(&out_path)?;
+    schema::write_header(&mut out_file, OutputKind::RepeatSeqs)?;
+    for mut record in read_repeat_seqs(&repeat_seqs)? {
+        let multiplicity = record.multiplicity.unwrap_or(1) as u64;
+        reads += multiplicity;
+        let mut seq = record.seq.into_bytes();
+        let mut qualities = record.qualities.as_deref().and_then(decode_qualities);
+        if qualities
+            .as_ref()
+            .is_some_and(|qualities| qualities.len() != seq.len())
+        {
+            bail!(
+                "A repeat sequence of locus {} in {} and its qualities differ in length",
+                record.locus_id,
+                repeat_seqs.display()
+            );
+        }
+
+        if let Some(start) = adapter_start(&seq, &adapters, params.min_adapter_overlap) {
+            if start == 0 {
+                debug!("Dropping a read of {} that is all adapter", record.locus_id);
+                tally.dropped_reads += multiplicity;
+                continue;
+            }
+            seq.truncate(start);
+            if let Some(qualities) = qualities.as_mut() {
+                qualities.truncate(start);
+            }
+            tally.trimmed_reads += multiplicity;
+        }
+
+        let mut masked = vec![false; seq.len()];
+        if let (Some(min_quality), Some(qualities)) = (params.min_quality, qualities.as_ref()) {
+            for (masked, q) in masked.iter_mut().zip(qualities) {
+                if *q < min_quality {
+                    *masked = true;
+                    tally.low_quality_bases += multiplicity;
+                }
+            }
+        }
+        if let Some(min_len) = params.low_complexity {
+            match motifs.get(&record.locus_id) {
+                Some(motif) => {
+                    for stretch in low_complexity_stretches(&seq, motif.as_bytes(), min_len) {
+                        for masked in &mut masked[stretch] {
+                            if !*masked {
+                                *masked = true;
+                                tally.low_complexity_bases += multiplicity;
+                            }
+                        }
+                    }
+                }
+                None => tally.unknown_locus_reads += multiplicity,
+            }
+        }
+        for (i, _) in masked.iter().enumerate().filter(|(_, masked)| **masked) {
+            seq[i] = MASK_BASE;
+            if let Some(qualities) = qualities.as_mut() {
+                qualities[i] = MASK_QUALITY;
+            }
+        }
+
+        record.seq = String::from_utf8(seq).expect("repeat sequences are ASCII");
+        if let Some(qualities) = &qualities {
+            record.qualities = Some(encode_qualities(qualities));
+        }
+        record.write_to(&mut out_file)?;
+    }
+    out_file.finish()?;
+
+    info!(
+        reads, trimmed_reads = tally.trimmed_reads, dropped_reads = tally.dropped_reads,
+        low_quality_bases = tally.low_quality_bases,
+        low_complexity_bases = tally.low_complexity_bases;
+        "Masked {} reads: trimmed adapter residue off {} and dropped {} that were all adapter, masked {} low-quality and {} low-complexity bases",
+        reads,
+        tally.trimmed_reads,
+        tally.dropped_reads,
+        tally.low_quality_bases,
+        tally.low_complexity_bases
+    );
+    if tally.unknown_locus_reads > 0 {
+        info!(
+            unknown_locus_reads = tally.unknown_locus_reads;
+            "{} reads are of loci missing from the catalog, so their low-complexity stretches were not masked",
+            tally.unknown_locus_reads
+        );
+    }
+    if reads == tally.dropped_reads {
+        return Err(StrifError::EmptyOutput {
+            path: out_path.display().to_string(),
+            message: format!("{} has no repeat sequences left", repeat_seqs.display()),
+        }
+        .into());
+    }
+
+    info!("Done!");
+    Ok(())
+}
+
+/// Where the earliest adapter starts in a repeat sequence, either whole or
+/// as a prefix of at least `min_overlap` bases running to its end, as a read
+/// reads into the adapter past a short fragment
+fn adapter_start(seq: &[u8], adapters: &[Vec<u8>], min_overlap: usize) -> Option<usize> {
+    adapters
+        .iter()
+        .filter_map(|adapter| {
+            (0..seq.len()).find(|&start| {
+                let rest = &seq[start..];
+                let overlap = rest.len().min(adapter.len());
+                overlap >= min_overlap.min(adapter.len())
+                    && rest[..overlap].eq_ignore_ascii_case(&adapter[..overlap])
+            })
+        })
+        .min()
+}
+
+/// The homopolymer and dinucleotide stretches of a repeat sequence of at
+/// least `min_len` bases that are not themselves a repeat of the motif, e.g.
+/// a poly-A run within a CGG repeat
+fn low_complexity_stretches(seq: &[u8], motif: &[u8], min_len: usize) -> Vec<Range<usize>> {
+    let mut stretches = Vec::new();
+    for period in LOW_COMPLEXITY_PERIODS {
+        let mut start = 0;
+        for end in period..=seq.len() {
+            let repeats = end < seq.len()
+                && seq[end].eq_ignore_ascii_case(&seq[end - period])
+                && seq[end] != MASK_BASE;
+            if repeats {
+                continue;
+            }
+            let stretch = start..end;
+            if stretch.len() >= min_len.max(period + 1)
+                && !is_pure_repeat(&seq[stretch.clone()].to_ascii_uppercase(), motif)
+            {
+                stretches.push(stretch);
+            }
+            start = end + 1 - period;
+        }
+    }
+    stretches
+}
+
+/// The command line arguments of `strif mask`
+#[derive(Debug, clap::Args)]
+pub struct MaskArgs {
+    /// The path to the repeat sequences
+    pub repeat_seqs: PathBuf,
+
+    /// The path to a JSON file containing the catalog of repeat loci
+    pub str_catalog: PathBuf,
+
+    /// The path to write the masked repeat sequences to. Defaults to the same directory as the input.
+    pub output: Option<PathBuf>,
+
+    /// An adapter sequence to trim off the end of repeat sequences that read into it, e.g.
+    /// AGATCGGAAGAGC for Illumina TruSeq. Can be given several times.
+    #[clap(long)]
+    pub adapter: Vec<String>,
+
+    /// The fewest bases of an adapter that a repeat sequence must end in to be trimmed
+    #[clap(long, default_value = "8")]
+    pub min_adapter_overlap: usize,
+
+    /// Replace bases below this Phred quality with N, for repeat sequences with qualities
+    #[clap(long)]
+    pub min_quality: Option<u8>,
+
+    /// Replace homopolymer and dinucleotide stretches of at least this many bases that are
+    /// not the motif of their locus with N
+    #[clap(long)]
+    pub low_complexity: Option<usize>,
+}
+
+impl MaskArgs {
+    /// Runs `strif mask`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let MaskArgs {
+            repeat_seqs,
+            str_catalog,
+            output,
+            adapter,
+            min_adapter_overlap,
+            min_quality,
+            low_complexity,
+        } = self;
+        let out_path: PathBuf = output
+            .unwrap_or_else(|| get_default_out_path(&repeat_seqs, "masked_repeat_seqs", "tsv"));
+        let params = MaskParams {
+            adapters: adapter,
+            min_adapter_overlap,
+            min_quality,
+            low_complexity,
+        };
+        Plan::new("mask", dry_run, force)
+            .records("repeat sequences", &repeat_seqs, "reads")
+            .catalog(&str_catalog, None)
+            .output("masked repeat sequences", &out_path)
+            .execute(|| mask(repeat_seqs, str_catalog, out_path, params))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::testing::write_catalog;
+
+    #[test]
+    fn adapters_are_found_whole_or_running_off_the_end() {
+        let adapters = [b"AGATCGGAAGAGC".to_vec()];
+        assert_eq!(adapter_start(b"CAGCAGAGATCGG", &adapters, 5), Some(6));
+        assert_eq!(adapter_start(b"agatcggaagagcCAG", &adapters, 5), Some(0));
+        // four bases of the adapter are too few to tell it from the repeat
+        assert_eq!(adapter_start(b"CAGCAGCAGAGAT", &adapters, 5), None);
+        assert_eq!(adapter_start(b"CAGCAGCAG", &[], 5), None);
+    }
+
+    #[test]
+    fn low_complexity_stretches_other_than_the_motif_are_found() {
+        assert_eq!(
+            low_complexity_stretches(b"CGGCGGAAAAAACGGCGG", b"CGG", 5),
+            [6..12, 6..12]
+        );
+        assert_eq!(
+            low_complexity_stretches(b"CGGCGGATATATCGGCGG", b"CGG", 5),
+            vec![Range { start: 6, end: 12 }]
+        );
+        // stretches of the motif itself are the repeat
+        assert!(low_complexity_stretches(b"AAAAAAAA", b"A", 5).is_empty());
+        assert!(low_complexity_stretches(b"CGGAAAACGG", b"CGG", 5).is_empty());
+    }
+
+    #[test]
+    fn masked_repeat_sequences_keep_the_columns_of_the_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let repeat_seqs = dir.path().join("sample.repeat_seqs.tsv");
+        fs::write(
+            &repeat_seqs,
+            "HTT\tCAGCAGAAAAAACAGCAG\tI#IIIIIIIIIIIIIIII\t1\n\
+             HTT\tCAGCAGAGATCGGAAGAGC\t*\t1\n\
+             FMR1\tAGATCGGAAGAGC\t*\t2\n",
+        )
+        .unwrap();
+        let out = dir.path().join("sample.masked.repeat_seqs.tsv");
+        let params = MaskParams {
+            adapters: vec!["agatcggaagagc".to_string()],
+            min_adapter_overlap: 5,
+            min_quality: Some(20),
+            low_complexity: Some(5),
+        };
+        mask(repeat_seqs, write_catalog(dir.path()), out.clone(), params).unwrap();
+        let records: Vec<(String, Option<String>)> = read_repeat_seqs(&out)
+            .unwrap()
+            .into_iter()
+            .map(|record| (record.seq, record.qualities))
+            .collect();
+        // the low-quality base and the poly-A run are masked, the adapter
+        // trimmed off and the read that is all adapter dropped
+        assert_eq!(
+            records,
+            [
+                (
+                    "CNGCAGNNNNNNCAGCAG".to_string(),
+                    Some("I!IIII!!!!!!IIIIII".to_string())
+                ),
+                ("CAGCAG".to_string(), Some("*".to_string())),
+            ]
+        );
+    }
+}
@@ -0,0 +1,576 @@
+use std::io::prelude::*;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+use log::info;
+
+use crate::merge::read_merged_profile;
+use crate::plan::Plan;
+use crate::schema::{self, OutputKind};
+use crate::utils::{create_output, get_default_out_path};
+
+/// File formats understood by `matrix`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum MatrixFormat {
+    /// Comma-separated values with a header row and a sample_id column
+    Csv,
+    /// Tab-separated values with a header row and a sample_id column
+    Tsv,
+    /// Apache Parquet with a sample_id column (requires the `parquet` feature)
+    Parquet,
+    /// A NumPy .npy array of float64, with sample IDs in a .samples.txt file
+    Npy,
+}
+
+impl MatrixFormat {
+    fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "csv" => Some(MatrixFormat::Csv),
+            "tsv" | "txt" => Some(MatrixFormat::Tsv),
+            "parquet" | "pq" => Some(MatrixFormat::Parquet),
+            "npy" => Some(MatrixFormat::Npy),
+            _ => None,
+        }
+    }
+}
+
+/// Groups of features that can be exported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum FeatureKind {
+    /// The total normalized interruption count of each locus
+    Burden,
+    /// The normalized count of each interruption at each locus
+    Interruption,
+    /// Per-sample purity statistics summarized over all covered loci
+    Purity,
+}
+
+impl FeatureKind {
+    fn name(&self) -> &'static str {
+        match self {
+            FeatureKind::Burden => "burden",
+            FeatureKind::Interruption => "interruption",
+            FeatureKind::Purity => "purity",
+        }
+    }
+}
+
+pub struct MatrixParams {
+    pub format: Option<MatrixFormat>,
+    pub features: Vec<FeatureKind>,
+    /// Minimum read count for a sample to be considered covered at a locus
+    pub min_read_count: u32,
+    /// Minimum number of samples with a nonzero count to keep an interruption feature
+    pub min_samples: usize,
+    /// The value of features at loci a sample does not cover. Defaults to NaN.
+    pub fill: Option<f64>,
+}
+
+/// Per-sample purity statistics (name, description)
+const PURITY_STATS: [(&str, &str); 4] = [
+    ("covered_loci", "Number of loci covered by the sample"),
+    (
+        "pure_fraction",
+        "Fraction of covered loci without interruptions",
+    ),
+    ("mean_burden", "Mean interruption burden over covered loci"),
+    (
+        "max_burden",
+        "Maximum interruption burden over covered loci",
+    ),
+];
+
+/// A column of the matrix with its entry in the feature dictionary
+pub(crate) struct Feature {
+    pub name: String,
+    pub kind: FeatureKind,
+    pub locus_id: String,
+    pub interruption: String,
+    pub description: String,
+    /// The value of each sample, in the order of the matrix rows
+    pub values: Vec<f64>,
+}
+
+/// Exports a merged profile as a samples x features numeric matrix along with
+/// a feature dictionary describing each column
+pub fn matrix(merged_profile: PathBuf, out_path: PathBuf, params: MatrixParams) -> Result<()> {
+    let format = match params.format.or_else(|| MatrixFormat::from_path(&out_path)) {
+        Some(format) => format,
+        None => bail!(
+            "Could not infer the format of {} from its extension, use --format",
+            out_path.display()
+        ),
+    };
+    if params.features.is_empty() {
+        bail!("At least one kind of feature must be selected");
+    }
+
+    let (samples, features) = build_features(&merged_profile, &params)?;
+    info!(
+        "Writing {} samples x {} features...",
+        samples.len(),
+        features.len()
+    );
+
+    match format {
+        MatrixFormat::Csv => write_delimited(&samples, &features, &out_path, b',')?,
+        MatrixFormat::Tsv => write_delimited(&samples, &features, &out_path, b'\t')?,
+        MatrixFormat::Parquet => parquet_io::write(&samples, &features, &out_path)?,
+        MatrixFormat::Npy => {
+            write_npy(&samples, &features, &out_path)?;
+            let samples_path = out_path.with_extension("samples.txt");
+            let mut samples_file = create_output(&samples_path)?;
+            for sample in &samples {
+                writeln!(samples_file, "{}", sample)?;
+            }
+            samples_file.finish()?;
+        }
+    }
+    write_dictionary(&features, &out_path.with_extension("features.tsv"))?;
+
+    info!("Done!");
+    Ok(())
+}
+
+/// Loads a merged profile and builds the features of each of its samples,
+/// in the order of their IDs
+pub(crate) fn build_features(
+    merged_profile: &Path,
+    params: &MatrixParams,
+) -> Result<(Vec<String>, Vec<Feature>)> {
+    info!("Loading merged profile...");
+    let records = read_merged_profile(merged_profile)?;
+    let samples: Vec<String> = records
+        .iter()
+        .flat_map(|r| r.read_counts.iter().map(|(id, _)| id.clone()))
+        .collect::<BTreeSet<String>>()
+        .into_iter()
+        .collect();
+    if samples.is_empty() {
+        bail!("{} has no samples", merged_profile.display());
+    }
+    let sample_index: HashMap<&str, usize> = samples
+        .iter()
+        .enumerate()
+        .map(|(i, id)| (id.as_str(), i))
+        .collect();
+    let missing = params.fill.unwrap_or(f64::NAN);
+
+    info!("Building features...");
+    let mut features: Vec<Feature> = Vec::new();
+    // per-sample burdens at each covered locus, for the purity features
+    let mut covered_burdens: Vec<Vec<f64>> = vec![Vec::new(); samples.len()];
+    for record in &records {
+        let mut covered = vec![false; samples.len()];
+        for (id, read_count) in &record.read_counts {
+            if *read_count >= params.min_read_count {
+                covered[sample_index[id.as_str()]] = true;
+            }
+        }
+        let mut burdens = vec![0.0; samples.len()];
+        let mut interruptions: BTreeMap<&str, Vec<f64>> = BTreeMap::new();
+        for (id, interruption, count) in &record.interruption_counts {
+            let i = match sample_index.get(id.as_str()) {
+                Some(&i) if covered[i] => i,
+                _ => continue,
+            };
+            burdens[i] += count;
+            interruptions
+                .entry(interruption)
+                .or_insert_with(|| vec![0.0; samples.len()])[i] += count;
+        }
+        for (i, burden) in burdens.iter().enumerate() {
+            if covered[i] {
+                covered_burdens[i].push(*burden);
+            }
+        }
+        let mask = |values: Vec<f64>| -> Vec<f64> {
+            values
+                .into_iter()
+                .zip(&covered)
+                .map(|(v, c)| if *c { v } else { missing })
+                .collect()
+        };
+
+        if params.features.contains(&FeatureKind::Burden) {
+            features.push(Feature {
+                name: format!("{}:burden", record.locus_id),
+                kind: FeatureKind::Burden,
+                locus_id: record.locus_id.clone(),
+                interruption: ".".to_string(),
+                description: format!(
+                    "Total normalized interruption count of the {} repeat at {}",
+                    record.motif, record.reference_region
+                ),
+                values: mask(burdens),
+            });
+        }
+        if params.features.contains(&FeatureKind::Interruption) {
+            for (interruption, values) in interruptions {
+                if values.iter().filter(|v| **v > 0.0).count() < params.min_samples {
+                    continue;
+                }
+                features.push(Feature {
+                    name: format!("{}:{}", record.locus_id, interruption),
+                    kind: FeatureKind::Interruption,
+                    locus_id: record.locus_id.clone(),
+                    interruption: interruption.to_string(),
+                    description: format!(
+                        "Normalized count of the {} interruption in the {} repeat at {}",
+                        interruption, record.motif, record.reference_region
+                    ),
+                    values: mask(values),
+                });
+            }
+        }
+    }
+
+    if params.features.contains(&FeatureKind::Purity) {
+        for (name, description) in PURITY_STATS {
+            features.push(Feature {
+                name: format!("purity:{}", name),
+                kind: FeatureKind::Purity,
+                locus_id: ".".to_string(),
+                interruption: ".".to_string(),
+                description: description.to_string(),
+                values: covered_burdens
+                    .iter()
+                    .map(|b| {
+                        if b.is_empty() {
+                            missing
+                        } else {
+                            purity_stat(name, b)
+                        }
+                    })
+                    .collect(),
+            });
+        }
+    }
+    Ok((samples, features))
+}
+
+/// Computes a purity statistic from the burdens of a sample at its covered loci
+fn purity_stat(name: &str, burdens: &[f64]) -> f64 {
+    let n = burdens.len() as f64;
+    match name {
+        "covered_loci" => n,
+        "pure_fraction" => burdens.iter().filter(|b| **b == 0.0).count() as f64 / n,
+        "mean_burden" => burdens.iter().sum::<f64>() / n,
+        "max_burden" => burdens.iter().copied().fold(f64::NAN, f64::max),
+        _ => unreachable!(),
+    }
+}
+
+fn write_delimited(
+    samples: &[String],
+    features: &[Feature],
+    out_path: &Path,
+    delimiter: u8,
+) -> Result<()> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(create_output(out_path)?);
+    writer.write_record(
+        std::iter::once("sample_id").chain(features.iter().map(|f| f.name.as_str())),
+    )?;
+    for (i, sample) in samples.iter().enumerate() {
+        writer.write_record(
+            std::iter::once(sample.clone()).chain(features.iter().map(|f| f.values[i].to_string())),
+        )?;
+    }
+    writer.into_inner().map_err(|e| e.into_error())?.finish()?;
+    Ok(())
+}
+
+/// Writes a row-major float64 array in the NumPy .npy format (version 1.0)
+fn write_npy(samples: &[String], features: &[Feature], out_path: &Path) -> Result<()> {
+    let mut header = format!(
+        "{{'descr': '<f8', 'fortran_order': False, 'shape': ({}, {}), }}",
+        samples.len(),
+        features.len()
+    );
+    // the magic string, version and header length take 10 bytes, and the
+    // header is padded so that the data starts on a 64-byte boundary
+    let padding = 64 - (10 + header.len() + 1) % 64;
+    header.push_str(&" ".repeat(padding % 64));
+    header.push('\n');
+
+    let mut out_file = create_output(out_path)?;
+    out_file.write_all(b"\x93NUMPY\x01\x00")?;
+    out_file.write_all(&(header.len() as u16).to_le_bytes())?;
+    out_file.write_all(header.as_bytes())?;
+    for i in 0..samples.len() {
+        for feature in features {
+            out_file.write_all(&feature.values[i].to_le_bytes())?;
+        }
+    }
+    out_file.finish()?;
+    Ok(())
+}
+
+fn write_dictionary(features: &[Feature], out_path: &Path) -> Result<()> {
+    let mut out_file = create_output(out_path)?;
+    schema::write_header(&mut out_file, OutputKind::FeatureDictionary)?;
+    for (i, feature) in features.iter().enumerate() {
+        writeln!(
+            out_file,
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            i,
+            feature.name,
+            feature.kind.name(),
+            feature.locus_id,
+            feature.interruption,
+            feature.description
+        )?;
+    }
+    out_file.finish()?;
+    Ok(())
+}
+
+#[cfg(feature = "parquet")]
+mod parquet_io {
+    use std::{path::Path, sync::Arc};
+
+    use anyhow::Result;
+    use arrow_array::{ArrayRef, Float64Array, RecordBatch, StringArray};
+    use arrow_schema::{DataType, Field, Schema};
+    use parquet::arrow::ArrowWriter;
+
+    use super::Feature;
+    use crate::utils::create_output;
+
+    pub fn write(samples: &[String], features: &[Feature], out_path: &Path) -> Result<()> {
+        let mut fields: Vec<Field> = vec![Field::new("sample_id", DataType::Utf8, false)];
+        let mut columns: Vec<ArrayRef> = vec![Arc::new(StringArray::from(samples.to_vec()))];
+        for feature in features {
+            fields.push(Field::new(&feature.name, DataType::Float64, false));
+            columns.push(Arc::new(Float64Array::from(feature.values.clone())));
+        }
+        let schema = Arc::new(Schema::new(fields));
+        let batch = RecordBatch::try_new(schema.clone(), columns)?;
+        let mut writer = ArrowWriter::try_new(create_output(out_path)?, schema, None)?;
+        writer.write(&batch)?;
+        writer.into_inner()?.finish()?;
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "parquet"))]
+mod parquet_io {
+    use std::path::Path;
+
+    use anyhow::{bail, Result};
+
+    use super::Feature;
+
+    pub fn write(_samples: &[String], _features: &[Feature], _out_path: &Path) -> Result<()> {
+        bail!("Parquet support requires strif to be built with the `parquet` feature")
+    }
+}
+
+/// The command line arguments of `strif matrix`
+#[derive(Debug, clap::Args)]
+pub struct MatrixArgs {
+    /// The path to the merged profile
+    pub merged_profile: PathBuf,
+
+    /// The path to the matrix. Its format is inferred from the extension unless --format is given. Defaults to a CSV in the same directory as the merged profile.
+    pub output: Option<PathBuf>,
+
+    /// The format of the matrix
+    #[clap(long, value_enum)]
+    pub format: Option<MatrixFormat>,
+
+    /// The kinds of features to export, separated by commas
+    #[clap(
+        short = 'f',
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "burden,interruption,purity"
+    )]
+    pub features: Vec<FeatureKind>,
+
+    /// Minimum read count for a sample to be considered covered at a locus
+    #[clap(short = 'm', long, default_value = "1")]
+    pub min_read_count: u32,
+
+    /// Minimum number of samples with a nonzero count to keep an interruption feature
+    #[clap(short = 'n', long, default_value = "1")]
+    pub min_samples: usize,
+
+    /// The value of features at loci a sample does not cover. Defaults to NaN.
+    #[clap(long)]
+    pub fill: Option<f64>,
+}
+
+impl MatrixArgs {
+    /// Runs `strif matrix`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let MatrixArgs {
+            merged_profile,
+            output,
+            format,
+            features,
+            min_read_count,
+            min_samples,
+            fill,
+        } = self;
+        let out_path: PathBuf =
+            output.unwrap_or_else(|| get_default_out_path(&merged_profile, "matrix", "csv"));
+        let params = MatrixParams {
+            format,
+            features,
+            min_read_count,
+            min_samples,
+            fill,
+        };
+        Plan::new("matrix", dry_run, force)
+            .merged_profile("merged profile", &merged_profile)
+            .output("matrix", &out_path)
+            .execute(|| matrix(merged_profile, out_path, params))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::testing::{data_rows, merged_record, write_merged_profile_file};
+
+    fn params(features: &[FeatureKind], min_samples: usize, fill: Option<f64>) -> MatrixParams {
+        MatrixParams {
+            format: None,
+            features: features.to_vec(),
+            min_read_count: 1,
+            min_samples,
+            fill,
+        }
+    }
+
+    /// s2 does not cover L1, so its counts there are ignored
+    fn write_cohort(dir: &Path) -> PathBuf {
+        write_merged_profile_file(
+            dir,
+            "merged.tsv",
+            &[
+                merged_record(
+                    "L1",
+                    &[("s1", 10), ("s2", 0), ("s3", 5)],
+                    &[
+                        ("s1", "CAT", 0.5),
+                        ("s1", "CTG", 0.25),
+                        ("s2", "CAT", 2.0),
+                        ("s3", "CAT", 1.0),
+                    ],
+                ),
+                merged_record("L2", &[("s1", 10), ("s2", 10), ("s3", 10)], &[]),
+            ],
+        )
+    }
+
+    fn values<'a>(features: &'a [Feature], name: &str) -> &'a [f64] {
+        &features.iter().find(|f| f.name == name).unwrap().values
+    }
+
+    #[test]
+    fn features_are_masked_at_loci_samples_do_not_cover() {
+        let dir = tempfile::tempdir().unwrap();
+        let merged = write_cohort(dir.path());
+        let (samples, features) = build_features(
+            &merged,
+            &params(&[FeatureKind::Burden, FeatureKind::Interruption], 1, None),
+        )
+        .unwrap();
+
+        assert_eq!(samples, ["s1", "s2", "s3"]);
+        let names: Vec<&str> = features.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["L1:burden", "L1:CAT", "L1:CTG", "L2:burden"]);
+        let burden = values(&features, "L1:burden");
+        assert_eq!(burden[0], 0.75);
+        assert!(burden[1].is_nan());
+        assert_eq!(burden[2], 1.0);
+        assert_eq!(values(&features, "L1:CTG")[2], 0.0);
+        assert_eq!(values(&features, "L2:burden"), [0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn rare_interruptions_are_dropped_and_purity_is_summarized() {
+        let dir = tempfile::tempdir().unwrap();
+        let merged = write_cohort(dir.path());
+        let (_, features) = build_features(
+            &merged,
+            &params(&[FeatureKind::Interruption, FeatureKind::Purity], 2, None),
+        )
+        .unwrap();
+
+        assert!(features.iter().all(|f| f.name != "L1:CTG"));
+        assert_eq!(values(&features, "purity:covered_loci"), [2.0, 1.0, 2.0]);
+        assert_eq!(values(&features, "purity:pure_fraction"), [0.5, 1.0, 0.5]);
+        assert_eq!(values(&features, "purity:mean_burden"), [0.375, 0.0, 0.5]);
+        assert_eq!(values(&features, "purity:max_burden"), [0.75, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn matrices_are_written_with_a_feature_dictionary() {
+        let dir = tempfile::tempdir().unwrap();
+        let merged = write_cohort(dir.path());
+        let out_path = dir.path().join("matrix.csv");
+        matrix(
+            merged,
+            out_path.clone(),
+            params(&[FeatureKind::Burden], 1, Some(0.0)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&out_path).unwrap(),
+            "sample_id,L1:burden,L2:burden\ns1,0.75,0\ns2,0,0\ns3,1,0\n"
+        );
+        let dictionary = data_rows(&dir.path().join("matrix.features.tsv"));
+        assert_eq!(dictionary.len(), 2);
+        assert!(dictionary[0].starts_with("0\tL1:burden\tburden\tL1\t.\t"));
+    }
+
+    #[test]
+    fn npy_matrices_have_an_aligned_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let merged = write_cohort(dir.path());
+        let out_path = dir.path().join("matrix.npy");
+        matrix(
+            merged,
+            out_path.clone(),
+            params(&[FeatureKind::Burden], 1, Some(0.0)),
+        )
+        .unwrap();
+
+        let npy = fs::read(&out_path).unwrap();
+        assert_eq!(&npy[..8], b"\x93NUMPY\x01\x00");
+        let header_len = u16::from_le_bytes([npy[8], npy[9]]) as usize;
+        assert_eq!((10 + header_len) % 64, 0);
+        assert_eq!(npy.len(), 10 + header_len + 3 * 2 * 8);
+        let first = f64::from_le_bytes(npy[10 + header_len..18 + header_len].try_into().unwrap());
+        assert_eq!(first, 0.75);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("matrix.samples.txt")).unwrap(),
+            "s1\ns2\ns3\n"
+        );
+    }
+
+    #[test]
+    fn unknown_extensions_need_an_explicit_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let merged = write_cohort(dir.path());
+        let err = matrix(
+            merged,
+            dir.path().join("matrix.bin"),
+            params(&[FeatureKind::Burden], 1, None),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("use --format"));
+    }
+}
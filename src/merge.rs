@@ -1,35 +1,85 @@
 use std::io::prelude::*;
-use std::{collections::HashMap, fs::File, path::PathBuf};
+use std::{
+    borrow::Cow,
+    collections::{hash_map::Entry, HashMap},
+    fs::File,
+    io::BufReader,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+};
 
-use anyhow::{Ok, Result};
+use anyhow::{bail, Context, Ok, Result};
 use log::{info, warn};
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::arrow_stream;
+use crate::config::load_options;
+use crate::error::{field, parse_field, StrifError};
+use crate::intern::{Interner, Symbol};
+use crate::pedigree::{de_novo, DeNovoParams};
+use crate::plan::Plan;
+use crate::profile::{json_field, json_str, parse_interruption_counts, read_count_unit};
+use crate::pseudonym::Pseudonyms;
+use crate::schema::{self, OutputKind, TsvReader};
+use crate::scratch::ScratchDir;
+use crate::utils::{
+    builder_methods, create_output, get_default_out_path, open_maybe_gzipped, ByteSize,
+    OutputFormat, Region, Shard,
+};
 
 type LocusId = String;
 type Motif = String;
-type Interruption = String;
 type SampleId = String;
 type ReferenceRegion = String;
 type Count = u32;
 type NormCount = f64;
 
-type LocusInterruptionCounts = HashMap<(SampleId, Interruption), NormCount>;
-type InterruptionCounts = HashMap<LocusId, LocusInterruptionCounts>;
-type ReadCounts = HashMap<LocusId, Vec<(SampleId, Count)>>;
+type LocusInterruptionCounts = FxHashMap<(Symbol, Symbol), NormCount>;
+
+/// The approximate memory of an interned (sample, read count) pair, doubled
+/// for the spare capacity of the growing vector
+pub(crate) const READ_COUNT_BYTES: u64 = 16;
+/// The approximate memory of an interned ((sample, interruption), count)
+/// entry of a hash map, likewise doubled
+pub(crate) const INTERRUPTION_COUNT_BYTES: u64 = 34;
+/// The approximate memory of the map entries and strings of a locus, besides
+/// its counts
+const LOCUS_BYTES: u64 = 160;
+
+/// The counts of all samples at each locus. Locus, sample and interruption
+/// IDs are interned, since the same IDs recur for every sample of a cohort.
+pub struct MergedProfile {
+    loci: Interner,
+    samples: Interner,
+    interruptions: Interner,
+    /// (sample, interruption) counts at each locus
+    interruption_counts: FxHashMap<Symbol, LocusInterruptionCounts>,
+    read_counts: FxHashMap<Symbol, Vec<(Symbol, Count)>>,
+    motifs: FxHashMap<Symbol, Motif>,
+    reference_regions: FxHashMap<Symbol, ReferenceRegion>,
+    /// The approximate memory used by the counts, checked against `--max-memory`
+    bytes: u64,
+}
 
-struct MergedProfile {
-    interruption_counts: InterruptionCounts,
-    read_counts: ReadCounts,
-    motifs: HashMap<LocusId, Motif>,
-    reference_regions: HashMap<LocusId, ReferenceRegion>,
+impl Default for MergedProfile {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MergedProfile {
     pub fn new() -> Self {
         Self {
-            interruption_counts: HashMap::new(),
-            read_counts: HashMap::new(),
-            motifs: HashMap::new(),
-            reference_regions: HashMap::new(),
+            loci: Interner::new(),
+            samples: Interner::new(),
+            interruptions: Interner::new(),
+            interruption_counts: FxHashMap::default(),
+            read_counts: FxHashMap::default(),
+            motifs: FxHashMap::default(),
+            reference_regions: FxHashMap::default(),
+            bytes: 0,
         }
     }
 
@@ -40,79 +90,468 @@ impl MergedProfile {
         interruption: &str,
         count: f64,
     ) {
-        self.interruption_counts
-            .entry(locus_id.to_string())
-            .or_insert_with(HashMap::new)
-            .entry((sample_id.to_string(), interruption.to_string()))
-            .and_modify(|c| *c += count)
-            .or_insert(count);
+        let key = (
+            self.samples.intern(sample_id),
+            self.interruptions.intern(interruption),
+        );
+        let counts = self
+            .interruption_counts
+            .entry(self.loci.intern(locus_id))
+            .or_default();
+        match counts.entry(key) {
+            Entry::Occupied(mut entry) => *entry.get_mut() += count,
+            Entry::Vacant(entry) => {
+                entry.insert(count);
+                self.bytes += INTERRUPTION_COUNT_BYTES;
+            }
+        }
     }
 
     pub fn add_read_count(&mut self, locus_id: &str, sample_id: &str, count: u32) {
+        let sample = self.samples.intern(sample_id);
         self.read_counts
-            .entry(locus_id.to_string())
-            .or_insert_with(Vec::new)
-            .push((sample_id.to_string(), count));
+            .entry(self.loci.intern(locus_id))
+            .or_default()
+            .push((sample, count));
+        self.bytes += READ_COUNT_BYTES;
     }
 
     pub fn add_reference_region(&mut self, locus_id: &str, reference_region: &str) {
-        self.reference_regions
-            .entry(locus_id.to_string())
-            .or_insert(reference_region.to_string());
+        if let Entry::Vacant(entry) = self.reference_regions.entry(self.loci.intern(locus_id)) {
+            entry.insert(reference_region.to_string());
+            self.bytes += reference_region.len() as u64;
+        }
     }
 
     pub fn add_motif(&mut self, locus_id: &str, motif: &str) {
-        self.motifs
-            .entry(locus_id.to_string())
-            .or_insert(motif.to_string());
+        if let Entry::Vacant(entry) = self.motifs.entry(self.loci.intern(locus_id)) {
+            entry.insert(motif.to_string());
+            self.bytes += LOCUS_BYTES + (2 * locus_id.len() + motif.len()) as u64;
+        }
     }
 
-    pub fn write_to(&self, out: PathBuf) -> Result<()> {
-        let mut out_file: File = File::create(out)?;
-        writeln!(
-            out_file,
-            "locus_id\treference_region\tmotif\tread_counts\tinterruption_counts"
-        )?;
-        let default_interruption_counts: LocusInterruptionCounts = HashMap::new();
-        for (locus_id, motif) in &self.motifs {
-            let reference_region: &String = self.reference_regions.get(locus_id).unwrap();
-            let read_counts: &Vec<(String, u32)> = self.read_counts.get(locus_id).unwrap();
-            let interruption_counts = self
-                .interruption_counts
-                .get(locus_id)
-                .unwrap_or(&default_interruption_counts);
+    /// Writes the loci in order of their IDs and returns how many there were
+    pub fn write_to(&self, out: &Path, format: OutputFormat) -> Result<usize> {
+        let records = self
+            .sorted_loci()
+            .into_iter()
+            .map(|(locus, motif)| Ok(self.record(locus, motif)));
+        write_records(out, format, records)
+    }
 
-            let read_counts_str = read_counts
-                .iter()
-                .map(|(sample_id, count)| format!("{}:{}", sample_id, count))
-                .collect::<Vec<String>>()
-                .join(",");
-            let interruption_counts_str = interruption_counts
-                .iter()
-                .map(|((sample_id, interruption), count)| {
-                    format!("{}:{}:{}", sample_id, interruption, count)
-                })
-                .collect::<Vec<String>>()
-                .join(",");
-            writeln!(
-                out_file,
-                "{}\t{}\t{}\t{}\t{}",
-                locus_id, reference_region, motif, read_counts_str, interruption_counts_str
-            )?;
+    /// The records of the loci in order of their IDs
+    pub fn records(&self) -> Vec<MergedProfileRecord> {
+        self.sorted_loci()
+            .into_iter()
+            .map(|(locus, motif)| self.record(locus, motif))
+            .collect()
+    }
+
+    /// The loci in order of their IDs, so that the same inputs always give
+    /// the same file
+    fn sorted_loci(&self) -> Vec<(Symbol, &str)> {
+        let mut loci: Vec<(Symbol, &str)> = self
+            .motifs
+            .iter()
+            .map(|(locus, motif)| (*locus, motif.as_str()))
+            .collect();
+        loci.sort_unstable_by(|a, b| self.loci.resolve(a.0).cmp(self.loci.resolve(b.0)));
+        loci
+    }
+
+    /// Restores the strings of a locus's counts, sorted by sample and
+    /// interruption
+    fn record(&self, locus: Symbol, motif: &str) -> MergedProfileRecord {
+        let mut read_counts: Vec<(SampleId, Count)> = self
+            .read_counts
+            .get(&locus)
+            .map(|counts| {
+                counts
+                    .iter()
+                    .map(|(sample, count)| (self.samples.resolve(*sample).to_string(), *count))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mut interruption_counts: Vec<(SampleId, String, NormCount)> = self
+            .interruption_counts
+            .get(&locus)
+            .map(|counts| {
+                counts
+                    .iter()
+                    .map(|((sample, interruption), count)| {
+                        (
+                            self.samples.resolve(*sample).to_string(),
+                            self.interruptions.resolve(*interruption).to_string(),
+                            *count,
+                        )
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        read_counts.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        interruption_counts.sort_unstable_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+        MergedProfileRecord {
+            locus_id: self.loci.resolve(locus).to_string(),
+            reference_region: self.reference_regions[&locus].clone(),
+            motif: motif.to_string(),
+            read_counts,
+            interruption_counts,
         }
+    }
+}
+
+/// Writes merged profile records in the given format and returns how many
+/// there were
+fn write_records<I>(out: &Path, format: OutputFormat, records: I) -> Result<usize>
+where
+    I: Iterator<Item = Result<MergedProfileRecord>>,
+{
+    let mut out_file = create_output(out)?;
+    if format == OutputFormat::ArrowStream {
+        let count = arrow_stream::write_merged_profile(&mut out_file, records)?;
+        out_file.finish()?;
+        return Ok(count);
+    }
+    if format == OutputFormat::Tsv {
+        schema::write_header(&mut out_file, OutputKind::MergedProfile)?;
+    }
+    let mut count = 0;
+    for record in records {
+        let record = record?;
+        if format == OutputFormat::Tsv {
+            record.write_to(&mut out_file)?;
+        } else {
+            writeln!(out_file, "{}", record.to_json())?;
+        }
+        count += 1;
+    }
+    out_file.finish()?;
+    Ok(count)
+}
+
+/// The parts of a merged profile that were written to temporary files to
+/// stay within the memory budget, in a scratch directory that is created on
+/// the first spill and removed when dropped
+struct Spills {
+    out_path: PathBuf,
+    scratch: Option<ScratchDir>,
+    paths: Vec<PathBuf>,
+}
+
+impl Spills {
+    fn new(out_path: &Path) -> Self {
+        Self {
+            out_path: out_path.to_path_buf(),
+            scratch: None,
+            paths: Vec::new(),
+        }
+    }
+
+    /// Writes the loci of a merged profile to a new file in the scratch
+    /// directory and empties the profile
+    fn spill(&mut self, merged_profile: &mut MergedProfile) -> Result<()> {
+        if merged_profile.motifs.is_empty() {
+            return Ok(());
+        }
+        let scratch = match &mut self.scratch {
+            Some(scratch) => scratch,
+            None => self
+                .scratch
+                .insert(ScratchDir::create("merge", &self.out_path)?),
+        };
+        // the text of a part takes about as much as its loci in memory
+        scratch.check_space(merged_profile.bytes)?;
+        let path = scratch.file(&format!("spill{}.tsv", self.paths.len() + 1));
+        info!(
+            "Spilling {} loci (~{}) to {}...",
+            merged_profile.motifs.len(),
+            ByteSize(merged_profile.bytes),
+            path.display()
+        );
+        self.paths.push(path.clone());
+        merged_profile.write_to(&path, OutputFormat::Tsv)?;
+        *merged_profile = MergedProfile::new();
         Ok(())
     }
+
+    /// Merges the spilled parts into the output and returns the number of loci
+    fn merge_into(&self, out: &Path, format: OutputFormat) -> Result<usize> {
+        info!(
+            "Merging {} spilled parts of the merged profile...",
+            self.paths.len()
+        );
+        let mut merger = SpillMerger {
+            readers: Vec::new(),
+            next_records: Vec::new(),
+        };
+        for path in &self.paths {
+            let source = path.display().to_string();
+            let file = File::open(path).with_context(|| format!("Could not open {}", source))?;
+            merger.readers.push((
+                schema::tsv_reader(file, OutputKind::MergedProfile, &source)?,
+                source,
+            ));
+        }
+        for i in 0..merger.readers.len() {
+            let record = merger.read_next(i)?;
+            merger.next_records.push(record);
+        }
+        write_records(out, format, merger)
+    }
+}
+
+/// Yields the loci of several spilled parts in order, combining the counts
+/// of loci found in more than one part
+struct SpillMerger {
+    readers: Vec<(TsvReader<File>, String)>,
+    next_records: Vec<Option<MergedProfileRecord>>,
+}
+
+impl SpillMerger {
+    fn read_next(&mut self, i: usize) -> Result<Option<MergedProfileRecord>> {
+        let (reader, source) = &mut self.readers[i];
+        let mut record = csv::StringRecord::new();
+        if !reader.read_record(&mut record)? {
+            return Ok(None);
+        }
+        Ok(Some(parse_merged_record(&record, source)?))
+    }
+
+    fn next_record(&mut self) -> Result<Option<MergedProfileRecord>> {
+        // each part is sorted by locus ID, so the smallest of the next records
+        // is the next locus
+        let locus_id = match self
+            .next_records
+            .iter()
+            .flatten()
+            .map(|record| &record.locus_id)
+            .min()
+        {
+            Some(locus_id) => locus_id.clone(),
+            None => return Ok(None),
+        };
+        // parts are combined in the order they were spilled, which is the
+        // order the samples were added in
+        let mut combined: Option<MergedProfileRecord> = None;
+        for i in 0..self.next_records.len() {
+            if self.next_records[i]
+                .as_ref()
+                .is_none_or(|record| record.locus_id != locus_id)
+            {
+                continue;
+            }
+            let record = self.next_records[i].take().unwrap();
+            self.next_records[i] = self.read_next(i)?;
+            match &mut combined {
+                Some(combined) => {
+                    combined.read_counts.extend(record.read_counts);
+                    combined
+                        .interruption_counts
+                        .extend(record.interruption_counts);
+                }
+                None => combined = Some(record),
+            }
+        }
+        let mut combined = combined.unwrap();
+        combined.read_counts.sort_by(|a, b| a.0.cmp(&b.0));
+        combined
+            .interruption_counts
+            .sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+        combined.interruption_counts.dedup_by(|next, prev| {
+            if (&next.0, &next.1) == (&prev.0, &prev.1) {
+                prev.2 += next.2;
+                true
+            } else {
+                false
+            }
+        });
+        Ok(Some(combined))
+    }
+}
+
+impl Iterator for SpillMerger {
+    type Item = Result<MergedProfileRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+/// The options of `merge`: which loci and reads are kept, how counts are
+/// normalized and the format of the merged profile. Its fields are the
+/// options of the command line, and it can be built with its builder methods,
+/// e.g. `MergeOptions::default().read_length(100).shard(shard)`, or loaded
+/// from the `[merge]` table of a configuration file with
+/// [`MergeOptions::from_config`].
+#[derive(Debug, Clone, PartialEq, clap::Args, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct MergeOptions {
+    /// Filter locus IDs using a regular expression. Defaults to None.
+    /// This is useful for filtering out loci that are not of interest.
+    #[clap(short = 'f', long)]
+    pub filter: Option<String>,
+
+    /// Minimum read count to include in the merged profile. Defaults to 1.
+    /// This is useful for filtering out loci with low coverage.
+    #[clap(short = 'm', long, default_value = "1")]
+    pub min_read_count: u32,
+
+    /// The sequencing read length. Used for normalizing the interruption counts.
+    #[clap(short = 'l', long, default_value = "150")]
+    pub read_length: u32,
+
+    /// Only merge the i-th of N deterministic subsets of loci, written as i/N.
+    /// Shard outputs can be combined with `merge-combine`.
+    #[clap(long)]
+    pub shard: Option<Shard>,
+
+    /// The format of the merged profile. `jsonl` writes one JSON object per locus with nested
+    /// `read_counts` and `interruption_counts` objects, and `arrow-stream` writes Arrow IPC
+    /// record batches, to stdout unless an output path is given.
+    #[clap(long, value_enum, default_value = "tsv")]
+    pub output_format: OutputFormat,
+
+    /// The approximate memory to use for counts, e.g. 48G. When exceeded,
+    /// the counts so far are written to temporary files next to the output
+    /// and merged at the end.
+    #[clap(long)]
+    pub max_memory: Option<ByteSize>,
+
+    /// Keep the repeat lengths of interruptions, counting each interruption
+    /// separately in bins of this many bp of repeat length (1 for exact lengths)
+    #[clap(long)]
+    pub length_bin: Option<NonZeroU32>,
+
+    /// Replace the sample IDs of the merged profile with generated codes,
+    /// written with the sample they stand for to a key file
+    #[clap(long, conflicts_with = "ped")]
+    pub pseudonymize: bool,
+
+    /// The key file of the sample codes. Defaults to the same directory as
+    /// the manifest. An existing key is reused, so that reruns and shards
+    /// share codes.
+    #[clap(long, requires = "pseudonymize")]
+    pub pseudonym_key: Option<PathBuf>,
+
+    /// A BED file of correction factors, e.g. of mappability or GC bias, with the fraction
+    /// of the expected reads each region recruits in its fourth column. The expected reads
+    /// of each locus are scaled by the factors of the intervals it overlaps.
+    #[clap(long)]
+    pub correction_factors: Option<PathBuf>,
+
+    /// Write the loci and samples that were skipped, with the reason for each, to this file
+    #[clap(skip)]
+    pub skip_report: Option<PathBuf>,
+}
+
+/// The defaults of the command line
+impl Default for MergeOptions {
+    fn default() -> Self {
+        MergeOptions {
+            filter: None,
+            min_read_count: 1,
+            read_length: 150,
+            shard: None,
+            output_format: OutputFormat::default(),
+            max_memory: None,
+            length_bin: None,
+            pseudonymize: false,
+            pseudonym_key: None,
+            correction_factors: None,
+            skip_report: None,
+        }
+    }
+}
+
+impl MergeOptions {
+    builder_methods! {
+        filter: Option<String>,
+        min_read_count: u32,
+        read_length: u32,
+        shard: Option<Shard>,
+        output_format: OutputFormat,
+        max_memory: Option<ByteSize>,
+        length_bin: Option<NonZeroU32>,
+        pseudonymize: bool,
+        pseudonym_key: Option<PathBuf>,
+        correction_factors: Option<PathBuf>,
+        skip_report: Option<PathBuf>,
+    }
+
+    /// Loads the options from the `[merge]` table of a configuration file
+    pub fn from_config(path: &Path) -> Result<Self> {
+        load_options(path, "merge")
+    }
+
+    /// The files written when merging a manifest: the merged profile at the
+    /// output path, by default next to the manifest, with the skip report and
+    /// the de novo candidates of a pedigree next to it. Those of a merged
+    /// profile streamed to stdout are written next to the manifest.
+    pub fn outputs(&self, manifest: &Path, output: Option<PathBuf>, de_novo: bool) -> MergeOutputs {
+        let shard_suffix = |name: &str| match self.shard {
+            Some(shard) => format!("{}.shard{}of{}", name, shard.index, shard.count),
+            None => name.to_string(),
+        };
+        let streamed = output.is_none() && self.output_format.default_out_path().is_some();
+        let merged_profile = output
+            .or_else(|| self.output_format.default_out_path())
+            .unwrap_or_else(|| {
+                get_default_out_path(
+                    manifest,
+                    &shard_suffix("merged_profile"),
+                    self.output_format.extension(),
+                )
+            });
+        let next_to = if streamed { manifest } else { &merged_profile };
+        MergeOutputs {
+            skip_report: get_default_out_path(next_to, &shard_suffix("merge_skips"), "tsv"),
+            de_novo: de_novo.then(|| get_default_out_path(next_to, "de_novo", "tsv")),
+            merged_profile,
+        }
+    }
+
+    /// The key file of the sample codes when pseudonymizing, which defaults
+    /// to the same directory as the manifest
+    pub fn pseudonym_key_path(&self, manifest: &Path) -> Option<PathBuf> {
+        self.pseudonymize.then(|| {
+            self.pseudonym_key
+                .clone()
+                .unwrap_or_else(|| get_default_out_path(manifest, "pseudonym_key", "tsv"))
+        })
+    }
+}
+
+/// The files written by `merge`, see [`MergeOptions::outputs`]
+pub struct MergeOutputs {
+    pub merged_profile: PathBuf,
+    pub skip_report: PathBuf,
+    pub de_novo: Option<PathBuf>,
 }
 
+/// Merges the profiles of a manifest into a merged profile
 pub fn merge(
     manifest: PathBuf,
     read_depths: PathBuf,
     out_path: PathBuf,
-    filter: Option<String>,
-    min_read_count: u32,
-    read_len: u32,
+    options: &MergeOptions,
 ) -> Result<()> {
-    info!("Merging profiles from manifest...");
+    let MergeOptions {
+        filter,
+        min_read_count,
+        read_length: read_len,
+        shard,
+        output_format,
+        max_memory,
+        length_bin,
+        correction_factors,
+        skip_report,
+        ..
+    } = options.clone();
+    let pseudonym_key = options.pseudonym_key_path(&manifest);
+    match shard {
+        Some(shard) => info!("Merging shard {} of profiles from manifest...", shard),
+        None => info!("Merging profiles from manifest..."),
+    }
 
     // create a regex filter if provided
     let filter_regex = match filter {
@@ -120,105 +559,1290 @@ pub fn merge(
         None => None,
     };
 
-    // load manifest, which is a TSV with columns: sample, case_control, profile_path (no headers)
-    let mut profiles: Vec<(SampleId, PathBuf)> = Vec::new();
-    let mut reader = csv::ReaderBuilder::new()
-        .delimiter(b'\t')
-        .has_headers(false)
-        .from_path(manifest)?;
-    for result in reader.records() {
-        let record = result?;
-        let sample_id = record.get(0).unwrap().to_string();
-        let profile_path: PathBuf = PathBuf::from(record.get(2).unwrap());
-        profiles.push((sample_id, profile_path));
-    }
+    let profiles: Vec<ManifestEntry> = load_manifest(&manifest)?;
 
-    // load the read depths file, which is a TSV with columns: sample, read_depth (no headers)
-    let mut read_depths_map: HashMap<SampleId, f64> = HashMap::new();
-    let mut read_depths_reader = csv::ReaderBuilder::new()
-        .delimiter(b'\t')
-        .has_headers(false)
-        .from_path(read_depths)?;
-    for result in read_depths_reader.records() {
-        let record = result?;
-        let sample_id = record.get(0).unwrap().to_string();
-        let read_depth: f64 = record.get(1).unwrap().parse::<f64>()?;
-        read_depths_map.insert(sample_id, read_depth);
-    }
+    let read_depths_map = load_read_depths(&read_depths)?;
+    let read_depths_source = read_depths.display().to_string();
+    // counts of fragments must be normalized by depths of fragments, or the
+    // samples would be off by the overlap of their mates
+    let count_unit = read_count_unit(&read_depths)?;
+    let correction_factors = correction_factors
+        .as_deref()
+        .map(CorrectionFactors::from_path)
+        .transpose()?;
+    // the factor of each locus, found once for all samples, or None for loci
+    // outside the intervals of the track
+    let mut locus_factors: HashMap<String, Option<f64>> = HashMap::new();
+
+    let pseudonyms = match pseudonym_key {
+        Some(key_path) => {
+            let sample_ids: Vec<&str> = profiles.iter().map(|p| p.sample_id.as_str()).collect();
+            Some(Pseudonyms::load_or_create(&key_path, &sample_ids)?)
+        }
+        None => None,
+    };
 
     // open each profile and add to merged profile
     let mut merged_profile = MergedProfile::new();
-    for (sample_id, profile_path) in profiles {
-        info!("Processing {} profile...", sample_id);
-        let mut reader: csv::Reader<File> = csv::ReaderBuilder::new()
+    let mut spills = Spills::new(&out_path);
+    let mut skips: Vec<(String, SkipCounts)> = Vec::new();
+    for ManifestEntry {
+        sample_id,
+        path: profile_path,
+        ..
+    } in profiles
+    {
+        info!(sample = sample_id.as_str(); "Processing {} profile...", sample_id);
+        let read_depth =
+            *read_depths_map
+                .get(&sample_id)
+                .ok_or_else(|| StrifError::MissingSample {
+                    sample_id: sample_id.clone(),
+                    path: read_depths_source.clone(),
+                })?;
+        // the sample is written under its code, but logged under its ID
+        let out_id = match &pseudonyms {
+            Some(pseudonyms) => pseudonyms.code(&sample_id),
+            None => &sample_id,
+        };
+        let profile_source = profile_path.display().to_string();
+        let profile_unit = read_count_unit(&profile_path)?;
+        if profile_unit != count_unit {
+            bail!(
+                "{} counts {}s but the read depths of {} are of {}s, profile and estimate the depth of every sample with the same unit",
+                profile_source,
+                profile_unit.name(),
+                read_depths_source,
+                count_unit.name()
+            );
+        }
+        let file = open_maybe_gzipped(&profile_path)
+            .with_context(|| format!("Could not open {}", profile_source))?;
+        let mut reader = csv::ReaderBuilder::new()
             .delimiter(b'\t')
             .has_headers(true)
-            .from_path(profile_path)?;
+            .comment(Some(b'#'))
+            .from_reader(file);
+        let headers = reader.headers()?.clone();
+        schema::validate(&profile_path, &headers, OutputKind::Profile)?;
+        let mut counts = SkipCounts::default();
         for result in reader.records() {
             let record: csv::StringRecord = result?;
-            let locus_id: &str = record.get(0).unwrap();
+            counts.rows += 1;
+            let Some(locus_id) = field(&record, 0, &profile_source)
+                .inspect_err(|e| warn!(sample = sample_id.as_str(); "Skipping a row: {}", e))
+                .ok()
+            else {
+                counts.parse_failure += 1;
+                continue;
+            };
 
             // skip if locus_id does not match filter
             if let Some(filter_regex) = &filter_regex {
                 if !filter_regex.is_match(locus_id) {
+                    counts.filtered += 1;
+                    continue;
+                }
+            }
+
+            // skip if locus_id belongs to another shard
+            if let Some(shard) = &shard {
+                if !shard.contains(locus_id) {
+                    counts.other_shard += 1;
                     continue;
                 }
             }
 
+            let Some((read_count, reference_region, motif, interruption_counts)) =
+                parse_profile_row(&record, &profile_source)
+                    .inspect_err(
+                        |e| warn!(sample = sample_id.as_str(), locus_id; "Skipping a row: {:#}", e),
+                    )
+                    .ok()
+            else {
+                counts.parse_failure += 1;
+                continue;
+            };
+
             // skip if read count is below minimum otherwise add to merged profile
-            let read_count: u32 = record.get(3).unwrap().parse::<u32>()?;
             if read_count < min_read_count {
+                counts.below_min_read_count += 1;
                 continue;
-            } else {
-                merged_profile.add_read_count(locus_id, &sample_id, read_count);
             }
+            counts.merged += 1;
+            merged_profile.add_read_count(locus_id, out_id, read_count);
+            let correction = match &correction_factors {
+                Some(correction_factors) => *locus_factors
+                    .entry(locus_id.to_string())
+                    .or_insert_with(|| correction_factors.factor(reference_region)),
+                None => None,
+            }
+            .unwrap_or(1.0);
 
             // add reference region and motif to merged profile
-            let reference_region: &str = record.get(1).unwrap();
             merged_profile.add_reference_region(locus_id, reference_region);
-            let motif: &str = record.get(2).unwrap();
             merged_profile.add_motif(locus_id, motif);
 
-            let interruption_counts_str: &str = record.get(4).unwrap();
-            if interruption_counts_str.len() == 0 {
-                continue;
-            }
-
-            let interruption_counts: Vec<&str> = interruption_counts_str.split(",").collect();
-            for interruption_count in interruption_counts {
-                let interruption_count: Vec<&str> = interruption_count.split(":").collect();
-                let interruption: &str = interruption_count[0];
-                let repeat_len: u32 = interruption_count[1].parse::<u32>()?;
+            for (interruption, repeat_len, count) in interruption_counts {
+                let interruption: &str = &interruption;
                 if repeat_len == 0 || repeat_len > read_len {
-                    warn!("Sample {} has an invalid repeat length={} for {} with a '{}' interruption. Read length={}.", sample_id, repeat_len, locus_id, interruption, read_len);
+                    warn!(
+                        sample = sample_id.as_str(), locus_id, interruption, repeat_len, read_len;
+                        "Sample {} has an invalid repeat length={} for {} with a '{}' interruption. Read length={}. Skipping it.",
+                        sample_id, repeat_len, locus_id, interruption, read_len
+                    );
+                    counts.invalid_repeat_len += 1;
+                    continue;
                 }
-                let count: u32 = interruption_count[2].parse::<u32>()?;
-                let read_depth = read_depths_map.get(&sample_id).unwrap().clone();
                 let norm_count: f64 =
-                    norm_interruption_count(count, read_len, repeat_len, read_depth);
+                    norm_interruption_count(count, read_len, repeat_len, read_depth, correction);
+                let interruption: Cow<str> = match length_bin {
+                    Some(length_bin) => {
+                        Cow::Owned(length_stratum(interruption, repeat_len, length_bin))
+                    }
+                    None => Cow::Borrowed(interruption),
+                };
+                let interruption: &str = &interruption;
                 if norm_count.is_infinite() || norm_count.is_nan() || norm_count < 0.0 {
                     warn!(
+                        sample = sample_id.as_str(), locus_id, interruption, norm_count, count, read_len, repeat_len, read_depth;
                         "Sample {} has an invalid normalized count={} for {} with a '{}' interruption. Raw count={}, read length={}, repeat length={}, read depth={}.",
                         sample_id, norm_count, locus_id, interruption, count, read_len, repeat_len, read_depth
                     );
                 }
-                merged_profile.increment_interruption(
-                    locus_id,
-                    &sample_id,
-                    interruption,
-                    norm_count,
+                merged_profile.increment_interruption(locus_id, out_id, interruption, norm_count);
+            }
+
+            if max_memory.is_some_and(|max_memory| merged_profile.bytes > max_memory.0) {
+                spills.spill(&mut merged_profile)?;
+            }
+        }
+        if counts.skipped() > 0 {
+            info!(
+                sample = sample_id.as_str(), filtered = counts.filtered,
+                below_min_read_count = counts.below_min_read_count,
+                invalid_repeat_len = counts.invalid_repeat_len,
+                parse_failure = counts.parse_failure;
+                "Skipped {} of {} rows of {}: {} filtered out, {} below the minimum read count, {} that could not be parsed; skipped {} interruption counts with an invalid repeat length",
+                counts.filtered + counts.below_min_read_count + counts.parse_failure,
+                counts.rows,
+                sample_id,
+                counts.filtered,
+                counts.below_min_read_count,
+                counts.parse_failure,
+                counts.invalid_repeat_len
+            );
+        }
+        skips.push((sample_id, counts));
+    }
+    if let Some(skip_report) = skip_report {
+        write_skip_report(&skip_report, &skips)?;
+    }
+    if let Some(correction_factors) = &correction_factors {
+        let uncorrected = locus_factors
+            .values()
+            .filter(|factor| factor.is_none())
+            .count();
+        info!(
+            loci = locus_factors.len(), uncorrected;
+            "Corrected the counts of {} loci by the factors of {}, {} loci outside its intervals were left uncorrected",
+            locus_factors.len() - uncorrected,
+            correction_factors.source,
+            uncorrected
+        );
+    }
+
+    let loci = if spills.paths.is_empty() {
+        let loci = merged_profile.motifs.len();
+        info!(loci; "Writing merged profile of {} loci...", loci);
+        merged_profile.write_to(&out_path, output_format)?
+    } else {
+        spills.spill(&mut merged_profile)?;
+        let loci = spills.merge_into(&out_path, output_format)?;
+        info!(loci; "Wrote merged profile of {} loci", loci);
+        loci
+    };
+    // a shard can legitimately have no loci when there are many shards
+    if loci == 0 && shard.is_none() {
+        return Err(StrifError::EmptyOutput {
+            path: out_path.display().to_string(),
+            message: "no loci of the profiles pass the filters".to_string(),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// The rows of a profile that `merge` merged or skipped, by reason
+#[derive(Debug, Clone, Copy, Default)]
+struct SkipCounts {
+    rows: usize,
+    merged: usize,
+    /// Rows of loci not matching `--filter`
+    filtered: usize,
+    /// Rows of loci of another shard than that of `--shard`
+    other_shard: usize,
+    below_min_read_count: usize,
+    /// Rows with missing columns or unparseable values
+    parse_failure: usize,
+    /// Interruption counts, rather than rows, skipped for a repeat length of
+    /// 0 or longer than the read length
+    invalid_repeat_len: usize,
+}
+
+impl SkipCounts {
+    /// The rows and interruption counts skipped for a reason that may need
+    /// a look, which excludes those of other shards
+    fn skipped(&self) -> usize {
+        self.filtered + self.below_min_read_count + self.parse_failure + self.invalid_repeat_len
+    }
+}
+
+/// The read count, reference region, motif and interruption counts of a row
+/// of a profile
+type ProfileRow<'a> = (u32, &'a str, &'a str, Vec<(String, u32, f64)>);
+
+fn parse_profile_row<'a>(record: &'a csv::StringRecord, source: &str) -> Result<ProfileRow<'a>> {
+    let read_count: u32 = parse_field(record, 3, "read count", source)?;
+    let reference_region: &str = field(record, 1, source)?;
+    let motif: &str = field(record, 2, source)?;
+    let interruption_counts = parse_interruption_counts(field(record, 4, source)?)
+        .map_err(|e| StrifError::malformed_row(source, record, format!("{:#}", e)))?;
+    Ok((read_count, reference_region, motif, interruption_counts))
+}
+
+/// Writes the rows of each sample that `merge` merged and skipped by reason
+fn write_skip_report(path: &Path, skips: &[(String, SkipCounts)]) -> Result<()> {
+    let mut out_file = create_output(path)?;
+    schema::write_header(&mut out_file, OutputKind::MergeSkips)?;
+    for (sample_id, counts) in skips {
+        writeln!(
+            out_file,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            sample_id,
+            counts.rows,
+            counts.merged,
+            counts.filtered,
+            counts.other_shard,
+            counts.below_min_read_count,
+            counts.parse_failure,
+            counts.invalid_repeat_len
+        )?;
+    }
+    out_file.finish()?;
+    info!(
+        "Wrote the rows skipped for each sample to {}",
+        path.display()
+    );
+    Ok(())
+}
+
+/// A row of the manifest
+#[derive(Clone)]
+pub struct ManifestEntry {
+    pub sample_id: SampleId,
+    /// The case-control status of the sample
+    pub status: String,
+    /// The path to the sample's profile (or BAMlet when running the full pipeline)
+    pub path: PathBuf,
+}
+
+/// Loads the manifest, which is a TSV with columns: sample, case_control, profile_path (no headers).
+/// Lines starting with `#` are comments.
+pub fn load_manifest(manifest: &Path) -> Result<Vec<ManifestEntry>> {
+    let mut entries: Vec<ManifestEntry> = Vec::new();
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(false)
+        .comment(Some(b'#'))
+        .from_path(manifest)?;
+    for result in reader.records() {
+        let record = result?;
+        if record.len() < 3 {
+            return Err(StrifError::malformed_row(
+                manifest.display(),
+                &record,
+                "expected a sample ID, case-control status and profile path",
+            )
+            .into());
+        }
+        entries.push(ManifestEntry {
+            sample_id: record[0].to_string(),
+            status: record[1].to_string(),
+            path: PathBuf::from(&record[2]),
+        });
+    }
+    Ok(entries)
+}
+
+/// Loads the read depths file, which is a TSV with columns: sample, read_depth (no headers)
+pub fn load_read_depths(read_depths: &Path) -> Result<HashMap<SampleId, f64>> {
+    let mut read_depths_map: HashMap<SampleId, f64> = HashMap::new();
+    let mut read_depths_reader = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(false)
+        .comment(Some(b'#'))
+        .from_path(read_depths)?;
+    let read_depths_source = read_depths.display().to_string();
+    for result in read_depths_reader.records() {
+        let record = result?;
+        let sample_id = field(&record, 0, &read_depths_source)?.to_string();
+        let read_depth: f64 = parse_field(&record, 1, "read depth", &read_depths_source)?;
+        read_depths_map.insert(sample_id, read_depth);
+    }
+    Ok(read_depths_map)
+}
+
+/// Unions the merged profiles of several shards into a single merged profile
+pub fn merge_combine(shards: Vec<PathBuf>, out_path: PathBuf) -> Result<()> {
+    info!("Combining {} merged profile shards...", shards.len());
+
+    let mut out_file = create_output(&out_path)?;
+    schema::write_header(&mut out_file, OutputKind::MergedProfile)?;
+
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .from_writer(out_file);
+    let mut readers: Vec<csv::StringRecordsIntoIter<File>> = Vec::new();
+    for shard_path in &shards {
+        let mut reader: csv::Reader<File> = csv::ReaderBuilder::new()
+            .delimiter(b'\t')
+            .has_headers(true)
+            .comment(Some(b'#'))
+            .from_path(shard_path)?;
+        let headers = reader.headers()?.clone();
+        schema::validate(shard_path, &headers, OutputKind::MergedProfile)?;
+        readers.push(reader.into_records());
+    }
+
+    // each shard is sorted by locus ID, so writing the smallest of the next
+    // rows of the shards keeps the combined profile sorted
+    let mut next_rows: Vec<Option<csv::StringRecord>> = Vec::new();
+    for reader in &mut readers {
+        next_rows.push(reader.next().transpose()?);
+    }
+    let mut seen_loci: HashMap<LocusId, usize> = HashMap::new();
+    while let Some(i) = (0..next_rows.len())
+        .filter_map(|i| next_rows[i].as_ref().map(|row| (i, row.get(0))))
+        .min_by_key(|(_, locus_id)| *locus_id)
+        .map(|(i, _)| i)
+    {
+        let record = next_rows[i].take().unwrap();
+        next_rows[i] = readers[i].next().transpose()?;
+        let locus_id: &str = field(&record, 0, shards[i].display())?;
+        if let Some(other) = seen_loci.get(locus_id) {
+            bail!(
+                "Locus {} appears in both {} and {}. Were the shards created with the same shard count?",
+                locus_id,
+                shards[*other].display(),
+                shards[i].display()
+            );
+        }
+        seen_loci.insert(locus_id.to_string(), i);
+        writer.write_record(&record)?;
+    }
+    writer.into_inner().map_err(|e| e.into_error())?.finish()?;
+
+    Ok(())
+}
+
+/// Labels an interruption with the bin of repeat lengths it was seen in, as
+/// `interruption@length` for bins of 1 bp and `interruption@start-end` otherwise
+pub fn length_stratum(interruption: &str, repeat_len: u32, length_bin: NonZeroU32) -> String {
+    let width = length_bin.get();
+    if width == 1 {
+        return format!("{}@{}", interruption, repeat_len);
+    }
+    let start = repeat_len / width * width;
+    format!("{}@{}-{}", interruption, start, start + width - 1)
+}
+
+/// Splits the length stratum of `--length-bin` off an interruption, e.g.
+/// `AG@90-99` into `AG` and `90-99`. Interruptions without one are returned
+/// whole.
+pub fn split_length_stratum(interruption: &str) -> (&str, Option<&str>) {
+    let is_length = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    match interruption.rsplit_once('@') {
+        Some((base, stratum))
+            if is_length(stratum)
+                || stratum
+                    .split_once('-')
+                    .is_some_and(|(start, end)| is_length(start) && is_length(end)) =>
+        {
+            (base, Some(stratum))
+        }
+        _ => (interruption, None),
+    }
+}
+
+/// Normalizes a raw interruption count by the number of reads expected to
+/// span a repeat of the given length, scaled by the correction factor of the
+/// locus (1 for none)
+pub fn norm_interruption_count(
+    count: f64,
+    read_len: u32,
+    repeat_len: u32,
+    read_depth: f64,
+    correction: f64,
+) -> f64 {
+    let num_possible_start: u32 = read_len.saturating_sub(repeat_len) + 1;
+    let expected_num_reads: f64 = num_possible_start as f64 * read_depth * correction;
+    count / expected_num_reads
+}
+
+/// A track of per-locus correction factors, e.g. of mappability or GC bias,
+/// as the intervals of a bedGraph-like BED file with the factor in its fourth
+/// column. A factor is the fraction of the reads expected from the global
+/// read depth that a region recruits, so loci in regions of low mappability
+/// are not mistaken for interruption-poor ones.
+pub struct CorrectionFactors {
+    source: String,
+    /// The (start, end, factor) intervals of each chromosome, sorted by start
+    intervals: HashMap<String, Vec<(u64, u64, f64)>>,
+    /// The length of the longest interval of each chromosome, which bounds
+    /// how far before a locus an interval overlapping it can start
+    max_len: HashMap<String, u64>,
+}
+
+impl CorrectionFactors {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let source = path.display().to_string();
+        let reader = BufReader::new(open_maybe_gzipped(path)?);
+        let mut intervals: HashMap<String, Vec<(u64, u64, f64)>> = HashMap::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line.with_context(|| format!("Could not read {}", source))?;
+            if line.starts_with('#')
+                || line.starts_with("track")
+                || line.starts_with("browser")
+                || line.trim().is_empty()
+            {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() < 4 {
+                bail!(
+                    "{} line {} has {} columns, expected chrom, start, end and correction factor",
+                    source,
+                    i + 1,
+                    fields.len()
                 );
             }
+            let start: u64 = fields[1]
+                .parse()
+                .with_context(|| format!("Invalid start in {} line {}", source, i + 1))?;
+            let end: u64 = fields[2]
+                .parse()
+                .with_context(|| format!("Invalid end in {} line {}", source, i + 1))?;
+            let factor: f64 = fields[3].parse().with_context(|| {
+                format!("Invalid correction factor in {} line {}", source, i + 1)
+            })?;
+            if !(factor.is_finite() && factor > 0.0) {
+                bail!(
+                    "{} line {} has a correction factor of {}, expected a positive number",
+                    source,
+                    i + 1,
+                    fields[3]
+                );
+            }
+            intervals
+                .entry(fields[0].to_string())
+                .or_default()
+                .push((start, end, factor));
+        }
+        let mut max_len: HashMap<String, u64> = HashMap::new();
+        for (chrom, chrom_intervals) in intervals.iter_mut() {
+            chrom_intervals.sort_unstable_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(&b.1)));
+            let longest = chrom_intervals
+                .iter()
+                .map(|(start, end, _)| end.saturating_sub(*start))
+                .max()
+                .unwrap_or(0);
+            max_len.insert(chrom.clone(), longest);
+        }
+        Ok(Self {
+            source,
+            intervals,
+            max_len,
+        })
+    }
+
+    /// The factor of a locus, the mean of the factors of the intervals it
+    /// overlaps weighted by their overlap, or None if it overlaps none or its
+    /// reference region is not of the form chrom:start-end
+    pub fn factor(&self, reference_region: &str) -> Option<f64> {
+        let region: Region = reference_region.parse().ok()?;
+        let intervals = self.intervals.get(&region.chrom)?;
+        let earliest_start = region.start.saturating_sub(self.max_len[&region.chrom]);
+        let first = intervals.partition_point(|(start, _, _)| *start < earliest_start);
+        // a locus of a single position still takes the factor around it
+        let region_end = region.end.max(region.start + 1);
+        let mut weighted_sum = 0.0;
+        let mut overlap_sum = 0;
+        for &(start, end, factor) in &intervals[first..] {
+            if start >= region_end {
+                break;
+            }
+            let overlap = end.min(region_end).saturating_sub(start.max(region.start));
+            weighted_sum += factor * overlap as f64;
+            overlap_sum += overlap;
+        }
+        (overlap_sum > 0).then(|| weighted_sum / overlap_sum as f64)
+    }
+}
+
+/// A row of a merged interruption profile
+pub struct MergedProfileRecord {
+    pub locus_id: String,
+    pub reference_region: String,
+    pub motif: String,
+    /// (sample, read count)
+    pub read_counts: Vec<(String, u32)>,
+    /// (sample, interruption, normalized count)
+    pub interruption_counts: Vec<(String, String, f64)>,
+}
+
+impl MergedProfileRecord {
+    /// Writes the record as a row of a merged profile
+    pub fn write_to<W: Write>(&self, out: &mut W) -> Result<()> {
+        write!(
+            out,
+            "{}\t{}\t{}\t",
+            self.locus_id, self.reference_region, self.motif
+        )?;
+        for (i, (sample_id, count)) in self.read_counts.iter().enumerate() {
+            let sep = if i == 0 { "" } else { "," };
+            write!(out, "{}{}:{}", sep, sample_id, count)?;
+        }
+        write!(out, "\t")?;
+        for (i, (sample_id, interruption, count)) in self.interruption_counts.iter().enumerate() {
+            let sep = if i == 0 { "" } else { "," };
+            write!(out, "{}{}:{}:{}", sep, sample_id, interruption, count)?;
         }
+        writeln!(out)?;
+        Ok(())
+    }
+
+    /// Packs the read counts as `sample:count,...`
+    pub fn packed_read_counts(&self) -> String {
+        self.read_counts
+            .iter()
+            .map(|(sample_id, count)| format!("{}:{}", sample_id, count))
+            .collect::<Vec<String>>()
+            .join(",")
     }
 
-    merged_profile.write_to(out_path)?;
+    /// Packs the interruption counts as `sample:interruption:count,...`
+    pub fn packed_interruption_counts(&self) -> String {
+        self.interruption_counts
+            .iter()
+            .map(|(sample_id, interruption, count)| {
+                format!("{}:{}:{}", sample_id, interruption, count)
+            })
+            .collect::<Vec<String>>()
+            .join(",")
+    }
+
+    /// Converts the record to a JSON object with nested read and interruption counts
+    pub fn to_json(&self) -> Value {
+        let read_counts_obj: serde_json::Map<String, Value> = self
+            .read_counts
+            .iter()
+            .map(|(sample_id, count)| (sample_id.clone(), json!(count)))
+            .collect();
+        let mut interruption_counts_obj: serde_json::Map<String, Value> = serde_json::Map::new();
+        for (sample_id, interruption, count) in &self.interruption_counts {
+            interruption_counts_obj
+                .entry(sample_id.clone())
+                .or_insert_with(|| json!({}))
+                .as_object_mut()
+                .unwrap()
+                .insert(interruption.clone(), json!(count));
+        }
+        json!({
+            "locus_id": self.locus_id,
+            "reference_region": self.reference_region,
+            "motif": self.motif,
+            "read_counts": read_counts_obj,
+            "interruption_counts": interruption_counts_obj,
+        })
+    }
 
+    pub fn from_json(value: &Value) -> Result<Self> {
+        let mut read_counts: Vec<(String, u32)> = Vec::new();
+        if let Some(obj) = json_field(value, "read_counts")?.as_object() {
+            for (sample_id, count) in obj {
+                match count.as_u64() {
+                    Some(count) => read_counts.push((sample_id.clone(), count as u32)),
+                    None => bail!("Invalid read count for {} in {}", sample_id, value),
+                }
+            }
+        }
+        let mut interruption_counts: Vec<(String, String, f64)> = Vec::new();
+        if let Some(obj) = json_field(value, "interruption_counts")?.as_object() {
+            for (sample_id, counts) in obj {
+                for (interruption, count) in counts.as_object().into_iter().flatten() {
+                    match count.as_f64() {
+                        Some(count) => interruption_counts.push((
+                            sample_id.clone(),
+                            interruption.clone(),
+                            count,
+                        )),
+                        None => bail!("Invalid interruption count for {} in {}", sample_id, value),
+                    }
+                }
+            }
+        }
+        Ok(Self {
+            locus_id: json_str(value, "locus_id")?,
+            reference_region: json_str(value, "reference_region")?,
+            motif: json_str(value, "motif")?,
+            read_counts,
+            interruption_counts,
+        })
+    }
+}
+
+/// Reads a merged interruption profile written by `merge`
+pub fn read_merged_profile(path: &Path) -> Result<Vec<MergedProfileRecord>> {
+    let file =
+        open_maybe_gzipped(path).with_context(|| format!("Could not open {}", path.display()))?;
+    read_merged_profile_from(file, &path.display().to_string())
+}
+
+/// Reads the records of a merged profile from a stream. The source is only
+/// used in error messages.
+pub fn read_merged_profile_from<R: Read>(
+    reader: R,
+    source: &str,
+) -> Result<Vec<MergedProfileRecord>> {
+    let mut reader = schema::tsv_reader(reader, OutputKind::MergedProfile, source)?;
+
+    let mut records: Vec<MergedProfileRecord> = Vec::new();
+    for result in reader.records() {
+        records.push(parse_merged_record(&result?, source)?);
+    }
+    Ok(records)
+}
+
+/// Parses a row of a merged profile
+pub(crate) fn parse_merged_record(
+    record: &csv::StringRecord,
+    source: &str,
+) -> Result<MergedProfileRecord> {
+    let read_counts = parse_read_counts(field(record, 3, source)?).map_err(|e| {
+        StrifError::malformed_row(source, record, format!("invalid read counts: {:#}", e))
+    })?;
+    let interruption_counts =
+        parse_merged_interruption_counts(field(record, 4, source)?).map_err(|e| {
+            StrifError::malformed_row(
+                source,
+                record,
+                format!("invalid interruption counts: {:#}", e),
+            )
+        })?;
+    Ok(MergedProfileRecord {
+        locus_id: field(record, 0, source)?.to_string(),
+        reference_region: field(record, 1, source)?.to_string(),
+        motif: field(record, 2, source)?.to_string(),
+        read_counts,
+        interruption_counts,
+    })
+}
+
+/// Writes records as a merged profile, starting with the schema line and header row
+pub fn write_merged_profile<W: Write>(out: &mut W, records: &[MergedProfileRecord]) -> Result<()> {
+    schema::write_header(out, OutputKind::MergedProfile)?;
+    for record in records {
+        record.write_to(out)?;
+    }
     Ok(())
 }
 
-fn norm_interruption_count(count: u32, read_len: u32, repeat_len: u32, read_depth: f64) -> f64 {
-    let num_possible_start: u32 = read_len - repeat_len + 1;
-    let expected_num_reads: f64 = num_possible_start as f64 * read_depth;
-    (count as f64) / expected_num_reads
+/// Parses a packed `sample:count,...` column
+pub fn parse_read_counts(s: &str) -> Result<Vec<(String, u32)>> {
+    let mut counts: Vec<(String, u32)> = Vec::new();
+    if s.is_empty() {
+        return Ok(counts);
+    }
+    for entry in s.split(',') {
+        let (sample_id, count) = match entry.rsplit_once(':') {
+            Some(fields) => fields,
+            None => bail!("Expected sample:count, got '{}'", entry),
+        };
+        counts.push((sample_id.to_string(), count.parse::<u32>()?));
+    }
+    Ok(counts)
+}
+
+/// Parses a packed `sample:interruption:normalized_count,...` column
+pub fn parse_merged_interruption_counts(s: &str) -> Result<Vec<(String, String, f64)>> {
+    let mut counts: Vec<(String, String, f64)> = Vec::new();
+    if s.is_empty() {
+        return Ok(counts);
+    }
+    for entry in s.split(',') {
+        let fields: Vec<&str> = entry.rsplitn(3, ':').collect();
+        if fields.len() != 3 {
+            bail!("Expected sample:interruption:count, got '{}'", entry);
+        }
+        counts.push((
+            fields[2].to_string(),
+            fields[1].to_string(),
+            fields[0].parse::<f64>()?,
+        ));
+    }
+    Ok(counts)
+}
+
+/// The command line arguments of `strif merge`
+#[derive(Debug, clap::Args)]
+pub struct MergeArgs {
+    /// The path to the manifest file containing paths to BAMlets and case-control status
+    pub manifest: PathBuf,
+
+    /// The path to a TSV file containing the global average read depth for each sample
+    /// This is needed for normalizing the interription counts.
+    /// The file should have two columns: sample ID and read depth.
+    /// The sample ID should match the sample ID in the manifest.
+    pub read_depths: PathBuf,
+
+    /// The path to the merged profile. Defaults to the same directory as the manifest.
+    pub output: Option<PathBuf>,
+
+    #[clap(flatten)]
+    pub options: MergeOptions,
+
+    /// A PED file of the cohort. For each trio with all three profiles in
+    /// the manifest, interruptions of the proband that both parents lack
+    /// are written to a de novo candidates table next to the merged profile.
+    #[clap(long)]
+    pub ped: Option<PathBuf>,
+
+    /// The fewest reads each parent needs at a locus for a de novo candidate
+    #[clap(long, default_value = "10", requires = "ped")]
+    pub de_novo_min_depth: u32,
+
+    /// The fewest proband reads supporting a de novo candidate
+    #[clap(long, default_value = "3", requires = "ped")]
+    pub de_novo_min_reads: f64,
+}
+
+impl MergeArgs {
+    /// Runs `strif merge`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let MergeArgs {
+            manifest,
+            read_depths,
+            output,
+            ped,
+            de_novo_min_depth,
+            de_novo_min_reads,
+            options,
+        } = self;
+        let outputs = options.outputs(&manifest, output, ped.is_some());
+        let options = MergeOptions {
+            skip_report: Some(outputs.skip_report.clone()),
+            ..options
+        };
+        let shard = options.shard;
+        Plan::new("merge", dry_run, force)
+            .merge(&manifest, &read_depths, options.filter.as_deref(), shard)
+            .maybe_input("pedigree", ped.as_deref())
+            .maybe_input("correction factors", options.correction_factors.as_deref())
+            .output("merged profile", &outputs.merged_profile)
+            .output("skip report", &outputs.skip_report)
+            .maybe_output("de novo candidates", outputs.de_novo.as_deref())
+            .maybe_reused_output(
+                "pseudonym key",
+                options.pseudonym_key_path(&manifest).as_deref(),
+            )
+            .execute(|| {
+                merge(
+                    manifest.clone(),
+                    read_depths,
+                    outputs.merged_profile,
+                    &options,
+                )?;
+                if let (Some(ped), Some(de_novo_path)) = (ped, outputs.de_novo) {
+                    let params = DeNovoParams {
+                        min_supporting_reads: de_novo_min_reads,
+                        min_parent_reads: de_novo_min_depth,
+                    };
+                    de_novo(
+                        &manifest,
+                        &ped,
+                        &de_novo_path,
+                        &params,
+                        options.filter.as_deref(),
+                        shard,
+                    )?;
+                }
+                Ok(())
+            })
+    }
+}
+
+/// The command line arguments of `strif merge-combine`
+#[derive(Debug, clap::Args)]
+pub struct MergeCombineArgs {
+    /// The paths to the merged profile shards
+    #[clap(required = true)]
+    pub shards: Vec<PathBuf>,
+
+    /// The path to the combined merged profile
+    #[clap(short, long)]
+    pub output: PathBuf,
+}
+
+impl MergeCombineArgs {
+    /// Runs `strif merge-combine`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let MergeCombineArgs { shards, output } = self;
+        Plan::new("merge-combine", dry_run, force)
+            .inputs("shards", &shards)
+            .output("merged profile", &output)
+            .execute(|| merge_combine(shards, output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::profile::{write_profile, ProfileRecord, COUNT_UNIT_PREFIX};
+    use crate::testing::{merged_record, profile_record, write_merged_profile_file};
+
+    /// A directory with the profiles of a cohort, its manifest and read
+    /// depths of 1
+    struct Cohort {
+        dir: TempDir,
+        manifest: PathBuf,
+        read_depths: PathBuf,
+    }
+
+    impl Cohort {
+        fn new(samples: &[(&str, Vec<ProfileRecord>)]) -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let mut manifest = String::new();
+            let mut read_depths = String::new();
+            for (sample_id, records) in samples {
+                let path = dir.path().join(format!("{}.profile.tsv", sample_id));
+                let mut out = Vec::new();
+                write_profile(&mut out, records).unwrap();
+                fs::write(&path, out).unwrap();
+                manifest.push_str(&format!("{}\tcase\t{}\n", sample_id, path.display()));
+                read_depths.push_str(&format!("{}\t1\n", sample_id));
+            }
+            let cohort = Cohort {
+                manifest: dir.path().join("manifest.tsv"),
+                read_depths: dir.path().join("read_depths.tsv"),
+                dir,
+            };
+            fs::write(&cohort.manifest, manifest).unwrap();
+            fs::write(&cohort.read_depths, read_depths).unwrap();
+            cohort
+        }
+
+        /// Merges the cohort into `name` and reads back its records
+        fn merge(&self, name: &str, options: &MergeOptions) -> Vec<MergedProfileRecord> {
+            let out = self.dir.path().join(name);
+            merge(
+                self.manifest.clone(),
+                self.read_depths.clone(),
+                out.clone(),
+                options,
+            )
+            .unwrap();
+            read_merged_profile(&out).unwrap()
+        }
+    }
+
+    /// The rows of merged profile records, for comparing merges
+    fn rows(records: &[MergedProfileRecord]) -> Vec<String> {
+        records
+            .iter()
+            .map(|record| {
+                let mut out = Vec::new();
+                record.write_to(&mut out).unwrap();
+                String::from_utf8(out).unwrap()
+            })
+            .collect()
+    }
+
+    /// A cohort of two samples sharing some of eight loci
+    fn two_sample_cohort() -> Cohort {
+        let loci = |sample: u32| -> Vec<ProfileRecord> {
+            (1..=8)
+                .filter(|locus| locus % sample == 0 || *locus < 4)
+                .map(|locus| {
+                    let locus_id = format!("L{}", locus);
+                    profile_record(&locus_id, 10 + locus, &[("CAT", 40, locus as f64)])
+                })
+                .collect()
+        };
+        Cohort::new(&[("s1", loci(1)), ("s2", loci(2))])
+    }
+
+    #[test]
+    fn shards_combine_into_the_unsharded_merge() {
+        let cohort = two_sample_cohort();
+        let unsharded = cohort.merge("merged.tsv", &MergeOptions::default());
+        assert_eq!(unsharded.len(), 8);
+
+        let mut shard_paths = Vec::new();
+        let mut shard_loci = 0;
+        for index in 1..=2 {
+            let shard = Shard { index, count: 2 };
+            let name = format!("merged.shard{}.tsv", index);
+            let records = cohort.merge(&name, &MergeOptions::default().shard(shard));
+            assert!(!records.is_empty());
+            assert!(records
+                .iter()
+                .all(|record| shard.contains(&record.locus_id)));
+            shard_loci += records.len();
+            shard_paths.push(cohort.dir.path().join(name));
+        }
+        assert_eq!(shard_loci, 8);
+
+        let combined = cohort.dir.path().join("combined.tsv");
+        merge_combine(shard_paths, combined.clone()).unwrap();
+        assert_eq!(
+            rows(&read_merged_profile(&combined).unwrap()),
+            rows(&unsharded)
+        );
+    }
+
+    #[test]
+    fn skip_reports_count_the_rows_of_each_sample_by_reason() {
+        let cohort = two_sample_cohort();
+        let skip_report = cohort.dir.path().join("merged.merge_skips.tsv");
+        let options = MergeOptions::default()
+            .filter("L[1-6]".to_string())
+            .min_read_count(13)
+            .read_length(30)
+            .skip_report(skip_report.clone());
+        cohort.merge("merged.tsv", &options);
+        // the repeat lengths of 40 bp are longer than the reads
+        assert_eq!(
+            crate::testing::data_rows(&skip_report),
+            ["s1\t8\t4\t2\t0\t2\t0\t4", "s2\t6\t3\t1\t0\t2\t0\t3"]
+        );
+    }
+
+    #[test]
+    fn profiles_and_read_depths_must_count_the_same_unit() {
+        let cohort = two_sample_cohort();
+        let read_depths = fs::read_to_string(&cohort.read_depths).unwrap();
+        fs::write(
+            &cohort.read_depths,
+            format!("{}fragment\n{}", COUNT_UNIT_PREFIX, read_depths),
+        )
+        .unwrap();
+        let error = merge(
+            cohort.manifest.clone(),
+            cohort.read_depths.clone(),
+            cohort.dir.path().join("merged.tsv"),
+            &MergeOptions::default(),
+        )
+        .unwrap_err();
+        let expected = format!(
+            "counts reads but the read depths of {} are of fragments",
+            cohort.read_depths.display()
+        );
+        assert!(error.to_string().contains(&expected));
+    }
+
+    #[test]
+    fn correction_factors_scale_the_expected_reads() {
+        assert_eq!(norm_interruption_count(2.0, 150, 51, 2.0, 1.0), 0.01);
+        // half the expected reads are recruited, so each counts twice
+        assert_eq!(norm_interruption_count(2.0, 150, 51, 2.0, 0.5), 0.02);
+        // repeats longer than the reads are spanned from one start
+        assert_eq!(norm_interruption_count(2.0, 150, 200, 2.0, 1.0), 1.0);
+
+        let dir = tempfile::tempdir().unwrap();
+        let track = dir.path().join("mappability.bed");
+        fs::write(
+            &track,
+            "track name=mappability\nchr1\t0\t100\t0.5\nchr1\t100\t300\t1.0\nchr2\t1000\t1100\t0.25\n",
+        )
+        .unwrap();
+        let factors = CorrectionFactors::from_path(&track).unwrap();
+        // weighted by the overlap of each interval
+        assert_eq!(factors.factor("chr1:50-150"), Some(0.75));
+        assert_eq!(factors.factor("chr2:1050-1050"), Some(0.25));
+        assert_eq!(factors.factor("chr1:400-500"), None);
+        assert_eq!(factors.factor("chr3:50-150"), None);
+        assert_eq!(factors.factor("unplaced"), None);
+
+        // the loci of the cohort are at chr1:100-200, which recruits half the reads
+        let cohort = two_sample_cohort();
+        fs::write(&track, "chr1\t0\t1000\t0.5\n").unwrap();
+        let uncorrected = cohort.merge("uncorrected.tsv", &MergeOptions::default());
+        let corrected = cohort.merge(
+            "corrected.tsv",
+            &MergeOptions::default().correction_factors(track),
+        );
+        assert_eq!(corrected.len(), uncorrected.len());
+        for (corrected, uncorrected) in corrected.iter().zip(&uncorrected) {
+            assert_eq!(corrected.read_counts, uncorrected.read_counts);
+            for (a, b) in corrected
+                .interruption_counts
+                .iter()
+                .zip(&uncorrected.interruption_counts)
+            {
+                assert!((a.2 - 2.0 * b.2).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_correction_factors_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let track = dir.path().join("factors.bed");
+        let error = |contents: &str| {
+            fs::write(&track, contents).unwrap();
+            CorrectionFactors::from_path(&track)
+                .err()
+                .unwrap()
+                .to_string()
+        };
+        assert!(error("chr1\t0\t100\n").contains("line 1 has 3 columns"));
+        assert!(error("chr1\t0\t100\t0\n").contains("expected a positive number"));
+        assert!(error("chr1\t0\t100\tNaN\n").contains("expected a positive number"));
+        assert!(error("chr1\tstart\t100\t1\n").contains("Invalid start"));
+    }
+
+    #[test]
+    fn jsonl_merges_round_trip_to_the_tsv_records() {
+        let cohort = two_sample_cohort();
+        let tsv = cohort.merge("merged.tsv", &MergeOptions::default());
+
+        let out = cohort.dir.path().join("merged.jsonl");
+        let options = MergeOptions::default().output_format(OutputFormat::Jsonl);
+        merge(
+            cohort.manifest.clone(),
+            cohort.read_depths.clone(),
+            out.clone(),
+            &options,
+        )
+        .unwrap();
+        let jsonl: Vec<MergedProfileRecord> = fs::read_to_string(&out)
+            .unwrap()
+            .lines()
+            .map(|line| MergedProfileRecord::from_json(&serde_json::from_str(line).unwrap()))
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(rows(&jsonl), rows(&tsv));
+
+        let value = tsv[0].to_json();
+        assert_eq!(value["read_counts"]["s2"], json!(11));
+        assert_eq!(
+            value["interruption_counts"]["s1"]["CAT"],
+            json!(1.0 / 111.0)
+        );
+        assert!(MergedProfileRecord::from_json(&json!({ "locus_id": "L1" })).is_err());
+    }
+
+    #[test]
+    fn length_strata_split_back_into_interruptions() {
+        let bin = |width| NonZeroU32::new(width).unwrap();
+        assert_eq!(length_stratum("AG", 98, bin(1)), "AG@98");
+        assert_eq!(length_stratum("AG", 98, bin(10)), "AG@90-99");
+        assert_eq!(split_length_stratum("AG@98"), ("AG", Some("98")));
+        assert_eq!(split_length_stratum("AG@90-99"), ("AG", Some("90-99")));
+        assert_eq!(split_length_stratum("AG"), ("AG", None));
+        assert_eq!(split_length_stratum("AG@x"), ("AG@x", None));
+    }
+
+    #[test]
+    fn length_bins_keep_interruptions_of_each_stratum_apart() {
+        let cohort = Cohort::new(&[(
+            "s1",
+            vec![profile_record(
+                "L1",
+                10,
+                &[("CAT", 30, 2.0), ("CAT", 35, 1.0), ("CAT", 45, 1.0)],
+            )],
+        )]);
+        let options = MergeOptions::default().length_bin(NonZeroU32::new(10).unwrap());
+        let records = cohort.merge("merged.tsv", &options);
+        let counts: Vec<(&str, f64)> = records[0]
+            .interruption_counts
+            .iter()
+            .map(|(_, interruption, count)| (interruption.as_str(), *count))
+            .collect();
+        // counts are normalized by the repeat length of each read before
+        // they are binned
+        let expected_30s = 2.0 / 121.0 + 1.0 / 116.0;
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts[0].0, "CAT@30-39");
+        assert!((counts[0].1 - expected_30s).abs() < 1e-6);
+        assert_eq!(counts[1].0, "CAT@40-49");
+        assert!((counts[1].1 - 1.0 / 106.0).abs() < 1e-6);
+
+        let unbinned = cohort.merge("unbinned.tsv", &MergeOptions::default());
+        assert_eq!(unbinned[0].interruption_counts.len(), 1);
+        assert_eq!(unbinned[0].interruption_counts[0].1, "CAT");
+    }
+
+    #[test]
+    fn merged_profile_records_are_sorted_by_locus() {
+        let mut merged_profile = MergedProfile::default();
+        for locus_id in ["L2", "L1"] {
+            merged_profile.add_motif(locus_id, "CAG");
+            merged_profile.add_reference_region(locus_id, "chr1:100-200");
+        }
+        merged_profile.add_read_count("L2", "s2", 7);
+        merged_profile.add_read_count("L2", "s1", 5);
+        merged_profile.increment_interruption("L2", "s1", "CAT", 0.5);
+        let records = merged_profile.records();
+        assert_eq!(records[0].locus_id, "L1");
+        assert_eq!(records[1].packed_read_counts(), "s1:5,s2:7");
+        assert_eq!(records[1].packed_interruption_counts(), "s1:CAT:0.5");
+    }
+
+    #[test]
+    fn counts_of_a_sample_and_interruption_accumulate() {
+        let cohort = Cohort::new(&[
+            (
+                "s1",
+                vec![profile_record(
+                    "L1",
+                    10,
+                    &[("CAT", 30, 2.0), ("CAT", 40, 1.0)],
+                )],
+            ),
+            (
+                "s2",
+                vec![profile_record(
+                    "L1",
+                    10,
+                    &[("CAT", 40, 1.0), ("CTG", 40, 1.0)],
+                )],
+            ),
+        ]);
+        let records = cohort.merge("merged.tsv", &MergeOptions::default());
+        let counts = &records[0].interruption_counts;
+        assert_eq!(counts.len(), 3);
+        assert_eq!((counts[0].0.as_str(), counts[0].1.as_str()), ("s1", "CAT"));
+        assert!((counts[0].2 - (2.0 / 121.0 + 1.0 / 111.0)).abs() < 1e-6);
+        assert!((counts[1].2 - 1.0 / 111.0).abs() < 1e-6);
+        assert_eq!(counts[2].1, "CTG");
+    }
+
+    #[test]
+    fn merges_do_not_depend_on_the_order_of_the_manifest() {
+        let s1 = || {
+            vec![
+                profile_record("L2", 10, &[("CTG", 40, 1.0)]),
+                profile_record("L1", 10, &[]),
+            ]
+        };
+        let s2 = || {
+            vec![profile_record(
+                "L1",
+                12,
+                &[("CAT", 40, 1.0), ("CAA", 40, 1.0)],
+            )]
+        };
+        let forward = Cohort::new(&[("s1", s1()), ("s2", s2())]);
+        let backward = Cohort::new(&[("s2", s2()), ("s1", s1())]);
+        let records = forward.merge("merged.tsv", &MergeOptions::default());
+        assert_eq!(
+            rows(&records),
+            rows(&backward.merge("merged.tsv", &MergeOptions::default()))
+        );
+        assert_eq!(records[0].packed_read_counts(), "s1:10,s2:12");
+        let interruptions: Vec<&str> = records[0]
+            .interruption_counts
+            .iter()
+            .map(|(_, interruption, _)| interruption.as_str())
+            .collect();
+        assert_eq!(interruptions, ["CAA", "CAT"]);
+    }
+
+    #[test]
+    fn spilled_merges_equal_the_in_memory_merge() {
+        let cohort = two_sample_cohort();
+        let in_memory = cohort.merge("merged.tsv", &MergeOptions::default());
+        // a budget of one byte spills after every row
+        let options = MergeOptions::default().max_memory(ByteSize(1));
+        let spilled = cohort.merge("spilled.tsv", &options);
+        assert_eq!(rows(&spilled), rows(&in_memory));
+
+        let jsonl = cohort.dir.path().join("spilled.jsonl");
+        merge(
+            cohort.manifest.clone(),
+            cohort.read_depths.clone(),
+            jsonl.clone(),
+            &options.clone().output_format(OutputFormat::Jsonl),
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(jsonl).unwrap().lines().count(), 8);
+
+        // the scratch directory of the spills is removed
+        assert!(fs::read_dir(cohort.dir.path()).unwrap().all(|entry| !entry
+            .unwrap()
+            .file_name()
+            .to_string_lossy()
+            .starts_with('.')));
+    }
+
+    #[test]
+    fn spilled_parts_combine_the_counts_of_shared_loci() {
+        let dir = tempfile::tempdir().unwrap();
+        let parts = vec![
+            write_merged_profile_file(
+                dir.path(),
+                "spill1.tsv",
+                &[
+                    merged_record("L1", &[("s2", 5)], &[("s2", "CAT", 0.5)]),
+                    merged_record("L3", &[("s2", 5)], &[]),
+                ],
+            ),
+            write_merged_profile_file(
+                dir.path(),
+                "spill2.tsv",
+                &[merged_record(
+                    "L1",
+                    &[("s1", 7)],
+                    &[("s1", "CAT", 0.25), ("s2", "CAT", 0.25)],
+                )],
+            ),
+        ];
+        let mut spills = Spills::new(&dir.path().join("merged.tsv"));
+        spills.paths = parts;
+        let out = dir.path().join("merged.tsv");
+        assert_eq!(spills.merge_into(&out, OutputFormat::Tsv).unwrap(), 2);
+
+        let records = read_merged_profile(&out).unwrap();
+        assert_eq!(records[0].packed_read_counts(), "s1:7,s2:5");
+        assert_eq!(
+            records[0].packed_interruption_counts(),
+            "s1:CAT:0.25,s2:CAT:0.75"
+        );
+        assert_eq!(records[1].locus_id, "L3");
+    }
+
+    #[test]
+    fn dry_runs_of_merge_write_nothing() {
+        let cohort = Cohort::new(&[("s1", vec![profile_record("L1", 10, &[])])]);
+        let output = cohort.dir.path().join("merged.tsv");
+        let args = MergeArgs {
+            manifest: cohort.manifest.clone(),
+            read_depths: cohort.read_depths.clone(),
+            output: Some(output.clone()),
+            options: MergeOptions::default(),
+            ped: None,
+            de_novo_min_depth: 10,
+            de_novo_min_reads: 3.0,
+        };
+        args.execute(true, false).unwrap();
+        assert!(!output.exists());
+    }
 }
@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use log::info;
+
+use crate::convert::{read_tsv, write_tsv, Records};
+use crate::extract::{RepeatSeqRecord, MISSING_QUALITIES};
+use crate::plan::Plan;
+use crate::schema::{self, SCHEMA_VERSION};
+
+/// Rewrites extract, profile and merge outputs written by earlier releases of
+/// strif in the current schema, so that they can be merged with new runs.
+/// With an output directory, each file is written there under its own name;
+/// without one, each file is replaced in place and files already in the
+/// current schema are left alone.
+pub fn migrate(inputs: Vec<PathBuf>, out_dir: Option<PathBuf>) -> Result<()> {
+    let out_paths: Vec<PathBuf> = match &out_dir {
+        Some(out_dir) => {
+            fs::create_dir_all(out_dir)
+                .with_context(|| format!("Could not create {}", out_dir.display()))?;
+            migrated_paths(&inputs, out_dir)?
+        }
+        None => inputs.clone(),
+    };
+
+    let mut migrated = 0;
+    for (input, out_path) in inputs.iter().zip(&out_paths) {
+        let version = schema::schema_version(input)
+            .with_context(|| format!("Could not read {}", input.display()))?;
+        if version == SCHEMA_VERSION && out_dir.is_none() {
+            info!(
+                "{} is already in schema version {}, skipping",
+                input.display(),
+                SCHEMA_VERSION
+            );
+            continue;
+        }
+        let records = read_tsv(input)?;
+        if let Records::RepeatSeqs(records) = &records {
+            check_qualities(records, input)?;
+        }
+        info!(
+            "Migrating {} '{}' records of {} from schema version {} to {}...",
+            records.len(),
+            records.kind().name(),
+            input.display(),
+            version,
+            SCHEMA_VERSION
+        );
+        write_tsv(&records, out_path)?;
+        migrated += 1;
+    }
+
+    info!("Migrated {} of {} files", migrated, inputs.len());
+    Ok(())
+}
+
+/// Checks that the third column of repeat sequences holds their base
+/// qualities. Repeat sequences have no header row, so any file of two or three
+/// columns, such as a manifest, would otherwise be migrated as repeat sequences.
+fn check_qualities(records: &[RepeatSeqRecord], input: &Path) -> Result<()> {
+    for (i, record) in records.iter().enumerate() {
+        if let Some(qualities) = &record.qualities {
+            if qualities != MISSING_QUALITIES && qualities.len() != record.seq.len() {
+                bail!(
+                    "Repeat sequence {} of {} has {} bases but {} base qualities, is it a file of repeat sequences?",
+                    i + 1,
+                    input.display(),
+                    record.seq.len(),
+                    qualities.len()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The paths that `migrate` writes files to in an output directory. Inputs
+/// with the same file name would overwrite each other, so they are rejected.
+pub fn migrated_paths(inputs: &[PathBuf], out_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut seen: HashMap<&std::ffi::OsStr, &Path> = HashMap::new();
+    let mut out_paths = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let file_name = match input.file_name() {
+            Some(file_name) => file_name,
+            None => bail!("{} is not a file", input.display()),
+        };
+        if let Some(other) = seen.insert(file_name, input) {
+            bail!(
+                "{} and {} have the same file name and would overwrite each other in {}",
+                other.display(),
+                input.display(),
+                out_dir.display()
+            );
+        }
+        out_paths.push(out_dir.join(file_name));
+    }
+    Ok(out_paths)
+}
+
+/// The command line arguments of `strif migrate`
+#[derive(Debug, clap::Args)]
+pub struct MigrateArgs {
+    /// The paths to the files to migrate
+    #[clap(required = true)]
+    pub inputs: Vec<PathBuf>,
+
+    /// The directory to write the migrated files to, under their own names
+    #[clap(short, long, required_unless_present = "in_place")]
+    pub out_dir: Option<PathBuf>,
+
+    /// Replace each file with its migrated version instead. Files already in the current schema are left alone.
+    #[clap(long, action, conflicts_with = "out_dir")]
+    pub in_place: bool,
+}
+
+impl MigrateArgs {
+    /// Runs `strif migrate`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let MigrateArgs {
+            inputs,
+            out_dir,
+            in_place: _,
+        } = self;
+        let mut plan = Plan::new("migrate", dry_run, force);
+        for input in &inputs {
+            plan.migration(input);
+        }
+        match &out_dir {
+            Some(out_dir) => {
+                for out_path in migrated_paths(&inputs, out_dir)? {
+                    plan.output("migrated file", &out_path);
+                }
+            }
+            None => {
+                plan.note("output", "in place");
+            }
+        }
+        plan.execute(|| migrate(inputs, out_dir))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::read_repeat_seqs;
+
+    #[test]
+    fn legacy_repeat_seqs_are_rewritten_in_the_current_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy = dir.path().join("sample.repeat_seqs.tsv");
+        fs::write(&legacy, "HTT\tCAGCAGCAG\nHTT\tCAGCATCAG\t?????????\n").unwrap();
+        assert_eq!(schema::schema_version(&legacy).unwrap(), 1);
+
+        let out_dir = dir.path().join("migrated");
+        migrate(vec![legacy.clone()], Some(out_dir.clone())).unwrap();
+        let migrated = out_dir.join("sample.repeat_seqs.tsv");
+        assert_eq!(schema::schema_version(&migrated).unwrap(), SCHEMA_VERSION);
+        let records = read_repeat_seqs(&migrated).unwrap();
+        let seqs: Vec<&str> = records.iter().map(|record| record.seq.as_str()).collect();
+        assert_eq!(seqs, ["CAGCAGCAG", "CAGCATCAG"]);
+        assert_eq!(records[1].qualities.as_deref(), Some("?????????"));
+
+        // files in the current schema are left alone in place
+        let before = fs::read_to_string(&migrated).unwrap();
+        migrate(vec![legacy.clone(), migrated.clone()], None).unwrap();
+        assert_eq!(fs::read_to_string(&migrated).unwrap(), before);
+        assert_eq!(schema::schema_version(&legacy).unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn files_that_are_not_repeat_seqs_are_not_migrated_as_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dir.path().join("manifest.tsv");
+        fs::write(&manifest, "s1\tCAGCAG\ts1.profile\n").unwrap();
+        let out_dir = dir.path().join("migrated");
+        let error = migrate(vec![manifest], Some(out_dir)).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("is it a file of repeat sequences?"));
+    }
+
+    #[test]
+    fn inputs_sharing_a_file_name_are_rejected() {
+        let inputs = [PathBuf::from("a/sample.tsv"), PathBuf::from("b/sample.tsv")];
+        assert!(migrated_paths(&inputs, Path::new("out")).is_err());
+        let inputs = [PathBuf::from("a/s1.tsv"), PathBuf::from("b/s2.tsv")];
+        assert_eq!(
+            migrated_paths(&inputs, Path::new("out")).unwrap(),
+            [PathBuf::from("out/s1.tsv"), PathBuf::from("out/s2.tsv")]
+        );
+    }
+}
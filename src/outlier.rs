@@ -0,0 +1,428 @@
+use std::io::prelude::*;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+use log::{info, warn};
+
+use crate::merge::{norm_interruption_count, read_merged_profile, MergedProfileRecord};
+use crate::plan::Plan;
+use crate::profile::read_profile;
+use crate::schema::{self, OutputKind};
+use crate::stats::quantile;
+use crate::utils::{create_output, get_default_out_path};
+
+/// Scales the median absolute deviation to the standard deviation of a normal distribution
+const MAD_SCALE: f64 = 1.4826;
+/// Scales the mean absolute deviation to the standard deviation of a normal distribution
+const MEAN_AD_SCALE: f64 = 1.2533;
+
+pub struct OutlierParams {
+    /// Global average read depth of the sample, needed to normalize a per-sample profile
+    pub read_depth: Option<f64>,
+    pub read_length: u32,
+    /// The sample to screen when the input is a merged profile
+    pub sample_id: Option<String>,
+    pub min_read_count: u32,
+    /// Minimum number of background samples covering a locus
+    pub min_background: usize,
+}
+
+/// A locus of the screened sample with normalized interruption counts
+struct SampleLocus {
+    read_count: u32,
+    interruption_counts: BTreeMap<String, f64>,
+}
+
+struct Outlier<'a> {
+    background: &'a MergedProfileRecord,
+    read_count: u32,
+    burden: f64,
+    background_samples: usize,
+    median: f64,
+    mad: f64,
+    z_score: f64,
+    empirical_p: f64,
+    top_interruption: String,
+}
+
+/// Compares the interruption burden of one sample against a background
+/// cohort at each locus and ranks loci by outlier score
+pub fn outlier(
+    background: PathBuf,
+    sample: PathBuf,
+    out_path: PathBuf,
+    params: OutlierParams,
+) -> Result<()> {
+    info!("Loading sample...");
+    let (sample_id, sample_loci) = load_sample(&sample, &params)?;
+    info!("Loading background cohort...");
+    let background_records = read_merged_profile(&background)?;
+
+    info!("Screening {} against the background...", sample_id);
+    let mut outliers: Vec<Outlier> = Vec::new();
+    let mut n_skipped: usize = 0;
+    for record in &background_records {
+        let locus = match sample_loci.get(&record.locus_id) {
+            Some(locus) if locus.read_count >= params.min_read_count => locus,
+            _ => continue,
+        };
+
+        // burden of each background sample, excluding the screened sample
+        let mut burdens: HashMap<&str, f64> = record
+            .read_counts
+            .iter()
+            .filter(|(id, count)| *id != sample_id && *count >= params.min_read_count)
+            .map(|(id, _)| (id.as_str(), 0.0))
+            .collect();
+        let mut interruption_totals: HashMap<&str, f64> = HashMap::new();
+        for (id, interruption, count) in &record.interruption_counts {
+            if let Some(burden) = burdens.get_mut(id.as_str()) {
+                *burden += count;
+                *interruption_totals.entry(interruption).or_default() += count;
+            }
+        }
+        if burdens.len() < params.min_background {
+            n_skipped += 1;
+            continue;
+        }
+        let n = burdens.len() as f64;
+
+        let burden: f64 = locus.interruption_counts.values().sum();
+        let mut values: Vec<f64> = burdens.into_values().collect();
+        values.sort_by(|a, b| a.total_cmp(b));
+        let median = quantile(&values, 0.5);
+        let mut deviations: Vec<f64> = values.iter().map(|v| (v - median).abs()).collect();
+        deviations.sort_by(|a, b| a.total_cmp(b));
+        let mad = quantile(&deviations, 0.5);
+        let z_score = robust_z_score(burden, median, mad, &values);
+        let as_extreme = values.iter().filter(|v| **v >= burden).count() as f64;
+
+        // the interruption most in excess of its background mean
+        let top_interruption = locus
+            .interruption_counts
+            .iter()
+            .map(|(interruption, count)| {
+                let mean = interruption_totals
+                    .get(interruption.as_str())
+                    .unwrap_or(&0.0)
+                    / n;
+                (interruption, count - mean)
+            })
+            .filter(|(_, excess)| *excess > 0.0)
+            .max_by(|a, b| a.1.total_cmp(&b.1).then(b.0.cmp(a.0)))
+            .map(|(interruption, _)| interruption.clone())
+            .unwrap_or_else(|| ".".to_string());
+
+        outliers.push(Outlier {
+            background: record,
+            read_count: locus.read_count,
+            burden,
+            background_samples: values.len(),
+            median,
+            mad,
+            z_score,
+            empirical_p: (as_extreme + 1.0) / (n + 1.0),
+            top_interruption,
+        });
+    }
+    if n_skipped > 0 {
+        warn!(
+            "Skipped {} loci with fewer than {} background samples",
+            n_skipped, params.min_background
+        );
+    }
+    if outliers.is_empty() {
+        bail!(
+            "No loci of {} are covered by both the sample and the background",
+            sample.display()
+        );
+    }
+
+    outliers.sort_by(|a, b| {
+        b.z_score
+            .total_cmp(&a.z_score)
+            .then(a.background.locus_id.cmp(&b.background.locus_id))
+    });
+    let mut out_file = create_output(&out_path)?;
+    schema::write_header(&mut out_file, OutputKind::Outlier)?;
+    for o in &outliers {
+        writeln!(
+            out_file,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            o.background.locus_id,
+            o.background.reference_region,
+            o.background.motif,
+            o.read_count,
+            o.burden,
+            o.background_samples,
+            o.median,
+            o.mad,
+            o.z_score,
+            o.empirical_p,
+            o.top_interruption
+        )?;
+    }
+
+    info!("Screened {} loci", outliers.len());
+    out_file.finish()?;
+
+    info!("Done!");
+    Ok(())
+}
+
+/// Robust z-score using the median absolute deviation. When more than half of
+/// the background has the same burden (so the MAD is 0), the mean absolute
+/// deviation is used instead, and a locus where the whole background agrees
+/// scores 0 unless the sample differs from it.
+fn robust_z_score(value: f64, median: f64, mad: f64, background: &[f64]) -> f64 {
+    let scale = if mad > 0.0 {
+        MAD_SCALE * mad
+    } else {
+        let mean_deviation =
+            background.iter().map(|v| (v - median).abs()).sum::<f64>() / background.len() as f64;
+        MEAN_AD_SCALE * mean_deviation
+    };
+    if scale > 0.0 {
+        (value - median) / scale
+    } else if value > median {
+        f64::INFINITY
+    } else {
+        0.0
+    }
+}
+
+/// Loads the screened sample from a per-sample profile (normalized with the
+/// given read depth) or from a merged profile
+fn load_sample(
+    path: &Path,
+    params: &OutlierParams,
+) -> Result<(String, HashMap<String, SampleLocus>)> {
+    let mut loci: HashMap<String, SampleLocus> = HashMap::new();
+    match schema::detect(path)? {
+        OutputKind::Profile => {
+            let read_depth = match params.read_depth {
+                Some(read_depth) if read_depth > 0.0 => read_depth,
+                _ => bail!("A positive --read-depth is needed to normalize a per-sample profile"),
+            };
+            let sample_id = match &params.sample_id {
+                Some(sample_id) => sample_id.clone(),
+                None => {
+                    let file_name = path.file_name().unwrap().to_str().unwrap();
+                    file_name.split('.').next().unwrap().to_string()
+                }
+            };
+            for record in read_profile(path)? {
+                let mut interruption_counts: BTreeMap<String, f64> = BTreeMap::new();
+                for (interruption, repeat_len, count) in record.interruption_counts {
+                    if repeat_len == 0 || repeat_len > params.read_length {
+                        warn!(
+                            "Skipping a '{}' interruption at {} with an invalid repeat length={}",
+                            interruption, record.locus_id, repeat_len
+                        );
+                        continue;
+                    }
+                    *interruption_counts.entry(interruption).or_default() +=
+                        norm_interruption_count(
+                            count,
+                            params.read_length,
+                            repeat_len,
+                            read_depth,
+                            1.0,
+                        );
+                }
+                loci.insert(
+                    record.locus_id,
+                    SampleLocus {
+                        read_count: record.read_count,
+                        interruption_counts,
+                    },
+                );
+            }
+            Ok((sample_id, loci))
+        }
+        OutputKind::MergedProfile => {
+            let records = read_merged_profile(path)?;
+            let sample_id = match &params.sample_id {
+                Some(sample_id) => sample_id.clone(),
+                None => {
+                    let mut sample_ids: Vec<&str> = records
+                        .iter()
+                        .flat_map(|r| r.read_counts.iter().map(|(id, _)| id.as_str()))
+                        .collect();
+                    sample_ids.sort_unstable();
+                    sample_ids.dedup();
+                    match sample_ids[..] {
+                        [sample_id] => sample_id.to_string(),
+                        _ => bail!(
+                            "{} has {} samples, use --sample-id to choose one",
+                            path.display(),
+                            sample_ids.len()
+                        ),
+                    }
+                }
+            };
+            for record in records {
+                let read_count = match record.read_counts.iter().find(|(id, _)| *id == sample_id) {
+                    Some((_, read_count)) => *read_count,
+                    None => continue,
+                };
+                let mut interruption_counts: BTreeMap<String, f64> = BTreeMap::new();
+                for (id, interruption, count) in record.interruption_counts {
+                    if id == sample_id {
+                        *interruption_counts.entry(interruption).or_default() += count;
+                    }
+                }
+                loci.insert(
+                    record.locus_id,
+                    SampleLocus {
+                        read_count,
+                        interruption_counts,
+                    },
+                );
+            }
+            Ok((sample_id, loci))
+        }
+        kind => bail!(
+            "{} is a '{}' file, expected a profile or merged profile",
+            path.display(),
+            kind.name()
+        ),
+    }
+}
+
+/// The command line arguments of `strif outlier`
+#[derive(Debug, clap::Args)]
+pub struct OutlierArgs {
+    /// The path to the sample's profile, or a merged profile containing the sample
+    pub sample: PathBuf,
+
+    /// The path to the outlier scores. Defaults to the same directory as the sample.
+    pub output: Option<PathBuf>,
+
+    /// The path to the merged profile of the background cohort (e.g. controls)
+    #[clap(short = 'b', long)]
+    pub background: PathBuf,
+
+    /// The global average read depth of the sample. Required for per-sample profiles.
+    #[clap(short = 'd', long)]
+    pub read_depth: Option<f64>,
+
+    /// The sequencing read length. Used for normalizing the interruption counts.
+    #[clap(short = 'l', long, default_value = "150")]
+    pub read_length: u32,
+
+    /// The sample ID. Required if a merged profile with several samples is given.
+    #[clap(short = 's', long)]
+    pub sample_id: Option<String>,
+
+    /// Minimum read count of the sample and of background samples at a locus
+    #[clap(short = 'm', long, default_value = "1")]
+    pub min_read_count: u32,
+
+    /// Minimum number of background samples covering a locus
+    #[clap(short = 'n', long, default_value = "5")]
+    pub min_background: usize,
+}
+
+impl OutlierArgs {
+    /// Runs `strif outlier`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let OutlierArgs {
+            sample,
+            output,
+            background,
+            read_depth,
+            read_length,
+            sample_id,
+            min_read_count,
+            min_background,
+        } = self;
+        let out_path: PathBuf =
+            output.unwrap_or_else(|| get_default_out_path(&sample, "outliers", "tsv"));
+        let params = OutlierParams {
+            read_depth,
+            read_length,
+            sample_id,
+            min_read_count,
+            min_background,
+        };
+        Plan::new("outlier", dry_run, force)
+            .profile("sample profile", &sample)
+            .merged_profile("background", &background)
+            .output("outliers", &out_path)
+            .execute(|| outlier(background, sample, out_path, params))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{data_rows, merged_record, write_merged_profile_file};
+
+    #[test]
+    fn loci_are_ranked_by_robust_z_score_against_the_background() {
+        let dir = tempfile::tempdir().unwrap();
+        let background_samples = [("b1", 10), ("b2", 10), ("b3", 10), ("b4", 10), ("b5", 10)];
+        let background = write_merged_profile_file(
+            dir.path(),
+            "background.tsv",
+            &[
+                merged_record(
+                    "L1",
+                    &background_samples,
+                    &[
+                        ("b1", "CAT", 0.1),
+                        ("b2", "CAT", 0.2),
+                        ("b3", "CAT", 0.2),
+                        ("b4", "CAT", 0.3),
+                        ("b5", "CAT", 0.4),
+                    ],
+                ),
+                merged_record("L2", &background_samples, &[("b1", "A", 0.5)]),
+                merged_record("L3", &background_samples[..2], &[]),
+            ],
+        );
+        let sample = write_merged_profile_file(
+            dir.path(),
+            "sample.tsv",
+            &[
+                merged_record("L1", &[("x", 10)], &[("x", "CAT", 0.2), ("x", "CCG", 0.8)]),
+                merged_record("L2", &[("x", 10)], &[]),
+                merged_record("L3", &[("x", 10)], &[("x", "A", 1.0)]),
+            ],
+        );
+        let out = dir.path().join("outliers.tsv");
+        let params = OutlierParams {
+            read_depth: None,
+            read_length: 150,
+            sample_id: None,
+            min_read_count: 5,
+            min_background: 3,
+        };
+        outlier(background, sample, out.clone(), params).unwrap();
+        let rows = data_rows(&out);
+        // L3 has too few background samples
+        assert_eq!(rows.len(), 2);
+        let l1: Vec<&str> = rows[0].split('\t').collect();
+        assert_eq!(&l1[..4], ["L1", "chr1:100-200", "CAG", "10"]);
+        assert_eq!(l1[5], "5");
+        let z_score: f64 = l1[8].parse().unwrap();
+        assert!((z_score - 0.8 / (MAD_SCALE * 0.1)).abs() < 1e-9);
+        assert_eq!(l1[9], (1.0 / 6.0).to_string());
+        assert_eq!(l1[10], "CCG");
+        assert!(rows[1].starts_with("L2\t"));
+        assert!(rows[1].ends_with("\t1\t."));
+    }
+
+    #[test]
+    fn backgrounds_without_spread_fall_back_to_the_mean_deviation() {
+        let background = [0.0, 0.0, 0.0, 1.0];
+        let scale = MEAN_AD_SCALE * 0.25;
+        assert_eq!(robust_z_score(2.0, 0.0, 0.0, &background), 2.0 / scale);
+        assert_eq!(robust_z_score(2.0, 0.0, 0.0, &[0.0; 4]), f64::INFINITY);
+        assert_eq!(robust_z_score(0.0, 0.0, 0.0, &[0.0; 4]), 0.0);
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use log::warn;
+use regex::Regex;
+
+use crate::error::StrifError;
+use crate::interruptions::LocusParams;
+
+/// The settings of a row of a locus parameters file, each of which replaces
+/// the global one if given
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LocusOverride {
+    pub match_score: Option<i32>,
+    pub mismatch_penalty: Option<i32>,
+    pub gap_open_penalty: Option<i32>,
+    pub gap_extend_penalty: Option<i32>,
+    pub padding: Option<usize>,
+    pub merge_distance: Option<usize>,
+    pub min_insertion_len: Option<usize>,
+    /// Reads whose alignment purity is below this are skipped
+    pub min_purity: Option<f64>,
+}
+
+/// The columns of a locus parameters file besides `locus`
+const COLUMNS: [&str; 8] = [
+    "match_score",
+    "mismatch_penalty",
+    "gap_open_penalty",
+    "gap_extend_penalty",
+    "padding",
+    "merge_distance",
+    "min_insertion_len",
+    "min_purity",
+];
+
+impl LocusOverride {
+    /// Replaces the settings of a locus by those given here
+    pub fn apply(&self, params: &mut LocusParams) {
+        let scores = &mut params.scores;
+        scores.match_score = self.match_score.unwrap_or(scores.match_score);
+        scores.mismatch_penalty = self.mismatch_penalty.unwrap_or(scores.mismatch_penalty);
+        scores.gap_open_penalty = self.gap_open_penalty.unwrap_or(scores.gap_open_penalty);
+        scores.gap_extend_penalty = self.gap_extend_penalty.unwrap_or(scores.gap_extend_penalty);
+        params.padding = self.padding.unwrap_or(params.padding);
+        params.merge_distance = self.merge_distance.unwrap_or(params.merge_distance);
+        params.min_insertion_len = self.min_insertion_len.unwrap_or(params.min_insertion_len);
+    }
+
+    /// Combines the settings of two rows matching a locus, those of the
+    /// later row winning
+    fn then(self, later: LocusOverride) -> LocusOverride {
+        LocusOverride {
+            match_score: later.match_score.or(self.match_score),
+            mismatch_penalty: later.mismatch_penalty.or(self.mismatch_penalty),
+            gap_open_penalty: later.gap_open_penalty.or(self.gap_open_penalty),
+            gap_extend_penalty: later.gap_extend_penalty.or(self.gap_extend_penalty),
+            padding: later.padding.or(self.padding),
+            merge_distance: later.merge_distance.or(self.merge_distance),
+            min_insertion_len: later.min_insertion_len.or(self.min_insertion_len),
+            min_purity: later.min_purity.or(self.min_purity),
+        }
+    }
+}
+
+/// The rows of a locus parameters file, each giving the settings of the loci
+/// whose IDs match its pattern
+pub struct LocusOverrides {
+    /// The pattern as written, its regular expression and its settings
+    rules: Vec<(String, Regex, LocusOverride)>,
+}
+
+impl LocusOverrides {
+    /// Loads a TSV with a `locus` column holding a locus ID or a regular
+    /// expression matching whole locus IDs, and any of the columns of
+    /// [`LocusOverride`]. Empty and `.` values keep the global setting.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let source = &path.display().to_string();
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(b'\t')
+            .has_headers(true)
+            .comment(Some(b'#'))
+            .from_path(path)
+            .with_context(|| format!("Could not open {}", source))?;
+        let headers = reader.headers()?.clone();
+        if headers.get(0) != Some("locus") {
+            bail!("The first column of {} should be 'locus'", source);
+        }
+        for header in headers.iter().skip(1) {
+            if !COLUMNS.contains(&header) {
+                bail!(
+                    "Unknown column '{}' in {}, expected any of: {}",
+                    header,
+                    source,
+                    COLUMNS.join(", ")
+                );
+            }
+        }
+
+        let mut rules = Vec::new();
+        for result in reader.records() {
+            let record = result?;
+            let pattern = Regex::new(&format!("^(?:{})$", &record[0])).map_err(|e| {
+                StrifError::malformed_row(source, &record, format!("invalid locus pattern: {}", e))
+            })?;
+            let mut rule = LocusOverride::default();
+            for (header, value) in headers.iter().zip(record.iter()).skip(1) {
+                if value.is_empty() || value == "." {
+                    continue;
+                }
+                let invalid = || {
+                    StrifError::malformed_row(
+                        source,
+                        &record,
+                        format!("invalid {} '{}'", header, value),
+                    )
+                };
+                let int = || value.parse::<i32>().map_err(|_| invalid());
+                let len = || value.parse::<usize>().map_err(|_| invalid());
+                match header {
+                    "match_score" => rule.match_score = Some(int()?),
+                    "mismatch_penalty" => rule.mismatch_penalty = Some(int()?),
+                    "gap_open_penalty" => rule.gap_open_penalty = Some(int()?),
+                    "gap_extend_penalty" => rule.gap_extend_penalty = Some(int()?),
+                    "padding" => rule.padding = Some(len()?),
+                    "merge_distance" => rule.merge_distance = Some(len()?),
+                    "min_insertion_len" => rule.min_insertion_len = Some(len()?),
+                    _ => match value.parse::<f64>() {
+                        Ok(purity) if (0.0..=1.0).contains(&purity) => {
+                            rule.min_purity = Some(purity)
+                        }
+                        _ => return Err(invalid().into()),
+                    },
+                }
+            }
+            rules.push((record[0].to_string(), pattern, rule));
+        }
+        Ok(LocusOverrides { rules })
+    }
+
+    /// The settings of each locus matched by a row, combining those of all
+    /// rows matching it in file order. Rows matching none of the loci are
+    /// reported, as they usually are typos.
+    pub fn resolve<'a, I>(&self, locus_ids: I) -> HashMap<String, LocusOverride>
+    where
+        I: IntoIterator<Item = &'a String>,
+    {
+        let mut matched = vec![false; self.rules.len()];
+        let mut resolved = HashMap::new();
+        for locus_id in locus_ids {
+            let mut locus_override: Option<LocusOverride> = None;
+            for (i, (_, pattern, rule)) in self.rules.iter().enumerate() {
+                if pattern.is_match(locus_id) {
+                    matched[i] = true;
+                    locus_override = Some(locus_override.unwrap_or_default().then(*rule));
+                }
+            }
+            if let Some(locus_override) = locus_override {
+                resolved.insert(locus_id.clone(), locus_override);
+            }
+        }
+        for ((pattern, _, _), matched) in self.rules.iter().zip(matched) {
+            if !matched {
+                warn!(
+                    "The locus parameters of '{}' match no locus of the catalog",
+                    pattern
+                );
+            }
+        }
+        resolved
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::AlignmentScoreParams;
+
+    fn load(contents: &str) -> Result<LocusOverrides> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("locus_params.tsv");
+        std::fs::write(&path, contents).unwrap();
+        LocusOverrides::from_path(&path)
+    }
+
+    #[test]
+    fn later_rows_matching_a_locus_win() {
+        let overrides = load(
+            "# loci with long interruptions\n\
+             locus\tmerge_distance\tmin_purity\tpadding\n\
+             HTT|FMR1\t2\t0.5\t.\n\
+             FMR1\t3\t\t4\n\
+             ATXN[0-9]+\t1\t.\t.\n",
+        )
+        .unwrap();
+        let loci = ["HTT", "FMR1", "HTTX"].map(String::from);
+        let resolved = overrides.resolve(&loci);
+
+        assert_eq!(resolved.len(), 2);
+        assert_eq!(
+            resolved["HTT"],
+            LocusOverride {
+                merge_distance: Some(2),
+                min_purity: Some(0.5),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            resolved["FMR1"],
+            LocusOverride {
+                merge_distance: Some(3),
+                min_purity: Some(0.5),
+                padding: Some(4),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn overrides_replace_only_the_settings_they_give() {
+        let locus_override = LocusOverride {
+            mismatch_penalty: Some(4),
+            min_insertion_len: Some(2),
+            ..Default::default()
+        };
+        let mut params = LocusParams::from(AlignmentScoreParams::default());
+        locus_override.apply(&mut params);
+
+        assert_eq!(
+            params.scores,
+            AlignmentScoreParams {
+                mismatch_penalty: 4,
+                ..Default::default()
+            }
+        );
+        assert_eq!(params.min_insertion_len, 2);
+        assert_eq!(params.merge_distance, 0);
+    }
+
+    #[test]
+    fn malformed_locus_params_are_rejected() {
+        let message = |contents| load(contents).err().unwrap().to_string();
+
+        assert!(message("id\tpadding\nHTT\t1\n").contains("should be 'locus'"));
+        assert!(message("locus\tpurity\nHTT\t0.5\n").contains("Unknown column 'purity'"));
+        assert!(message("locus\tpadding\nHTT\t-1\n").contains("invalid padding '-1'"));
+        assert!(message("locus\tmin_purity\nHTT\t1.5\n").contains("invalid min_purity '1.5'"));
+        assert!(message("locus\tpadding\nHTT(\t1\n").contains("invalid locus pattern"));
+    }
+}
@@ -0,0 +1,673 @@
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use anyhow::{bail, Result};
+use log::{debug, info, warn};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::matrix::{build_features, Feature, FeatureKind, MatrixParams};
+use crate::plan::Plan;
+use crate::schema::{self, OutputKind};
+use crate::threads::threads;
+use crate::utils::{create_output, get_default_out_path};
+
+/// The number of extra components iterated on alongside those requested,
+/// which speeds up the convergence of the last requested ones
+const OVERSAMPLING: usize = 10;
+const MAX_ITERATIONS: usize = 300;
+/// The relative change of the variances of the components between two
+/// iterations below which the iteration stops
+const TOLERANCE: f64 = 1e-9;
+/// The correlation above which two samples are reported as possible duplicates
+const DUPLICATE_CORRELATION: f64 = 0.99;
+
+pub struct PcaParams {
+    pub features: Vec<FeatureKind>,
+    /// Minimum read count for a sample to be considered covered at a locus
+    pub min_read_count: u32,
+    /// Minimum number of samples with a nonzero count to keep an interruption feature
+    pub min_samples: usize,
+    /// The number of principal components to compute
+    pub components: usize,
+    /// The largest fraction of samples a feature may be missing in. Missing
+    /// values of the features kept are replaced by the feature's mean.
+    pub max_missing: f64,
+    /// Scale each feature to unit variance instead of only centering it
+    pub scale: bool,
+    /// The seed of the starting vectors of the iteration
+    pub seed: u64,
+}
+
+/// Computes the principal components of the samples of a merged profile from
+/// their features and the correlation of each pair of samples. Writes the
+/// scores of the samples to `out_path`, and the loadings of the features,
+/// the variance explained by each component and the most similar sample of
+/// each sample next to it.
+pub fn pca(merged_profile: PathBuf, out_path: PathBuf, params: PcaParams) -> Result<()> {
+    if params.features.is_empty() {
+        bail!("At least one kind of feature must be selected");
+    }
+    if params.components == 0 {
+        bail!("At least one component must be computed");
+    }
+    if !(0.0..=1.0).contains(&params.max_missing) {
+        bail!("--max-missing must be between 0 and 1");
+    }
+
+    let matrix_params = MatrixParams {
+        format: None,
+        features: params.features.clone(),
+        min_read_count: params.min_read_count,
+        min_samples: params.min_samples,
+        fill: None,
+    };
+    let (samples, features) = build_features(&merged_profile, &matrix_params)?;
+    if samples.len() < 3 {
+        bail!(
+            "{} has {} sample(s), at least 3 are needed",
+            merged_profile.display(),
+            samples.len()
+        );
+    }
+    let (kept, data) = prepare(&features, samples.len(), &params);
+    if kept.is_empty() {
+        bail!("No feature varies across the samples, try lowering --min-samples or raising --max-missing");
+    }
+    let n_components = params.components.min(samples.len() - 1).min(kept.len());
+    if n_components < params.components {
+        warn!(
+            "Computing {} components, as there are {} samples and {} features",
+            n_components,
+            samples.len(),
+            kept.len()
+        );
+    }
+
+    info!(
+        "Computing {} principal components of {} samples x {} features...",
+        n_components,
+        samples.len(),
+        kept.len()
+    );
+    let (eigenvalues, loadings) = principal_components(&data, n_components, params.seed);
+    let scores: Vec<Vec<f64>> = data
+        .iter()
+        .map(|row| loadings.iter().map(|loading| dot(row, loading)).collect())
+        .collect();
+    let total: f64 = data.iter().map(|row| dot(row, row)).sum();
+    let degrees = (samples.len() - 1) as f64;
+    for (i, eigenvalue) in eigenvalues.iter().enumerate().take(3) {
+        info!(
+            "PC{} explains {:.1}% of the variance",
+            i + 1,
+            100.0 * eigenvalue / total
+        );
+    }
+
+    info!("Computing sample similarities...");
+    let similarities = nearest_samples(&data);
+    for (i, (nearest, correlation, _)) in similarities.iter().enumerate() {
+        // each pair is reported once
+        if *correlation >= DUPLICATE_CORRELATION && (*nearest > i || similarities[*nearest].0 != i)
+        {
+            warn!(
+                "Samples {} and {} have a correlation of {:.4} and may be duplicates",
+                samples[i], samples[*nearest], correlation
+            );
+        }
+    }
+
+    let kept_features: Vec<&Feature> = kept.iter().map(|&j| &features[j]).collect();
+    let [scores_path, loadings_path, variance_path, similarity_path] =
+        output_paths(&out_path).map(|(_, path)| path);
+    write_scores(&samples, &scores, &scores_path)?;
+    write_loadings(&kept_features, &loadings, &loadings_path)?;
+    write_variance(&eigenvalues, total, degrees, &variance_path)?;
+    write_similarity(&samples, &similarities, &similarity_path)?;
+
+    info!("Done!");
+    Ok(())
+}
+
+/// The files written by `pca` with their labels: the scores at the output
+/// path, and the loadings, variance explained and sample similarities next
+/// to it
+pub fn output_paths(out_path: &Path) -> [(&'static str, PathBuf); 4] {
+    [
+        ("principal component scores", out_path.to_path_buf()),
+        ("loadings", out_path.with_extension("loadings.tsv")),
+        (
+            "variance explained",
+            out_path.with_extension("variance.tsv"),
+        ),
+        (
+            "sample similarities",
+            out_path.with_extension("similarity.tsv"),
+        ),
+    ]
+}
+
+/// Selects the features present in enough samples that vary, and returns
+/// their indices and the samples x features matrix of their centered (and
+/// optionally scaled) values, with missing values replaced by the mean
+fn prepare(
+    features: &[Feature],
+    n_samples: usize,
+    params: &PcaParams,
+) -> (Vec<usize>, Vec<Vec<f64>>) {
+    let max_missing = (params.max_missing * n_samples as f64).floor() as usize;
+    let mut kept: Vec<usize> = Vec::new();
+    let mut columns: Vec<Vec<f64>> = Vec::new();
+    let (mut too_missing, mut constant) = (0, 0);
+    for (j, feature) in features.iter().enumerate() {
+        let present: Vec<f64> = feature
+            .values
+            .iter()
+            .copied()
+            .filter(|v| !v.is_nan())
+            .collect();
+        if n_samples - present.len() > max_missing || present.is_empty() {
+            too_missing += 1;
+            continue;
+        }
+        let mean = present.iter().sum::<f64>() / present.len() as f64;
+        let variance =
+            present.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n_samples - 1) as f64;
+        if variance <= f64::EPSILON * mean.abs().max(1.0) {
+            constant += 1;
+            continue;
+        }
+        let scale = if params.scale { variance.sqrt() } else { 1.0 };
+        kept.push(j);
+        columns.push(
+            feature
+                .values
+                .iter()
+                .map(|v| if v.is_nan() { 0.0 } else { (v - mean) / scale })
+                .collect(),
+        );
+    }
+    info!(
+        "Using {} of {} features, leaving out {} missing in more than {} samples and {} without variance",
+        kept.len(),
+        features.len(),
+        too_missing,
+        max_missing,
+        constant
+    );
+    let data = (0..n_samples)
+        .map(|i| columns.iter().map(|column| column[i]).collect())
+        .collect();
+    (kept, data)
+}
+
+/// Computes the largest principal components of a centered samples x
+/// features matrix by subspace iteration, without forming its covariance
+/// matrix. Returns the eigenvalues of `XᵀX` of the components in decreasing
+/// order and their loadings, each a unit vector over the features whose
+/// largest entry is positive.
+fn principal_components(
+    x: &[Vec<f64>],
+    n_components: usize,
+    seed: u64,
+) -> (Vec<f64>, Vec<Vec<f64>>) {
+    let (n, p) = (x.len(), x[0].len());
+    let width = (n_components + OVERSAMPLING).min(n).min(p);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut basis: Vec<Vec<f64>> = (0..width)
+        .map(|_| (0..p).map(|_| rng.gen::<f64>() - 0.5).collect())
+        .collect();
+    orthonormalize(&mut basis);
+
+    let mut previous: Vec<f64> = vec![0.0; n_components];
+    let mut converged = false;
+    for iteration in 0..MAX_ITERATIONS {
+        let mut projected = parallel_map(width, |j| multiply(x, &basis[j]));
+        let estimates = top_eigenvalues(&gram(&projected), n_components);
+        let change = estimates
+            .iter()
+            .zip(&previous)
+            .map(|(estimate, previous)| {
+                (estimate - previous).abs() / estimate.max(f64::MIN_POSITIVE)
+            })
+            .fold(0.0, f64::max);
+        previous = estimates;
+        if change < TOLERANCE {
+            debug!("Converged after {} iterations", iteration + 1);
+            converged = true;
+            break;
+        }
+        orthonormalize(&mut projected);
+        basis = parallel_map(width, |j| multiply_transposed(x, &projected[j]));
+        orthonormalize(&mut basis);
+    }
+    if !converged {
+        warn!(
+            "The principal components did not converge in {} iterations, the last ones may be approximate",
+            MAX_ITERATIONS
+        );
+    }
+
+    // the eigenvectors of the covariance restricted to the subspace
+    let projected = parallel_map(width, |j| multiply(x, &basis[j]));
+    let (eigenvalues, eigenvectors) = symmetric_eigen(&gram(&projected));
+    let mut order: Vec<usize> = (0..width).collect();
+    order.sort_by(|&a, &b| eigenvalues[b].total_cmp(&eigenvalues[a]));
+    let mut loadings: Vec<Vec<f64>> = Vec::with_capacity(n_components);
+    for &m in order.iter().take(n_components) {
+        let mut loading = vec![0.0; p];
+        for (j, vector) in basis.iter().enumerate() {
+            let weight = eigenvectors[j][m];
+            for (l, v) in loading.iter_mut().zip(vector) {
+                *l += weight * v;
+            }
+        }
+        let largest = loading
+            .iter()
+            .copied()
+            .max_by(|a, b| a.abs().total_cmp(&b.abs()))
+            .unwrap_or(0.0);
+        if largest < 0.0 {
+            loading.iter_mut().for_each(|l| *l = -*l);
+        }
+        loadings.push(loading);
+    }
+    let eigenvalues = order
+        .iter()
+        .take(n_components)
+        .map(|&m| eigenvalues[m].max(0.0))
+        .collect();
+    (eigenvalues, loadings)
+}
+
+/// The Gram matrix of vectors, whose entries are their dot products
+fn gram(vectors: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    vectors
+        .iter()
+        .map(|u| vectors.iter().map(|v| dot(u, v)).collect())
+        .collect()
+}
+
+/// The largest eigenvalues of a small symmetric matrix in decreasing order
+fn top_eigenvalues(matrix: &[Vec<f64>], n: usize) -> Vec<f64> {
+    let (mut eigenvalues, _) = symmetric_eigen(matrix);
+    eigenvalues.sort_by(|a, b| b.total_cmp(a));
+    eigenvalues.truncate(n);
+    eigenvalues
+}
+
+/// Computes the eigenvalues and eigenvectors of a small symmetric matrix by
+/// the cyclic Jacobi method. The eigenvectors are the columns of the
+/// returned matrix.
+fn symmetric_eigen(matrix: &[Vec<f64>]) -> (Vec<f64>, Vec<Vec<f64>>) {
+    let n = matrix.len();
+    let mut a: Vec<Vec<f64>> = matrix.to_vec();
+    let mut v: Vec<Vec<f64>> = (0..n)
+        .map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+        .collect();
+    let norm: f64 = a.iter().flatten().map(|x| x * x).sum();
+    for _ in 0..100 {
+        let off_diagonal: f64 = (0..n)
+            .flat_map(|i| (i + 1..n).map(move |j| (i, j)))
+            .map(|(i, j)| a[i][j] * a[i][j])
+            .sum();
+        if off_diagonal <= norm * 1e-30 {
+            break;
+        }
+        for p in 0..n {
+            for q in p + 1..n {
+                if a[p][q] == 0.0 {
+                    continue;
+                }
+                let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                for row in a.iter_mut() {
+                    let (akp, akq) = (row[p], row[q]);
+                    row[p] = c * akp - s * akq;
+                    row[q] = s * akp + c * akq;
+                }
+                let (rows_before, rows_after) = a.split_at_mut(q);
+                for (apk, aqk) in rows_before[p].iter_mut().zip(rows_after[0].iter_mut()) {
+                    let (x, y) = (*apk, *aqk);
+                    *apk = c * x - s * y;
+                    *aqk = s * x + c * y;
+                }
+                for row in v.iter_mut() {
+                    let (vkp, vkq) = (row[p], row[q]);
+                    row[p] = c * vkp - s * vkq;
+                    row[q] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+    ((0..n).map(|i| a[i][i]).collect(), v)
+}
+
+/// Orthonormalizes vectors in place by modified Gram-Schmidt, zeroing those
+/// that depend on the previous ones
+fn orthonormalize(vectors: &mut [Vec<f64>]) {
+    for i in 0..vectors.len() {
+        let (previous, rest) = vectors.split_at_mut(i);
+        let vector = &mut rest[0];
+        let initial_norm = dot(vector, vector).sqrt();
+        // twice for numerical stability
+        for _ in 0..2 {
+            for other in previous.iter() {
+                let projection = dot(vector, other);
+                for (v, o) in vector.iter_mut().zip(other) {
+                    *v -= projection * o;
+                }
+            }
+        }
+        let norm = dot(vector, vector).sqrt();
+        if norm <= initial_norm * 1e-10 || norm == 0.0 {
+            vector.iter_mut().for_each(|v| *v = 0.0);
+        } else {
+            vector.iter_mut().for_each(|v| *v /= norm);
+        }
+    }
+}
+
+/// For each sample, the most correlated other sample, their Pearson
+/// correlation over the features and the mean correlation with all other
+/// samples
+fn nearest_samples(data: &[Vec<f64>]) -> Vec<(usize, f64, f64)> {
+    let normalized: Vec<Vec<f64>> = data
+        .iter()
+        .map(|row| {
+            let mean = row.iter().sum::<f64>() / row.len() as f64;
+            let centered: Vec<f64> = row.iter().map(|v| v - mean).collect();
+            let norm = dot(&centered, &centered).sqrt();
+            centered
+                .into_iter()
+                .map(|v| if norm > 0.0 { v / norm } else { 0.0 })
+                .collect()
+        })
+        .collect();
+    parallel_map(normalized.len(), |i| {
+        let mut nearest = (usize::MAX, f64::NEG_INFINITY);
+        let mut sum = 0.0;
+        for (j, other) in normalized.iter().enumerate() {
+            if i == j {
+                continue;
+            }
+            let correlation = dot(&normalized[i], other);
+            sum += correlation;
+            if correlation > nearest.1 {
+                nearest = (j, correlation);
+            }
+        }
+        (nearest.0, nearest.1, sum / (normalized.len() - 1) as f64)
+    })
+}
+
+/// Computes `f` for each index on --threads threads, keeping their order
+fn parallel_map<T, F>(n: usize, f: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(usize) -> T + Sync,
+{
+    let n_threads = threads().clamp(1, n.max(1));
+    let f = &f;
+    let mut partials: Vec<Vec<(usize, T)>> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..n_threads)
+            .map(|worker| {
+                scope.spawn(move || (worker..n).step_by(n_threads).map(|i| (i, f(i))).collect())
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    });
+    let mut results: Vec<(usize, T)> = partials.iter_mut().flat_map(|p| p.drain(..)).collect();
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// Multiplies the matrix by a vector over its columns
+fn multiply(x: &[Vec<f64>], v: &[f64]) -> Vec<f64> {
+    x.iter().map(|row| dot(row, v)).collect()
+}
+
+/// Multiplies the transpose of the matrix by a vector over its rows
+fn multiply_transposed(x: &[Vec<f64>], u: &[f64]) -> Vec<f64> {
+    let mut result = vec![0.0; x[0].len()];
+    for (row, weight) in x.iter().zip(u) {
+        for (r, v) in result.iter_mut().zip(row) {
+            *r += weight * v;
+        }
+    }
+    result
+}
+
+/// The names of the columns of the components
+fn component_columns(n_components: usize) -> String {
+    (1..=n_components)
+        .map(|i| format!("PC{}", i))
+        .collect::<Vec<String>>()
+        .join("\t")
+}
+
+fn write_row<W: Write>(out: &mut W, name: &str, values: &[f64]) -> Result<()> {
+    write!(out, "{}", name)?;
+    for value in values {
+        write!(out, "\t{:.6}", value)?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+fn write_scores(samples: &[String], scores: &[Vec<f64>], out_path: &Path) -> Result<()> {
+    let n_components = scores.first().map_or(0, Vec::len);
+    let mut out_file = create_output(out_path)?;
+    writeln!(out_file, "sample_id\t{}", component_columns(n_components))?;
+    for (sample, row) in samples.iter().zip(scores) {
+        write_row(&mut out_file, sample, row)?;
+    }
+    out_file.finish()?;
+    Ok(())
+}
+
+fn write_loadings(features: &[&Feature], loadings: &[Vec<f64>], out_path: &Path) -> Result<()> {
+    let mut out_file = create_output(out_path)?;
+    writeln!(out_file, "feature\t{}", component_columns(loadings.len()))?;
+    for (j, feature) in features.iter().enumerate() {
+        let row: Vec<f64> = loadings.iter().map(|loading| loading[j]).collect();
+        write_row(&mut out_file, &feature.name, &row)?;
+    }
+    out_file.finish()?;
+    Ok(())
+}
+
+fn write_variance(eigenvalues: &[f64], total: f64, degrees: f64, out_path: &Path) -> Result<()> {
+    let mut out_file = create_output(out_path)?;
+    schema::write_header(&mut out_file, OutputKind::PcaVariance)?;
+    let mut cumulative = 0.0;
+    for (i, eigenvalue) in eigenvalues.iter().enumerate() {
+        let explained = eigenvalue / total;
+        cumulative += explained;
+        writeln!(
+            out_file,
+            "PC{}\t{:.6}\t{:.6}\t{:.6}",
+            i + 1,
+            eigenvalue / degrees,
+            explained,
+            cumulative
+        )?;
+    }
+    out_file.finish()?;
+    Ok(())
+}
+
+fn write_similarity(
+    samples: &[String],
+    similarities: &[(usize, f64, f64)],
+    out_path: &Path,
+) -> Result<()> {
+    let mut out_file = create_output(out_path)?;
+    schema::write_header(&mut out_file, OutputKind::SampleSimilarity)?;
+    for (sample, (nearest, correlation, mean_correlation)) in samples.iter().zip(similarities) {
+        writeln!(
+            out_file,
+            "{}\t{}\t{:.6}\t{:.6}",
+            sample, samples[*nearest], correlation, mean_correlation
+        )?;
+    }
+    out_file.finish()?;
+    Ok(())
+}
+
+/// The command line arguments of `strif pca`
+#[derive(Debug, clap::Args)]
+pub struct PcaArgs {
+    /// The path to the merged profile
+    pub merged_profile: PathBuf,
+
+    /// The path to write the principal component scores of the samples to. The loadings,
+    /// variance explained and sample similarities are written next to it. Defaults to the
+    /// same directory as the merged profile.
+    pub output: Option<PathBuf>,
+
+    /// The kinds of features to use, separated by commas
+    #[clap(
+        short = 'f',
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "interruption"
+    )]
+    pub features: Vec<FeatureKind>,
+
+    /// Minimum read count for a sample to be considered covered at a locus
+    #[clap(short = 'm', long, default_value = "1")]
+    pub min_read_count: u32,
+
+    /// Minimum number of samples with a nonzero count to keep an interruption feature
+    #[clap(short = 'n', long, default_value = "2")]
+    pub min_samples: usize,
+
+    /// The number of principal components to compute
+    #[clap(short = 'k', long, default_value = "10")]
+    pub components: usize,
+
+    /// The largest fraction of samples a feature may be missing in. Missing values of the
+    /// features kept are replaced by the feature's mean.
+    #[clap(long, default_value = "0.1")]
+    pub max_missing: f64,
+
+    /// Scale each feature to unit variance instead of only centering it
+    #[clap(long)]
+    pub scale: bool,
+
+    /// The seed of the starting vectors of the iteration
+    #[clap(long, default_value = "0")]
+    pub seed: u64,
+}
+
+impl PcaArgs {
+    /// Runs `strif pca`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let PcaArgs {
+            merged_profile,
+            output,
+            features,
+            min_read_count,
+            min_samples,
+            components,
+            max_missing,
+            scale,
+            seed,
+        } = self;
+        let out_path: PathBuf =
+            output.unwrap_or_else(|| get_default_out_path(&merged_profile, "pca", "tsv"));
+        let params = PcaParams {
+            features,
+            min_read_count,
+            min_samples,
+            components,
+            max_missing,
+            scale,
+            seed,
+        };
+        let mut plan = Plan::new("pca", dry_run, force);
+        plan.merged_profile("merged profile", &merged_profile);
+        for (label, path) in output_paths(&out_path) {
+            plan.output(label, &path);
+        }
+        plan.execute(|| pca(merged_profile, out_path, params))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{data_rows, merged_record, write_merged_profile_file};
+
+    #[test]
+    fn principal_components_are_the_eigenvectors_of_the_covariance() {
+        let x = vec![
+            vec![3.0, 0.0],
+            vec![-3.0, 0.0],
+            vec![0.0, 1.0],
+            vec![0.0, -1.0],
+        ];
+        let (eigenvalues, loadings) = principal_components(&x, 2, 1);
+        assert!((eigenvalues[0] - 18.0).abs() < 1e-9);
+        assert!((eigenvalues[1] - 2.0).abs() < 1e-9);
+        // the largest entry of each loading is positive
+        assert!((loadings[0][0] - 1.0).abs() < 1e-9 && loadings[0][1].abs() < 1e-9);
+        assert!(loadings[1][0].abs() < 1e-9 && (loadings[1][1] - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn duplicate_samples_are_each_others_nearest_sample() {
+        let dir = tempfile::tempdir().unwrap();
+        let read_counts = [("s1", 10), ("s2", 10), ("s3", 10), ("s4", 10)];
+        let counts = |interruption, values: [f64; 4]| -> Vec<(&str, &str, f64)> {
+            ["s1", "s2", "s3", "s4"]
+                .into_iter()
+                .zip(values)
+                .map(|(sample, value)| (sample, interruption, value))
+                .collect()
+        };
+        let merged = write_merged_profile_file(
+            dir.path(),
+            "merged.tsv",
+            &[
+                merged_record("L1", &read_counts, &counts("CAT", [1.0, 2.0, 0.5, 1.0])),
+                merged_record("L2", &read_counts, &counts("CTG", [0.2, 0.1, 0.9, 0.2])),
+                merged_record("L3", &read_counts, &counts("CAT", [3.0, 1.0, 2.0, 3.0])),
+            ],
+        );
+        let out = dir.path().join("pca.tsv");
+        let params = PcaParams {
+            features: vec![FeatureKind::Interruption],
+            min_read_count: 1,
+            min_samples: 1,
+            components: 2,
+            max_missing: 0.0,
+            scale: false,
+            seed: 1,
+        };
+        pca(merged, out.clone(), params).unwrap();
+
+        let [scores, _, variance, similarity] = output_paths(&out).map(|(_, path)| path);
+        let scores = data_rows(&scores);
+        assert_eq!(scores.len(), 4);
+        // duplicates have the same scores
+        assert_eq!(scores[0].replacen("s1", "s4", 1), scores[3]);
+        let variance = data_rows(&variance);
+        assert_eq!(variance.len(), 2);
+        let similarity = data_rows(&similarity);
+        assert!(similarity[0].starts_with("s1\ts4\t1.000000\t"));
+        assert!(similarity[3].starts_with("s4\ts1\t1.000000\t"));
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+
+use crate::merge::load_manifest;
+use crate::profile::{read_profile, ProfileRecord};
+use crate::schema::{self, OutputKind};
+use crate::utils::{create_output, Shard};
+
+/// A proband and both parents, all of which are in the manifest
+pub struct Trio {
+    pub family_id: String,
+    pub proband: String,
+    pub father: String,
+    pub mother: String,
+}
+
+/// Loads the trios of a PED file whose members all have a profile. The PED
+/// file has whitespace-separated columns: family ID, individual ID, father
+/// ID, mother ID, sex and phenotype, with `0` for unknown parents.
+fn load_trios(ped: &Path, samples: &[&str]) -> Result<Vec<Trio>> {
+    let text =
+        fs::read_to_string(ped).with_context(|| format!("Could not open {}", ped.display()))?;
+    let mut trios: Vec<Trio> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 4 {
+            bail!(
+                "{} line {} should have a family ID, individual ID, father ID and mother ID",
+                ped.display(),
+                i + 1
+            );
+        }
+        let (family_id, proband, father, mother) = (fields[0], fields[1], fields[2], fields[3]);
+        if father == "0" || mother == "0" {
+            continue;
+        }
+        let missing: Vec<&str> = [proband, father, mother]
+            .into_iter()
+            .filter(|sample_id| !samples.contains(sample_id))
+            .collect();
+        if !missing.is_empty() {
+            warn!(
+                "Skipping the trio of {} in family {}, {} not in the manifest",
+                proband,
+                family_id,
+                missing.join(" and ")
+            );
+            continue;
+        }
+        trios.push(Trio {
+            family_id: family_id.to_string(),
+            proband: proband.to_string(),
+            father: father.to_string(),
+            mother: mother.to_string(),
+        });
+    }
+    Ok(trios)
+}
+
+/// The thresholds for calling an interruption of a proband de novo
+pub struct DeNovoParams {
+    /// The fewest reads of the proband supporting the interruption
+    pub min_supporting_reads: f64,
+    /// The fewest reads each parent needs at the locus for the absence of the
+    /// interruption to count
+    pub min_parent_reads: u32,
+}
+
+/// The reads of a sample at a locus and the reads supporting each of its
+/// interruptions over all repeat lengths
+struct LocusSupport<'a> {
+    record: &'a ProfileRecord,
+    support: HashMap<&'a str, f64>,
+}
+
+fn locus_support<'a>(
+    records: &'a [ProfileRecord],
+    keep: &impl Fn(&str) -> bool,
+) -> HashMap<&'a str, LocusSupport<'a>> {
+    records
+        .iter()
+        .filter(|record| keep(&record.locus_id))
+        .map(|record| {
+            let mut support: HashMap<&str, f64> = HashMap::new();
+            for (interruption, _, count) in &record.interruption_counts {
+                *support.entry(interruption.as_str()).or_default() += count;
+            }
+            (record.locus_id.as_str(), LocusSupport { record, support })
+        })
+        .collect()
+}
+
+/// Writes the interruptions that each proband of a PED file carries and both
+/// parents lack at loci the parents cover, reading the profiles of the
+/// manifest one trio at a time. Quality-weighted counts are rounded, so a
+/// parent whose reads add up to less than half a read lacks the interruption.
+pub fn de_novo(
+    manifest: &Path,
+    ped: &Path,
+    out_path: &Path,
+    params: &DeNovoParams,
+    filter: Option<&str>,
+    shard: Option<Shard>,
+) -> Result<()> {
+    let entries = load_manifest(manifest)?;
+    let paths: HashMap<&str, &PathBuf> = entries
+        .iter()
+        .map(|entry| (entry.sample_id.as_str(), &entry.path))
+        .collect();
+    let samples: Vec<&str> = paths.keys().copied().collect();
+    let trios = load_trios(ped, &samples)?;
+    if trios.is_empty() {
+        warn!(
+            "{} has no trios with profiles in the manifest",
+            ped.display()
+        );
+    }
+    let filter = filter.map(regex::Regex::new).transpose()?;
+    let keep = |locus_id: &str| {
+        filter
+            .as_ref()
+            .is_none_or(|filter| filter.is_match(locus_id))
+            && shard.is_none_or(|shard| shard.contains(locus_id))
+    };
+
+    let mut out_file = create_output(out_path)?;
+    schema::write_header(&mut out_file, OutputKind::DeNovoCandidates)?;
+    let mut total = 0;
+    for trio in &trios {
+        info!(
+            "Looking for de novo interruptions of {} in family {}...",
+            trio.proband, trio.family_id
+        );
+        let proband = read_profile(paths[trio.proband.as_str()])?;
+        let father = read_profile(paths[trio.father.as_str()])?;
+        let mother = read_profile(paths[trio.mother.as_str()])?;
+        let father = locus_support(&father, &keep);
+        let mother = locus_support(&mother, &keep);
+
+        let mut candidates = 0;
+        for (locus_id, proband) in locus_support(&proband, &keep) {
+            let (father, mother) = match (father.get(locus_id), mother.get(locus_id)) {
+                (Some(father), Some(mother))
+                    if father.record.read_count >= params.min_parent_reads
+                        && mother.record.read_count >= params.min_parent_reads =>
+                {
+                    (father, mother)
+                }
+                _ => continue,
+            };
+            let mut interruptions: Vec<(&str, f64)> = proband
+                .support
+                .iter()
+                .filter(|(interruption, supporting_reads)| {
+                    **supporting_reads >= params.min_supporting_reads
+                        && [father, mother].iter().all(|parent| {
+                            parent.support.get(*interruption).copied().unwrap_or(0.0) < 0.5
+                        })
+                })
+                .map(|(interruption, supporting_reads)| (*interruption, *supporting_reads))
+                .collect();
+            interruptions.sort_by(|a, b| a.0.cmp(b.0));
+            let record = proband.record;
+            for (interruption, supporting_reads) in interruptions {
+                writeln!(
+                    out_file,
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.4}\t{}\t{}",
+                    trio.family_id,
+                    trio.proband,
+                    trio.father,
+                    trio.mother,
+                    record.locus_id,
+                    record.reference_region,
+                    record.motif,
+                    interruption,
+                    supporting_reads,
+                    record.read_count,
+                    supporting_reads / record.read_count as f64,
+                    father.record.read_count,
+                    mother.record.read_count
+                )?;
+                candidates += 1;
+            }
+        }
+        info!(
+            candidates;
+            "Found {} de novo interruption candidates in {}",
+            candidates,
+            trio.proband
+        );
+        total += candidates;
+    }
+    out_file.finish()?;
+
+    info!(
+        "Wrote {} de novo interruption candidates of {} trios to {}",
+        total,
+        trios.len(),
+        out_path.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{data_rows, profile_record, write_profile_file};
+
+    #[test]
+    fn interruptions_both_parents_lack_are_de_novo() {
+        let dir = tempfile::tempdir().unwrap();
+        let profiles = [
+            (
+                "child",
+                vec![
+                    profile_record("L1", 20, &[("A", 30, 2.0), ("A", 33, 1.0), ("T", 30, 3.0)]),
+                    profile_record("L2", 20, &[("G", 30, 5.0)]),
+                ],
+            ),
+            (
+                "dad",
+                vec![
+                    profile_record("L1", 20, &[("T", 30, 1.0)]),
+                    profile_record("L2", 20, &[]),
+                ],
+            ),
+            (
+                "mom",
+                vec![
+                    profile_record("L1", 20, &[("A", 30, 0.4)]),
+                    // too few reads to tell whether L2 has the interruption
+                    profile_record("L2", 5, &[]),
+                ],
+            ),
+        ];
+        let mut manifest = String::new();
+        for (sample_id, records) in &profiles {
+            let name = format!("{}.profile", sample_id);
+            let path = write_profile_file(dir.path(), &name, records);
+            manifest += &format!("{}\tcase\t{}\n", sample_id, path.display());
+        }
+        let manifest_path = dir.path().join("manifest.tsv");
+        fs::write(&manifest_path, manifest).unwrap();
+        let ped = dir.path().join("family.ped");
+        // the second child has no profile and the founders no parents
+        fs::write(
+            &ped,
+            "F1 child dad mom 1 2\nF1 dad 0 0 1 1\nF1 mom 0 0 2 1\nF1 child2 dad mom 2 2\n",
+        )
+        .unwrap();
+        let out = dir.path().join("de_novo.tsv");
+        let params = DeNovoParams {
+            min_supporting_reads: 2.0,
+            min_parent_reads: 10,
+        };
+        de_novo(&manifest_path, &ped, &out, &params, None, None).unwrap();
+        // the A of the mother rounds down to no reads
+        assert_eq!(
+            data_rows(&out),
+            ["F1\tchild\tdad\tmom\tL1\tchr1:100-200\tCAG\tA\t3\t20\t0.1500\t20\t20"]
+        );
+    }
+}
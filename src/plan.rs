@@ -0,0 +1,635 @@
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::{info, warn};
+
+use crate::catalog::Catalog;
+use crate::error::{field, parse_field, StrifError};
+use crate::extract::{count_bamlet_reads, shard_paths, SHARD_SUFFIX};
+use crate::merge::{
+    load_manifest, load_read_depths, parse_read_counts, ManifestEntry, INTERRUPTION_COUNT_BYTES,
+    READ_COUNT_BYTES,
+};
+use crate::profile::{read_profile, ProfileOptions};
+use crate::run::RunParams;
+use crate::schema::{self, OutputKind};
+use crate::utils::{self, open_maybe_gzipped, ByteSize, Shard};
+
+/// The inputs and outputs of a subcommand. With `--dry-run` the plan is
+/// printed instead of doing the work: each input is validated and counted,
+/// and problems are collected so that all of them are reported at once.
+/// Otherwise the inputs are left to the subcommand and only the outputs are
+/// checked before it runs.
+pub struct Plan {
+    subcommand: String,
+    dry_run: bool,
+    force: bool,
+    steps: Vec<(String, String)>,
+    problems: Vec<anyhow::Error>,
+    outputs: Vec<(String, PathBuf)>,
+}
+
+impl Plan {
+    pub fn new(subcommand: &str, dry_run: bool, force: bool) -> Self {
+        Plan {
+            subcommand: subcommand.to_string(),
+            dry_run,
+            force,
+            steps: Vec::new(),
+            problems: Vec::new(),
+            outputs: Vec::new(),
+        }
+    }
+
+    /// Prints the plan with `--dry-run`, otherwise checks the outputs and
+    /// does the work
+    pub fn execute(&mut self, work: impl FnOnce() -> Result<()>) -> Result<()> {
+        if self.dry_run {
+            return self.finish();
+        }
+        check_outputs(&self.outputs, self.force)?;
+        work()
+    }
+
+    /// Adds a line to the plan
+    pub fn note(&mut self, label: &str, value: impl Display) -> &mut Self {
+        self.steps.push((label.to_string(), value.to_string()));
+        self
+    }
+
+    fn check<T>(&mut self, label: &str, path: &Path, result: Result<T>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.note(label, format!("{} (invalid)", path.display()));
+                self.problems.push(e);
+                None
+            }
+        }
+    }
+
+    /// An input that only needs to exist
+    pub fn input(&mut self, label: &str, path: &Path) -> &mut Self {
+        if !self.dry_run {
+            return self;
+        }
+        let size = std::fs::metadata(path)
+            .with_context(|| format!("Could not open {}", path.display()))
+            .map(|metadata| metadata.len());
+        if let Some(size) = self.check(label, path, size) {
+            self.note(label, format!("{} ({})", path.display(), ByteSize(size)));
+        }
+        self
+    }
+
+    pub fn maybe_input(&mut self, label: &str, path: Option<&Path>) -> &mut Self {
+        match path {
+            Some(path) => self.input(label, path),
+            None => self,
+        }
+    }
+
+    pub fn inputs(&mut self, label: &str, paths: &[PathBuf]) -> &mut Self {
+        if !self.dry_run {
+            return self;
+        }
+        for path in paths {
+            self.input(label, path);
+        }
+        self
+    }
+
+    /// An output of an earlier release, with its kind and schema version
+    pub fn migration(&mut self, path: &Path) -> &mut Self {
+        if !self.dry_run {
+            return self;
+        }
+        let schema = schema::detect(path)
+            .and_then(|kind| Ok((kind, schema::schema_version(path)?)))
+            .with_context(|| format!("Could not read the schema of {}", path.display()));
+        if let Some((kind, version)) = self.check("input", path, schema) {
+            self.note(
+                "input",
+                format!("{} ({}, version {})", path.display(), kind.name(), version),
+            );
+        }
+        self
+    }
+
+    /// A tabular input, whose rows are counted. The header row of strif
+    /// outputs and `#` comments are not counted.
+    pub fn records(&mut self, label: &str, path: &Path, unit: &str) -> &mut Self {
+        if !self.dry_run {
+            return self;
+        }
+        if let Some(rows) = self.check(label, path, count_rows(path)) {
+            self.note(label, format!("{} ({} {})", path.display(), rows, unit));
+        }
+        self
+    }
+
+    pub fn catalog(&mut self, path: &Path, filter: Option<&str>) -> &mut Self {
+        if !self.dry_run {
+            return self;
+        }
+        let loci = Catalog::from_path(path).and_then(|catalog| {
+            let filter = filter.map(regex::Regex::new).transpose()?;
+            Ok(catalog
+                .loci
+                .iter()
+                .filter(|locus| filter.as_ref().is_none_or(|f| f.is_match(&locus.locus_id)))
+                .count())
+        });
+        if let Some(loci) = self.check("catalog", path, loci) {
+            let filtered = if filter.is_some() {
+                " matching the filter"
+            } else {
+                ""
+            };
+            self.note(
+                "catalog",
+                format!("{} ({} loci{})", path.display(), loci, filtered),
+            );
+        }
+        self
+    }
+
+    pub fn profile(&mut self, label: &str, path: &Path) -> &mut Self {
+        if !self.dry_run {
+            return self;
+        }
+        if let Some((loci, reads)) = self.check(label, path, count_profile(path)) {
+            self.note(
+                label,
+                format!("{} ({} loci, {} reads)", path.display(), loci, reads),
+            );
+        }
+        self
+    }
+
+    pub fn merged_profile(&mut self, label: &str, path: &Path) -> &mut Self {
+        if !self.dry_run {
+            return self;
+        }
+        if let Some((loci, samples)) = self.check(label, path, count_merged_profile(path)) {
+            self.note(
+                label,
+                format!("{} ({} loci, {} samples)", path.display(), loci, samples),
+            );
+        }
+        self
+    }
+
+    /// A manifest, whose samples are counted and whose files (`what`) must exist
+    pub fn manifest(&mut self, path: &Path, what: &str) -> Option<Vec<ManifestEntry>> {
+        if !self.dry_run {
+            return None;
+        }
+        let entries = self.check("manifest", path, load_manifest(path))?;
+        self.note(
+            "manifest",
+            format!("{} ({} samples)", path.display(), entries.len()),
+        );
+        for entry in &entries {
+            if !entry.path.exists() {
+                self.problems.push(anyhow!(
+                    "The {} of sample {} does not exist: {}",
+                    what,
+                    entry.sample_id,
+                    entry.path.display()
+                ));
+            }
+        }
+        Some(entries)
+    }
+
+    /// A read depths file, which must have every sample of the manifest
+    pub fn read_depths(&mut self, path: &Path, entries: Option<&[ManifestEntry]>) -> &mut Self {
+        if !self.dry_run {
+            return self;
+        }
+        let depths = match self.check("read depths", path, load_read_depths(path)) {
+            Some(depths) => depths,
+            None => return self,
+        };
+        self.note(
+            "read depths",
+            format!("{} ({} samples)", path.display(), depths.len()),
+        );
+        for entry in entries.unwrap_or_default() {
+            if !depths.contains_key(&entry.sample_id) {
+                self.problems.push(
+                    crate::StrifError::MissingSample {
+                        sample_id: entry.sample_id.clone(),
+                        path: path.display().to_string(),
+                    }
+                    .into(),
+                );
+            }
+        }
+        self
+    }
+
+    /// A BAMlet, whose reads are counted
+    pub fn bamlet(&mut self, path: &Path) -> &mut Self {
+        if !self.dry_run {
+            return self;
+        }
+        if let Some(reads) = self.check("BAMlet", path, count_bamlet_reads(path)) {
+            self.note("BAMlet", format!("{} ({} reads)", path.display(), reads));
+        }
+        self
+    }
+
+    /// Estimates the memory used by `merge` from the size of the first
+    /// profile, assuming the others are alike
+    pub fn merge_memory(
+        &mut self,
+        entries: &[ManifestEntry],
+        filter: Option<&str>,
+        shard: Option<Shard>,
+    ) -> &mut Self {
+        if !self.dry_run {
+            return self;
+        }
+        let first = match entries.iter().find(|entry| entry.path.exists()) {
+            Some(first) => first,
+            None => return self,
+        };
+        let estimate = read_profile(&first.path).and_then(|records| {
+            let filter = filter.map(regex::Regex::new).transpose()?;
+            let mut bytes_per_sample: u64 = 0;
+            for record in records.iter().filter(|r| {
+                filter.as_ref().is_none_or(|f| f.is_match(&r.locus_id))
+                    && shard.is_none_or(|s| s.contains(&r.locus_id))
+            }) {
+                bytes_per_sample += READ_COUNT_BYTES;
+                // an entry per distinct interruption
+                let mut interruptions: Vec<&str> = record
+                    .interruption_counts
+                    .iter()
+                    .map(|(interruption, _, _)| interruption.as_str())
+                    .collect();
+                interruptions.sort_unstable();
+                interruptions.dedup();
+                bytes_per_sample += interruptions.len() as u64 * INTERRUPTION_COUNT_BYTES;
+            }
+            Ok(bytes_per_sample * entries.len() as u64)
+        });
+        if let Some(bytes) = self.check("estimated memory", &first.path, estimate) {
+            self.note(
+                "estimated memory",
+                format!(
+                    "~{} (from the profile of {})",
+                    ByteSize(bytes),
+                    first.sample_id
+                ),
+            );
+        }
+        self
+    }
+
+    /// The profiles and read depths of `merge`
+    pub fn merge(
+        &mut self,
+        manifest: &Path,
+        read_depths: &Path,
+        filter: Option<&str>,
+        shard: Option<Shard>,
+    ) -> &mut Self {
+        let entries = self.manifest(manifest, "profile");
+        self.read_depths(read_depths, entries.as_deref());
+        if let Some(filter) = filter {
+            self.note("filter", filter);
+        }
+        if let Some(shard) = shard {
+            self.note("shard", shard);
+        }
+        if let Some(entries) = &entries {
+            self.merge_memory(entries, filter, shard);
+        }
+        self
+    }
+
+    /// The BAMlets, catalog and read depths of `run`
+    pub fn run(
+        &mut self,
+        manifest: &Path,
+        str_catalog: &Path,
+        read_depths: &Path,
+        out_dir: &Path,
+        params: &RunParams,
+    ) -> &mut Self {
+        let entries = self.manifest(manifest, "BAMlet");
+        self.catalog(str_catalog, params.profile.filter.as_deref())
+            .maybe_input("locus parameters", params.profile.locus_params.as_deref())
+            .maybe_input("score matrix", params.profile.score_matrix.as_deref())
+            .maybe_input("reference", params.profile.reference.as_deref());
+        self.read_depths(read_depths, entries.as_deref());
+        if let Some(entries) = &entries {
+            self.note(
+                "steps",
+                format!(
+                    "extract and profile {} samples with {} jobs, then merge",
+                    entries.len(),
+                    params.jobs.max(1)
+                ),
+            );
+        }
+        if out_dir.is_dir() {
+            let resume = if params.resume {
+                "exists, completed steps would be skipped"
+            } else {
+                "exists, all steps would be rerun"
+            };
+            self.note(
+                "output directory",
+                format!("{} ({})", out_dir.display(), resume),
+            );
+        } else {
+            self.note("output directory", out_dir.display());
+        }
+        self
+    }
+
+    /// The catalog, optional inputs and outputs of `profile` and
+    /// `extract-profile`
+    pub fn profiling(
+        &mut self,
+        str_catalog: &Path,
+        input: &Path,
+        out_path: &Path,
+        options: &ProfileOptions,
+    ) -> &mut Self {
+        self.catalog(str_catalog, options.filter.as_deref())
+            .maybe_input("locus parameters", options.locus_params.as_deref())
+            .maybe_input("score matrix", options.score_matrix.as_deref())
+            .maybe_input("reference", options.reference.as_deref())
+            .output("profile", out_path)
+            .maybe_output(
+                "visual alignments",
+                options.alignments_path(input).as_deref(),
+            )
+            .maybe_output("read interruptions", options.read_interruptions.as_deref())
+            .maybe_output("rejected reads", options.rejects.as_deref())
+            .maybe_output("motif usage", options.motif_usage.as_deref())
+    }
+
+    /// An output, whose directory must exist, and which is only overwritten
+    /// with `--force`
+    pub fn output(&mut self, label: &str, path: &Path) -> &mut Self {
+        self.outputs.push((label.to_string(), path.to_path_buf()));
+        if !self.dry_run {
+            return self;
+        }
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        if !dir.is_dir() {
+            self.note(label, format!("{} (invalid)", path.display()));
+            self.problems.push(anyhow!(
+                "The directory of {} does not exist",
+                path.display()
+            ));
+        } else if path.exists() {
+            self.note(
+                label,
+                format!("{} (exists, overwritten only with --force)", path.display()),
+            );
+        } else {
+            self.note(label, path.display());
+        }
+        self
+    }
+
+    pub fn maybe_output(&mut self, label: &str, path: Option<&Path>) -> &mut Self {
+        match path {
+            Some(path) => self.output(label, path),
+            None => self,
+        }
+    }
+
+    /// An output that is read back if it exists instead of being
+    /// overwritten, such as a key kept across runs
+    pub fn maybe_reused_output(&mut self, label: &str, path: Option<&Path>) -> &mut Self {
+        match path {
+            Some(path) if path.exists() => {
+                self.note(label, format!("{} (exists, reused)", path.display()))
+            }
+            Some(path) => self.note(label, path.display()),
+            None => self,
+        }
+    }
+
+    /// Prints the plan and fails if any of the inputs are invalid
+    fn finish(&mut self) -> Result<()> {
+        println!("Dry run of `strif {}`, nothing was done:", self.subcommand);
+        let width = self.steps.iter().map(|(label, _)| label.len()).max();
+        for (label, value) in &self.steps {
+            println!("  {:<width$}  {}", label, value, width = width.unwrap_or(0));
+        }
+        if self.problems.is_empty() {
+            return Ok(());
+        }
+        println!("Problems:");
+        for problem in &self.problems {
+            println!("  {:#}", problem);
+        }
+        let count = self.problems.len();
+        Err(self
+            .problems
+            .remove(0)
+            .context(format!("The dry run found {} problem(s)", count)))
+    }
+}
+
+/// Logs where each output will be written and refuses to overwrite existing
+/// outputs unless `--force` was given, so that outputs whose default paths
+/// collide are not lost
+pub fn check_outputs(outputs: &[(String, PathBuf)], force: bool) -> Result<()> {
+    for (label, path) in outputs {
+        // special files such as /dev/stdout are written to, not replaced
+        if path.is_file() && !utils::is_special_file(path) {
+            if !force {
+                bail!(
+                    "{} ({}) already exists, pass --force to overwrite it",
+                    path.display(),
+                    label
+                );
+            }
+            warn!("Overwriting {} ({})", path.display(), label);
+        } else {
+            info!("Writing the {} to {}", label, path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Counts the loci and reads of a profile while streaming its rows, which
+/// are checked like `read_profile` checks them without being kept
+fn count_profile(path: &Path) -> Result<(usize, u64)> {
+    let source = path.display().to_string();
+    let file =
+        open_maybe_gzipped(path).with_context(|| format!("Could not open {}", path.display()))?;
+    let mut reader = schema::tsv_reader(file, OutputKind::Profile, &source)?;
+    let mut record = csv::StringRecord::new();
+    let (mut loci, mut reads) = (0, 0);
+    while reader.read_record(&mut record)? {
+        loci += 1;
+        reads += parse_field::<u64>(&record, 3, "read count", source.as_str())?;
+    }
+    Ok((loci, reads))
+}
+
+/// Counts the loci and distinct samples of a merged profile while streaming
+/// its rows
+fn count_merged_profile(path: &Path) -> Result<(usize, usize)> {
+    let source = path.display().to_string();
+    let file =
+        open_maybe_gzipped(path).with_context(|| format!("Could not open {}", path.display()))?;
+    let mut reader = schema::tsv_reader(file, OutputKind::MergedProfile, &source)?;
+    let mut record = csv::StringRecord::new();
+    let mut loci: usize = 0;
+    let mut samples: HashSet<String> = HashSet::new();
+    while reader.read_record(&mut record)? {
+        loci += 1;
+        let read_counts = parse_read_counts(field(&record, 3, source.as_str())?).map_err(|e| {
+            StrifError::malformed_row(
+                source.as_str(),
+                &record,
+                format!("invalid read counts: {:#}", e),
+            )
+        })?;
+        for (sample, _) in read_counts {
+            samples.insert(sample);
+        }
+    }
+    Ok((loci, samples.len()))
+}
+
+/// Counts the data rows of a tabular file, or of every shard in a directory of
+/// repeat sequence shards
+fn count_rows(path: &Path) -> Result<usize> {
+    if path.is_dir() {
+        let shards = shard_paths(path)?;
+        if shards.is_empty() {
+            bail!(
+                "{} has no repeat sequence shards (*{})",
+                path.display(),
+                SHARD_SUFFIX
+            );
+        }
+        return shards.iter().map(|shard| count_rows(shard)).sum();
+    }
+    let file = File::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+    let mut rows: usize = 0;
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if !line.is_empty() && !line.starts_with('#') {
+            rows += 1;
+        }
+    }
+    // strif outputs other than repeat sequences have a header row
+    let has_header = schema::detect(path).is_ok_and(|kind| kind != OutputKind::RepeatSeqs);
+    Ok(if has_header {
+        rows.saturating_sub(1)
+    } else {
+        rows
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::merge::{write_merged_profile, MergedProfileRecord};
+
+    fn merged_record(locus_id: &str, samples: &[&str]) -> MergedProfileRecord {
+        MergedProfileRecord {
+            locus_id: locus_id.to_string(),
+            reference_region: "chr1:100-200".to_string(),
+            motif: "CAG".to_string(),
+            read_counts: samples.iter().map(|s| (s.to_string(), 10)).collect(),
+            interruption_counts: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn dry_run_counts_profile_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        // a legacy profile without a schema line or purity columns
+        let path = dir.path().join("sample.profile.tsv");
+        fs::write(
+            &path,
+            "locus_id\treference_region\tmotif\tread_count\tinterruption_counts\n\
+             L1\tchr1:100-200\tCAG\t12\tCAT:5:1\n\
+             L2\tchr1:300-400\tCGG\t0\t\n",
+        )
+        .unwrap();
+        assert_eq!(count_profile(&path).unwrap(), (2, 12));
+
+        fs::write(
+            &path,
+            "locus_id\treference_region\tmotif\tread_count\tinterruption_counts\n\
+             L1\tchr1:100-200\tCAG\tmany\t\n",
+        )
+        .unwrap();
+        assert!(count_profile(&path).is_err());
+    }
+
+    #[test]
+    fn dry_run_counts_distinct_merged_samples() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("merged.tsv");
+        let mut out = Vec::new();
+        let records = [
+            merged_record("L1", &["s1", "s2"]),
+            merged_record("L2", &["s2", "s3"]),
+            merged_record("L3", &[]),
+        ];
+        write_merged_profile(&mut out, &records).unwrap();
+        fs::write(&path, out).unwrap();
+        assert_eq!(count_merged_profile(&path).unwrap(), (3, 3));
+
+        let mut plan = Plan::new("query", true, false);
+        plan.merged_profile("merged profile", &path);
+        assert_eq!(
+            plan.steps,
+            vec![(
+                "merged profile".to_string(),
+                format!("{} (3 loci, 3 samples)", path.display())
+            )]
+        );
+    }
+
+    #[test]
+    fn existing_outputs_are_only_overwritten_with_force() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("merged.tsv");
+        fs::write(&path, "old\n").unwrap();
+        let outputs = [("merged profile".to_string(), path.clone())];
+        let fresh = [("report".to_string(), dir.path().join("report.html"))];
+
+        let err = check_outputs(&outputs, false).unwrap_err();
+        assert!(err.to_string().ends_with("pass --force to overwrite it"));
+        check_outputs(&outputs, true).unwrap();
+        check_outputs(&fresh, false).unwrap();
+
+        let mut ran = false;
+        let result = Plan::new("merge", false, false)
+            .output("merged profile", &path)
+            .execute(|| {
+                ran = true;
+                Ok(())
+            });
+        assert!(result.is_err());
+        assert!(!ran);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old\n");
+    }
+}
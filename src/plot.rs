@@ -0,0 +1,862 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Ok, Result};
+use bio::alignment::AlignmentOperation;
+use log::info;
+use plotters::coord::Shift;
+use plotters::prelude::*;
+
+use crate::error::{field, parse_field};
+use crate::extract::read_repeat_seqs;
+use crate::interruptions::{InterruptionFinder, LocusParams};
+use crate::merge::{load_manifest, read_merged_profile, MergedProfileRecord};
+use crate::plan::Plan;
+use crate::presets::{Platform, Preset};
+use crate::profile::{load_str_catalog, read_profile};
+use crate::schema::{self, OutputKind};
+use crate::utils::{create_output, get_default_out_path, open_maybe_gzipped, AlignmentScoreParams};
+
+pub(crate) const WIDTH: u32 = 900;
+pub(crate) const HEIGHT: u32 = 600;
+const FONT: &str = "sans-serif";
+const MAX_SPECTRUM_BARS: usize = 25;
+const HISTOGRAM_BINS: usize = 30;
+/// The height of a read in a waterfall plot, in pixels
+const READ_HEIGHT: u32 = 6;
+
+/// The kinds of figures produced by `plot`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PlotKind {
+    /// Interruption spectrum of a single locus (profile or merged profile)
+    Spectrum,
+    /// Distribution of total normalized interruption burden per sample (merged profile)
+    Burden,
+    /// Mean normalized burden per locus in cases versus controls (merged profile)
+    CaseControl,
+}
+
+impl PlotKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PlotKind::Spectrum => "spectrum",
+            PlotKind::Burden => "burden",
+            PlotKind::CaseControl => "case_control",
+        }
+    }
+
+    /// The path the plot of an input is written to if no output is given,
+    /// in the same directory as the input
+    pub fn default_out_path(&self, input: &Path, locus: Option<&str>) -> PathBuf {
+        let suffix = match locus {
+            Some(locus) if *self == PlotKind::Spectrum => format!("spectrum.{}", locus),
+            _ => self.name().to_string(),
+        };
+        get_default_out_path(input, &suffix, "svg")
+    }
+}
+
+pub fn plot(
+    kind: PlotKind,
+    input: PathBuf,
+    out_path: PathBuf,
+    locus: Option<String>,
+    manifest: Option<PathBuf>,
+) -> Result<()> {
+    info!("Plotting {}...", kind.name());
+    match kind {
+        PlotKind::Spectrum => {
+            let locus = match locus {
+                Some(locus) => locus,
+                None => bail!("A --locus is required for spectrum plots"),
+            };
+            plot_spectrum(&input, &out_path, &locus)?;
+        }
+        PlotKind::Burden => plot_burden(&input, &out_path)?,
+        PlotKind::CaseControl => {
+            let manifest = match manifest {
+                Some(manifest) => manifest,
+                None => bail!("A --manifest is required for case-control plots"),
+            };
+            plot_case_control(&input, &out_path, &manifest)?;
+        }
+    }
+    info!("Done!");
+    Ok(())
+}
+
+fn plot_spectrum(input: &Path, out_path: &Path, locus_id: &str) -> Result<()> {
+    let mut totals: HashMap<String, f64> = HashMap::new();
+    let y_label = match schema::detect(input)? {
+        OutputKind::Profile => {
+            let record = read_profile(input)?
+                .into_iter()
+                .find(|r| r.locus_id == locus_id);
+            let record = match record {
+                Some(record) => record,
+                None => bail!("Locus {} is not in {}", locus_id, input.display()),
+            };
+            for (interruption, _, count) in record.interruption_counts {
+                *totals.entry(interruption).or_default() += count;
+            }
+            "Interruption count"
+        }
+        OutputKind::MergedProfile => {
+            let record = read_merged_profile(input)?
+                .into_iter()
+                .find(|r| r.locus_id == locus_id);
+            let record = match record {
+                Some(record) => record,
+                None => bail!("Locus {} is not in {}", locus_id, input.display()),
+            };
+            for (_, interruption, count) in record.interruption_counts {
+                *totals.entry(interruption).or_default() += count;
+            }
+            "Normalized interruption count (summed over samples)"
+        }
+        kind => bail!(
+            "{} is a '{}' file, expected a profile or merged profile",
+            input.display(),
+            kind.name()
+        ),
+    };
+
+    let bars = spectrum_bars(totals);
+    if bars.is_empty() {
+        bail!("Locus {} has no interruptions to plot", locus_id);
+    }
+    write_svg(out_path, (WIDTH, HEIGHT), |root| {
+        draw_spectrum(root, locus_id, &bars, y_label)
+    })
+}
+
+/// The most frequent interruptions, in decreasing order of their totals
+pub(crate) fn spectrum_bars(totals: HashMap<String, f64>) -> Vec<(String, f64)> {
+    let mut bars: Vec<(String, f64)> = totals.into_iter().collect();
+    bars.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    bars.truncate(MAX_SPECTRUM_BARS);
+    bars
+}
+
+pub(crate) fn draw_spectrum(
+    root: &DrawingArea<SVGBackend, Shift>,
+    locus_id: &str,
+    bars: &[(String, f64)],
+    y_label: &str,
+) -> Result<()> {
+    let max = bars
+        .iter()
+        .map(|(_, v)| *v)
+        .fold(0.0, f64::max)
+        .max(f64::EPSILON);
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(root)
+        .caption(format!("Interruption spectrum of {}", locus_id), (FONT, 24))
+        .margin(20)
+        .x_label_area_size(60)
+        .y_label_area_size(80)
+        .build_cartesian_2d((0..bars.len()).into_segmented(), 0.0..max * 1.1)?;
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .x_labels(bars.len())
+        .x_label_formatter(&|x| match x {
+            SegmentValue::CenterOf(i) if *i < bars.len() => bars[*i].0.clone(),
+            _ => String::new(),
+        })
+        .x_desc("Interruption")
+        .y_desc(y_label)
+        .draw()?;
+    chart.draw_series(
+        Histogram::vertical(&chart)
+            .style(BLUE.mix(0.7).filled())
+            .margin(5)
+            .data(bars.iter().enumerate().map(|(i, (_, v))| (i, *v))),
+    )?;
+    Ok(())
+}
+
+/// Total normalized interruption burden across all loci for each sample
+pub(crate) fn sample_burdens(records: &[MergedProfileRecord]) -> BTreeMap<String, f64> {
+    let mut burdens: BTreeMap<String, f64> = BTreeMap::new();
+    for record in records {
+        for (sample_id, _) in &record.read_counts {
+            burdens.entry(sample_id.clone()).or_default();
+        }
+        for (sample_id, _, count) in &record.interruption_counts {
+            *burdens.entry(sample_id.clone()).or_default() += count;
+        }
+    }
+    burdens
+}
+
+fn plot_burden(input: &Path, out_path: &Path) -> Result<()> {
+    let burdens: Vec<f64> = sample_burdens(&read_merged_profile(input)?)
+        .into_values()
+        .collect();
+    if burdens.is_empty() {
+        bail!("{} has no samples to plot", input.display());
+    }
+    write_svg(out_path, (WIDTH, HEIGHT), |root| {
+        draw_burden(root, &burdens)
+    })
+}
+
+pub(crate) fn draw_burden(root: &DrawingArea<SVGBackend, Shift>, burdens: &[f64]) -> Result<()> {
+    let max = burdens
+        .iter()
+        .copied()
+        .fold(0.0, f64::max)
+        .max(f64::EPSILON);
+    let bin_width = max / HISTOGRAM_BINS as f64;
+    let mut bins: Vec<u32> = vec![0; HISTOGRAM_BINS];
+    for burden in burdens {
+        let bin = ((burden / bin_width) as usize).min(HISTOGRAM_BINS - 1);
+        bins[bin] += 1;
+    }
+    let max_count = *bins.iter().max().unwrap();
+
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(root)
+        .caption(
+            format!("Interruption burden across {} samples", burdens.len()),
+            (FONT, 24),
+        )
+        .margin(20)
+        .x_label_area_size(60)
+        .y_label_area_size(80)
+        .build_cartesian_2d(0.0..max, 0..max_count + 1)?;
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .x_desc("Total normalized interruption count")
+        .y_desc("Samples")
+        .draw()?;
+    chart.draw_series(bins.iter().enumerate().map(|(i, count)| {
+        let x0 = i as f64 * bin_width;
+        Rectangle::new([(x0, 0), (x0 + bin_width, *count)], BLUE.mix(0.7).filled())
+    }))?;
+    Ok(())
+}
+
+/// Renders a figure to an SVG string instead of a file
+pub(crate) fn svg_string<F>(size: (u32, u32), draw: F) -> Result<String>
+where
+    F: FnOnce(&DrawingArea<SVGBackend, Shift>) -> Result<()>,
+{
+    let mut svg = String::new();
+    {
+        let root = SVGBackend::with_string(&mut svg, size).into_drawing_area();
+        draw(&root)?;
+        root.present()?;
+    }
+    Ok(svg)
+}
+
+/// Renders a figure and writes it to `out_path` once it is complete
+fn write_svg<F>(out_path: &Path, size: (u32, u32), draw: F) -> Result<()>
+where
+    F: FnOnce(&DrawingArea<SVGBackend, Shift>) -> Result<()>,
+{
+    let svg = svg_string(size, draw)?;
+    let mut out_file = create_output(out_path)?;
+    out_file.write_all(svg.as_bytes())?;
+    out_file.finish()?;
+    Ok(())
+}
+
+fn plot_case_control(input: &Path, out_path: &Path, manifest: &Path) -> Result<()> {
+    let statuses: HashMap<String, String> = load_manifest(manifest)?
+        .into_iter()
+        .map(|entry| (entry.sample_id, entry.status.to_lowercase()))
+        .collect();
+
+    // mean normalized burden per locus in controls (x) and cases (y)
+    let mut points: Vec<(f64, f64)> = Vec::new();
+    for record in read_merged_profile(input)? {
+        let mut burdens: HashMap<&str, f64> = HashMap::new();
+        for (sample_id, _, count) in &record.interruption_counts {
+            *burdens.entry(sample_id).or_default() += count;
+        }
+        let mut sums = [0.0, 0.0];
+        let mut counts = [0.0, 0.0];
+        for (sample_id, _) in &record.read_counts {
+            let group = match statuses.get(sample_id).map(|s| s.as_str()) {
+                Some("control") => 0,
+                Some("case") => 1,
+                _ => continue,
+            };
+            sums[group] += burdens.get(sample_id.as_str()).unwrap_or(&0.0);
+            counts[group] += 1.0;
+        }
+        if counts[0] > 0.0 && counts[1] > 0.0 {
+            points.push((sums[0] / counts[0], sums[1] / counts[1]));
+        }
+    }
+    if points.is_empty() {
+        bail!(
+            "No loci in {} have both case and control samples",
+            input.display()
+        );
+    }
+    let max = points
+        .iter()
+        .map(|(x, y)| x.max(*y))
+        .fold(0.0, f64::max)
+        .max(f64::EPSILON)
+        * 1.05;
+
+    write_svg(out_path, (HEIGHT, HEIGHT), |root| {
+        root.fill(&WHITE)?;
+        let mut chart = ChartBuilder::on(root)
+            .caption("Mean interruption burden per locus", (FONT, 24))
+            .margin(20)
+            .x_label_area_size(60)
+            .y_label_area_size(80)
+            .build_cartesian_2d(0.0..max, 0.0..max)?;
+        chart
+            .configure_mesh()
+            .x_desc("Controls")
+            .y_desc("Cases")
+            .draw()?;
+        chart.draw_series(LineSeries::new(
+            vec![(0.0, 0.0), (max, max)],
+            BLACK.mix(0.4),
+        ))?;
+        chart.draw_series(
+            points
+                .iter()
+                .map(|point| Circle::new(*point, 3, RED.mix(0.6).filled())),
+        )?;
+        Ok(())
+    })
+}
+
+/// How a base of a read aligns to the pure repeat in a waterfall plot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BaseState {
+    Match,
+    Substitution,
+    Insertion,
+    /// A base of an interruption from a read interruptions file, which does
+    /// not tell substitutions and insertions apart
+    Interruption,
+}
+
+impl BaseState {
+    fn label(&self) -> &'static str {
+        match self {
+            BaseState::Match => "Match",
+            BaseState::Substitution => "Substitution",
+            BaseState::Insertion => "Insertion",
+            BaseState::Interruption => "Interruption",
+        }
+    }
+
+    fn color(&self) -> RGBColor {
+        match self {
+            BaseState::Match => RGBColor(200, 200, 200),
+            BaseState::Substitution => RGBColor(214, 39, 40),
+            BaseState::Insertion => RGBColor(255, 127, 14),
+            BaseState::Interruption => RGBColor(148, 103, 189),
+        }
+    }
+}
+
+/// A row of a waterfall plot: the state of each base of a read and the
+/// positions in the read before which bases of the pure repeat are deleted
+struct ReadRow {
+    states: Vec<BaseState>,
+    deletions: Vec<usize>,
+}
+
+impl ReadRow {
+    /// The runs of bases in the same state, as (state, start, end)
+    fn runs(&self) -> Vec<(BaseState, usize, usize)> {
+        let mut runs: Vec<(BaseState, usize, usize)> = Vec::new();
+        for (i, state) in self.states.iter().enumerate() {
+            match runs.last_mut() {
+                Some(run) if run.0 == *state => run.2 = i + 1,
+                _ => runs.push((*state, i, i + 1)),
+            }
+        }
+        runs
+    }
+}
+
+/// The settings used to align the repeat sequences of a waterfall plot
+pub struct LocusPlotParams {
+    /// The catalog giving the motif of the locus, needed for repeat sequences
+    pub catalog: Option<PathBuf>,
+    pub preset: Option<Preset>,
+    pub platform: Platform,
+    /// The number of reads plotted, the first ones of the locus in the input
+    pub max_reads: usize,
+}
+
+/// Plots the reads of a locus as a waterfall of rows, one per read, sorted by
+/// repeat length and colored by how each base aligns to the pure repeat.
+/// Repeat sequences are aligned as `profile` aligns them, while a read
+/// interruptions file from `profile --read-interruptions` only has the reads
+/// with interruptions and their spans.
+pub fn plot_locus(
+    input: PathBuf,
+    out_path: PathBuf,
+    locus_id: &str,
+    params: LocusPlotParams,
+) -> Result<()> {
+    info!("Plotting the reads of {}...", locus_id);
+    let (mut rows, total) = match schema::detect(&input)? {
+        OutputKind::RepeatSeqs => {
+            let catalog = match params.catalog {
+                Some(catalog) => catalog,
+                None => bail!("A --catalog is required to align repeat sequences"),
+            };
+            let (motifs, _, _, _, _) = load_str_catalog(catalog, None)?;
+            let motif = match motifs.get(locus_id) {
+                Some(motif) => motif,
+                None => bail!("Locus {} is not in the catalog", locus_id),
+            };
+            let mut locus_params: LocusParams = match params.preset {
+                Some(preset) => preset.locus_params(motif.len()),
+                None => AlignmentScoreParams::default().into(),
+            };
+            params.platform.adjust(&mut locus_params);
+            let mut finder = InterruptionFinder::with_locus_params(locus_params);
+            let seqs: Vec<String> = read_repeat_seqs(&input)?
+                .into_iter()
+                .filter(|record| record.locus_id == locus_id)
+                .map(|record| record.seq)
+                .collect();
+            let rows: Vec<ReadRow> = seqs
+                .iter()
+                .take(params.max_reads)
+                .map(|seq| aligned_row(&mut finder, seq.as_bytes(), motif.as_bytes()))
+                .collect();
+            (rows, seqs.len())
+        }
+        OutputKind::ReadInterruptions => {
+            let rows = read_interruption_rows(&input, locus_id)?;
+            let total = rows.len();
+            (rows.into_iter().take(params.max_reads).collect(), total)
+        }
+        OutputKind::Profile | OutputKind::MergedProfile => bail!(
+            "{} is a profile, which has no reads to plot. Plot the repeat sequences or the \
+             read interruptions (`profile --read-interruptions`) it was made from.",
+            input.display()
+        ),
+        kind => bail!(
+            "{} is a '{}' file, expected repeat sequences or read interruptions",
+            input.display(),
+            kind.name()
+        ),
+    };
+    if rows.is_empty() {
+        bail!("Locus {} has no reads in {}", locus_id, input.display());
+    }
+    if rows.len() < total {
+        info!(
+            "Plotting the first {} of {} reads, raise --max-reads to plot more",
+            rows.len(),
+            total
+        );
+    }
+    rows.sort_by_key(|row| Reverse(row.states.len()));
+
+    let height = (160 + rows.len() as u32 * READ_HEIGHT).max(HEIGHT / 2);
+    write_svg(&out_path, (WIDTH, height), |root| {
+        draw_waterfall(root, locus_id, &rows)
+    })?;
+    info!("Done!");
+    Ok(())
+}
+
+/// Aligns a repeat sequence to the pure repeat of its motif
+fn aligned_row(finder: &mut InterruptionFinder, seq: &[u8], motif: &[u8]) -> ReadRow {
+    let (alignment, _) = finder.align(seq, motif);
+    let mut row = ReadRow {
+        states: vec![BaseState::Match; seq.len()],
+        deletions: Vec::new(),
+    };
+    // positions in the path are 1-based counts of the bases aligned so far
+    for (x, _, op) in alignment.path() {
+        match op {
+            AlignmentOperation::Subst => row.states[x - 1] = BaseState::Substitution,
+            AlignmentOperation::Ins => row.states[x - 1] = BaseState::Insertion,
+            AlignmentOperation::Del => row.deletions.push(x),
+            _ => {}
+        }
+    }
+    row
+}
+
+/// Reads the interruption spans of the reads of a locus from a read
+/// interruptions file, in the order of the reads
+fn read_interruption_rows(path: &Path, locus_id: &str) -> Result<Vec<ReadRow>> {
+    let source = path.display().to_string();
+    let file = open_maybe_gzipped(path).with_context(|| format!("Could not open {}", source))?;
+    let mut reader = schema::tsv_reader(file, OutputKind::ReadInterruptions, &source)?;
+    let mut rows: Vec<(usize, ReadRow)> = Vec::new();
+    for result in reader.records() {
+        let record = result?;
+        if field(&record, 0, &source)? != locus_id {
+            continue;
+        }
+        let read: usize = parse_field(&record, 1, "read", source.as_str())?;
+        let repeat_len: usize = parse_field(&record, 2, "repeat length", source.as_str())?;
+        let start: usize = parse_field(&record, 4, "start", source.as_str())?;
+        let end: usize = parse_field(&record, 5, "end", source.as_str())?;
+        if start > end || end > repeat_len {
+            bail!(
+                "{} has an interruption at {}..{} of read {} of {}, which is {} bp long",
+                source,
+                start,
+                end,
+                read,
+                locus_id,
+                repeat_len
+            );
+        }
+        if rows.last().is_none_or(|(number, _)| *number != read) {
+            let row = ReadRow {
+                states: vec![BaseState::Match; repeat_len],
+                deletions: Vec::new(),
+            };
+            rows.push((read, row));
+        }
+        let (_, row) = rows.last_mut().unwrap();
+        row.states[start..end].fill(BaseState::Interruption);
+    }
+    Ok(rows.into_iter().map(|(_, row)| row).collect())
+}
+
+fn draw_waterfall(
+    root: &DrawingArea<SVGBackend, Shift>,
+    locus_id: &str,
+    rows: &[ReadRow],
+) -> Result<()> {
+    let max_len = rows.iter().map(|row| row.states.len()).max().unwrap_or(0);
+    let n = rows.len() as f64;
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(root)
+        .caption(format!("Reads of {}", locus_id), (FONT, 24))
+        .margin(20)
+        .x_label_area_size(50)
+        .y_label_area_size(60)
+        .build_cartesian_2d(0.0..max_len.max(1) as f64, 0.0..n)?;
+    chart
+        .configure_mesh()
+        .disable_mesh()
+        .x_label_formatter(&|x| format!("{}", x.round()))
+        .y_label_formatter(&|y| format!("{}", (n - y).round()))
+        .x_desc("Position in the repeat (bp)")
+        .y_desc("Read")
+        .draw()?;
+
+    // the longest read is drawn at the top
+    let top = |i: usize| n - i as f64;
+    let mut states: Vec<BaseState> = rows.iter().flat_map(|row| row.states.clone()).collect();
+    states.sort_by_key(|state| *state as u8);
+    states.dedup();
+    for state in states {
+        let color = state.color();
+        chart
+            .draw_series(rows.iter().enumerate().flat_map(|(i, row)| {
+                row.runs()
+                    .into_iter()
+                    .filter(move |run| run.0 == state)
+                    .map(move |(_, start, end)| {
+                        Rectangle::new(
+                            [(start as f64, top(i) - 0.9), (end as f64, top(i) - 0.1)],
+                            color.filled(),
+                        )
+                    })
+            }))?
+            .label(state.label())
+            .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 10, y + 5)], color.filled()));
+    }
+    if rows.iter().any(|row| !row.deletions.is_empty()) {
+        chart
+            .draw_series(rows.iter().enumerate().flat_map(|(i, row)| {
+                row.deletions.iter().map(move |x| {
+                    PathElement::new(
+                        vec![(*x as f64, top(i)), (*x as f64, top(i) - 1.0)],
+                        BLACK.stroke_width(2),
+                    )
+                })
+            }))?
+            .label("Deletion")
+            .legend(|(x, y)| PathElement::new(vec![(x + 5, y - 5), (x + 5, y + 5)], BLACK));
+    }
+    chart
+        .configure_series_labels()
+        .position(SeriesLabelPosition::LowerRight)
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .label_font((FONT, 14))
+        .draw()?;
+    Ok(())
+}
+
+/// The command line arguments of `strif plot`
+#[derive(Debug, clap::Args)]
+pub struct PlotArgs {
+    /// The kind of figure to plot
+    #[clap(value_enum)]
+    pub kind: PlotKind,
+
+    /// The path to a profile or merged profile
+    pub input: PathBuf,
+
+    /// The path to the SVG file. Defaults to the same directory as the input.
+    pub output: Option<PathBuf>,
+
+    /// The locus to plot the spectrum of
+    #[clap(short = 'l', long)]
+    pub locus: Option<String>,
+
+    /// The path to the manifest file, used for case-control status
+    #[clap(short = 'm', long)]
+    pub manifest: Option<PathBuf>,
+}
+
+impl PlotArgs {
+    /// Runs `strif plot`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let PlotArgs {
+            kind,
+            input,
+            output,
+            locus,
+            manifest,
+        } = self;
+        let out_path: PathBuf =
+            output.unwrap_or_else(|| kind.default_out_path(&input, locus.as_deref()));
+        Plan::new("plot", dry_run, force)
+            .records("input", &input, "rows")
+            .maybe_input("manifest", manifest.as_deref())
+            .output("plot", &out_path)
+            .execute(|| plot(kind, input, out_path, locus, manifest))
+    }
+}
+
+/// The command line arguments of `strif plot-locus`
+#[derive(Debug, clap::Args)]
+pub struct PlotLocusArgs {
+    /// The path to repeat sequences from `extract`, or read interruptions from `profile
+    /// --read-interruptions`
+    pub input: PathBuf,
+
+    /// The path to the SVG file. Defaults to the same directory as the input.
+    pub output: Option<PathBuf>,
+
+    /// The locus to plot the reads of
+    #[clap(short = 'l', long)]
+    pub locus: String,
+
+    /// The path to a JSON file containing the catalog of repeat loci, needed to align repeat
+    /// sequences
+    #[clap(short = 'c', long = "catalog")]
+    pub str_catalog: Option<PathBuf>,
+
+    /// Built-in alignment settings used instead of the default scores, as in `profile`
+    #[clap(long, value_enum)]
+    pub preset: Option<Preset>,
+
+    /// The sequencing platform of the reads, as in `profile`
+    #[clap(long, value_enum, default_value = "illumina")]
+    pub platform: Platform,
+
+    /// The maximum number of reads plotted, the first ones of the locus in the input
+    #[clap(long, default_value = "200")]
+    pub max_reads: usize,
+}
+
+impl PlotLocusArgs {
+    /// Runs `strif plot-locus`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let PlotLocusArgs {
+            input,
+            output,
+            locus,
+            str_catalog,
+            preset,
+            platform,
+            max_reads,
+        } = self;
+        let out_path: PathBuf = output
+            .unwrap_or_else(|| get_default_out_path(&input, &format!("reads.{}", locus), "svg"));
+        Plan::new("plot-locus", dry_run, force)
+            .records("input", &input, "rows")
+            .maybe_input("catalog", str_catalog.as_deref())
+            .output("plot", &out_path)
+            .execute(|| {
+                let params = LocusPlotParams {
+                    catalog: str_catalog,
+                    preset,
+                    platform,
+                    max_reads,
+                };
+                plot_locus(input, out_path, &locus, params)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::testing::{profile_record, write_profile_file};
+
+    #[test]
+    fn spectra_keep_the_most_frequent_interruptions() {
+        let mut totals: HashMap<String, f64> =
+            (0..30).map(|i| (format!("I{:02}", i), i as f64)).collect();
+        totals.insert("TIE".to_string(), 29.0);
+        let bars = spectrum_bars(totals);
+        assert_eq!(bars.len(), MAX_SPECTRUM_BARS);
+        assert_eq!(bars[0], ("I29".to_string(), 29.0));
+        assert_eq!(bars[1], ("TIE".to_string(), 29.0));
+        assert_eq!(bars[24].0, "I06");
+    }
+
+    #[test]
+    fn spectrum_plots_label_the_interruptions_of_a_locus() {
+        let dir = tempfile::tempdir().unwrap();
+        let profile = write_profile_file(
+            dir.path(),
+            "profile.tsv",
+            &[
+                profile_record(
+                    "L1",
+                    10,
+                    &[("CAT", 40, 3.0), ("CAT", 41, 1.0), ("CCG", 40, 2.0)],
+                ),
+                profile_record("L2", 10, &[]),
+            ],
+        );
+        let out = dir.path().join("spectrum.svg");
+        let plot_locus = |locus: &str| {
+            plot(
+                PlotKind::Spectrum,
+                profile.clone(),
+                out.clone(),
+                Some(locus.to_string()),
+                None,
+            )
+        };
+        plot_locus("L1").unwrap();
+        let svg = fs::read_to_string(&out).unwrap();
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("Interruption spectrum of L1"));
+        assert!(svg.contains("\nCAT\n") && svg.contains("\nCCG\n"));
+
+        assert_eq!(
+            plot_locus("L2").unwrap_err().to_string(),
+            "Locus L2 has no interruptions to plot"
+        );
+        assert!(plot_locus("L3").is_err());
+        assert_eq!(
+            PlotKind::Spectrum.default_out_path(&profile, Some("L1")),
+            dir.path().join("profile.spectrum.L1.svg")
+        );
+    }
+
+    #[test]
+    fn sample_burdens_include_samples_without_interruptions() {
+        let record = MergedProfileRecord {
+            locus_id: "L1".to_string(),
+            reference_region: "chr1:100-200".to_string(),
+            motif: "CAG".to_string(),
+            read_counts: vec![("s1".to_string(), 10), ("s2".to_string(), 10)],
+            interruption_counts: vec![
+                ("s1".to_string(), "CAT".to_string(), 0.5),
+                ("s1".to_string(), "A".to_string(), 0.25),
+            ],
+        };
+        let burdens = sample_burdens(&[record]);
+        assert_eq!(
+            burdens.into_iter().collect::<Vec<_>>(),
+            [("s1".to_string(), 0.75), ("s2".to_string(), 0.0)]
+        );
+    }
+
+    #[test]
+    fn waterfall_rows_mark_how_each_base_aligns() {
+        let mut finder = InterruptionFinder::new(AlignmentScoreParams::default());
+        let row = aligned_row(&mut finder, b"CAGCATCAGTTCAGCAG", b"CAG");
+        assert_eq!(
+            row.runs(),
+            [
+                (BaseState::Match, 0, 5),
+                (BaseState::Substitution, 5, 6),
+                (BaseState::Match, 6, 9),
+                (BaseState::Insertion, 9, 11),
+                (BaseState::Match, 11, 17),
+            ]
+        );
+        assert!(row.deletions.is_empty());
+    }
+
+    #[test]
+    fn locus_plots_draw_repeat_seqs_and_read_interruptions() {
+        use crate::profile::{profile, ProfileOptions};
+        use crate::testing::write_catalog;
+
+        let dir = tempfile::tempdir().unwrap();
+        let repeat_seqs = dir.path().join("sample.repeat_seqs.tsv");
+        let mut text = Vec::new();
+        schema::write_header(&mut text, OutputKind::RepeatSeqs).unwrap();
+        text.extend_from_slice(
+            b"HTT\tCAGCAGCAGCAG\nHTT\tCAGCATCAGCAGCAG\nHTT\tCAGCAGTTCAGCAG\nFMR1\tCGGCGG\n",
+        );
+        fs::write(&repeat_seqs, text).unwrap();
+        let catalog = write_catalog(dir.path());
+        let params = || LocusPlotParams {
+            catalog: Some(catalog.clone()),
+            preset: None,
+            platform: Platform::Illumina,
+            max_reads: 2,
+        };
+        let out = dir.path().join("HTT.svg");
+        plot_locus(repeat_seqs.clone(), out.clone(), "HTT", params()).unwrap();
+        let svg = fs::read_to_string(&out).unwrap();
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("Substitution"));
+        assert!(plot_locus(repeat_seqs.clone(), out.clone(), "ATXN1", params()).is_err());
+
+        // read interruptions only have the spans of the reads with interruptions
+        let reads = dir.path().join("sample.read_interruptions.tsv");
+        let options = ProfileOptions::default().read_interruptions(reads.clone());
+        profile(
+            repeat_seqs,
+            catalog.clone(),
+            dir.path().join("sample.profile"),
+            &options,
+        )
+        .unwrap();
+        let rows = read_interruption_rows(&reads, "HTT").unwrap();
+        let runs: Vec<_> = rows.iter().map(ReadRow::runs).collect();
+        assert_eq!(
+            runs,
+            [
+                vec![
+                    (BaseState::Match, 0, 5),
+                    (BaseState::Interruption, 5, 6),
+                    (BaseState::Match, 6, 15)
+                ],
+                vec![
+                    (BaseState::Match, 0, 6),
+                    (BaseState::Interruption, 6, 8),
+                    (BaseState::Match, 8, 14)
+                ],
+            ]
+        );
+        plot_locus(reads, out, "HTT", params()).unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+
+use crate::interruptions::LocusParams;
+use crate::utils::AlignmentScoreParams;
+
+/// The longest motif of a short tandem repeat with `--preset auto`
+const SHORT_STR_MAX_MOTIF_LEN: usize = 6;
+/// The longest motif of a long tandem repeat with `--preset auto`, beyond
+/// which loci are treated as VNTRs
+const LONG_STR_MAX_MOTIF_LEN: usize = 24;
+
+/// Built-in alignment settings for kinds of loci and sequencing platforms.
+/// The padding and merge distance of each scale with the motif length of
+/// the locus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    /// Picks short-str, long-str or vntr for each locus by its motif length
+    Auto,
+    /// Short reads of loci with motifs of up to 6 bp, the default settings
+    ShortStr,
+    /// Short reads of loci with motifs of 7 to 24 bp
+    LongStr,
+    /// Short reads of VNTRs, whose motifs of more than 24 bp vary between copies
+    Vntr,
+    /// Nanopore reads, whose frequent small errors split interruptions
+    Ont,
+}
+
+impl Preset {
+    /// The settings of a locus with a motif of the given length
+    pub fn locus_params(&self, motif_len: usize) -> LocusParams {
+        match self {
+            Preset::Auto => Self::for_motif_len(motif_len).locus_params(motif_len),
+            Preset::ShortStr => AlignmentScoreParams::default().into(),
+            Preset::LongStr => LocusParams {
+                scores: AlignmentScoreParams {
+                    match_score: 1,
+                    mismatch_penalty: 6,
+                    gap_open_penalty: 8,
+                    gap_extend_penalty: 1,
+                },
+                padding: 2,
+                merge_distance: motif_len / 4,
+                min_insertion_len: 1,
+                matrix: None,
+            },
+            Preset::Vntr => LocusParams {
+                scores: AlignmentScoreParams {
+                    match_score: 1,
+                    mismatch_penalty: 4,
+                    gap_open_penalty: 6,
+                    gap_extend_penalty: 1,
+                },
+                padding: 1,
+                merge_distance: motif_len / 2,
+                min_insertion_len: 1,
+                matrix: None,
+            },
+            Preset::Ont => LocusParams {
+                scores: AlignmentScoreParams {
+                    match_score: 1,
+                    mismatch_penalty: 4,
+                    gap_open_penalty: 4,
+                    gap_extend_penalty: 2,
+                },
+                padding: 4,
+                merge_distance: (motif_len / 2).max(2),
+                min_insertion_len: 1,
+                matrix: None,
+            },
+        }
+    }
+
+    /// The preset for short reads of a locus with a motif of the given length
+    pub fn for_motif_len(motif_len: usize) -> Preset {
+        if motif_len <= SHORT_STR_MAX_MOTIF_LEN {
+            Preset::ShortStr
+        } else if motif_len <= LONG_STR_MAX_MOTIF_LEN {
+            Preset::LongStr
+        } else {
+            Preset::Vntr
+        }
+    }
+}
+
+/// The gap open penalty of nanopore reads, low enough that a single-base
+/// indel costs less than a mismatch
+const ONT_GAP_OPEN_PENALTY: i32 = 1;
+/// The gap extension penalty of nanopore reads, high enough that indels of
+/// several bases cost more than with short reads
+const ONT_GAP_EXTEND_PENALTY: i32 = 3;
+
+/// The sequencing platform of the reads, which adjusts the indel model of
+/// the scores or preset of each locus
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Platform {
+    /// Short reads, whose indels are rare, with the scores unchanged
+    #[default]
+    Illumina,
+    /// Nanopore reads, whose frequent single-base indels are sequencing errors
+    Ont,
+}
+
+impl Platform {
+    /// Adjusts the settings of a locus to the error model of the platform
+    pub fn adjust(&self, params: &mut LocusParams) {
+        match self {
+            Platform::Illumina => {}
+            Platform::Ont => {
+                params.scores.gap_open_penalty = ONT_GAP_OPEN_PENALTY;
+                params.scores.gap_extend_penalty = ONT_GAP_EXTEND_PENALTY;
+                params.min_insertion_len = 2;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_presets_follow_the_motif_length() {
+        assert_eq!(Preset::for_motif_len(1), Preset::ShortStr);
+        assert_eq!(Preset::for_motif_len(6), Preset::ShortStr);
+        assert_eq!(Preset::for_motif_len(7), Preset::LongStr);
+        assert_eq!(Preset::for_motif_len(24), Preset::LongStr);
+        assert_eq!(Preset::for_motif_len(25), Preset::Vntr);
+        assert_eq!(
+            Preset::Auto.locus_params(3),
+            LocusParams::from(AlignmentScoreParams::default())
+        );
+        assert_eq!(Preset::Auto.locus_params(40), Preset::Vntr.locus_params(40));
+    }
+
+    #[test]
+    fn merge_distances_scale_with_the_motif_length() {
+        assert_eq!(Preset::LongStr.locus_params(12).merge_distance, 3);
+        assert_eq!(Preset::Vntr.locus_params(40).merge_distance, 20);
+        assert_eq!(Preset::Ont.locus_params(2).merge_distance, 2);
+        assert_eq!(Preset::ShortStr.locus_params(6).merge_distance, 0);
+    }
+}
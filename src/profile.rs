@@ -1,40 +1,340 @@
 use std::io::prelude::*;
-use std::{collections::HashMap, fs::File, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{self, File},
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
 
-use anyhow::{Ok, Result};
-use bio::alignment::pairwise::Aligner;
-use bio::alignment::{Alignment, AlignmentOperation};
-use log::{debug, info};
+use anyhow::{anyhow, bail, Context, Ok, Result};
+use bio::io::fasta;
+use log::{debug, info, warn};
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 
-use crate::utils::AlignmentScoreParams;
+use crate::arrow_stream;
+use crate::catalog::{Catalog, Flanks, ReadFilters};
+use crate::config::load_options;
+use crate::error::{field, parse_field, StrifError};
+use crate::extract::{
+    decode_qualities, for_each_repeat_seq, parse_anchoring, parse_multiplicity, parse_read_name,
+    shard_paths, Anchoring, ExtractOptions, SHARD_SUFFIX,
+};
+use crate::intern::{Interner, Symbol};
+use crate::interruptions::{
+    alignment_purity, estimate_copy_number, find_interruption_spans, flank_overlaps,
+    is_pure_repeat, span_penalty, InterruptionFinder, LocusParams, ScoreMatrix,
+};
+use crate::overrides::{LocusOverride, LocusOverrides};
+use crate::plan::Plan;
+use crate::presets::{Platform, Preset};
+use crate::schema::{self, OutputKind};
+use crate::threads::threads;
+use crate::utils::{
+    builder_methods, create_output, get_default_out_path, is_gzipped_path, open_maybe_gzipped,
+    reference_span, sort_by_coordinates, AlignmentScoreParams, BgzfWriter, OutputFile,
+    OutputFormat, Region,
+};
 
-struct Profile {
-    interruption_counts: HashMap<String, HashMap<(String, u32), u32>>,
-    read_counts: HashMap<String, u32>,
+/// The counts of a sample at each locus, with interned locus IDs and
+/// interruptions since the same ones recur across reads
+pub struct Profile {
+    loci: Interner,
+    interruptions: Interner,
+    /// (interruption, repeat length) counts at each locus, which are expected
+    /// counts when weighted by base qualities
+    interruption_counts: FxHashMap<Symbol, FxHashMap<(Symbol, u32), f64>>,
+    read_counts: FxHashMap<Symbol, u32>,
+    /// The purities of the reads at each locus
+    purities: FxHashMap<Symbol, Histogram>,
+    /// The confidences of the interruptions at each locus
+    confidences: FxHashMap<Symbol, Confidences>,
+    /// The estimated motif copy numbers of the reads at each locus
+    copy_numbers: FxHashMap<Symbol, Histogram>,
+    /// The reads counted with each candidate motif at the loci with several
+    motif_counts: FxHashMap<Symbol, HashMap<String, u32>>,
+    /// Whether the counts are of reads or of fragments
+    count_unit: CountUnit,
+}
+
+/// The sum, number and maximum of the confidences of the interruptions of a
+/// locus, each counted once per read it stands for
+#[derive(Debug, Clone, Copy, Default)]
+struct Confidences {
+    sum: f64,
+    count: u32,
+    max: f64,
+}
+
+impl Confidences {
+    fn add(&mut self, confidence: f64, reads: u32) {
+        self.sum += confidence * reads as f64;
+        self.count += reads;
+        self.max = self.max.max(confidence);
+    }
+
+    fn combine(&mut self, other: Confidences) {
+        self.sum += other.sum;
+        self.count += other.count;
+        self.max = self.max.max(other.max);
+    }
+
+    /// The mean and maximum confidence, or None without interruptions
+    fn summary(&self) -> (Option<f64>, Option<f64>) {
+        match self.count {
+            0 => (None, None),
+            count => (Some(self.sum / count as f64), Some(self.max)),
+        }
+    }
+}
+
+/// The width of the bins of a [`Histogram`], finer than the precision of the
+/// profile output
+const HISTOGRAM_BIN_WIDTH: f64 = 0.0001;
+
+/// The values of the reads of a locus, e.g. their purities or copy numbers,
+/// counted in bins so that memory does not grow with the depth of the locus.
+/// The mean is exact and the median is exact up to the width of a bin.
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    bins: BTreeMap<u64, u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    /// Adds a non-negative value, counted once per read it stands for
+    fn add(&mut self, value: f64, reads: u32) {
+        if reads == 0 {
+            return;
+        }
+        let bin = (value / HISTOGRAM_BIN_WIDTH).round() as u64;
+        *self.bins.entry(bin).or_default() += reads as u64;
+        self.sum += value * reads as f64;
+        self.count += reads as u64;
+    }
+
+    fn combine(&mut self, other: Histogram) {
+        for (bin, count) in other.bins {
+            *self.bins.entry(bin).or_default() += count;
+        }
+        self.sum += other.sum;
+        self.count += other.count;
+    }
+
+    fn mean(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.sum / self.count as f64)
+    }
+
+    /// The median, halfway between the two middle values for an even count
+    /// as in [`crate::stats::quantile`]
+    fn median(&self) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        let (lower_rank, upper_rank) = ((self.count - 1) / 2, self.count / 2);
+        let mut lower = None;
+        let mut seen = 0;
+        for (&bin, &count) in &self.bins {
+            seen += count;
+            if lower.is_none() && seen > lower_rank {
+                lower = Some(bin);
+            }
+            if seen > upper_rank {
+                let bins = lower.unwrap_or(bin) + bin;
+                return Some(bins as f64 / 2.0 * HISTOGRAM_BIN_WIDTH);
+            }
+        }
+        None
+    }
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Profile {
     pub fn new() -> Self {
         Self {
-            interruption_counts: HashMap::new(),
-            read_counts: HashMap::new(),
+            loci: Interner::new(),
+            interruptions: Interner::new(),
+            interruption_counts: FxHashMap::default(),
+            read_counts: FxHashMap::default(),
+            purities: FxHashMap::default(),
+            confidences: FxHashMap::default(),
+            copy_numbers: FxHashMap::default(),
+            motif_counts: FxHashMap::default(),
+            count_unit: CountUnit::Read,
         }
     }
 
-    pub fn increment_interruption(&mut self, locus_id: &str, interruption: &str, repeat_len: u32) {
-        self.interruption_counts
-            .entry(locus_id.to_string())
-            .or_insert_with(HashMap::new)
-            .entry((interruption.to_string(), repeat_len))
-            .and_modify(|count| *count += 1)
-            .or_insert(1);
+    pub fn increment_interruption(
+        &mut self,
+        locus_id: &str,
+        interruption: &str,
+        repeat_len: u32,
+        weight: f64,
+    ) {
+        let key = (self.interruptions.intern(interruption), repeat_len);
+        *self
+            .interruption_counts
+            .entry(self.loci.intern(locus_id))
+            .or_default()
+            .entry(key)
+            .or_default() += weight;
+    }
+
+    pub fn increment_read_count(&mut self, locus_id: &str, reads: u32) {
+        *self
+            .read_counts
+            .entry(self.loci.intern(locus_id))
+            .or_default() += reads;
+    }
+
+    /// Adds the counts of another profile to this one
+    fn combine(&mut self, other: Profile) {
+        for (locus, count) in other.read_counts {
+            let locus = self.loci.intern(other.loci.resolve(locus));
+            *self.read_counts.entry(locus).or_default() += count;
+        }
+        for (locus, counts) in other.interruption_counts {
+            let locus = self.loci.intern(other.loci.resolve(locus));
+            for ((interruption, repeat_len), count) in counts {
+                let key = (
+                    self.interruptions
+                        .intern(other.interruptions.resolve(interruption)),
+                    repeat_len,
+                );
+                *self
+                    .interruption_counts
+                    .entry(locus)
+                    .or_default()
+                    .entry(key)
+                    .or_default() += count;
+            }
+        }
+        for (locus, purities) in other.purities {
+            let locus = self.loci.intern(other.loci.resolve(locus));
+            self.purities.entry(locus).or_default().combine(purities);
+        }
+        for (locus, confidences) in other.confidences {
+            let locus = self.loci.intern(other.loci.resolve(locus));
+            self.confidences
+                .entry(locus)
+                .or_default()
+                .combine(confidences);
+        }
+        for (locus, copy_numbers) in other.copy_numbers {
+            let locus = self.loci.intern(other.loci.resolve(locus));
+            self.copy_numbers
+                .entry(locus)
+                .or_default()
+                .combine(copy_numbers);
+        }
+        for (locus, motif_counts) in other.motif_counts {
+            let locus = self.loci.intern(other.loci.resolve(locus));
+            let counts = self.motif_counts.entry(locus).or_default();
+            for (motif, count) in motif_counts {
+                *counts.entry(motif).or_default() += count;
+            }
+        }
+    }
+
+    pub fn add_purity(&mut self, locus_id: &str, purity: f64, reads: u32) {
+        self.purities
+            .entry(self.loci.intern(locus_id))
+            .or_default()
+            .add(purity, reads);
+    }
+
+    pub fn add_copy_number(&mut self, locus_id: &str, copy_number: f64, reads: u32) {
+        self.copy_numbers
+            .entry(self.loci.intern(locus_id))
+            .or_default()
+            .add(copy_number, reads);
+    }
+
+    pub fn add_motif_count(&mut self, locus_id: &str, motif: &[u8], reads: u32) {
+        let counts = self
+            .motif_counts
+            .entry(self.loci.intern(locus_id))
+            .or_default();
+        let motif = String::from_utf8_lossy(motif);
+        match counts.get_mut(motif.as_ref()) {
+            Some(count) => *count += reads,
+            None => {
+                counts.insert(motif.into_owned(), reads);
+            }
+        }
+    }
+
+    pub fn add_confidence(&mut self, locus_id: &str, confidence: f64, reads: u32) {
+        self.confidences
+            .entry(self.loci.intern(locus_id))
+            .or_default()
+            .add(confidence, reads);
+    }
+
+    /// The mean and maximum confidence of the interruptions of a locus
+    fn confidence_summary(&self, locus: Option<Symbol>) -> (Option<f64>, Option<f64>) {
+        locus
+            .and_then(|locus| self.confidences.get(&locus))
+            .map_or((None, None), Confidences::summary)
     }
 
-    pub fn increment_read_count(&mut self, locus_id: &str) {
-        self.read_counts
-            .entry(locus_id.to_string())
-            .and_modify(|count| *count += 1)
-            .or_insert(1);
+    /// The mean and median purity of the reads of a locus
+    fn purity_summary(&self, locus: Option<Symbol>) -> (Option<f64>, Option<f64>) {
+        locus
+            .and_then(|locus| self.purities.get(&locus))
+            .map_or((None, None), |purities| {
+                (purities.mean(), purities.median())
+            })
+    }
+
+    /// The median estimated copy number of the reads of a locus
+    fn median_copy_number(&self, locus: Option<Symbol>) -> Option<f64> {
+        locus
+            .and_then(|locus| self.copy_numbers.get(&locus))
+            .and_then(Histogram::median)
+    }
+
+    /// Writes the reads counted with each candidate motif of the loci with
+    /// several, in the order of the catalog, and the fraction of the reads of
+    /// the locus they are
+    fn write_motif_usage(
+        &self,
+        out: &Path,
+        candidate_motifs: &HashMap<String, Vec<String>>,
+    ) -> Result<()> {
+        let mut out_file = create_output(out)?;
+        schema::write_header(&mut out_file, OutputKind::MotifUsage)?;
+        let mut loci: Vec<(&String, &Vec<String>)> = candidate_motifs.iter().collect();
+        loci.sort_unstable();
+        for (locus_id, motifs) in loci {
+            let counts = self
+                .loci
+                .get(locus_id)
+                .and_then(|locus| self.motif_counts.get(&locus));
+            let total: u32 = counts.map_or(0, |counts| counts.values().sum());
+            for motif in motifs {
+                let count = counts
+                    .and_then(|counts| counts.get(motif))
+                    .copied()
+                    .unwrap_or(0);
+                let fraction = match total {
+                    0 => ".".to_string(),
+                    total => round_count(count as f64 / total as f64).to_string(),
+                };
+                writeln!(out_file, "{}\t{}\t{}\t{}", locus_id, motif, count, fraction)?;
+            }
+        }
+        out_file.finish()?;
+        Ok(())
     }
 
     pub fn write_to(
@@ -42,189 +342,2381 @@ impl Profile {
         out: PathBuf,
         motifs: &HashMap<String, String>,
         reference_regions: &HashMap<String, String>,
+        format: OutputFormat,
     ) -> Result<()> {
-        let mut out_file: File = File::create(out)?;
+        let out_file = create_output(&out)?;
+        if format != OutputFormat::Tsv {
+            let mut out_file = out_file;
+            let mut loci: Vec<(&String, &String)> = motifs.iter().collect();
+            loci.sort_unstable();
+            let records = loci.into_iter().map(|(locus_id, motif)| {
+                Ok(self.record(locus_id, motif, &reference_regions[locus_id]))
+            });
+            if format == OutputFormat::ArrowStream {
+                arrow_stream::write_profile(&mut out_file, records)?;
+            } else {
+                for record in records {
+                    writeln!(out_file, "{}", record?.to_json())?;
+                }
+            }
+            out_file.finish()?;
+        } else if is_gzipped_path(&out) {
+            let mut out_file = BgzfWriter::new(out_file);
+            self.write_rows(&mut out_file, motifs, reference_regions, true)?;
+            out_file.finish()?.finish()?;
+        } else {
+            let mut out_file = out_file;
+            self.write_rows(&mut out_file, motifs, reference_regions, false)?;
+            out_file.finish()?;
+        }
+        Ok(())
+    }
+
+    /// The counts of a locus as a record, as they are written to the TSV
+    pub fn record(&self, locus_id: &str, motif: &str, reference_region: &str) -> ProfileRecord {
+        let locus = self.loci.get(locus_id);
+        let mut interruption_counts: Vec<(String, u32, f64)> = locus
+            .and_then(|locus| self.interruption_counts.get(&locus))
+            .into_iter()
+            .flatten()
+            .map(|((interruption, repeat_len), count)| {
+                (
+                    self.interruptions.resolve(*interruption).to_string(),
+                    *repeat_len,
+                    round_count(*count),
+                )
+            })
+            .collect();
+        interruption_counts.sort_unstable_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+        let (mean_purity, median_purity) = self.purity_summary(locus);
+        // purities are rounded as in the TSV
+        let (mean_purity, median_purity) =
+            (mean_purity.map(round_count), median_purity.map(round_count));
+        let (mean_confidence, max_confidence) = self.confidence_summary(locus);
+        let (mean_confidence, max_confidence) = (
+            mean_confidence.map(round_confidence),
+            max_confidence.map(round_confidence),
+        );
+        ProfileRecord {
+            locus_id: locus_id.to_string(),
+            reference_region: reference_region.to_string(),
+            motif: motif.to_string(),
+            read_count: locus
+                .and_then(|locus| self.read_counts.get(&locus))
+                .copied()
+                .unwrap_or(0),
+            interruption_counts,
+            mean_purity,
+            median_purity,
+            mean_confidence,
+            max_confidence,
+            median_copy_number: self.median_copy_number(locus).map(round_copy_number),
+        }
+    }
+
+    /// Writes the profile, with its loci sorted by coordinates instead of
+    /// locus ID if `by_coordinates` is set
+    fn write_rows<W: Write>(
+        &self,
+        out_file: &mut W,
+        motifs: &HashMap<String, String>,
+        reference_regions: &HashMap<String, String>,
+        by_coordinates: bool,
+    ) -> Result<()> {
+        schema::write_header(out_file, OutputKind::Profile)?;
+        if self.count_unit == CountUnit::Fragment {
+            writeln!(out_file, "{}{}", COUNT_UNIT_PREFIX, self.count_unit.name())?;
+        }
+
+        // loci and interruptions are sorted so that the same inputs always
+        // give the same file
+        let mut loci: Vec<(&String, &String)> = motifs.iter().collect();
+        loci.sort_unstable();
+        if by_coordinates {
+            sort_by_coordinates(&mut loci, |(locus_id, _)| {
+                reference_regions.get(*locus_id).unwrap()
+            });
+        }
+        for (locus_id, motif) in loci {
+            self.record(locus_id, motif, &reference_regions[locus_id])
+                .write_to(out_file)?;
+        }
+        Ok(())
+    }
+}
+
+/// How `profile` treats reads by the anchoring scores of their flanks, which
+/// are judged by the weaker of the two
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AnchorParams {
+    /// Skip reads whose weaker flank scores below this
+    pub min_score: Option<i32>,
+    /// Weight the interruptions of reads whose weaker flank scores below this
+    /// by their score over it
+    pub full_score: Option<NonZeroU32>,
+}
+
+impl AnchorParams {
+    fn is_set(&self) -> bool {
+        self.min_score.is_some() || self.full_score.is_some()
+    }
+
+    /// The weight of the interruptions of a read, or None to skip the read
+    fn weight(&self, anchoring: Anchoring) -> Option<f64> {
+        let score = anchoring.left.min(anchoring.right);
+        if self.min_score.is_some_and(|min_score| score < min_score) {
+            return None;
+        }
+        Some(match self.full_score {
+            Some(full_score) => (score as f64 / full_score.get() as f64).clamp(0.0, 1.0),
+            None => 1.0,
+        })
+    }
+}
+
+/// The motifs and alignment settings shared by every file of repeat
+/// sequences profiled into one profile
+struct ProfileSettings<'a> {
+    motifs: &'a HashMap<String, String>,
+    align_params: AlignmentScoreParams,
+    score_matrix: Option<ScoreMatrix>,
+    preset: Option<Preset>,
+    platform: Platform,
+    quality_weighted: bool,
+    anchor_params: AnchorParams,
+    /// The settings of the loci of `--locus-params`
+    overrides: &'a HashMap<String, LocusOverride>,
+    /// The read filters of the loci of the catalog that have any
+    read_filters: &'a HashMap<String, ReadFilters>,
+    count_unit: CountUnit,
+    /// The flanks of the loci that have any, from the catalog or `--reference`
+    flanks: &'a HashMap<String, Flanks>,
+    /// The motifs that the reads of the loci with several are aligned to
+    candidate_motifs: &'a HashMap<String, Vec<String>>,
+}
+
+impl ProfileSettings<'_> {
+    /// Whether any setting judges reads by their anchoring scores
+    fn uses_anchoring(&self) -> bool {
+        self.anchor_params.is_set()
+            || self
+                .read_filters
+                .values()
+                .any(|read_filters| read_filters.min_anchor.is_some())
+    }
+}
+
+/// The reads that `profile` skipped, could not weight as asked or counted
+/// without aligning them
+#[derive(Debug, Default)]
+struct ReadTally {
+    /// Reads without base qualities despite `--quality-weighted`
+    unweighted: usize,
+    /// Reads without anchoring scores despite the anchoring options
+    unanchored: usize,
+    /// Reads skipped for a flank below `--min-anchor-score`
+    poorly_anchored: usize,
+    /// Reads skipped for a purity below the `min_purity` of their locus
+    impure: usize,
+    /// The reads of each locus skipped for being outside the read filters of
+    /// its catalog entry
+    filtered: BTreeMap<String, usize>,
+    /// Reads counted as pure without aligning them
+    prescreened: usize,
+    /// Reads skipped as the mate of a fragment already counted
+    mates: usize,
+    /// Reads without a read name despite `--count-unit fragment`
+    unnamed: usize,
+    /// The reads of each locus with flank bases trimmed off their repeat
+    /// sequence
+    flank_trimmed: BTreeMap<String, usize>,
+    /// The flank bases trimmed off, counted once per read they stand for
+    flank_trimmed_bases: usize,
+}
+
+impl ReadTally {
+    fn add(&mut self, other: ReadTally) {
+        self.unweighted += other.unweighted;
+        self.unanchored += other.unanchored;
+        self.poorly_anchored += other.poorly_anchored;
+        self.impure += other.impure;
+        self.prescreened += other.prescreened;
+        self.mates += other.mates;
+        self.unnamed += other.unnamed;
+        self.flank_trimmed_bases += other.flank_trimmed_bases;
+        for (locus_id, reads) in other.flank_trimmed {
+            *self.flank_trimmed.entry(locus_id).or_default() += reads;
+        }
+        for (locus_id, reads) in other.filtered {
+            *self.filtered.entry(locus_id).or_default() += reads;
+        }
+    }
+}
+
+/// The per-read outputs of `profile`, which are only written for a single file
+/// of repeat sequences
+#[derive(Default)]
+struct ReadOutputs {
+    alignments: Option<OutputFile>,
+    reads: Option<OutputFile>,
+    rejects: Option<OutputFile>,
+}
+
+impl ReadOutputs {
+    /// Writes a skipped read to the rejects output, if any, with its purity
+    /// if it was aligned
+    fn reject(
+        &mut self,
+        read: &RepeatRead,
+        reason: RejectReason,
+        purity: Option<f64>,
+    ) -> Result<()> {
+        let Some(rejects) = self.rejects.as_mut() else {
+            return Ok(());
+        };
+        let anchor_score = read
+            .anchoring
+            .map(|anchoring| anchoring.left.min(anchoring.right));
         writeln!(
-            out_file,
-            "locus_id\treference_region\tmotif\tread_count\tinterruption_counts"
+            rejects,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            read.locus_id,
+            read.number,
+            read.multiplicity,
+            read.seq.len(),
+            reason.code(),
+            format_purity(purity),
+            anchor_score.map_or(".".to_string(), |score| score.to_string())
         )?;
+        Ok(())
+    }
+}
 
-        let default_read_count: u32 = 0;
-        let default_interruptions: HashMap<(String, u32), u32> = HashMap::new();
+/// Why `profile` skipped a read, written to the rejects output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RejectReason {
+    /// The locus of the read is not in the catalog, or was filtered out
+    NotInCatalog,
+    /// The weaker flank scores below `--min-anchor-score`
+    PoorlyAnchored,
+    /// The repeat sequence is shorter than the `MinRepeatLen` of its locus
+    BelowMinRepeatLen,
+    /// The repeat sequence is longer than the `MaxRepeatLen` of its locus
+    AboveMaxRepeatLen,
+    /// The weaker flank scores below the `MinAnchor` of its locus
+    BelowMinAnchor,
+    /// The purity is below the `min_purity` of `--locus-params`
+    BelowMinPurity,
+    /// With `--count-unit fragment`, the mate of the read was already counted
+    CountedMate,
+}
 
-        for (locus_id, motif) in motifs {
-            let reference_region = reference_regions.get(locus_id).unwrap();
-            let read_count = self
-                .read_counts
-                .get(locus_id)
-                .unwrap_or(&default_read_count);
-            let interruptions = self
-                .interruption_counts
-                .get(locus_id)
-                .unwrap_or(&default_interruptions);
-            let interruptions_str: String = interruptions
-                .iter()
-                .map(|((interruption, repeat_len), count)| {
-                    format!("{}:{}:{}", interruption, repeat_len, count)
-                })
-                .collect::<Vec<String>>()
-                .join(",");
-            writeln!(
-                out_file,
-                "{}\t{}\t{}\t{}\t{}",
-                locus_id, reference_region, motif, read_count, interruptions_str
-            )?;
+impl RejectReason {
+    fn code(&self) -> &'static str {
+        match self {
+            RejectReason::NotInCatalog => "not_in_catalog",
+            RejectReason::PoorlyAnchored => "poorly_anchored",
+            RejectReason::BelowMinRepeatLen => "below_min_repeat_len",
+            RejectReason::AboveMaxRepeatLen => "above_max_repeat_len",
+            RejectReason::BelowMinAnchor => "below_min_anchor",
+            RejectReason::BelowMinPurity => "below_min_purity",
+            RejectReason::CountedMate => "counted_mate",
         }
-        Ok(())
     }
 }
 
+/// The prefix of the comment line that marks a profile or a read depths file
+/// whose counts are of fragments
+pub const COUNT_UNIT_PREFIX: &str = "#strif_count_unit=";
+
+/// What a read count of `profile` counts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CountUnit {
+    /// Every read, so both mates of a fragment count when they cover a locus
+    #[default]
+    Read,
+    /// Every fragment once, which needs read names from `extract --read-names`
+    Fragment,
+}
+
+impl CountUnit {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CountUnit::Read => "read",
+            CountUnit::Fragment => "fragment",
+        }
+    }
+}
+
+/// The count unit of a profile or read depths file, from its
+/// `#strif_count_unit=` line. Files without one count reads.
+pub fn read_count_unit(path: &Path) -> Result<CountUnit> {
+    let source = path.display().to_string();
+    for (i, line) in open_maybe_gzipped(path)?.lines().enumerate() {
+        let line = line.with_context(|| format!("Could not read {}", source))?;
+        if let Some(unit) = line.trim_end().strip_prefix(COUNT_UNIT_PREFIX) {
+            return match unit {
+                "read" => Ok(CountUnit::Read),
+                "fragment" => Ok(CountUnit::Fragment),
+                _ => bail!("Unknown count unit '{}' in {}", unit, source),
+            };
+        }
+        // the schema line and header row come before the comment lines
+        if i >= 2 && !line.starts_with('#') {
+            break;
+        }
+    }
+    Ok(CountUnit::Read)
+}
+
+/// Loads a substitution matrix for `--score-matrix`
+pub fn load_score_matrix(path: &Path) -> Result<ScoreMatrix> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("Could not open {}", path.display()))?;
+    text.parse::<ScoreMatrix>()
+        .map_err(|e| anyhow!("{}: {}", path.display(), e))
+}
+
+/// Where `profile` reads the repeat sequences of a sample from
+enum ProfileInput {
+    /// A file of repeat sequences, or a directory of shards
+    RepeatSeqs(PathBuf),
+    /// A BAMlet, whose repeat sequences are profiled as they are extracted
+    Bamlet(PathBuf),
+}
+
+/// The options of `profile` and `extract-profile`: the optional outputs, the
+/// alignment scores and how reads are counted. Its fields are the options of
+/// the command line, and it can be built with its builder methods, e.g.
+/// `ProfileOptions::default().preset(Preset::Auto).filter("^chr1_")`, or
+/// loaded from the `[profile]` table of a configuration file with
+/// [`ProfileOptions::from_config`].
+#[derive(Debug, Clone, PartialEq, clap::Args, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ProfileOptions {
+    /// Output visual alignments. Default is false.
+    #[clap(short = 'z', action)]
+    pub visual_alignments: bool,
+
+    /// The path to the visual alignments, which defaults to the same directory
+    /// as the input with a `.viz_align.txt` suffix
+    #[clap(skip)]
+    pub output_alignments: Option<PathBuf>,
+
+    /// Write each interruption found in each read, with its position and, for
+    /// inputs with base qualities, its mean and minimum base quality
+    #[clap(long)]
+    pub read_interruptions: Option<PathBuf>,
+
+    /// Write the reads that were skipped to this file, with the reason for each and its
+    /// purity and anchoring score where known
+    #[clap(long)]
+    pub rejects: Option<PathBuf>,
+
+    /// Write the reads counted with each candidate motif of the loci whose catalog entry
+    /// has CandidateMotifs to this file
+    #[clap(long)]
+    pub motif_usage: Option<PathBuf>,
+
+    /// Count each interruption by the probability that it is not a sequencing
+    /// error given its base qualities, giving expected counts. Requires base
+    /// qualities in the input, e.g. repeat sequences extracted with `--qualities`.
+    #[clap(long)]
+    pub quality_weighted: bool,
+
+    /// Skip reads whose weaker flank has an anchoring score below this. Requires
+    /// anchoring scores in the input, e.g. repeat sequences extracted with
+    /// `--anchoring`.
+    #[clap(long, allow_negative_numbers = true)]
+    pub min_anchor_score: Option<i32>,
+
+    /// Weight the interruptions of reads whose weaker flank has an anchoring
+    /// score below this by their score over it. Requires anchoring scores in the
+    /// input, e.g. repeat sequences extracted with `--anchoring`.
+    #[clap(long)]
+    pub full_anchor_score: Option<NonZeroU32>,
+
+    /// A TSV of alignment settings for some loci, applied on top of the global ones. Its
+    /// `locus` column holds a locus ID or a regular expression, and its other columns any of
+    /// match_score, mismatch_penalty, gap_open_penalty, gap_extend_penalty, padding,
+    /// merge_distance, min_insertion_len and min_purity.
+    #[clap(long)]
+    pub locus_params: Option<PathBuf>,
+
+    /// Filter locus IDs using a regular expression. Defaults to None.
+    /// This is useful for filtering out loci that are not of interest.
+    #[clap(short = 'f', long)]
+    pub filter: Option<String>,
+
+    #[clap(short = 'A', long, default_value = "1")]
+    pub match_score: i32,
+
+    #[clap(short = 'B', long, default_value = "8")]
+    pub mismatch_penalty: i32,
+
+    #[clap(short = 'O', long, default_value = "10")]
+    pub gap_open_penalty: i32,
+
+    #[clap(short = 'E', long, default_value = "1")]
+    pub gap_extend_penalty: i32,
+
+    /// A substitution matrix scoring each read base (rows) against each motif base (columns),
+    /// used instead of the match score and mismatch penalty, also with a preset
+    #[clap(long, conflicts_with_all = ["match_score", "mismatch_penalty"])]
+    pub score_matrix: Option<PathBuf>,
+
+    /// Built-in alignment settings used instead of the scores above, with a padding and merge
+    /// distance that scale with the motif length of each locus
+    #[clap(
+        long,
+        value_enum,
+        conflicts_with_all = ["match_score", "mismatch_penalty", "gap_open_penalty", "gap_extend_penalty"]
+    )]
+    pub preset: Option<Preset>,
+
+    /// The sequencing platform of the reads. `ont` makes single-base indels cheaper and longer
+    /// ones dearer than the gap penalties of the scores or preset, and does not report lone
+    /// single-base insertions as interruptions.
+    #[clap(
+        long,
+        value_enum,
+        default_value = "illumina",
+        conflicts_with_all = ["gap_open_penalty", "gap_extend_penalty"]
+    )]
+    pub platform: Platform,
+
+    /// The format of the profile. `arrow-stream` writes Arrow IPC record batches, to stdout
+    /// unless an output path is given.
+    #[clap(long, value_enum, default_value = "tsv")]
+    pub output_format: OutputFormat,
+
+    /// Whether read counts count reads or fragments. `fragment` counts the mates of a pair
+    /// that both cover a locus once; merge its profiles with the depths of `depth --fragments`.
+    #[clap(long, value_enum, default_value = "read")]
+    pub count_unit: CountUnit,
+
+    /// An indexed reference FASTA to take the flanks of loci from, for loci whose catalog
+    /// entry has no LeftFlank or RightFlank. Repeat sequences starting or ending with their
+    /// flank have it trimmed off.
+    #[clap(long)]
+    pub reference: Option<PathBuf>,
+}
+
+/// The defaults of the command line
+impl Default for ProfileOptions {
+    fn default() -> Self {
+        let scores = AlignmentScoreParams::default();
+        ProfileOptions {
+            visual_alignments: false,
+            output_alignments: None,
+            read_interruptions: None,
+            rejects: None,
+            motif_usage: None,
+            quality_weighted: false,
+            min_anchor_score: None,
+            full_anchor_score: None,
+            locus_params: None,
+            filter: None,
+            match_score: scores.match_score,
+            mismatch_penalty: scores.mismatch_penalty,
+            gap_open_penalty: scores.gap_open_penalty,
+            gap_extend_penalty: scores.gap_extend_penalty,
+            score_matrix: None,
+            preset: None,
+            platform: Platform::default(),
+            output_format: OutputFormat::default(),
+            count_unit: CountUnit::default(),
+            reference: None,
+        }
+    }
+}
+
+impl ProfileOptions {
+    builder_methods! {
+        visual_alignments: bool,
+        output_alignments: Option<PathBuf>,
+        read_interruptions: Option<PathBuf>,
+        rejects: Option<PathBuf>,
+        motif_usage: Option<PathBuf>,
+        quality_weighted: bool,
+        min_anchor_score: Option<i32>,
+        full_anchor_score: Option<NonZeroU32>,
+        locus_params: Option<PathBuf>,
+        filter: Option<String>,
+        match_score: i32,
+        mismatch_penalty: i32,
+        gap_open_penalty: i32,
+        gap_extend_penalty: i32,
+        score_matrix: Option<PathBuf>,
+        preset: Option<Preset>,
+        platform: Platform,
+        output_format: OutputFormat,
+        count_unit: CountUnit,
+        reference: Option<PathBuf>,
+    }
+
+    /// Sets the four alignment scores at once
+    pub fn scores(mut self, scores: AlignmentScoreParams) -> Self {
+        self.match_score = scores.match_score;
+        self.mismatch_penalty = scores.mismatch_penalty;
+        self.gap_open_penalty = scores.gap_open_penalty;
+        self.gap_extend_penalty = scores.gap_extend_penalty;
+        self
+    }
+
+    /// Loads the options from the `[profile]` table of a configuration file
+    pub fn from_config(path: &Path) -> Result<Self> {
+        load_options(path, "profile")
+    }
+
+    pub fn align_params(&self) -> AlignmentScoreParams {
+        AlignmentScoreParams {
+            match_score: self.match_score,
+            mismatch_penalty: self.mismatch_penalty,
+            gap_open_penalty: self.gap_open_penalty,
+            gap_extend_penalty: self.gap_extend_penalty,
+        }
+    }
+
+    pub fn anchor_params(&self) -> AnchorParams {
+        AnchorParams {
+            min_score: self.min_anchor_score,
+            full_score: self.full_anchor_score,
+        }
+    }
+
+    /// The path the profile of an input is written to if no output is given:
+    /// stdout for streamed formats, otherwise the same directory as the input
+    pub fn out_path(&self, input: &Path, output: Option<PathBuf>) -> PathBuf {
+        output
+            .or_else(|| self.output_format.default_out_path())
+            .unwrap_or_else(|| {
+                get_default_out_path(input, "strif_profile", self.output_format.extension())
+            })
+    }
+
+    /// The path the visual alignments of an input are written to, if they are
+    pub fn alignments_path(&self, input: &Path) -> Option<PathBuf> {
+        self.visual_alignments.then(|| {
+            self.output_alignments
+                .clone()
+                .unwrap_or_else(|| get_default_out_path(input, "viz_align", "txt"))
+        })
+    }
+}
+
+/// Profiles the interruptions of a file of repeat sequences, or of a
+/// directory of repeat sequence shards in parallel
 pub fn profile(
     repeat_seqs: PathBuf,
     str_catalog: PathBuf,
     out: PathBuf,
-    out_alignments: PathBuf,
-    align_params: AlignmentScoreParams,
-    write_alignments: bool,
-    filter: Option<String>,
+    options: &ProfileOptions,
 ) -> Result<()> {
-    let repeat_seqs_file: File = File::open(repeat_seqs)?;
-    let mut alignments_file: Option<File> = if write_alignments {
-        Some(File::create(out_alignments)?)
-    } else {
-        None
+    profile_input(
+        ProfileInput::RepeatSeqs(repeat_seqs),
+        str_catalog,
+        out,
+        options,
+    )
+}
+
+/// Profiles the repeat sequences of a BAMlet in a single pass, aligning each
+/// one as it is extracted instead of writing them to a file first. Base
+/// qualities and anchoring scores are only extracted when the options that
+/// use them are given.
+pub fn extract_profile(
+    bamlet: PathBuf,
+    str_catalog: PathBuf,
+    out: PathBuf,
+    options: &ProfileOptions,
+) -> Result<()> {
+    profile_input(ProfileInput::Bamlet(bamlet), str_catalog, out, options)
+}
+
+fn profile_input(
+    input: ProfileInput,
+    str_catalog: PathBuf,
+    out: PathBuf,
+    options: &ProfileOptions,
+) -> Result<()> {
+    let out_alignments = options.alignments_path(match &input {
+        ProfileInput::RepeatSeqs(path) | ProfileInput::Bamlet(path) => path,
+    });
+    let out_reads = options.read_interruptions.as_ref();
+    let out_rejects = options.rejects.as_ref();
+    let score_matrix = options
+        .score_matrix
+        .as_deref()
+        .map(load_score_matrix)
+        .transpose()?;
+    let anchor_params = options.anchor_params();
+    let count_unit = options.count_unit;
+    let shards: Option<Vec<PathBuf>> = match &input {
+        ProfileInput::RepeatSeqs(repeat_seqs) if repeat_seqs.is_dir() => {
+            if out_alignments.is_some() || out_reads.is_some() || out_rejects.is_some() {
+                bail!(
+                    "Visual alignments, read interruptions and rejected reads need a single file of repeat sequences, not a directory of shards"
+                );
+            }
+            let shards = shard_paths(repeat_seqs)?;
+            if shards.is_empty() {
+                bail!(
+                    "{} has no repeat sequence shards (*{})",
+                    repeat_seqs.display(),
+                    SHARD_SUFFIX
+                );
+            }
+            Some(shards)
+        }
+        _ => None,
     };
 
     info!("Loading STR catalog...");
-    let (motifs, reference_regions) = load_str_catalog(str_catalog, filter)?;
+    let (motifs, reference_regions, read_filters, mut flanks, candidate_motifs) =
+        load_str_catalog(str_catalog, options.filter.clone())?;
+    if let Some(reference) = &options.reference {
+        info!("Fetching the flanks of the loci from the reference...");
+        fetch_reference_flanks(reference, &reference_regions, &mut flanks)?;
+    }
+    let overrides = match &options.locus_params {
+        Some(locus_params) => LocusOverrides::from_path(locus_params)?.resolve(motifs.keys()),
+        None => HashMap::new(),
+    };
+    let settings = ProfileSettings {
+        motifs: &motifs,
+        align_params: options.align_params(),
+        score_matrix,
+        preset: options.preset,
+        platform: options.platform,
+        quality_weighted: options.quality_weighted,
+        anchor_params,
+        overrides: &overrides,
+        read_filters: &read_filters,
+        count_unit,
+        flanks: &flanks,
+        candidate_motifs: &candidate_motifs,
+    };
 
-    let mut profile: Profile = Profile::new();
+    let (mut profile, tally) = match shards {
+        Some(shards) => profile_shards(&shards, &settings)?,
+        None => {
+            let mut outputs = ReadOutputs::default();
+            if let Some(out_alignments) = &out_alignments {
+                outputs.alignments = Some(create_output(out_alignments)?);
+            }
+            if let Some(out_reads) = out_reads {
+                let mut reads_file = create_output(out_reads)?;
+                schema::write_header(&mut reads_file, OutputKind::ReadInterruptions)?;
+                outputs.reads = Some(reads_file);
+            }
+            if let Some(out_rejects) = out_rejects {
+                let mut rejects_file = create_output(out_rejects)?;
+                schema::write_header(&mut rejects_file, OutputKind::ProfileRejects)?;
+                outputs.rejects = Some(rejects_file);
+            }
+            info!("Profiling interruptions...");
+            let mut profile = Profile::new();
+            let tally = match &input {
+                ProfileInput::RepeatSeqs(repeat_seqs) => {
+                    profile_file(repeat_seqs, &settings, &mut profile, &mut outputs)?
+                }
+                ProfileInput::Bamlet(bamlet) => profile_bamlet(
+                    bamlet,
+                    options.reference.as_deref(),
+                    &settings,
+                    &mut profile,
+                    &mut outputs,
+                )?,
+            };
+            if let Some(alignments_file) = outputs.alignments {
+                alignments_file.finish()?;
+            }
+            if let Some(reads_file) = outputs.reads {
+                reads_file.finish()?;
+            }
+            if let Some(rejects_file) = outputs.rejects {
+                rejects_file.finish()?;
+            }
+            (profile, tally)
+        }
+    };
+    if tally.unweighted > 0 {
+        warn!(
+            unweighted_reads = tally.unweighted;
+            "{} reads have no base qualities and were counted without weights, extract them with --qualities",
+            tally.unweighted
+        );
+    }
+    if tally.unanchored > 0 {
+        warn!(
+            unanchored_reads = tally.unanchored;
+            "{} reads have no anchoring scores and were counted fully, extract them with --anchoring",
+            tally.unanchored
+        );
+    }
+    if let Some(min_score) = anchor_params.min_score {
+        info!(
+            poorly_anchored_reads = tally.poorly_anchored;
+            "Skipped {} reads with a flank anchoring score below {}",
+            tally.poorly_anchored,
+            min_score
+        );
+    }
+    if !tally.filtered.is_empty() {
+        let filtered: usize = tally.filtered.values().sum();
+        info!(
+            filtered_reads = filtered;
+            "Skipped {} reads outside the read filters of their locus in the catalog",
+            filtered
+        );
+        for (locus_id, reads) in &tally.filtered {
+            debug!(
+                "Skipped {} reads of {} outside its read filters",
+                reads, locus_id
+            );
+        }
+    }
+    if tally.impure > 0 {
+        info!(
+            impure_reads = tally.impure;
+            "Skipped {} reads with a purity below the minimum of their locus",
+            tally.impure
+        );
+    }
+    if !tally.flank_trimmed.is_empty() {
+        let trimmed: usize = tally.flank_trimmed.values().sum();
+        info!(
+            flank_trimmed_reads = trimmed, flank_trimmed_bases = tally.flank_trimmed_bases;
+            "Trimmed {} flank bases off {} reads whose repeat sequence started or ended with the flank of its locus",
+            tally.flank_trimmed_bases,
+            trimmed
+        );
+        for (locus_id, reads) in &tally.flank_trimmed {
+            debug!("Trimmed flank bases off {} reads of {}", reads, locus_id);
+        }
+    }
+    if count_unit == CountUnit::Fragment {
+        info!(
+            mate_reads = tally.mates;
+            "Skipped {} reads whose mate was already counted in the same fragment",
+            tally.mates
+        );
+    }
+    if tally.unnamed > 0 {
+        warn!(
+            unnamed_reads = tally.unnamed;
+            "{} reads have no read name and were counted as fragments of their own, extract them with --read-names",
+            tally.unnamed
+        );
+    }
+    debug!(
+        prescreened_reads = tally.prescreened;
+        "Counted {} reads that are exact repeats of their motif without aligning them",
+        tally.prescreened
+    );
+
+    profile.count_unit = count_unit;
+    let reads: u32 = profile.read_counts.values().sum();
+    let loci = profile.read_counts.len();
+    info!(reads, loci; "Writing profile of {} reads at {} loci to output file...", reads, loci);
+    profile.write_to(
+        out.clone(),
+        &motifs,
+        &reference_regions,
+        options.output_format,
+    )?;
+    if !candidate_motifs.is_empty() {
+        info!(
+            multi_motif_loci = candidate_motifs.len();
+            "Aligned the reads of {} loci with several candidate motifs to the one each fits best",
+            candidate_motifs.len()
+        );
+    }
+    if let Some(out_motif_usage) = &options.motif_usage {
+        info!("Writing the reads of each candidate motif to output file...");
+        profile.write_motif_usage(out_motif_usage, &candidate_motifs)?;
+    }
+    if reads == 0 {
+        return Err(StrifError::EmptyOutput {
+            path: out.display().to_string(),
+            message: match input {
+                ProfileInput::RepeatSeqs(_) => {
+                    "none of the repeat sequences are at loci of the catalog"
+                }
+                ProfileInput::Bamlet(_) => {
+                    "none of the reads of the BAMlet are at loci of the catalog"
+                }
+            }
+            .to_string(),
+        }
+        .into());
+    }
 
+    info!("Done!");
+
+    Ok(())
+}
+
+/// Profiles the shards of a directory in parallel. Each thread takes the next
+/// shard not yet taken, so a shard of a deep locus does not hold up the rest,
+/// and the profiles of the shards are combined in the order of their names so
+/// that the output does not depend on the number of threads.
+fn profile_shards(shards: &[PathBuf], settings: &ProfileSettings) -> Result<(Profile, ReadTally)> {
+    let workers = threads().min(shards.len());
+    info!(
+        shards = shards.len(), threads = workers;
+        "Profiling interruptions in {} shards with {} threads...",
+        shards.len(),
+        workers
+    );
+    let next_shard = AtomicUsize::new(0);
+    let mut results: Vec<(usize, Result<(Profile, ReadTally)>)> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    loop {
+                        let i = next_shard.fetch_add(1, Ordering::Relaxed);
+                        let Some(shard) = shards.get(i) else {
+                            break;
+                        };
+                        debug!("Profiling {}...", shard.display());
+                        let mut profile = Profile::new();
+                        let result = profile_file(
+                            shard,
+                            settings,
+                            &mut profile,
+                            &mut ReadOutputs::default(),
+                        )
+                        .map(|tally| (profile, tally));
+                        results.push((i, result));
+                    }
+                    results
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("a profiling thread panicked"))
+            .collect()
+    });
+    results.sort_unstable_by_key(|(i, _)| *i);
+
+    let mut profile = Profile::new();
+    let mut tally = ReadTally::default();
+    for (_, result) in results {
+        let (shard_profile, shard_tally) = result?;
+        profile.combine(shard_profile);
+        tally.add(shard_tally);
+    }
+    Ok((profile, tally))
+}
+
+/// Profiles a file of repeat sequences into a profile, returning the reads
+/// that were skipped or could not be weighted as asked
+fn profile_file(
+    repeat_seqs: &Path,
+    settings: &ProfileSettings,
+    profile: &mut Profile,
+    outputs: &mut ReadOutputs,
+) -> Result<ReadTally> {
+    let repeat_seqs_source = repeat_seqs.display().to_string();
+    let repeat_seqs_file: File = File::open(repeat_seqs)
+        .with_context(|| format!("Could not open {}", repeat_seqs_source))?;
     let repeat_seqs = csv::ReaderBuilder::new()
         .delimiter(b'\t')
         .has_headers(false)
+        .flexible(true)
+        .comment(Some(b'#'))
         .from_reader(repeat_seqs_file)
         .into_records();
 
-    info!("Profiling interruptions...");
+    let mut profiler = ReadProfiler::new(settings);
+    for (read, record) in repeat_seqs.enumerate() {
+        let record: csv::StringRecord = record?;
+        let locus_id: &str = field(&record, 0, &repeat_seqs_source)?;
 
-    let match_fn = |a: u8, b: u8| {
-        if a == b {
-            align_params.match_score
+        // skip if locus is not in STR catalog
+        let motif = settings.motifs.get(locus_id);
+        if motif.is_none() && outputs.rejects.is_none() {
+            debug!("Skipping locus {}...", locus_id);
+            continue;
+        }
+        let repeat_seq: &str = field(&record, 1, &repeat_seqs_source)?;
+        // rows collapsed by `dedup` stand for several reads
+        let multiplicity: u32 = parse_multiplicity(&record, &repeat_seqs_source)?;
+        let anchoring = if settings.uses_anchoring() || outputs.rejects.is_some() {
+            parse_anchoring(&record, &repeat_seqs_source)?
         } else {
-            -align_params.mismatch_penalty
+            None
+        };
+        let Some(motif) = motif else {
+            debug!("Skipping locus {}...", locus_id);
+            let read = RepeatRead {
+                number: read + 1,
+                locus_id,
+                motif: b"",
+                seq: repeat_seq.as_bytes(),
+                qualities: None,
+                multiplicity,
+                anchoring,
+                read_name: None,
+            };
+            outputs.reject(&read, RejectReason::NotInCatalog, None)?;
+            continue;
+        };
+        let qualities: Option<Vec<u8>> = record.get(2).and_then(decode_qualities);
+        let read_name = parse_read_name(&record);
+        if qualities
+            .as_ref()
+            .is_some_and(|qualities| qualities.len() != repeat_seq.len())
+        {
+            return Err(StrifError::malformed_row(
+                &repeat_seqs_source,
+                &record,
+                "the qualities and the repeat sequence differ in length",
+            )
+            .into());
         }
+        profiler.add_read(
+            &RepeatRead {
+                number: read + 1,
+                locus_id,
+                motif: motif.as_bytes(),
+                seq: repeat_seq.as_bytes(),
+                qualities: qualities.as_deref(),
+                multiplicity,
+                anchoring,
+                read_name,
+            },
+            profile,
+            outputs,
+        )?;
+    }
+
+    Ok(profiler.tally)
+}
+
+/// Profiles the repeat sequences of a BAMlet's reads into a profile as they
+/// are extracted, returning the reads that could not be weighted as asked.
+/// The reference, if given, also decodes CRAM BAMlets.
+fn profile_bamlet(
+    bamlet: &Path,
+    reference: Option<&Path>,
+    settings: &ProfileSettings,
+    profile: &mut Profile,
+    outputs: &mut ReadOutputs,
+) -> Result<ReadTally> {
+    let options = ExtractOptions::default()
+        .qualities(settings.quality_weighted || outputs.reads.is_some())
+        .anchoring(settings.uses_anchoring() || outputs.rejects.is_some())
+        .read_names(settings.count_unit == CountUnit::Fragment);
+    let options = ExtractOptions {
+        reference: reference.map(Path::to_path_buf),
+        ..options
     };
+    let mut profiler = ReadProfiler::new(settings);
+    let mut read = 0;
+    for_each_repeat_seq(bamlet, &options, None, None, |record, _| {
+        read += 1;
+        let Some(motif) = settings.motifs.get(&record.locus_id) else {
+            debug!("Skipping locus {}...", record.locus_id);
+            let read = RepeatRead {
+                number: read,
+                locus_id: &record.locus_id,
+                motif: b"",
+                seq: record.seq.as_bytes(),
+                qualities: None,
+                multiplicity: 1,
+                anchoring: record.anchoring,
+                read_name: None,
+            };
+            return outputs.reject(&read, RejectReason::NotInCatalog, None);
+        };
+        let qualities: Option<Vec<u8>> = record.qualities.as_deref().and_then(decode_qualities);
+        profiler.add_read(
+            &RepeatRead {
+                number: read,
+                locus_id: &record.locus_id,
+                motif: motif.as_bytes(),
+                seq: record.seq.as_bytes(),
+                qualities: qualities.as_deref(),
+                multiplicity: 1,
+                anchoring: record.anchoring,
+                read_name: record.read_name.as_deref(),
+            },
+            profile,
+            outputs,
+        )
+    })?;
+    Ok(profiler.tally)
+}
 
-    let mut aligner = Aligner::new(
-        -align_params.gap_open_penalty,
-        -align_params.gap_extend_penalty,
-        &match_fn,
-    );
+/// A repeat sequence at a locus of the catalog
+#[derive(Clone, Copy)]
+struct RepeatRead<'a> {
+    /// The row of the read among the repeat sequences, counting from 1
+    number: usize,
+    locus_id: &'a str,
+    motif: &'a [u8],
+    seq: &'a [u8],
+    /// The Phred base qualities of the repeat sequence, if extracted
+    qualities: Option<&'a [u8]>,
+    /// The number of reads the repeat sequence stands for
+    multiplicity: u32,
+    anchoring: Option<Anchoring>,
+    /// The name of the fragment of the read, if extracted
+    read_name: Option<&'a str>,
+}
 
-    for record in repeat_seqs {
-        let record: csv::StringRecord = record?;
-        let locus_id: String = record.get(0).unwrap().to_string();
+/// Aligns repeat sequences and counts their interruptions, whether they are
+/// read from a file or extracted from a BAMlet
+struct ReadProfiler<'a, 's> {
+    settings: &'s ProfileSettings<'a>,
+    /// A finder per distinct setting, as a preset can give each locus its own
+    finders: FxHashMap<LocusParams, InterruptionFinder>,
+    /// The locus of the last read, whose fragments `fragments` holds
+    fragment_locus: String,
+    /// The fragments of the current locus already counted with
+    /// `--count-unit fragment`, cleared when the locus changes so that only
+    /// the names of one locus are held at a time
+    fragments: HashSet<String>,
+    tally: ReadTally,
+}
 
-        // skip if locus is not in STR catalog
-        if !motifs.contains_key(locus_id.as_str()) {
-            debug!("Skipping locus {}...", locus_id);
-            continue;
+impl<'a, 's> ReadProfiler<'a, 's> {
+    fn new(settings: &'s ProfileSettings<'a>) -> Self {
+        Self {
+            settings,
+            finders: FxHashMap::default(),
+            fragment_locus: String::new(),
+            fragments: HashSet::new(),
+            tally: ReadTally::default(),
         }
+    }
 
-        let repeat_seq: String = record.get(1).unwrap().to_string();
-        let motif: String = motifs.get(&locus_id).unwrap().to_string();
-        let motif: Vec<u8> = motif.as_bytes().to_vec();
-
-        let observed_seq: Vec<u8> = repeat_seq.as_bytes().to_vec();
-        let pure_seq = create_pure_seq(&motif, repeat_seq.len(), 4);
+    fn add_read(
+        &mut self,
+        read: &RepeatRead,
+        profile: &mut Profile,
+        outputs: &mut ReadOutputs,
+    ) -> Result<()> {
+        let settings = self.settings;
+        let multiplicity = read.multiplicity;
+        // the mates of a fragment both cover the repeat when it is shorter
+        // than the fragment, so only the first one counted stands for it
+        let fragment = match (settings.count_unit, read.read_name) {
+            (CountUnit::Read, _) => None,
+            (CountUnit::Fragment, Some(read_name)) => {
+                // the reads of a locus are adjacent in the repeat sequences
+                // of a sorted BAMlet, as are the mates of a fragment
+                if self.fragment_locus != read.locus_id {
+                    self.fragment_locus.clear();
+                    self.fragment_locus.push_str(read.locus_id);
+                    self.fragments.clear();
+                }
+                if self.fragments.contains(read_name) {
+                    self.tally.mates += multiplicity as usize;
+                    return outputs.reject(read, RejectReason::CountedMate, None);
+                }
+                Some(read_name.to_string())
+            }
+            (CountUnit::Fragment, None) => {
+                self.tally.unnamed += multiplicity as usize;
+                None
+            }
+        };
+        // a repeat sequence whose boundaries were set inside the flanks of
+        // its locus starts or ends with flank bases, which would otherwise
+        // be counted as a large interruption at its boundary
+        let (left_trim, right_trim) = match settings.flanks.get(read.locus_id) {
+            Some(flanks) => flank_overlaps(
+                read.seq,
+                flanks.left.as_deref().map(str::as_bytes),
+                flanks.right.as_deref().map(str::as_bytes),
+                read.motif,
+            ),
+            None => (0, 0),
+        };
+        let trimmed_read;
+        let read = if left_trim + right_trim > 0 {
+            *self
+                .tally
+                .flank_trimmed
+                .entry(read.locus_id.to_string())
+                .or_default() += multiplicity as usize;
+            self.tally.flank_trimmed_bases += (left_trim + right_trim) * multiplicity as usize;
+            let end = read.seq.len() - right_trim;
+            trimmed_read = RepeatRead {
+                seq: &read.seq[left_trim..end],
+                qualities: read.qualities.map(|qualities| &qualities[left_trim..end]),
+                ..*read
+            };
+            &trimmed_read
+        } else {
+            read
+        };
+        if let Some(read_filters) = settings.read_filters.get(read.locus_id) {
+            let repeat_len = read.seq.len();
+            let reason = if read_filters
+                .min_repeat_len
+                .is_some_and(|min| repeat_len < min as usize)
+            {
+                Some(RejectReason::BelowMinRepeatLen)
+            } else if read_filters
+                .max_repeat_len
+                .is_some_and(|max| repeat_len > max as usize)
+            {
+                Some(RejectReason::AboveMaxRepeatLen)
+            } else {
+                match (read_filters.min_anchor, read.anchoring) {
+                    (Some(min_anchor), Some(anchoring))
+                        if anchoring.left.min(anchoring.right) < min_anchor =>
+                    {
+                        Some(RejectReason::BelowMinAnchor)
+                    }
+                    (Some(_), None) if !settings.anchor_params.is_set() => {
+                        self.tally.unanchored += multiplicity as usize;
+                        None
+                    }
+                    _ => None,
+                }
+            };
+            if let Some(reason) = reason {
+                *self
+                    .tally
+                    .filtered
+                    .entry(read.locus_id.to_string())
+                    .or_default() += multiplicity as usize;
+                return outputs.reject(read, reason, None);
+            }
+        }
+        let mut anchor_weight = 1.0;
+        if settings.anchor_params.is_set() {
+            match read.anchoring {
+                Some(anchoring) => match settings.anchor_params.weight(anchoring) {
+                    Some(weight) => anchor_weight = weight,
+                    None => {
+                        self.tally.poorly_anchored += multiplicity as usize;
+                        return outputs.reject(read, RejectReason::PoorlyAnchored, None);
+                    }
+                },
+                None => self.tally.unanchored += multiplicity as usize,
+            }
+        }
 
-        let alignment: Alignment = aligner.semiglobal(&observed_seq, &pure_seq);
+        let observed_seq = read.seq;
+        let qualities = read.qualities;
+        if settings.quality_weighted && qualities.is_none() {
+            self.tally.unweighted += multiplicity as usize;
+        }
+        let locus_override = settings.overrides.get(read.locus_id);
+        // the reads of a locus with several candidate motifs are profiled
+        // against the one they fit best
+        let candidates = settings.candidate_motifs.get(read.locus_id);
+        let motif = match candidates {
+            Some(candidates) => self.best_motif(observed_seq, candidates, locus_override),
+            None => read.motif,
+        };
+        let locus_params = self.locus_params(motif, locus_override);
+        let copy_number = estimate_copy_number(observed_seq, motif.len());
+        // most reads are exact repeats of their motif, whose alignment is all
+        // matches, so they are counted without aligning them unless their
+        // visual alignment is written
+        if outputs.alignments.is_none()
+            && locus_params.aligns_pure_repeats_exactly(motif)
+            && is_pure_repeat(observed_seq, motif)
+        {
+            self.tally.prescreened += multiplicity as usize;
+            self.fragments.extend(fragment);
+            profile.increment_read_count(read.locus_id, multiplicity);
+            if candidates.is_some() {
+                profile.add_motif_count(read.locus_id, motif, multiplicity);
+            }
+            profile.add_purity(read.locus_id, 1.0, multiplicity);
+            profile.add_copy_number(read.locus_id, copy_number, multiplicity);
+            return Ok(());
+        }
+        let finder = self
+            .finders
+            .entry(locus_params)
+            .or_insert_with(|| InterruptionFinder::with_locus_params(locus_params));
+        let (alignment, pure_seq) = finder.align(observed_seq, motif);
 
         // write visual alignment to file
-        if alignments_file.is_some() {
-            let alignments_file = alignments_file.as_mut().unwrap();
-            writeln!(alignments_file, "Locus {}:", locus_id)?;
+        if let Some(alignments_file) = outputs.alignments.as_mut() {
+            match candidates {
+                Some(_) => writeln!(
+                    alignments_file,
+                    "Locus {} ({}):",
+                    read.locus_id,
+                    String::from_utf8_lossy(motif)
+                )?,
+                None => writeln!(alignments_file, "Locus {}:", read.locus_id)?,
+            }
             writeln!(
                 alignments_file,
                 "{}",
-                alignment.pretty(&observed_seq, &pure_seq, 80)
+                alignment.pretty(observed_seq, pure_seq, 80)
             )?;
         }
 
-        let interruptions: Vec<String> = find_interruptions(alignment, &observed_seq);
+        let spans = find_interruption_spans(
+            &alignment,
+            locus_params.merge_distance,
+            locus_params.min_insertion_len,
+        );
 
-        profile.increment_read_count(&locus_id);
+        let purity = alignment_purity(&alignment);
+        let min_purity = locus_override.and_then(|locus_override| locus_override.min_purity);
+        if let (Some(purity), Some(min_purity)) = (purity, min_purity) {
+            if purity < min_purity {
+                self.tally.impure += multiplicity as usize;
+                return outputs.reject(read, RejectReason::BelowMinPurity, Some(purity));
+            }
+        }
+        self.fragments.extend(fragment);
+        profile.increment_read_count(read.locus_id, multiplicity);
+        if candidates.is_some() {
+            profile.add_motif_count(read.locus_id, motif, multiplicity);
+        }
+        if let Some(purity) = purity {
+            profile.add_purity(read.locus_id, purity, multiplicity);
+        }
+        profile.add_copy_number(read.locus_id, copy_number, multiplicity);
 
-        for interruption in &interruptions {
-            profile.increment_interruption(&locus_id, &interruption, observed_seq.len() as u32);
+        for span in spans {
+            let interruption = String::from_utf8_lossy(&observed_seq[span.clone()]);
+            let weight = match qualities {
+                Some(qualities) if settings.quality_weighted => {
+                    interruption_weight(&qualities[span.clone()])
+                }
+                _ => 1.0,
+            } * anchor_weight
+                * multiplicity as f64;
+            profile.increment_interruption(
+                read.locus_id,
+                &interruption,
+                observed_seq.len() as u32,
+                weight,
+            );
+            let penalty = span_penalty(&alignment, observed_seq, pure_seq, &span, &locus_params);
+            let confidence = interruption_confidence(
+                qualities.map(|qualities| &qualities[span.clone()]),
+                penalty,
+                anchor_weight,
+            );
+            profile.add_confidence(read.locus_id, confidence, multiplicity);
+            if let Some(reads_file) = outputs.reads.as_mut() {
+                let (mean_quality, min_quality) = match qualities {
+                    Some(qualities) => span_qualities(&qualities[span.clone()]),
+                    None => (".".to_string(), ".".to_string()),
+                };
+                writeln!(
+                    reads_file,
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.1}\t{:.1}\t{}\t{}",
+                    read.locus_id,
+                    read.number,
+                    observed_seq.len(),
+                    interruption,
+                    span.start,
+                    span.end,
+                    mean_quality,
+                    min_quality,
+                    confidence,
+                    copy_number,
+                    left_trim,
+                    right_trim
+                )?;
+            }
         }
+        Ok(())
     }
 
-    info!("Writing profile to output file...");
-    profile.write_to(out, &motifs, &reference_regions)?;
+    /// The alignment settings of a motif, from the preset or scores, the
+    /// platform and the overrides of the locus
+    fn locus_params(&self, motif: &[u8], locus_override: Option<&LocusOverride>) -> LocusParams {
+        let settings = self.settings;
+        let mut locus_params: LocusParams = match settings.preset {
+            Some(preset) => preset.locus_params(motif.len()),
+            None => settings.align_params.into(),
+        };
+        if settings.score_matrix.is_some() {
+            locus_params.matrix = settings.score_matrix;
+        }
+        settings.platform.adjust(&mut locus_params);
+        if let Some(locus_override) = locus_override {
+            locus_override.apply(&mut locus_params);
+        }
+        locus_params
+    }
 
-    info!("Done!");
+    /// The candidate motif a repeat sequence fits best: the first one it is
+    /// an exact repeat of, or else the one it aligns to with the highest
+    /// score, the earlier one on ties
+    fn best_motif<'m>(
+        &mut self,
+        seq: &[u8],
+        candidates: &'m [String],
+        locus_override: Option<&LocusOverride>,
+    ) -> &'m [u8] {
+        let candidates = candidates.iter().map(String::as_bytes);
+        if let Some(motif) = candidates.clone().find(|motif| is_pure_repeat(seq, motif)) {
+            return motif;
+        }
+        let mut best: Option<(i32, &[u8])> = None;
+        for motif in candidates {
+            let locus_params = self.locus_params(motif, locus_override);
+            let finder = self
+                .finders
+                .entry(locus_params)
+                .or_insert_with(|| InterruptionFinder::with_locus_params(locus_params));
+            let score = finder.align(seq, motif).0.score;
+            if best.is_none_or(|(best_score, _)| score > best_score) {
+                best = Some((score, motif));
+            }
+        }
+        best.map_or(b"", |(_, motif)| motif)
+    }
+}
 
-    Ok(())
+/// The probability that an interruption is real rather than a sequencing
+/// error, which is the probability that none of its bases were miscalled
+fn interruption_weight(qualities: &[u8]) -> f64 {
+    qualities
+        .iter()
+        .map(|q| 1.0 - 10f64.powf(-(*q as f64) / 10.0))
+        .product()
+}
+
+/// The highest confidence given to an interruption, as for base qualities
+const MAX_CONFIDENCE: f64 = 60.0;
+
+/// The Phred-scaled confidence that an interruption is real: that its bases
+/// were not miscalled given their qualities, that it is not an artifact of the
+/// alignment given its alignment penalty read as a Phred score, and that the
+/// read is placed at the locus given the anchoring weight of its flanks
+fn interruption_confidence(qualities: Option<&[u8]>, penalty: i32, anchor_weight: f64) -> f64 {
+    let real = qualities.map_or(1.0, interruption_weight)
+        * (1.0 - 10f64.powf(-(penalty.max(0) as f64) / 10.0))
+        * anchor_weight;
+    (-10.0 * (1.0 - real).log10()).clamp(0.0, MAX_CONFIDENCE)
+}
+
+/// Rounds a confidence as it is written to the TSV
+fn round_confidence(confidence: f64) -> f64 {
+    (confidence * 10.0).round() / 10.0
 }
 
-fn find_interruptions(alignment: Alignment, observed: &[u8]) -> Vec<String> {
-    // Given an alignment, find the interruptions in the repeat sequence
-    // by looking at the path and finding consecutive insertions or substitutions
-    let path = alignment.path();
-    let mut interruptions: Vec<String> = Vec::new();
-    let mut interruption: Vec<u8> = Vec::new();
-    for step in path.iter() {
-        let (observed_idx, _, op) = step;
-        if *op == AlignmentOperation::Subst || *op == AlignmentOperation::Ins {
-            // if *op == AlignmentOperation::Ins {
-            interruption.push(observed[*observed_idx - 1]);
-        } else if !interruption.is_empty() {
-            interruptions.push(String::from_utf8(interruption).unwrap());
-            interruption = Vec::new();
+/// Rounds an estimated copy number as it is written to the TSV
+fn round_copy_number(copy_number: f64) -> f64 {
+    (copy_number * 10.0).round() / 10.0
+}
+
+/// Rounds an expected interruption count for the profile output. Whole counts
+/// are written without a fractional part.
+fn round_count(count: f64) -> f64 {
+    (count * 10_000.0).round() / 10_000.0
+}
+
+/// The mean and minimum base quality of an interruption, formatted for the
+/// per-read output
+fn span_qualities(qualities: &[u8]) -> (String, String) {
+    match qualities.iter().min() {
+        Some(min) => {
+            let sum: u32 = qualities.iter().map(|q| *q as u32).sum();
+            let mean = sum as f64 / qualities.len() as f64;
+            (format!("{:.1}", mean), min.to_string())
         }
+        None => (".".to_string(), ".".to_string()),
     }
-    interruptions
 }
 
-fn create_pure_seq(motif: &[u8], len: usize, pad: usize) -> Vec<u8> {
-    // Given a motif, create a pure sequence of the motif with length
-    // len and pad the end with pad copies of the motif
-    let n = len / motif.len() + 1 + pad;
-    motif.repeat(n)
+/// The bases of each flank that `--reference` fetches for loci whose catalog
+/// entry gives none, enough to hold the misplaced boundaries of a locus
+const REFERENCE_FLANK_LEN: u64 = 50;
+
+/// Fetches the flanks that the catalog does not give from an indexed
+/// reference FASTA, next to the reference region of each locus
+fn fetch_reference_flanks(
+    reference: &Path,
+    reference_regions: &HashMap<String, String>,
+    flanks: &mut HashMap<String, Flanks>,
+) -> Result<()> {
+    let mut reader = fasta::IndexedReader::from_file(&reference).with_context(|| {
+        format!(
+            "Could not open {}, it should be a FASTA file with a .fai index",
+            reference.display()
+        )
+    })?;
+    let contig_lens: HashMap<String, u64> = reader
+        .index
+        .sequences()
+        .into_iter()
+        .map(|sequence| (sequence.name, sequence.len))
+        .collect();
+    let mut seq: Vec<u8> = Vec::new();
+    let mut fetch = |chrom: &str, start: u64, end: u64| -> Result<Option<String>> {
+        let Some(&contig_len) = contig_lens.get(chrom) else {
+            bail!("{} has no contig {}", reference.display(), chrom);
+        };
+        // the flank of a locus at the end of a contig is cut short
+        let end = end.min(contig_len);
+        if start >= end {
+            return Ok(None);
+        }
+        reader.fetch(chrom, start, end).with_context(|| {
+            format!(
+                "Could not fetch {}:{}-{} from {}",
+                chrom,
+                start,
+                end,
+                reference.display()
+            )
+        })?;
+        reader.read(&mut seq)?;
+        Ok((!seq.is_empty()).then(|| String::from_utf8_lossy(&seq).to_ascii_uppercase()))
+    };
+    for (locus_id, reference_region) in reference_regions {
+        let region: Region = reference_region
+            .parse()
+            .map_err(|e| anyhow!("Locus {}: {}", locus_id, e))?;
+        let locus_flanks = flanks.entry(locus_id.clone()).or_default();
+        if locus_flanks.left.is_none() {
+            locus_flanks.left = fetch(
+                &region.chrom,
+                region.start.saturating_sub(REFERENCE_FLANK_LEN),
+                region.start,
+            )?;
+        }
+        if locus_flanks.right.is_none() {
+            locus_flanks.right =
+                fetch(&region.chrom, region.end, region.end + REFERENCE_FLANK_LEN)?;
+        }
+    }
+    Ok(())
 }
 
-fn load_str_catalog(
-    str_catalog: PathBuf,
-    filter: Option<String>,
-) -> Result<(HashMap<String, String>, HashMap<String, String>)> {
+/// The motifs, reference regions, read filters, flanks and candidate motifs
+/// of the loci of a catalog
+pub type StrCatalog = (
+    HashMap<String, String>,
+    HashMap<String, String>,
+    HashMap<String, ReadFilters>,
+    HashMap<String, Flanks>,
+    HashMap<String, Vec<String>>,
+);
+
+/// Loads the loci of a catalog whose IDs match the filter. Only loci with
+/// read filters are in the read filters map, only those with a flank in the
+/// flanks map, and only those with several motifs in the candidate motifs
+/// map, whose motifs start with the motif of the locus structure.
+pub fn load_str_catalog(str_catalog: PathBuf, filter: Option<String>) -> Result<StrCatalog> {
     // create a regex filter if provided
     let filter_regex = match filter {
         Some(filter) => Some(regex::Regex::new(&filter)?),
         None => None,
     };
 
-    let str_catalog_file: File = File::open(str_catalog)?;
-    let str_catalog: Vec<HashMap<String, String>> = serde_json::from_reader(str_catalog_file)?;
+    let catalog = Catalog::from_path(&str_catalog)?;
     let mut motifs: HashMap<String, String> = HashMap::new();
     let mut reference_regions: HashMap<String, String> = HashMap::new();
-    for mut record in str_catalog {
-        let locus_id: String = record.remove("LocusId").unwrap();
-
+    let mut read_filters: HashMap<String, ReadFilters> = HashMap::new();
+    let mut flanks: HashMap<String, Flanks> = HashMap::new();
+    let mut candidate_motifs: HashMap<String, Vec<String>> = HashMap::new();
+    for locus in catalog.loci {
         // if a filter is provided, skip if the locus id doesn't match
         if let Some(filter_regex) = &filter_regex {
-            if !filter_regex.is_match(&locus_id) {
+            if !filter_regex.is_match(&locus.locus_id) {
                 continue;
             }
         }
 
-        let reference_region: String = record.remove("ReferenceRegion").unwrap();
-        reference_regions.insert(locus_id.clone(), reference_region);
+        motifs.insert(locus.locus_id.clone(), locus.motif().to_string());
+        if locus.read_filters.is_set() {
+            read_filters.insert(locus.locus_id.clone(), locus.read_filters);
+        }
+        let locus_motifs = locus.motifs();
+        if locus_motifs.len() > 1 {
+            candidate_motifs.insert(
+                locus.locus_id.clone(),
+                locus_motifs.into_iter().map(str::to_string).collect(),
+            );
+        }
+        if locus.flanks.is_set() {
+            flanks.insert(locus.locus_id.clone(), locus.flanks);
+        }
+        reference_regions.insert(locus.locus_id, locus.reference_region);
+    }
+    Ok((
+        motifs,
+        reference_regions,
+        read_filters,
+        flanks,
+        candidate_motifs,
+    ))
+}
+
+/// A row of a per-sample interruption profile
+pub struct ProfileRecord {
+    pub locus_id: String,
+    pub reference_region: String,
+    pub motif: String,
+    pub read_count: u32,
+    /// (interruption, repeat length, count), where counts are fractional if
+    /// weighted by base qualities
+    pub interruption_counts: Vec<(String, u32, f64)>,
+    /// The mean and median fraction of alignment columns matching the pure
+    /// repeat, missing for loci without reads and profiles of schema version 1
+    pub mean_purity: Option<f64>,
+    pub median_purity: Option<f64>,
+    /// The mean and maximum Phred-scaled confidence of the interruptions of
+    /// the locus, missing for loci without interruptions and profiles of
+    /// schema versions before 4
+    pub mean_confidence: Option<f64>,
+    pub max_confidence: Option<f64>,
+    /// The median over the reads of the locus of their motif copy number
+    /// estimated from the periodicity of their sequence, missing for loci
+    /// without reads and profiles of schema versions before 6
+    pub median_copy_number: Option<f64>,
+}
+
+impl ProfileRecord {
+    /// Writes the record as a row of a per-sample profile
+    pub fn write_to<W: Write>(&self, out: &mut W) -> Result<()> {
+        write!(
+            out,
+            "{}\t{}\t{}\t{}\t",
+            self.locus_id, self.reference_region, self.motif, self.read_count
+        )?;
+        for (i, (interruption, repeat_len, count)) in self.interruption_counts.iter().enumerate() {
+            let sep = if i == 0 { "" } else { "," };
+            write!(out, "{}{}:{}:{}", sep, interruption, repeat_len, count)?;
+        }
+        writeln!(
+            out,
+            "\t{}\t{}\t{}\t{}\t{}\t{}",
+            format_purity(self.mean_purity),
+            format_purity(self.median_purity),
+            format_coordinates(&self.reference_region),
+            format_confidence(self.mean_confidence),
+            format_confidence(self.max_confidence),
+            format_copy_number(self.median_copy_number)
+        )?;
+        Ok(())
+    }
+
+    /// Packs the interruption counts as `interruption:repeat_len:count,...`
+    pub fn packed_interruption_counts(&self) -> String {
+        self.interruption_counts
+            .iter()
+            .map(|(interruption, repeat_len, count)| {
+                format!("{}:{}:{}", interruption, repeat_len, count)
+            })
+            .collect::<Vec<String>>()
+            .join(",")
+    }
+
+    pub fn to_json(&self) -> Value {
+        let interruption_counts: Vec<Value> = self
+            .interruption_counts
+            .iter()
+            .map(|(interruption, repeat_len, count)| {
+                json!({
+                    "interruption": interruption,
+                    "repeat_len": repeat_len,
+                    "count": count,
+                })
+            })
+            .collect();
+        json!({
+            "locus_id": self.locus_id,
+            "reference_region": self.reference_region,
+            "motif": self.motif,
+            "read_count": self.read_count,
+            "interruption_counts": interruption_counts,
+            "mean_purity": self.mean_purity,
+            "median_purity": self.median_purity,
+            "mean_confidence": self.mean_confidence,
+            "max_confidence": self.max_confidence,
+            "median_copy_number": self.median_copy_number,
+        })
+    }
+
+    pub fn from_json(value: &Value) -> Result<Self> {
+        let mut interruption_counts: Vec<(String, u32, f64)> = Vec::new();
+        for entry in json_field(value, "interruption_counts")?
+            .as_array()
+            .unwrap_or(&Vec::new())
+        {
+            interruption_counts.push((
+                json_str(entry, "interruption")?,
+                json_u32(entry, "repeat_len")?,
+                json_f64(entry, "count")?,
+            ));
+        }
+        Ok(Self {
+            locus_id: json_str(value, "locus_id")?,
+            reference_region: json_str(value, "reference_region")?,
+            motif: json_str(value, "motif")?,
+            read_count: json_u32(value, "read_count")?,
+            interruption_counts,
+            mean_purity: value.get("mean_purity").and_then(Value::as_f64),
+            median_purity: value.get("median_purity").and_then(Value::as_f64),
+            mean_confidence: value.get("mean_confidence").and_then(Value::as_f64),
+            max_confidence: value.get("max_confidence").and_then(Value::as_f64),
+            median_copy_number: value.get("median_copy_number").and_then(Value::as_f64),
+        })
+    }
+}
+
+pub(crate) fn json_field<'a>(value: &'a Value, key: &str) -> Result<&'a Value> {
+    match value.get(key) {
+        Some(field) => Ok(field),
+        None => bail!("Missing field '{}' in {}", key, value),
+    }
+}
+
+pub(crate) fn json_str(value: &Value, key: &str) -> Result<String> {
+    match json_field(value, key)?.as_str() {
+        Some(s) => Ok(s.to_string()),
+        None => bail!("Field '{}' should be a string in {}", key, value),
+    }
+}
+
+pub(crate) fn json_u32(value: &Value, key: &str) -> Result<u32> {
+    match json_field(value, key)?.as_u64() {
+        Some(n) => Ok(n as u32),
+        None => bail!("Field '{}' should be an integer in {}", key, value),
+    }
+}
+
+pub(crate) fn json_f64(value: &Value, key: &str) -> Result<f64> {
+    match json_field(value, key)?.as_f64() {
+        Some(n) => Ok(n),
+        None => bail!("Field '{}' should be a number in {}", key, value),
+    }
+}
+
+/// Reads a per-sample interruption profile written by `profile`
+pub fn read_profile(path: &Path) -> Result<Vec<ProfileRecord>> {
+    let file =
+        open_maybe_gzipped(path).with_context(|| format!("Could not open {}", path.display()))?;
+    read_profile_from(file, &path.display().to_string())
+}
+
+/// Reads the records of a per-sample profile from a stream. The source is only
+/// used in error messages.
+pub fn read_profile_from<R: Read>(reader: R, source: &str) -> Result<Vec<ProfileRecord>> {
+    let mut reader = schema::tsv_reader(reader, OutputKind::Profile, source)?;
+    // profiles of schema versions before 4 have no confidence columns
+    let headers = reader.headers()?.clone();
+    let mean_confidence_column = headers.iter().position(|h| h == "mean_confidence");
+    let max_confidence_column = headers.iter().position(|h| h == "max_confidence");
+    // nor do those before 6 have a copy number column
+    let copy_number_column = headers.iter().position(|h| h == "median_copy_number");
+
+    let mut records: Vec<ProfileRecord> = Vec::new();
+    for result in reader.records() {
+        let record: csv::StringRecord = result?;
+        let read_count: u32 = parse_field(&record, 3, "read count", source)?;
+        let interruption_counts =
+            parse_interruption_counts(field(&record, 4, source)?).map_err(|e| {
+                StrifError::malformed_row(
+                    source,
+                    &record,
+                    format!("invalid interruption counts: {:#}", e),
+                )
+            })?;
+        records.push(ProfileRecord {
+            locus_id: field(&record, 0, source)?.to_string(),
+            reference_region: field(&record, 1, source)?.to_string(),
+            motif: field(&record, 2, source)?.to_string(),
+            read_count,
+            interruption_counts,
+            mean_purity: parse_purity(&record, 5, source)?,
+            median_purity: parse_purity(&record, 6, source)?,
+            mean_confidence: parse_confidence(&record, mean_confidence_column, source)?,
+            max_confidence: parse_confidence(&record, max_confidence_column, source)?,
+            median_copy_number: parse_copy_number(&record, copy_number_column, source)?,
+        });
+    }
+    Ok(records)
+}
+
+/// Parses an optional purity column, which is `.` for loci without reads and
+/// absent from profiles of schema version 1
+fn parse_purity(record: &csv::StringRecord, i: usize, source: &str) -> Result<Option<f64>> {
+    match record.get(i) {
+        None | Some(".") => Ok(None),
+        Some(_) => Ok(Some(parse_field(record, i, "purity", source)?)),
+    }
+}
+
+/// Parses an optional confidence column, which is `.` for loci without
+/// interruptions
+fn parse_confidence(
+    record: &csv::StringRecord,
+    i: Option<usize>,
+    source: &str,
+) -> Result<Option<f64>> {
+    match i.and_then(|i| record.get(i).map(|value| (i, value))) {
+        None | Some((_, ".")) => Ok(None),
+        Some((i, _)) => Ok(Some(parse_field(record, i, "confidence", source)?)),
+    }
+}
+
+/// Parses an optional copy number column, which is `.` for loci without reads
+fn parse_copy_number(
+    record: &csv::StringRecord,
+    i: Option<usize>,
+    source: &str,
+) -> Result<Option<f64>> {
+    match i.and_then(|i| record.get(i).map(|value| (i, value))) {
+        None | Some((_, ".")) => Ok(None),
+        Some((i, _)) => Ok(Some(parse_field(record, i, "copy number", source)?)),
+    }
+}
+
+/// Formats the chrom, start and end columns parsed from a reference region,
+/// which are `.` if it cannot be parsed
+fn format_coordinates(reference_region: &str) -> String {
+    match reference_span(reference_region) {
+        Some(span) => format!("{}\t{}\t{}", span.chrom, span.start, span.end),
+        None => ".\t.\t.".to_string(),
+    }
+}
+
+fn format_confidence(confidence: Option<f64>) -> String {
+    match confidence {
+        Some(confidence) => format!("{:.1}", confidence),
+        None => ".".to_string(),
+    }
+}
+
+fn format_copy_number(copy_number: Option<f64>) -> String {
+    match copy_number {
+        Some(copy_number) => format!("{:.1}", copy_number),
+        None => ".".to_string(),
+    }
+}
+
+fn format_purity(purity: Option<f64>) -> String {
+    match purity {
+        Some(purity) => format!("{:.4}", purity),
+        None => ".".to_string(),
+    }
+}
+
+/// Writes records as a per-sample profile, starting with the schema line and header row
+pub fn write_profile<W: Write>(out: &mut W, records: &[ProfileRecord]) -> Result<()> {
+    schema::write_header(out, OutputKind::Profile)?;
+    for record in records {
+        record.write_to(out)?;
+    }
+    Ok(())
+}
+
+/// Parses a packed `interruption:repeat_len:count,...` column
+pub fn parse_interruption_counts(s: &str) -> Result<Vec<(String, u32, f64)>> {
+    let mut counts: Vec<(String, u32, f64)> = Vec::new();
+    if s.is_empty() {
+        return Ok(counts);
+    }
+    for entry in s.split(',') {
+        let fields: Vec<&str> = entry.split(':').collect();
+        if fields.len() != 3 {
+            bail!("Expected interruption:repeat_len:count, got '{}'", entry);
+        }
+        counts.push((
+            fields[0].to_string(),
+            fields[1].parse::<u32>()?,
+            fields[2].parse::<f64>()?,
+        ));
+    }
+    Ok(counts)
+}
+
+/// The command line arguments of `strif profile`
+#[derive(Debug, clap::Args)]
+pub struct ProfileArgs {
+    /// The path to the file containing repeat sequences, or to a directory
+    /// of repeat sequence shards from `extract --shard-by-locus`, which are
+    /// profiled in parallel
+    pub repeat_seqs: PathBuf,
+
+    /// The path to a JSON file containing the catalog of repeat loci
+    pub str_catalog: PathBuf,
+
+    /// The path to the interruption profile output file. Defaults to the same directory as the repeat sequences.
+    /// A path ending in .gz is written bgzip-compressed and sorted by coordinates for tabix.
+    pub output: Option<PathBuf>,
+
+    /// The path to visual alignments file. Defaults to the same directory as the repeat sequences.
+    pub output_alignments: Option<PathBuf>,
+
+    #[clap(flatten)]
+    pub options: ProfileOptions,
+}
+
+impl ProfileArgs {
+    /// Runs `strif profile`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let ProfileArgs {
+            repeat_seqs,
+            str_catalog,
+            output,
+            output_alignments,
+            options,
+        } = self;
+        let options = ProfileOptions {
+            output_alignments,
+            ..options
+        };
+        let out_path = options.out_path(&repeat_seqs, output);
+        Plan::new("profile", dry_run, force)
+            .records("repeat sequences", &repeat_seqs, "reads")
+            .profiling(&str_catalog, &repeat_seqs, &out_path, &options)
+            .execute(|| profile(repeat_seqs, str_catalog, out_path, &options))
+    }
+}
+
+/// The command line arguments of `strif extract-profile`
+#[derive(Debug, clap::Args)]
+pub struct ExtractProfileArgs {
+    /// The path to the ExpansionHunter BAMlet
+    pub bamlet: PathBuf,
+
+    /// The path to a JSON file containing the catalog of repeat loci
+    pub str_catalog: PathBuf,
+
+    /// The path to the interruption profile output file. Defaults to the same directory as the BAMlet.
+    /// A path ending in .gz is written bgzip-compressed and sorted by coordinates for tabix.
+    pub output: Option<PathBuf>,
+
+    /// The path to visual alignments file. Defaults to the same directory as the BAMlet.
+    pub output_alignments: Option<PathBuf>,
+
+    #[clap(flatten)]
+    pub options: ProfileOptions,
+}
+
+impl ExtractProfileArgs {
+    /// Runs `strif extract-profile`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let ExtractProfileArgs {
+            bamlet,
+            str_catalog,
+            output,
+            output_alignments,
+            options,
+        } = self;
+        let options = ProfileOptions {
+            output_alignments,
+            ..options
+        };
+        let out_path = options.out_path(&bamlet, output);
+        Plan::new("extract-profile", dry_run, force)
+            .bamlet(&bamlet)
+            .profiling(&str_catalog, &bamlet, &out_path, &options)
+            .execute(|| extract_profile(bamlet, str_catalog, out_path, &options))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{write_catalog, CATALOG};
+
+    /// Profiles repeat sequence rows against the test catalog and returns the
+    /// written profile
+    fn profile_output(rows: &[&str], options: &ProfileOptions) -> String {
+        profile_output_with_catalog(rows, CATALOG, options)
+    }
+
+    /// The profile of repeat sequence rows against a catalog other than `CATALOG`
+    fn profile_output_with_catalog(
+        rows: &[&str],
+        catalog: &str,
+        options: &ProfileOptions,
+    ) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        let repeat_seqs = dir.path().join("sample.repeat_seqs.tsv");
+        let catalog_path = dir.path().join("catalog.json");
+        fs::write(&catalog_path, catalog).unwrap();
+        let catalog = catalog_path;
+        let out = dir.path().join("sample.profile");
+        fs::write(&repeat_seqs, rows.join("\n") + "\n").unwrap();
+        profile(repeat_seqs, catalog, out.clone(), options).unwrap();
+        fs::read_to_string(out).unwrap()
+    }
+
+    /// The records of the profile of repeat sequence rows
+    fn profile_rows(rows: &[&str], options: &ProfileOptions) -> Vec<ProfileRecord> {
+        let output = profile_output(rows, options);
+        read_profile_from(output.as_bytes(), "profile").unwrap()
+    }
+
+    fn read_count(records: &[ProfileRecord], locus_id: &str) -> u32 {
+        records
+            .iter()
+            .find(|record| record.locus_id == locus_id)
+            .unwrap()
+            .read_count
+    }
+
+    #[test]
+    fn fragments_are_counted_once_per_locus() {
+        let rows = [
+            "HTT\tCAGCAGCAGCAGCAGCAG\t*\t1\t.\t.\tfrag1",
+            "HTT\tCAGCAGCAGCAGCAGCAG\t*\t1\t.\t.\tfrag1",
+            "HTT\tCAGCAGCAGCAGCAGCAG\t*\t1\t.\t.\tfrag2",
+            "FMR1\tCGGCGGCGGCGGCGGCGG\t*\t1\t.\t.\tfrag1",
+        ];
+        let reads = profile_rows(&rows, &ProfileOptions::default());
+        assert_eq!(read_count(&reads, "HTT"), 3);
+        assert_eq!(read_count(&reads, "FMR1"), 1);
+        // the mates of frag1 count once at HTT, and its name counts again at
+        // another locus
+        let fragments = profile_rows(
+            &rows,
+            &ProfileOptions::default().count_unit(CountUnit::Fragment),
+        );
+        assert_eq!(read_count(&fragments, "HTT"), 2);
+        assert_eq!(read_count(&fragments, "FMR1"), 1);
+    }
+
+    #[test]
+    fn tsv_rows_match_jsonl_records() {
+        let rows = [
+            "HTT\tCAGCAGCAGCAGCAGCAG\t*\t2",
+            "HTT\tCAGCAGCATCAGCAGCAG\t*\t1",
+            "FMR1\tCGGCGGCGGAGGCGGCGG\t*\t1",
+        ];
+        let tsv = profile_rows(&rows, &ProfileOptions::default());
+        let jsonl = profile_output(
+            &rows,
+            &ProfileOptions::default().output_format(OutputFormat::Jsonl),
+        );
+        let jsonl: Vec<Value> = jsonl
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let tsv: Vec<Value> = tsv.iter().map(ProfileRecord::to_json).collect();
+        assert_eq!(tsv, jsonl);
+    }
+
+    #[test]
+    fn histograms_summarize_values_by_the_reads_they_stand_for() {
+        let mut histogram = Histogram::default();
+        assert_eq!((histogram.mean(), histogram.median()), (None, None));
+        histogram.add(0.5, 1);
+        histogram.add(0.9, 0);
+        histogram.add(1.0, 2);
+        assert_eq!(histogram.median(), Some(1.0));
+        let mut other = Histogram::default();
+        other.add(0.75, 1);
+        histogram.combine(other);
+        // the median of an even count is halfway between the middle values
+        assert_eq!(histogram.mean(), Some(0.8125));
+        assert!((histogram.median().unwrap() - 0.875).abs() < 1e-9);
+    }
+
+    #[test]
+    fn purity_and_copy_number_summarize_the_reads_of_a_locus() {
+        let rows = [
+            "HTT\tCAGCAGCAGCAGCAGCAG\t*\t2",
+            "HTT\tCAGCAGCATCAGCAGCAG\t*\t1",
+        ];
+        let records = profile_rows(&rows, &ProfileOptions::default());
+        let htt = records.iter().find(|r| r.locus_id == "HTT").unwrap();
+        assert_eq!(htt.read_count, 3);
+        assert_eq!(htt.median_purity, Some(1.0));
+        assert!(htt.mean_purity.unwrap() < 1.0);
+        assert_eq!(htt.median_copy_number, Some(6.0));
+        // loci without reads have no summaries
+        let fmr1 = records.iter().find(|r| r.locus_id == "FMR1").unwrap();
+        assert_eq!(fmr1.read_count, 0);
+        assert_eq!(fmr1.mean_purity, None);
+        assert_eq!(fmr1.median_copy_number, None);
+    }
+
+    #[test]
+    fn loci_and_interruptions_are_written_in_order() {
+        let rows = [
+            "HTT\tCAGCAGCAGTTCAGCAGCAG\t*\t1",
+            "HTT\tCAGCAGCATCAGCAGCAG\t*\t1",
+            "HTT\tCAGCAGCAACAGCAGCAG\t*\t1",
+            "FMR1\tCGGCGGCGGCGGCGGCGG\t*\t1",
+        ];
+        let records = profile_rows(&rows, &ProfileOptions::default());
+        let locus_ids: Vec<&str> = records.iter().map(|r| r.locus_id.as_str()).collect();
+        assert_eq!(locus_ids, ["FMR1", "HTT"]);
+        let interruptions: Vec<&str> = records[1]
+            .interruption_counts
+            .iter()
+            .map(|(interruption, _, _)| interruption.as_str())
+            .collect();
+        assert_eq!(interruptions, ["A", "T", "TT"]);
+    }
+
+    #[test]
+    fn read_interruptions_carry_the_qualities_of_their_bases() {
+        let dir = tempfile::tempdir().unwrap();
+        let reads = dir.path().join("sample.reads.tsv");
+        // the T is called with quality 10 and the other bases with 40
+        let rows = [
+            "HTT\tCAGCAGCATCAGCAGCAG\tIIIIIIII+IIIIIIIII\t1",
+            "HTT\tCAGCAGCATCAGCAGCAG\t*\t1",
+        ];
+        profile_output(
+            &rows,
+            &ProfileOptions::default().read_interruptions(reads.clone()),
+        );
+        let rows = crate::testing::data_rows(&reads);
+        let columns: Vec<Vec<&str>> = rows.iter().map(|row| row.split('\t').collect()).collect();
+        assert_eq!(columns.len(), 2);
+        assert_eq!(
+            columns[0][..8],
+            ["HTT", "1", "18", "T", "8", "9", "10.0", "10"]
+        );
+        // reads without qualities have none for their interruptions
+        assert_eq!(columns[1][..8], ["HTT", "2", "18", "T", "8", "9", ".", "."]);
+        // the copy number leaves out the interruption
+        assert_eq!(columns[0][9], "5.7");
+    }
+
+    #[test]
+    fn quality_weighted_counts_are_the_probability_the_bases_are_right() {
+        let rows = [
+            "HTT\tCAGCAGCATCAGCAGCAG\tIIIIIIII+IIIIIIIII\t1",
+            "HTT\tCAGCAGCATCAGCAGCAG\tIIIIIIII5IIIIIIIII\t1",
+        ];
+        let count = |options: &ProfileOptions| {
+            let records = profile_rows(&rows, options);
+            let htt = records.iter().find(|r| r.locus_id == "HTT").unwrap();
+            htt.interruption_counts[0].2
+        };
+        assert_eq!(count(&ProfileOptions::default()), 2.0);
+        // qualities 10 and 20 are wrong with probabilities 0.1 and 0.01
+        let weighted = count(&ProfileOptions::default().quality_weighted(true));
+        assert!((weighted - 1.89).abs() < 1e-9);
+    }
+
+    #[test]
+    fn directories_of_shards_profile_like_a_single_file() {
+        let htt = [
+            "HTT\tCAGCAGCAGCAGCAGCAG\t*\t2",
+            "HTT\tCAGCAGCATCAGCAGCAG\t*\t1",
+        ];
+        let fmr1 = ["FMR1\tCGGCGGCGGAGGCGGCGG\t*\t1"];
+        let dir = tempfile::tempdir().unwrap();
+        let shards = dir.path().join("shards");
+        fs::create_dir(&shards).unwrap();
+        fs::write(shards.join("HTT.repeat_seqs.tsv"), htt.join("\n") + "\n").unwrap();
+        fs::write(shards.join("FMR1.repeat_seqs.tsv"), fmr1.join("\n") + "\n").unwrap();
+        let catalog = write_catalog(dir.path());
+        let out = dir.path().join("sample.profile");
+        let options = ProfileOptions::default();
+        profile(shards.clone(), catalog.clone(), out.clone(), &options).unwrap();
+        let rows: Vec<&str> = htt.iter().chain(&fmr1).copied().collect();
+        assert_eq!(
+            fs::read_to_string(&out).unwrap(),
+            profile_output(&rows, &options)
+        );
+        // per-read outputs need a single file
+        let options = options.read_interruptions(dir.path().join("reads.tsv"));
+        assert!(profile(shards, catalog, out, &options).is_err());
+    }
+
+    #[test]
+    fn poorly_anchored_reads_are_skipped_or_down_weighted() {
+        let rows = [
+            "HTT\tCAGCAGCATCAGCAGCAG\t*\t1\t20\t30",
+            "HTT\tCAGCAGCATCAGCAGCAG\t*\t1\t5\t30",
+            "HTT\tCAGCAGCATCAGCAGCAG\t*\t1\t30\t-2",
+        ];
+        let htt = |options: &ProfileOptions| {
+            let records = profile_rows(&rows, options);
+            let htt = records.iter().find(|r| r.locus_id == "HTT").unwrap();
+            (htt.read_count, htt.interruption_counts[0].2)
+        };
+        assert_eq!(htt(&ProfileOptions::default()), (3, 3.0));
+        // reads are judged by their weaker flank
+        assert_eq!(
+            htt(&ProfileOptions::default().min_anchor_score(10)),
+            (1, 1.0)
+        );
+        let full = NonZeroU32::new(20).unwrap();
+        assert_eq!(
+            htt(&ProfileOptions::default().full_anchor_score(full)),
+            (3, 1.25)
+        );
+    }
+
+    #[test]
+    fn locus_params_change_the_alignments_of_their_loci() {
+        let rows = [
+            "HTT\tCAGCATCAGCATCAGCAG\t*\t1",
+            "HTT\tCAGCAGCAGCAGCAGCAG\t*\t1",
+            "FMR1\tCGGCGTCGGCGTCGGCGG\t*\t1",
+        ];
+        let dir = tempfile::tempdir().unwrap();
+        let locus_params = dir.path().join("locus_params.tsv");
+        fs::write(
+            &locus_params,
+            "locus\tmerge_distance\tmin_purity\nHTT\t5\t.\nFMR1\t.\t0.99\n",
+        )
+        .unwrap();
+        let htt = |records: &[ProfileRecord]| {
+            let htt = records.iter().find(|r| r.locus_id == "HTT").unwrap();
+            htt.interruption_counts
+                .iter()
+                .map(|(intr, _, count)| (intr.clone(), *count))
+                .collect::<Vec<_>>()
+        };
+
+        let records = profile_rows(&rows, &ProfileOptions::default());
+        assert_eq!(htt(&records), [("T".to_string(), 2.0)]);
+        assert_eq!(read_count(&records, "FMR1"), 1);
+
+        // the two interruptions of HTT are merged, the impure FMR1 read skipped
+        let records = profile_rows(&rows, &ProfileOptions::default().locus_params(locus_params));
+        assert_eq!(htt(&records), [("TCAGCAT".to_string(), 1.0)]);
+        assert_eq!(read_count(&records, "HTT"), 2);
+        assert_eq!(read_count(&records, "FMR1"), 0);
+    }
+
+    #[test]
+    fn reads_outside_the_read_filters_of_their_locus_are_skipped() {
+        let catalog = r#"[
+            {"LocusId": "HTT", "LocusStructure": "(CAG)*", "ReferenceRegion": "chr4:3074876-3074933",
+             "MinRepeatLen": 12, "MaxRepeatLen": 18, "MinAnchor": 0},
+            {"LocusId": "FMR1", "LocusStructure": "(CGG)*", "ReferenceRegion": "chrX:147912050-147912110"}
+        ]"#;
+        let rows = [
+            "HTT\tCAGCAGCAG\t*\t1\t5\t5",
+            "HTT\tCAGCAGCAGCAGCAG\t*\t1\t5\t5",
+            "HTT\tCAGCAGCAGCAGCAGCAGCAG\t*\t1\t5\t5",
+            "HTT\tCAGCAGCAGCAGCAG\t*\t2\t5\t-1",
+            "FMR1\tCGGCGGCGG\t*\t1\t5\t-1",
+        ];
+        let output = profile_output_with_catalog(&rows, catalog, &ProfileOptions::default());
+        let records = read_profile_from(output.as_bytes(), "profile").unwrap();
+        assert_eq!(read_count(&records, "HTT"), 1);
+        assert_eq!(read_count(&records, "FMR1"), 1);
+    }
+
+    #[test]
+    fn skipped_reads_are_written_to_rejects_with_their_reason() {
+        let rows = [
+            "HTT\tCAGCAGCAGCAGCAGCAG\t*\t1\t20\t20",
+            "HTT\tCAGCAGCAGCAGCAGCAG\t*\t2\t20\t5",
+            "ATXN1\tCAGCAGCAG\t*\t1\t20\t20",
+            "FMR1\tCGGCGTCGGCGTCGGCGG\t*\t1\t20\t20",
+        ];
+        let dir = tempfile::tempdir().unwrap();
+        let locus_params = dir.path().join("locus_params.tsv");
+        fs::write(&locus_params, "locus\tmin_purity\nFMR1\t0.99\n").unwrap();
+        let rejects = dir.path().join("rejects.tsv");
+        let options = ProfileOptions::default()
+            .min_anchor_score(10)
+            .locus_params(locus_params)
+            .rejects(rejects.clone());
+        let records = profile_rows(&rows, &options);
+        assert_eq!(read_count(&records, "HTT"), 1);
+        assert_eq!(
+            crate::testing::data_rows(&rejects),
+            [
+                "HTT\t2\t2\t18\tpoorly_anchored\t.\t5",
+                "ATXN1\t3\t1\t9\tnot_in_catalog\t.\t20",
+                "FMR1\t4\t1\t18\tbelow_min_purity\t0.8889\t20",
+            ]
+        );
+    }
+
+    #[test]
+    fn pure_reads_counted_without_aligning_profile_like_aligned_ones() {
+        let rows = [
+            "HTT\tCAGCAGCAGCAGCAGCAG\t*\t2",
+            "HTT\tAGCAGCAGCAGCAGCAGC\t*\t1",
+            "HTT\tCAGCAGCATCAGCAGCAG\t*\t1",
+            "FMR1\tGGCGGCGGCGGCGG\t*\t1",
+        ];
+        let dir = tempfile::tempdir().unwrap();
+        // writing the visual alignments aligns every read
+        let aligned = ProfileOptions::default()
+            .visual_alignments(true)
+            .output_alignments(dir.path().join("alignments.txt"));
+        assert_eq!(
+            profile_output(&rows, &ProfileOptions::default()),
+            profile_output(&rows, &aligned)
+        );
+    }
+
+    #[test]
+    fn flank_bases_at_the_ends_of_repeat_sequences_are_trimmed() {
+        let catalog = r#"[
+            {"LocusId": "HTT", "LocusStructure": "(CAG)*", "ReferenceRegion": "chr4:3074876-3074933",
+             "LeftFlank": "CCCCGATTACAT", "RightFlank": "TGCATGCCCC"},
+            {"LocusId": "FMR1", "LocusStructure": "(CGG)*", "ReferenceRegion": "chrX:147912050-147912110"}
+        ]"#;
+        let rows = ["HTT\tGATTACATCAGCAGCATCAGCAGTGCATGC\t*\t1"];
+        let dir = tempfile::tempdir().unwrap();
+        let reads = dir.path().join("sample.reads.tsv");
+        let options = ProfileOptions::default().read_interruptions(reads.clone());
+        let output = profile_output_with_catalog(&rows, catalog, &options);
+        let records = read_profile_from(output.as_bytes(), "profile").unwrap();
+        let htt = records.iter().find(|r| r.locus_id == "HTT").unwrap();
+        assert_eq!(htt.interruption_counts, [("T".to_string(), 15, 1.0)]);
+        // the interruption is where it is in the trimmed sequence
+        let read_rows = crate::testing::data_rows(&reads);
+        let columns: Vec<&str> = read_rows[0].split('\t').collect();
+        assert_eq!(columns[2..6], ["15", "T", "8", "9"]);
+        assert_eq!(columns[10..], ["8", "7"]);
+
+        // without flanks, they are counted as interruptions
+        let records = profile_rows(&rows, &ProfileOptions::default());
+        let htt = records.iter().find(|r| r.locus_id == "HTT").unwrap();
+        assert!(htt.interruption_counts.len() > 1);
+    }
+
+    #[test]
+    fn reads_are_profiled_against_the_candidate_motif_they_fit_best() {
+        let catalog = r#"[
+            {"LocusId": "RFC1", "LocusStructure": "(AAAAG)*", "ReferenceRegion": "chr4:39348424-39348479",
+             "CandidateMotifs": ["AAAAG", "aaggg"]}
+        ]"#;
+        let rows = [
+            "RFC1\tAAAAGAAAAGAAAAGAAAAG\t*\t2",
+            "RFC1\tAAGGGAAGGGAAGGGAAGGG\t*\t1",
+            "RFC1\tAAGGGAAGGGAAGTGAAGGG\t*\t1",
+        ];
+        let dir = tempfile::tempdir().unwrap();
+        let motif_usage = dir.path().join("sample.motif_usage.tsv");
+        let options = ProfileOptions::default().motif_usage(motif_usage.clone());
+        let output = profile_output_with_catalog(&rows, catalog, &options);
+        let records = read_profile_from(output.as_bytes(), "profile").unwrap();
+        // Only the impure AAGGG read has an interruption; the pure reads of
+        // either motif align without any.
+        assert_eq!(records[0].interruption_counts, [("T".to_string(), 20, 1.0)]);
+        assert_eq!(
+            crate::testing::data_rows(&motif_usage),
+            ["RFC1\tAAAAG\t2\t0.5", "RFC1\tAAGGG\t2\t0.5"]
+        );
+    }
+
+    #[test]
+    fn bgzipped_profiles_are_sorted_by_coordinates() {
+        let rows = [
+            "HTT\tCAGCAGCATCAGCAGCAG\t*\t1",
+            "FMR1\tCGGCGGCGGAGGCGGCGG\t*\t1",
+        ];
+        let dir = tempfile::tempdir().unwrap();
+        let repeat_seqs = dir.path().join("sample.repeat_seqs.tsv");
+        fs::write(&repeat_seqs, rows.join("\n") + "\n").unwrap();
+        let out = dir.path().join("sample.profile.tsv.gz");
+        let catalog = write_catalog(dir.path());
+        profile(
+            repeat_seqs,
+            catalog,
+            out.clone(),
+            &ProfileOptions::default(),
+        )
+        .unwrap();
+
+        // chr4 comes before chrX, unlike HTT and FMR1
+        let bgzipped = read_profile(&out).unwrap();
+        let locus_ids: Vec<&str> = bgzipped.iter().map(|r| r.locus_id.as_str()).collect();
+        assert_eq!(locus_ids, ["HTT", "FMR1"]);
+        let plain = profile_rows(&rows, &ProfileOptions::default());
+        let plain: Vec<Value> = plain.iter().rev().map(ProfileRecord::to_json).collect();
+        let bgzipped: Vec<Value> = bgzipped.iter().map(ProfileRecord::to_json).collect();
+        assert_eq!(bgzipped, plain);
+
+        let output = profile_output(&rows, &ProfileOptions::default());
+        let htt = output.lines().find(|line| line.starts_with("HTT")).unwrap();
+        let columns: Vec<&str> = htt.split('\t').collect();
+        assert_eq!(columns[7..10], ["chr4", "3074876", "3074933"]);
+    }
+
+    #[cfg(feature = "htslib")]
+    #[test]
+    fn single_pass_profiles_equal_extracting_then_profiling() {
+        use crate::extract::{extract, ExtractOptions};
+
+        let dir = tempfile::tempdir().unwrap();
+        let bamlet = crate::testing::write_bamlet(
+            dir.path(),
+            &[
+                ("read1", "AAACAGCAGCAGCAGTTT", "HTT,100,0[3M]1[12M]2[3M]"),
+                ("read2", "AAACAGCATCAGCAGTTT", "HTT,100,0[3M]1[12M]2[3M]"),
+                ("read3", "AAACGGCGGAGGCGGTTT", "FMR1,100,0[3M]1[12M]2[3M]"),
+            ],
+        );
+        let catalog = write_catalog(dir.path());
+        let options = ProfileOptions::default().quality_weighted(true);
+        let single_pass = dir.path().join("single_pass.profile");
+        extract_profile(
+            bamlet.clone(),
+            catalog.clone(),
+            single_pass.clone(),
+            &options,
+        )
+        .unwrap();
+
+        let repeat_seqs = dir.path().join("sample.repeat_seqs.tsv");
+        let extract_options = ExtractOptions::default().qualities(true);
+        extract(bamlet, repeat_seqs.clone(), &extract_options).unwrap();
+        let two_pass = dir.path().join("two_pass.profile");
+        profile(repeat_seqs, catalog, two_pass.clone(), &options).unwrap();
+
+        let records = read_profile(&single_pass).unwrap();
+        assert_eq!(read_count(&records, "HTT"), 2);
+        assert_eq!(
+            fs::read_to_string(single_pass).unwrap(),
+            fs::read_to_string(two_pass).unwrap()
+        );
+    }
+
+    #[test]
+    fn nanopore_reads_do_not_report_lone_single_base_insertions() {
+        let rows = [
+            "HTT\tCAGCAGCAGTCAGCAGCAG\t*\t1",
+            "HTT\tCAGCAGCAGTTCAGCAGCAG\t*\t1",
+        ];
+        let interruptions = |options: &ProfileOptions| -> Vec<String> {
+            let records = profile_rows(&rows, options);
+            let htt = records.iter().find(|r| r.locus_id == "HTT").unwrap();
+            htt.interruption_counts
+                .iter()
+                .map(|(interruption, _, _)| interruption.clone())
+                .collect()
+        };
+        assert_eq!(interruptions(&ProfileOptions::default()), ["T", "TT"]);
+        let ont = ProfileOptions::default().platform(Platform::Ont);
+        assert_eq!(interruptions(&ont), ["TT"]);
+    }
+
+    #[test]
+    fn confidences_combine_base_qualities_alignment_penalties_and_anchoring() {
+        // a penalty reads as a Phred score
+        assert!((interruption_confidence(None, 9, 1.0) - 9.0).abs() < 1e-9);
+        let confidence = interruption_confidence(Some(&[10]), 30, 1.0);
+        assert!(confidence > 9.9 && confidence < 10.0);
+        assert_eq!(interruption_confidence(None, 9, 0.0), 0.0);
+        assert_eq!(interruption_confidence(None, 100, 1.0), MAX_CONFIDENCE);
 
-        let structure: String = record.remove("LocusStructure").unwrap();
-        let motif = structure[1..structure.len() - 2].to_string();
-        motifs.insert(locus_id, motif);
+        // a substitution costs 1 + 8 and an insertion of two bases 10 + 1 + 1
+        let rows = [
+            "HTT\tCAGCAGCATCAGCAGCAG\t*\t1",
+            "HTT\tCAGCAGCAGTTCAGCAGCAG\t*\t1",
+        ];
+        let records = profile_rows(&rows, &ProfileOptions::default());
+        let htt = records.iter().find(|r| r.locus_id == "HTT").unwrap();
+        assert_eq!(htt.mean_confidence, Some(10.5));
+        assert_eq!(htt.max_confidence, Some(12.0));
     }
-    Ok((motifs, reference_regions))
 }
@@ -0,0 +1,459 @@
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::Command;
+use log::info;
+use serde_json::json;
+use toml::{Table, Value};
+
+use crate::config::write_config;
+use crate::merge::{load_manifest, ManifestEntry};
+use crate::plan::Plan;
+use crate::run::{profile_path, repeat_seqs_path, write_profile_manifest};
+use crate::utils::create_output;
+
+/// The name of the option pointing to a project directory
+pub const PROJECT_ARG: &str = "project";
+
+/// The option naming the sample of a per-sample subcommand run in a project
+const SAMPLE_ARG: &str = "--sample";
+
+/// The subcommands that run on one sample of a project
+const PER_SAMPLE: [&str; 4] = ["extract", "profile", "extract-profile", "call"];
+
+/// The file of a project holding the paths of its inputs
+const PROJECT_FILE: &str = "project.toml";
+
+/// The configuration of a project, applied to every subcommand run in it
+const CONFIG_FILE: &str = "strif.toml";
+
+/// The record of every subcommand run in a project, a JSON object per line
+const RUNS_FILE: &str = "runs.jsonl";
+
+/// The paths of the inputs and outputs of a project, relative to its directory
+pub struct Project {
+    /// Sample ID, case-control status and BAMlet path of each sample
+    manifest: PathBuf,
+    catalog: PathBuf,
+    read_depths: PathBuf,
+    /// The directory every output is written to, laid out as by `run`
+    output_dir: PathBuf,
+}
+
+impl Project {
+    /// Loads the project file of a project directory
+    pub fn open(dir: &Path) -> Result<Self> {
+        let path = dir.join(PROJECT_FILE);
+        let text = fs::read_to_string(&path).with_context(|| {
+            format!(
+                "Could not read {}, is {} a project created by `strif init`?",
+                path.display(),
+                dir.display()
+            )
+        })?;
+        let table = text
+            .parse::<Table>()
+            .with_context(|| format!("Could not parse {}", path.display()))?;
+        let mut project = Project {
+            manifest: dir.join("manifest.tsv"),
+            catalog: dir.join("catalog.json"),
+            read_depths: dir.join("read_depths.tsv"),
+            output_dir: dir.join("output"),
+        };
+        for (key, value) in &table {
+            let target = match key.replace('_', "-").as_str() {
+                "manifest" => &mut project.manifest,
+                "catalog" => &mut project.catalog,
+                "read-depths" => &mut project.read_depths,
+                "output-dir" => &mut project.output_dir,
+                _ => bail!("{}: '{}' is not a project setting", path.display(), key),
+            };
+            match value {
+                Value::String(value) => *target = dir.join(value),
+                _ => bail!("{}: '{}' should be a path", path.display(), key),
+            }
+        }
+        Ok(project)
+    }
+
+    fn sample(&self, sample_id: &str) -> Result<ManifestEntry> {
+        let samples = load_manifest(&self.manifest)?;
+        match samples.into_iter().find(|s| s.sample_id == sample_id) {
+            Some(sample) => Ok(sample),
+            None => bail!(
+                "Sample {} is not in the manifest {}",
+                sample_id,
+                self.manifest.display()
+            ),
+        }
+    }
+
+    fn calls_path(&self, sample_id: &str) -> PathBuf {
+        self.output_dir
+            .join(format!("{}.strif_calls.tsv", sample_id))
+    }
+
+    /// The inputs and outputs of a subcommand run in the project, as its
+    /// positional arguments and options, or None if the subcommand takes none
+    /// from a project
+    fn arguments(&self, subcommand: &str, sample_id: Option<&str>) -> Result<Option<Arguments>> {
+        let sample = match sample_id {
+            Some(sample_id) => self.sample(sample_id)?,
+            None if PER_SAMPLE.contains(&subcommand) => bail!(
+                "`strif {}` needs {} to run in a project",
+                subcommand,
+                SAMPLE_ARG
+            ),
+            None => {
+                return Ok(match subcommand {
+                    "merge" => {
+                        let samples = load_manifest(&self.manifest)?;
+                        fs::create_dir_all(&self.output_dir)?;
+                        let profile_manifest = write_profile_manifest(&samples, &self.output_dir)?;
+                        Some(Arguments::positional(vec![
+                            profile_manifest,
+                            self.read_depths.clone(),
+                            self.output_dir.join("merged_profile.tsv"),
+                        ]))
+                    }
+                    "run" => Some(Arguments {
+                        positional: Vec::new(),
+                        options: vec![
+                            ("--manifest", self.manifest.clone()),
+                            ("--catalog", self.catalog.clone()),
+                            ("--read-depths", self.read_depths.clone()),
+                            ("--output-dir", self.output_dir.clone()),
+                        ],
+                    }),
+                    _ => None,
+                })
+            }
+        };
+        fs::create_dir_all(&self.output_dir)?;
+        let sample_id = &sample.sample_id;
+        let repeat_seqs = repeat_seqs_path(&self.output_dir, sample_id);
+        let profile = profile_path(&self.output_dir, sample_id);
+        Ok(Some(Arguments::positional(match subcommand {
+            "extract" => vec![sample.path, repeat_seqs],
+            "profile" => vec![repeat_seqs, self.catalog.clone(), profile],
+            "extract-profile" => vec![sample.path, self.catalog.clone(), profile],
+            "call" => vec![profile, self.calls_path(sample_id)],
+            _ => unreachable!(),
+        })))
+    }
+}
+
+/// The arguments a project adds to a command line
+struct Arguments {
+    positional: Vec<PathBuf>,
+    options: Vec<(&'static str, PathBuf)>,
+}
+
+impl Arguments {
+    fn positional(positional: Vec<PathBuf>) -> Self {
+        Self {
+            positional,
+            options: Vec::new(),
+        }
+    }
+}
+
+/// Creates a project directory with a project file pointing to its inputs, a
+/// configuration file listing every option, a manifest template and the
+/// output directory. Existing files are only replaced with `force`.
+pub fn init(command: &Command, dir: PathBuf, force: bool) -> Result<()> {
+    let project_path = dir.join(PROJECT_FILE);
+    let manifest_path = dir.join("manifest.tsv");
+    let config_path = dir.join(CONFIG_FILE);
+    for path in [&project_path, &manifest_path, &config_path] {
+        if path.exists() && !force {
+            bail!(
+                "{} already exists, choose another directory or pass --force",
+                path.display()
+            );
+        }
+    }
+    fs::create_dir_all(dir.join("output"))
+        .with_context(|| format!("Could not create {}", dir.display()))?;
+
+    let mut project_file = create_output(&project_path)?;
+    writeln!(
+        project_file,
+        "# strif project, used with `strif --project {}`.",
+        dir.display()
+    )?;
+    writeln!(
+        project_file,
+        "# Paths are relative to the project directory. Options for the subcommands"
+    )?;
+    writeln!(
+        project_file,
+        "# go in {}, which is applied to every run in the project.",
+        CONFIG_FILE
+    )?;
+    writeln!(project_file)?;
+    writeln!(
+        project_file,
+        "# Sample ID, case-control status and BAMlet path of each sample"
+    )?;
+    writeln!(project_file, "manifest = \"manifest.tsv\"")?;
+    writeln!(project_file, "# The catalog of repeat loci")?;
+    writeln!(project_file, "catalog = \"catalog.json\"")?;
+    writeln!(
+        project_file,
+        "# The global average read depth of each sample, used by merge"
+    )?;
+    writeln!(project_file, "read-depths = \"read_depths.tsv\"")?;
+    writeln!(
+        project_file,
+        "# The directory of the outputs, named after their samples as by `run`"
+    )?;
+    writeln!(project_file, "output-dir = \"output\"")?;
+    project_file.finish()?;
+
+    let mut manifest_file = create_output(&manifest_path)?;
+    writeln!(
+        manifest_file,
+        "# Sample ID, case-control status (case or control) and BAMlet path, separated by tabs."
+    )?;
+    writeln!(
+        manifest_file,
+        "# BAMlet paths are absolute or relative to the directory strif is run from."
+    )?;
+    writeln!(manifest_file, "# S1\tcase\t/data/S1.realigned.bam")?;
+    manifest_file.finish()?;
+
+    write_config(command, &config_path)?;
+    info!("Created the project {}", dir.display());
+    info!("Done!");
+    Ok(())
+}
+
+/// Fills in the inputs and outputs of a command line run with `--project`
+/// from the project's layout, taking the sample of per-sample subcommands
+/// from `--sample`, and points `--config` to the project's configuration if
+/// no other is given. Subcommands without inputs in the project only get its
+/// configuration.
+pub fn apply_project(command: &Command, args: Vec<OsString>) -> Result<Vec<OsString>> {
+    let matches = match command
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches_from(&args)
+    {
+        Ok(matches) => matches,
+        // let clap report the problem when the arguments are parsed for real
+        Err(_) => return Ok(args),
+    };
+    let dir = match matches.get_one::<PathBuf>(PROJECT_ARG) {
+        Some(dir) => dir.clone(),
+        None => return Ok(args),
+    };
+    let project = Project::open(&dir)?;
+    let subcommand = matches.subcommand_name().unwrap_or_default();
+    let (mut args, sample_id) = match PER_SAMPLE.contains(&subcommand) {
+        true => take_sample(args)?,
+        false => (args, None),
+    };
+
+    if let Some(arguments) = project.arguments(subcommand, sample_id.as_deref())? {
+        if args.iter().any(|a| a == "--") {
+            bail!("`--` cannot be used in a project, which gives the inputs and outputs");
+        }
+        for (flag, path) in arguments.options {
+            args.push(format!("{}={}", flag, path.display()).into());
+        }
+        if !arguments.positional.is_empty() {
+            args.push("--".into());
+            args.extend(arguments.positional.into_iter().map(OsString::from));
+        }
+    }
+
+    let config = dir.join(CONFIG_FILE);
+    if matches.get_one::<PathBuf>("config").is_none() && config.exists() {
+        args.insert(1, format!("--config={}", config.display()).into());
+    }
+    Ok(args)
+}
+
+/// Removes `--sample <SAMPLE>` from the command line of a per-sample
+/// subcommand, which has no such option of its own
+fn take_sample(args: Vec<OsString>) -> Result<(Vec<OsString>, Option<String>)> {
+    let mut rest: Vec<OsString> = Vec::with_capacity(args.len());
+    let mut sample_id: Option<String> = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let text = arg.to_string_lossy();
+        let value = if text == SAMPLE_ARG {
+            match args.next() {
+                Some(value) => value.to_string_lossy().into_owned(),
+                None => bail!("{} needs a sample ID", SAMPLE_ARG),
+            }
+        } else if let Some(value) = text.strip_prefix("--sample=") {
+            value.to_string()
+        } else {
+            rest.push(arg);
+            continue;
+        };
+        if sample_id.replace(value).is_some() {
+            bail!("{} can only be given once", SAMPLE_ARG);
+        }
+    }
+    Ok((rest, sample_id))
+}
+
+/// Appends a run of a subcommand in a project to the project's run record,
+/// with the command line after the project and configuration were applied
+pub fn record_run(dir: &Path, args: &[OsString], succeeded: bool) -> Result<()> {
+    let path = dir.join(RUNS_FILE);
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Could not open {}", path.display()))?;
+    let args: Vec<String> = args
+        .iter()
+        .skip(1)
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    let record = json!({
+        "time": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        "version": env!("CARGO_PKG_VERSION"),
+        "args": args,
+        "status": if succeeded { "completed" } else { "failed" },
+    });
+    writeln!(file, "{}", record)?;
+    Ok(())
+}
+
+/// The command line arguments of `strif init`
+#[derive(Debug, clap::Args)]
+pub struct InitArgs {
+    /// The project directory, which is created if it does not exist
+    pub dir: PathBuf,
+}
+
+impl InitArgs {
+    /// Runs `strif init` with the options of a command line, or prints its
+    /// plan with `--dry-run`
+    pub fn execute(self, command: Command, dry_run: bool, force: bool) -> Result<()> {
+        // the project files check for existing files themselves
+        Plan::new("init", dry_run, force)
+            .note("project", self.dir.display())
+            .execute(|| init(&command, self.dir, force))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{CommandFactory, Parser, Subcommand};
+
+    use super::*;
+
+    /// A command line shaped like strif's, with a per-sample and a cohort
+    /// subcommand
+    #[derive(Debug, Parser)]
+    struct Cli {
+        #[clap(long, global = true)]
+        config: Option<PathBuf>,
+        #[clap(long, global = true)]
+        project: Option<PathBuf>,
+        #[clap(subcommand)]
+        command: Commands,
+    }
+
+    #[derive(Debug, PartialEq, Subcommand)]
+    enum Commands {
+        Profile {
+            repeat_seqs: PathBuf,
+            catalog: PathBuf,
+            out: PathBuf,
+            #[clap(short = 'f', long)]
+            filter: Option<String>,
+        },
+        Merge {
+            manifest: PathBuf,
+            read_depths: PathBuf,
+            out: PathBuf,
+        },
+    }
+
+    fn parse_in_project(args: &[&str]) -> Result<Cli> {
+        let args: Vec<OsString> = ["strif"].iter().chain(args).map(OsString::from).collect();
+        let args = apply_project(&Cli::command(), args)?;
+        Ok(Cli::try_parse_from(args)?)
+    }
+
+    #[test]
+    fn projects_fill_in_the_inputs_and_outputs_of_subcommands() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("cohort");
+        init(&Cli::command(), project.clone(), false).unwrap();
+        assert!(init(&Cli::command(), project.clone(), false).is_err());
+        fs::write(project.join("manifest.tsv"), "S1\tcase\tS1.bam\n").unwrap();
+        let project_arg = format!("--project={}", project.display());
+        let output_dir = project.join("output");
+
+        let cli =
+            parse_in_project(&[&project_arg, "profile", "--sample", "S1", "-f", "^HTT$"]).unwrap();
+        assert_eq!(cli.config, Some(project.join(CONFIG_FILE)));
+        assert_eq!(
+            cli.command,
+            Commands::Profile {
+                repeat_seqs: repeat_seqs_path(&output_dir, "S1"),
+                catalog: project.join("catalog.json"),
+                out: profile_path(&output_dir, "S1"),
+                filter: Some("^HTT$".to_string()),
+            }
+        );
+        let cli = parse_in_project(&[&project_arg, "merge"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Commands::Merge { read_depths, .. } if read_depths == project.join("read_depths.tsv")
+        ));
+
+        // per-sample subcommands need a sample of the manifest
+        assert!(parse_in_project(&[&project_arg, "profile"]).is_err());
+        assert!(parse_in_project(&[&project_arg, "profile", "--sample=S2"]).is_err());
+    }
+
+    #[test]
+    fn project_files_only_hold_project_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join(PROJECT_FILE),
+            "catalog = \"loci.json\"\noutput_dir = \"out\"\n",
+        )
+        .unwrap();
+        let project = Project::open(dir.path()).unwrap();
+        assert_eq!(project.catalog, dir.path().join("loci.json"));
+        assert_eq!(project.output_dir, dir.path().join("out"));
+        assert_eq!(project.manifest, dir.path().join("manifest.tsv"));
+
+        fs::write(dir.path().join(PROJECT_FILE), "threads = 4\n").unwrap();
+        assert!(Project::open(dir.path()).is_err());
+        fs::write(dir.path().join(PROJECT_FILE), "catalog = 4\n").unwrap();
+        assert!(Project::open(dir.path()).is_err());
+    }
+
+    #[test]
+    fn runs_are_appended_to_the_run_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let args: Vec<OsString> = ["strif", "merge", "--", "m.tsv"]
+            .map(OsString::from)
+            .to_vec();
+        record_run(dir.path(), &args, true).unwrap();
+        record_run(dir.path(), &args, false).unwrap();
+        let runs: Vec<serde_json::Value> = fs::read_to_string(dir.path().join(RUNS_FILE))
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0]["args"], json!(["merge", "--", "m.tsv"]));
+        assert_eq!(runs[0]["status"], "completed");
+        assert_eq!(runs[1]["status"], "failed");
+    }
+}
@@ -0,0 +1,156 @@
+use std::ffi::OsString;
+use std::sync::Mutex;
+
+use chrono::SecondsFormat;
+use sha2::{Digest, Sha256};
+
+/// What produced the outputs of this process, written to the top of each
+/// output as comment lines
+struct Provenance {
+    command_line: String,
+    /// The arguments of the command line without the program
+    args: Vec<String>,
+    started: String,
+    catalog_sha256: Option<String>,
+}
+
+/// The provenance of this process, or `None` if it should not be recorded,
+/// e.g. when strif is used as a library
+static PROVENANCE: Mutex<Option<Provenance>> = Mutex::new(None);
+
+/// Records the command line of this process and the time it started, which
+/// turns on the provenance lines of its outputs
+pub fn record_command(args: &[OsString]) {
+    let command_line = args
+        .iter()
+        .map(|arg| quote(&arg.to_string_lossy()))
+        .collect::<Vec<String>>()
+        .join(" ");
+    let args = args
+        .iter()
+        .skip(1)
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    *PROVENANCE.lock().unwrap() = Some(Provenance {
+        command_line,
+        args,
+        started: now(),
+        catalog_sha256: None,
+    });
+}
+
+/// Records the SHA-256 checksum of the STR catalog the outputs were made with
+pub fn record_catalog(contents: &[u8]) {
+    if let Some(provenance) = PROVENANCE.lock().unwrap().as_mut() {
+        provenance.catalog_sha256 = Some(sha256_hex(contents));
+    }
+}
+
+/// The provenance fields of an output written now, in the order they are
+/// written. Empty if provenance is not recorded.
+pub fn fields() -> Vec<(&'static str, String)> {
+    let provenance = PROVENANCE.lock().unwrap();
+    let provenance = match provenance.as_ref() {
+        Some(provenance) => provenance,
+        None => return Vec::new(),
+    };
+    let mut fields = vec![
+        ("strif_version", env!("CARGO_PKG_VERSION").to_string()),
+        ("strif_command", provenance.command_line.clone()),
+    ];
+    if let Some(checksum) = &provenance.catalog_sha256 {
+        fields.push(("strif_catalog_sha256", checksum.clone()));
+    }
+    fields.push(("strif_started", provenance.started.clone()));
+    fields.push(("strif_written", now()));
+    fields
+}
+
+/// The arguments of the command line, or none if provenance is not recorded
+pub fn args() -> Vec<String> {
+    PROVENANCE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map_or_else(Vec::new, |provenance| provenance.args.clone())
+}
+
+/// The hexadecimal SHA-256 checksum of some bytes
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn now() -> String {
+    chrono::Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Quotes an argument for a POSIX shell if needed, so that the command line
+/// can be rerun as written. Line breaks are escaped to keep it on one line.
+fn quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=,+@%".contains(c));
+    let arg = arg.replace('\n', "\\n").replace('\r', "\\r");
+    if plain {
+        arg
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::{read_merged_profile_from, write_merged_profile};
+    use crate::profile::{read_profile_from, write_profile};
+    use crate::testing::{merged_record, profile_record};
+
+    /// Inserts provenance lines after the schema line and header row of an output
+    fn with_provenance(output: &[u8]) -> String {
+        let output = String::from_utf8(output.to_vec()).unwrap();
+        let mut lines: Vec<&str> = output.lines().collect();
+        lines.insert(2, "#strif_version=0.1.0");
+        lines.insert(3, "#strif_command=strif profile 'sample 1.repeat_seqs.tsv'");
+        lines.join("\n") + "\n"
+    }
+
+    #[test]
+    fn readers_skip_provenance_lines() {
+        let records = [profile_record("HTT", 3, &[("CAT", 40, 2.0)])];
+        let mut profile = Vec::new();
+        write_profile(&mut profile, &records).unwrap();
+        let read = read_profile_from(with_provenance(&profile).as_bytes(), "profile").unwrap();
+        let mut rewritten = Vec::new();
+        write_profile(&mut rewritten, &read).unwrap();
+        assert_eq!(rewritten, profile);
+
+        let records = [merged_record("HTT", &[("s1", 3)], &[("s1", "CAT", 0.5)])];
+        let mut merged = Vec::new();
+        write_merged_profile(&mut merged, &records).unwrap();
+        let read = read_merged_profile_from(with_provenance(&merged).as_bytes(), "merged").unwrap();
+        let mut rewritten = Vec::new();
+        write_merged_profile(&mut rewritten, &read).unwrap();
+        assert_eq!(rewritten, merged);
+    }
+
+    #[test]
+    fn command_lines_are_quoted_for_a_shell() {
+        assert_eq!(quote("--min-read-count=5"), "--min-read-count=5");
+        assert_eq!(quote("sample 1.bam"), "'sample 1.bam'");
+        assert_eq!(quote("it's"), "'it'\\''s'");
+        assert_eq!(quote("a\nb"), "'a\\nb'");
+        assert_eq!(quote(""), "''");
+    }
+
+    #[test]
+    fn checksums_are_hexadecimal_sha256() {
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
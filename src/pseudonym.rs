@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use log::info;
+use rand::seq::SliceRandom;
+
+use crate::error::field;
+use crate::schema::{self, OutputKind};
+use crate::utils::{create_output, open_maybe_gzipped};
+
+/// The prefix of generated sample codes
+const CODE_PREFIX: &str = "P";
+
+/// The codes that replace sample IDs in shared outputs, kept in a key file
+/// mapping each code back to its sample
+pub struct Pseudonyms {
+    codes: HashMap<String, String>,
+}
+
+impl Pseudonyms {
+    /// Loads the key file if it exists and gives every sample without a code
+    /// the next one, in a random order so that codes do not reveal the order
+    /// of the manifest. The key is rewritten if samples were added, so that
+    /// reruns and shards of a cohort share the same codes.
+    pub fn load_or_create(key_path: &Path, sample_ids: &[&str]) -> Result<Self> {
+        let mut codes = match key_path.exists() {
+            true => load_key(key_path)?,
+            false => HashMap::new(),
+        };
+        let mut next = codes
+            .values()
+            .filter_map(|code| code.strip_prefix(CODE_PREFIX)?.parse::<usize>().ok())
+            .max()
+            .unwrap_or(0);
+        let mut new_samples: Vec<&str> = sample_ids
+            .iter()
+            .copied()
+            .filter(|sample_id| !codes.contains_key(*sample_id))
+            .collect();
+        if new_samples.is_empty() {
+            info!("Using the sample codes of {}", key_path.display());
+            return Ok(Pseudonyms { codes });
+        }
+        new_samples.shuffle(&mut rand::thread_rng());
+        for sample_id in new_samples {
+            next += 1;
+            codes.insert(sample_id.to_string(), format!("{}{:04}", CODE_PREFIX, next));
+        }
+
+        let mut rows: Vec<(&String, &String)> = codes.iter().collect();
+        rows.sort_by(|a, b| a.1.len().cmp(&b.1.len()).then(a.1.cmp(b.1)));
+        let mut out = create_output(key_path)?;
+        schema::write_header(&mut out, OutputKind::PseudonymKey)?;
+        for (sample_id, code) in rows {
+            writeln!(out, "{}\t{}", code, sample_id)?;
+        }
+        out.finish()?;
+        info!("Wrote the sample codes to {}", key_path.display());
+        Ok(Pseudonyms { codes })
+    }
+
+    /// The code of a sample, which has one once the key is loaded
+    pub fn code<'a>(&'a self, sample_id: &'a str) -> &'a str {
+        self.codes.get(sample_id).map_or(sample_id, String::as_str)
+    }
+}
+
+/// Loads the sample ID of each code of a key file
+fn load_key(key_path: &Path) -> Result<HashMap<String, String>> {
+    let source = key_path.display().to_string();
+    let file =
+        open_maybe_gzipped(key_path).with_context(|| format!("Could not open {}", source))?;
+    let mut reader = schema::tsv_reader(file, OutputKind::PseudonymKey, &source)?;
+    let mut codes: HashMap<String, String> = HashMap::new();
+    let mut used: HashMap<String, String> = HashMap::new();
+    for result in reader.records() {
+        let record = result?;
+        let code = field(&record, 0, &source)?.to_string();
+        let sample_id = field(&record, 1, &source)?.to_string();
+        if let Some(other) = used.insert(code.clone(), sample_id.clone()) {
+            bail!(
+                "{} gives the code {} to both {} and {}",
+                source,
+                code,
+                other,
+                sample_id
+            );
+        }
+        codes.insert(sample_id, code);
+    }
+    Ok(codes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn samples_keep_their_codes_and_new_samples_get_the_next_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = dir.path().join("pseudonyms.tsv");
+        let first = Pseudonyms::load_or_create(&key, &["s1", "s2"]).unwrap();
+        let mut codes = [first.code("s1"), first.code("s2")];
+        codes.sort();
+        assert_eq!(codes, ["P0001", "P0002"]);
+
+        let second = Pseudonyms::load_or_create(&key, &["s2", "s3", "s1"]).unwrap();
+        assert_eq!(second.code("s1"), first.code("s1"));
+        assert_eq!(second.code("s2"), first.code("s2"));
+        assert_eq!(second.code("s3"), "P0003");
+        // samples outside the key keep their IDs
+        assert_eq!(second.code("s4"), "s4");
+    }
+
+    #[test]
+    fn keys_giving_a_code_to_two_samples_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = dir.path().join("pseudonyms.tsv");
+        let mut out = Vec::new();
+        schema::write_header(&mut out, OutputKind::PseudonymKey).unwrap();
+        out.extend_from_slice(b"P0001\ts1\nP0001\ts2\n");
+        fs::write(&key, out).unwrap();
+        let error = Pseudonyms::load_or_create(&key, &["s1"]).err().unwrap();
+        assert!(error.to_string().contains("both s1 and s2"));
+    }
+}
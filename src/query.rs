@@ -0,0 +1,316 @@
+use std::io::{self, prelude::*, BufWriter};
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+
+use crate::convert::{read_merged_profile_any, Format};
+use crate::index::ProfileIndex;
+use crate::merge::{parse_merged_record, MergedProfileRecord};
+use crate::plan::Plan;
+use crate::schema::{self, OutputKind};
+use crate::utils::{open_maybe_gzipped, Region};
+
+/// How `query` prints the matching records
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum QueryFormat {
+    /// A block per locus with a line per sample
+    Pretty,
+    /// The rows of a merged profile, with its schema line and header row
+    Tsv,
+    /// One JSON object per locus, as written by `convert`
+    Jsonl,
+}
+
+/// The loci and samples to look up. A locus must match every kind of
+/// criterion given, and any of the values given for each.
+pub struct QueryParams {
+    pub locus_ids: Vec<String>,
+    pub regions: Vec<Region>,
+    pub motifs: Vec<String>,
+    /// Only the counts of these samples are printed, and loci without any of
+    /// them are skipped
+    pub samples: Vec<String>,
+    pub format: QueryFormat,
+}
+
+impl QueryParams {
+    fn matches_locus(&self, locus_id: &str, reference_region: &str, motif: &str) -> bool {
+        if !self.locus_ids.is_empty() && !self.locus_ids.iter().any(|id| id == locus_id) {
+            return false;
+        }
+        if !self.motifs.is_empty() && !self.motifs.iter().any(|m| m.eq_ignore_ascii_case(motif)) {
+            return false;
+        }
+        if !self.regions.is_empty() {
+            return reference_region
+                .parse::<Region>()
+                .is_ok_and(|region| self.regions.iter().any(|r| r.overlaps(&region)));
+        }
+        true
+    }
+
+    /// Drops the counts of other samples, returning false if none are left
+    fn restrict_samples(&self, record: &mut MergedProfileRecord) -> bool {
+        if self.samples.is_empty() {
+            return true;
+        }
+        record
+            .read_counts
+            .retain(|(sample_id, _)| self.samples.contains(sample_id));
+        record
+            .interruption_counts
+            .retain(|(sample_id, _, _)| self.samples.contains(sample_id));
+        !record.read_counts.is_empty()
+    }
+}
+
+/// Prints the loci of a merged profile matching a query. Merged profiles in
+/// TSV format are streamed, and the count columns are only parsed for rows
+/// whose locus matches, so that a lookup does not load the whole file.
+/// Lookups by locus or region in a profile indexed by `index` only read the
+/// blocks holding the matching rows.
+pub fn query(input: PathBuf, params: QueryParams) -> Result<()> {
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    if params.format == QueryFormat::Tsv {
+        schema::write_header(&mut out, OutputKind::MergedProfile)?;
+    }
+
+    let (mut matched, mut total) = (0, 0);
+    let mut print = |record: &MergedProfileRecord| -> Result<()> {
+        matched += 1;
+        match params.format {
+            QueryFormat::Pretty => write_pretty(&mut out, record),
+            QueryFormat::Tsv => record.write_to(&mut out),
+            QueryFormat::Jsonl => Ok(writeln!(out, "{}", record.to_json())?),
+        }
+    };
+
+    // an index only helps lookups by locus or region, other queries scan the file
+    let index = if params.locus_ids.is_empty() && params.regions.is_empty() {
+        None
+    } else {
+        ProfileIndex::open(&input)?
+    };
+    match (index, Format::from_path(&input)) {
+        (Some(index), _) => {
+            total = index.entries.len();
+            let indices: Vec<usize> = index
+                .entries
+                .iter()
+                .enumerate()
+                .filter(|(_, entry)| {
+                    params.matches_locus(&entry.locus_id, &entry.reference_region, &entry.motif)
+                })
+                .map(|(i, _)| i)
+                .collect();
+            index.read_records(&indices, |mut record| {
+                if params.restrict_samples(&mut record) {
+                    print(&record)?;
+                }
+                Ok(())
+            })?;
+        }
+        (None, Some(Format::Tsv) | None) => {
+            let source = input.display().to_string();
+            let file =
+                open_maybe_gzipped(&input).with_context(|| format!("Could not open {}", source))?;
+            let mut reader = schema::tsv_reader(file, OutputKind::MergedProfile, &source)?;
+            for row in reader.records() {
+                let row = row?;
+                total += 1;
+                let (locus_id, reference_region, motif) = (
+                    row.get(0).unwrap_or_default(),
+                    row.get(1).unwrap_or_default(),
+                    row.get(2).unwrap_or_default(),
+                );
+                if !params.matches_locus(locus_id, reference_region, motif) {
+                    continue;
+                }
+                let mut record = parse_merged_record(&row, &source)?;
+                if params.restrict_samples(&mut record) {
+                    print(&record)?;
+                }
+            }
+        }
+        (None, Some(_)) => {
+            for mut record in read_merged_profile_any(&input)? {
+                total += 1;
+                if params.matches_locus(&record.locus_id, &record.reference_region, &record.motif)
+                    && params.restrict_samples(&mut record)
+                {
+                    print(&record)?;
+                }
+            }
+        }
+    }
+    out.flush()?;
+
+    if matched == 0 {
+        warn!("None of the {} loci match the query", total);
+    } else {
+        info!("{} of {} loci match the query", matched, total);
+    }
+    Ok(())
+}
+
+/// Writes a record as a block with a line per sample and its interruptions in
+/// order of decreasing count
+fn write_pretty<W: Write>(out: &mut W, record: &MergedProfileRecord) -> Result<()> {
+    writeln!(
+        out,
+        "{}\t{}\t{}",
+        record.locus_id, record.reference_region, record.motif
+    )?;
+    let width = record
+        .read_counts
+        .iter()
+        .map(|(sample_id, _)| sample_id.len())
+        .max()
+        .unwrap_or(0);
+    for (sample_id, read_count) in &record.read_counts {
+        let mut interruptions: Vec<(&str, f64)> = record
+            .interruption_counts
+            .iter()
+            .filter(|(id, _, _)| id == sample_id)
+            .map(|(_, interruption, count)| (interruption.as_str(), *count))
+            .collect();
+        interruptions.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        let interruptions: Vec<String> = interruptions
+            .iter()
+            .map(|(interruption, count)| format!("{}={:.4}", interruption, count))
+            .collect();
+        writeln!(
+            out,
+            "  {:width$}  {:>6} reads  {}",
+            sample_id,
+            read_count,
+            if interruptions.is_empty() {
+                "no interruptions".to_string()
+            } else {
+                interruptions.join(", ")
+            },
+            width = width
+        )?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// The command line arguments of `strif query`
+#[derive(Debug, clap::Args)]
+pub struct QueryArgs {
+    /// The path to the merged profile, in any format supported by `convert`
+    pub input: PathBuf,
+
+    /// Only print this locus. Can be repeated.
+    #[clap(short = 'l', long)]
+    pub locus: Vec<String>,
+
+    /// Only print loci overlapping this region (chrom:start-end). Can be repeated.
+    #[clap(short = 'r', long)]
+    pub region: Vec<Region>,
+
+    /// Only print loci with this motif. Can be repeated.
+    #[clap(long)]
+    pub motif: Vec<String>,
+
+    /// Only print the counts of this sample, skipping loci without it. Can be repeated.
+    #[clap(short = 's', long)]
+    pub sample: Vec<String>,
+
+    /// How to print the matching loci
+    #[clap(long, value_enum, default_value = "pretty")]
+    pub format: QueryFormat,
+}
+
+impl QueryArgs {
+    /// Runs `strif query`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let QueryArgs {
+            input,
+            locus,
+            region,
+            motif,
+            sample,
+            format,
+        } = self;
+        Plan::new("query", dry_run, force)
+            .input("merged profile", &input)
+            .execute(|| {
+                let params = QueryParams {
+                    locus_ids: locus,
+                    regions: region,
+                    motifs: motif,
+                    samples: sample,
+                    format,
+                };
+                query(input, params)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::merged_record;
+
+    fn params() -> QueryParams {
+        QueryParams {
+            locus_ids: Vec::new(),
+            regions: Vec::new(),
+            motifs: Vec::new(),
+            samples: Vec::new(),
+            format: QueryFormat::Pretty,
+        }
+    }
+
+    #[test]
+    fn loci_match_every_kind_of_criterion_and_any_of_its_values() {
+        assert!(params().matches_locus("HTT", "chr4:100-200", "CAG"));
+        let params = QueryParams {
+            locus_ids: vec!["HTT".to_string(), "FMR1".to_string()],
+            regions: vec!["chr4:150-300".parse().unwrap()],
+            motifs: vec!["cag".to_string()],
+            ..params()
+        };
+        assert!(params.matches_locus("HTT", "chr4:100-200", "CAG"));
+        assert!(!params.matches_locus("ATXN1", "chr4:100-200", "CAG"));
+        assert!(!params.matches_locus("HTT", "chr4:300-400", "CAG"));
+        assert!(!params.matches_locus("HTT", "chr4:100-200", "CGG"));
+    }
+
+    #[test]
+    fn loci_without_the_samples_of_the_query_are_skipped() {
+        let params = QueryParams {
+            samples: vec!["s2".to_string()],
+            ..params()
+        };
+        let mut record = merged_record(
+            "HTT",
+            &[("s1", 10), ("s2", 20)],
+            &[("s1", "A", 0.1), ("s2", "T", 0.2)],
+        );
+        assert!(params.restrict_samples(&mut record));
+        assert_eq!(record.read_counts, [("s2".to_string(), 20)]);
+        assert_eq!(record.interruption_counts.len(), 1);
+        let mut record = merged_record("HTT", &[("s1", 10)], &[]);
+        assert!(!params.restrict_samples(&mut record));
+    }
+
+    #[test]
+    fn pretty_blocks_list_interruptions_by_decreasing_count() {
+        let record = merged_record(
+            "HTT",
+            &[("s1", 10), ("sample2", 20)],
+            &[("s1", "A", 0.1), ("s1", "T", 0.2)],
+        );
+        let mut out = Vec::new();
+        write_pretty(&mut out, &record).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "HTT\tchr1:100-200\tCAG\n  s1           10 reads  T=0.2000, A=0.1000\n  sample2      20 reads  no interruptions\n\n"
+        );
+    }
+}
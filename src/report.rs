@@ -0,0 +1,544 @@
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+use log::info;
+
+use crate::error::{field, parse_field};
+use crate::merge::{load_manifest, read_merged_profile, MergedProfileRecord};
+use crate::plan::Plan;
+use crate::plot::{draw_burden, draw_spectrum, spectrum_bars, svg_string, HEIGHT, WIDTH};
+use crate::schema::{self, OutputKind};
+use crate::stats::quantile;
+use crate::utils::{create_output, get_default_out_path};
+
+const MAX_SAMPLE_INTERRUPTIONS: usize = 5;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em auto; max-width: 1100px; color: #222; }
+h1, h2, h3 { color: #1a3d6d; }
+table { border-collapse: collapse; margin: 1em 0; font-size: 0.9em; }
+th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: left; }
+th { background: #eef2f7; }
+tr.flagged td { background: #fdecea; }
+.locus { border-top: 2px solid #1a3d6d; margin-top: 2em; }
+.summary td:first-child { font-weight: bold; }
+svg { max-width: 100%; height: auto; }
+";
+
+/// An association result for a locus, from the output of `test`
+pub(crate) struct Association {
+    pub(crate) interruption: String,
+    pub(crate) effect: String,
+    pub(crate) p_value: f64,
+    pub(crate) adjusted_p_value: String,
+}
+
+/// Per-sample quality control metrics
+#[derive(Default)]
+struct SampleQc {
+    loci: usize,
+    reads: u64,
+    burden: f64,
+}
+
+/// Renders a merged profile into a standalone HTML report
+pub fn report(
+    merged_profile: PathBuf,
+    out_path: PathBuf,
+    manifest: Option<PathBuf>,
+    association: Option<PathBuf>,
+    top: usize,
+    title: String,
+) -> Result<()> {
+    info!("Loading merged profile...");
+    let records = read_merged_profile(&merged_profile)?;
+    if records.is_empty() {
+        bail!("{} has no loci to report", merged_profile.display());
+    }
+    let statuses: HashMap<String, String> = match &manifest {
+        Some(manifest) => load_manifest(manifest)?
+            .into_iter()
+            .map(|entry| (entry.sample_id, entry.status))
+            .collect(),
+        None => HashMap::new(),
+    };
+    let associations = match &association {
+        Some(association) => Some(load_associations(association)?),
+        None => None,
+    };
+
+    info!("Rendering report...");
+    let mut html = String::new();
+    writeln!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>",
+        escape(&title),
+        STYLE
+    )?;
+    writeln!(html, "<h1>{}</h1>", escape(&title))?;
+
+    let burdens = sample_burdens_by_locus(&records);
+    let qc = sample_qc(&records);
+    write_summary(&mut html, &merged_profile, &records, &qc)?;
+    write_sample_qc(&mut html, &qc, &statuses)?;
+
+    let ranked = rank_loci(&records, &burdens, associations.as_ref());
+    write_top_loci(&mut html, &records, &ranked, associations.as_ref(), top)?;
+
+    writeln!(html, "<h2 id=\"loci\">Locus details</h2>")?;
+    for &i in ranked.iter().take(top) {
+        write_locus(&mut html, &records[i], &burdens[i], &statuses)?;
+    }
+
+    writeln!(
+        html,
+        "<p><small>Generated by strif {}</small></p>\n</body>\n</html>",
+        env!("CARGO_PKG_VERSION")
+    )?;
+    let mut out_file = create_output(&out_path)?;
+    out_file.write_all(html.as_bytes())?;
+    out_file.finish()?;
+
+    info!("Done!");
+    Ok(())
+}
+
+/// Keeps the most significant test of each locus
+pub(crate) fn load_associations(path: &Path) -> Result<HashMap<String, Association>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(true)
+        .comment(Some(b'#'))
+        .from_path(path)?;
+    let headers = reader.headers()?.clone();
+    schema::validate(path, &headers, OutputKind::Association)?;
+    let source = path.display();
+
+    let mut associations: HashMap<String, Association> = HashMap::new();
+    for result in reader.records() {
+        let record = result?;
+        let p_value: f64 = parse_field(&record, 9, "p-value", &source)?;
+        let locus_id = field(&record, 0, &source)?.to_string();
+        if associations
+            .get(&locus_id)
+            .map(|a| a.p_value <= p_value)
+            .unwrap_or(false)
+        {
+            continue;
+        }
+        associations.insert(
+            locus_id,
+            Association {
+                interruption: field(&record, 3, &source)?.to_string(),
+                effect: field(&record, 6, &source)?.to_string(),
+                p_value,
+                adjusted_p_value: field(&record, 10, &source)?.to_string(),
+            },
+        );
+    }
+    Ok(associations)
+}
+
+/// Total normalized interruption count of each sample at each locus
+fn sample_burdens_by_locus(records: &[MergedProfileRecord]) -> Vec<HashMap<&str, f64>> {
+    records
+        .iter()
+        .map(|record| {
+            let mut burdens: HashMap<&str, f64> = HashMap::new();
+            for (sample_id, _, count) in &record.interruption_counts {
+                *burdens.entry(sample_id.as_str()).or_default() += count;
+            }
+            burdens
+        })
+        .collect()
+}
+
+fn sample_qc(records: &[MergedProfileRecord]) -> BTreeMap<String, SampleQc> {
+    let mut qc: BTreeMap<String, SampleQc> = BTreeMap::new();
+    for record in records {
+        for (sample_id, count) in &record.read_counts {
+            let sample = qc.entry(sample_id.clone()).or_default();
+            sample.loci += 1;
+            sample.reads += *count as u64;
+        }
+        for (sample_id, _, count) in &record.interruption_counts {
+            qc.entry(sample_id.clone()).or_default().burden += count;
+        }
+    }
+    qc
+}
+
+/// Orders loci by association p-value if available, otherwise by decreasing
+/// mean burden per sample
+fn rank_loci(
+    records: &[MergedProfileRecord],
+    burdens: &[HashMap<&str, f64>],
+    associations: Option<&HashMap<String, Association>>,
+) -> Vec<usize> {
+    let mean_burden = |i: usize| -> f64 {
+        let n = records[i].read_counts.len().max(1) as f64;
+        burdens[i].values().sum::<f64>() / n
+    };
+    let mut ranked: Vec<usize> = (0..records.len()).collect();
+    match associations {
+        Some(associations) => {
+            let p_value = |i: usize| -> f64 {
+                associations
+                    .get(&records[i].locus_id)
+                    .map(|a| a.p_value)
+                    .unwrap_or(f64::INFINITY)
+            };
+            ranked.sort_by(|&a, &b| {
+                p_value(a)
+                    .total_cmp(&p_value(b))
+                    .then(records[a].locus_id.cmp(&records[b].locus_id))
+            });
+        }
+        None => ranked.sort_by(|&a, &b| {
+            mean_burden(b)
+                .total_cmp(&mean_burden(a))
+                .then(records[a].locus_id.cmp(&records[b].locus_id))
+        }),
+    }
+    ranked
+}
+
+fn write_summary(
+    html: &mut String,
+    merged_profile: &Path,
+    records: &[MergedProfileRecord],
+    qc: &BTreeMap<String, SampleQc>,
+) -> Result<()> {
+    let loci_with_interruptions = records
+        .iter()
+        .filter(|r| !r.interruption_counts.is_empty())
+        .count();
+    writeln!(html, "<h2>Summary</h2>\n<table class=\"summary\">")?;
+    for (label, value) in [
+        ("Merged profile", merged_profile.display().to_string()),
+        ("Loci", records.len().to_string()),
+        (
+            "Loci with interruptions",
+            loci_with_interruptions.to_string(),
+        ),
+        ("Samples", qc.len().to_string()),
+    ] {
+        writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td></tr>",
+            label,
+            escape(&value)
+        )?;
+    }
+    writeln!(html, "</table>")?;
+    Ok(())
+}
+
+/// Flags samples with an outlying burden (above Q3 + 3 IQR) or fewer than
+/// half the median number of covered loci
+fn write_sample_qc(
+    html: &mut String,
+    qc: &BTreeMap<String, SampleQc>,
+    statuses: &HashMap<String, String>,
+) -> Result<()> {
+    let mut burdens: Vec<f64> = qc.values().map(|s| s.burden).collect();
+    burdens.sort_by(|a, b| a.total_cmp(b));
+    let mut loci: Vec<f64> = qc.values().map(|s| s.loci as f64).collect();
+    loci.sort_by(|a, b| a.total_cmp(b));
+    let (q1, q3) = (quantile(&burdens, 0.25), quantile(&burdens, 0.75));
+    let max_burden = q3 + 3.0 * (q3 - q1);
+    let min_loci = quantile(&loci, 0.5) / 2.0;
+
+    writeln!(html, "<h2 id=\"samples\">Sample QC</h2>")?;
+    let svg = svg_string((WIDTH, HEIGHT), |root| draw_burden(root, &burdens))?;
+    writeln!(html, "{}", svg)?;
+    writeln!(
+        html,
+        "<table>\n<tr><th>Sample</th><th>Status</th><th>Loci</th><th>Reads</th><th>Mean reads per locus</th><th>Total burden</th><th>Flags</th></tr>"
+    )?;
+    for (sample_id, sample) in qc {
+        let mut flags: Vec<&str> = Vec::new();
+        if sample.burden > max_burden {
+            flags.push("high burden");
+        }
+        if (sample.loci as f64) < min_loci {
+            flags.push("low coverage");
+        }
+        writeln!(
+            html,
+            "<tr{}><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.1}</td><td>{:.4}</td><td>{}</td></tr>",
+            if flags.is_empty() { "" } else { " class=\"flagged\"" },
+            escape(sample_id),
+            escape(statuses.get(sample_id).map(|s| s.as_str()).unwrap_or("")),
+            sample.loci,
+            sample.reads,
+            sample.reads as f64 / sample.loci.max(1) as f64,
+            sample.burden,
+            flags.join(", ")
+        )?;
+    }
+    writeln!(html, "</table>")?;
+    Ok(())
+}
+
+fn write_top_loci(
+    html: &mut String,
+    records: &[MergedProfileRecord],
+    ranked: &[usize],
+    associations: Option<&HashMap<String, Association>>,
+    top: usize,
+) -> Result<()> {
+    writeln!(html, "<h2 id=\"top\">Top loci</h2>\n<table>")?;
+    write!(
+        html,
+        "<tr><th>Locus</th><th>Region</th><th>Motif</th><th>Samples</th><th>Samples with interruptions</th><th>Mean burden</th>"
+    )?;
+    if associations.is_some() {
+        write!(
+            html,
+            "<th>Top interruption</th><th>Effect</th><th>P-value</th><th>Adjusted p-value</th>"
+        )?;
+    }
+    writeln!(html, "</tr>")?;
+
+    for &i in ranked.iter().take(top) {
+        let record = &records[i];
+        let mut carriers: Vec<&str> = record
+            .interruption_counts
+            .iter()
+            .map(|(sample_id, _, _)| sample_id.as_str())
+            .collect();
+        carriers.sort_unstable();
+        carriers.dedup();
+        let burden: f64 = record.interruption_counts.iter().map(|(_, _, c)| c).sum();
+        write!(
+            html,
+            "<tr><td><a href=\"#{}\">{}</a></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.6}</td>",
+            anchor(&record.locus_id),
+            escape(&record.locus_id),
+            escape(&record.reference_region),
+            escape(&record.motif),
+            record.read_counts.len(),
+            carriers.len(),
+            burden / record.read_counts.len().max(1) as f64
+        )?;
+        if let Some(associations) = associations {
+            match associations.get(&record.locus_id) {
+                Some(a) => write!(
+                    html,
+                    "<td>{}</td><td>{}</td><td>{:.3e}</td><td>{}</td>",
+                    escape(&a.interruption),
+                    escape(&a.effect),
+                    a.p_value,
+                    escape(&a.adjusted_p_value)
+                )?,
+                None => write!(html, "<td></td><td></td><td></td><td></td>")?,
+            }
+        }
+        writeln!(html, "</tr>")?;
+    }
+    writeln!(html, "</table>")?;
+    Ok(())
+}
+
+fn write_locus(
+    html: &mut String,
+    record: &MergedProfileRecord,
+    burdens: &HashMap<&str, f64>,
+    statuses: &HashMap<String, String>,
+) -> Result<()> {
+    writeln!(
+        html,
+        "<div class=\"locus\" id=\"{}\">\n<h3>{}</h3>\n<p>{} &middot; motif {} &middot; {} samples</p>",
+        anchor(&record.locus_id),
+        escape(&record.locus_id),
+        escape(&record.reference_region),
+        escape(&record.motif),
+        record.read_counts.len()
+    )?;
+
+    let mut totals: HashMap<String, f64> = HashMap::new();
+    let mut sample_interruptions: HashMap<&str, Vec<(&str, f64)>> = HashMap::new();
+    for (sample_id, interruption, count) in &record.interruption_counts {
+        *totals.entry(interruption.clone()).or_default() += count;
+        sample_interruptions
+            .entry(sample_id)
+            .or_default()
+            .push((interruption, *count));
+    }
+    let bars = spectrum_bars(totals);
+    if bars.is_empty() {
+        writeln!(html, "<p>No interruptions were observed at this locus.</p>")?;
+    } else {
+        let svg = svg_string((WIDTH, HEIGHT), |root| {
+            draw_spectrum(
+                root,
+                &record.locus_id,
+                &bars,
+                "Normalized interruption count (summed over samples)",
+            )
+        })?;
+        writeln!(html, "{}", svg)?;
+    }
+
+    writeln!(
+        html,
+        "<table>\n<tr><th>Sample</th><th>Status</th><th>Reads</th><th>Burden</th><th>Top interruptions</th></tr>"
+    )?;
+    for (sample_id, read_count) in &record.read_counts {
+        let mut interruptions = sample_interruptions
+            .remove(sample_id.as_str())
+            .unwrap_or_default();
+        interruptions.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(b.0)));
+        let top_interruptions: Vec<String> = interruptions
+            .iter()
+            .take(MAX_SAMPLE_INTERRUPTIONS)
+            .map(|(interruption, count)| format!("{} ({:.4})", escape(interruption), count))
+            .collect();
+        writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{:.6}</td><td>{}</td></tr>",
+            escape(sample_id),
+            escape(statuses.get(sample_id).map(|s| s.as_str()).unwrap_or("")),
+            read_count,
+            burdens.get(sample_id.as_str()).unwrap_or(&0.0),
+            top_interruptions.join(", ")
+        )?;
+    }
+    writeln!(
+        html,
+        "</table>\n<p><a href=\"#top\">Back to top loci</a></p>\n</div>"
+    )?;
+    Ok(())
+}
+
+fn anchor(locus_id: &str) -> String {
+    let id: String = locus_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("locus-{}", id)
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The command line arguments of `strif report`
+#[derive(Debug, clap::Args)]
+pub struct ReportArgs {
+    /// The path to the merged profile
+    pub merged_profile: PathBuf,
+
+    /// The path to the HTML report. Defaults to the same directory as the merged profile.
+    pub output: Option<PathBuf>,
+
+    /// The path to the manifest, used to show the case-control status of samples
+    #[clap(short = 'm', long)]
+    pub manifest: Option<PathBuf>,
+
+    /// The path to the output of `test`, used to rank loci by p-value
+    #[clap(short = 'a', long)]
+    pub association: Option<PathBuf>,
+
+    /// The number of top loci to list and detail
+    #[clap(short = 'n', long, default_value = "50")]
+    pub top: usize,
+
+    /// The title of the report
+    #[clap(short = 't', long, default_value = "strif cohort report")]
+    pub title: String,
+}
+
+impl ReportArgs {
+    /// Runs `strif report`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let ReportArgs {
+            merged_profile,
+            output,
+            manifest,
+            association,
+            top,
+            title,
+        } = self;
+        let out_path: PathBuf =
+            output.unwrap_or_else(|| get_default_out_path(&merged_profile, "report", "html"));
+        Plan::new("report", dry_run, force)
+            .merged_profile("merged profile", &merged_profile)
+            .maybe_input("manifest", manifest.as_deref())
+            .maybe_input("association results", association.as_deref())
+            .output("report", &out_path)
+            .execute(|| report(merged_profile, out_path, manifest, association, top, title))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::testing::{merged_record, write_merged_profile_file};
+
+    fn records() -> Vec<MergedProfileRecord> {
+        vec![
+            merged_record("L1", &[("s1", 10), ("s2", 10)], &[("s1", "CAT", 0.2)]),
+            merged_record("L2", &[("s1", 10)], &[("s1", "CAT", 0.15)]),
+            merged_record("L3", &[("s1", 10), ("s2", 10)], &[]),
+        ]
+    }
+
+    fn association(p_value: f64) -> Association {
+        Association {
+            interruption: ".".to_string(),
+            effect: "1".to_string(),
+            p_value,
+            adjusted_p_value: p_value.to_string(),
+        }
+    }
+
+    #[test]
+    fn loci_are_ranked_by_p_value_or_mean_burden() {
+        let records = records();
+        let burdens = sample_burdens_by_locus(&records);
+        // the burden of L1 is spread over two samples
+        assert_eq!(rank_loci(&records, &burdens, None), [1, 0, 2]);
+        let associations = HashMap::from([
+            ("L3".to_string(), association(0.01)),
+            ("L1".to_string(), association(0.2)),
+        ]);
+        assert_eq!(
+            rank_loci(&records, &burdens, Some(&associations)),
+            [2, 0, 1]
+        );
+    }
+
+    #[test]
+    fn reports_detail_the_top_loci() {
+        let dir = tempfile::tempdir().unwrap();
+        let merged_profile = write_merged_profile_file(dir.path(), "merged.tsv", &records());
+        let out = dir.path().join("report.html");
+        report(
+            merged_profile,
+            out.clone(),
+            None,
+            None,
+            2,
+            "<Cohort>".to_string(),
+        )
+        .unwrap();
+        let html = fs::read_to_string(out).unwrap();
+        assert!(html.contains("<h1>&lt;Cohort&gt;</h1>"));
+        assert!(html.contains(&format!("id=\"{}\"", anchor("L2"))));
+        assert!(html.contains(&format!("id=\"{}\"", anchor("L1"))));
+        assert!(!html.contains(&format!("id=\"{}\"", anchor("L3"))));
+        assert!(html.contains("<tr><td>Loci with interruptions</td><td>2</td></tr>"));
+    }
+}
@@ -0,0 +1,447 @@
+use std::io::prelude::*;
+use std::{
+    fs::{self, File},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use anyhow::{bail, Ok, Result};
+use log::{debug, error, info};
+
+use crate::extract::{extract, ExtractOptions};
+use crate::merge::{load_manifest, merge, ManifestEntry, MergeOptions};
+use crate::plan::Plan;
+use crate::profile::{profile, ProfileOptions};
+use crate::threads;
+use crate::utils::{create_output, get_default_out_path, remove_stale_temps, OutputFormat};
+
+pub struct RunParams {
+    /// How each sample is profiled. The per-read outputs it names are
+    /// written once per sample, see [`RunParams::sample_profile_options`].
+    pub profile: ProfileOptions,
+    /// How the profiles are merged
+    pub merge: MergeOptions,
+    /// The number of samples processed in parallel
+    pub jobs: usize,
+    /// Skip steps whose outputs were completed by a previous run
+    pub resume: bool,
+}
+
+impl RunParams {
+    /// Checks that the profiles can be merged, as `merge` reads TSV profiles
+    pub fn validate(&self) -> Result<()> {
+        if self.profile.output_format != OutputFormat::Tsv {
+            bail!("Profiles are merged from TSV files, so --output-format must be tsv");
+        }
+        Ok(())
+    }
+
+    /// The profile options of a sample, whose per-read outputs are written
+    /// to the output directory with the sample ID before their file name,
+    /// e.g. `HG001.rejects.tsv` for `--rejects rejects.tsv`
+    pub fn sample_profile_options(&self, sample_id: &str, out_dir: &Path) -> ProfileOptions {
+        let per_sample = |path: &Option<PathBuf>| {
+            path.as_ref().map(|path| {
+                let file_name = path.file_name().unwrap_or(path.as_os_str());
+                out_dir.join(format!("{}.{}", sample_id, file_name.to_string_lossy()))
+            })
+        };
+        ProfileOptions {
+            output_alignments: per_sample(&self.profile.output_alignments),
+            read_interruptions: per_sample(&self.profile.read_interruptions),
+            rejects: per_sample(&self.profile.rejects),
+            motif_usage: per_sample(&self.profile.motif_usage),
+            ..self.profile.clone()
+        }
+    }
+}
+
+/// Runs extract and profile for every sample in the manifest and merges the
+/// resulting profiles. The manifest has the same columns as the one used by
+/// `merge`, but the third column is the path to each sample's BAMlet.
+pub fn run(
+    manifest: PathBuf,
+    str_catalog: PathBuf,
+    read_depths: PathBuf,
+    out_dir: PathBuf,
+    params: RunParams,
+) -> Result<()> {
+    params.validate()?;
+    let samples: Vec<ManifestEntry> = load_manifest(&manifest)?;
+    fs::create_dir_all(&out_dir)?;
+    let stale_temps = remove_stale_temps(&out_dir)?;
+    if stale_temps > 0 {
+        info!(
+            "Removed {} temporary file(s) left by a previous run",
+            stale_temps
+        );
+    }
+    let jobs = params.jobs.max(1).min(samples.len().max(1));
+    let total_threads = threads::threads();
+    info!(
+        "Running pipeline for {} samples with {} parallel job(s) and {} thread(s)...",
+        samples.len(),
+        jobs,
+        total_threads
+    );
+
+    // the parallel jobs share the threads
+    threads::set_threads(NonZeroUsize::new(total_threads / jobs).unwrap_or(NonZeroUsize::MIN));
+    let next_sample = AtomicUsize::new(0);
+    let failures: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| loop {
+                let i = next_sample.fetch_add(1, Ordering::SeqCst);
+                let sample = match samples.get(i) {
+                    Some(sample) => sample,
+                    None => break,
+                };
+                if let Err(e) = run_sample(sample, &str_catalog, &out_dir, &params) {
+                    error!("[{}] {:#}", sample.sample_id, e);
+                    failures.lock().unwrap().push(sample.sample_id.clone());
+                }
+            });
+        }
+    });
+    threads::set_threads(NonZeroUsize::new(total_threads).unwrap_or(NonZeroUsize::MIN));
+    let failures = failures.into_inner().unwrap();
+    if !failures.is_empty() {
+        bail!(
+            "{} sample(s) failed: {}. Rerun to resume from the failed steps.",
+            failures.len(),
+            failures.join(", ")
+        );
+    }
+
+    let profile_manifest = write_profile_manifest(&samples, &out_dir)?;
+
+    let merged_path = out_dir.join("merged_profile.tsv");
+    run_step("all", "merge", &merged_path, params.resume, || {
+        merge_profiles(&profile_manifest, &read_depths, &merged_path, &params)
+    })?;
+
+    info!(
+        "Pipeline finished, merged profile written to {}",
+        merged_path.display()
+    );
+
+    Ok(())
+}
+
+/// Merges the profiles of a manifest written by `write_profile_manifest`
+pub(crate) fn merge_profiles(
+    profile_manifest: &Path,
+    read_depths: &Path,
+    merged_path: &Path,
+    params: &RunParams,
+) -> Result<()> {
+    merge(
+        profile_manifest.to_path_buf(),
+        read_depths.to_path_buf(),
+        merged_path.to_path_buf(),
+        &params
+            .merge
+            .clone()
+            .skip_report(get_default_out_path(merged_path, "merge_skips", "tsv")),
+    )
+}
+
+pub(crate) fn run_sample(
+    sample: &ManifestEntry,
+    str_catalog: &Path,
+    out_dir: &Path,
+    params: &RunParams,
+) -> Result<()> {
+    let sample_id = &sample.sample_id;
+    let repeat_seqs_path = repeat_seqs_path(out_dir, sample_id);
+
+    run_step(
+        sample_id,
+        "extract",
+        &repeat_seqs_path,
+        params.resume,
+        || {
+            extract(
+                sample.path.clone(),
+                repeat_seqs_path.clone(),
+                &ExtractOptions::default(),
+            )
+        },
+    )?;
+    profile_sample(sample_id, &repeat_seqs_path, str_catalog, out_dir, params)
+}
+
+/// Profiles the repeat sequences of a sample into the output directory
+pub(crate) fn profile_sample(
+    sample_id: &str,
+    repeat_seqs_path: &Path,
+    str_catalog: &Path,
+    out_dir: &Path,
+    params: &RunParams,
+) -> Result<()> {
+    let profile_path = profile_path(out_dir, sample_id);
+    run_step(sample_id, "profile", &profile_path, params.resume, || {
+        profile(
+            repeat_seqs_path.to_path_buf(),
+            str_catalog.to_path_buf(),
+            profile_path.clone(),
+            &params.sample_profile_options(sample_id, out_dir),
+        )
+    })
+}
+
+pub(crate) fn repeat_seqs_path(out_dir: &Path, sample_id: &str) -> PathBuf {
+    out_dir.join(format!("{}.repeat_seqs.tsv", sample_id))
+}
+
+pub(crate) fn profile_path(out_dir: &Path, sample_id: &str) -> PathBuf {
+    out_dir.join(format!("{}.strif_profile.tsv", sample_id))
+}
+
+/// Writes the manifest of the profiles of the samples in an output
+/// directory, which is used by merge
+pub(crate) fn write_profile_manifest(samples: &[ManifestEntry], out_dir: &Path) -> Result<PathBuf> {
+    let profile_manifest = out_dir.join("profiles.manifest.tsv");
+    let mut manifest_file = create_output(&profile_manifest)?;
+    for sample in samples {
+        writeln!(
+            manifest_file,
+            "{}\t{}\t{}",
+            sample.sample_id,
+            sample.status,
+            profile_path(out_dir, &sample.sample_id).display()
+        )?;
+    }
+    manifest_file.finish()?;
+    Ok(profile_manifest)
+}
+
+/// Runs a pipeline step unless a previous run completed it. A step is only
+/// considered complete once its marker file exists, so outputs of steps that
+/// were interrupted are regenerated.
+fn run_step<F>(sample_id: &str, step: &str, output: &Path, resume: bool, f: F) -> Result<()>
+where
+    F: FnOnce() -> Result<()>,
+{
+    let mut marker = output.as_os_str().to_owned();
+    marker.push(".done");
+    let marker = PathBuf::from(marker);
+
+    if resume && marker.exists() && output.exists() {
+        info!(
+            sample = sample_id, step, status = "skipped";
+            "[{}] Skipping {}, already completed", sample_id, step
+        );
+        return Ok(());
+    }
+    if marker.exists() {
+        fs::remove_file(&marker)?;
+    }
+
+    info!(sample = sample_id, step, status = "started"; "[{}] Running {}...", sample_id, step);
+    f()?;
+    File::create(&marker)?;
+    debug!(sample = sample_id, step, status = "completed"; "[{}] Completed {}", sample_id, step);
+    Ok(())
+}
+
+/// The command line arguments of `strif run`
+#[derive(Debug, clap::Args)]
+pub struct RunArgs {
+    /// The path to a manifest with columns: sample ID, case-control status and BAMlet path
+    #[clap(short = 'm', long)]
+    pub manifest: PathBuf,
+
+    /// The path to a JSON file containing the catalog of repeat loci
+    #[clap(short = 'c', long = "catalog")]
+    pub str_catalog: PathBuf,
+
+    /// The path to a TSV file containing the global average read depth for each sample
+    #[clap(short = 'd', long)]
+    pub read_depths: PathBuf,
+
+    /// The directory to write all outputs to
+    #[clap(short = 'o', long, default_value = "strif_output")]
+    pub output_dir: PathBuf,
+
+    /// The number of samples to process in parallel
+    #[clap(short = 'j', long, default_value = "1")]
+    pub jobs: usize,
+
+    /// Rerun every step instead of resuming from completed steps
+    #[clap(long, action)]
+    pub no_resume: bool,
+
+    /// Minimum read count to include in the merged profile.
+    #[clap(long, default_value = "1")]
+    pub min_read_count: u32,
+
+    /// The sequencing read length. Used for normalizing the interruption counts.
+    #[clap(short = 'l', long, default_value = "150")]
+    pub read_length: u32,
+
+    // the filter also applies to the merge, and the per-read outputs are
+    // written per sample to the output directory
+    #[clap(flatten)]
+    pub options: ProfileOptions,
+}
+
+impl RunArgs {
+    /// Runs `strif run`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let RunArgs {
+            manifest,
+            str_catalog,
+            read_depths,
+            output_dir,
+            jobs,
+            no_resume,
+            min_read_count,
+            read_length,
+            options,
+        } = self;
+        let params = RunParams {
+            merge: MergeOptions {
+                filter: options.filter.clone(),
+                min_read_count,
+                read_length,
+                ..MergeOptions::default()
+            },
+            profile: options,
+            jobs,
+            resume: !no_resume,
+        };
+        Plan::new("run", dry_run, force)
+            .run(&manifest, &str_catalog, &read_depths, &output_dir, &params)
+            .execute(|| run(manifest, str_catalog, read_depths, output_dir, params))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::merge::read_merged_profile;
+    use crate::testing::write_catalog;
+
+    fn params(profile: ProfileOptions) -> RunParams {
+        RunParams {
+            profile,
+            merge: MergeOptions::default(),
+            jobs: 1,
+            resume: true,
+        }
+    }
+
+    #[test]
+    fn per_read_outputs_are_written_per_sample() {
+        let params = params(
+            ProfileOptions::default()
+                .rejects("qc/rejects.tsv")
+                .motif_usage("motif_usage.tsv")
+                .preset(crate::presets::Preset::Vntr),
+        );
+        let options = params.sample_profile_options("HG001", Path::new("out"));
+        assert_eq!(
+            options.rejects,
+            Some(PathBuf::from("out/HG001.rejects.tsv"))
+        );
+        assert_eq!(
+            options.motif_usage,
+            Some(PathBuf::from("out/HG001.motif_usage.tsv"))
+        );
+        assert_eq!(options.read_interruptions, None);
+        assert_eq!(options.preset, params.profile.preset);
+    }
+
+    #[test]
+    fn profiles_must_be_tsv_to_be_merged() {
+        assert!(params(ProfileOptions::default()).validate().is_ok());
+        let jsonl = ProfileOptions::default().output_format(OutputFormat::Jsonl);
+        assert!(params(jsonl).validate().is_err());
+    }
+
+    #[test]
+    fn completed_steps_are_skipped_when_resuming() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("step.tsv");
+        let runs = Cell::new(0);
+        let step = |resume| {
+            run_step("s1", "step", &output, resume, || {
+                runs.set(runs.get() + 1);
+                fs::write(&output, "")?;
+                Ok(())
+            })
+        };
+        step(true).unwrap();
+        step(true).unwrap();
+        assert_eq!(runs.get(), 1);
+        step(false).unwrap();
+        assert_eq!(runs.get(), 2);
+
+        // a step interrupted before its marker was written is rerun
+        fs::remove_file(dir.path().join("step.tsv.done")).unwrap();
+        step(true).unwrap();
+        assert_eq!(runs.get(), 3);
+    }
+
+    #[test]
+    fn sample_profiles_are_merged_across_the_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let catalog = write_catalog(dir.path());
+        let out_dir = dir.path().join("out");
+        fs::create_dir(&out_dir).unwrap();
+        let samples: Vec<ManifestEntry> = [("s1", "case"), ("s2", "control")]
+            .into_iter()
+            .map(|(sample_id, status)| ManifestEntry {
+                sample_id: sample_id.to_string(),
+                status: status.to_string(),
+                path: dir.path().join(format!("{}.bam", sample_id)),
+            })
+            .collect();
+        let params = params(ProfileOptions::default());
+        for (sample, reads) in samples.iter().zip([
+            "HTT	CAGCAGCAGCAGCATCAGCAGCAGCAG
+HTT	CAGCAGCAGCAGCAGCAGCAGCAG
+",
+            "HTT	CAGCAGCAGCAGCAGCAGCAGCAG
+FMR1	CGGCGGCGGCGGCGGCGG
+",
+        ]) {
+            let repeat_seqs = repeat_seqs_path(&out_dir, &sample.sample_id);
+            fs::write(&repeat_seqs, reads).unwrap();
+            profile_sample(&sample.sample_id, &repeat_seqs, &catalog, &out_dir, &params).unwrap();
+            assert!(profile_path(&out_dir, &sample.sample_id).exists());
+        }
+
+        let profile_manifest = write_profile_manifest(&samples, &out_dir).unwrap();
+        let read_depths = dir.path().join("read_depths.tsv");
+        fs::write(&read_depths, "s1\t1\ns2\t1\n").unwrap();
+        let merged_path = out_dir.join("merged_profile.tsv");
+        merge_profiles(&profile_manifest, &read_depths, &merged_path, &params).unwrap();
+        assert!(out_dir.join("merged_profile.merge_skips.tsv").exists());
+
+        let records = read_merged_profile(&merged_path).unwrap();
+        let loci: Vec<(&str, &[(String, u32)])> = records
+            .iter()
+            .map(|record| (record.locus_id.as_str(), &record.read_counts[..]))
+            .collect();
+        assert_eq!(
+            loci,
+            [
+                ("FMR1", &[("s2".to_string(), 1)][..]),
+                ("HTT", &[("s1".to_string(), 2), ("s2".to_string(), 1)][..]),
+            ]
+        );
+        let htt = &records[1];
+        assert_eq!(htt.interruption_counts.len(), 1);
+        assert_eq!(&htt.interruption_counts[0].0, "s1");
+    }
+}
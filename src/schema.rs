@@ -0,0 +1,763 @@
+use std::io::{prelude::*, BufReader, Chain, Cursor};
+use std::path::Path;
+
+use anyhow::{bail, Context, Ok, Result};
+
+use crate::provenance;
+use crate::utils::open_maybe_gzipped;
+
+/// The current version of the strif output schemas. Bump this whenever the
+/// columns of any output change.
+pub const SCHEMA_VERSION: u32 = 8;
+
+/// The schema version assumed for files written before schema lines were
+/// introduced
+const LEGACY_SCHEMA_VERSION: u32 = 1;
+
+const SCHEMA_LINE_PREFIX: &str = "#strif_schema=";
+
+/// The kinds of tabular output produced by strif
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputKind {
+    RepeatSeqs,
+    Profile,
+    MergedProfile,
+    Calls,
+    Comparison,
+    AnnotatedProfile,
+    AnnotatedMergedProfile,
+    Association,
+    Outlier,
+    GeneBurden,
+    FeatureDictionary,
+    LiftoverMapping,
+    Benchmark,
+    ReadInterruptions,
+    DeNovoCandidates,
+    PseudonymKey,
+    RejectedReads,
+    ProfileRejects,
+    MergeSkips,
+    PcaVariance,
+    SampleSimilarity,
+    MotifUsage,
+}
+
+impl OutputKind {
+    /// All output kinds, used when detecting the kind of a file
+    pub const ALL: [OutputKind; 22] = [
+        OutputKind::RepeatSeqs,
+        OutputKind::Profile,
+        OutputKind::MergedProfile,
+        OutputKind::Calls,
+        OutputKind::Comparison,
+        OutputKind::AnnotatedProfile,
+        OutputKind::AnnotatedMergedProfile,
+        OutputKind::Association,
+        OutputKind::Outlier,
+        OutputKind::GeneBurden,
+        OutputKind::FeatureDictionary,
+        OutputKind::LiftoverMapping,
+        OutputKind::Benchmark,
+        OutputKind::ReadInterruptions,
+        OutputKind::DeNovoCandidates,
+        OutputKind::PseudonymKey,
+        OutputKind::RejectedReads,
+        OutputKind::ProfileRejects,
+        OutputKind::MergeSkips,
+        OutputKind::PcaVariance,
+        OutputKind::SampleSimilarity,
+        OutputKind::MotifUsage,
+    ];
+
+    pub fn from_name(name: &str) -> Option<OutputKind> {
+        OutputKind::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputKind::RepeatSeqs => "repeat_seqs",
+            OutputKind::Profile => "profile",
+            OutputKind::MergedProfile => "merged_profile",
+            OutputKind::Calls => "calls",
+            OutputKind::Comparison => "comparison",
+            OutputKind::AnnotatedProfile => "annotated_profile",
+            OutputKind::AnnotatedMergedProfile => "annotated_merged_profile",
+            OutputKind::Association => "association",
+            OutputKind::Outlier => "outlier",
+            OutputKind::GeneBurden => "gene_burden",
+            OutputKind::FeatureDictionary => "feature_dictionary",
+            OutputKind::LiftoverMapping => "liftover_mapping",
+            OutputKind::Benchmark => "benchmark",
+            OutputKind::ReadInterruptions => "read_interruptions",
+            OutputKind::DeNovoCandidates => "de_novo_candidates",
+            OutputKind::PseudonymKey => "pseudonym_key",
+            OutputKind::RejectedReads => "rejected_reads",
+            OutputKind::ProfileRejects => "profile_rejects",
+            OutputKind::MergeSkips => "merge_skips",
+            OutputKind::PcaVariance => "pca_variance",
+            OutputKind::SampleSimilarity => "sample_similarity",
+            OutputKind::MotifUsage => "motif_usage",
+        }
+    }
+
+    /// The expected header columns, or `None` if the output has no header row
+    pub fn columns(&self) -> Option<&'static [&'static str]> {
+        match self {
+            OutputKind::RepeatSeqs => None,
+            OutputKind::Profile => Some(&[
+                "locus_id",
+                "reference_region",
+                "motif",
+                "read_count",
+                "interruption_counts",
+                "mean_purity",
+                "median_purity",
+                "chrom",
+                "start",
+                "end",
+                "mean_confidence",
+                "max_confidence",
+                "median_copy_number",
+            ]),
+            OutputKind::MergedProfile => Some(&[
+                "locus_id",
+                "reference_region",
+                "motif",
+                "read_counts",
+                "interruption_counts",
+            ]),
+            OutputKind::Calls => Some(&[
+                "locus_id",
+                "reference_region",
+                "motif",
+                "read_count",
+                "interruption",
+                "supporting_reads",
+                "allele_fraction",
+                "genotype",
+                "quality",
+                "filter",
+                "allele_lengths",
+            ]),
+            OutputKind::Comparison => Some(&[
+                "locus_id",
+                "reference_region",
+                "motif",
+                "read_count_a",
+                "read_count_b",
+                "read_count_rel_diff",
+                "max_rate_diff",
+                "interruption_rate_diffs",
+                "status",
+            ]),
+            OutputKind::AnnotatedProfile => Some(&[
+                "locus_id",
+                "reference_region",
+                "motif",
+                "read_count",
+                "interruption_counts",
+                "mean_purity",
+                "median_purity",
+                "chrom",
+                "start",
+                "end",
+                "mean_confidence",
+                "max_confidence",
+                "median_copy_number",
+                "gene",
+                "context",
+                "disease",
+            ]),
+            OutputKind::AnnotatedMergedProfile => Some(&[
+                "locus_id",
+                "reference_region",
+                "motif",
+                "read_counts",
+                "interruption_counts",
+                "gene",
+                "context",
+                "disease",
+            ]),
+            OutputKind::Association => Some(&[
+                "locus_id",
+                "reference_region",
+                "motif",
+                "interruption",
+                "model",
+                "n_samples",
+                "effect",
+                "std_error",
+                "statistic",
+                "p_value",
+                "adjusted_p_value",
+                "empirical_p_value",
+                "fwer_p_value",
+                "comparison",
+                "group_sizes",
+                "group_means",
+            ]),
+            OutputKind::Outlier => Some(&[
+                "locus_id",
+                "reference_region",
+                "motif",
+                "read_count",
+                "burden",
+                "background_samples",
+                "background_median",
+                "background_mad",
+                "z_score",
+                "empirical_p",
+                "top_interruption",
+            ]),
+            OutputKind::GeneBurden => Some(&[
+                "group",
+                "loci",
+                "n_cases",
+                "n_controls",
+                "mean_burden_cases",
+                "mean_burden_controls",
+                "effect",
+                "statistic",
+                "p_value",
+                "adjusted_p_value",
+            ]),
+            OutputKind::FeatureDictionary => Some(&[
+                "index",
+                "feature",
+                "kind",
+                "locus_id",
+                "interruption",
+                "description",
+            ]),
+            OutputKind::LiftoverMapping => Some(&[
+                "locus_id",
+                "reference_region",
+                "lifted_locus_id",
+                "lifted_region",
+                "strand",
+                "mapped_fraction",
+                "status",
+            ]),
+            OutputKind::Benchmark => Some(&[
+                "match_score",
+                "mismatch_penalty",
+                "gap_open_penalty",
+                "gap_extend_penalty",
+                "reads",
+                "interrupted_reads",
+                "sensitivity",
+                "false_positive_rate",
+                "reads_per_second",
+            ]),
+            OutputKind::ReadInterruptions => Some(&[
+                "locus_id",
+                "read",
+                "repeat_len",
+                "interruption",
+                "start",
+                "end",
+                "mean_quality",
+                "min_quality",
+                "confidence",
+                "copy_number",
+                "left_flank_trimmed",
+                "right_flank_trimmed",
+            ]),
+            OutputKind::DeNovoCandidates => Some(&[
+                "family_id",
+                "proband",
+                "father",
+                "mother",
+                "locus_id",
+                "reference_region",
+                "motif",
+                "interruption",
+                "supporting_reads",
+                "read_count",
+                "allele_fraction",
+                "father_read_count",
+                "mother_read_count",
+            ]),
+            OutputKind::PseudonymKey => Some(&["code", "sample_id"]),
+            OutputKind::RejectedReads => Some(&["read", "read_name", "reason", "locus_id"]),
+            OutputKind::ProfileRejects => Some(&[
+                "locus_id",
+                "read",
+                "reads",
+                "repeat_len",
+                "reason",
+                "purity",
+                "anchor_score",
+            ]),
+            OutputKind::MergeSkips => Some(&[
+                "sample_id",
+                "rows",
+                "merged",
+                "filtered",
+                "other_shard",
+                "below_min_read_count",
+                "parse_failure",
+                "invalid_repeat_len",
+            ]),
+            OutputKind::PcaVariance => Some(&[
+                "component",
+                "variance",
+                "variance_explained",
+                "cumulative_variance_explained",
+            ]),
+            OutputKind::SampleSimilarity => Some(&[
+                "sample_id",
+                "nearest_sample",
+                "correlation",
+                "mean_correlation",
+            ]),
+            OutputKind::MotifUsage => Some(&["locus_id", "motif", "read_count", "fraction"]),
+        }
+    }
+
+    /// The columns of the output in a schema version, so that files written
+    /// by earlier releases can still be read
+    pub fn columns_in_version(&self, version: u32) -> Option<&'static [&'static str]> {
+        match (self, version) {
+            // version 1 had no purity columns
+            (OutputKind::Profile, 0..=1) => Some(&[
+                "locus_id",
+                "reference_region",
+                "motif",
+                "read_count",
+                "interruption_counts",
+            ]),
+            (OutputKind::AnnotatedProfile, 0..=1) => Some(&[
+                "locus_id",
+                "reference_region",
+                "motif",
+                "read_count",
+                "interruption_counts",
+                "gene",
+                "context",
+                "disease",
+            ]),
+            // version 2 had no coordinate columns
+            (OutputKind::Profile, 2) => Some(&[
+                "locus_id",
+                "reference_region",
+                "motif",
+                "read_count",
+                "interruption_counts",
+                "mean_purity",
+                "median_purity",
+            ]),
+            (OutputKind::AnnotatedProfile, 2) => Some(&[
+                "locus_id",
+                "reference_region",
+                "motif",
+                "read_count",
+                "interruption_counts",
+                "mean_purity",
+                "median_purity",
+                "gene",
+                "context",
+                "disease",
+            ]),
+            // version 3 had no confidence columns
+            (OutputKind::Profile, 3) => Some(&[
+                "locus_id",
+                "reference_region",
+                "motif",
+                "read_count",
+                "interruption_counts",
+                "mean_purity",
+                "median_purity",
+                "chrom",
+                "start",
+                "end",
+            ]),
+            (OutputKind::AnnotatedProfile, 3) => Some(&[
+                "locus_id",
+                "reference_region",
+                "motif",
+                "read_count",
+                "interruption_counts",
+                "mean_purity",
+                "median_purity",
+                "chrom",
+                "start",
+                "end",
+                "gene",
+                "context",
+                "disease",
+            ]),
+            (OutputKind::ReadInterruptions, 0..=3) => Some(&[
+                "locus_id",
+                "read",
+                "repeat_len",
+                "interruption",
+                "start",
+                "end",
+                "mean_quality",
+                "min_quality",
+            ]),
+            // version 4 had no permutation columns
+            (OutputKind::Association, 0..=4) => Some(&[
+                "locus_id",
+                "reference_region",
+                "motif",
+                "interruption",
+                "model",
+                "n_samples",
+                "effect",
+                "std_error",
+                "statistic",
+                "p_value",
+                "adjusted_p_value",
+            ]),
+            // version 5 had no copy number columns
+            (OutputKind::Profile, 4..=5) => Some(&[
+                "locus_id",
+                "reference_region",
+                "motif",
+                "read_count",
+                "interruption_counts",
+                "mean_purity",
+                "median_purity",
+                "chrom",
+                "start",
+                "end",
+                "mean_confidence",
+                "max_confidence",
+            ]),
+            (OutputKind::AnnotatedProfile, 4..=5) => Some(&[
+                "locus_id",
+                "reference_region",
+                "motif",
+                "read_count",
+                "interruption_counts",
+                "mean_purity",
+                "median_purity",
+                "chrom",
+                "start",
+                "end",
+                "mean_confidence",
+                "max_confidence",
+                "gene",
+                "context",
+                "disease",
+            ]),
+            (OutputKind::ReadInterruptions, 4..=5) => Some(&[
+                "locus_id",
+                "read",
+                "repeat_len",
+                "interruption",
+                "start",
+                "end",
+                "mean_quality",
+                "min_quality",
+                "confidence",
+            ]),
+            // version 6 had no flank trimming columns
+            (OutputKind::ReadInterruptions, 6) => Some(&[
+                "locus_id",
+                "read",
+                "repeat_len",
+                "interruption",
+                "start",
+                "end",
+                "mean_quality",
+                "min_quality",
+                "confidence",
+                "copy_number",
+            ]),
+            // version 7 had no group columns
+            (OutputKind::Association, 5..=7) => Some(&[
+                "locus_id",
+                "reference_region",
+                "motif",
+                "interruption",
+                "model",
+                "n_samples",
+                "effect",
+                "std_error",
+                "statistic",
+                "p_value",
+                "adjusted_p_value",
+                "empirical_p_value",
+                "fwer_p_value",
+            ]),
+            _ => self.columns(),
+        }
+    }
+}
+
+/// Writes the schema/version line, the header row (if any) and the
+/// provenance lines of this process. The provenance lines come last so that
+/// the header row stays the second line, e.g. for `tabix -S 2`.
+pub fn write_header<W: Write>(out: &mut W, kind: OutputKind) -> Result<()> {
+    writeln!(
+        out,
+        "{}{};version={}",
+        SCHEMA_LINE_PREFIX,
+        kind.name(),
+        SCHEMA_VERSION
+    )?;
+    if let Some(columns) = kind.columns() {
+        writeln!(out, "{}", columns.join("\t"))?;
+    }
+    for (key, value) in provenance::fields() {
+        writeln!(out, "#{}={}", key, value)?;
+    }
+    Ok(())
+}
+
+/// Reads the schema line at the top of a file. Returns `None` for files
+/// written before schema lines were introduced.
+pub fn read_schema(path: &Path) -> Result<Option<(String, u32)>> {
+    let mut first_line = String::new();
+    open_maybe_gzipped(path)?.read_line(&mut first_line)?;
+    parse_schema_line(&first_line, &path.display().to_string())
+}
+
+/// The schema version of a file, from its schema line or, for files written
+/// before schema lines were introduced, the legacy version
+pub fn schema_version(path: &Path) -> Result<u32> {
+    Ok(read_schema(path)?.map_or(LEGACY_SCHEMA_VERSION, |(_, version)| version))
+}
+
+/// Parses a schema line. Returns `None` if the line is not a schema line.
+pub fn parse_schema_line(line: &str, source: &str) -> Result<Option<(String, u32)>> {
+    let schema = match line.trim_end().strip_prefix(SCHEMA_LINE_PREFIX) {
+        Some(schema) => schema,
+        None => return Ok(None),
+    };
+    let (kind, version) = match schema.split_once(";version=") {
+        Some((kind, version)) => (kind, version),
+        None => bail!("Malformed schema line in {}: '{}'", source, line.trim_end()),
+    };
+    let version: u32 = version
+        .parse()
+        .with_context(|| format!("Malformed schema version in {}", source))?;
+    Ok(Some((kind.to_string(), version)))
+}
+
+/// Detects the kind of a strif output from its schema line or, for files
+/// written before schema lines were introduced, from its header row
+pub fn detect(path: &Path) -> Result<OutputKind> {
+    if let Some((name, _)) = read_schema(path)? {
+        return match OutputKind::from_name(&name) {
+            Some(kind) => Ok(kind),
+            None => bail!("{} has an unknown schema '{}'", path.display(), name),
+        };
+    }
+
+    let mut first_line = String::new();
+    for line in open_maybe_gzipped(path)?.lines() {
+        let line = line?;
+        if !line.starts_with('#') {
+            first_line = line;
+            break;
+        }
+    }
+    let fields: Vec<&str> = first_line.split('\t').collect();
+    for kind in OutputKind::ALL {
+        if kind.columns_in_version(LEGACY_SCHEMA_VERSION) == Some(&fields[..]) {
+            return Ok(kind);
+        }
+    }
+    // repeat sequences have no header row, a third column if extracted with
+    // base qualities, a fourth if collapsed by `dedup`, a fifth and sixth if
+    // extracted with anchoring scores and a seventh with read names
+    if matches!(fields.len(), 2..=4 | 6..=7) {
+        return Ok(OutputKind::RepeatSeqs);
+    }
+    bail!(
+        "Could not detect the kind of strif output in {}",
+        path.display()
+    )
+}
+
+/// Checks that the schema line (if present) and the header row of a file
+/// match the expected output kind
+pub fn validate(path: &Path, headers: &csv::StringRecord, kind: OutputKind) -> Result<()> {
+    let source = path.display().to_string();
+    let mut version = LEGACY_SCHEMA_VERSION;
+    if let Some((found_kind, found_version)) = read_schema(path)? {
+        check_schema(&found_kind, found_version, kind, &source)?;
+        version = found_version;
+    }
+    validate_columns(headers, kind, version, &source)
+}
+
+/// Checks that a schema read from a file or stream matches the expected output kind
+pub fn check_schema(found_kind: &str, version: u32, kind: OutputKind, source: &str) -> Result<()> {
+    if found_kind != kind.name() {
+        bail!(
+            "{} is a '{}' file, expected a '{}' file",
+            source,
+            found_kind,
+            kind.name()
+        );
+    }
+    if version > SCHEMA_VERSION {
+        bail!(
+            "{} has schema version {}, but this version of strif only supports up to version {}",
+            source,
+            version,
+            SCHEMA_VERSION
+        );
+    }
+    Ok(())
+}
+
+/// Checks that a header row matches the columns of the expected output kind
+/// in the schema version of the file
+pub fn validate_columns(
+    headers: &csv::StringRecord,
+    kind: OutputKind,
+    version: u32,
+    source: &str,
+) -> Result<()> {
+    let expected = match kind.columns_in_version(version) {
+        Some(expected) => expected,
+        None => return Ok(()),
+    };
+    for (i, column) in expected.iter().enumerate() {
+        match headers.get(i) {
+            Some(found) if found == *column => {}
+            Some(found) => bail!(
+                "{} has an unexpected column '{}' at position {}, expected '{}'",
+                source,
+                found,
+                i + 1,
+                column
+            ),
+            None => bail!(
+                "{} is missing the '{}' column at position {}",
+                source,
+                column,
+                i + 1
+            ),
+        }
+    }
+    if headers.len() > expected.len() {
+        bail!(
+            "{} has an unexpected extra column '{}' at position {}",
+            source,
+            headers.get(expected.len()).unwrap(),
+            expected.len() + 1
+        );
+    }
+    Ok(())
+}
+
+/// A TSV reader over a stream whose first line was already read
+pub type TsvReader<R> = csv::Reader<Chain<Cursor<String>, BufReader<R>>>;
+
+/// Opens a TSV stream of the expected output kind, checking its schema line
+/// (if present) and header row
+pub fn tsv_reader<R: Read>(reader: R, kind: OutputKind, source: &str) -> Result<TsvReader<R>> {
+    let mut reader = BufReader::new(reader);
+    let mut first_line = String::new();
+    reader.read_line(&mut first_line)?;
+    let mut version = LEGACY_SCHEMA_VERSION;
+    if let Some((found_kind, found_version)) = parse_schema_line(&first_line, source)? {
+        check_schema(&found_kind, found_version, kind, source)?;
+        version = found_version;
+    }
+    // put the first line back so that a legacy header row is not lost
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(true)
+        .comment(Some(b'#'))
+        .from_reader(Cursor::new(first_line).chain(reader));
+    let headers = reader.headers()?.clone();
+    validate_columns(&headers, kind, version, source)?;
+    Ok(reader)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn headers(columns: &[&str]) -> csv::StringRecord {
+        csv::StringRecord::from(columns.to_vec())
+    }
+
+    #[test]
+    fn written_headers_are_read_back() {
+        let mut out = Vec::new();
+        write_header(&mut out, OutputKind::MergedProfile).unwrap();
+        let mut reader = tsv_reader(&out[..], OutputKind::MergedProfile, "merged").unwrap();
+        assert_eq!(
+            reader.headers().unwrap(),
+            &headers(OutputKind::MergedProfile.columns().unwrap())
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("merged.tsv");
+        fs::write(&path, &out).unwrap();
+        assert_eq!(
+            read_schema(&path).unwrap(),
+            Some(("merged_profile".to_string(), SCHEMA_VERSION))
+        );
+        assert_eq!(detect(&path).unwrap(), OutputKind::MergedProfile);
+    }
+
+    #[test]
+    fn files_without_a_schema_line_are_legacy() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profile.tsv");
+        fs::write(
+            &path,
+            "locus_id\treference_region\tmotif\tread_count\tinterruption_counts\n\
+             HTT\tchr4:3074876-3074933\tCAG\t3\tCAA:1\n",
+        )
+        .unwrap();
+        assert_eq!(read_schema(&path).unwrap(), None);
+        assert_eq!(schema_version(&path).unwrap(), LEGACY_SCHEMA_VERSION);
+        assert_eq!(detect(&path).unwrap(), OutputKind::Profile);
+
+        // the legacy header row lacks the purity columns of the current schema
+        let legacy = fs::read(&path).unwrap();
+        let mut reader = tsv_reader(&legacy[..], OutputKind::Profile, "profile").unwrap();
+        assert_eq!(reader.records().count(), 1);
+    }
+
+    #[test]
+    fn mismatched_columns_are_rejected() {
+        let columns = OutputKind::Profile.columns().unwrap();
+        let mut renamed = columns.to_vec();
+        renamed[3] = "reads";
+        let error = validate_columns(&headers(&renamed), OutputKind::Profile, SCHEMA_VERSION, "p")
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "p has an unexpected column 'reads' at position 4, expected 'read_count'"
+        );
+
+        let missing = &columns[..columns.len() - 1];
+        let error = validate_columns(&headers(missing), OutputKind::Profile, SCHEMA_VERSION, "p")
+            .unwrap_err();
+        assert!(error.to_string().starts_with("p is missing the"));
+
+        let mut extra = columns.to_vec();
+        extra.push("notes");
+        let error = validate_columns(&headers(&extra), OutputKind::Profile, SCHEMA_VERSION, "p")
+            .unwrap_err();
+        assert!(error.to_string().contains("extra column 'notes'"));
+    }
+
+    #[test]
+    fn newer_schema_versions_and_other_kinds_are_rejected() {
+        let newer = format!("#strif_schema=profile;version={}\n", SCHEMA_VERSION + 1);
+        assert!(tsv_reader(newer.as_bytes(), OutputKind::Profile, "p").is_err());
+        let other = format!("#strif_schema=calls;version={}\n", SCHEMA_VERSION);
+        let error = tsv_reader(other.as_bytes(), OutputKind::Profile, "p").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "p is a 'calls' file, expected a 'profile' file"
+        );
+        assert!(parse_schema_line("#strif_schema=profile", "p").is_err());
+    }
+}
@@ -0,0 +1,171 @@
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use log::debug;
+
+use crate::utils::{ByteSize, TMP_SUFFIX};
+
+/// The directory set with `--tmp-dir`, or None to keep scratch files next to
+/// the output they are for
+static TMP_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// The scratch directories created by this process, which tells apart those
+/// created within the same second
+static CREATED: AtomicUsize = AtomicUsize::new(0);
+
+/// Free space left on the filesystem of a scratch directory beyond what a
+/// write needs, so that strif does not fill it up for other processes
+const MIN_FREE_SPACE: u64 = 64 * 1024 * 1024;
+
+/// Sets the directory that all subcommands create their scratch directories
+/// in, e.g. a fast local disk of a compute node
+pub fn set_tmp_dir(dir: PathBuf) {
+    *TMP_DIR.lock().unwrap() = Some(dir);
+}
+
+/// The directory set with `--tmp-dir`
+pub fn tmp_dir() -> Option<PathBuf> {
+    TMP_DIR.lock().unwrap().clone()
+}
+
+/// A directory of its own for the temporary files of one run, e.g. the parts
+/// `merge --max-memory` spills to disk. It is created in the directory set
+/// with `--tmp-dir`, or else next to the output it is for, and is removed
+/// with everything in it when dropped. Its name is hidden and ends in the
+/// suffix of temporary outputs, so that `strif run` and `strif watch` remove
+/// those that runs killed midway left behind.
+pub struct ScratchDir {
+    path: PathBuf,
+}
+
+impl ScratchDir {
+    /// Creates a scratch directory for the temporary files of an output
+    pub fn create(purpose: &str, out_path: &Path) -> Result<Self> {
+        let parent = match tmp_dir() {
+            Some(tmp_dir) => tmp_dir,
+            None => match out_path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                _ => PathBuf::from("."),
+            },
+        };
+        if !parent.is_dir() {
+            bail!(
+                "The temporary directory {} does not exist or is not a directory",
+                parent.display()
+            );
+        }
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let path = parent.join(format!(
+            ".strif-{}-{}-{}-{}{}",
+            purpose,
+            process::id(),
+            started,
+            CREATED.fetch_add(1, Ordering::Relaxed),
+            TMP_SUFFIX
+        ));
+        std::fs::create_dir(&path).with_context(|| {
+            format!("Could not create the scratch directory {}", path.display())
+        })?;
+        debug!("Created the scratch directory {}", path.display());
+        Ok(Self { path })
+    }
+
+    /// The path of a file in the scratch directory
+    pub fn file(&self, name: &str) -> PathBuf {
+        self.path.join(name)
+    }
+
+    /// The bytes free on the filesystem of the scratch directory, where they
+    /// can be told
+    pub fn free_space(&self) -> Option<u64> {
+        free_space(&self.path)
+    }
+
+    /// Fails if the filesystem of the scratch directory has less than about
+    /// `bytes` free, before a write that would otherwise fail midway. Where
+    /// the free space cannot be told, every write is let through.
+    pub fn check_space(&self, bytes: u64) -> Result<()> {
+        let Some(free) = self.free_space() else {
+            return Ok(());
+        };
+        let needed = bytes.saturating_add(MIN_FREE_SPACE);
+        if free < needed {
+            bail!(
+                "The filesystem of {} has {} free but ~{} is needed, pass --tmp-dir to use a directory with more space",
+                self.path.display(),
+                ByteSize(free),
+                ByteSize(needed)
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// The bytes free to unprivileged users on the filesystem of a path
+#[cfg(unix)]
+fn free_space(path: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stats = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: path is NUL-terminated and statvfs fills in stats on success
+    if unsafe { libc::statvfs(path.as_ptr(), stats.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    // SAFETY: statvfs returned 0, so it initialized stats
+    let stats = unsafe { stats.assume_init() };
+    #[allow(clippy::unnecessary_cast, clippy::useless_conversion)]
+    Some(u64::from(stats.f_bavail) * u64::from(stats.f_frsize))
+}
+
+#[cfg(not(unix))]
+fn free_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scratch_directories_are_unique_hidden_and_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let out_path = dir.path().join("merged.tsv");
+        let scratch = ScratchDir::create("merge", &out_path).unwrap();
+        let other = ScratchDir::create("merge", &out_path).unwrap();
+        assert_ne!(scratch.path, other.path);
+        assert_eq!(scratch.path.parent(), Some(dir.path()));
+        let name = scratch.path.file_name().unwrap().to_string_lossy();
+        assert!(name.starts_with(".strif-merge-") && name.ends_with(TMP_SUFFIX));
+
+        let part = scratch.file("part1.tsv");
+        std::fs::write(&part, "L1\n").unwrap();
+        let path = scratch.path.clone();
+        drop(scratch);
+        assert!(!path.exists());
+        assert!(other.path.is_dir());
+    }
+
+    #[test]
+    fn scratch_space_is_checked_before_writing() {
+        let dir = tempfile::tempdir().unwrap();
+        let scratch = ScratchDir::create("sort", &dir.path().join("out.tsv")).unwrap();
+        assert!(scratch.check_space(u64::MAX / 2).is_err());
+
+        let missing = dir.path().join("missing").join("out.tsv");
+        assert!(ScratchDir::create("sort", &missing).is_err());
+    }
+}
@@ -0,0 +1,548 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
+
+use anyhow::{anyhow, bail, Result};
+use log::{info, warn};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Response, Server};
+
+use crate::convert::read_merged_profile_any;
+use crate::index::{sample_summaries, ProfileIndex, SampleSummary};
+use crate::merge::MergedProfileRecord;
+use crate::plan::Plan;
+use crate::utils::Region;
+
+pub struct ServeParams {
+    pub host: String,
+    pub port: u16,
+    /// The largest number of loci returned by a single query
+    pub max_results: usize,
+}
+
+/// The regions of the loci on a chromosome
+#[derive(Default)]
+struct ChromIndex {
+    /// (start, end, record index) sorted by start
+    regions: Vec<(u64, u64, usize)>,
+    /// The length of the longest region, bounding how far back overlaps can start
+    max_len: u64,
+}
+
+/// Where the records of the served profile are kept
+enum Store {
+    /// Loaded into memory
+    Loaded(Vec<MergedProfileRecord>),
+    /// Read on demand from a bgzipped profile through its sidecar index
+    Indexed(ProfileIndex),
+}
+
+/// A merged profile indexed for queries
+struct Index {
+    store: Store,
+    len: usize,
+    by_locus_id: HashMap<String, usize>,
+    by_motif: HashMap<String, Vec<usize>>,
+    by_chrom: HashMap<String, ChromIndex>,
+    samples: BTreeMap<String, SampleSummary>,
+}
+
+/// Serves a read-only HTTP/JSON API to query a merged profile by locus,
+/// region, sample or motif. A profile indexed by `index` is not loaded into
+/// memory, only the rows that a query returns are read.
+pub fn serve(input: PathBuf, params: ServeParams) -> Result<()> {
+    let store = match ProfileIndex::open(&input)? {
+        Some(profile_index) => {
+            info!("Loading the index of {}...", input.display());
+            Store::Indexed(profile_index)
+        }
+        None => {
+            info!("Loading {}...", input.display());
+            Store::Loaded(read_merged_profile_any(&input)?)
+        }
+    };
+    let index = Index::new(store);
+    info!(
+        "Indexed {} loci and {} samples",
+        index.len,
+        index.samples.len()
+    );
+
+    let address = format!("{}:{}", params.host, params.port);
+    let server =
+        Server::http(&address).map_err(|e| anyhow!("Could not listen on {}: {}", address, e))?;
+    info!("Serving on http://{}/", address);
+    for request in server.incoming_requests() {
+        let (status, body) = if *request.method() == Method::Get {
+            index.handle(request.url(), params.max_results)
+        } else {
+            (405, json!({"error": "Only GET requests are supported"}))
+        };
+        info!("{} {} {}", request.method(), request.url(), status);
+        let response = Response::from_string(body.to_string())
+            .with_status_code(status)
+            .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+            .with_header(Header::from_bytes("Access-Control-Allow-Origin", "*").unwrap());
+        if let Err(e) = request.respond(response) {
+            warn!("Could not send a response: {}", e);
+        }
+    }
+    Ok(())
+}
+
+impl Index {
+    fn new(store: Store) -> Self {
+        // (locus ID, reference region, motif) of each locus
+        let (loci, samples): (Vec<(&str, &str, &str)>, _) = match &store {
+            Store::Loaded(records) => (
+                records
+                    .iter()
+                    .map(|r| {
+                        (
+                            r.locus_id.as_str(),
+                            r.reference_region.as_str(),
+                            r.motif.as_str(),
+                        )
+                    })
+                    .collect(),
+                sample_summaries(records),
+            ),
+            Store::Indexed(index) => (
+                index
+                    .entries
+                    .iter()
+                    .map(|e| {
+                        (
+                            e.locus_id.as_str(),
+                            e.reference_region.as_str(),
+                            e.motif.as_str(),
+                        )
+                    })
+                    .collect(),
+                index.samples.clone(),
+            ),
+        };
+
+        let mut by_locus_id: HashMap<String, usize> = HashMap::new();
+        let mut by_motif: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut by_chrom: HashMap<String, ChromIndex> = HashMap::new();
+        for (i, &(locus_id, reference_region, motif)) in loci.iter().enumerate() {
+            by_locus_id.insert(locus_id.to_string(), i);
+            by_motif.entry(motif.to_uppercase()).or_default().push(i);
+            match reference_region.parse::<Region>() {
+                Ok(region) => {
+                    let chrom = by_chrom.entry(region.chrom).or_default();
+                    chrom.regions.push((region.start, region.end, i));
+                    chrom.max_len = chrom.max_len.max(region.end - region.start);
+                }
+                Err(e) => warn!("Locus {} cannot be queried by region: {}", locus_id, e),
+            }
+        }
+        for chrom in by_chrom.values_mut() {
+            chrom.regions.sort_unstable();
+        }
+        let len = loci.len();
+        Index {
+            store,
+            len,
+            by_locus_id,
+            by_motif,
+            by_chrom,
+            samples,
+        }
+    }
+
+    /// Answers a GET request with an HTTP status code and a JSON body
+    fn handle(&self, url: &str, max_results: usize) -> (u16, Value) {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        let query: HashMap<String, String> = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (percent_decode(key), percent_decode(value))
+            })
+            .collect();
+        let segments: Vec<String> = path
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(percent_decode)
+            .collect();
+
+        let result = match segments.iter().map(|s| s.as_str()).collect::<Vec<_>>()[..] {
+            [] => Ok(self.info()),
+            ["loci"] => self.query(&query, query.get("sample").map(|s| s.as_str()), max_results),
+            ["loci", locus_id] => match self.by_locus_id.get(locus_id) {
+                Some(&i) => {
+                    let mut locus = Value::Null;
+                    self.for_each_record(&[i], |record| locus = record.to_json())
+                        .map(|()| locus)
+                }
+                None => {
+                    return (
+                        404,
+                        json!({"error": format!("Unknown locus '{}'", locus_id)}),
+                    )
+                }
+            },
+            ["samples"] => Ok(json!(self
+                .samples
+                .iter()
+                .map(|(sample_id, s)| json!({
+                    "sample_id": sample_id,
+                    "loci": s.loci,
+                    "reads": s.reads,
+                    "burden": s.burden,
+                }))
+                .collect::<Vec<Value>>())),
+            ["samples", sample_id] => {
+                if !self.samples.contains_key(sample_id) {
+                    return (
+                        404,
+                        json!({"error": format!("Unknown sample '{}'", sample_id)}),
+                    );
+                }
+                self.query(&query, Some(sample_id), max_results)
+            }
+            _ => {
+                return (
+                    404,
+                    json!({"error": format!("Unknown endpoint '{}'", path)}),
+                )
+            }
+        };
+        match result {
+            Ok(body) => (200, body),
+            Err(e) => (400, json!({"error": e.to_string()})),
+        }
+    }
+
+    fn info(&self) -> Value {
+        json!({
+            "strif_version": env!("CARGO_PKG_VERSION"),
+            "loci": self.len,
+            "samples": self.samples.len(),
+            "endpoints": {
+                "/loci": "Loci filtered by locus_id, region, motif and sample, paged with limit and offset",
+                "/loci/<locus_id>": "A single locus",
+                "/samples": "The samples with their number of loci, reads and total burden",
+                "/samples/<sample_id>": "The loci of a sample, filtered and paged like /loci",
+            },
+        })
+    }
+
+    /// Loci matching all the given filters, with counts restricted to the
+    /// sample if one is given
+    fn query(
+        &self,
+        query: &HashMap<String, String>,
+        sample: Option<&str>,
+        max_results: usize,
+    ) -> Result<Value> {
+        for key in query.keys() {
+            if !["locus_id", "region", "motif", "sample", "limit", "offset"].contains(&key.as_str())
+            {
+                bail!("Unknown query parameter '{}'", key);
+            }
+        }
+        let parse_usize = |key: &str, default: usize| -> Result<usize> {
+            match query.get(key) {
+                Some(value) => value
+                    .parse()
+                    .map_err(|_| anyhow!("'{}' should be a non-negative integer", key)),
+                None => Ok(default),
+            }
+        };
+        let limit = parse_usize("limit", 100)?.min(max_results);
+        let offset = parse_usize("offset", 0)?;
+
+        let overlapping: Option<Vec<usize>> = match query.get("region") {
+            Some(region) => {
+                let region: Region = region.parse().map_err(|e: String| anyhow!(e))?;
+                Some(self.overlapping(&region))
+            }
+            None => None,
+        };
+        let mut matches: Vec<usize> = match (query.get("locus_id"), &overlapping) {
+            (Some(locus_ids), _) => locus_ids
+                .split(',')
+                .filter_map(|locus_id| self.by_locus_id.get(locus_id).copied())
+                .collect(),
+            (None, Some(overlapping)) => overlapping.clone(),
+            (None, None) => (0..self.len).collect(),
+        };
+        if let Some(overlapping) = &overlapping {
+            matches.retain(|i| overlapping.binary_search(i).is_ok());
+        }
+        if let Some(motif) = query.get("motif") {
+            let with_motif = self
+                .by_motif
+                .get(&motif.to_uppercase())
+                .cloned()
+                .unwrap_or_default();
+            matches.retain(|i| with_motif.binary_search(i).is_ok());
+        }
+
+        let mut loci: Vec<Value> = Vec::new();
+        let total = match sample {
+            // the counts of every match are needed to tell which have the sample
+            Some(sample) => {
+                let mut total = 0;
+                self.for_each_record(&matches, |record| {
+                    if record.read_counts.iter().any(|(id, _)| id == sample) {
+                        if total >= offset && loci.len() < limit {
+                            loci.push(restrict_to_sample(record, sample).to_json());
+                        }
+                        total += 1;
+                    }
+                })?;
+                total
+            }
+            None => {
+                let page: Vec<usize> = matches.iter().skip(offset).take(limit).copied().collect();
+                self.for_each_record(&page, |record| loci.push(record.to_json()))?;
+                matches.len()
+            }
+        };
+        Ok(json!({
+            "total": total,
+            "offset": offset,
+            "limit": limit,
+            "loci": loci,
+        }))
+    }
+
+    /// Calls a function with the records of the loci at the given indices, in
+    /// order
+    fn for_each_record(
+        &self,
+        indices: &[usize],
+        mut f: impl FnMut(&MergedProfileRecord),
+    ) -> Result<()> {
+        match &self.store {
+            Store::Loaded(records) => {
+                for &i in indices {
+                    f(&records[i]);
+                }
+                Ok(())
+            }
+            Store::Indexed(index) => index.read_records(indices, |record| {
+                f(&record);
+                Ok(())
+            }),
+        }
+    }
+
+    /// Indices of the loci overlapping a region, in file order
+    fn overlapping(&self, region: &Region) -> Vec<usize> {
+        let chrom = match self.by_chrom.get(&region.chrom) {
+            Some(chrom) => chrom,
+            None => return Vec::new(),
+        };
+        let first = chrom
+            .regions
+            .partition_point(|(start, _, _)| start + chrom.max_len <= region.start);
+        let mut overlapping: Vec<usize> = chrom.regions[first..]
+            .iter()
+            .take_while(|(start, _, _)| *start < region.end)
+            .filter(|(_, end, _)| *end > region.start)
+            .map(|(_, _, i)| *i)
+            .collect();
+        overlapping.sort_unstable();
+        overlapping
+    }
+}
+
+fn restrict_to_sample(record: &MergedProfileRecord, sample: &str) -> MergedProfileRecord {
+    MergedProfileRecord {
+        locus_id: record.locus_id.clone(),
+        reference_region: record.reference_region.clone(),
+        motif: record.motif.clone(),
+        read_counts: record
+            .read_counts
+            .iter()
+            .filter(|(id, _)| id == sample)
+            .cloned()
+            .collect(),
+        interruption_counts: record
+            .interruption_counts
+            .iter()
+            .filter(|(id, _, _)| id == sample)
+            .cloned()
+            .collect(),
+    }
+}
+
+/// Decodes `%XX` escapes and `+` in a URL component
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let byte = bytes
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                match byte {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 3;
+                        continue;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// The command line arguments of `strif serve`
+#[derive(Debug, clap::Args)]
+pub struct ServeArgs {
+    /// The path to the merged profile (TSV, JSON, JSONL or Parquet)
+    #[clap(short = 'i', long)]
+    pub input: PathBuf,
+
+    /// The address to listen on
+    #[clap(long, default_value = "127.0.0.1")]
+    pub host: String,
+
+    /// The port to listen on
+    #[clap(short = 'p', long, default_value = "8080")]
+    pub port: u16,
+
+    /// The largest number of loci returned by a single query
+    #[clap(long, default_value = "1000")]
+    pub max_results: usize,
+}
+
+impl ServeArgs {
+    /// Runs `strif serve`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let ServeArgs {
+            input,
+            host,
+            port,
+            max_results,
+        } = self;
+        let params = ServeParams {
+            host,
+            port,
+            max_results,
+        };
+        Plan::new("serve", dry_run, force)
+            .input("input", &input)
+            .note("address", format!("{}:{}", params.host, params.port))
+            .execute(|| serve(input, params))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::merged_record;
+
+    fn index() -> Index {
+        let mut htt = merged_record(
+            "HTT",
+            &[("s1", 10), ("s2", 20)],
+            &[("s1", "CAA", 0.5), ("s2", "CAT", 1.0)],
+        );
+        htt.reference_region = "chr4:100-200".to_string();
+        let mut fmr1 = merged_record("FMR1", &[("s1", 10)], &[]);
+        fmr1.reference_region = "chrX:1000-1100".to_string();
+        fmr1.motif = "CGG".to_string();
+        // a long locus that starts before queries near its end
+        let mut atxn3 = merged_record("ATXN3", &[("s2", 10)], &[]);
+        atxn3.reference_region = "chr4:150-1000".to_string();
+        Index::new(Store::Loaded(vec![htt, fmr1, atxn3]))
+    }
+
+    fn locus_ids(body: &Value) -> Vec<&str> {
+        body["loci"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|locus| locus["locus_id"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn loci_are_queried_by_region_and_motif() {
+        let index = index();
+        let (status, body) = index.handle("/", 100);
+        assert_eq!(status, 200);
+        assert_eq!(
+            (body["loci"].clone(), body["samples"].clone()),
+            (json!(3), json!(2))
+        );
+
+        let (_, body) = index.handle("/loci?region=chr4%3A900-950", 100);
+        assert_eq!(locus_ids(&body), ["ATXN3"]);
+        let (_, body) = index.handle("/loci?region=chr4:190-195", 100);
+        assert_eq!(locus_ids(&body), ["HTT", "ATXN3"]);
+        let (_, body) = index.handle("/loci?motif=cgg", 100);
+        assert_eq!(locus_ids(&body), ["FMR1"]);
+        let (_, body) = index.handle("/loci?locus_id=FMR1,HTT&region=chr4:1-2000", 100);
+        assert_eq!(locus_ids(&body), ["HTT"]);
+    }
+
+    #[test]
+    fn queries_are_paged_up_to_the_maximum_number_of_results() {
+        let index = index();
+        let (_, body) = index.handle("/loci?motif=CAG&limit=1&offset=1", 100);
+        assert_eq!(body["total"], 2);
+        assert_eq!(locus_ids(&body), ["ATXN3"]);
+
+        let (_, body) = index.handle("/loci?limit=50", 2);
+        assert_eq!(body["limit"], 2);
+        assert_eq!(locus_ids(&body), ["HTT", "FMR1"]);
+    }
+
+    #[test]
+    fn sample_queries_only_return_the_counts_of_the_sample() {
+        let index = index();
+        let (_, body) = index.handle("/samples/s2", 100);
+        assert_eq!(body["total"], 2);
+        assert_eq!(locus_ids(&body), ["HTT", "ATXN3"]);
+        assert_eq!(body["loci"][0]["read_counts"], json!({"s2": 20}));
+        assert_eq!(
+            body["loci"][0]["interruption_counts"],
+            json!({"s2": {"CAT": 1.0}})
+        );
+
+        let (_, body) = index.handle("/samples", 100);
+        assert_eq!(body[0]["sample_id"], "s1");
+        assert_eq!(body[0]["loci"], 2);
+        assert_eq!(body[0]["reads"], 20);
+    }
+
+    #[test]
+    fn bad_requests_are_answered_with_errors() {
+        let index = index();
+        assert_eq!(index.handle("/loci/FMR1", 100).1["motif"], "CGG");
+        assert_eq!(index.handle("/loci/ATXN1", 100).0, 404);
+        assert_eq!(index.handle("/samples/s3", 100).0, 404);
+        assert_eq!(index.handle("/genes", 100).0, 404);
+        let (status, body) = index.handle("/loci?gene=HTT", 100);
+        assert_eq!(status, 400);
+        assert_eq!(body["error"], "Unknown query parameter 'gene'");
+        assert_eq!(index.handle("/loci?limit=-1", 100).0, 400);
+        assert_eq!(index.handle("/loci?region=chr4", 100).0, 400);
+    }
+
+    #[test]
+    fn url_components_are_percent_decoded() {
+        assert_eq!(percent_decode("chr1%3A100-200"), "chr1:100-200");
+        assert_eq!(percent_decode("a+b%2Cc"), "a b,c");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz"), "%zz");
+    }
+}
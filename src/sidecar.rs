@@ -0,0 +1,189 @@
+use std::fs::File;
+use std::io::{prelude::*, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::provenance;
+use crate::schema::{self, OutputKind};
+use crate::utils::{create_output, open_maybe_gzipped};
+
+/// The suffix of the sidecar of an output, appended to its file name
+pub const SIDECAR_SUFFIX: &str = ".strif.json";
+
+/// Whether outputs get a sidecar, set with `--checksums`
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Makes every output written from now on get a JSON sidecar
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub(crate) fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// The path of the sidecar of an output
+pub fn sidecar_path(output: &Path) -> PathBuf {
+    let mut path = output.as_os_str().to_owned();
+    path.push(SIDECAR_SUFFIX);
+    PathBuf::from(path)
+}
+
+/// What the sidecar of an output records about its contents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileSummary {
+    pub size: u64,
+    pub sha256: String,
+    /// The number of data rows of a strif TSV, without its comment lines and
+    /// header row. None for other files, e.g. plots and Arrow streams.
+    pub rows: Option<u64>,
+}
+
+/// Reads an output to compute its size, checksum and number of rows
+pub fn summarize(path: &Path) -> Result<FileSummary> {
+    let file = File::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+    let mut reader = BufReader::new(file);
+    let mut hasher = Sha256::new();
+    let mut size: u64 = 0;
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+        hasher.update(buf);
+        let n = buf.len();
+        size += n as u64;
+        reader.consume(n);
+    }
+    let sha256 = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    // files that are not text, such as Arrow streams, have no schema line
+    let kind = schema::read_schema(path)
+        .ok()
+        .flatten()
+        .and_then(|(name, _)| OutputKind::from_name(&name));
+    let rows = match kind {
+        Some(kind) => Some(count_rows(path, kind)?),
+        None => None,
+    };
+    Ok(FileSummary { size, sha256, rows })
+}
+
+/// Counts the data rows of a strif TSV, which may be gzipped
+fn count_rows(path: &Path, kind: OutputKind) -> Result<u64> {
+    let mut reader = open_maybe_gzipped(path)?;
+    let mut line = Vec::new();
+    let mut rows: u64 = 0;
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        if !line.starts_with(b"#") {
+            rows += 1;
+        }
+        line.clear();
+    }
+    if kind.columns().is_some() {
+        rows = rows.saturating_sub(1);
+    }
+    Ok(rows)
+}
+
+/// Writes the sidecar of an output that was just written, with its size,
+/// SHA-256 checksum, number of rows and the provenance of this process
+pub(crate) fn write_sidecar(output: &Path) -> Result<()> {
+    let summary = summarize(output)?;
+    let file_name = output
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut sidecar = Map::new();
+    sidecar.insert("file".to_string(), json!(file_name));
+    sidecar.insert("size".to_string(), json!(summary.size));
+    sidecar.insert("sha256".to_string(), json!(summary.sha256));
+    if let Some(rows) = summary.rows {
+        sidecar.insert("rows".to_string(), json!(rows));
+    }
+    if let Some((kind, version)) = schema::read_schema(output).ok().flatten() {
+        sidecar.insert("kind".to_string(), json!(kind));
+        sidecar.insert("schema_version".to_string(), json!(version));
+    }
+    for (key, value) in provenance::fields() {
+        let key = key.strip_prefix("strif_").unwrap_or(key);
+        sidecar.insert(key.to_string(), json!(value));
+    }
+    let args = provenance::args();
+    if !args.is_empty() {
+        sidecar.insert("args".to_string(), json!(args));
+    }
+
+    let mut out = create_output(&sidecar_path(output))?;
+    serde_json::to_writer_pretty(&mut out, &Value::Object(sidecar))?;
+    writeln!(out)?;
+    out.finish_without_sidecar()?;
+    Ok(())
+}
+
+/// Reads the sidecar of an output, returning the output's path and the
+/// summary recorded for it
+pub fn read_sidecar(sidecar: &Path) -> Result<(PathBuf, FileSummary)> {
+    let source = sidecar.display().to_string();
+    let file = File::open(sidecar).with_context(|| format!("Could not open {}", source))?;
+    let value: Value = serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("{} is not a JSON object", source))?;
+    let invalid = |name: &str| anyhow!("{} has no valid '{}' field", source, name);
+    let file_name = value
+        .get("file")
+        .and_then(Value::as_str)
+        .ok_or_else(|| invalid("file"))?;
+    let size = value
+        .get("size")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| invalid("size"))?;
+    let sha256 = value
+        .get("sha256")
+        .and_then(Value::as_str)
+        .ok_or_else(|| invalid("sha256"))?
+        .to_string();
+    let rows = value.get("rows").and_then(Value::as_u64);
+    let output = sidecar.with_file_name(file_name);
+    Ok((output, FileSummary { size, sha256, rows }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{profile_record, write_profile_file};
+
+    #[test]
+    fn sidecars_record_the_checksum_and_rows_of_outputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let records = [
+            profile_record("HTT", 3, &[("CAT", 40, 2.0)]),
+            profile_record("FMR1", 5, &[]),
+        ];
+        let profile = write_profile_file(dir.path(), "sample.profile.tsv", &records);
+        write_sidecar(&profile).unwrap();
+
+        let sidecar = dir.path().join("sample.profile.tsv.strif.json");
+        assert_eq!(sidecar_path(&profile), sidecar);
+        let (output, summary) = read_sidecar(&sidecar).unwrap();
+        assert_eq!(output, profile);
+        assert_eq!(summary.rows, Some(2));
+        assert_eq!(summary.size, std::fs::metadata(&profile).unwrap().len());
+        assert_eq!(
+            summary.sha256,
+            provenance::sha256_hex(&std::fs::read(&profile).unwrap())
+        );
+
+        // files without a schema line have no rows
+        let plot = dir.path().join("plot.svg");
+        std::fs::write(&plot, "<svg/>\n").unwrap();
+        assert_eq!(summarize(&plot).unwrap().rows, None);
+    }
+}
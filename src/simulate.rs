@@ -0,0 +1,498 @@
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Ok, Result};
+use log::info;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+use crate::plan::Plan;
+use crate::profile::load_str_catalog;
+use crate::schema::{self, OutputKind};
+use crate::utils::{create_output, get_default_out_path};
+
+const ALPHABET: &[u8] = b"ACGT";
+
+/// How interruptions are introduced into simulated alleles
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum InterruptionMode {
+    /// Interruptions are inserted between repeat bases
+    Insert,
+    /// Interruptions replace repeat bases
+    Substitute,
+    /// Each interruption is randomly inserted or substituted
+    Both,
+}
+
+pub struct SimulationParams {
+    pub coverage: u32,
+    pub min_repeat_len: usize,
+    pub max_repeat_len: usize,
+    pub interruption_rate: f64,
+    pub min_interruption_len: usize,
+    pub max_interruption_len: usize,
+    pub interruption_mode: InterruptionMode,
+    pub substitution_error_rate: f64,
+    pub indel_error_rate: f64,
+    pub seed: u64,
+}
+
+struct Allele {
+    seq: Vec<u8>,
+    interruption: String,
+    interruption_pos: Option<usize>,
+}
+
+/// A simulated read with the allele it was sampled from
+pub struct SimulatedRead<'a> {
+    pub read_id: usize,
+    pub locus_id: &'a str,
+    pub motif: &'a [u8],
+    /// 1 or 2
+    pub allele: usize,
+    /// The interruption of the allele, empty if it is a pure repeat
+    pub interruption: &'a str,
+    pub interruption_pos: Option<usize>,
+    pub allele_seq: &'a [u8],
+    pub read_seq: Vec<u8>,
+}
+
+impl SimulationParams {
+    pub fn validate(&self) -> Result<()> {
+        if self.min_repeat_len == 0 || self.min_repeat_len > self.max_repeat_len {
+            bail!(
+                "Invalid repeat length range {}-{}",
+                self.min_repeat_len,
+                self.max_repeat_len
+            );
+        }
+        if self.min_interruption_len == 0 || self.min_interruption_len > self.max_interruption_len {
+            bail!(
+                "Invalid interruption length range {}-{}",
+                self.min_interruption_len,
+                self.max_interruption_len
+            );
+        }
+        for (name, rate) in [
+            ("interruption rate", self.interruption_rate),
+            ("substitution error rate", self.substitution_error_rate),
+            ("indel error rate", self.indel_error_rate),
+        ] {
+            if !(0.0..=1.0).contains(&rate) {
+                bail!("The {} must be between 0 and 1, got {}", name, rate);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The path the simulated reads of a catalog are written to if no output is
+/// given, in the same directory as the catalog
+pub fn default_out_path(str_catalog: &Path, fastq: bool) -> PathBuf {
+    if fastq {
+        get_default_out_path(str_catalog, "simulated", "fastq")
+    } else {
+        get_default_out_path(str_catalog, "simulated_repeat_seqs", "tsv")
+    }
+}
+
+pub fn simulate(
+    str_catalog: PathBuf,
+    out_path: PathBuf,
+    truth_path: PathBuf,
+    params: SimulationParams,
+    fastq: bool,
+    filter: Option<String>,
+) -> Result<()> {
+    params.validate()?;
+
+    info!("Loading STR catalog...");
+    let (motifs, _, _, _, _) = load_str_catalog(str_catalog, filter)?;
+
+    let mut out_file = create_output(&out_path)?;
+    let mut truth_file = create_output(&truth_path)?;
+
+    if !fastq {
+        schema::write_header(&mut out_file, OutputKind::RepeatSeqs)?;
+    }
+    writeln!(
+        truth_file,
+        "read_id\tlocus_id\tallele\tmotif\tinterruption\tinterruption_pos\tallele_seq\tread_seq"
+    )?;
+
+    info!("Simulating reads for {} loci...", motifs.len());
+    simulate_reads(&motifs, &params, |read| {
+        let read_seq = std::str::from_utf8(&read.read_seq).unwrap();
+        if fastq {
+            writeln!(
+                out_file,
+                "@{}:{}\n{}\n+\n{}",
+                read.locus_id,
+                read.read_id,
+                read_seq,
+                "I".repeat(read_seq.len())
+            )?;
+        } else {
+            writeln!(out_file, "{}\t{}", read.locus_id, read_seq)?;
+        }
+
+        let interruption_pos = read
+            .interruption_pos
+            .map(|pos| pos.to_string())
+            .unwrap_or_default();
+        writeln!(
+            truth_file,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            read.read_id,
+            read.locus_id,
+            read.allele,
+            std::str::from_utf8(read.motif).unwrap(),
+            read.interruption,
+            interruption_pos,
+            std::str::from_utf8(read.allele_seq).unwrap(),
+            read_seq
+        )?;
+        Ok(())
+    })?;
+
+    out_file.finish()?;
+    truth_file.finish()?;
+
+    info!("Done!");
+
+    Ok(())
+}
+
+/// Simulates two alleles per locus and samples `coverage` reads from them,
+/// passing each read to `emit`. Loci are visited in sorted order so that a
+/// given seed always produces the same reads.
+pub fn simulate_reads<F>(
+    motifs: &HashMap<String, String>,
+    params: &SimulationParams,
+    mut emit: F,
+) -> Result<()>
+where
+    F: FnMut(SimulatedRead) -> Result<()>,
+{
+    let mut loci: Vec<(&String, &String)> = motifs.iter().collect();
+    loci.sort();
+
+    let mut rng = StdRng::seed_from_u64(params.seed);
+    let mut read_id: usize = 0;
+    for (locus_id, motif) in loci {
+        let motif = motif.as_bytes();
+        let alleles = [
+            simulate_allele(motif, params, &mut rng),
+            simulate_allele(motif, params, &mut rng),
+        ];
+
+        for _ in 0..params.coverage {
+            let allele_idx: usize = rng.gen_range(0..alleles.len());
+            let allele = &alleles[allele_idx];
+            emit(SimulatedRead {
+                read_id,
+                locus_id,
+                motif,
+                allele: allele_idx + 1,
+                interruption: &allele.interruption,
+                interruption_pos: allele.interruption_pos,
+                allele_seq: &allele.seq,
+                read_seq: add_sequencing_errors(&allele.seq, params, &mut rng),
+            })?;
+            read_id += 1;
+        }
+    }
+    Ok(())
+}
+
+fn simulate_allele(motif: &[u8], params: &SimulationParams, rng: &mut StdRng) -> Allele {
+    let repeat_len: usize = rng.gen_range(params.min_repeat_len..=params.max_repeat_len);
+
+    // pure repeat starting at a random phase of the motif
+    let phase: usize = rng.gen_range(0..motif.len());
+    let mut seq: Vec<u8> = motif
+        .iter()
+        .cycle()
+        .skip(phase)
+        .take(repeat_len)
+        .copied()
+        .collect();
+
+    if !rng.gen_bool(params.interruption_rate) {
+        return Allele {
+            seq,
+            interruption: String::new(),
+            interruption_pos: None,
+        };
+    }
+
+    let insert = match params.interruption_mode {
+        InterruptionMode::Insert => true,
+        InterruptionMode::Substitute => false,
+        InterruptionMode::Both => rng.gen_bool(0.5),
+    };
+    let interruption_len: usize = rng
+        .gen_range(params.min_interruption_len..=params.max_interruption_len)
+        .min(repeat_len.saturating_sub(2).max(1));
+    // keep at least one repeat base on either side of the interruption
+    let max_pos = repeat_len.saturating_sub(interruption_len + 1).max(1);
+    let interruption_pos: usize = rng.gen_range(1..=max_pos);
+
+    let interruption: Vec<u8> = loop {
+        let candidate: Vec<u8> = (0..interruption_len)
+            .map(|_| *ALPHABET.choose(rng).unwrap())
+            .collect();
+        // a substitution identical to the bases it replaces is not an interruption
+        let replaced = &seq[interruption_pos..(interruption_pos + interruption_len).min(seq.len())];
+        if insert || candidate != replaced {
+            break candidate;
+        }
+    };
+
+    if insert {
+        seq.splice(interruption_pos..interruption_pos, interruption.clone());
+        seq.truncate(repeat_len);
+    } else {
+        let end = (interruption_pos + interruption_len).min(seq.len());
+        seq.splice(interruption_pos..end, interruption.clone());
+    }
+
+    Allele {
+        seq,
+        interruption: String::from_utf8(interruption).unwrap(),
+        interruption_pos: Some(interruption_pos),
+    }
+}
+
+fn add_sequencing_errors(seq: &[u8], params: &SimulationParams, rng: &mut StdRng) -> Vec<u8> {
+    let mut read: Vec<u8> = Vec::with_capacity(seq.len());
+    for &base in seq {
+        if params.indel_error_rate > 0.0 && rng.gen_bool(params.indel_error_rate) {
+            // equally likely to delete the base or insert a random base after it
+            if rng.gen_bool(0.5) {
+                continue;
+            }
+            read.push(base);
+            read.push(*ALPHABET.choose(rng).unwrap());
+        } else if params.substitution_error_rate > 0.0
+            && rng.gen_bool(params.substitution_error_rate)
+        {
+            let others: Vec<u8> = ALPHABET.iter().copied().filter(|&b| b != base).collect();
+            read.push(*others.choose(rng).unwrap());
+        } else {
+            read.push(base);
+        }
+    }
+    read
+}
+
+/// The command line arguments of `strif simulate`
+#[derive(Debug, clap::Args)]
+pub struct SimulateArgs {
+    /// The path to a JSON file containing the catalog of repeat loci
+    pub str_catalog: PathBuf,
+
+    /// The path to write the simulated repeat sequences to. Defaults to the same directory as the catalog.
+    pub output: Option<PathBuf>,
+
+    /// The path to write the truth file to. Defaults to the same directory as the catalog.
+    pub truth: Option<PathBuf>,
+
+    /// Write reads as FASTQ instead of the repeat sequences format
+    #[clap(long, action)]
+    pub fastq: bool,
+
+    /// Filter locus IDs using a regular expression. Defaults to None.
+    #[clap(short = 'f', long)]
+    pub filter: Option<String>,
+
+    /// The number of reads to simulate per locus
+    #[clap(short = 'c', long, default_value = "30")]
+    pub coverage: u32,
+
+    /// The minimum allele length in base pairs
+    #[clap(long, default_value = "10")]
+    pub min_repeat_len: usize,
+
+    /// The maximum allele length in base pairs
+    #[clap(long, default_value = "100")]
+    pub max_repeat_len: usize,
+
+    /// The probability that an allele carries an interruption
+    #[clap(long, default_value = "0.5")]
+    pub interruption_rate: f64,
+
+    /// The minimum interruption length in base pairs
+    #[clap(long, default_value = "1")]
+    pub min_interruption_len: usize,
+
+    /// The maximum interruption length in base pairs
+    #[clap(long, default_value = "3")]
+    pub max_interruption_len: usize,
+
+    /// How interruptions are introduced into alleles
+    #[clap(long, value_enum, default_value = "both")]
+    pub interruption_mode: InterruptionMode,
+
+    /// The per-base probability of a sequencing substitution error
+    #[clap(long, default_value = "0.001")]
+    pub substitution_error_rate: f64,
+
+    /// The per-base probability of a sequencing insertion or deletion error
+    #[clap(long, default_value = "0")]
+    pub indel_error_rate: f64,
+
+    /// The random seed
+    #[clap(long, default_value = "42")]
+    pub seed: u64,
+}
+
+impl SimulateArgs {
+    /// Runs `strif simulate`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let SimulateArgs {
+            str_catalog,
+            output,
+            truth,
+            fastq,
+            filter,
+            coverage,
+            min_repeat_len,
+            max_repeat_len,
+            interruption_rate,
+            min_interruption_len,
+            max_interruption_len,
+            interruption_mode,
+            substitution_error_rate,
+            indel_error_rate,
+            seed,
+        } = self;
+        let out_path: PathBuf = output.unwrap_or_else(|| default_out_path(&str_catalog, fastq));
+        let truth_path: PathBuf =
+            truth.unwrap_or_else(|| get_default_out_path(&str_catalog, "simulated_truth", "tsv"));
+        let params = SimulationParams {
+            coverage,
+            min_repeat_len,
+            max_repeat_len,
+            interruption_rate,
+            min_interruption_len,
+            max_interruption_len,
+            interruption_mode,
+            substitution_error_rate,
+            indel_error_rate,
+            seed,
+        };
+        Plan::new("simulate", dry_run, force)
+            .catalog(&str_catalog, filter.as_deref())
+            .output("simulated reads", &out_path)
+            .output("truth", &truth_path)
+            .execute(|| simulate(str_catalog, out_path, truth_path, params, fastq, filter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(interruption_rate: f64, interruption_mode: InterruptionMode) -> SimulationParams {
+        SimulationParams {
+            coverage: 20,
+            min_repeat_len: 30,
+            max_repeat_len: 60,
+            interruption_rate,
+            min_interruption_len: 1,
+            max_interruption_len: 3,
+            interruption_mode,
+            substitution_error_rate: 0.0,
+            indel_error_rate: 0.0,
+            seed: 7,
+        }
+    }
+
+    fn motifs() -> HashMap<String, String> {
+        HashMap::from([
+            ("HTT".to_string(), "CAG".to_string()),
+            ("FMR1".to_string(), "CGG".to_string()),
+        ])
+    }
+
+    /// (locus, allele, interruption, read) of each simulated read
+    fn reads(params: &SimulationParams) -> Vec<(String, usize, String, Vec<u8>)> {
+        let mut reads = Vec::new();
+        simulate_reads(&motifs(), params, |read| {
+            reads.push((
+                read.locus_id.to_string(),
+                read.allele,
+                read.interruption.to_string(),
+                read.read_seq,
+            ));
+            Ok(())
+        })
+        .unwrap();
+        reads
+    }
+
+    #[test]
+    fn a_seed_always_simulates_the_same_reads() {
+        let mut params = params(0.5, InterruptionMode::Both);
+        params.substitution_error_rate = 0.01;
+        params.indel_error_rate = 0.01;
+        let first = reads(&params);
+        assert_eq!(first.len(), 40);
+        // loci are visited in sorted order
+        assert_eq!(first[0].0, "FMR1");
+        assert_eq!(first, reads(&params));
+        params.seed += 1;
+        assert_ne!(first, reads(&params));
+    }
+
+    #[test]
+    fn pure_alleles_repeat_the_motif() {
+        for (locus_id, _, interruption, read) in reads(&params(0.0, InterruptionMode::Both)) {
+            let motif = &motifs()[&locus_id];
+            assert!(interruption.is_empty());
+            assert!((30..=60).contains(&read.len()));
+            // the repeat starts at any phase of the motif
+            let doubled = motif.repeat(2);
+            let phase = doubled.find(std::str::from_utf8(&read[..3]).unwrap());
+            let expected: Vec<u8> = motif
+                .bytes()
+                .cycle()
+                .skip(phase.unwrap())
+                .take(read.len())
+                .collect();
+            assert_eq!(read, expected);
+        }
+    }
+
+    #[test]
+    fn substituted_interruptions_keep_the_repeat_length() {
+        let mut params = params(1.0, InterruptionMode::Substitute);
+        params.min_repeat_len = 45;
+        params.max_repeat_len = 45;
+        let mut rng = StdRng::seed_from_u64(params.seed);
+        for _ in 0..50 {
+            let allele = simulate_allele(b"CAG", &params, &mut rng);
+            let pos = allele.interruption_pos.unwrap();
+            let len = allele.interruption.len();
+            assert_eq!(allele.seq.len(), 45);
+            assert!(pos >= 1 && pos + len < 45);
+            assert_eq!(&allele.seq[pos..pos + len], allele.interruption.as_bytes());
+        }
+    }
+
+    #[test]
+    fn invalid_ranges_and_rates_are_rejected() {
+        let mut invalid = params(0.5, InterruptionMode::Insert);
+        invalid.min_repeat_len = 70;
+        assert!(invalid.validate().is_err());
+        let mut invalid = params(0.5, InterruptionMode::Insert);
+        invalid.indel_error_rate = 1.5;
+        assert_eq!(
+            invalid.validate().unwrap_err().to_string(),
+            "The indel error rate must be between 0 and 1, got 1.5"
+        );
+    }
+}
@@ -0,0 +1,322 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Ok, Result};
+use serde_json::{json, Map, Value};
+
+use crate::error::field;
+use crate::extract::parse_multiplicity;
+use crate::merge::read_merged_profile;
+use crate::plan::Plan;
+use crate::profile::read_profile;
+use crate::schema::{self, OutputKind};
+
+/// Summarizes a strif output file, printing either human-readable text or JSON
+pub fn stats(input: PathBuf, as_json: bool) -> Result<()> {
+    let kind = schema::detect(&input)?;
+    let summary = match kind {
+        OutputKind::RepeatSeqs => repeat_seqs_stats(&input)?,
+        OutputKind::Profile => profile_stats(&input)?,
+        OutputKind::MergedProfile => merged_profile_stats(&input)?,
+        kind => bail!(
+            "Summary statistics are not available for '{}' files",
+            kind.name()
+        ),
+    };
+
+    let mut output = Map::new();
+    output.insert("file".to_string(), json!(input.display().to_string()));
+    output.insert("kind".to_string(), json!(kind.name()));
+    output.extend(summary);
+
+    if as_json {
+        println!("{}", serde_json::to_string_pretty(&Value::Object(output))?);
+    } else {
+        print_human(&output, 0);
+    }
+    Ok(())
+}
+
+fn repeat_seqs_stats(input: &Path) -> Result<Map<String, Value>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(false)
+        .flexible(true)
+        .comment(Some(b'#'))
+        .from_reader(File::open(input)?);
+
+    let source = input.display().to_string();
+    let mut reads_per_locus: HashMap<String, f64> = HashMap::new();
+    let mut repeat_lens: Vec<f64> = Vec::new();
+    for result in reader.records() {
+        let record: csv::StringRecord = result?;
+        // rows collapsed by `dedup` stand for several reads
+        let multiplicity = parse_multiplicity(&record, &source)?;
+        *reads_per_locus
+            .entry(field(&record, 0, &source)?.to_string())
+            .or_default() += multiplicity as f64;
+        let repeat_len = record.get(1).map(|s| s.len()).unwrap_or(0) as f64;
+        repeat_lens.extend(std::iter::repeat_n(repeat_len, multiplicity as usize));
+    }
+
+    let mut summary = Map::new();
+    summary.insert("loci".to_string(), json!(reads_per_locus.len()));
+    summary.insert("reads".to_string(), json!(repeat_lens.len()));
+    summary.insert(
+        "reads_per_locus".to_string(),
+        quantiles(reads_per_locus.into_values().collect()),
+    );
+    summary.insert("repeat_length".to_string(), quantiles(repeat_lens));
+    Ok(summary)
+}
+
+fn profile_stats(input: &Path) -> Result<Map<String, Value>> {
+    let records = read_profile(input)?;
+
+    let mut total_reads: u64 = 0;
+    let mut total_interruptions: u64 = 0;
+    let mut loci_with_reads: usize = 0;
+    let mut loci_with_interruptions: usize = 0;
+    let mut coverage: Vec<f64> = Vec::new();
+    let mut interruption_totals: HashMap<&str, u64> = HashMap::new();
+    for record in &records {
+        total_reads += record.read_count as u64;
+        coverage.push(record.read_count as f64);
+        if record.read_count > 0 {
+            loci_with_reads += 1;
+        }
+        if !record.interruption_counts.is_empty() {
+            loci_with_interruptions += 1;
+        }
+        for (interruption, _, count) in &record.interruption_counts {
+            // expected counts of quality-weighted profiles are rounded
+            let count = count.round() as u64;
+            total_interruptions += count;
+            *interruption_totals.entry(interruption).or_default() += count;
+        }
+    }
+
+    let mut summary = Map::new();
+    summary.insert("loci".to_string(), json!(records.len()));
+    summary.insert("loci_with_reads".to_string(), json!(loci_with_reads));
+    summary.insert(
+        "loci_with_interruptions".to_string(),
+        json!(loci_with_interruptions),
+    );
+    summary.insert("reads".to_string(), json!(total_reads));
+    summary.insert("interruptions".to_string(), json!(total_interruptions));
+    summary.insert(
+        "interruptions_per_read".to_string(),
+        json!(ratio(total_interruptions as f64, total_reads as f64)),
+    );
+    summary.insert("reads_per_locus".to_string(), quantiles(coverage));
+    summary.insert(
+        "top_interruptions".to_string(),
+        top_counts(interruption_totals, 10),
+    );
+    Ok(summary)
+}
+
+fn merged_profile_stats(input: &Path) -> Result<Map<String, Value>> {
+    let records = read_merged_profile(input)?;
+
+    let mut samples: HashSet<&str> = HashSet::new();
+    let mut total_reads: u64 = 0;
+    let mut loci_with_interruptions: usize = 0;
+    let mut samples_per_locus: Vec<f64> = Vec::new();
+    let mut coverage: Vec<f64> = Vec::new();
+    let mut burden_per_locus: Vec<f64> = Vec::new();
+    let mut interruption_samples: HashMap<&str, u64> = HashMap::new();
+    for record in &records {
+        samples_per_locus.push(record.read_counts.len() as f64);
+        for (sample_id, count) in &record.read_counts {
+            samples.insert(sample_id);
+            total_reads += *count as u64;
+            coverage.push(*count as f64);
+        }
+        if !record.interruption_counts.is_empty() {
+            loci_with_interruptions += 1;
+        }
+        let burden: f64 = record.interruption_counts.iter().map(|(_, _, c)| c).sum();
+        burden_per_locus.push(ratio(burden, record.read_counts.len() as f64));
+        for (_, interruption, _) in &record.interruption_counts {
+            *interruption_samples.entry(interruption).or_default() += 1;
+        }
+    }
+
+    let mut summary = Map::new();
+    summary.insert("loci".to_string(), json!(records.len()));
+    summary.insert("samples".to_string(), json!(samples.len()));
+    summary.insert(
+        "loci_with_interruptions".to_string(),
+        json!(loci_with_interruptions),
+    );
+    summary.insert("reads".to_string(), json!(total_reads));
+    summary.insert(
+        "samples_per_locus".to_string(),
+        quantiles(samples_per_locus),
+    );
+    summary.insert("reads_per_sample_locus".to_string(), quantiles(coverage));
+    summary.insert(
+        "mean_normalized_burden_per_locus".to_string(),
+        quantiles(burden_per_locus),
+    );
+    summary.insert(
+        "top_interruptions_by_sample_loci".to_string(),
+        top_counts(interruption_samples, 10),
+    );
+    Ok(summary)
+}
+
+/// Returns the value at quantile `q` of sorted values using linear interpolation
+pub fn quantile(sorted: &[f64], q: f64) -> f64 {
+    if sorted.is_empty() {
+        return f64::NAN;
+    }
+    let pos = q * (sorted.len() - 1) as f64;
+    let lower = pos.floor() as usize;
+    let upper = pos.ceil() as usize;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (pos - lower as f64)
+}
+
+fn quantiles(mut values: Vec<f64>) -> Value {
+    if values.is_empty() {
+        return Value::Null;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    json!({
+        "mean": mean,
+        "min": quantile(&values, 0.0),
+        "q25": quantile(&values, 0.25),
+        "median": quantile(&values, 0.5),
+        "q75": quantile(&values, 0.75),
+        "max": quantile(&values, 1.0),
+    })
+}
+
+fn top_counts(counts: HashMap<&str, u64>, n: usize) -> Value {
+    let mut counts: Vec<(&str, u64)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    let top: Map<String, Value> = counts
+        .into_iter()
+        .take(n)
+        .map(|(key, count)| (key.to_string(), json!(count)))
+        .collect();
+    Value::Object(top)
+}
+
+fn ratio(numerator: f64, denominator: f64) -> f64 {
+    if denominator == 0.0 {
+        0.0
+    } else {
+        numerator / denominator
+    }
+}
+
+fn print_human(map: &Map<String, Value>, indent: usize) {
+    for (key, value) in map {
+        let label = key.replace('_', " ");
+        match value {
+            Value::Object(inner) if inner.is_empty() => {
+                println!("{:indent$}{}: none", "", label, indent = indent)
+            }
+            Value::Object(inner) => {
+                println!("{:indent$}{}:", "", label, indent = indent);
+                print_human(inner, indent + 2);
+            }
+            Value::Number(n) if n.is_f64() => println!(
+                "{:indent$}{}: {:.4}",
+                "",
+                label,
+                n.as_f64().unwrap(),
+                indent = indent
+            ),
+            Value::String(s) => println!("{:indent$}{}: {}", "", label, s, indent = indent),
+            Value::Null => println!("{:indent$}{}: n/a", "", label, indent = indent),
+            other => println!("{:indent$}{}: {}", "", label, other, indent = indent),
+        }
+    }
+}
+
+/// The command line arguments of `strif stats`
+#[derive(Debug, clap::Args)]
+pub struct StatsArgs {
+    /// The path to the strif output
+    pub input: PathBuf,
+
+    /// Print the statistics as JSON
+    #[clap(long, action)]
+    pub json: bool,
+}
+
+impl StatsArgs {
+    /// Runs `strif stats`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let StatsArgs { input, json } = self;
+        Plan::new("stats", dry_run, force)
+            .records("input", &input, "rows")
+            .execute(|| stats(input, json))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::testing::{profile_record, write_profile_file};
+
+    #[test]
+    fn quantiles_interpolate_between_values() {
+        let values = [1.0, 2.0, 4.0, 8.0];
+        assert_eq!(quantile(&values, 0.0), 1.0);
+        assert_eq!(quantile(&values, 0.5), 3.0);
+        assert_eq!(quantile(&values, 0.75), 5.0);
+        assert_eq!(quantile(&values, 1.0), 8.0);
+        assert!(quantile(&[], 0.5).is_nan());
+    }
+
+    #[test]
+    fn profiles_are_summarized_over_loci() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_profile_file(
+            dir.path(),
+            "profile.tsv",
+            &[
+                profile_record(
+                    "L1",
+                    10,
+                    &[("CAT", 40, 3.0), ("CAT", 41, 1.0), ("A", 40, 2.0)],
+                ),
+                profile_record("L2", 30, &[("A", 40, 2.6)]),
+                profile_record("L3", 0, &[]),
+            ],
+        );
+        let summary = profile_stats(&path).unwrap();
+        assert_eq!(summary["loci"], json!(3));
+        assert_eq!(summary["loci_with_reads"], json!(2));
+        assert_eq!(summary["loci_with_interruptions"], json!(2));
+        assert_eq!(summary["reads"], json!(40));
+        // fractional counts are rounded
+        assert_eq!(summary["interruptions"], json!(9));
+        assert_eq!(summary["interruptions_per_read"], json!(0.225));
+        assert_eq!(summary["reads_per_locus"]["median"], json!(10.0));
+        assert_eq!(summary["top_interruptions"], json!({ "A": 5, "CAT": 4 }));
+    }
+
+    #[test]
+    fn repeat_seqs_are_summarized_per_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("repeat_seqs.tsv");
+        fs::write(&path, "L1\tCAGCAG\nL1\tCAGCAGCAG\nL2\tCAG\n").unwrap();
+        let summary = repeat_seqs_stats(&path).unwrap();
+        assert_eq!(summary["loci"], json!(2));
+        assert_eq!(summary["reads"], json!(3));
+        assert_eq!(summary["repeat_length"]["max"], json!(9.0));
+        assert_eq!(summary["reads_per_locus"]["mean"], json!(1.5));
+    }
+}
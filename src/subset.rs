@@ -0,0 +1,333 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{prelude::*, BufReader};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use regex::Regex;
+
+use crate::convert::read_merged_profile_any;
+use crate::error::StrifError;
+use crate::merge::MergedProfileRecord;
+use crate::plan::Plan;
+use crate::schema::{self, OutputKind};
+use crate::utils::{create_output, get_default_out_path, Region};
+
+/// The samples and loci to keep. A locus must match every kind of locus
+/// criterion given, and any of the values given for each.
+pub struct SubsetParams {
+    /// Only these samples are kept, or every sample if empty
+    pub samples: Vec<String>,
+    pub exclude_samples: Vec<String>,
+    pub locus_ids: Vec<String>,
+    pub locus_regex: Option<Regex>,
+    pub regions: Vec<Region>,
+}
+
+impl SubsetParams {
+    fn keep_locus(&self, locus_id: &str, reference_region: &str) -> bool {
+        if !self.locus_ids.is_empty() && !self.locus_ids.iter().any(|id| id == locus_id) {
+            return false;
+        }
+        if let Some(locus_regex) = &self.locus_regex {
+            if !locus_regex.is_match(locus_id) {
+                return false;
+            }
+        }
+        if !self.regions.is_empty() {
+            return reference_region
+                .parse::<Region>()
+                .is_ok_and(|region| self.regions.iter().any(|r| r.overlaps(&region)));
+        }
+        true
+    }
+
+    fn keep_sample(&self, sample_id: &str) -> bool {
+        (self.samples.is_empty() || self.samples.iter().any(|s| s == sample_id))
+            && !self.exclude_samples.iter().any(|s| s == sample_id)
+    }
+
+    /// Drops the counts of other samples, returning false if none are left
+    fn restrict_samples(&self, record: &mut MergedProfileRecord) -> bool {
+        record
+            .read_counts
+            .retain(|(sample_id, _)| self.keep_sample(sample_id));
+        record
+            .interruption_counts
+            .retain(|(sample_id, _, _)| self.keep_sample(sample_id));
+        !record.read_counts.is_empty()
+    }
+}
+
+/// Reads a list of IDs, one per line, skipping blank lines and `#` comments
+pub fn read_id_list(path: &Path) -> Result<Vec<String>> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("Could not open {}", path.display()))?;
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Reads the regions of a BED file, whose coordinates are compared with the
+/// reference regions of loci as they are
+pub fn read_bed_regions(path: &Path) -> Result<Vec<Region>> {
+    let reader = BufReader::new(
+        File::open(path).with_context(|| format!("Could not open {}", path.display()))?,
+    );
+    let mut regions: Vec<Region> = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+            || line.trim().is_empty()
+        {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 3 {
+            bail!(
+                "{} line {} has {} columns, expected at least 3",
+                path.display(),
+                i + 1,
+                fields.len()
+            );
+        }
+        regions.push(Region {
+            chrom: fields[0].to_string(),
+            start: fields[1]
+                .parse()
+                .with_context(|| format!("Invalid start in {} line {}", path.display(), i + 1))?,
+            end: fields[2]
+                .parse()
+                .with_context(|| format!("Invalid end in {} line {}", path.display(), i + 1))?,
+        });
+    }
+    Ok(regions)
+}
+
+/// Writes the given samples and loci of a merged profile to a new merged
+/// profile. Counts are copied as they are, since each sample was normalized
+/// by its own read depth, and loci left without samples are dropped.
+pub fn subset(input: PathBuf, out_path: PathBuf, params: SubsetParams) -> Result<()> {
+    info!("Reading {}...", input.display());
+    let records = read_merged_profile_any(&input)?;
+    let total = records.len();
+
+    let mut found_samples: HashSet<String> = HashSet::new();
+    let mut found_loci: HashSet<String> = HashSet::new();
+    let mut out_file = create_output(&out_path)?;
+    schema::write_header(&mut out_file, OutputKind::MergedProfile)?;
+    let mut kept = 0;
+    for mut record in records {
+        found_samples.extend(record.read_counts.iter().map(|(s, _)| s.clone()));
+        found_loci.insert(record.locus_id.clone());
+        if !params.keep_locus(&record.locus_id, &record.reference_region)
+            || !params.restrict_samples(&mut record)
+        {
+            continue;
+        }
+        record.write_to(&mut out_file)?;
+        kept += 1;
+    }
+    out_file.finish()?;
+
+    for (ids, found, what) in [
+        (&params.samples, &found_samples, "samples are"),
+        (&params.exclude_samples, &found_samples, "samples are"),
+        (&params.locus_ids, &found_loci, "loci are"),
+    ] {
+        let missing: Vec<&str> = ids
+            .iter()
+            .filter(|id| !found.contains(*id))
+            .map(String::as_str)
+            .collect();
+        if !missing.is_empty() {
+            warn!(
+                "{} {} not in {}: {}",
+                missing.len(),
+                what,
+                input.display(),
+                missing.join(", ")
+            );
+        }
+    }
+    if kept == 0 {
+        return Err(StrifError::EmptyOutput {
+            path: out_path.display().to_string(),
+            message: "no loci of the merged profile have the selected samples and loci".to_string(),
+        }
+        .into());
+    }
+
+    info!("Kept {} of {} loci", kept, total);
+    info!("Done!");
+    Ok(())
+}
+
+/// The command line arguments of `strif subset`
+#[derive(Debug, clap::Args)]
+pub struct SubsetArgs {
+    /// The path to the merged profile, in any format supported by `convert`
+    pub input: PathBuf,
+
+    /// The path to the subset. Defaults to the same directory as the input.
+    pub output: Option<PathBuf>,
+
+    /// Only keep this sample. Can be repeated.
+    #[clap(short = 's', long)]
+    pub sample: Vec<String>,
+
+    /// Only keep the samples listed in this file, one per line
+    #[clap(long)]
+    pub samples: Option<PathBuf>,
+
+    /// Drop this sample. Can be repeated.
+    #[clap(long)]
+    pub exclude_sample: Vec<String>,
+
+    /// Drop the samples listed in this file, one per line
+    #[clap(long)]
+    pub exclude_samples: Option<PathBuf>,
+
+    /// Only keep this locus. Can be repeated.
+    #[clap(short = 'l', long)]
+    pub locus: Vec<String>,
+
+    /// Only keep the loci listed in this file, one per line
+    #[clap(long)]
+    pub loci: Option<PathBuf>,
+
+    /// Only keep loci whose IDs match this regular expression
+    #[clap(short = 'f', long)]
+    pub filter: Option<String>,
+
+    /// Only keep loci overlapping this region (chrom:start-end). Can be repeated.
+    #[clap(short = 'r', long)]
+    pub region: Vec<Region>,
+
+    /// Only keep loci overlapping the regions of this BED file
+    #[clap(long)]
+    pub bed: Option<PathBuf>,
+}
+
+impl SubsetArgs {
+    /// Runs `strif subset`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let SubsetArgs {
+            input,
+            output,
+            mut sample,
+            samples,
+            mut exclude_sample,
+            exclude_samples,
+            mut locus,
+            loci,
+            filter: locus_filter,
+            mut region,
+            bed,
+        } = self;
+        let out_path: PathBuf =
+            output.unwrap_or_else(|| get_default_out_path(&input, "subset", "tsv"));
+        if let Some(samples) = &samples {
+            sample.extend(read_id_list(samples)?);
+        }
+        if let Some(exclude_samples) = &exclude_samples {
+            exclude_sample.extend(read_id_list(exclude_samples)?);
+        }
+        if let Some(loci) = &loci {
+            locus.extend(read_id_list(loci)?);
+        }
+        if let Some(bed) = &bed {
+            region.extend(read_bed_regions(bed)?);
+        }
+        let params = SubsetParams {
+            samples: sample,
+            exclude_samples: exclude_sample,
+            locus_ids: locus,
+            locus_regex: locus_filter.map(|f| regex::Regex::new(&f)).transpose()?,
+            regions: region,
+        };
+        Plan::new("subset", dry_run, force)
+            .records("input", &input, "loci")
+            .output("subset", &out_path)
+            .execute(|| subset(input, out_path, params))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::read_merged_profile;
+    use crate::testing::{merged_record, write_merged_profile_file};
+
+    fn keep_all() -> SubsetParams {
+        SubsetParams {
+            samples: Vec::new(),
+            exclude_samples: Vec::new(),
+            locus_ids: Vec::new(),
+            locus_regex: None,
+            regions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn subsets_keep_the_counts_of_the_selected_samples_and_loci() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut fmr1 = merged_record("FMR1", &[("s1", 5)], &[("s1", "A", 0.2)]);
+        fmr1.reference_region = "chrX:100-200".to_string();
+        let input = write_merged_profile_file(
+            dir.path(),
+            "cohort.tsv",
+            &[
+                merged_record(
+                    "HTT",
+                    &[("s1", 10), ("s2", 20), ("s3", 30)],
+                    &[("s1", "A", 0.5), ("s2", "T", 0.25)],
+                ),
+                merged_record("ATXN1", &[("s1", 10), ("s3", 30)], &[]),
+                fmr1,
+            ],
+        );
+        let out = dir.path().join("subset.tsv");
+        let params = SubsetParams {
+            exclude_samples: vec!["s1".to_string()],
+            locus_regex: Some(Regex::new("^(HTT|FMR1)$").unwrap()),
+            regions: vec!["chr1:150-160".parse().unwrap()],
+            ..keep_all()
+        };
+        subset(input.clone(), out.clone(), params).unwrap();
+        let records = read_merged_profile(&out).unwrap();
+        // counts are copied as they are, without renormalizing
+        let expected = merged_record("HTT", &[("s2", 20), ("s3", 30)], &[("s2", "T", 0.25)]);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].to_json(), expected.to_json());
+
+        // loci without the selected samples are dropped
+        let params = SubsetParams {
+            samples: vec!["s4".to_string()],
+            ..keep_all()
+        };
+        assert!(subset(input, out, params).is_err());
+    }
+
+    #[test]
+    fn bed_regions_and_id_lists_skip_comments() {
+        let dir = tempfile::tempdir().unwrap();
+        let bed = dir.path().join("regions.bed");
+        fs::write(&bed, "track name=loci\n# comment\nchr1\t100\t200\tHTT\n\n").unwrap();
+        let regions = read_bed_regions(&bed).unwrap();
+        assert_eq!(regions.len(), 1);
+        assert_eq!((regions[0].start, regions[0].end), (100, 200));
+        fs::write(&bed, "chr1\t100\n").unwrap();
+        assert!(read_bed_regions(&bed).is_err());
+
+        let ids = dir.path().join("samples.txt");
+        fs::write(&ids, "# cases\ns1\n  s2  \n\n").unwrap();
+        assert_eq!(read_id_list(&ids).unwrap(), ["s1", "s2"]);
+    }
+}
@@ -0,0 +1,138 @@
+//! Fixtures shared by the unit tests of the subcommands
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::merge::{write_merged_profile, MergedProfileRecord};
+use crate::profile::{write_profile, ProfileRecord};
+
+/// A catalog of a CAG and a CGG repeat
+pub const CATALOG: &str = r#"[
+    {"LocusId": "HTT", "LocusStructure": "(CAG)*", "ReferenceRegion": "chr4:3074876-3074933"},
+    {"LocusId": "FMR1", "LocusStructure": "(CGG)*", "ReferenceRegion": "chrX:147912050-147912110"}
+]"#;
+
+/// Writes `CATALOG` into a directory and returns its path
+pub fn write_catalog(dir: &Path) -> PathBuf {
+    let path = dir.join("catalog.json");
+    fs::write(&path, CATALOG).unwrap();
+    path
+}
+
+/// The row of a locus with a CAG motif in a per-sample profile, with
+/// `(interruption, repeat length, count)` interruption counts
+pub fn profile_record(
+    locus_id: &str,
+    read_count: u32,
+    interruption_counts: &[(&str, u32, f64)],
+) -> ProfileRecord {
+    ProfileRecord {
+        locus_id: locus_id.to_string(),
+        reference_region: "chr1:100-200".to_string(),
+        motif: "CAG".to_string(),
+        read_count,
+        interruption_counts: interruption_counts
+            .iter()
+            .map(|(interruption, repeat_len, count)| {
+                (interruption.to_string(), *repeat_len, *count)
+            })
+            .collect(),
+        mean_purity: None,
+        median_purity: None,
+        mean_confidence: None,
+        max_confidence: None,
+        median_copy_number: None,
+    }
+}
+
+/// Writes a per-sample profile into a directory and returns its path
+pub fn write_profile_file(dir: &Path, name: &str, records: &[ProfileRecord]) -> PathBuf {
+    let path = dir.join(name);
+    let mut out = Vec::new();
+    write_profile(&mut out, records).unwrap();
+    fs::write(&path, out).unwrap();
+    path
+}
+
+/// The row of a locus with a CAG motif in a merged profile, with
+/// `(sample, read count)` read counts and `(sample, interruption, normalized
+/// count)` interruption counts
+pub fn merged_record(
+    locus_id: &str,
+    read_counts: &[(&str, u32)],
+    interruption_counts: &[(&str, &str, f64)],
+) -> MergedProfileRecord {
+    MergedProfileRecord {
+        locus_id: locus_id.to_string(),
+        reference_region: "chr1:100-200".to_string(),
+        motif: "CAG".to_string(),
+        read_counts: read_counts
+            .iter()
+            .map(|(sample_id, count)| (sample_id.to_string(), *count))
+            .collect(),
+        interruption_counts: interruption_counts
+            .iter()
+            .map(|(sample_id, interruption, count)| {
+                (sample_id.to_string(), interruption.to_string(), *count)
+            })
+            .collect(),
+    }
+}
+
+/// Writes a merged profile into a directory and returns its path
+pub fn write_merged_profile_file(
+    dir: &Path,
+    name: &str,
+    records: &[MergedProfileRecord],
+) -> PathBuf {
+    let path = dir.join(name);
+    let mut out = Vec::new();
+    write_merged_profile(&mut out, records).unwrap();
+    fs::write(&path, out).unwrap();
+    path
+}
+
+/// The data rows of a tabular strif output, without its schema, provenance
+/// and header lines
+pub fn data_rows(path: &Path) -> Vec<String> {
+    fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .skip(1)
+        .map(str::to_string)
+        .collect()
+}
+
+/// Writes a BAMlet of unmapped reads into a directory and returns its path,
+/// from `(read name, sequence, XG tag)` reads whose bases all have quality 30.
+/// Reads with an empty XG tag are written without one.
+#[cfg(feature = "htslib")]
+pub fn write_bamlet(dir: &Path, reads: &[(&str, &str, &str)]) -> PathBuf {
+    use rust_htslib::bam::{self, header::HeaderRecord, record::Aux};
+
+    let path = dir.join("sample.bamlet.bam");
+    // a header without contigs cannot be read back
+    let mut header = bam::Header::new();
+    header.push_record(
+        HeaderRecord::new(b"SQ")
+            .push_tag(b"SN", "chr1")
+            .push_tag(b"LN", 1000),
+    );
+    let mut writer = bam::Writer::from_path(&path, &header, bam::Format::Bam).unwrap();
+    for (read_name, seq, xg) in reads {
+        let mut record = bam::Record::new();
+        let qual = vec![30; seq.len()];
+        record.set(read_name.as_bytes(), None, seq.as_bytes(), &qual);
+        record.set_tid(-1);
+        record.set_pos(-1);
+        record.set_mtid(-1);
+        record.set_mpos(-1);
+        record.set_unmapped();
+        if !xg.is_empty() {
+            record.push_aux(b"XG", Aux::String(xg)).unwrap();
+        }
+        writer.write(&record).unwrap();
+    }
+    path
+}
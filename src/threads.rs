@@ -0,0 +1,42 @@
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// The number of threads set with `--threads`, or 0 to use every available core
+static THREADS: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of cores strif may use. Unlike the number of cores of the
+/// machine, this respects the CPU affinity and the cgroup CPU quota that
+/// containers and batch schedulers such as Slurm or Kubernetes set.
+pub fn available_cores() -> usize {
+    thread::available_parallelism().map_or(1, NonZeroUsize::get)
+}
+
+/// Sets the number of threads used by all subcommands
+pub fn set_threads(threads: NonZeroUsize) {
+    THREADS.store(threads.get(), Ordering::Relaxed);
+}
+
+/// The number of threads subcommands should use, which defaults to the
+/// available cores
+pub fn threads() -> usize {
+    match THREADS.load(Ordering::Relaxed) {
+        0 => available_cores(),
+        threads => threads,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_thread_count_defaults_to_the_available_cores() {
+        assert!(available_cores() >= 1);
+        if THREADS.load(Ordering::Relaxed) == 0 {
+            assert_eq!(threads(), available_cores());
+        }
+        set_threads(NonZeroUsize::new(1).unwrap());
+        assert_eq!(threads(), 1);
+    }
+}
@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use log::{info, warn};
+
+use crate::convert::read_merged_profile_any;
+use crate::merge::{load_manifest, MergedProfileRecord};
+use crate::plan::Plan;
+use crate::utils::{create_output, get_default_out_path, reference_span, Region};
+
+/// The groups of samples written to a track each, by case-control status
+pub(crate) const GROUPS: [&str; 2] = ["case", "control"];
+
+/// The path of the track of a group of samples: the output path with the
+/// group inserted before its extension
+pub fn group_path(out_path: &Path, group: &str) -> PathBuf {
+    let name = out_path.file_name().unwrap_or_default().to_string_lossy();
+    let name = match name.rsplit_once('.') {
+        Some((stem, extension)) => format!("{}.{}s.{}", stem, group, extension),
+        None => format!("{}.{}s", name, group),
+    };
+    out_path.with_file_name(name)
+}
+
+/// The tracks written to an output path with their labels: one per group
+/// with a manifest, otherwise one for all samples
+pub fn track_paths(out_path: &Path, grouped: bool) -> Vec<(String, PathBuf)> {
+    match grouped {
+        true => GROUPS
+            .iter()
+            .map(|group| (format!("{} track", group), group_path(out_path, group)))
+            .collect(),
+        false => vec![("track".to_string(), out_path.to_path_buf())],
+    }
+}
+
+/// The mean over the samples of each group of their normalized interruption
+/// burden at a locus, i.e. the sum of their normalized interruption counts.
+/// Samples with reads at the locus but no interruptions count as zero.
+pub(crate) fn mean_burdens(
+    record: &MergedProfileRecord,
+    groups: Option<&HashMap<String, usize>>,
+) -> Vec<Option<f64>> {
+    let mut burdens: HashMap<&str, f64> = HashMap::new();
+    for (sample_id, _, count) in &record.interruption_counts {
+        *burdens.entry(sample_id).or_default() += count;
+    }
+    let group_count = if groups.is_some() { GROUPS.len() } else { 1 };
+    let mut sums = vec![0.0; group_count];
+    let mut samples = vec![0usize; group_count];
+    for (sample_id, _) in &record.read_counts {
+        let group = match groups {
+            Some(groups) => match groups.get(sample_id) {
+                Some(group) => *group,
+                None => continue,
+            },
+            None => 0,
+        };
+        sums[group] += burdens.get(sample_id.as_str()).copied().unwrap_or(0.0);
+        samples[group] += 1;
+    }
+    sums.into_iter()
+        .zip(samples)
+        .map(|(sum, samples)| (samples > 0).then(|| sum / samples as f64))
+        .collect()
+}
+
+/// Writes the mean normalized interruption burden of each locus of a merged
+/// profile as a bedGraph track, or with a manifest, a track of the cases and
+/// one of the controls. Loci are sorted by chromosome name and position, as
+/// `bedGraphToBigWig` expects, and loci without a parseable reference region
+/// are left out.
+pub fn track(input: PathBuf, out_path: PathBuf, manifest: Option<PathBuf>) -> Result<()> {
+    let groups: Option<HashMap<String, usize>> = match &manifest {
+        Some(manifest) => Some(
+            load_manifest(manifest)?
+                .into_iter()
+                .filter_map(|entry| {
+                    let status = entry.status.to_lowercase();
+                    let group = GROUPS.iter().position(|group| *group == status)?;
+                    Some((entry.sample_id, group))
+                })
+                .collect(),
+        ),
+        None => None,
+    };
+
+    info!("Reading {}...", input.display());
+    let records = read_merged_profile_any(&input)?;
+    let mut loci: Vec<(Region, Vec<Option<f64>>)> = Vec::new();
+    let mut unplaced = 0;
+    for record in &records {
+        match reference_span(&record.reference_region) {
+            Some(span) => loci.push((span, mean_burdens(record, groups.as_ref()))),
+            None => unplaced += 1,
+        }
+    }
+    if unplaced > 0 {
+        warn!(
+            unplaced;
+            "Left out {} loci without a parseable reference region",
+            unplaced
+        );
+    }
+    loci.sort_by(|a, b| a.0.cmp(&b.0));
+    let overlapping = loci
+        .windows(2)
+        .filter(|pair| pair[0].0.overlaps(&pair[1].0))
+        .count();
+    if overlapping > 0 {
+        warn!(
+            overlapping;
+            "{} loci overlap the next locus, which bedGraphToBigWig does not accept",
+            overlapping
+        );
+    }
+
+    let tracks: Vec<(PathBuf, String)> = match &groups {
+        Some(_) => GROUPS
+            .iter()
+            .map(|group| (group_path(&out_path, group), format!("{}s", group)))
+            .collect(),
+        None => vec![(out_path.clone(), "all samples".to_string())],
+    };
+    let name = input
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    for (i, (path, samples)) in tracks.iter().enumerate() {
+        let mut out_file = create_output(path)?;
+        writeln!(
+            out_file,
+            "track type=bedGraph name=\"{} {}\" description=\"Mean normalized interruption burden of {} in {}\"",
+            name, samples, samples, name
+        )?;
+        let mut written = 0;
+        for (span, burdens) in &loci {
+            if let Some(burden) = burdens[i] {
+                writeln!(
+                    out_file,
+                    "{}\t{}\t{}\t{}",
+                    span.chrom, span.start, span.end, burden
+                )?;
+                written += 1;
+            }
+        }
+        out_file.finish()?;
+        if written == 0 {
+            bail!("No loci of {} have {}", input.display(), samples);
+        }
+        info!(
+            loci = written;
+            "Wrote the burden of {} at {} loci to {}",
+            samples,
+            written,
+            path.display()
+        );
+    }
+
+    info!("Done!");
+    Ok(())
+}
+
+/// The command line arguments of `strif track`
+#[derive(Debug, clap::Args)]
+pub struct TrackArgs {
+    /// The path to the merged profile, in any format supported by `convert`
+    pub input: PathBuf,
+
+    /// The path to the bedGraph file. Defaults to the same directory as the input.
+    /// With --manifest, the tracks of cases and controls are written next to it.
+    pub output: Option<PathBuf>,
+
+    /// The path to the manifest file. Writes a track of the cases and one of the
+    /// controls instead of one of all samples.
+    #[clap(short = 'm', long)]
+    pub manifest: Option<PathBuf>,
+}
+
+impl TrackArgs {
+    /// Runs `strif track`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let TrackArgs {
+            input,
+            output,
+            manifest,
+        } = self;
+        let out_path: PathBuf =
+            output.unwrap_or_else(|| get_default_out_path(&input, "burden", "bedGraph"));
+        let mut plan = Plan::new("track", dry_run, force);
+        plan.records("input", &input, "loci")
+            .maybe_input("manifest", manifest.as_deref());
+        for (label, path) in track_paths(&out_path, manifest.is_some()) {
+            plan.output(&label, &path);
+        }
+        plan.execute(|| track(input, out_path, manifest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::testing::{merged_record, write_merged_profile_file};
+
+    #[test]
+    fn tracks_hold_the_mean_burden_of_each_group() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut atxn1 = merged_record("ATXN1", &[("s1", 10)], &[("s1", "A", 0.5)]);
+        atxn1.reference_region = "chr1:50-80".to_string();
+        let mut unplaced = merged_record("FMR1", &[("s1", 10)], &[]);
+        unplaced.reference_region = "unplaced".to_string();
+        let input = write_merged_profile_file(
+            dir.path(),
+            "cohort.tsv",
+            &[
+                merged_record(
+                    "HTT",
+                    &[("s1", 10), ("s2", 20), ("s3", 30)],
+                    &[("s1", "A", 0.5), ("s1", "T", 0.25), ("s2", "A", 0.25)],
+                ),
+                atxn1,
+                unplaced,
+            ],
+        );
+        let out = dir.path().join("burden.bedgraph");
+        track(input.clone(), out.clone(), None).unwrap();
+        // loci are sorted by position, and samples without interruptions count as zero
+        let lines: Vec<String> = fs::read_to_string(&out)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(str::to_string)
+            .collect();
+        assert_eq!(
+            lines,
+            ["chr1\t50\t80\t0.5", "chr1\t100\t200\t0.3333333333333333"]
+        );
+
+        let manifest = dir.path().join("manifest.tsv");
+        fs::write(
+            &manifest,
+            "s1\tcase\ts1.profile\ns2\tcase\ts2.profile\ns3\tcontrol\ts3.profile\n",
+        )
+        .unwrap();
+        track(input, out.clone(), Some(manifest)).unwrap();
+        let cases = fs::read_to_string(group_path(&out, "case")).unwrap();
+        assert!(cases.ends_with("chr1\t100\t200\t0.5\n"));
+        // the controls have no reads at ATXN1
+        let controls = fs::read_to_string(dir.path().join("burden.controls.bedgraph")).unwrap();
+        assert_eq!(
+            controls.lines().skip(1).collect::<Vec<_>>(),
+            ["chr1\t100\t200\t0"]
+        );
+    }
+}
@@ -1,5 +1,16 @@
-use std::path::PathBuf;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
+use flate2::read::MultiGzDecoder;
+use flate2::{write::DeflateEncoder, Compression, Crc};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::sidecar;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct AlignmentScoreParams {
     pub match_score: i32,
     pub mismatch_penalty: i32,
@@ -7,9 +18,238 @@ pub struct AlignmentScoreParams {
     pub gap_extend_penalty: i32,
 }
 
-pub fn get_default_out_path(input: &PathBuf, suffix: &str, ext: &str) -> PathBuf {
-    let mut out_path: PathBuf = input.clone();
-    let mut file_prefix = input.file_stem().unwrap().to_str().unwrap();
+/// The scores used by `profile` unless others are given
+impl Default for AlignmentScoreParams {
+    fn default() -> Self {
+        AlignmentScoreParams {
+            match_score: 1,
+            mismatch_penalty: 8,
+            gap_open_penalty: 10,
+            gap_extend_penalty: 1,
+        }
+    }
+}
+
+/// Opens a plain or gzip/BGZF-compressed text file, detected from its first bytes
+pub(crate) fn open_maybe_gzipped(path: &Path) -> std::io::Result<Box<dyn BufRead>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// The largest amount of uncompressed data in a BGZF block, as used by bgzip
+const BGZF_BLOCK_SIZE: usize = 0xff00;
+
+/// The empty block that marks the end of a BGZF file
+const BGZF_EOF: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Writes the BGZF format of bgzip: a series of gzip members of at most 64 KB
+/// each, so that a reader can seek to any member and decompress from there.
+/// Positions are virtual offsets, with the offset of a member in the file in
+/// the upper 48 bits and the offset within its uncompressed data in the
+/// lower 16.
+pub(crate) struct BgzfWriter<W: Write> {
+    inner: W,
+    block: Vec<u8>,
+    /// The number of compressed bytes written so far
+    compressed_offset: u64,
+}
+
+impl<W: Write> BgzfWriter<W> {
+    pub fn new(inner: W) -> Self {
+        BgzfWriter {
+            inner,
+            block: Vec::with_capacity(BGZF_BLOCK_SIZE),
+            compressed_offset: 0,
+        }
+    }
+
+    /// The virtual offset of the next byte written
+    pub fn virtual_offset(&self) -> u64 {
+        (self.compressed_offset << 16) | self.block.len() as u64
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&self.block)?;
+        let compressed = encoder.finish()?;
+        let mut crc = Crc::new();
+        crc.update(&self.block);
+
+        // gzip header with the BC extra field holding the member size minus one
+        let block_size = 18 + compressed.len() + 8;
+        let mut header: [u8; 18] = [
+            0x1f, 0x8b, 0x08, 0x04, 0, 0, 0, 0, 0, 0xff, 0x06, 0x00, b'B', b'C', 0x02, 0x00, 0, 0,
+        ];
+        header[16..].copy_from_slice(&((block_size - 1) as u16).to_le_bytes());
+        self.inner.write_all(&header)?;
+        self.inner.write_all(&compressed)?;
+        self.inner.write_all(&crc.sum().to_le_bytes())?;
+        self.inner
+            .write_all(&(self.block.len() as u32).to_le_bytes())?;
+
+        self.compressed_offset += block_size as u64;
+        self.block.clear();
+        Ok(())
+    }
+
+    /// Writes the last block and the end-of-file marker, returning the
+    /// underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.block.is_empty() {
+            self.write_block()?;
+        }
+        self.inner.write_all(&BGZF_EOF)?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for BgzfWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(BGZF_BLOCK_SIZE - self.block.len());
+        self.block.extend_from_slice(&buf[..n]);
+        // writing full blocks eagerly keeps virtual offsets within a block
+        if self.block.len() == BGZF_BLOCK_SIZE {
+            self.write_block()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Whether a path has a `.gz` extension, which outputs that support it are
+/// written BGZF-compressed for
+pub(crate) fn is_gzipped_path(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("gz"))
+}
+
+/// The suffix of the temporary files that outputs are written to
+pub(crate) const TMP_SUFFIX: &str = ".strif-tmp";
+
+/// An output file whose writes are buffered and go to a hidden temporary
+/// file next to it, which [`OutputFile::finish`] renames into place. A run
+/// that fails or is killed midway thus never leaves a truncated output for
+/// later steps to consume. The temporary file is removed if the output is
+/// dropped without being finished, and is overwritten by the next run if
+/// the process was killed.
+pub(crate) struct OutputFile {
+    writer: Option<BufWriter<File>>,
+    path: PathBuf,
+    /// None when writing directly to the destination, e.g. /dev/stdout
+    tmp_path: Option<PathBuf>,
+}
+
+impl OutputFile {
+    /// Flushes the output and moves it to its destination, writing its
+    /// sidecar if `--checksums` was given
+    pub fn finish(self) -> io::Result<()> {
+        // outputs written to special files such as /dev/stdout get no sidecar
+        let write_sidecar = sidecar::is_enabled() && self.tmp_path.is_some();
+        let path = self.path.clone();
+        self.finish_without_sidecar()?;
+        if write_sidecar {
+            sidecar::write_sidecar(&path).map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the output and moves it to its destination
+    pub fn finish_without_sidecar(mut self) -> io::Result<()> {
+        let mut writer = self.writer.take().unwrap();
+        writer.flush()?;
+        drop(writer);
+        if let Some(tmp_path) = self.tmp_path.take() {
+            std::fs::rename(&tmp_path, &self.path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.as_mut().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.as_mut().unwrap().flush()
+    }
+}
+
+impl Drop for OutputFile {
+    fn drop(&mut self) {
+        // close the file before removing it, which Windows requires
+        self.writer.take();
+        if let Some(tmp_path) = &self.tmp_path {
+            let _ = std::fs::remove_file(tmp_path);
+        }
+    }
+}
+
+/// Returns true for special files such as pipes and devices, which outputs
+/// are written to rather than replaced. Paths under /dev such as /dev/stdout
+/// are special even when they resolve to a regular file, e.g. a redirection.
+pub fn is_special_file(path: &Path) -> bool {
+    path.starts_with("/dev") || std::fs::metadata(path).is_ok_and(|metadata| !metadata.is_file())
+}
+
+/// Creates an output file. Its writes are only visible at its path once
+/// [`OutputFile::finish`] is called.
+pub(crate) fn create_output(path: &Path) -> io::Result<OutputFile> {
+    // special files such as pipes cannot be replaced by a rename
+    let is_special = is_special_file(path);
+    let file_name = path.file_name().map(|name| name.to_string_lossy());
+    let tmp_path = match file_name {
+        Some(file_name) if !is_special => {
+            Some(path.with_file_name(format!(".{}{}", file_name, TMP_SUFFIX)))
+        }
+        _ => None,
+    };
+    let file = File::create(tmp_path.as_deref().unwrap_or(path))?;
+    Ok(OutputFile {
+        writer: Some(BufWriter::new(file)),
+        path: path.to_path_buf(),
+        tmp_path,
+    })
+}
+
+/// Removes the temporary files and scratch directories that runs killed
+/// midway left in a directory and returns how many there were
+pub(crate) fn remove_stale_temps(dir: &Path) -> io::Result<usize> {
+    let mut removed = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if !name.starts_with('.') || !name.ends_with(TMP_SUFFIX) {
+            continue;
+        }
+        let file_type = entry.file_type()?;
+        if file_type.is_file() {
+            std::fs::remove_file(entry.path())?;
+        } else if file_type.is_dir() {
+            std::fs::remove_dir_all(entry.path())?;
+        } else {
+            continue;
+        }
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+pub fn get_default_out_path(input: &Path, suffix: &str, ext: &str) -> PathBuf {
+    let mut out_path: PathBuf = input.to_path_buf();
+    let file_stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    let mut file_prefix: &str = &file_stem;
 
     // extract text before first period
     if let Some(period_idx) = file_prefix.find('.') {
@@ -19,3 +259,367 @@ pub fn get_default_out_path(input: &PathBuf, suffix: &str, ext: &str) -> PathBuf
     out_path.set_file_name(format!("{}.{}.{}", file_prefix, suffix, ext));
     out_path
 }
+
+/// A deterministic subset of loci, written as `i/N` on the command line
+/// with 1 <= i <= N
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    pub index: u32,
+    pub count: u32,
+}
+
+impl Shard {
+    /// The shard of `count` shards that a locus belongs to
+    pub fn of(locus_id: &str, count: u32) -> Self {
+        let index = (stable_hash(locus_id) % count as u64) as u32 + 1;
+        Self { index, count }
+    }
+
+    /// Returns true if the locus belongs to this shard
+    pub fn contains(&self, locus_id: &str) -> bool {
+        stable_hash(locus_id) % self.count as u64 == (self.index - 1) as u64
+    }
+}
+
+impl FromStr for Shard {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (index, count) = s
+            .split_once('/')
+            .ok_or_else(|| format!("expected a shard of the form i/N, got '{}'", s))?;
+        let index: u32 = index
+            .parse()
+            .map_err(|_| format!("invalid shard index '{}'", index))?;
+        let count: u32 = count
+            .parse()
+            .map_err(|_| format!("invalid shard count '{}'", count))?;
+        if count == 0 || index == 0 || index > count {
+            return Err(format!("shard index must be between 1 and {}", count));
+        }
+        Ok(Self { index, count })
+    }
+}
+
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+impl Serialize for Shard {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Shard {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_from_str(deserializer)
+    }
+}
+
+/// An amount of memory, written as a number of bytes with an optional K, M,
+/// G or T suffix for KiB, MiB, GiB or TiB (e.g. `64G` or `1.5T`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteSize(pub u64);
+
+impl FromStr for ByteSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let upper = s.trim().to_uppercase();
+        let number = upper
+            .trim_end_matches('B')
+            .trim_end_matches('I')
+            .trim_end_matches(['K', 'M', 'G', 'T']);
+        let multiplier: u64 = match &upper[number.len()..] {
+            "" | "B" => 1,
+            "K" | "KB" | "KIB" => 1 << 10,
+            "M" | "MB" | "MIB" => 1 << 20,
+            "G" | "GB" | "GIB" => 1 << 30,
+            "T" | "TB" | "TIB" => 1 << 40,
+            unit => return Err(format!("invalid unit '{}' in '{}'", unit, s)),
+        };
+        let number: f64 = number
+            .trim()
+            .parse()
+            .map_err(|_| format!("expected an amount of memory such as 64G, got '{}'", s))?;
+        if !number.is_finite() || number <= 0.0 {
+            return Err(format!("amount of memory must be positive, got '{}'", s));
+        }
+        Ok(Self((number * multiplier as f64) as u64))
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+        let mut size = self.0 as f64;
+        let mut unit = 0;
+        while size >= 1024.0 && unit < UNITS.len() - 1 {
+            size /= 1024.0;
+            unit += 1;
+        }
+        if unit == 0 {
+            write!(f, "{} B", self.0)
+        } else {
+            write!(f, "{:.1} {}", size, UNITS[unit])
+        }
+    }
+}
+
+/// Written as a number of bytes, which reads back exactly
+impl Serialize for ByteSize {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for ByteSize {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_from_str(deserializer)
+    }
+}
+
+/// Deserializes a value from the string it is written as on the command
+/// line, e.g. `2/8` for a shard or `64G` for an amount of memory
+fn deserialize_from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(de::Error::custom)
+}
+
+/// Defines a builder method for each field of an options struct, taking the
+/// value of the field or, for an `Option` field, the value it is set to
+macro_rules! builder_methods {
+    () => {};
+    ($field:ident: Option<$ty:ty>, $($rest:tt)*) => {
+        #[doc = concat!("Sets `", stringify!($field), "`")]
+        pub fn $field(mut self, $field: impl Into<$ty>) -> Self {
+            self.$field = Some($field.into());
+            self
+        }
+        $crate::utils::builder_methods!($($rest)*);
+    };
+    ($field:ident: $ty:ty, $($rest:tt)*) => {
+        #[doc = concat!("Sets `", stringify!($field), "`")]
+        pub fn $field(mut self, $field: $ty) -> Self {
+            self.$field = $field;
+            self
+        }
+        $crate::utils::builder_methods!($($rest)*);
+    };
+}
+pub(crate) use builder_methods;
+
+/// FNV-1a hash, which unlike the standard library hasher is stable across
+/// platforms and Rust versions
+pub fn stable_hash(s: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in s.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// The file format of a tabular output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// Tab-separated values with packed count columns
+    #[default]
+    Tsv,
+    /// One JSON object per locus with nested count objects
+    Jsonl,
+    /// Arrow IPC record batches with the TSV columns, written to stdout unless
+    /// an output path is given (requires the `arrow` feature)
+    ArrowStream,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Tsv => "tsv",
+            OutputFormat::Jsonl => "jsonl",
+            OutputFormat::ArrowStream => "arrows",
+        }
+    }
+
+    /// The path outputs in this format are written to if none is given, or
+    /// None to write them next to the input
+    pub fn default_out_path(&self) -> Option<PathBuf> {
+        match self {
+            OutputFormat::ArrowStream => Some(PathBuf::from("/dev/stdout")),
+            OutputFormat::Tsv | OutputFormat::Jsonl => None,
+        }
+    }
+}
+
+/// A genomic region in the `chrom:start-end` format used by ReferenceRegion
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Region {
+    pub chrom: String,
+    pub start: u64,
+    pub end: u64,
+}
+
+impl Region {
+    pub fn overlaps(&self, other: &Region) -> bool {
+        self.chrom == other.chrom && self.start < other.end && other.start < self.end
+    }
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (chrom, range) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("expected a region of the form chrom:start-end, got '{}'", s))?;
+        let (start, end) = range
+            .split_once('-')
+            .ok_or_else(|| format!("expected a region of the form chrom:start-end, got '{}'", s))?;
+        let start: u64 = start
+            .replace(',', "")
+            .parse()
+            .map_err(|_| format!("invalid start position in region '{}'", s))?;
+        let end: u64 = end
+            .replace(',', "")
+            .parse()
+            .map_err(|_| format!("invalid end position in region '{}'", s))?;
+        if end < start {
+            return Err(format!("region '{}' ends before it starts", s));
+        }
+        Ok(Self {
+            chrom: chrom.to_string(),
+            start,
+            end,
+        })
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}-{}", self.chrom, self.start, self.end)
+    }
+}
+
+/// A key ordering chromosomes naturally, so that chr2 comes before chr10 and
+/// the numbered chromosomes before X, Y and M
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct ChromKey(u8, u64, String);
+
+impl ChromKey {
+    pub fn of(chrom: &str) -> Self {
+        let name = chrom.strip_prefix("chr").unwrap_or(chrom);
+        match name {
+            "X" => ChromKey(1, 0, String::new()),
+            "Y" => ChromKey(2, 0, String::new()),
+            "M" | "MT" => ChromKey(3, 0, String::new()),
+            _ => match name.parse::<u64>() {
+                Ok(n) => ChromKey(0, n, String::new()),
+                Err(_) => ChromKey(4, 0, chrom.to_string()),
+            },
+        }
+    }
+}
+
+/// The span of a reference region, which lists one or more intervals of a
+/// chromosome separated by commas, or None if it cannot be parsed
+pub(crate) fn reference_span(reference_region: &str) -> Option<Region> {
+    let mut span: Option<Region> = None;
+    for interval in reference_region.split(',') {
+        let interval: Region = interval.trim().parse().ok()?;
+        span = Some(match span {
+            None => interval,
+            Some(span) if span.chrom == interval.chrom => Region {
+                start: span.start.min(interval.start),
+                end: span.end.max(interval.end),
+                chrom: span.chrom,
+            },
+            Some(_) => return None,
+        });
+    }
+    span
+}
+
+/// Sorts items by the coordinates of their reference regions, keeping the
+/// order of items with the same coordinates and placing those whose
+/// reference region cannot be parsed last
+pub(crate) fn sort_by_coordinates<T>(items: &mut [T], reference_region: impl Fn(&T) -> &str) {
+    items.sort_by_cached_key(|item| {
+        let span = reference_span(reference_region(item));
+        (
+            span.is_none(),
+            span.map(|span| (ChromKey::of(&span.chrom), span.start, span.end)),
+        )
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn outputs_only_appear_once_finished() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("merged.tsv");
+        fs::write(&path, "old\n").unwrap();
+
+        let mut out = create_output(&path).unwrap();
+        writeln!(out, "new").unwrap();
+        out.flush().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old\n");
+        assert!(dir.path().join(".merged.tsv.strif-tmp").exists());
+        out.finish().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+        assert!(!dir.path().join(".merged.tsv.strif-tmp").exists());
+    }
+
+    #[test]
+    fn abandoned_outputs_leave_no_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("merged.tsv");
+        let mut out = create_output(&path).unwrap();
+        writeln!(out, "partial").unwrap();
+        drop(out);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn stale_temporary_files_are_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(".merged.tsv.strif-tmp"), "partial").unwrap();
+        fs::create_dir(dir.path().join(".scratch.strif-tmp")).unwrap();
+        fs::write(dir.path().join("merged.tsv"), "done").unwrap();
+        fs::write(dir.path().join(".hidden"), "").unwrap();
+
+        assert_eq!(remove_stale_temps(dir.path()).unwrap(), 2);
+        let mut left: Vec<String> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        left.sort();
+        assert_eq!(left, [".hidden", "merged.tsv"]);
+    }
+
+    #[test]
+    fn memory_budgets_are_parsed_with_binary_units() {
+        assert_eq!("512".parse::<ByteSize>(), Ok(ByteSize(512)));
+        assert_eq!("64k".parse::<ByteSize>(), Ok(ByteSize(64 << 10)));
+        assert_eq!("1.5G".parse::<ByteSize>(), Ok(ByteSize(3 << 29)));
+        assert_eq!("2GiB".parse::<ByteSize>(), Ok(ByteSize(2 << 30)));
+        assert!("64X".parse::<ByteSize>().is_err());
+        assert!("0M".parse::<ByteSize>().is_err());
+        assert!("lots".parse::<ByteSize>().is_err());
+    }
+}
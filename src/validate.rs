@@ -0,0 +1,437 @@
+use std::io::prelude::*;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+use lazy_static::lazy_static;
+use log::info;
+use regex::Regex;
+use serde_json::Value;
+
+use crate::interruptions::is_iupac_code;
+use crate::plan::Plan;
+use crate::utils::Region;
+
+/// Checks a catalog, manifest and read depths file, reporting every problem
+/// found rather than stopping at the first one
+pub fn validate(
+    str_catalog: Option<PathBuf>,
+    fai: Option<PathBuf>,
+    manifest: Option<PathBuf>,
+    read_depths: Option<PathBuf>,
+) -> Result<()> {
+    if str_catalog.is_none() && manifest.is_none() && read_depths.is_none() {
+        bail!(
+            "Nothing to validate, provide at least one of --catalog, --manifest or --read-depths"
+        );
+    }
+
+    let mut problems: Vec<String> = Vec::new();
+
+    if let Some(str_catalog) = &str_catalog {
+        info!("Validating STR catalog...");
+        let contig_lengths = match &fai {
+            Some(fai) => Some(load_fai(fai)?),
+            None => None,
+        };
+        validate_catalog(str_catalog, contig_lengths.as_ref(), &mut problems)?;
+    }
+
+    let mut manifest_samples: Vec<String> = Vec::new();
+    if let Some(manifest) = &manifest {
+        info!("Validating manifest...");
+        manifest_samples = validate_manifest(manifest, &mut problems)?;
+    }
+
+    if let Some(read_depths) = &read_depths {
+        info!("Validating read depths...");
+        let depth_samples = validate_read_depths(read_depths, &mut problems)?;
+        for sample_id in &manifest_samples {
+            if !depth_samples.contains(sample_id) {
+                problems.push(format!(
+                    "{}: sample {} from the manifest has no read depth",
+                    read_depths.display(),
+                    sample_id
+                ));
+            }
+        }
+    }
+
+    if problems.is_empty() {
+        info!("No problems found");
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    bail!("Found {} problem(s)", problems.len())
+}
+
+/// Loads contig lengths from a FASTA index
+pub fn load_fai(fai: &Path) -> Result<HashMap<String, u64>> {
+    let mut contig_lengths: HashMap<String, u64> = HashMap::new();
+    for (i, line) in BufReader::new(File::open(fai)?).lines().enumerate() {
+        let line = line?;
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 2 {
+            bail!(
+                "{} line {} is not a valid FASTA index line",
+                fai.display(),
+                i + 1
+            );
+        }
+        let length: u64 = match fields[1].parse() {
+            Ok(length) => length,
+            Err(_) => bail!("{} line {} has an invalid length", fai.display(), i + 1),
+        };
+        contig_lengths.insert(fields[0].to_string(), length);
+    }
+    Ok(contig_lengths)
+}
+
+pub(crate) fn validate_catalog(
+    str_catalog: &Path,
+    contig_lengths: Option<&HashMap<String, u64>>,
+    problems: &mut Vec<String>,
+) -> Result<()> {
+    lazy_static! {
+        static ref RE_STRUCTURE: Regex = Regex::new(r"^\(([A-Za-z]+)\)\*$").unwrap();
+    }
+
+    let catalog: Value = serde_json::from_reader(BufReader::new(File::open(str_catalog)?))?;
+    let entries = match catalog.as_array() {
+        Some(entries) => entries,
+        None => {
+            problems.push(format!(
+                "{}: the catalog should be a JSON array of loci",
+                str_catalog.display()
+            ));
+            return Ok(());
+        }
+    };
+
+    let mut seen_ids: HashSet<&str> = HashSet::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let mut problem = |message: String| {
+            problems.push(format!(
+                "{}: entry {}: {}",
+                str_catalog.display(),
+                i + 1,
+                message
+            ))
+        };
+
+        let locus_id = match entry.get("LocusId").and_then(Value::as_str) {
+            Some(locus_id) => locus_id,
+            None => {
+                problem("missing a string LocusId".to_string());
+                continue;
+            }
+        };
+        if !seen_ids.insert(locus_id) {
+            problem(format!("duplicate LocusId {}", locus_id));
+        }
+
+        match entry.get("LocusStructure").and_then(Value::as_str) {
+            Some(structure) => match RE_STRUCTURE.captures(structure) {
+                Some(captures) => {
+                    let motif = &captures[1];
+                    if let Some(base) = motif
+                        .chars()
+                        .find(|c| !c.is_ascii() || !is_iupac_code(*c as u8))
+                    {
+                        problem(format!(
+                            "{} has a motif with an unexpected base '{}', expected A, C, G, T or an IUPAC ambiguity code",
+                            locus_id, base
+                        ));
+                    }
+                }
+                None => problem(format!(
+                    "{} has a LocusStructure '{}' that is not a single repeat of the form (MOTIF)*",
+                    locus_id, structure
+                )),
+            },
+            None => problem(format!("{} is missing a string LocusStructure", locus_id)),
+        }
+
+        let candidate_motifs: Vec<Option<&str>> = match entry.get("CandidateMotifs") {
+            None | Some(Value::Null) => Vec::new(),
+            Some(Value::String(motifs)) => motifs.split(',').map(Some).collect(),
+            Some(Value::Array(motifs)) => motifs.iter().map(Value::as_str).collect(),
+            Some(_) => vec![None],
+        };
+        for motif in candidate_motifs {
+            match motif.map(str::trim) {
+                Some(motif) if !motif.is_empty() => {
+                    if let Some(base) = motif
+                        .chars()
+                        .find(|c| !c.is_ascii() || !is_iupac_code(*c as u8))
+                    {
+                        problem(format!(
+                            "{} has a candidate motif '{}' with an unexpected base '{}'",
+                            locus_id, motif, base
+                        ));
+                    }
+                }
+                _ => problem(format!(
+                    "{} has CandidateMotifs that are not a list of motifs",
+                    locus_id
+                )),
+            }
+        }
+
+        match entry.get("ReferenceRegion").and_then(Value::as_str) {
+            Some(region) => match region.parse::<Region>() {
+                Ok(region) => {
+                    if let Some(contig_lengths) = contig_lengths {
+                        match contig_lengths.get(&region.chrom) {
+                            Some(length) if region.end > *length => problem(format!(
+                                "{} has a ReferenceRegion {} beyond the end of {} ({} bp)",
+                                locus_id, region, region.chrom, length
+                            )),
+                            Some(_) => {}
+                            None => problem(format!(
+                                "{} has a ReferenceRegion on {}, which is not in the FASTA index",
+                                locus_id, region.chrom
+                            )),
+                        }
+                    }
+                }
+                Err(e) => problem(format!(
+                    "{} has an invalid ReferenceRegion: {}",
+                    locus_id, e
+                )),
+            },
+            None => problem(format!("{} is missing a string ReferenceRegion", locus_id)),
+        }
+    }
+    Ok(())
+}
+
+/// Validates the manifest and returns its sample IDs
+pub(crate) fn validate_manifest(
+    manifest: &Path,
+    problems: &mut Vec<String>,
+) -> Result<Vec<String>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(false)
+        .flexible(true)
+        .comment(Some(b'#'))
+        .from_path(manifest)?;
+
+    let mut sample_ids: Vec<String> = Vec::new();
+    for result in reader.records() {
+        let record = result?;
+        let line = record.position().map(|p| p.line()).unwrap_or(0);
+        if record.len() < 3 {
+            problems.push(format!(
+                "{}: line {} has {} columns, expected sample ID, status and profile path",
+                manifest.display(),
+                line,
+                record.len()
+            ));
+            continue;
+        }
+        let sample_id = record.get(0).unwrap();
+        if sample_ids.iter().any(|s| s == sample_id) {
+            problems.push(format!(
+                "{}: line {} has a duplicate sample ID {}",
+                manifest.display(),
+                line,
+                sample_id
+            ));
+        }
+        sample_ids.push(sample_id.to_string());
+
+        let profile_path = Path::new(record.get(2).unwrap());
+        if !profile_path.is_file() {
+            problems.push(format!(
+                "{}: line {} refers to a profile that does not exist: {}",
+                manifest.display(),
+                line,
+                profile_path.display()
+            ));
+        }
+    }
+    Ok(sample_ids)
+}
+
+/// Validates the read depths file and returns its sample IDs
+pub(crate) fn validate_read_depths(
+    read_depths: &Path,
+    problems: &mut Vec<String>,
+) -> Result<HashSet<String>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(false)
+        .flexible(true)
+        .comment(Some(b'#'))
+        .from_path(read_depths)?;
+
+    let mut sample_ids: HashSet<String> = HashSet::new();
+    for result in reader.records() {
+        let record = result?;
+        let line = record.position().map(|p| p.line()).unwrap_or(0);
+        if record.len() < 2 {
+            problems.push(format!(
+                "{}: line {} has {} columns, expected sample ID and read depth",
+                read_depths.display(),
+                line,
+                record.len()
+            ));
+            continue;
+        }
+        let sample_id = record.get(0).unwrap();
+        if !sample_ids.insert(sample_id.to_string()) {
+            problems.push(format!(
+                "{}: line {} has a duplicate sample ID {}",
+                read_depths.display(),
+                line,
+                sample_id
+            ));
+        }
+        match record.get(1).unwrap().parse::<f64>() {
+            Ok(depth) if depth > 0.0 && depth.is_finite() => {}
+            _ => problems.push(format!(
+                "{}: line {} has an invalid read depth '{}' for {}",
+                read_depths.display(),
+                line,
+                record.get(1).unwrap(),
+                sample_id
+            )),
+        }
+    }
+    Ok(sample_ids)
+}
+
+/// The command line arguments of `strif validate`
+#[derive(Debug, clap::Args)]
+pub struct ValidateArgs {
+    /// The path to a JSON file containing the catalog of repeat loci
+    #[clap(short = 'c', long = "catalog")]
+    pub str_catalog: Option<PathBuf>,
+
+    /// The path to a FASTA index (.fai) used to check catalog reference regions
+    #[clap(long, requires = "str_catalog")]
+    pub fai: Option<PathBuf>,
+
+    /// The path to the manifest file used by merge
+    #[clap(short = 'm', long)]
+    pub manifest: Option<PathBuf>,
+
+    /// The path to the read depths file used by merge
+    #[clap(short = 'd', long)]
+    pub read_depths: Option<PathBuf>,
+}
+
+impl ValidateArgs {
+    /// Runs `strif validate`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let ValidateArgs {
+            str_catalog,
+            fai,
+            manifest,
+            read_depths,
+        } = self;
+        Plan::new("validate", dry_run, force)
+            .maybe_input("catalog", str_catalog.as_deref())
+            .maybe_input("FASTA index", fai.as_deref())
+            .maybe_input("manifest", manifest.as_deref())
+            .maybe_input("read depths", read_depths.as_deref())
+            .execute(|| validate(str_catalog, fai, manifest, read_depths))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// The problems found in a catalog, without the path of the catalog
+    fn catalog_problems(catalog: &str, fai: Option<&str>) -> Vec<String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("catalog.json");
+        fs::write(&path, catalog).unwrap();
+        let contig_lengths = fai.map(|fai| {
+            let fai_path = dir.path().join("ref.fa.fai");
+            fs::write(&fai_path, fai).unwrap();
+            load_fai(&fai_path).unwrap()
+        });
+        let mut problems = Vec::new();
+        validate_catalog(&path, contig_lengths.as_ref(), &mut problems).unwrap();
+        let prefix = format!("{}: ", path.display());
+        problems
+            .iter()
+            .map(|problem| problem.strip_prefix(&prefix).unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn every_problem_of_a_catalog_is_reported() {
+        let catalog = r#"[
+            {"LocusId": "L1", "LocusStructure": "(CAG)*", "ReferenceRegion": "chr1:100-130"},
+            {"LocusId": "L1", "LocusStructure": "(CXG)*", "ReferenceRegion": "chr1:100"},
+            {"LocusId": "L3", "LocusStructure": "(CAG)*(CCG)*", "ReferenceRegion": "chr9:1-10"},
+            {"LocusId": "L4", "LocusStructure": "(CNG)*", "ReferenceRegion": "chr1:900-1100",
+             "CandidateMotifs": ["CAG", ""]},
+            {"LocusStructure": "(CAG)*"}
+        ]"#;
+        let problems = catalog_problems(catalog, Some("chr1\t1000\t6\t60\t61\n"));
+        assert_eq!(problems.len(), 8, "{:#?}", problems);
+        assert_eq!(problems[0], "entry 2: duplicate LocusId L1");
+        assert!(problems[1].starts_with("entry 2: L1 has a motif with an unexpected base 'X'"));
+        assert!(problems[2].starts_with("entry 2: L1 has an invalid ReferenceRegion"));
+        assert!(problems[3].contains("that is not a single repeat of the form (MOTIF)*"));
+        assert_eq!(
+            problems[4],
+            "entry 3: L3 has a ReferenceRegion on chr9, which is not in the FASTA index"
+        );
+        // IUPAC codes are valid motif bases
+        assert_eq!(
+            problems[5],
+            "entry 4: L4 has CandidateMotifs that are not a list of motifs"
+        );
+        assert_eq!(
+            problems[6],
+            "entry 4: L4 has a ReferenceRegion chr1:900-1100 beyond the end of chr1 (1000 bp)"
+        );
+        assert_eq!(problems[7], "entry 5: missing a string LocusId");
+        assert_eq!(catalog_problems("[]", None), Vec::<String>::new());
+    }
+
+    #[test]
+    fn samples_of_the_manifest_need_a_read_depth() {
+        let dir = tempfile::tempdir().unwrap();
+        let profile = dir.path().join("s1.tsv");
+        fs::write(&profile, "").unwrap();
+        let manifest = dir.path().join("manifest.tsv");
+        fs::write(
+            &manifest,
+            format!(
+                "s1\tcase\t{}\ns2\tcontrol\t{}\n",
+                profile.display(),
+                dir.path().join("s2.tsv").display()
+            ),
+        )
+        .unwrap();
+        let read_depths = dir.path().join("read_depths.tsv");
+        fs::write(&read_depths, "s1\t0\n").unwrap();
+
+        let mut problems = Vec::new();
+        let samples = validate_manifest(&manifest, &mut problems).unwrap();
+        assert_eq!(samples, ["s1", "s2"]);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("line 2 refers to a profile that does not exist"));
+        validate_read_depths(&read_depths, &mut problems).unwrap();
+        assert!(problems[1].ends_with("line 1 has an invalid read depth '0' for s1"));
+
+        let error = validate(None, None, Some(manifest), Some(read_depths)).unwrap_err();
+        assert_eq!(error.to_string(), "Found 3 problem(s)");
+    }
+}
@@ -0,0 +1,479 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+
+use crate::convert::read_merged_profile_any;
+use crate::error::{field, parse_field, StrifError};
+use crate::merge::{split_length_stratum, MergedProfileRecord};
+use crate::plan::Plan;
+use crate::schema::{self, OutputKind};
+use crate::utils::{
+    create_output, get_default_out_path, is_gzipped_path, open_maybe_gzipped, reference_span,
+    sort_by_coordinates, BgzfWriter, Region,
+};
+
+/// A call of an interruption at a locus in a sample, from the output of `call`
+struct InterruptionCall {
+    genotype: String,
+    quality: f64,
+}
+
+/// The calls of a sample at a locus
+#[derive(Default)]
+struct LocusCalls {
+    /// The calls of each interruption that is not homozygous reference
+    interruptions: HashMap<String, InterruptionCall>,
+    /// The distinct filters of the calls
+    filters: BTreeSet<String>,
+}
+
+impl LocusCalls {
+    /// The genotype of the locus: homozygous for the interrupted allele if
+    /// any interruption is, heterozygous if any is and reference otherwise
+    fn genotype(&self) -> &'static str {
+        let genotypes: Vec<&str> = self
+            .interruptions
+            .values()
+            .map(|call| call.genotype.as_str())
+            .collect();
+        if genotypes.contains(&"1/1") {
+            "1/1"
+        } else if genotypes.contains(&"0/1") {
+            "0/1"
+        } else {
+            "0/0"
+        }
+    }
+
+    /// The filters of the calls, which are PASS only if every call passes
+    fn filter(&self) -> String {
+        let failed: Vec<&str> = self
+            .filters
+            .iter()
+            .map(|filter| filter.as_str())
+            .filter(|filter| *filter != "PASS")
+            .collect();
+        if failed.is_empty() {
+            "PASS".to_string()
+        } else {
+            failed.join(";")
+        }
+    }
+}
+
+/// Loads the calls manifest, which is a TSV with columns: sample,
+/// calls_path (no headers), and the calls of each sample by locus
+fn load_calls(manifest: &Path) -> Result<HashMap<String, HashMap<String, LocusCalls>>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(false)
+        .comment(Some(b'#'))
+        .from_path(manifest)
+        .with_context(|| format!("Could not open {}", manifest.display()))?;
+    let manifest_source = manifest.display().to_string();
+    let mut calls: HashMap<String, HashMap<String, LocusCalls>> = HashMap::new();
+    for result in reader.records() {
+        let record = result?;
+        let sample_id = field(&record, 0, &manifest_source)?.to_string();
+        let path = PathBuf::from(field(&record, 1, &manifest_source)?);
+
+        let source = path.display().to_string();
+        let file =
+            open_maybe_gzipped(&path).with_context(|| format!("Could not open {}", source))?;
+        let mut calls_reader = schema::tsv_reader(file, OutputKind::Calls, &source)?;
+        let sample_calls = calls.entry(sample_id).or_default();
+        for row in calls_reader.records() {
+            let row = row?;
+            let locus_id = field(&row, 0, &source)?;
+            let interruption = field(&row, 4, &source)?;
+            let locus = sample_calls.entry(locus_id.to_string()).or_default();
+            locus.filters.insert(field(&row, 9, &source)?.to_string());
+            // loci without any interruption have a single row without one
+            if interruption != "." {
+                locus.interruptions.insert(
+                    interruption.to_string(),
+                    InterruptionCall {
+                        genotype: field(&row, 7, &source)?.to_string(),
+                        quality: parse_field(&row, 8, "quality", &source)?,
+                    },
+                );
+            }
+        }
+    }
+    Ok(calls)
+}
+
+/// Exports a merged profile as a multi-sample VCF with a site per locus,
+/// whose per-sample fields hold the presence and normalized count of each
+/// interruption and the interruption burden. With the outputs of `call` for
+/// the samples, the sites also carry genotypes. Outputs ending in `.gz` are
+/// written BGZF-compressed so that they can be indexed with tabix.
+pub fn vcf(input: PathBuf, out_path: PathBuf, calls_manifest: Option<PathBuf>) -> Result<()> {
+    info!("Loading merged profile...");
+    let mut records = read_merged_profile_any(&input)?;
+    let calls = match &calls_manifest {
+        Some(manifest) => {
+            info!("Loading calls...");
+            Some(load_calls(manifest)?)
+        }
+        None => None,
+    };
+
+    let samples: BTreeSet<&str> = records
+        .iter()
+        .flat_map(|record| record.read_counts.iter())
+        .map(|(sample_id, _)| sample_id.as_str())
+        .collect();
+    let samples: Vec<String> = samples.into_iter().map(str::to_string).collect();
+    if let (Some(calls), Some(manifest)) = (&calls, &calls_manifest) {
+        for sample_id in calls.keys() {
+            if !samples.contains(sample_id) {
+                warn!(
+                    "Sample {} of {} is not in the merged profile, its calls are skipped",
+                    sample_id,
+                    manifest.display()
+                );
+            }
+        }
+        for sample_id in &samples {
+            if !calls.contains_key(sample_id) {
+                warn!(
+                    "Sample {} has no calls in {}, its genotypes are missing",
+                    sample_id,
+                    manifest.display()
+                );
+            }
+        }
+    }
+
+    let unplaced = records
+        .iter()
+        .filter(|record| reference_span(&record.reference_region).is_none())
+        .count();
+    if unplaced > 0 {
+        warn!(
+            unplaced;
+            "Skipping {} loci without a parseable reference region",
+            unplaced
+        );
+    }
+    records.retain(|record| reference_span(&record.reference_region).is_some());
+    sort_by_coordinates(&mut records, |record| &record.reference_region);
+
+    info!(
+        "Writing {} sites and {} samples to {}...",
+        records.len(),
+        samples.len(),
+        out_path.display()
+    );
+    let out_file = create_output(&out_path)?;
+    if is_gzipped_path(&out_path) {
+        let mut out_file = BgzfWriter::new(out_file);
+        write_vcf(&mut out_file, &records, &samples, calls.as_ref())?;
+        out_file.finish()?.finish()?;
+    } else {
+        let mut out_file = out_file;
+        write_vcf(&mut out_file, &records, &samples, calls.as_ref())?;
+        out_file.finish()?;
+    }
+    if records.is_empty() {
+        return Err(StrifError::EmptyOutput {
+            path: out_path.display().to_string(),
+            message: format!("{} has no loci with a reference region", input.display()),
+        }
+        .into());
+    }
+
+    info!("Done!");
+    Ok(())
+}
+
+fn write_vcf<W: Write>(
+    out: &mut W,
+    records: &[MergedProfileRecord],
+    samples: &[String],
+    calls: Option<&HashMap<String, HashMap<String, LocusCalls>>>,
+) -> Result<()> {
+    writeln!(out, "##fileformat=VCFv4.2")?;
+    writeln!(out, "##source=strif v{}", env!("CARGO_PKG_VERSION"))?;
+    let mut contigs: Vec<String> = Vec::new();
+    for record in records {
+        let chrom = reference_span(&record.reference_region).unwrap().chrom;
+        if contigs.last() != Some(&chrom) {
+            contigs.push(chrom);
+        }
+    }
+    for contig in &contigs {
+        writeln!(out, "##contig=<ID={}>", contig)?;
+    }
+    for line in [
+        r#"##ALT=<ID=INT,Description="Repeat with interruptions of its motif">"#,
+        r#"##INFO=<ID=END,Number=1,Type=Integer,Description="End position of the repeat">"#,
+        r#"##INFO=<ID=RU,Number=1,Type=String,Description="Repeat unit (motif) of the locus">"#,
+        r#"##INFO=<ID=INTERRUPTIONS,Number=.,Type=String,Description="Interruptions found at the locus in any sample, in the order of the per-interruption FORMAT fields">"#,
+    ] {
+        writeln!(out, "{}", line)?;
+    }
+    let mut format_keys = vec!["GT"];
+    if calls.is_some() {
+        format_keys.extend(["FT", "IGT", "IGQ"]);
+    }
+    format_keys.extend(["DP", "IP", "IC", "BUR"]);
+    writeln!(
+        out,
+        r#"##FORMAT=<ID=GT,Number=1,Type=String,Description="Genotype of the most severe interruption called by strif call, missing without calls">"#
+    )?;
+    if calls.is_some() {
+        for line in [
+            r#"##FORMAT=<ID=FT,Number=1,Type=String,Description="Filters of the calls of the locus">"#,
+            r#"##FORMAT=<ID=IGT,Number=.,Type=String,Description="Called genotype of each interruption">"#,
+            r#"##FORMAT=<ID=IGQ,Number=.,Type=Float,Description="Phred-scaled quality of the genotype of each called interruption">"#,
+        ] {
+            writeln!(out, "{}", line)?;
+        }
+    }
+    for line in [
+        r#"##FORMAT=<ID=DP,Number=1,Type=Integer,Description="Reads spanning the repeat">"#,
+        r#"##FORMAT=<ID=IP,Number=.,Type=Integer,Description="Presence (1) or absence (0) of each interruption">"#,
+        r#"##FORMAT=<ID=IC,Number=.,Type=Float,Description="Normalized count of each interruption">"#,
+        r#"##FORMAT=<ID=BUR,Number=1,Type=Float,Description="Interruption burden, the sum of the normalized interruption counts">"#,
+    ] {
+        writeln!(out, "{}", line)?;
+    }
+    write!(out, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT")?;
+    for sample_id in samples {
+        write!(out, "\t{}", sample_id)?;
+    }
+    writeln!(out)?;
+
+    let format = format_keys.join(":");
+    for record in records {
+        let span: Region = reference_span(&record.reference_region).unwrap();
+        let read_counts: HashMap<&str, u32> = record
+            .read_counts
+            .iter()
+            .map(|(sample_id, read_count)| (sample_id.as_str(), *read_count))
+            .collect();
+        let mut counts: HashMap<(&str, &str), f64> = HashMap::new();
+        let mut interruptions: BTreeSet<&str> = BTreeSet::new();
+        for (sample_id, interruption, count) in &record.interruption_counts {
+            // the strata of `merge --length-bin` are counted as one
+            // interruption, as `call` calls them
+            let (interruption, _) = split_length_stratum(interruption);
+            *counts
+                .entry((sample_id.as_str(), interruption))
+                .or_default() += count;
+            interruptions.insert(interruption);
+        }
+        let locus_calls = |sample_id: &str| -> Option<&LocusCalls> {
+            calls?.get(sample_id)?.get(&record.locus_id)
+        };
+        for sample_id in samples {
+            if let Some(sample_calls) = locus_calls(sample_id) {
+                interruptions.extend(sample_calls.interruptions.keys().map(String::as_str));
+            }
+        }
+        let interruptions: Vec<&str> = interruptions.into_iter().collect();
+
+        write!(
+            out,
+            "{}\t{}\t{}\tN\t{}\t.\t.\tEND={};RU={}",
+            span.chrom,
+            span.start + 1,
+            record.locus_id,
+            if interruptions.is_empty() {
+                "."
+            } else {
+                "<INT>"
+            },
+            span.end,
+            record.motif
+        )?;
+        if !interruptions.is_empty() {
+            write!(out, ";INTERRUPTIONS={}", interruptions.join(","))?;
+        }
+        write!(out, "\t{}", format)?;
+
+        for sample_id in samples {
+            let read_count = match read_counts.get(sample_id.as_str()) {
+                Some(read_count) => *read_count,
+                None => {
+                    write!(out, "\t{}", vec!["."; format_keys.len()].join(":"))?;
+                    continue;
+                }
+            };
+            let sample_calls = locus_calls(sample_id);
+            let mut fields: Vec<String> = Vec::with_capacity(format_keys.len());
+            fields.push(match sample_calls {
+                Some(sample_calls) => sample_calls.genotype().to_string(),
+                None => "./.".to_string(),
+            });
+            if calls.is_some() {
+                match sample_calls {
+                    Some(sample_calls) => {
+                        fields.push(sample_calls.filter());
+                        fields.push(per_interruption(&interruptions, |interruption| {
+                            match sample_calls.interruptions.get(interruption) {
+                                Some(call) => call.genotype.clone(),
+                                None => "0/0".to_string(),
+                            }
+                        }));
+                        fields.push(per_interruption(&interruptions, |interruption| {
+                            match sample_calls.interruptions.get(interruption) {
+                                Some(call) => format!("{:.1}", call.quality),
+                                None => ".".to_string(),
+                            }
+                        }));
+                    }
+                    None => fields.extend([".", ".", "."].map(String::from)),
+                }
+            }
+            let count = |interruption: &str| -> f64 {
+                counts
+                    .get(&(sample_id.as_str(), interruption))
+                    .copied()
+                    .unwrap_or(0.0)
+            };
+            fields.push(read_count.to_string());
+            fields.push(per_interruption(&interruptions, |interruption| {
+                u8::from(count(interruption) > 0.0).to_string()
+            }));
+            fields.push(per_interruption(&interruptions, |interruption| {
+                format!("{:.6}", count(interruption))
+            }));
+            let burden: f64 = interruptions.iter().map(|i| count(i)).sum();
+            fields.push(format!("{:.6}", burden));
+            write!(out, "\t{}", fields.join(":"))?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Formats a value per interruption as a comma-separated list, or `.` if the
+/// locus has no interruptions
+fn per_interruption(interruptions: &[&str], value: impl Fn(&str) -> String) -> String {
+    if interruptions.is_empty() {
+        return ".".to_string();
+    }
+    interruptions
+        .iter()
+        .map(|interruption| value(interruption))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// The command line arguments of `strif vcf`
+#[derive(Debug, clap::Args)]
+pub struct VcfArgs {
+    /// The path to the merged profile, in any format supported by `convert`
+    pub merged_profile: PathBuf,
+
+    /// The path to the VCF, written bgzip-compressed if it ends in .gz. Defaults to the same directory as the merged profile.
+    pub output: Option<PathBuf>,
+
+    /// The path to a TSV with columns: sample ID and the path to the output of `call` for the sample,
+    /// used to add genotypes
+    #[clap(short = 'c', long)]
+    pub calls: Option<PathBuf>,
+}
+
+impl VcfArgs {
+    /// Runs `strif vcf`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let VcfArgs {
+            merged_profile,
+            output,
+            calls,
+        } = self;
+        let out_path: PathBuf =
+            output.unwrap_or_else(|| get_default_out_path(&merged_profile, "interruptions", "vcf"));
+        Plan::new("vcf", dry_run, force)
+            .input("merged profile", &merged_profile)
+            .maybe_input("calls manifest", calls.as_deref())
+            .output("VCF", &out_path)
+            .execute(|| vcf(merged_profile, out_path, calls))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use super::*;
+    use crate::merge::length_stratum;
+    use crate::testing::merged_record;
+
+    /// The data lines of the VCF of records
+    fn sites(
+        records: &[MergedProfileRecord],
+        samples: &[&str],
+        calls: Option<&HashMap<String, HashMap<String, LocusCalls>>>,
+    ) -> Vec<String> {
+        let samples: Vec<String> = samples.iter().map(|s| s.to_string()).collect();
+        let mut out = Vec::new();
+        write_vcf(&mut out, records, &samples, calls).unwrap();
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn sites_hold_the_counts_of_each_interruption_per_sample() {
+        let bin = NonZeroU32::new(10).unwrap();
+        let record = merged_record(
+            "HTT",
+            &[("s1", 10), ("s2", 5)],
+            &[
+                ("s1", &length_stratum("A", 12, bin), 0.25),
+                ("s1", &length_stratum("A", 25, bin), 0.25),
+                ("s1", "T", 0.25),
+            ],
+        );
+        assert_eq!(
+            sites(&[record], &["s1", "s2", "s3"], None),
+            [
+                "chr1\t101\tHTT\tN\t<INT>\t.\t.\tEND=200;RU=CAG;INTERRUPTIONS=A,T\tGT:DP:IP:IC:BUR\t\
+                 ./.:10:1,1:0.500000,0.250000:0.750000\t\
+                 ./.:5:0,0:0.000000,0.000000:0.000000\t\
+                 .:.:.:.:."
+            ]
+        );
+    }
+
+    #[test]
+    fn sites_carry_the_genotypes_of_calls() {
+        let record = merged_record("HTT", &[("s1", 10), ("s2", 5)], &[("s1", "A", 0.5)]);
+        let mut locus = LocusCalls::default();
+        locus.interruptions.insert(
+            "A".to_string(),
+            InterruptionCall {
+                genotype: "0/1".to_string(),
+                quality: 31.25,
+            },
+        );
+        locus
+            .filters
+            .extend(["PASS".to_string(), "LowDepth".to_string()]);
+        let calls = HashMap::from([(
+            "s1".to_string(),
+            HashMap::from([("HTT".to_string(), locus)]),
+        )]);
+        let sites = sites(&[record], &["s1", "s2"], Some(&calls));
+        let samples: Vec<&str> = sites[0].split('\t').skip(8).collect();
+        assert_eq!(
+            samples,
+            [
+                "GT:FT:IGT:IGQ:DP:IP:IC:BUR",
+                "0/1:LowDepth:0/1:31.2:10:1:0.500000:0.500000",
+                "./.:.:.:.:5:0:0.000000:0.000000",
+            ]
+        );
+    }
+}
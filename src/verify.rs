@@ -0,0 +1,164 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use log::{debug, info, warn};
+
+use crate::plan::Plan;
+use crate::sidecar::{read_sidecar, summarize, SIDECAR_SUFFIX};
+
+/// Checks the outputs in a directory and its subdirectories against their
+/// sidecars, reporting every output that is missing or has changed rather
+/// than stopping at the first one
+pub fn verify(dir: PathBuf) -> Result<()> {
+    let mut sidecars: Vec<PathBuf> = Vec::new();
+    let mut files: Vec<PathBuf> = Vec::new();
+    collect_files(&dir, &mut sidecars, &mut files)?;
+    if sidecars.is_empty() {
+        bail!(
+            "{} has no sidecars, write them by running strif with --checksums",
+            dir.display()
+        );
+    }
+    info!("Verifying {} outputs...", sidecars.len());
+
+    let mut problems: Vec<String> = Vec::new();
+    let mut covered: HashSet<PathBuf> = HashSet::new();
+    for sidecar in &sidecars {
+        let (output, expected) = read_sidecar(sidecar)?;
+        covered.insert(output.clone());
+        if !output.is_file() {
+            problems.push(format!("{}: missing", output.display()));
+            continue;
+        }
+        let found = summarize(&output)?;
+        if found.size != expected.size {
+            problems.push(format!(
+                "{}: size is {} bytes, expected {}",
+                output.display(),
+                found.size,
+                expected.size
+            ));
+        } else if found.sha256 != expected.sha256 {
+            problems.push(format!(
+                "{}: SHA-256 checksum does not match",
+                output.display()
+            ));
+        }
+        if let (Some(found_rows), Some(expected_rows)) = (found.rows, expected.rows) {
+            if found_rows != expected_rows {
+                problems.push(format!(
+                    "{}: has {} rows, expected {}",
+                    output.display(),
+                    found_rows,
+                    expected_rows
+                ));
+            }
+        }
+        debug!("Verified {}", output.display());
+    }
+
+    let uncovered: Vec<&PathBuf> = files
+        .iter()
+        .filter(|file| !covered.contains(*file))
+        .collect();
+    if !uncovered.is_empty() {
+        warn!(
+            "{} file(s) have no sidecar and were not verified",
+            uncovered.len()
+        );
+        for file in uncovered {
+            debug!("{} has no sidecar", file.display());
+        }
+    }
+
+    if problems.is_empty() {
+        info!("All {} outputs match their sidecars", sidecars.len());
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    bail!("Found {} problem(s)", problems.len())
+}
+
+/// Collects the sidecars and the other files of a directory and its
+/// subdirectories, leaving out hidden files such as unfinished outputs
+fn collect_files(dir: &Path, sidecars: &mut Vec<PathBuf>, files: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries: Vec<_> = fs::read_dir(dir)
+        .with_context(|| format!("Could not read {}", dir.display()))?
+        .collect::<std::io::Result<_>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            collect_files(&path, sidecars, files)?;
+        } else if name.ends_with(SIDECAR_SUFFIX) {
+            sidecars.push(path);
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// The command line arguments of `strif verify`
+#[derive(Debug, clap::Args)]
+pub struct VerifyArgs {
+    /// The directory of outputs, whose subdirectories are checked too
+    pub dir: PathBuf,
+}
+
+impl VerifyArgs {
+    /// Runs `strif verify`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let VerifyArgs { dir } = self;
+        Plan::new("verify", dry_run, force)
+            .input("directory", &dir)
+            .execute(|| verify(dir))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sidecar::write_sidecar;
+    use crate::testing::{profile_record, write_profile_file};
+
+    #[test]
+    fn changed_and_missing_outputs_fail_verification() {
+        let dir = tempfile::tempdir().unwrap();
+        let records = [profile_record("HTT", 3, &[("CAT", 40, 2.0)])];
+        let samples = dir.path().join("samples");
+        fs::create_dir(&samples).unwrap();
+        let profiles: Vec<PathBuf> = ["s1", "s2"]
+            .iter()
+            .map(|sample| {
+                let name = format!("{}.profile.tsv", sample);
+                let profile = write_profile_file(&samples, &name, &records);
+                write_sidecar(&profile).unwrap();
+                profile
+            })
+            .collect();
+        verify(dir.path().to_path_buf()).unwrap();
+
+        // the same size, but other contents
+        let changed = fs::read_to_string(&profiles[0])
+            .unwrap()
+            .replace("HTT", "ATX");
+        fs::write(&profiles[0], changed).unwrap();
+        let error = verify(dir.path().to_path_buf()).unwrap_err();
+        assert_eq!(error.to_string(), "Found 1 problem(s)");
+        fs::remove_file(&profiles[1]).unwrap();
+        let error = verify(dir.path().to_path_buf()).unwrap_err();
+        assert_eq!(error.to_string(), "Found 2 problem(s)");
+
+        let empty = tempfile::tempdir().unwrap();
+        assert!(verify(empty.path().to_path_buf()).is_err());
+    }
+}
@@ -0,0 +1,433 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use log::{debug, error, info, warn};
+
+use crate::merge::{load_manifest, load_read_depths, ManifestEntry, MergeOptions};
+use crate::plan::Plan;
+use crate::profile::ProfileOptions;
+use crate::run::{merge_profiles, profile_sample, run_sample, write_profile_manifest, RunParams};
+use crate::schema::{self, OutputKind};
+use crate::utils::remove_stale_temps;
+
+/// The status given to samples picked up by `watch`, which have none
+const UNKNOWN_STATUS: &str = "unknown";
+
+/// The kinds of inputs `watch` processes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InputKind {
+    Bamlet,
+    RepeatSeqs,
+}
+
+impl InputKind {
+    /// The kind of a file of the input directory, or None if it is not an
+    /// input. Repeat sequences are told from other TSVs by their contents.
+    fn of(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy();
+        if name.starts_with('.') {
+            return None;
+        }
+        if name.ends_with(".bam") || name.ends_with(".cram") {
+            return Some(InputKind::Bamlet);
+        }
+        if name.ends_with(".tsv") && schema::detect(path).ok()? == OutputKind::RepeatSeqs {
+            return Some(InputKind::RepeatSeqs);
+        }
+        None
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            InputKind::Bamlet => "BAMlet",
+            InputKind::RepeatSeqs => "repeat sequences",
+        }
+    }
+}
+
+pub struct WatchParams {
+    /// How to profile and merge the samples, as in `run`
+    pub run: RunParams,
+    /// The time between two scans of the input directory
+    pub interval: Duration,
+    /// Process the inputs already in the directory and exit
+    pub once: bool,
+}
+
+/// The samples processed so far, kept in the output directory so that
+/// `watch` picks up where it stopped when restarted
+struct WatchState {
+    path: PathBuf,
+    samples: Vec<ManifestEntry>,
+}
+
+impl WatchState {
+    fn load(out_dir: &Path) -> Result<Self> {
+        let path = out_dir.join("watch.samples.tsv");
+        let samples = match path.exists() {
+            true => load_manifest(&path)?,
+            false => Vec::new(),
+        };
+        Ok(WatchState { path, samples })
+    }
+
+    fn has_input(&self, path: &Path) -> bool {
+        self.samples.iter().any(|sample| sample.path == path)
+    }
+
+    fn sample(&self, sample_id: &str) -> Option<&ManifestEntry> {
+        self.samples
+            .iter()
+            .find(|sample| sample.sample_id == sample_id)
+    }
+
+    /// Records a processed sample, appending it to the state file
+    fn add(&mut self, sample: ManifestEntry) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Could not open {}", self.path.display()))?;
+        writeln!(
+            file,
+            "{}\t{}\t{}",
+            sample.sample_id,
+            sample.status,
+            sample.path.display()
+        )?;
+        self.samples.push(sample);
+        Ok(())
+    }
+}
+
+/// The sample ID of an input, which is its file name up to the first dot
+fn sample_id(path: &Path) -> String {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    name.split('.').next().unwrap_or_default().to_string()
+}
+
+/// Monitors a directory for new BAMlets and repeat sequences, profiles each
+/// one as it arrives and merges the profiles of every sample processed so far
+/// into a merged profile in the output directory, which is replaced as a
+/// whole so that readers never see it half-written. A file is processed once
+/// its size and modification time are the same in two consecutive scans, so
+/// that files still being copied are left for later. Samples whose read depth
+/// is not yet in the read depths file, which is reread before each merge, are
+/// profiled but only merged once it is.
+pub fn watch(
+    input_dir: PathBuf,
+    str_catalog: PathBuf,
+    read_depths: PathBuf,
+    out_dir: PathBuf,
+    params: WatchParams,
+) -> Result<()> {
+    if !input_dir.is_dir() {
+        anyhow::bail!("{} is not a directory", input_dir.display());
+    }
+    params.run.validate()?;
+    fs::create_dir_all(&out_dir)?;
+    let stale_temps = remove_stale_temps(&out_dir)?;
+    if stale_temps > 0 {
+        info!(
+            "Removed {} temporary file(s) left by a previous run",
+            stale_temps
+        );
+    }
+    let mut state = WatchState::load(&out_dir)?;
+    if !state.samples.is_empty() {
+        info!(
+            "Resuming with the {} sample(s) already processed",
+            state.samples.len()
+        );
+    }
+    let merged_path = out_dir.join("merged_profile.tsv");
+    info!(
+        "Watching {} for new BAMlets and repeat sequences...",
+        input_dir.display()
+    );
+
+    // the size and modification time of each file when last scanned, and the
+    // files that failed or clash with a processed sample
+    let mut last_seen: HashMap<PathBuf, (u64, SystemTime)> = HashMap::new();
+    let mut rejected: HashSet<PathBuf> = HashSet::new();
+    // the samples in the merged profile and those waiting for a read depth
+    let mut merged: Option<usize> = None;
+    let mut waiting: HashSet<String> = HashSet::new();
+    loop {
+        let mut paths: Vec<PathBuf> = fs::read_dir(&input_dir)
+            .with_context(|| format!("Could not read {}", input_dir.display()))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file() && !state.has_input(path) && !rejected.contains(path))
+            .collect();
+        paths.sort();
+        for path in paths {
+            let metadata = fs::metadata(&path)?;
+            let stamp = (metadata.len(), metadata.modified()?);
+            if !params.once && last_seen.insert(path.clone(), stamp) != Some(stamp) {
+                continue;
+            }
+            last_seen.remove(&path);
+            let kind = match InputKind::of(&path) {
+                Some(kind) => kind,
+                None => {
+                    debug!("Ignoring {}", path.display());
+                    rejected.insert(path);
+                    continue;
+                }
+            };
+            let sample_id = sample_id(&path);
+            if let Some(other) = state.sample(&sample_id) {
+                warn!(
+                    sample = sample_id.as_str();
+                    "Skipping {}, sample {} was already processed from {}",
+                    path.display(),
+                    sample_id,
+                    other.path.display()
+                );
+                rejected.insert(path);
+                continue;
+            }
+            info!(
+                sample = sample_id.as_str();
+                "[{}] Processing new {} {}...",
+                sample_id,
+                kind.name(),
+                path.display()
+            );
+            let sample = ManifestEntry {
+                sample_id: sample_id.clone(),
+                status: UNKNOWN_STATUS.to_string(),
+                path: path.clone(),
+            };
+            let processed = match kind {
+                InputKind::Bamlet => run_sample(&sample, &str_catalog, &out_dir, &params.run),
+                InputKind::RepeatSeqs => {
+                    profile_sample(&sample_id, &path, &str_catalog, &out_dir, &params.run)
+                }
+            };
+            match processed {
+                Ok(()) => state.add(sample)?,
+                Err(e) => {
+                    error!("[{}] {:#}", sample_id, e);
+                    rejected.insert(path);
+                }
+            }
+        }
+
+        if merged.is_none_or(|merged| merged < state.samples.len()) {
+            let depths = load_read_depths(&read_depths)?;
+            let ready: Vec<ManifestEntry> = state
+                .samples
+                .iter()
+                .filter(|sample| {
+                    let ready = depths.contains_key(&sample.sample_id);
+                    if !ready && waiting.insert(sample.sample_id.clone()) {
+                        warn!(
+                            sample = sample.sample_id.as_str();
+                            "[{}] Waiting for the read depth of the sample in {} to merge it",
+                            sample.sample_id,
+                            read_depths.display()
+                        );
+                    }
+                    ready
+                })
+                .cloned()
+                .collect();
+            if !ready.is_empty() && merged != Some(ready.len()) {
+                let profile_manifest = write_profile_manifest(&ready, &out_dir)?;
+                merge_profiles(&profile_manifest, &read_depths, &merged_path, &params.run)?;
+                info!(
+                    "Merged {} sample(s) into {}",
+                    ready.len(),
+                    merged_path.display()
+                );
+                merged = Some(ready.len());
+            }
+        }
+
+        if params.once {
+            break;
+        }
+        thread::sleep(params.interval);
+    }
+
+    info!("Done!");
+    Ok(())
+}
+
+/// The command line arguments of `strif watch`
+#[derive(Debug, clap::Args)]
+pub struct WatchArgs {
+    /// The directory to monitor. Files are named <sample ID>.<anything>.bam, .cram or .tsv.
+    #[clap(long)]
+    pub input_dir: PathBuf,
+
+    /// The path to a JSON file containing the catalog of repeat loci
+    #[clap(short = 'c', long = "catalog")]
+    pub str_catalog: PathBuf,
+
+    /// The path to a TSV file containing the global average read depth for each sample.
+    /// Reread before each merge, so that depths can be added as samples arrive.
+    #[clap(short = 'd', long)]
+    pub read_depths: PathBuf,
+
+    /// The directory to write all outputs to
+    #[clap(short = 'o', long, default_value = "strif_output")]
+    pub output_dir: PathBuf,
+
+    /// The number of seconds between two scans of the input directory
+    #[clap(long, default_value = "10")]
+    pub interval: u64,
+
+    /// Process the files already in the input directory and exit
+    #[clap(long, action)]
+    pub once: bool,
+
+    /// Minimum read count to include in the merged profile.
+    #[clap(long, default_value = "1")]
+    pub min_read_count: u32,
+
+    /// The sequencing read length. Used for normalizing the interruption counts.
+    #[clap(short = 'l', long, default_value = "150")]
+    pub read_length: u32,
+
+    // the filter also applies to the merge, and the per-read outputs are
+    // written per sample to the output directory
+    #[clap(flatten)]
+    pub options: ProfileOptions,
+}
+
+impl WatchArgs {
+    /// Runs `strif watch`, or prints its plan with `--dry-run`
+    pub fn execute(self, dry_run: bool, force: bool) -> Result<()> {
+        let WatchArgs {
+            input_dir,
+            str_catalog,
+            read_depths,
+            output_dir,
+            interval,
+            once,
+            min_read_count,
+            read_length,
+            options,
+        } = self;
+        let params = WatchParams {
+            run: RunParams {
+                merge: MergeOptions {
+                    filter: options.filter.clone(),
+                    min_read_count,
+                    read_length,
+                    ..MergeOptions::default()
+                },
+                profile: options,
+                jobs: 1,
+                resume: true,
+            },
+            interval: Duration::from_secs(interval),
+            once,
+        };
+        let mode = match once {
+            true => "once".to_string(),
+            false => format!("every {} seconds", interval),
+        };
+        Plan::new("watch", dry_run, force)
+            .note("input directory", input_dir.display())
+            .note("scan", mode)
+            .catalog(&str_catalog, params.run.profile.filter.as_deref())
+            .read_depths(&read_depths, None)
+            .note("output directory", output_dir.display())
+            .execute(|| watch(input_dir, str_catalog, read_depths, output_dir, params))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::read_merged_profile;
+    use crate::testing::write_catalog;
+
+    fn write_repeat_seqs(path: &Path, rows: &str) {
+        let mut text = Vec::new();
+        schema::write_header(&mut text, OutputKind::RepeatSeqs).unwrap();
+        text.extend_from_slice(rows.as_bytes());
+        fs::write(path, text).unwrap();
+    }
+
+    fn params() -> WatchParams {
+        WatchParams {
+            run: RunParams {
+                profile: ProfileOptions::default(),
+                merge: MergeOptions::default(),
+                jobs: 1,
+                resume: false,
+            },
+            interval: Duration::from_millis(10),
+            once: true,
+        }
+    }
+
+    fn merged_samples(path: &Path) -> Vec<String> {
+        read_merged_profile(path).unwrap()[0]
+            .read_counts
+            .iter()
+            .map(|(sample_id, _)| sample_id.clone())
+            .collect()
+    }
+
+    #[test]
+    fn new_inputs_are_merged_once_their_read_depth_is_known() {
+        let dir = tempfile::tempdir().unwrap();
+        let input_dir = dir.path().join("inbox");
+        let out_dir = dir.path().join("out");
+        fs::create_dir(&input_dir).unwrap();
+        let catalog = write_catalog(dir.path());
+        let read_depths = dir.path().join("read_depths.tsv");
+        fs::write(&read_depths, "s1\t30\n").unwrap();
+        write_repeat_seqs(&input_dir.join("s1.repeat_seqs.tsv"), "HTT\tCAGCATCAG\n");
+        write_repeat_seqs(&input_dir.join("s2.repeat_seqs.tsv"), "HTT\tCAGCAGCAG\n");
+        fs::write(input_dir.join("notes.txt"), "not an input\n").unwrap();
+        let run = || {
+            watch(
+                input_dir.clone(),
+                catalog.clone(),
+                read_depths.clone(),
+                out_dir.clone(),
+                params(),
+            )
+        };
+
+        run().unwrap();
+        let merged_path = out_dir.join("merged_profile.tsv");
+        assert_eq!(merged_samples(&merged_path), ["s1"]);
+        assert_eq!(WatchState::load(&out_dir).unwrap().samples.len(), 2);
+
+        // a restart picks up where the last run stopped, skipping another
+        // input of a processed sample
+        fs::write(&read_depths, "s1\t30\ns2\t30\n").unwrap();
+        write_repeat_seqs(&input_dir.join("s1.other.tsv"), "HTT\tCAGCAGCAG\n");
+        run().unwrap();
+        assert_eq!(merged_samples(&merged_path), ["s1", "s2"]);
+        let state = WatchState::load(&out_dir).unwrap();
+        assert_eq!(state.samples.len(), 2);
+        assert_eq!(state.sample("s1").unwrap().status, UNKNOWN_STATUS);
+    }
+
+    #[test]
+    fn inputs_are_told_apart_by_name_and_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let repeat_seqs = dir.path().join("s1.repeat_seqs.tsv");
+        write_repeat_seqs(&repeat_seqs, "HTT\tCAG\n");
+        assert_eq!(InputKind::of(&repeat_seqs), Some(InputKind::RepeatSeqs));
+        assert_eq!(
+            InputKind::of(Path::new("s1.realigned.bam")),
+            Some(InputKind::Bamlet)
+        );
+        assert_eq!(InputKind::of(Path::new(".s1.bam")), None);
+        assert_eq!(sample_id(Path::new("in/HG001.realigned.bam")), "HG001");
+    }
+}
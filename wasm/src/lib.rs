@@ -0,0 +1,121 @@
+//! WebAssembly bindings for strif's interruption detection, so that a browser
+//! can find the interruptions of a pasted repeat sequence with the same
+//! algorithm as `strif profile`.
+
+use wasm_bindgen::prelude::*;
+
+use strif_core::interruptions::is_iupac_code;
+use strif_core::utils::AlignmentScoreParams;
+use strif_core::InterruptionFinder as CoreFinder;
+
+/// Finds the interruptions of repeat sequences by aligning them to pure
+/// repeats of their motif. Scores that are not given default to those of
+/// `strif profile`.
+#[wasm_bindgen]
+pub struct InterruptionFinder {
+    finder: CoreFinder,
+}
+
+#[wasm_bindgen]
+impl InterruptionFinder {
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        match_score: Option<i32>,
+        mismatch_penalty: Option<i32>,
+        gap_open_penalty: Option<i32>,
+        gap_extend_penalty: Option<i32>,
+    ) -> InterruptionFinder {
+        let defaults = AlignmentScoreParams::default();
+        InterruptionFinder {
+            finder: CoreFinder::new(AlignmentScoreParams {
+                match_score: match_score.unwrap_or(defaults.match_score),
+                mismatch_penalty: mismatch_penalty.unwrap_or(defaults.mismatch_penalty),
+                gap_open_penalty: gap_open_penalty.unwrap_or(defaults.gap_open_penalty),
+                gap_extend_penalty: gap_extend_penalty.unwrap_or(defaults.gap_extend_penalty),
+            }),
+        }
+    }
+
+    /// Returns the interruptions of a repeat sequence in the order they occur
+    pub fn find(&mut self, repeat_seq: &str, motif: &str) -> Result<Vec<String>, JsError> {
+        let (repeat_seq, motif) = clean_inputs(repeat_seq, motif)?;
+        Ok(self.finder.find(&repeat_seq, &motif))
+    }
+
+    /// Returns the alignment of a repeat sequence to a pure repeat of the
+    /// motif as text, wrapped at `width` columns (80 by default)
+    pub fn alignment(
+        &mut self,
+        repeat_seq: &str,
+        motif: &str,
+        width: Option<usize>,
+    ) -> Result<String, JsError> {
+        let (repeat_seq, motif) = clean_inputs(repeat_seq, motif)?;
+        let (alignment, pure_seq) = self.finder.align(&repeat_seq, &motif);
+        Ok(alignment.pretty(&repeat_seq, pure_seq, width.unwrap_or(80)))
+    }
+}
+
+/// Returns the interruptions of a repeat sequence with the default scores
+#[wasm_bindgen(js_name = findInterruptions)]
+pub fn find_interruptions(repeat_seq: &str, motif: &str) -> Result<Vec<String>, JsError> {
+    InterruptionFinder::new(None, None, None, None).find(repeat_seq, motif)
+}
+
+/// Upper-cases pasted sequences and drops whitespace, such as line breaks of
+/// FASTA-formatted text
+fn clean_inputs(repeat_seq: &str, motif: &str) -> Result<(Vec<u8>, Vec<u8>), JsError> {
+    let clean = |seq: &str| -> String {
+        seq.chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| c.to_ascii_uppercase())
+            .collect()
+    };
+    let repeat_seq = clean(repeat_seq);
+    if let Some(c) = repeat_seq.chars().find(|c| !"ACGTN".contains(*c)) {
+        return Err(JsError::new(&format!(
+            "Invalid base '{}' in the repeat sequence, expected A, C, G, T or N",
+            c
+        )));
+    }
+    let motif = clean(motif);
+    if let Some(c) = motif
+        .chars()
+        .find(|c| !c.is_ascii() || !is_iupac_code(*c as u8))
+    {
+        return Err(JsError::new(&format!(
+            "Invalid base '{}' in the motif, expected A, C, G, T or an IUPAC ambiguity code",
+            c
+        )));
+    }
+    if motif.is_empty() {
+        return Err(JsError::new("The motif cannot be empty"));
+    }
+    Ok((repeat_seq.into_bytes(), motif.into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pasted_sequences_are_cleaned_before_alignment() {
+        let mut finder = InterruptionFinder::new(None, None, None, None);
+        assert_eq!(finder.find("cagcag\ncaacag cag\n", "cag").unwrap(), ["A"]);
+        assert_eq!(
+            find_interruptions("CAGCAGCAGTTTTCAGCAGCAG", "CAG").unwrap(),
+            ["TTTT"]
+        );
+    }
+
+    #[test]
+    fn alignments_are_wrapped_at_the_given_width() {
+        let mut finder = InterruptionFinder::new(None, None, None, None);
+        let text = finder.alignment("CAGCAACAG", "CAG", None).unwrap();
+        assert!(text.contains("CAGCAACAG\n"));
+        assert!(text.contains("|||||\\|||\n"));
+        let wrapped = finder.alignment("CAGCAACAG", "CAG", Some(10)).unwrap();
+        assert!(wrapped.lines().all(|line| line.len() <= 10));
+        assert!(wrapped.lines().count() > text.lines().count());
+    }
+}